@@ -9,6 +9,17 @@ typedef int32_t RESULT;
 
 typedef struct RdpRuntime *RDP;
 
+/**
+ * Called with the result when `rdp_stop_graceful` is done.
+ */
+typedef void (*RdpStopCallback)(RESULT result);
+
+/**
+ * Called with the `id` of each config applied, and the error if it's rejected or null
+ * if it's running. The strings are only valid during the call.
+ */
+typedef void (*RdpConfigStatusCallback)(const char *id, const char *error);
+
 /**
  * No error.
  */
@@ -29,10 +40,34 @@ typedef struct RdpRuntime *RDP;
  */
 #define RESULT_ERR_CLOSED -3
 
+/**
+ * The connections are not finished in time and are stopped.
+ */
+#define RESULT_ERR_TIMEOUT -4
+
+/**
+ * The handle is null, e.g. it's already stopped.
+ */
+#define RESULT_ERR_NULL -5
+
 void rdp_setup_stdout_logger(void);
 
 RESULT rdp_run(RDP *rabbit_digger, const char *config);
 
 RESULT rdp_update_config(RDP rabbit_digger, const char *config);
 
+/**
+ * Call `cb` with the status of each config given by `rdp_update_config` from now on,
+ * in order. The first call also gets the status of the config given by `rdp_run`.
+ * `rdp_update_config` only tells whether the config is sent.
+ */
+RESULT rdp_on_config_status(RDP rabbit_digger, RdpConfigStatusCallback cb);
+
 RESULT rdp_stop(RDP *rabbit_digger);
+
+/**
+ * Stop the servers and wait up to `timeout_ms` for the connections to finish in
+ * the background, then shut down the runtime and call `cb` with the result.
+ * `rabbit_digger` is set to null immediately.
+ */
+RESULT rdp_stop_graceful(RDP *rabbit_digger, uint64_t timeout_ms, RdpStopCallback cb);
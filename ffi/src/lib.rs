@@ -1,17 +1,43 @@
-use rabbit_digger_pro::{config::ImportSource, App};
-use std::{ffi::CStr, os::raw::c_char, ptr};
-use tokio::{runtime::Runtime, sync::mpsc};
+use rabbit_digger_pro::{
+    config::ImportSource,
+    rabbit_digger::{ConfigStatus, RabbitDigger},
+    App,
+};
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+    ptr,
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+use tokio::{
+    runtime::Runtime,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc,
+    },
+};
 use tokio_stream::{wrappers::UnboundedReceiverStream, StreamExt};
 use tracing_subscriber::{layer::SubscriberExt, prelude::*};
 
 struct RdpRuntime {
     runtime: Runtime,
     sender: mpsc::UnboundedSender<String>,
+    rd: RabbitDigger,
+    /// Subscribed before the first config is applied, taken by the first
+    /// `rdp_on_config_status`.
+    first_status: Mutex<Option<broadcast::Receiver<ConfigStatus>>>,
 }
 
 #[repr(transparent)]
 pub struct RDP(*mut RdpRuntime);
 pub type RESULT = i32;
+/// Called with the result when `rdp_stop_graceful` is done.
+pub type RdpStopCallback = Option<extern "C" fn(result: RESULT)>;
+/// Called with the `id` of each config applied, and the error if it's rejected or null
+/// if it's running. The strings are only valid during the call.
+pub type RdpConfigStatusCallback = Option<extern "C" fn(id: *const c_char, error: *const c_char)>;
 
 /// No error.
 pub const RESULT_OK: RESULT = 0;
@@ -21,6 +47,10 @@ pub const RESULT_ERR_UNKNOWN: RESULT = -1;
 pub const RESULT_ERR_UTF8: RESULT = -2;
 /// The other side is closed.
 pub const RESULT_ERR_CLOSED: RESULT = -3;
+/// The connections are not finished in time and are stopped.
+pub const RESULT_ERR_TIMEOUT: RESULT = -4;
+/// The handle is null, e.g. it's already stopped.
+pub const RESULT_ERR_NULL: RESULT = -5;
 
 #[no_mangle]
 pub extern "C" fn rdp_setup_stdout_logger() {
@@ -48,8 +78,10 @@ pub extern "C" fn rdp_run(rabbit_digger: *mut RDP, config: *const c_char) -> RES
     let runtime = Runtime::new().expect("Failed to run tokio");
     let (tx, rx) = mpsc::unbounded_channel();
     tx.send(config).expect("Failed to send config");
-    match runtime.block_on(async move {
+    let (rd, first_status) = match runtime.block_on(async move {
         let app = App::new().await?;
+        let rd = app.rd.clone();
+        let first_status = rd.subscribe_config_status();
 
         let rx = UnboundedReceiverStream::new(rx).map(ImportSource::Text);
         let config_stream = Box::pin(app.cfg_mgr.config_stream_from_sources(rx).await?);
@@ -60,9 +92,9 @@ pub extern "C" fn rdp_run(rabbit_digger: *mut RDP, config: *const c_char) -> RES
             }
         });
 
-        Result::<_, anyhow::Error>::Ok(())
+        Result::<_, anyhow::Error>::Ok((rd, first_status))
     }) {
-        Ok(_) => {}
+        Ok(r) => r,
         Err(_) => {
             return RESULT_ERR_UNKNOWN;
         }
@@ -70,6 +102,8 @@ pub extern "C" fn rdp_run(rabbit_digger: *mut RDP, config: *const c_char) -> RES
     let rt = RdpRuntime {
         runtime,
         sender: tx,
+        rd,
+        first_status: Mutex::new(Some(first_status)),
     };
     unsafe {
         *rabbit_digger = RDP(Box::into_raw(Box::new(rt)));
@@ -94,13 +128,141 @@ pub extern "C" fn rdp_update_config(rabbit_digger: RDP, config: *const c_char) -
     }
 }
 
+/// Call `cb` with the status of each config given by `rdp_update_config` from now on,
+/// in order. The first call also gets the status of the config given by `rdp_run`.
+/// `rdp_update_config` only tells whether the config is sent.
+#[no_mangle]
+pub extern "C" fn rdp_on_config_status(rabbit_digger: RDP, cb: RdpConfigStatusCallback) -> RESULT {
+    let rt: &RdpRuntime = unsafe { &*(rabbit_digger.0 as *const RdpRuntime) };
+    let cb = match cb {
+        Some(cb) => cb,
+        None => return RESULT_OK,
+    };
+
+    let mut status = match rt.first_status.lock().unwrap().take() {
+        Some(status) => status,
+        None => rt.rd.subscribe_config_status(),
+    };
+    rt.runtime.spawn(async move {
+        loop {
+            match status.recv().await {
+                Ok(status) => {
+                    let id = CString::new(status.id).unwrap_or_default();
+                    let error = status
+                        .error
+                        .map(|e| CString::new(e.replace('\0', "")).unwrap_or_default());
+                    cb(
+                        id.as_ptr(),
+                        error.as_ref().map_or(ptr::null(), |e| e.as_ptr()),
+                    );
+                }
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("{} config statuses are dropped", n);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    RESULT_OK
+}
+
 #[no_mangle]
 pub extern "C" fn rdp_stop(rabbit_digger: *mut RDP) -> RESULT {
-    unsafe {
-        let rt = Box::from_raw((*rabbit_digger).0);
-        *rabbit_digger = RDP(ptr::null_mut());
+    let rt = match unsafe { take_runtime(rabbit_digger) } {
+        Some(rt) => rt,
+        None => return RESULT_ERR_NULL,
+    };
+    rt.runtime.shutdown_background();
+    RESULT_OK
+}
 
+/// Stop the servers and wait up to `timeout_ms` for the connections to finish in
+/// the background, then shut down the runtime and call `cb` with the result.
+/// `rabbit_digger` is set to null immediately.
+#[no_mangle]
+pub extern "C" fn rdp_stop_graceful(
+    rabbit_digger: *mut RDP,
+    timeout_ms: u64,
+    cb: RdpStopCallback,
+) -> RESULT {
+    let rt = match unsafe { take_runtime(rabbit_digger) } {
+        Some(rt) => rt,
+        None => return RESULT_ERR_NULL,
+    };
+
+    thread::spawn(move || {
+        let rd = rt.rd.clone();
+        let result = match rt
+            .runtime
+            .block_on(rd.stop_graceful(Duration::from_millis(timeout_ms)))
+        {
+            Ok(true) => RESULT_OK,
+            Ok(false) => RESULT_ERR_TIMEOUT,
+            Err(e) => {
+                tracing::error!("Failed to stop gracefully: {:?}", e);
+                RESULT_ERR_UNKNOWN
+            }
+        };
         rt.runtime.shutdown_background();
-    }
+
+        if let Some(cb) = cb {
+            cb(result);
+        }
+    });
+
     RESULT_OK
 }
+
+/// Take the runtime of `rabbit_digger` and set it to null, `None` if either is null.
+unsafe fn take_runtime(rabbit_digger: *mut RDP) -> Option<Box<RdpRuntime>> {
+    if rabbit_digger.is_null() || (*rabbit_digger).0.is_null() {
+        return None;
+    }
+    let rt = Box::from_raw((*rabbit_digger).0);
+    *rabbit_digger = RDP(ptr::null_mut());
+    Some(rt)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::CString,
+        sync::{mpsc, Mutex},
+    };
+
+    use super::*;
+
+    static STOPPED: Mutex<Option<mpsc::Sender<RESULT>>> = Mutex::new(None);
+
+    extern "C" fn on_stopped(result: RESULT) {
+        if let Some(tx) = &*STOPPED.lock().unwrap() {
+            tx.send(result).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_stop_graceful() {
+        let (tx, rx) = mpsc::channel();
+        *STOPPED.lock().unwrap() = Some(tx);
+
+        let config = CString::new("{}").unwrap();
+        let mut rdp = RDP(ptr::null_mut());
+        assert_eq!(rdp_run(&mut rdp, config.as_ptr()), RESULT_OK);
+        assert!(!rdp.0.is_null());
+
+        assert_eq!(
+            rdp_stop_graceful(&mut rdp, 1000, Some(on_stopped)),
+            RESULT_OK
+        );
+        assert!(rdp.0.is_null());
+
+        let result = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(result, RESULT_OK);
+
+        // stopping again is an error, not a double free
+        assert_eq!(rdp_stop_graceful(&mut rdp, 1000, None), RESULT_ERR_NULL);
+        assert_eq!(rdp_stop(&mut rdp), RESULT_ERR_NULL);
+        assert_eq!(rdp_stop(ptr::null_mut()), RESULT_ERR_NULL);
+    }
+}
@@ -0,0 +1,10 @@
+use net::{GrpcNet, GrpcNetConfig};
+use rd_interface::{init_registry, register_net};
+
+mod net;
+mod server;
+mod stream;
+
+register_net!(GrpcNet, "grpc", GrpcNetConfig, GrpcNet::new, title = "gRPC");
+
+init_registry!(net: [GrpcNet]);
@@ -0,0 +1,318 @@
+use bytes::Bytes;
+use h2::client::{self, SendRequest};
+use http::{
+    header::{CONTENT_TYPE, TE},
+    uri, Request,
+};
+use parking_lot::Mutex;
+use rd_interface::{
+    async_trait, error::map_other, prelude::*, registry::NetRef, Address, Context, INet, IntoDyn,
+    Net, Result, TcpListener, TcpStream,
+};
+use std::net::SocketAddr;
+
+use crate::{server::GrpcListener, stream::GrpcStream};
+
+fn default_service_name() -> String {
+    "GunService".to_string()
+}
+
+fn default_max_message_size() -> usize {
+    16 * 1024 * 1024
+}
+
+/// Tunnel the TCP streams in gRPC streams over HTTP/2, compatible with the gRPC
+/// transport of v2ray. It's usually used under trojan or vmess, behind a CDN or nginx.
+///
+/// Binding on this net accepts the gRPC streams, so the servers listening on it
+/// serve their protocol over gRPC.
+#[rd_config]
+#[derive(Debug)]
+pub struct GrpcNetConfig {
+    /// gRPC service name, the streams are requested at `/<service_name>/Tun`.
+    #[serde(default = "default_service_name")]
+    service_name: String,
+    /// `:authority` of the requests. The address of the server is used if not set.
+    #[serde(default)]
+    host: Option<String>,
+    /// The largest gRPC message accepted in bytes, the stream is closed with an error
+    /// if the peer sends a larger one. Default is 16 MiB.
+    #[serde(default = "default_max_message_size")]
+    max_message_size: usize,
+
+    #[serde(default)]
+    net: NetRef,
+}
+
+/// An HTTP/2 connection shared by the streams to the same address.
+#[derive(Clone)]
+struct Connection {
+    addr: Address,
+    client: SendRequest<Bytes>,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+}
+
+pub struct GrpcNet {
+    path: String,
+    host: Option<String>,
+    max_message_size: usize,
+    net: Net,
+    conn: Mutex<Option<Connection>>,
+}
+
+impl GrpcNet {
+    pub fn new(config: GrpcNetConfig) -> Result<Self> {
+        Ok(GrpcNet {
+            path: format!("/{}/Tun", config.service_name),
+            host: config.host,
+            max_message_size: config.max_message_size,
+            net: config.net.value_cloned(),
+            conn: Mutex::new(None),
+        })
+    }
+
+    /// Returns the connection to `addr` if it's still open.
+    async fn reuse(&self, addr: &Address) -> Option<Connection> {
+        let conn = self.conn.lock().clone().filter(|c| &c.addr == addr)?;
+        match conn.client.ready().await {
+            Ok(client) => Some(Connection { client, ..conn }),
+            Err(e) => {
+                tracing::debug!("gRPC connection to {} is closed: {:?}", addr, e);
+                None
+            }
+        }
+    }
+
+    async fn connect(&self, ctx: &mut Context, addr: &Address) -> Result<Connection> {
+        if let Some(conn) = self.reuse(addr).await {
+            return Ok(conn);
+        }
+
+        let tcp = self.net.tcp_connect(ctx, addr).await?;
+        let peer_addr = tcp.peer_addr().await.ok();
+        let local_addr = tcp.local_addr().await.ok();
+
+        let (client, conn) = client::handshake(tcp).await.map_err(map_other)?;
+        tokio::spawn(async move {
+            if let Err(e) = conn.await {
+                tracing::debug!("Error in gRPC connection: {:?}", e);
+            }
+        });
+        let client = client.ready().await.map_err(map_other)?;
+
+        let conn = Connection {
+            addr: addr.clone(),
+            client,
+            peer_addr,
+            local_addr,
+        };
+        *self.conn.lock() = Some(conn.clone());
+
+        Ok(conn)
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for GrpcNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let authority = match &self.host {
+            Some(host) => host.clone(),
+            None => addr.to_string(),
+        };
+        let uri = uri::Builder::new()
+            .scheme("https")
+            .authority(authority)
+            .path_and_query(self.path.as_str())
+            .build()
+            .map_err(map_other)?;
+        let request = Request::post(uri)
+            .header(CONTENT_TYPE, "application/grpc")
+            .header(TE, "trailers")
+            .body(())
+            .map_err(map_other)?;
+
+        let Connection {
+            mut client,
+            peer_addr,
+            local_addr,
+            ..
+        } = self.connect(ctx, addr).await?;
+        let (response, send) = client.send_request(request, false).map_err(map_other)?;
+
+        Ok(
+            GrpcStream::new_client(response, send, self.max_message_size, peer_addr, local_addr)
+                .into_dyn(),
+        )
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpBind for GrpcNet {
+    async fn tcp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<TcpListener> {
+        let listener = self.net.tcp_bind(ctx, addr).await?;
+        Ok(GrpcListener::new(listener, self.path.clone(), self.max_message_size).into_dyn())
+    }
+}
+
+impl INet for GrpcNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        Some(self)
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rd_interface::{Arc, IntoAddress};
+    use rd_std::tests::{assert_net_provider, ProviderCapability, TestNet};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Counts the TCP connections made through it.
+    struct CountNet {
+        net: Net,
+        count: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl rd_interface::TcpConnect for CountNet {
+        async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            self.net.tcp_connect(ctx, addr).await
+        }
+    }
+
+    impl INet for CountNet {
+        fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+            Some(self)
+        }
+    }
+
+    fn grpc_net(net: &Net, service_name: &str) -> Net {
+        GrpcNet::new(GrpcNetConfig {
+            service_name: service_name.to_string(),
+            host: Some("example.com".to_string()),
+            max_message_size: default_max_message_size(),
+            net: NetRef::new_with_value("test".into(), net.clone()),
+        })
+        .unwrap()
+        .into_dyn()
+    }
+
+    #[test]
+    fn test_provider() {
+        let net = TestNet::new().into_dyn();
+
+        assert_net_provider(
+            &grpc_net(&net, "GunService"),
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let net = TestNet::new().into_dyn();
+        let grpc = grpc_net(&net, "GunService");
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+
+        let listener = grpc.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+        let server = tokio::spawn(async move {
+            // two streams, the data is echoed with a prefix
+            for _ in 0..2 {
+                let (mut tcp, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    tcp.read_to_end(&mut buf).await.unwrap();
+                    tcp.write_all(b"echo: ").await.unwrap();
+                    tcp.write_all(&buf).await.unwrap();
+                    tcp.shutdown().await.unwrap();
+                });
+            }
+        });
+
+        for data in [b"hello".to_vec(), vec![1u8; 100 * 1024]] {
+            let mut tcp = grpc.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+            tcp.write_all(&data).await.unwrap();
+            tcp.shutdown().await.unwrap();
+
+            let mut buf = Vec::new();
+            tcp.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(&buf[..6], b"echo: ");
+            assert_eq!(&buf[6..], &data[..]);
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wrong_service_name() {
+        let net = TestNet::new().into_dyn();
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+
+        let _listener = grpc_net(&net, "GunService")
+            .tcp_bind(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+
+        let mut tcp = grpc_net(&net, "Other")
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+        let mut buf = [0u8; 16];
+        assert!(tcp.read(&mut buf).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reuse_connection() {
+        let net = TestNet::new().into_dyn();
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+
+        let listener = grpc_net(&net, "GunService")
+            .tcp_bind(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut tcp, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 16];
+                    let size = tcp.read(&mut buf).await.unwrap();
+                    tcp.write_all(&buf[..size]).await.unwrap();
+                });
+            }
+        });
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let counted = CountNet {
+            net,
+            count: count.clone(),
+        }
+        .into_dyn();
+        let grpc = grpc_net(&counted, "GunService");
+
+        let mut streams = Vec::new();
+        for data in [b"first", b"other"] {
+            let mut tcp = grpc.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+            tcp.write_all(data).await.unwrap();
+            let mut buf = [0u8; 5];
+            tcp.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, data);
+            streams.push(tcp);
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,120 @@
+use std::net::SocketAddr;
+
+use h2::server;
+use http::{header::CONTENT_TYPE, Response, StatusCode};
+use rd_interface::{
+    async_trait, error::map_other, Arc, Error, ITcpListener, IntoDyn, Result, TcpListener,
+    TcpStream,
+};
+use rd_std::util::{accept_with_backoff, DropAbort, ACCEPT_BACKOFF};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::stream::GrpcStream;
+
+type Accepted = Result<(TcpStream, SocketAddr)>;
+
+/// Accepts the gRPC streams of all the HTTP/2 connections to the inner listener.
+pub struct GrpcListener {
+    listener: Arc<TcpListener>,
+    rx: Mutex<mpsc::UnboundedReceiver<Accepted>>,
+    _task: DropAbort<()>,
+}
+
+impl GrpcListener {
+    pub fn new(listener: TcpListener, path: String, max_message_size: usize) -> Self {
+        let listener = Arc::new(listener);
+        let (tx, rx) = mpsc::unbounded_channel();
+        let task = tokio::spawn(accept_loop(listener.clone(), path, max_message_size, tx));
+
+        GrpcListener {
+            listener,
+            rx: Mutex::new(rx),
+            _task: DropAbort::new(task),
+        }
+    }
+}
+
+async fn accept_loop(
+    listener: Arc<TcpListener>,
+    path: String,
+    max_message_size: usize,
+    tx: mpsc::UnboundedSender<Accepted>,
+) {
+    loop {
+        let (tcp, addr) = match accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await {
+            Ok(v) => v,
+            Err(e) => {
+                let _ = tx.send(Err(e));
+                return;
+            }
+        };
+        let local_addr = listener.local_addr().await.ok();
+        let path = path.clone();
+        let tx = tx.clone();
+        // the connection is served until it's closed, so the accepted streams keep working
+        // after the listener is dropped.
+        tokio::spawn(async move {
+            if let Err(e) =
+                serve_connection(tcp, addr, local_addr, &path, max_message_size, tx).await
+            {
+                tracing::debug!("Error when serving gRPC connection from {}: {:?}", addr, e);
+            }
+        });
+    }
+}
+
+async fn serve_connection(
+    tcp: TcpStream,
+    addr: SocketAddr,
+    local_addr: Option<SocketAddr>,
+    path: &str,
+    max_message_size: usize,
+    tx: mpsc::UnboundedSender<Accepted>,
+) -> Result<()> {
+    let mut conn = server::handshake(tcp).await.map_err(map_other)?;
+
+    while let Some(request) = conn.accept().await {
+        let (request, mut respond) = request.map_err(map_other)?;
+
+        if request.uri().path() != path {
+            let response = Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(())
+                .map_err(map_other)?;
+            respond.send_response(response, true).map_err(map_other)?;
+            continue;
+        }
+
+        let response = Response::builder()
+            .header(CONTENT_TYPE, "application/grpc")
+            .body(())
+            .map_err(map_other)?;
+        let send = respond.send_response(response, false).map_err(map_other)?;
+        let stream = GrpcStream::new_server(
+            request.into_body(),
+            send,
+            max_message_size,
+            Some(addr),
+            local_addr,
+        );
+
+        // the stream is reset if the listener is dropped
+        let _ = tx.send(Ok((stream.into_dyn(), addr)));
+    }
+
+    Ok(())
+}
+
+#[async_trait]
+impl ITcpListener for GrpcListener {
+    async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        match self.rx.lock().await.recv().await {
+            Some(accepted) => accepted,
+            None => Err(Error::other("gRPC listener is closed")),
+        }
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().await
+    }
+}
@@ -0,0 +1,325 @@
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{self, Poll},
+};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::ready;
+use h2::{client::ResponseFuture, RecvStream, SendStream};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use rd_interface::{async_trait, ITcpStream, ReadBuf, Result, NOT_IMPLEMENTED};
+
+/// Length of the gRPC message header: 1 byte compressed flag and 4 bytes length.
+const HEADER_LEN: usize = 5;
+/// Tag of the field `bytes data = 1` of the v2ray `Hunk` message.
+const HUNK_DATA_TAG: u8 = 0x0a;
+
+enum Recv {
+    Response(ResponseFuture),
+    Body(RecvStream),
+}
+
+/// A `TcpStream` tunneled in a bidirectional gRPC stream, following the
+/// v2ray convention: each message is a `Hunk { bytes data = 1; }`.
+pub struct GrpcStream {
+    recv: Recv,
+    send: SendStream<Bytes>,
+    is_server: bool,
+    /// Received bytes which are not decoded to a message yet.
+    read_buf: BytesMut,
+    max_message_size: usize,
+    /// Decoded data which is not read yet.
+    data: Bytes,
+    shutdown: bool,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+}
+
+impl GrpcStream {
+    pub fn new_client(
+        response: ResponseFuture,
+        send: SendStream<Bytes>,
+        max_message_size: usize,
+        peer_addr: Option<SocketAddr>,
+        local_addr: Option<SocketAddr>,
+    ) -> Self {
+        GrpcStream::new(
+            Recv::Response(response),
+            send,
+            false,
+            max_message_size,
+            peer_addr,
+            local_addr,
+        )
+    }
+
+    pub fn new_server(
+        body: RecvStream,
+        send: SendStream<Bytes>,
+        max_message_size: usize,
+        peer_addr: Option<SocketAddr>,
+        local_addr: Option<SocketAddr>,
+    ) -> Self {
+        GrpcStream::new(
+            Recv::Body(body),
+            send,
+            true,
+            max_message_size,
+            peer_addr,
+            local_addr,
+        )
+    }
+
+    fn new(
+        recv: Recv,
+        send: SendStream<Bytes>,
+        is_server: bool,
+        max_message_size: usize,
+        peer_addr: Option<SocketAddr>,
+        local_addr: Option<SocketAddr>,
+    ) -> Self {
+        GrpcStream {
+            recv,
+            send,
+            is_server,
+            read_buf: BytesMut::new(),
+            max_message_size,
+            data: Bytes::new(),
+            shutdown: false,
+            peer_addr,
+            local_addr,
+        }
+    }
+
+    fn poll_body(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<&mut RecvStream>> {
+        if let Recv::Response(response) = &mut self.recv {
+            let response = ready!(Pin::new(response).poll(cx)).map_err(h2_to_io)?;
+            if response.status() != StatusCode::OK {
+                return Poll::Ready(Err(io::Error::other(format!(
+                    "unexpected gRPC response status: {}",
+                    response.status()
+                ))));
+            }
+            self.recv = Recv::Body(response.into_body());
+        }
+        match &mut self.recv {
+            Recv::Body(body) => Poll::Ready(Ok(body)),
+            Recv::Response(_) => unreachable!(),
+        }
+    }
+}
+
+pub(crate) fn h2_to_io(e: h2::Error) -> io::Error {
+    if e.is_io() {
+        e.into_io().expect("checked by is_io")
+    } else {
+        io::Error::other(e)
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn put_varint(buf: &mut BytesMut, mut n: usize) {
+    while n >= 0x80 {
+        buf.put_u8(n as u8 | 0x80);
+        n >>= 7;
+    }
+    buf.put_u8(n as u8);
+}
+
+fn get_varint(buf: &mut Bytes) -> io::Result<usize> {
+    let mut n = 0usize;
+    for shift in (0..64).step_by(7) {
+        if !buf.has_remaining() {
+            break;
+        }
+        let b = buf.get_u8();
+        n |= ((b & 0x7f) as usize) << shift;
+        if b & 0x80 == 0 {
+            return Ok(n);
+        }
+    }
+    Err(invalid_data("invalid varint in gRPC message"))
+}
+
+fn varint_len(n: usize) -> usize {
+    let bits = usize::BITS - n.leading_zeros();
+    (bits.max(1) as usize).div_ceil(7)
+}
+
+/// Encode `data` as a gRPC message of `Hunk`.
+fn encode_frame(data: &[u8]) -> Bytes {
+    let msg_len = 1 + varint_len(data.len()) + data.len();
+    let mut buf = BytesMut::with_capacity(HEADER_LEN + msg_len);
+
+    buf.put_u8(0);
+    buf.put_u32(msg_len as u32);
+    buf.put_u8(HUNK_DATA_TAG);
+    put_varint(&mut buf, data.len());
+    buf.put_slice(data);
+
+    buf.freeze()
+}
+
+/// Decode the data of a `Hunk` from `buf`, returns `None` if the message is not complete.
+/// Messages longer than `max_len` are rejected before they are buffered.
+fn decode_frame(buf: &mut BytesMut, max_len: usize) -> io::Result<Option<Bytes>> {
+    if buf.len() < HEADER_LEN {
+        return Ok(None);
+    }
+    if buf[0] != 0 {
+        return Err(invalid_data("compressed gRPC message is not supported"));
+    }
+    let msg_len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+    if msg_len > max_len {
+        return Err(invalid_data("gRPC message is too large"));
+    }
+    if buf.len() < HEADER_LEN + msg_len {
+        buf.reserve(HEADER_LEN + msg_len - buf.len());
+        return Ok(None);
+    }
+    buf.advance(HEADER_LEN);
+    let mut msg = buf.split_to(msg_len).freeze();
+
+    // an empty message has no data
+    if msg.is_empty() {
+        return Ok(Some(Bytes::new()));
+    }
+    if msg.get_u8() != HUNK_DATA_TAG {
+        return Err(invalid_data("unexpected field in gRPC message"));
+    }
+    let len = get_varint(&mut msg)?;
+    if msg.len() < len {
+        return Err(invalid_data("gRPC message is truncated"));
+    }
+
+    Ok(Some(msg.split_to(len)))
+}
+
+#[async_trait]
+impl ITcpStream for GrpcStream {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            if !self.data.is_empty() {
+                let to_read = self.data.len().min(buf.remaining());
+                buf.put_slice(&self.data.split_to(to_read));
+                return Poll::Ready(Ok(()));
+            }
+            if let Some(data) = decode_frame(&mut self.read_buf, self.max_message_size)? {
+                self.data = data;
+                continue;
+            }
+
+            let body = ready!(self.poll_body(cx))?;
+            match ready!(body.poll_data(cx)) {
+                Some(Ok(chunk)) => {
+                    let _ = body.flow_control().release_capacity(chunk.len());
+                    self.read_buf.extend_from_slice(&chunk);
+                }
+                Some(Err(e)) => return Poll::Ready(Err(h2_to_io(e))),
+                None if self.read_buf.is_empty() => return Poll::Ready(Ok(())),
+                None => return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into())),
+            }
+        }
+    }
+
+    fn poll_write(&mut self, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        self.send.reserve_capacity(buf.len());
+        match ready!(self.send.poll_capacity(cx)) {
+            Some(Ok(cap)) => {
+                // the message header may exceed the capacity a little, h2 buffers it
+                let n = cap.min(buf.len());
+                self.send
+                    .send_data(encode_frame(&buf[..n]), false)
+                    .map_err(h2_to_io)?;
+                Poll::Ready(Ok(n))
+            }
+            Some(Err(e)) => Poll::Ready(Err(h2_to_io(e))),
+            None => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(&mut self, _cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(&mut self, _cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        if !self.shutdown {
+            let result = if self.is_server {
+                let mut trailers = HeaderMap::new();
+                trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                self.send.send_trailers(trailers)
+            } else {
+                self.send.send_data(Bytes::new(), true)
+            };
+            result.map_err(h2_to_io)?;
+            self.shutdown = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        self.peer_addr.ok_or(NOT_IMPLEMENTED)
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.local_addr.ok_or(NOT_IMPLEMENTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_LEN: usize = 1024 * 1024;
+
+    #[test]
+    fn test_frame() {
+        for len in [0, 1, 127, 128, 300, 70000] {
+            let data = vec![7u8; len];
+            let frame = encode_frame(&data);
+            assert_eq!(frame.len(), HEADER_LEN + 1 + varint_len(len) + len);
+
+            // feed the frame byte by byte
+            let mut buf = BytesMut::new();
+            for (i, b) in frame.iter().enumerate() {
+                let decoded = decode_frame(&mut buf, MAX_LEN).unwrap();
+                assert!(decoded.is_none(), "decoded at {}", i);
+                buf.put_u8(*b);
+            }
+            assert_eq!(decode_frame(&mut buf, MAX_LEN).unwrap().unwrap(), data);
+            assert!(buf.is_empty());
+        }
+
+        assert_eq!(&encode_frame(b"hi")[..], b"\x00\x00\x00\x00\x04\x0a\x02hi");
+
+        let mut buf = BytesMut::from(&b"\x01\x00\x00\x00\x00"[..]);
+        assert!(decode_frame(&mut buf, MAX_LEN).is_err());
+    }
+
+    #[test]
+    fn test_frame_too_large() {
+        let frame = encode_frame(&[7u8; 100]);
+        let mut buf = BytesMut::from(&frame[..HEADER_LEN]);
+        let err = decode_frame(&mut buf, 64).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut buf = BytesMut::from(&frame[..]);
+        assert!(decode_frame(&mut buf, frame.len() - HEADER_LEN)
+            .unwrap()
+            .is_some());
+    }
+}
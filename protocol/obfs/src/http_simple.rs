@@ -11,7 +11,7 @@ use pin_project_lite::pin_project;
 use rand::prelude::*;
 use rd_interface::{
     async_trait, prelude::*, rd_config, Address, AsyncWrite, ITcpStream, IntoDyn, ReadBuf, Result,
-    TcpStream, NOT_IMPLEMENTED,
+    TcpStream,
 };
 use tokio::io::AsyncRead;
 
@@ -30,6 +30,8 @@ pub struct HttpSimple {
     method: String,
     #[serde(default = "def_uri")]
     uri: String,
+    /// `Host` of the request, independent of the destination,
+    /// e.g. the domain fronted by a CDN.
     host: String,
 }
 
@@ -40,11 +42,11 @@ impl Obfs for HttpSimple {
         _ctx: &mut rd_interface::Context,
         _addr: &Address,
     ) -> Result<TcpStream> {
-        Ok(Connect::new(tcp, self.clone()).into_dyn())
+        Ok(HttpStream::new(tcp, self.clone(), false).into_dyn())
     }
 
-    fn tcp_accept(&self, _tcp: TcpStream, _addr: std::net::SocketAddr) -> Result<TcpStream> {
-        Err(NOT_IMPLEMENTED)
+    fn tcp_accept(&self, tcp: TcpStream, _addr: std::net::SocketAddr) -> Result<TcpStream> {
+        Ok(HttpStream::new(tcp, self.clone(), true).into_dyn())
     }
 }
 
@@ -61,27 +63,65 @@ enum ReadState {
 }
 
 pin_project! {
-    struct Connect {
+    /// Sends an HTTP request before the first write of the client, or a response
+    /// before the first write of the server, and skips the one of the other side.
+    struct HttpStream {
         inner: TcpStream,
         write: WriteState,
         read: ReadState,
         param: HttpSimple,
+        accept: bool,
     }
 }
 
-impl Connect {
-    fn new(tcp: TcpStream, param: HttpSimple) -> Connect {
-        Connect {
+impl HttpStream {
+    fn new(tcp: TcpStream, param: HttpSimple, accept: bool) -> HttpStream {
+        HttpStream {
             inner: tcp,
             write: WriteState::Wait,
             read: ReadState::Read(vec![0u8; 8192], 0),
             param,
+            accept,
         }
     }
+    fn header(&self, len: usize) -> io::Result<Vec<u8>> {
+        let major = thread_rng().next_u32() % 51;
+        let minor = thread_rng().next_u32() % 2;
+
+        let key_bytes: [u8; 16] = thread_rng().gen();
+        let key = STANDARD.encode(key_bytes);
+
+        let mut cursor = Cursor::new(Vec::<u8>::with_capacity(1024));
+        if self.accept {
+            cursor.write_fmt(format_args!(
+                "HTTP/1.1 101 Switching Protocols\r
+Server: nginx/1.{major}.{minor}\r
+Upgrade: websocket\r
+Connection: Upgrade\r
+Sec-WebSocket-Accept: {key}\r
+\r\n",
+            ))?;
+        } else {
+            cursor.write_fmt(format_args!(
+                "{method} {path} HTTP/1.1\r
+Host: {host}\r
+User-Agent: curl/7.{major}.{minor}\r
+Upgrade: websocket\r
+Connection: Upgrade\r
+Sec-WebSocket-Key: {key}\r
+Content-Length: {len}\r
+\r\n",
+                method = self.param.method,
+                path = self.param.uri,
+                host = self.param.host,
+            ))?;
+        }
+        Ok(cursor.into_inner())
+    }
 }
 
 #[async_trait]
-impl ITcpStream for Connect {
+impl ITcpStream for HttpStream {
     async fn peer_addr(&self) -> Result<std::net::SocketAddr> {
         self.inner.peer_addr().await
     }
@@ -129,32 +169,10 @@ impl ITcpStream for Connect {
         loop {
             match &mut self.write {
                 WriteState::Wait => {
-                    let major = thread_rng().next_u32() % 51;
-                    let minor = thread_rng().next_u32() % 2;
-
-                    let key_bytes: [u8; 16] = thread_rng().gen();
-                    let key = STANDARD.encode(key_bytes);
-
-                    let mut cursor = Cursor::new(Vec::<u8>::with_capacity(1024));
-                    cursor.write_fmt(format_args!(
-                        "{method} {path} HTTP/1.1\r
-Host: {host}\r
-User-Agent: curl/7.{major}.{minor}\r
-Upgrade: websocket\r
-Connection: Upgrade\r
-Sec-WebSocket-Key: {key}\r
-Content-Length: {len}\r
-\r\n",
-                        method = self.param.method,
-                        path = self.param.uri,
-                        host = self.param.host,
-                        len = buf.len(),
-                    ))?;
-                    cursor.write_all(buf)?;
-
-                    let buf = cursor.into_inner();
+                    let mut header = self.header(buf.len())?;
+                    header.extend_from_slice(buf);
 
-                    self.write = WriteState::Write(buf, 0);
+                    self.write = WriteState::Write(header, 0);
                 }
                 WriteState::Write(ref buf, pos) => {
                     let wrote = ready!(Pin::new(&mut self.inner).poll_write(cx, &buf[*pos..]))?;
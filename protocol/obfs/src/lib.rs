@@ -11,6 +11,7 @@ mod plain;
 
 impl Builder<Net> for ObfsNet {
     const NAME: &'static str = "obfs";
+    const TITLE: &'static str = "Obfuscation";
     type Config = ObfsNetConfig;
     type Item = ObfsNet;
 
@@ -56,3 +57,21 @@ impl Obfs for ObfsType {
         }
     }
 }
+
+/// Obfs stacked in order, the first one is the nearest to the transport.
+///
+/// Both sides wrap the stream in this order. Each wrapper undoes the one on the
+/// other side at the same depth, so the data written by the last wrapper of
+/// `tcp_connect` is read by the last wrapper of `tcp_accept`, and the layers are
+/// unwrapped in the reverse order of wrapping.
+impl<T: Obfs> Obfs for Vec<T> {
+    fn tcp_connect(&self, tcp: TcpStream, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        self.iter()
+            .try_fold(tcp, |tcp, obfs| obfs.tcp_connect(tcp, ctx, addr))
+    }
+
+    fn tcp_accept(&self, tcp: TcpStream, addr: SocketAddr) -> Result<TcpStream> {
+        self.iter()
+            .try_fold(tcp, |tcp, obfs| obfs.tcp_accept(tcp, addr))
+    }
+}
@@ -2,8 +2,8 @@ use std::net::SocketAddr;
 
 use crate::{Obfs, ObfsType};
 use rd_interface::{
-    async_trait, prelude::*, registry::NetRef, Address, Arc, Context, INet, ITcpListener, IntoDyn,
-    Net, Result, TcpListener, TcpStream,
+    async_trait, prelude::*, registry::NetRef, Address, Arc, Context, Error, INet, ITcpListener,
+    IntoDyn, Net, Result, TcpListener, TcpStream,
 };
 
 type BoxObfs = Arc<dyn Obfs + Send + Sync + 'static>;
@@ -14,19 +14,28 @@ pub struct ObfsNetConfig {
     #[serde(default)]
     pub net: NetRef,
     #[serde(flatten)]
-    pub obfs_type: ObfsType,
+    pub obfs_type: Option<ObfsType>,
+    /// Obfs stacked on `obfs_type`, applied in order.
+    /// e.g. `[{ "http": {...} }, { "plain": {} }]` wraps the stream with http first.
+    #[serde(default)]
+    pub chain: Vec<ObfsType>,
 }
 
 pub struct ObfsNet {
     net: Net,
-    obfs: Arc<ObfsType>,
+    obfs: Arc<Vec<ObfsType>>,
 }
 
 impl ObfsNet {
     pub fn new(config: ObfsNetConfig) -> Result<Self> {
+        let obfs: Vec<_> = config.obfs_type.into_iter().chain(config.chain).collect();
+        if obfs.is_empty() {
+            return Err(Error::other("obfs is not set"));
+        }
+
         Ok(ObfsNet {
             net: config.net.value_cloned(),
-            obfs: Arc::new(config.obfs_type),
+            obfs: Arc::new(obfs),
         })
     }
 }
@@ -55,6 +64,10 @@ impl INet for ObfsNet {
     fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
         Some(self)
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 struct ObfsTcpListener(TcpListener, BoxObfs);
@@ -73,7 +86,9 @@ impl ITcpListener for ObfsTcpListener {
 
 #[cfg(test)]
 mod tests {
+    use rd_interface::IntoAddress;
     use rd_std::tests::{assert_net_provider, ProviderCapability, TestNet};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
     use super::*;
 
@@ -83,7 +98,8 @@ mod tests {
 
         let obfs = ObfsNet::new(ObfsNetConfig {
             net: NetRef::new_with_value("test".into(), net.clone()),
-            obfs_type: ObfsType::Plain(Default::default()),
+            obfs_type: Some(ObfsType::Plain(Default::default())),
+            chain: Vec::new(),
         })
         .unwrap()
         .into_dyn();
@@ -97,4 +113,138 @@ mod tests {
             },
         );
     }
+
+    #[test]
+    fn test_no_obfs() {
+        let net = TestNet::new().into_dyn();
+
+        let result = ObfsNet::new(ObfsNetConfig {
+            net: NetRef::new_with_value("test".into(), net),
+            obfs_type: None,
+            chain: Vec::new(),
+        });
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chain() {
+        let net = TestNet::new().into_dyn();
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+
+        let listener = net.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+        let server = tokio::spawn(async move {
+            // unwrap the http request, then echo the body
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            while !buf.ends_with(b"hello") {
+                let mut chunk = [0u8; 1024];
+                let n = tcp.read(&mut chunk).await.unwrap();
+                assert_ne!(n, 0);
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            assert!(buf.starts_with(b"GET / HTTP/1.1\r\nHost: example.com\r\n"));
+
+            tcp.write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\nhello")
+                .await
+                .unwrap();
+        });
+
+        let obfs = ObfsNet::new(ObfsNetConfig {
+            net: NetRef::new_with_value("test".into(), net.clone()),
+            obfs_type: None,
+            chain: serde_json::from_value(serde_json::json!([
+                { "http": { "host": "example.com" } },
+                { "plain": null },
+            ]))
+            .unwrap(),
+        })
+        .unwrap()
+        .into_dyn();
+
+        let mut tcp = obfs.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        tcp.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        tcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_chain_round_trip() {
+        let net = TestNet::new().into_dyn();
+        let addr = "127.0.0.1:26668".into_address().unwrap();
+        let obfs = || {
+            ObfsNet::new(ObfsNetConfig {
+                net: NetRef::new_with_value("test".into(), net.clone()),
+                obfs_type: None,
+                chain: serde_json::from_value(serde_json::json!([
+                    { "http": { "host": "example.com" } },
+                    { "plain": null },
+                ]))
+                .unwrap(),
+            })
+            .unwrap()
+            .into_dyn()
+        };
+
+        // the server unwraps the stream by the same chain, then echoes
+        let listener = obfs().tcp_bind(&mut Context::new(), &addr).await.unwrap();
+        let server = tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 5];
+            tcp.read_exact(&mut buf).await.unwrap();
+            tcp.write_all(&buf).await.unwrap();
+            buf
+        });
+
+        let mut tcp = obfs()
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+        tcp.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        tcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        assert_eq!(&server.await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_http_host() {
+        let net = TestNet::new().into_dyn();
+        let addr = "127.0.0.1:26667".into_address().unwrap();
+
+        let listener = net.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+        let server = tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut buf = Vec::new();
+            while !buf.ends_with(b"hello") {
+                let mut chunk = [0u8; 1024];
+                let n = tcp.read(&mut chunk).await.unwrap();
+                assert_ne!(n, 0);
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            buf
+        });
+
+        let obfs = ObfsNet::new(ObfsNetConfig {
+            net: NetRef::new_with_value("test".into(), net.clone()),
+            obfs_type: serde_json::from_value(serde_json::json!({
+                "http": { "host": "cdn.example.com" }
+            }))
+            .unwrap(),
+            chain: Vec::new(),
+        })
+        .unwrap()
+        .into_dyn();
+
+        // the stream goes to the destination, while the request carries the override host
+        let mut tcp = obfs.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        tcp.write_all(b"hello").await.unwrap();
+
+        let request = String::from_utf8(server.await.unwrap()).unwrap();
+        assert!(request.contains("\r\nHost: cdn.example.com\r\n"));
+        assert!(!request.contains("127.0.0.1"));
+        assert!(request.ends_with("\r\n\r\nhello"));
+    }
 }
@@ -1,6 +1,6 @@
 use std::net::Ipv4Addr;
 
-use rd_interface::{config::Config, prelude::*};
+use rd_interface::prelude::*;
 use tokio_smoltcp::smoltcp::{phy::Medium, wire::IpCidr};
 
 #[rd_config]
@@ -20,19 +20,22 @@ pub struct TunTapConfig {
     pub host_addr: String,
 }
 
+#[rd_config]
+pub struct FdDeviceConfig {
+    /// an opened TUN file descriptor, e.g. the one from Android's `VpnService`.
+    /// it's duplicated, so the owner should keep it open until the net is dropped.
+    pub fd: i32,
+}
+
 #[rd_config]
 #[serde(untagged)]
-pub enum MaybeString<T>
-where
-    T: Config,
-{
+pub enum DeviceConfig {
     #[cfg(feature = "libpcap")]
     String(String),
-    Other(T),
+    Fd(FdDeviceConfig),
+    Other(TunTapConfig),
 }
 
-pub type DeviceConfig = MaybeString<TunTapConfig>;
-
 #[rd_config]
 #[derive(Clone, Copy)]
 pub enum Layer {
@@ -97,6 +100,23 @@ pub struct RawNetConfig {
 
     #[serde(default)]
     pub forward: bool,
+
+    /// Max TCP or UDP flows tracked when `forward` is enabled, the least recently
+    /// active flow is broken when it's full.
+    #[serde(default = "default_nat_size")]
+    pub nat_size: usize,
+
+    /// Seconds a UDP flow is kept without any packet when `forward` is enabled.
+    #[serde(default = "default_udp_timeout")]
+    pub udp_timeout: u64,
+}
+
+fn default_nat_size() -> usize {
+    1024
+}
+
+fn default_udp_timeout() -> u64 {
+    30
 }
 
 pub struct TunTapSetup {
@@ -7,11 +7,15 @@ use rd_interface::{Error, Result};
 use tokio_smoltcp::smoltcp::wire::{EthernetAddress, IpCidr};
 
 mod boxed;
+#[cfg(unix)]
+mod fd;
 mod interface_info;
 #[cfg(unix)]
 mod unix;
 #[cfg(unix)]
 use crate::device::unix::get_tun;
+#[cfg(all(test, unix))]
+pub(crate) use fd::FdDevice;
 
 #[cfg(feature = "libpcap")]
 mod pcap_dev;
@@ -59,6 +63,15 @@ pub fn get_device(config: &RawNetConfig) -> Result<(EthernetAddress, BoxedAsyncD
 
             (interface_info.ethernet_address, BoxedAsyncDevice(device))
         }
+        #[cfg(unix)]
+        DeviceConfig::Fd(cfg) => {
+            let device = Box::new(fd::FdDevice::new(cfg.fd, config.mtu)?);
+            (EthernetAddress::BROADCAST, BoxedAsyncDevice(device))
+        }
+        #[cfg(windows)]
+        DeviceConfig::Fd(_) => {
+            return Err(Error::Other("fd device is not supported on Windows".into()))
+        }
         DeviceConfig::Other(cfg) => {
             let host_addr = Ipv4Addr::from_str(&cfg.host_addr)
                 .map_err(|_| Error::Other("Failed to parse host_addr".into()))?;
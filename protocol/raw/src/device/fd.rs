@@ -0,0 +1,119 @@
+use std::{
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::{ready, Sink, Stream};
+use nix::{
+    fcntl::{fcntl, FcntlArg, OFlag},
+    unistd::{dup, read, write},
+};
+use rd_interface::{error::map_other, Error, Result};
+use tokio::io::unix::AsyncFd;
+use tokio_smoltcp::{
+    device::{AsyncDevice, DeviceCapabilities, Packet},
+    smoltcp::phy::{Checksum, Medium},
+};
+
+/// A TUN device opened by others, e.g. the one of Android's `VpnService`. Each read
+/// or write is an IP packet, so it can be a datagram socket as well.
+pub struct FdDevice {
+    fd: AsyncFd<OwnedFd>,
+    caps: DeviceCapabilities,
+    send_buf: Option<Packet>,
+}
+
+impl FdDevice {
+    /// Uses a duplicate of `fd`, so `fd` stays open when the device is dropped and
+    /// the net can be built again from the same config.
+    pub fn new(fd: RawFd, mtu: usize) -> Result<FdDevice> {
+        if fd < 0 {
+            return Err(Error::other(format!("Invalid fd: {}", fd)));
+        }
+        let fd = dup(fd).map_err(map_other)?;
+        // SAFETY: the fd is just duplicated, nothing else owns it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let flags =
+            OFlag::from_bits_truncate(fcntl(fd.as_raw_fd(), FcntlArg::F_GETFL).map_err(map_other)?);
+        fcntl(fd.as_raw_fd(), FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK)).map_err(map_other)?;
+        let fd = AsyncFd::new(fd)?;
+
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ip;
+        caps.max_transmission_unit = mtu;
+        caps.checksum.ipv4 = Checksum::Tx;
+        caps.checksum.tcp = Checksum::Tx;
+        caps.checksum.udp = Checksum::Tx;
+        caps.checksum.icmpv4 = Checksum::Tx;
+        caps.checksum.icmpv6 = Checksum::Tx;
+
+        Ok(FdDevice {
+            fd,
+            caps,
+            send_buf: None,
+        })
+    }
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while let Some(packet) = &self.send_buf {
+            let mut guard = ready!(self.fd.poll_write_ready(cx))?;
+            match guard.try_io(|fd| write(fd.as_raw_fd(), packet).map_err(io::Error::from)) {
+                Ok(result) => {
+                    self.send_buf = None;
+                    result?;
+                }
+                Err(_would_block) => continue,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Stream for FdDevice {
+    type Item = io::Result<Packet>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut buf = vec![0u8; self.caps.max_transmission_unit];
+        loop {
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+            match guard.try_io(|fd| read(fd.as_raw_fd(), &mut buf).map_err(io::Error::from)) {
+                // closed, e.g. the other end of the socket
+                Ok(Ok(0)) => return Poll::Ready(None),
+                Ok(Ok(size)) => {
+                    buf.truncate(size);
+                    return Poll::Ready(Some(Ok(buf)));
+                }
+                Ok(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl Sink<Packet> for FdDevice {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_send(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Packet) -> Result<(), Self::Error> {
+        self.send_buf = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_send(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_send(cx)
+    }
+}
+
+impl AsyncDevice for FdDevice {
+    fn capabilities(&self) -> &DeviceCapabilities {
+        &self.caps
+    }
+}
@@ -1,32 +1,37 @@
 use std::{
     io,
     net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
 };
 
-use rd_interface::{Arc, Context, IntoAddress, Net, Result, TcpStream};
-use rd_std::{util::forward_udp, ContextExt};
-use tokio::select;
+use rd_interface::{Context, IntoAddress, Net, Result, TcpStream};
+use rd_std::{
+    util::{forward_udp, is_transient_accept_error, ACCEPT_BACKOFF},
+    ContextExt,
+};
+use tokio::{select, time::sleep};
 use tokio_smoltcp::{
     smoltcp::wire::{IpCidr, IpProtocol, IpVersion},
-    Net as SmoltcpNet, RawSocket, TcpListener,
+    RawSocket, TcpListener,
 };
 
-use crate::gateway::MapTable;
+use crate::{gateway::MapTable, net::NetParams};
+
+pub(crate) use bypass::{Bypass, BypassNet};
 
+mod bypass;
 mod source;
 
 struct Forward {
     net: Net,
     map: MapTable,
     ip_cidr: IpCidr,
+    nat_size: usize,
+    udp_timeout: Duration,
 }
 
-pub async fn forward_net(
-    net: Net,
-    smoltcp_net: Arc<SmoltcpNet>,
-    map: MapTable,
-    ip_cidr: IpCidr,
-) -> io::Result<()> {
+pub(crate) async fn forward_net(net: Net, params: &NetParams) -> io::Result<()> {
+    let smoltcp_net = &params.smoltcp_net;
     let tcp_listener = smoltcp_net
         .tcp_bind(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 1).into())
         .await?;
@@ -34,7 +39,13 @@ pub async fn forward_net(
         .raw_socket(IpVersion::Ipv4, IpProtocol::Udp)
         .await?;
 
-    let forward = Forward { net, map, ip_cidr };
+    let forward = Forward {
+        net,
+        map: params.map.clone(),
+        ip_cidr: params.ip_cidr,
+        nat_size: params.nat_size,
+        udp_timeout: params.udp_timeout,
+    };
 
     let tcp_task = forward.serve_tcp(tcp_listener);
     let udp_task = forward.serve_udp(raw_socket);
@@ -50,29 +61,213 @@ pub async fn forward_net(
 impl Forward {
     async fn serve_tcp(&self, mut listener: TcpListener) -> Result<()> {
         loop {
-            let (tcp, addr) = listener.accept().await?;
+            let (tcp, addr) = match listener.accept().await {
+                Ok(v) => v,
+                Err(e) if is_transient_accept_error(&e) => {
+                    tracing::warn!("Failed to accept, retry in {:?}: {:?}", ACCEPT_BACKOFF, e);
+                    sleep(ACCEPT_BACKOFF).await;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
             let orig_addr = self.map.get(&match addr {
                 SocketAddr::V4(v4) => v4,
                 _ => continue,
             });
-            if let Some(orig_addr) = orig_addr {
-                let net = self.net.clone();
-                tokio::spawn(async move {
-                    let ctx = &mut Context::from_socketaddr(addr);
+            let orig_addr = match orig_addr {
+                Some(orig_addr) => orig_addr,
+                None => {
+                    tracing::debug!("No original address of the flow from {}", addr);
+                    continue;
+                }
+            };
+            let net = self.net.clone();
+            tokio::spawn(async move {
+                let ctx = &mut Context::from_socketaddr(addr);
+                let result = async {
                     let target = net
                         .tcp_connect(ctx, &SocketAddr::from(orig_addr).into_address()?)
                         .await?;
                     ctx.connect_tcp(TcpStream::from(tcp), target).await?;
                     Ok(()) as Result<()>
-                });
-            }
+                }
+                .await;
+                if let Err(e) = result {
+                    tracing::debug!("Failed to forward {} -> {}: {:?}", addr, orig_addr, e);
+                }
+            });
         }
     }
     async fn serve_udp(&self, raw: RawSocket) -> Result<()> {
         let source = source::Source::new(raw, self.ip_cidr);
 
-        forward_udp::forward_udp(source, self.net.clone(), None).await?;
+        forward_udp::forward_udp_with_ttl(
+            source,
+            self.net.clone(),
+            None,
+            self.udp_timeout,
+            self.nat_size,
+        )
+        .await?;
 
         Ok(())
     }
 }
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{os::unix::io::AsRawFd, os::unix::net::UnixDatagram};
+
+    use rd_interface::{registry::Builder, Arc, IntoDyn};
+    use rd_std::{
+        tests::{spawn_echo_server, spawn_echo_server_udp, TestNet},
+        util::NotImplementedNet,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::timeout,
+    };
+    use tokio_smoltcp::{
+        smoltcp::wire::{EthernetAddress, IpAddress},
+        BufferSize, Net as SmoltcpNet, NetConfig,
+    };
+
+    use super::*;
+    use crate::{
+        config::{DeviceConfig, FdDeviceConfig, RawNetConfig},
+        device::FdDevice,
+        net::RawNet,
+    };
+
+    /// Forwards the flows of the returned host net to the echo servers at 1.1.1.1:80
+    /// over TCP and 1.1.1.1:53 over UDP. The fds are kept open by the returned pair.
+    /// If `bypass`, the echo servers are only reachable by bypassing the tunnel.
+    async fn tun2socks(
+        udp_timeout: u64,
+        bypass: bool,
+    ) -> (SmoltcpNet, (UnixDatagram, UnixDatagram)) {
+        // each end of the pair acts as a TUN device
+        let (tun, host) = UnixDatagram::pair().unwrap();
+
+        let raw = RawNet::build(RawNetConfig {
+            device: DeviceConfig::Fd(FdDeviceConfig {
+                fd: tun.as_raw_fd(),
+            }),
+            gateway: None,
+            ip_addr: "10.0.0.2/24".to_string(),
+            ethernet_addr: None,
+            mtu: 1500,
+            forward: true,
+            nat_size: 16,
+            udp_timeout,
+        })
+        .unwrap();
+        let params = raw.get_params().unwrap();
+
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "1.1.1.1:80").await;
+        spawn_echo_server_udp(&net, "1.1.1.1:53").await;
+        let net = if bypass {
+            let bypass = Bypass::new(vec![IpCidr::new(IpAddress::v4(1, 1, 1, 1), 32)]);
+            BypassNet::new(NotImplementedNet.into_dyn(), net, Arc::new(bypass)).into_dyn()
+        } else {
+            net
+        };
+        tokio::spawn(async move { forward_net(net, &params).await });
+
+        // the host routes everything to the TUN
+        let host_net = SmoltcpNet::new(
+            FdDevice::new(host.as_raw_fd(), 1500).unwrap(),
+            NetConfig {
+                ethernet_addr: EthernetAddress::BROADCAST,
+                ip_addr: IpCidr::new(IpAddress::v4(10, 0, 0, 1), 24),
+                gateway: vec![IpAddress::v4(10, 0, 0, 2)],
+                buffer_size: BufferSize::default(),
+            },
+        );
+
+        (host_net, (tun, host))
+    }
+
+    #[test]
+    fn test_invalid_fd() {
+        assert!(FdDevice::new(-1, 1500).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tun2socks() {
+        let (host, _fds) = tun2socks(30, false).await;
+
+        let echo = async {
+            let mut tcp = host
+                .tcp_connect("1.1.1.1:80".parse().unwrap())
+                .await
+                .unwrap();
+            for data in [&b"hello"[..], &[0xaa; 8192][..]] {
+                tcp.write_all(data).await.unwrap();
+                let mut buf = vec![0u8; data.len()];
+                tcp.read_exact(&mut buf).await.unwrap();
+                assert_eq!(buf, data);
+            }
+        };
+        timeout(Duration::from_secs(10), echo).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tun2socks_udp() {
+        let (host, _fds) = tun2socks(1, false).await;
+        let udp = host
+            .udp_bind("0.0.0.0:5353".parse().unwrap())
+            .await
+            .unwrap();
+        let target: SocketAddr = "1.1.1.1:53".parse().unwrap();
+
+        let echo = |data: &'static [u8]| {
+            let udp = &udp;
+            async move {
+                udp.send_to(data, target).await.unwrap();
+                let mut buf = [0u8; 64];
+                let (size, from) = udp.recv_from(&mut buf).await.unwrap();
+                assert_eq!(&buf[..size], data);
+                assert_eq!(from, target);
+            }
+        };
+        timeout(Duration::from_secs(10), echo(b"hello"))
+            .await
+            .unwrap();
+
+        // the idle flow is closed, the next packet opens a new one
+        sleep(Duration::from_millis(1500)).await;
+        timeout(Duration::from_secs(10), echo(b"again"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bypass() {
+        let (host, _fds) = tun2socks(30, true).await;
+
+        let echo = async {
+            let mut tcp = host
+                .tcp_connect("1.1.1.1:80".parse().unwrap())
+                .await
+                .unwrap();
+            tcp.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            tcp.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+
+            let udp = host
+                .udp_bind("0.0.0.0:5353".parse().unwrap())
+                .await
+                .unwrap();
+            udp.send_to(b"hello", "1.1.1.1:53".parse().unwrap())
+                .await
+                .unwrap();
+            let mut buf = [0u8; 64];
+            let (size, _) = udp.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..size], b"hello");
+        };
+        timeout(Duration::from_secs(10), echo).await.unwrap();
+    }
+}
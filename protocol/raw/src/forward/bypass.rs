@@ -0,0 +1,198 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    task::{self, Poll},
+};
+
+use parking_lot::RwLock;
+use rd_interface::{
+    async_trait, Address, Arc, Context, INet, IUdpSocket, IntoDyn, Net, Result, TcpStream,
+    UdpSocket,
+};
+use rd_std::util::UdpConnector;
+use tokio_smoltcp::smoltcp::wire::IpCidr;
+
+/// The destinations whose flows go through `bypass_net` instead of `net`.
+pub struct Bypass {
+    cidrs: Vec<IpCidr>,
+    /// The addresses of the servers, replaced on each resolve.
+    resolved: RwLock<Vec<Ipv4Addr>>,
+}
+
+impl Bypass {
+    pub fn new(cidrs: Vec<IpCidr>) -> Bypass {
+        Bypass {
+            cidrs,
+            resolved: RwLock::new(Vec::new()),
+        }
+    }
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // RawNet only support IPv4
+        let v4 = match ip {
+            IpAddr::V4(v4) => v4,
+            IpAddr::V6(_) => return false,
+        };
+        self.cidrs.iter().any(|cidr| cidr.contains_addr(&v4.into()))
+            || self.resolved.read().contains(&v4)
+    }
+    fn contains_addr(&self, addr: &Address) -> bool {
+        match addr {
+            Address::SocketAddr(addr) => self.contains(addr.ip()),
+            Address::Domain(..) => false,
+        }
+    }
+    /// Resolves `servers` by `net` and bypasses their addresses instead of the
+    /// ones of the last resolve.
+    pub async fn resolve(&self, net: &Net, servers: &[Address]) {
+        let mut resolved = Vec::new();
+        for server in servers {
+            let addrs = match net.lookup_host(&mut Context::new(), server).await {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    tracing::warn!("Failed to resolve bypass server {}: {:?}", server, e);
+                    continue;
+                }
+            };
+            for addr in addrs {
+                if let IpAddr::V4(v4) = addr.ip() {
+                    if !resolved.contains(&v4) {
+                        tracing::debug!("bypass {} for {}", v4, server);
+                        resolved.push(v4);
+                    }
+                }
+            }
+        }
+        *self.resolved.write() = resolved;
+    }
+}
+
+/// A Net connects the bypassed destinations by `bypass_net`, the others by `net`.
+pub struct BypassNet {
+    net: Net,
+    bypass_net: Net,
+    bypass: Arc<Bypass>,
+}
+
+impl BypassNet {
+    pub fn new(net: Net, bypass_net: Net, bypass: Arc<Bypass>) -> BypassNet {
+        BypassNet {
+            net,
+            bypass_net,
+            bypass,
+        }
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for BypassNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        if self.bypass.contains_addr(addr) {
+            self.bypass_net.tcp_connect(ctx, addr).await
+        } else {
+            self.net.tcp_connect(ctx, addr).await
+        }
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for BypassNet {
+    async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
+        Ok(BypassUdp {
+            net: lazy_bind(self.net.clone(), ctx.clone(), addr.clone()),
+            bypass_net: lazy_bind(self.bypass_net.clone(), ctx.clone(), addr.clone()),
+            bypass: self.bypass.clone(),
+        }
+        .into_dyn())
+    }
+}
+
+impl INet for BypassNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        Some(self)
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+fn lazy_bind(net: Net, mut ctx: Context, addr: Address) -> UdpConnector {
+    UdpConnector::new(Box::new(move |_, _| {
+        Box::pin(async move { net.udp_bind(&mut ctx, &addr).await })
+    }))
+}
+
+/// Sends to the bypassed destinations by the socket of `bypass_net`, each socket is
+/// bound on its first packet.
+struct BypassUdp {
+    net: UdpConnector,
+    bypass_net: UdpConnector,
+    bypass: Arc<Bypass>,
+}
+
+#[async_trait]
+impl IUdpSocket for BypassUdp {
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut rd_interface::ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        if let Poll::Ready(r) = self.net.poll_recv_from(cx, buf) {
+            return Poll::Ready(r);
+        }
+        self.bypass_net.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &Address,
+    ) -> Poll<io::Result<usize>> {
+        if self.bypass.contains_addr(target) {
+            self.bypass_net.poll_send_to(cx, buf, target)
+        } else {
+            self.net.poll_send_to(cx, buf, target)
+        }
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.net.local_addr().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::IntoDyn;
+    use rd_std::tests::TestNet;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let bypass = Bypass::new(vec![IpCidr::new(Ipv4Addr::new(1, 1, 1, 0).into(), 24)]);
+        let net = TestNet::new().into_dyn();
+        let servers = [
+            "example.com:443".parse().unwrap(),
+            "127.0.0.1:443".parse().unwrap(),
+        ];
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        assert!(!bypass.contains(localhost));
+
+        bypass.resolve(&net, &servers).await;
+        bypass.resolve(&net, &servers).await;
+        assert!(bypass.contains(localhost));
+        assert!(bypass.contains(IpAddr::from([1, 1, 1, 1])));
+        // the same address is bypassed once, not once per server and resolve
+        assert_eq!(*bypass.resolved.read(), [Ipv4Addr::LOCALHOST]);
+
+        // the servers gone are no longer bypassed
+        bypass.resolve(&net, &[]).await;
+        assert!(!bypass.contains(localhost));
+        assert!(bypass.contains(IpAddr::from([1, 1, 1, 1])));
+    }
+}
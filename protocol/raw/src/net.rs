@@ -1,6 +1,7 @@
 use std::{
     net::{IpAddr, SocketAddrV4},
     str::FromStr,
+    time::Duration,
 };
 
 use crate::{
@@ -23,6 +24,8 @@ pub(crate) struct NetParams {
     pub(crate) smoltcp_net: Arc<SmoltcpNet>,
     pub(crate) map: MapTable,
     pub(crate) ip_cidr: IpCidr,
+    pub(crate) nat_size: usize,
+    pub(crate) udp_timeout: Duration,
 }
 
 pub struct RawNet {
@@ -65,7 +68,8 @@ impl RawNet {
 
         let mut params = None;
         let smoltcp_net = if config.forward {
-            let device = GatewayDevice::new(device, ethernet_addr, 100, ip_cidr, ip_addr);
+            let device =
+                GatewayDevice::new(device, ethernet_addr, config.nat_size, ip_cidr, ip_addr);
             let map = device.get_map();
             let smoltcp_net = Arc::new(SmoltcpNet::new(device, net_config));
 
@@ -73,6 +77,8 @@ impl RawNet {
                 smoltcp_net: smoltcp_net.clone(),
                 map,
                 ip_cidr,
+                nat_size: config.nat_size,
+                udp_timeout: Duration::from_secs(config.udp_timeout),
             });
             smoltcp_net
         } else {
@@ -91,6 +97,7 @@ impl RawNet {
 
 impl Builder<Net> for RawNet {
     const NAME: &'static str = "raw";
+    const TITLE: &'static str = "Raw Device";
 
     type Config = RawNetConfig;
     type Item = RawNet;
@@ -1,11 +1,15 @@
+use std::str::FromStr;
+
 use crate::{
-    forward::forward_net,
+    forward::{forward_net, Bypass, BypassNet},
     net::{NetParams, RawNet},
 };
 use rd_interface::{
-    async_trait, config::NetRef, prelude::*, rd_config, registry::Builder, Error, IServer, Net,
-    Result, Server,
+    async_trait, config::NetRef, prelude::*, rd_config, registry::Builder, Address, Arc, Error,
+    IServer, IntoDyn, Net, Result, Server,
 };
+use rd_std::util::DropAbort;
+use tokio_smoltcp::smoltcp::wire::IpCidr;
 
 #[rd_config]
 pub struct RawServerConfig {
@@ -13,24 +17,40 @@ pub struct RawServerConfig {
     net: NetRef,
     /// Must be raw net.
     listen: NetRef,
+    /// The net of the bypassed flows. It should go around the tunnel, e.g. a `local`
+    /// net bound to the physical interface, or the flows loop back.
+    #[serde(default)]
+    bypass_net: NetRef,
+    /// IP Cidrs whose traffic goes through `bypass_net`.
+    #[serde(default)]
+    bypass: Vec<String>,
+    /// Servers whose traffic goes through `bypass_net`. The upstream proxy servers
+    /// of `net` are added when the config is loaded. Domains are resolved by
+    /// `bypass_net` when the server starts.
+    #[serde(default)]
+    bypass_server: Vec<Address>,
 }
 
 pub struct RawServer {
     net: Net,
+    bypass_net: Net,
+    bypass: Arc<Bypass>,
+    bypass_server: Vec<Address>,
     params: NetParams,
 }
 
 #[async_trait]
 impl IServer for RawServer {
     async fn start(&self) -> rd_interface::Result<()> {
-        let params = &self.params;
-        forward_net(
-            self.net.clone(),
-            params.smoltcp_net.clone(),
-            params.map.clone(),
-            params.ip_cidr,
-        )
-        .await?;
+        // the flows to the servers go through `net` until they are resolved
+        let bypass = self.bypass.clone();
+        let bypass_net = self.bypass_net.clone();
+        let bypass_server = self.bypass_server.clone();
+        let _resolve = DropAbort::new(tokio::spawn(async move {
+            bypass.resolve(&bypass_net, &bypass_server).await
+        }));
+
+        forward_net(self.net.clone(), &self.params).await?;
 
         Ok(())
     }
@@ -38,7 +58,22 @@ impl IServer for RawServer {
 
 impl RawServer {
     fn new(config: RawServerConfig) -> Result<Self> {
-        let net = config.net.value_cloned();
+        let bypass_net = config.bypass_net.value_cloned();
+        let bypass = config
+            .bypass
+            .iter()
+            .map(|cidr| {
+                IpCidr::from_str(cidr)
+                    .map_err(|_| Error::Other(format!("Failed to parse bypass: {}", cidr).into()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let bypass = Arc::new(Bypass::new(bypass));
+        let net = BypassNet::new(
+            config.net.value_cloned(),
+            bypass_net.clone(),
+            bypass.clone(),
+        )
+        .into_dyn();
         let listen = config.listen.value_cloned();
         let raw_net = listen
             .get_inner_net_by::<RawNet>()
@@ -47,12 +82,19 @@ impl RawServer {
             .get_params()
             .ok_or_else(|| Error::other("The `raw` net must has forward enabled."))?;
 
-        Ok(RawServer { net, params })
+        Ok(RawServer {
+            net,
+            bypass_net,
+            bypass,
+            bypass_server: config.bypass_server,
+            params,
+        })
     }
 }
 
 impl Builder<Server> for RawServer {
     const NAME: &'static str = "raw";
+    const TITLE: &'static str = "Raw Device Forwarding";
 
     type Config = RawServerConfig;
     type Item = RawServer;
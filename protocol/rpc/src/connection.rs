@@ -1,7 +1,7 @@
 use std::{fmt::Debug, io};
 
 use crate::types::{Request, Response};
-use rd_interface::TcpStream;
+use rd_interface::{Error, Result, TcpStream};
 use serde::{de::DeserializeOwned, Serialize};
 use tokio::{
     io::{split, AsyncReadExt, AsyncWriteExt},
@@ -15,13 +15,71 @@ use tokio::{
 // 1MB
 const MAX_ITEM_SIZE: u32 = 1 * 1024 * 1024;
 const MAX_DATA_SIZE: u32 = 1 * 1024 * 1024;
-
-#[derive(Copy, Clone)]
+/// Max size of the tunneled stream data in one frame. Each stream has at most
+/// one read and one write in flight, so the memory used by a stream is bounded
+/// no matter how much data is transferred.
+pub const MAX_CHUNK_SIZE: usize = 64 * 1024;
+/// The version of the handshake and the framing, sent before the codec id. The
+/// unversioned clients start with a frame size, whose first byte is always 0.
+///
+/// Version 2 sends the context with `Command::LookupHost`.
+pub const PROTOCOL_VERSION: u8 = 2;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Codec {
     Json,
     Cbor,
+    MessagePack,
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::Json => 0,
+            Codec::Cbor => 1,
+            Codec::MessagePack => 2,
+        }
+    }
+    fn from_id(id: u8) -> Option<Codec> {
+        match id {
+            0 => Some(Codec::Json),
+            1 => Some(Codec::Cbor),
+            2 => Some(Codec::MessagePack),
+            _ => None,
+        }
+    }
+    /// Tell the server the protocol version and which codec is used on this connection.
+    pub async fn advertise(self, tcp: &mut TcpStream) -> Result<()> {
+        tcp.write_all(&[PROTOCOL_VERSION, self.id()]).await?;
+        Ok(())
+    }
+    /// Read the codec advertised by the client. If `accept` is set, other codecs
+    /// are rejected.
+    pub async fn negotiate(tcp: &mut TcpStream, accept: Option<Codec>) -> Result<Codec> {
+        let version = tcp.read_u8().await?;
+        if version != PROTOCOL_VERSION {
+            return Err(Error::other(format!(
+                "Unsupported protocol version: {}, expected {}",
+                version, PROTOCOL_VERSION
+            )));
+        }
+        let id = tcp.read_u8().await?;
+        let codec =
+            Codec::from_id(id).ok_or_else(|| Error::other(format!("Unknown codec id: {}", id)))?;
+
+        match accept {
+            Some(accept) if accept != codec => Err(Error::other(format!(
+                "Codec {:?} is not accepted, expected {:?}",
+                codec, accept
+            ))),
+            _ => Ok(codec),
+        }
+    }
 }
 
+/// Each frame is the 4-byte big-endian size of the encoded item and of the data,
+/// followed by the item and then the data. The frames are never scanned for a
+/// delimiter, so the binary codecs and the data may contain any bytes.
 pub struct Connection<Item, SinkItem> {
     read_rx: Mutex<Receiver<(Item, Vec<u8>)>>,
     write_tx: Sender<(SinkItem, Option<Vec<u8>>)>,
@@ -74,6 +132,7 @@ where
                 let item = match codec {
                     Codec::Cbor => cbor4ii::serde::from_slice(&item_buf).map_err(map_err)?,
                     Codec::Json => serde_json::from_slice(&item_buf).map_err(map_err)?,
+                    Codec::MessagePack => rmp_serde::from_slice(&item_buf).map_err(map_err)?,
                 };
 
                 // No receiver, exit normally
@@ -89,6 +148,7 @@ where
                 let item_buf = match codec {
                     Codec::Cbor => cbor4ii::serde::to_vec(Vec::new(), &item).map_err(map_err)?,
                     Codec::Json => serde_json::to_vec(&item).map_err(map_err)?,
+                    Codec::MessagePack => rmp_serde::to_vec_named(&item).map_err(map_err)?,
                 };
                 let data_size = data.as_ref().map(|d| d.len() as u32).unwrap_or(0);
                 let item_size = item_buf.len() as u32;
@@ -1,9 +1,10 @@
-use net::RpcNet;
+pub use net::RpcNet;
 use rd_interface::{
     config::NetRef, prelude::*, rd_config, registry::Builder, Address, Net, Registry, Result,
     Server,
 };
-use server::RpcServer;
+pub use server::RpcServer;
+pub use types::ControlMessage;
 
 mod connection;
 mod net;
@@ -18,6 +19,7 @@ mod types;
 pub enum Codec {
     Json,
     Cbor,
+    MessagePack,
 }
 
 impl Default for Codec {
@@ -31,6 +33,7 @@ impl From<Codec> for connection::Codec {
         match this {
             Codec::Json => connection::Codec::Json,
             Codec::Cbor => connection::Codec::Cbor,
+            Codec::MessagePack => connection::Codec::MessagePack,
         }
     }
 }
@@ -42,6 +45,9 @@ pub struct RpcNetConfig {
     server: Address,
     #[serde(default)]
     codec: Codec,
+    /// receive the control messages pushed by the server, e.g. config changes and health
+    #[serde(default)]
+    control: bool,
 }
 
 #[rd_config]
@@ -52,12 +58,14 @@ pub struct RpcServerConfig {
     net: NetRef,
     #[serde(default)]
     listen: NetRef,
+    /// only accept this codec. By default any codec the client uses is accepted.
     #[serde(default)]
-    codec: Codec,
+    codec: Option<Codec>,
 }
 
 impl Builder<Net> for RpcNet {
     const NAME: &'static str = "rpc";
+    const TITLE: &'static str = "RPC Client";
     type Config = RpcNetConfig;
     type Item = Self;
 
@@ -67,12 +75,14 @@ impl Builder<Net> for RpcNet {
             config.server,
             true,
             config.codec.into(),
-        ))
+        )
+        .control(config.control))
     }
 }
 
 impl Builder<Server> for RpcServer {
     const NAME: &'static str = "rpc";
+    const TITLE: &'static str = "RPC Server";
     type Config = RpcServerConfig;
     type Item = Self;
 
@@ -88,7 +98,7 @@ impl Builder<Server> for RpcServer {
             listen.value_cloned(),
             net.value_cloned(),
             bind,
-            codec.into(),
+            codec.map(Into::into),
         ))
     }
 }
@@ -1,12 +1,14 @@
-use crate::types::Command;
+use crate::types::{Command, ControlMessage, CONTROL_VERSION};
 use crate::{connection::Codec, session::ClientSession};
 
 use self::socket::TcpListenerWrapper;
 
+use parking_lot::Mutex as SyncMutex;
 use rd_interface::{async_trait, Address, Context, INet, IntoDyn, Net, Result, TcpStream};
 
+use rd_std::util::DropAbort;
 use socket::{TcpWrapper, UdpWrapper};
-use tokio::sync::{Mutex, OnceCell};
+use tokio::sync::{broadcast, Mutex, OnceCell};
 
 mod socket;
 
@@ -17,6 +19,41 @@ pub struct RpcNet {
 
     sess: Mutex<OnceCell<Result<ClientSession>>>,
     codec: Codec,
+    control: Option<broadcast::Sender<ControlMessage>>,
+    /// Receives the control messages of the current session.
+    control_task: SyncMutex<Option<DropAbort<()>>>,
+}
+
+/// The control messages buffered for the subscribers, the older ones are skipped.
+const CONTROL_CAPACITY: usize = 16;
+
+/// Long polls the control messages of `sess` until it fails, e.g. the session is closed.
+/// The session is reconnected after the server reports it's unhealthy.
+async fn receive_control(sess: ClientSession, tx: broadcast::Sender<ControlMessage>) {
+    loop {
+        let result = async {
+            let (resp, _) = sess
+                .send(Command::Control(CONTROL_VERSION), None)
+                .await?
+                .wait()
+                .await?;
+            resp.into_value::<ControlMessage>()
+        }
+        .await;
+        match result {
+            Ok(msg) => {
+                tracing::debug!("Control message: {:?}", msg);
+                if let ControlMessage::Health { healthy: false, .. } = msg {
+                    sess.mark_closed();
+                }
+                let _ = tx.send(msg);
+            }
+            Err(e) => {
+                tracing::warn!("Control channel stopped: {:?}", e);
+                break;
+            }
+        }
+    }
 }
 
 impl RpcNet {
@@ -27,16 +64,39 @@ impl RpcNet {
             auto_reconnect,
             sess: Mutex::new(OnceCell::new()),
             codec,
+            control: None,
+            control_task: SyncMutex::new(None),
+        }
+    }
+    /// Receive the control messages pushed by the server, it's resumed when the
+    /// session is reconnected.
+    pub fn control(mut self, enabled: bool) -> Self {
+        self.control = enabled.then(|| broadcast::channel(CONTROL_CAPACITY).0);
+        self
+    }
+    /// The control messages received from now on, `None` if the control channel is disabled.
+    pub fn subscribe_control(&self) -> Option<broadcast::Receiver<ControlMessage>> {
+        self.control.as_ref().map(|tx| tx.subscribe())
+    }
+    async fn new_sess(&self) -> Result<ClientSession> {
+        let sess = ClientSession::new(&self.net, &self.endpoint, self.codec).await?;
+        if let Some(tx) = &self.control {
+            if sess.capabilities().control == Some(CONTROL_VERSION) {
+                let task = tokio::spawn(receive_control(sess.clone(), tx.clone()));
+                *self.control_task.lock() = Some(DropAbort::new(task));
+            } else {
+                tracing::warn!(
+                    "The server doesn't support the control channel version {}",
+                    CONTROL_VERSION
+                );
+            }
         }
+        Ok(sess)
     }
     pub async fn get_sess(&self) -> Result<ClientSession> {
         let mut sess = self.sess.lock().await;
         Ok(loop {
-            let client_sess = sess
-                .get_or_init(|| ClientSession::new(&self.net, &self.endpoint, self.codec))
-                .await
-                .as_ref()
-                .cloned();
+            let client_sess = sess.get_or_init(|| self.new_sess()).await.as_ref().cloned();
             let client_sess = match client_sess {
                 Ok(s) => s,
                 Err(e) => {
@@ -121,9 +181,15 @@ impl rd_interface::UdpBind for RpcNet {
 
 #[async_trait]
 impl rd_interface::LookupHost for RpcNet {
-    async fn lookup_host(&self, addr: &Address) -> Result<Vec<std::net::SocketAddr>> {
+    async fn lookup_host(
+        &self,
+        ctx: &mut Context,
+        addr: &Address,
+    ) -> Result<Vec<std::net::SocketAddr>> {
         let conn = self.get_sess().await?;
-        let getter = conn.send(Command::LookupHost(addr.clone()), None).await?;
+        let getter = conn
+            .send(Command::LookupHost(ctx.to_value(), addr.clone()), None)
+            .await?;
 
         let (resp, _) = getter.wait().await?;
 
@@ -147,6 +213,10 @@ impl INet for RpcNet {
     fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
         Some(self)
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 #[cfg(test)]
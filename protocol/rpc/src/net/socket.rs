@@ -4,7 +4,10 @@ use std::{
     task::{self, Poll},
 };
 
-use crate::types::{Command, Object};
+use crate::{
+    connection::MAX_CHUNK_SIZE,
+    types::{Command, Object},
+};
 
 use crate::session::ClientSession;
 use futures::ready;
@@ -187,7 +190,10 @@ impl AsyncFnRead for TcpAsyncFn {
     async fn read(&mut self, buf_size: usize) -> io::Result<Vec<u8>> {
         let getter = self
             .conn
-            .send(Command::Read(self.obj, buf_size as u32), None)
+            .send(
+                Command::Read(self.obj, buf_size.min(MAX_CHUNK_SIZE) as u32),
+                None,
+            )
             .await?;
 
         let (resp, data) = getter.wait().await?;
@@ -198,7 +204,8 @@ impl AsyncFnRead for TcpAsyncFn {
 
 #[async_trait]
 impl AsyncFnWrite for TcpAsyncFn {
-    async fn write(&mut self, buf: Vec<u8>) -> io::Result<usize> {
+    async fn write(&mut self, mut buf: Vec<u8>) -> io::Result<usize> {
+        buf.truncate(MAX_CHUNK_SIZE);
         let getter = self.conn.send(Command::Write(self.obj), Some(buf)).await?;
 
         let (resp, _) = getter.wait().await?;
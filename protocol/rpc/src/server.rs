@@ -5,33 +5,54 @@ use rd_interface::{
     async_trait, constant::UDP_BUFFER_SIZE, Address, Arc, AsyncRead, AsyncWrite, Context, IServer,
     Net, ReadBuf, Result, TcpStream,
 };
+use rd_std::util::{accept_with_backoff, ACCEPT_BACKOFF};
 use serde_json::to_value;
-use tokio::{select, sync::Notify};
+use tokio::{
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        Mutex, Notify,
+    },
+};
 
 use crate::{
-    connection::Codec,
+    connection::{Codec, MAX_CHUNK_SIZE},
     session::{Obj, RequestGetter, ServerSession},
-    types::{Command, RpcValue},
+    types::{Capabilities, Command, ControlMessage, RpcValue, CONTROL_VERSION},
 };
 
+/// The control messages buffered for each session, the older ones are skipped.
+const CONTROL_CAPACITY: usize = 16;
+
+type ControlReceiver = Arc<Mutex<broadcast::Receiver<ControlMessage>>>;
+
 #[derive(Clone)]
 pub struct RpcServer {
     listen: Net,
     net: Net,
     bind: Address,
     stopper: Arc<Notify>,
-    codec: Codec,
+    /// Only accept this codec if set, otherwise accept any codec the client advertises.
+    codec: Option<Codec>,
+    control: broadcast::Sender<ControlMessage>,
 }
 impl RpcServer {
-    pub fn new(listen: Net, net: Net, bind: Address, codec: Codec) -> RpcServer {
+    pub fn new(listen: Net, net: Net, bind: Address, codec: Option<Codec>) -> RpcServer {
+        let (control, _) = broadcast::channel(CONTROL_CAPACITY);
         RpcServer {
             listen,
             net,
             bind,
             stopper: Arc::new(Notify::new()),
             codec,
+            control,
         }
     }
+    /// Push `msg` to the control channel of all the sessions, returns how many
+    /// sessions it's sent to.
+    pub fn notify(&self, msg: ControlMessage) -> usize {
+        self.control.send(msg).unwrap_or(0)
+    }
 }
 
 struct Guard<F>(Option<F>)
@@ -65,10 +86,16 @@ impl IServer for RpcServer {
             .listen
             .tcp_bind(&mut Context::new(), &self.bind)
             .await?;
-        let _guard = Guard::new(|| self.stopper.notify_waiters());
+        let _guard = Guard::new(|| {
+            self.notify(ControlMessage::Health {
+                healthy: false,
+                reason: Some("Server stopped".to_string()),
+            });
+            self.stopper.notify_waiters()
+        });
 
         loop {
-            let (conn, _) = listener.accept().await?;
+            let (conn, _) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
             let this = self.clone();
             tokio::spawn(async move {
                 let result = select! {
@@ -84,7 +111,11 @@ impl IServer for RpcServer {
 }
 
 impl RpcServer {
-    async fn handle_req(&self, req: &RequestGetter) -> Result<(RpcValue, Option<Vec<u8>>)> {
+    async fn handle_req(
+        &self,
+        req: &RequestGetter,
+        control: &ControlReceiver,
+    ) -> Result<(RpcValue, Option<Vec<u8>>)> {
         match req.cmd() {
             Command::TcpConnect(ctx, addr) => {
                 let mut ctx = Context::from_value(ctx.clone())?;
@@ -121,8 +152,9 @@ impl RpcServer {
 
                 Ok((RpcValue::Null, None))
             }
-            Command::LookupHost(addr) => {
-                let addrs = self.net.lookup_host(addr).await?;
+            Command::LookupHost(ctx, addr) => {
+                let mut ctx = Context::from_value(ctx.clone())?;
+                let addrs = self.net.lookup_host(&mut ctx, addr).await?;
 
                 Ok((RpcValue::Value(to_value(addrs)?), None))
             }
@@ -160,7 +192,7 @@ impl RpcServer {
             }
             Command::Read(obj, buf_size) => {
                 let obj = req.get_object(*obj)?;
-                let mut buf = vec![0u8; *buf_size as usize];
+                let mut buf = vec![0u8; (*buf_size as usize).min(MAX_CHUNK_SIZE)];
 
                 poll_fn(move |cx| {
                     let mut tcp = ready!(obj.poll_lock(cx));
@@ -240,18 +272,48 @@ impl RpcServer {
 
                 Ok((RpcValue::Value(to_value(addr)?), None))
             }
+            Command::Control(version) => {
+                if *version != CONTROL_VERSION {
+                    return Err(rd_interface::Error::other(format!(
+                        "Unsupported control version: {}",
+                        version
+                    )));
+                }
+                let mut control = control.lock().await;
+                let msg = loop {
+                    match control.recv().await {
+                        Ok(msg) => break msg,
+                        Err(RecvError::Lagged(n)) => {
+                            tracing::warn!("{} control messages are skipped", n)
+                        }
+                        Err(RecvError::Closed) => {
+                            return Err(rd_interface::Error::other("Control channel closed"))
+                        }
+                    }
+                };
+
+                Ok((RpcValue::Value(to_value(msg)?), None))
+            }
             _ => Err(rd_interface::Error::other("Invalid command")),
         }
     }
-    async fn handle_conn(&self, tcp: TcpStream) -> Result<()> {
-        let sess = ServerSession::new(tcp, self.codec);
+    async fn handle_conn(&self, mut tcp: TcpStream) -> Result<()> {
+        let codec = Codec::negotiate(&mut tcp, self.codec).await?;
+        let sess = ServerSession::new(tcp, codec);
         let handshake_req = sess.recv().await?;
         // TODO: handle session_id
         let _session_id = match handshake_req.cmd() {
             Command::Handshake(session_id) => session_id,
             _ => return Err(rd_interface::Error::other("Invalid handshake")),
         };
-        handshake_req.response(Ok(RpcValue::Null), None).await?;
+        let capabilities = Capabilities {
+            control: Some(CONTROL_VERSION),
+        };
+        handshake_req
+            .response(Ok(RpcValue::Null), Some(serde_json::to_vec(&capabilities)?))
+            .await?;
+        // the messages pushed before the client asks for them are kept
+        let control = Arc::new(Mutex::new(self.control.subscribe()));
 
         let notify = Arc::new(Notify::new());
         let on_close = close_callback(sess.clone(), notify.clone());
@@ -265,9 +327,11 @@ impl RpcServer {
             let this = self.clone();
             let notify = notify.clone();
             let on_close = on_close.clone();
+            let control = control.clone();
             tokio::spawn(async move {
                 let result = select! {
-                    r = this.handle_req(&req) => r,
+                    biased;
+                    r = this.handle_req(&req, &control) => r,
                     _ = notify.notified() => return Err(rd_interface::Error::other("Connection closed")),
                 };
                 let (result, data) = match result {
@@ -16,7 +16,7 @@ mod state;
 
 use crate::{
     connection::{ClientConnection, Codec, ServerConnection},
-    types::{Command, Object, Request, Response, RpcValue},
+    types::{Capabilities, Command, Object, Request, Response, RpcValue},
 };
 
 use self::state::{ServerSessionState, Shared};
@@ -26,27 +26,34 @@ pub struct ClientSession {
     conn: Arc<ClientConnection>,
     state: Arc<ClientSessionState>,
     closed: Arc<AtomicBool>,
+    capabilities: Arc<Capabilities>,
 }
 
 impl ClientSession {
     pub async fn new(net: &Net, endpoint: &Address, codec: Codec) -> Result<Self> {
-        let tcp = net.tcp_connect(&mut Context::new(), endpoint).await?;
+        let mut tcp = net.tcp_connect(&mut Context::new(), endpoint).await?;
+        codec.advertise(&mut tcp).await?;
 
-        let t = Self {
+        let mut t = Self {
             conn: Arc::new(ClientConnection::new(tcp, codec)),
             state: Arc::new(ClientSessionState::new()),
             closed: Arc::new(AtomicBool::new(false)),
+            capabilities: Default::default(),
         };
 
-        t.send(Command::Handshake(t.state.session_id()), None)
+        let (resp, data) = t
+            .send(Command::Handshake(t.state.session_id()), None)
             .await?
             .wait()
-            .await?
-            .0
-            .into_null()?;
+            .await?;
+        resp.into_null()?;
+        t.capabilities = Arc::new(Capabilities::from_slice(&data)?);
 
         Ok(t)
     }
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
 
     async fn wait_response(&self) -> Result<()> {
         let (resp, data) = self.conn.next().await?;
@@ -108,6 +115,10 @@ impl ClientSession {
     pub fn is_closed(&self) -> bool {
         self.closed.load(Ordering::Relaxed)
     }
+    /// Let the next request reconnect, the pending ones are not affected.
+    pub fn mark_closed(&self) {
+        self.closed.store(true, Ordering::Relaxed)
+    }
 }
 
 pub struct ResponseGetter {
@@ -1,5 +1,7 @@
 use super::*;
-use crate::connection::Codec;
+use crate::connection::{Codec, PROTOCOL_VERSION};
+use crate::session::ServerSession;
+use crate::types::{RpcValue, CONTROL_VERSION};
 use rd_interface::{Context, INet, IntoAddress};
 use rd_interface::{IServer, IntoDyn};
 use rd_std::tests::{
@@ -7,6 +9,7 @@ use rd_std::tests::{
 };
 use std::time::Duration;
 use tokio::{
+    io::{split, AsyncReadExt, AsyncWriteExt},
     task::yield_now,
     time::{sleep, timeout},
 };
@@ -15,6 +18,7 @@ use tokio::{
 async fn test_rpc_server_client() {
     test_rpc_server_client_codec(Codec::Cbor).await;
     test_rpc_server_client_codec(Codec::Json).await;
+    test_rpc_server_client_codec(Codec::MessagePack).await;
 }
 
 async fn test_rpc_server_client_codec(codec: Codec) {
@@ -26,7 +30,7 @@ async fn test_rpc_server_client_codec(codec: Codec) {
         local.clone(),
         local.clone(),
         "127.0.0.1:16666".into_address().unwrap(),
-        codec,
+        Some(codec),
     );
     let client = RpcNet::new(
         local.clone(),
@@ -54,6 +58,7 @@ async fn test_rpc_server_client_codec(codec: Codec) {
 async fn test_broken_session() {
     test_broken_session_codec(Codec::Cbor).await;
     test_broken_session_codec(Codec::Json).await;
+    test_broken_session_codec(Codec::MessagePack).await;
 }
 
 async fn test_broken_session_codec(codec: Codec) {
@@ -64,7 +69,7 @@ async fn test_broken_session_codec(codec: Codec) {
         local.clone(),
         local.clone(),
         "127.0.0.1:16666".into_address().unwrap(),
-        codec,
+        Some(codec),
     );
     let client = RpcNet::new(
         local.clone(),
@@ -122,6 +127,7 @@ async fn test_broken_session_codec(codec: Codec) {
 async fn test_client_reconnect() {
     test_client_reconnect_codec(Codec::Cbor).await;
     test_client_reconnect_codec(Codec::Json).await;
+    test_client_reconnect_codec(Codec::MessagePack).await;
 }
 
 async fn test_client_reconnect_codec(codec: Codec) {
@@ -132,7 +138,7 @@ async fn test_client_reconnect_codec(codec: Codec) {
         local.clone(),
         local.clone(),
         "127.0.0.1:16666".into_address().unwrap(),
-        codec,
+        Some(codec),
     );
     let client = RpcNet::new(
         local.clone(),
@@ -171,3 +177,309 @@ async fn test_client_reconnect_codec(codec: Codec) {
         .await
         .unwrap();
 }
+
+#[tokio::test]
+async fn test_cross_codec() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26666").await;
+    spawn_echo_server_udp(&local, "127.0.0.1:26666").await;
+
+    let server = RpcServer::new(
+        local.clone(),
+        local.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+        None,
+    );
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(10)).await;
+
+    for codec in [Codec::Json, Codec::Cbor, Codec::MessagePack] {
+        let client = RpcNet::new(
+            local.clone(),
+            "127.0.0.1:16666".into_address().unwrap(),
+            false,
+            codec,
+        )
+        .into_dyn();
+
+        assert_echo(&client, "127.0.0.1:26666").await;
+        assert_echo_udp(&client, "127.0.0.1:26666").await;
+    }
+}
+
+#[tokio::test]
+async fn test_rejected_codec() {
+    let local = TestNet::new().into_dyn();
+
+    let server = RpcServer::new(
+        local.clone(),
+        local.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+        Some(Codec::Cbor),
+    );
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(10)).await;
+
+    let client = RpcNet::new(
+        local.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+        false,
+        Codec::Json,
+    );
+    assert!(client.get_sess().await.is_err());
+}
+
+#[tokio::test]
+async fn test_protocol_version() {
+    let local = TestNet::new().into_dyn();
+    let addr = "127.0.0.1:16668".into_address().unwrap();
+    let listener = local.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+
+    // an unversioned client starts with the size of a frame
+    for preamble in [[0, 0], [PROTOCOL_VERSION + 1, 1], [PROTOCOL_VERSION, 9]] {
+        let mut client = local.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        client.write_all(&preamble).await.unwrap();
+        let (mut tcp, _) = listener.accept().await.unwrap();
+        assert!(Codec::negotiate(&mut tcp, None).await.is_err());
+    }
+
+    let mut client = local.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+    Codec::MessagePack.advertise(&mut client).await.unwrap();
+    let (mut tcp, _) = listener.accept().await.unwrap();
+    assert_eq!(
+        Codec::negotiate(&mut tcp, None).await.unwrap(),
+        Codec::MessagePack
+    );
+}
+
+#[tokio::test]
+async fn test_mixed_version() {
+    let local = TestNet::new().into_dyn();
+
+    // a version 1 client is rejected by the server
+    let addr = "127.0.0.1:16669".into_address().unwrap();
+    let listener = local.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+    let mut client = local.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+    client.write_all(&[1, 0]).await.unwrap();
+    let (mut tcp, _) = listener.accept().await.unwrap();
+    let err = Codec::negotiate(&mut tcp, None).await.unwrap_err();
+    assert!(err.to_string().contains(&format!(
+        "Unsupported protocol version: 1, expected {}",
+        PROTOCOL_VERSION
+    )));
+    drop(listener);
+
+    // a version 1 server closes the connection, the requests fail
+    let addr = "127.0.0.1:16670".into_address().unwrap();
+    let listener = local.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+    tokio::spawn(async move {
+        let (mut tcp, _) = listener.accept().await.unwrap();
+        let version = tcp.read_u8().await.unwrap();
+        assert_eq!(version, PROTOCOL_VERSION);
+    });
+
+    let client = RpcNet::new(local.clone(), addr, false, Codec::Json).into_dyn();
+    let target = "127.0.0.1:26669".into_address().unwrap();
+    assert!(client
+        .tcp_connect(&mut Context::new(), &target)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_large_transfer() {
+    const SIZE: usize = 8 * 1024 * 1024;
+
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26666").await;
+
+    let server = RpcServer::new(
+        local.clone(),
+        local.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+        None,
+    );
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(10)).await;
+
+    let client = RpcNet::new(
+        local.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+        false,
+        Codec::Cbor,
+    )
+    .into_dyn();
+    let tcp = client
+        .tcp_connect(
+            &mut Context::new(),
+            &"127.0.0.1:26666".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+    let (mut rx, mut tx) = split(tcp);
+
+    // larger than the max frame size, must be split into chunks
+    let data = (0..SIZE).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let expected = data.clone();
+    let writer = tokio::spawn(async move {
+        tx.write_all(&data).await.unwrap();
+        tx.flush().await.unwrap();
+    });
+
+    let mut received = vec![0u8; SIZE];
+    timeout(Duration::from_secs(30), rx.read_exact(&mut received))
+        .await
+        .unwrap()
+        .unwrap();
+    writer.await.unwrap();
+
+    assert!(received == expected);
+}
+
+#[tokio::test]
+async fn test_delimiter_bytes() {
+    for codec in [Codec::Json, Codec::Cbor, Codec::MessagePack] {
+        test_delimiter_bytes_codec(codec).await;
+    }
+}
+
+async fn test_delimiter_bytes_codec(codec: Codec) {
+    use crate::{
+        connection::{ClientConnection, ServerConnection},
+        types::{Command, Object, Request, Response},
+    };
+
+    let local = TestNet::new().into_dyn();
+    let addr = "127.0.0.1:16667".into_address().unwrap();
+    let listener = local.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+    let client = local.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+
+    let client = ClientConnection::new(client, codec);
+    let server = ServerConnection::new(server, codec);
+
+    // newlines, NULs, CBOR breaks and bytes looking like a frame header
+    let data = b"a\nb\r\n\0\xff\x00\x00\x00\x01\n\n".repeat(100);
+    let error = "line\nbreak\r\n\0}\"".to_string();
+    for seq_id in 0..3 {
+        client
+            .send(
+                Request {
+                    cmd: Command::Write(Object::from_u32(seq_id)),
+                    seq_id,
+                },
+                Some(data.clone()),
+            )
+            .await
+            .unwrap();
+        let (req, received) = server.next().await.unwrap();
+        assert_eq!(req.seq_id, seq_id);
+        assert_eq!(received, data);
+
+        server
+            .send(
+                Response {
+                    seq_id,
+                    result: Err(error.clone()),
+                },
+                Some(data.clone()),
+            )
+            .await
+            .unwrap();
+        let (resp, received) = client.next().await.unwrap();
+        assert_eq!(resp.seq_id, seq_id);
+        assert_eq!(resp.result.unwrap_err(), error);
+        assert_eq!(received, data);
+    }
+}
+
+#[tokio::test]
+async fn test_control_channel() {
+    let local = TestNet::new().into_dyn();
+
+    let server = RpcServer::new(
+        local.clone(),
+        local.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+        None,
+    );
+    let server2 = server.clone();
+    tokio::spawn(async move { server2.start().await });
+
+    sleep(Duration::from_millis(10)).await;
+
+    let client = RpcNet::new(
+        local.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+        false,
+        Codec::Cbor,
+    )
+    .control(true);
+    let mut control = client.subscribe_control().unwrap();
+    let sess = client.get_sess().await.unwrap();
+    assert_eq!(sess.capabilities().control, Some(CONTROL_VERSION));
+
+    let health = ControlMessage::Health {
+        healthy: false,
+        reason: Some("overloaded".to_string()),
+    };
+    assert_eq!(server.notify(ControlMessage::ConfigChanged), 1);
+    assert_eq!(server.notify(health.clone()), 1);
+    for expected in [ControlMessage::ConfigChanged, health] {
+        let msg = timeout(Duration::from_secs(1), control.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(msg, expected);
+    }
+    // reconnect after the server reports it's unhealthy
+    assert!(sess.is_closed());
+
+    // the control channel is optional
+    let client = RpcNet::new(
+        local.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+        false,
+        Codec::Cbor,
+    );
+    assert!(client.subscribe_control().is_none());
+}
+
+#[tokio::test]
+async fn test_control_without_capability() {
+    let local = TestNet::new().into_dyn();
+    let listener = local
+        .tcp_bind(
+            &mut Context::new(),
+            &"127.0.0.1:16667".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+    let server = tokio::spawn(async move {
+        let (mut tcp, _) = listener.accept().await.unwrap();
+        let codec = Codec::negotiate(&mut tcp, None).await.unwrap();
+        let sess = ServerSession::new(tcp, codec);
+        // the older servers answer the handshake without the capabilities
+        let handshake = sess.recv().await.unwrap();
+        handshake.response(Ok(RpcValue::Null), None).await.unwrap();
+        let req = sess.recv().await.unwrap();
+        format!("{:?}", req.cmd())
+    });
+
+    let client = RpcNet::new(
+        local.clone(),
+        "127.0.0.1:16667".into_address().unwrap(),
+        false,
+        Codec::Cbor,
+    )
+    .control(true);
+    let sess = client.get_sess().await.unwrap();
+    assert_eq!(sess.capabilities().control, None);
+
+    // no control request is sent
+    assert!(timeout(Duration::from_millis(100), server).await.is_err());
+    assert!(!sess.is_closed());
+}
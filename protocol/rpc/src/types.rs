@@ -18,6 +18,38 @@ pub enum RpcValue {
     ObjectValue(Object, Value),
 }
 
+/// The version of the control channel, the server rejects the other versions.
+pub const CONTROL_VERSION: u32 = 1;
+
+/// A message pushed from the server to the client on the control channel.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ControlMessage {
+    /// The config of the server is changed.
+    ConfigChanged,
+    /// The health of the server, with the reason if it's unhealthy.
+    Health {
+        healthy: bool,
+        reason: Option<String>,
+    },
+}
+
+/// What the server supports. It's sent in the data of the handshake response, which
+/// is empty from the older servers, so the response itself is still `Null`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Capabilities {
+    /// The version of the control channel, `None` if it's not supported.
+    pub control: Option<u32>,
+}
+
+impl Capabilities {
+    pub fn from_slice(data: &[u8]) -> rd_interface::Result<Capabilities> {
+        if data.is_empty() {
+            return Ok(Capabilities::default());
+        }
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub enum Error {
     ObjectNotFound,
@@ -30,7 +62,7 @@ pub enum Command {
     TcpConnect(Value, Address),
     TcpBind(Value, Address),
     UdpBind(Value, Address),
-    LookupHost(Address),
+    LookupHost(Value, Address),
     Accept(Object),
     Read(Object, u32),
     Write(Object),
@@ -41,6 +73,9 @@ pub enum Command {
     LocalAddr(Object),
     PeerAddr(Object),
     Close(Object),
+    /// Wait for the next control message, with the version of the control channel.
+    /// Only sent if the server has the capability, the older servers can't parse it.
+    Control(u32),
 }
 
 #[derive(Debug, Deserialize, Serialize)]
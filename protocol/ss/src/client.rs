@@ -1,10 +1,14 @@
 use std::io;
 
-use super::wrapper::{Cipher, WrapAddress, WrapSSTcp, WrapSSUdp};
+use super::{
+    udp_over_tcp::{UdpOverTcp, UDP_OVER_TCP_DOMAIN},
+    wrapper::{Cipher, WrapAddress, WrapSSTcp, WrapSSUdp},
+};
 use rd_interface::{
-    async_trait, prelude::*, registry::NetRef, Address, Error, INet, IntoDyn, Net, Result,
-    TcpStream, UdpSocket,
+    async_trait, prelude::*, registry::NetRef, Address, INet, IntoDyn, Net, Result, TcpStream,
+    UdpSocket, NOT_IMPLEMENTED,
 };
+use rd_std::util::ResolveNet;
 use shadowsocks::{
     config::{ServerConfig, ServerType},
     context::{Context, SharedContext},
@@ -17,13 +21,21 @@ pub struct SSNetConfig {
     pub(crate) server: Address,
     #[serde(skip_serializing_if = "rd_interface::config::detailed_field")]
     pub(crate) password: String,
+    /// relay UDP through the server. `udp_bind` is not implemented if disabled.
     #[serde(default)]
     pub(crate) udp: bool,
+    /// send UDP packets in the TCP stream instead of the UDP relay
+    #[serde(default)]
+    pub(crate) udp_over_tcp: bool,
 
     pub(crate) cipher: Cipher,
 
     #[serde(default)]
     pub(crate) net: NetRef,
+    /// resolve the hostname of `server` by this net instead of `net`,
+    /// e.g. plain DNS while the traffic uses DoH.
+    #[serde(default)]
+    pub(crate) resolver: Option<NetRef>,
 }
 
 pub struct SSNet {
@@ -31,6 +43,7 @@ pub struct SSNet {
     cfg: ServerConfig,
     addr: Address,
     udp: bool,
+    udp_over_tcp: bool,
     net: Net,
 }
 
@@ -45,18 +58,16 @@ impl SSNet {
                 config.cipher.into(),
             ),
             udp: config.udp,
-            net: config.net.value_cloned(),
+            udp_over_tcp: config.udp_over_tcp,
+            net: match config.resolver {
+                Some(resolver) => {
+                    ResolveNet::new(config.net.value_cloned(), resolver.value_cloned()).into_dyn()
+                }
+                None => config.net.value_cloned(),
+            },
         }
     }
-}
-
-#[async_trait]
-impl rd_interface::TcpConnect for SSNet {
-    async fn tcp_connect(
-        &self,
-        ctx: &mut rd_interface::Context,
-        addr: &Address,
-    ) -> Result<TcpStream> {
+    async fn connect(&self, ctx: &mut rd_interface::Context, addr: &Address) -> Result<TcpStream> {
         let stream = self.net.tcp_connect(ctx, &self.addr).await?;
 
         let client = ProxyClientStream::from_stream(
@@ -69,6 +80,17 @@ impl rd_interface::TcpConnect for SSNet {
     }
 }
 
+#[async_trait]
+impl rd_interface::TcpConnect for SSNet {
+    async fn tcp_connect(
+        &self,
+        ctx: &mut rd_interface::Context,
+        addr: &Address,
+    ) -> Result<TcpStream> {
+        self.connect(ctx, addr).await
+    }
+}
+
 #[async_trait]
 impl rd_interface::UdpBind for SSNet {
     async fn udp_bind(
@@ -76,13 +98,19 @@ impl rd_interface::UdpBind for SSNet {
         ctx: &mut rd_interface::Context,
         _addr: &Address,
     ) -> Result<UdpSocket> {
+        if self.udp_over_tcp {
+            let stream = self
+                .connect(ctx, &Address::Domain(UDP_OVER_TCP_DOMAIN.to_string(), 0))
+                .await?;
+            return Ok(UdpOverTcp::new(stream).into_dyn());
+        }
         if !self.udp {
-            return Err(Error::NotEnabled);
+            return Err(NOT_IMPLEMENTED);
         }
 
         let server_addr = self
             .net
-            .lookup_host(&self.addr)
+            .lookup_host(ctx, &self.addr)
             .await?
             .into_iter()
             .next()
@@ -107,6 +135,10 @@ impl INet for SSNet {
     fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
         Some(self)
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 #[cfg(test)]
@@ -124,8 +156,10 @@ mod tests {
             server: "127.0.0.1:1234".into_address().unwrap(),
             password: "password".to_string(),
             udp: false,
+            udp_over_tcp: false,
             cipher: Cipher::AES_128_CCM,
             net: NetRef::new_with_value("test".into(), net),
+            resolver: None,
         })
         .into_dyn();
 
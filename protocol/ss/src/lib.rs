@@ -7,10 +7,12 @@ mod server;
 #[cfg(test)]
 mod tests;
 mod udp;
+mod udp_over_tcp;
 mod wrapper;
 
 impl Builder<Net> for SSNet {
     const NAME: &'static str = "shadowsocks";
+    const TITLE: &'static str = "Shadowsocks Client";
     type Config = SSNetConfig;
     type Item = Self;
 
@@ -21,6 +23,7 @@ impl Builder<Net> for SSNet {
 
 impl Builder<Server> for SSServer {
     const NAME: &'static str = "shadowsocks";
+    const TITLE: &'static str = "Shadowsocks Server";
     type Config = SSServerConfig;
     type Item = Self;
 
@@ -1,17 +1,22 @@
 use std::net::SocketAddr;
 
-use self::source::UdpSource;
-use super::wrapper::{Cipher, CryptoStream};
+use self::{record::RecordStream, source::UdpSource};
+use super::{
+    udp_over_tcp::{UdpOverTcp, UDP_OVER_TCP_DOMAIN},
+    wrapper::{Cipher, CryptoStream},
+};
 use rd_interface::{
-    async_trait, config::NetRef, prelude::*, Address, Arc, IServer, Net, Result, TcpStream,
+    async_trait, config::NetRef, prelude::*, Address, Arc, Error, IServer, IntoDyn, Net, Result,
+    TcpStream,
 };
-use rd_std::util::forward_udp;
+use rd_std::util::{accept_with_backoff, forward_udp, ACCEPT_BACKOFF};
 use rd_std::ContextExt;
 use shadowsocks::{config::ServerType, context::Context, ServerConfig};
 use socks5_protocol::Address as S5Addr;
-use tokio::select;
+use tokio::{io::AsyncWriteExt, select};
 use tracing::instrument;
 
+mod record;
 mod source;
 
 #[rd_config]
@@ -22,8 +27,15 @@ pub struct SSServerConfig {
     pub(crate) password: String,
     #[serde(default)]
     pub(crate) udp: bool,
+    /// accept UDP packets sent in the TCP stream
+    #[serde(default)]
+    pub(crate) udp_over_tcp: bool,
 
     pub(crate) cipher: Cipher,
+    /// relay the connections which fail to decrypt, e.g. active probes, to this address
+    /// to look like a normal service. They are closed if not set.
+    #[serde(default)]
+    pub(crate) fallback: Option<Address>,
     #[serde(default)]
     pub(crate) net: NetRef,
     #[serde(default)]
@@ -36,6 +48,8 @@ pub struct SSServer {
     cfg: Arc<ServerConfig>,
     listen: Net,
     net: Net,
+    udp_over_tcp: bool,
+    fallback: Option<Address>,
 }
 
 #[async_trait]
@@ -60,6 +74,8 @@ impl SSServer {
             cfg: Arc::new(svr_cfg),
             listen: cfg.listen.value_cloned(),
             net: cfg.net.value_cloned(),
+            udp_over_tcp: cfg.udp_over_tcp,
+            fallback: cfg.fallback,
         }
     }
     async fn serve_udp(&self) -> Result<()> {
@@ -87,12 +103,17 @@ impl SSServer {
             .tcp_bind(&mut rd_interface::Context::new(), &self.bind)
             .await?;
         loop {
-            let (socket, addr) = listener.accept().await?;
+            let (socket, addr) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
             let cfg = self.cfg.clone();
             let context = self.context.clone();
             let net = self.net.clone();
+            let udp_over_tcp = self.udp_over_tcp;
+            let fallback = self.fallback.clone();
             let _ = tokio::spawn(async move {
-                if let Err(e) = Self::serve_connection(cfg, context, socket, net, addr).await {
+                if let Err(e) =
+                    Self::serve_connection(cfg, context, socket, net, addr, udp_over_tcp, fallback)
+                        .await
+                {
                     tracing::error!("Error when serve_connection: {:?}", e);
                 }
             });
@@ -105,11 +126,36 @@ impl SSServer {
         socket: TcpStream,
         net: Net,
         addr: SocketAddr,
+        udp_over_tcp: bool,
+        fallback: Option<Address>,
     ) -> Result<()> {
-        let mut socket = CryptoStream::from_stream(context, socket, cfg.method(), cfg.key());
-        let target = S5Addr::read(&mut socket).await.map_err(|e| e.to_io_err())?;
-
         let ctx = &mut rd_interface::Context::from_socketaddr(addr);
+        let mut socket =
+            CryptoStream::from_stream(context, RecordStream::new(socket), cfg.method(), cfg.key());
+        let target = match S5Addr::read(&mut socket).await {
+            Ok(target) => target,
+            Err(e) => {
+                let stream = socket.into_inner();
+                // only the data that can't be decrypted is relayed, not a closed connection
+                return match &fallback {
+                    Some(fallback) if !stream.closed() => {
+                        Self::serve_fallback(ctx, &net, stream, fallback).await
+                    }
+                    _ => Err(e.to_io_err().into()),
+                };
+            }
+        };
+        socket.get_mut().stop();
+
+        if matches!(&target, S5Addr::Domain(d, _) if d == UDP_OVER_TCP_DOMAIN) {
+            if !udp_over_tcp {
+                return Err(Error::NotEnabled);
+            }
+            let udp = net.udp_bind(ctx, &Address::any_addr_port(&addr)).await?;
+            let channel = UdpOverTcp::new(TcpStream::from(socket));
+            ctx.connect_udp(channel.into_dyn(), udp).await?;
+            return Ok(());
+        }
         let target = net
             .tcp_connect(
                 ctx,
@@ -122,4 +168,18 @@ impl SSServer {
         ctx.connect_tcp(TcpStream::from(socket), target).await?;
         Ok(())
     }
+    async fn serve_fallback(
+        ctx: &mut rd_interface::Context,
+        net: &Net,
+        stream: RecordStream,
+        fallback: &Address,
+    ) -> Result<()> {
+        tracing::debug!("fallback to {}", fallback);
+
+        let (socket, head) = stream.into_inner();
+        let mut target = net.tcp_connect(ctx, fallback).await?;
+        target.write_all(&head).await?;
+        ctx.connect_tcp(socket, target).await?;
+        Ok(())
+    }
 }
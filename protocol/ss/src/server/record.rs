@@ -0,0 +1,75 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{self, Poll},
+};
+
+use futures::ready;
+use rd_interface::{AsyncRead, AsyncWrite, ReadBuf, TcpStream};
+
+/// Keeps the bytes read from the stream until [`stop`](RecordStream::stop) is called,
+/// so they can be relayed to the fallback if the handshake fails.
+pub struct RecordStream {
+    stream: TcpStream,
+    recorded: Option<Vec<u8>>,
+    closed: bool,
+}
+
+impl RecordStream {
+    pub fn new(stream: TcpStream) -> RecordStream {
+        RecordStream {
+            stream,
+            recorded: Some(Vec::new()),
+            closed: false,
+        }
+    }
+    pub fn stop(&mut self) {
+        self.recorded = None;
+    }
+    /// If the stream is closed or failed, so the error isn't caused by the data.
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+    pub fn into_inner(self) -> (TcpStream, Vec<u8>) {
+        (self.stream, self.recorded.unwrap_or_default())
+    }
+}
+
+impl AsyncRead for RecordStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let result = ready!(Pin::new(&mut self.stream).poll_read(cx, buf));
+        let read = &buf.filled()[filled..];
+
+        if result.is_err() || read.is_empty() {
+            self.closed = true;
+        }
+        if let Some(recorded) = &mut self.recorded {
+            recorded.extend_from_slice(read);
+        }
+
+        Poll::Ready(result)
+    }
+}
+
+impl AsyncWrite for RecordStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
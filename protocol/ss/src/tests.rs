@@ -1,12 +1,25 @@
 use crate::wrapper::Cipher;
 
 use super::*;
-use rd_interface::{config::NetRef, IServer, IntoAddress, IntoDyn, Value};
+use rd_interface::{
+    async_trait, config::NetRef, Address, Context, Error, INet, IServer, IntoAddress, IntoDyn,
+    Value,
+};
 use rd_std::tests::{
     assert_echo, assert_echo_udp, get_registry, spawn_echo_server, spawn_echo_server_udp, TestNet,
 };
-use std::time::Duration;
-use tokio::time::sleep;
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::sleep,
+};
 
 #[test]
 fn test_ss_smoke() {
@@ -27,7 +40,9 @@ async fn test_ss_server_client() {
         bind: server_addr.clone(),
         password: "password".into(),
         udp: true,
+        udp_over_tcp: false,
         cipher: Cipher::AES_128_GCM,
+        fallback: None,
     };
     let server = server::SSServer::new(server_cfg);
     tokio::spawn(async move { server.start().await });
@@ -38,11 +53,188 @@ async fn test_ss_server_client() {
         server: "localhost:16666".into_address().unwrap(),
         password: "password".into(),
         udp: true,
+        udp_over_tcp: false,
         cipher: Cipher::AES_128_GCM,
         net: NetRef::new_with_value(Value::String("local".to_string()), local.clone()),
+        resolver: None,
     };
     let client = client::SSNet::new(client_cfg).into_dyn();
 
     assert_echo(&client, "127.0.0.1:26666").await;
     assert_echo_udp(&client, "127.0.0.1:26666").await;
 }
+
+#[tokio::test]
+async fn test_ss_udp_over_tcp() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server_udp(&local, "127.0.0.1:26667").await;
+
+    let server_addr = "127.0.0.1:16667".into_address().unwrap();
+    let server_cfg = server::SSServerConfig {
+        listen: NetRef::new_with_value("local".to_string().into(), local.clone()),
+        net: NetRef::new_with_value("local".to_string().into(), local.clone()),
+        bind: server_addr.clone(),
+        password: "password".into(),
+        udp: false,
+        udp_over_tcp: true,
+        cipher: Cipher::AES_128_GCM,
+        fallback: None,
+    };
+    let server = server::SSServer::new(server_cfg);
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_secs(1)).await;
+
+    let client_cfg = client::SSNetConfig {
+        server: "localhost:16667".into_address().unwrap(),
+        password: "password".into(),
+        udp: false,
+        udp_over_tcp: true,
+        cipher: Cipher::AES_128_GCM,
+        net: NetRef::new_with_value(Value::String("local".to_string()), local.clone()),
+        resolver: None,
+    };
+    let client = client::SSNet::new(client_cfg).into_dyn();
+
+    assert_echo_udp(&client, "127.0.0.1:26667").await;
+}
+
+#[tokio::test]
+async fn test_ss_fallback() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26670").await;
+
+    let server_cfg = server::SSServerConfig {
+        listen: NetRef::new_with_value("local".to_string().into(), local.clone()),
+        net: NetRef::new_with_value("local".to_string().into(), local.clone()),
+        bind: "127.0.0.1:16670".into_address().unwrap(),
+        password: "password".into(),
+        udp: false,
+        udp_over_tcp: false,
+        cipher: Cipher::AES_128_GCM,
+        fallback: Some("127.0.0.1:26670".into_address().unwrap()),
+    };
+    let server = server::SSServer::new(server_cfg);
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_secs(1)).await;
+
+    // can't be decrypted
+    let garbage = b"GET / HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl/8.0\r\n\r\n";
+    let mut tcp = local
+        .tcp_connect(
+            &mut Context::new(),
+            &"127.0.0.1:16670".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+    tcp.write_all(garbage).await.unwrap();
+    let mut buf = vec![0u8; garbage.len()];
+    tcp.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, garbage);
+
+    // the shadowsocks clients are still served
+    let client_cfg = client::SSNetConfig {
+        server: "127.0.0.1:16670".into_address().unwrap(),
+        password: "password".into(),
+        udp: false,
+        udp_over_tcp: false,
+        cipher: Cipher::AES_128_GCM,
+        net: NetRef::new_with_value(Value::String("local".to_string()), local.clone()),
+        resolver: None,
+    };
+    let client = client::SSNet::new(client_cfg).into_dyn();
+    assert_echo(&client, "127.0.0.1:26670").await;
+}
+
+#[tokio::test]
+async fn test_ss_udp_flag() {
+    let local = TestNet::new().into_dyn();
+    let addr = "127.0.0.1:26669".into_address().unwrap();
+    let client = |udp| {
+        client::SSNet::new(client::SSNetConfig {
+            server: "127.0.0.1:16669".into_address().unwrap(),
+            password: "password".into(),
+            udp,
+            udp_over_tcp: false,
+            cipher: Cipher::AES_128_GCM,
+            net: NetRef::new_with_value(Value::String("local".to_string()), local.clone()),
+            resolver: None,
+        })
+        .into_dyn()
+    };
+
+    let result = client(true).udp_bind(&mut Context::new(), &addr).await;
+    assert!(result.is_ok());
+
+    let result = client(false).udp_bind(&mut Context::new(), &addr).await;
+    assert!(matches!(result, Err(Error::NotImplemented)));
+}
+
+/// Resolves every domain to 127.0.0.1 and counts the lookups.
+#[derive(Default)]
+struct CountingResolver(Arc<AtomicUsize>);
+
+#[async_trait]
+impl rd_interface::LookupHost for CountingResolver {
+    async fn lookup_host(
+        &self,
+        _ctx: &mut Context,
+        addr: &Address,
+    ) -> rd_interface::Result<Vec<SocketAddr>> {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Ok(vec![SocketAddr::from(([127, 0, 0, 1], addr.port()))])
+    }
+}
+
+impl INet for CountingResolver {
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        Some(self)
+    }
+}
+
+#[tokio::test]
+async fn test_ss_resolver() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26668").await;
+    spawn_echo_server_udp(&local, "127.0.0.1:26668").await;
+
+    let server_cfg = server::SSServerConfig {
+        listen: NetRef::new_with_value("local".to_string().into(), local.clone()),
+        net: NetRef::new_with_value("local".to_string().into(), local.clone()),
+        bind: "127.0.0.1:16668".into_address().unwrap(),
+        password: "password".into(),
+        udp: true,
+        udp_over_tcp: false,
+        cipher: Cipher::AES_128_GCM,
+        fallback: None,
+    };
+    let server = server::SSServer::new(server_cfg);
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_secs(1)).await;
+
+    let resolver = CountingResolver::default();
+    let lookups = resolver.0.clone();
+    // the chained net can't resolve it
+    let client_cfg = client::SSNetConfig {
+        server: "ss.example.com:16668".into_address().unwrap(),
+        password: "password".into(),
+        udp: true,
+        udp_over_tcp: false,
+        cipher: Cipher::AES_128_GCM,
+        net: NetRef::new_with_value(Value::String("local".to_string()), local.clone()),
+        resolver: Some(NetRef::new_with_value(
+            Value::String("resolver".to_string()),
+            resolver.into_dyn(),
+        )),
+    };
+    let client = client::SSNet::new(client_cfg).into_dyn();
+
+    assert_echo(&client, "127.0.0.1:26668").await;
+    assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    assert_echo(&client, "127.0.0.1:26668").await;
+    assert_echo_udp(&client, "127.0.0.1:26668").await;
+    // the resolved server address is reused
+    assert_eq!(lookups.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,274 @@
+//! UDP over TCP
+//!
+//! The client connects to the magic address `sp.udp-over-tcp.arpa` through the
+//! shadowsocks TCP stream, then every datagram is sent in the stream as a frame.
+//!
+//! ```plain
+//! +----------+--------+----------+
+//! | ADDRESS  |  LEN   | PAYLOAD  |
+//! +----------+--------+----------+
+//! | Variable |   2    | Variable |
+//! +----------+--------+----------+
+//! ```
+//!
+//! ADDRESS is a socks5 address, LEN is the length of PAYLOAD in big endian.
+use std::{
+    io::{self, Cursor, ErrorKind},
+    net::SocketAddr,
+    pin::Pin,
+    task::{self, Poll},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures::ready;
+use rd_interface::{
+    async_trait, Address as RDAddress, AsyncRead, AsyncWrite, IUdpChannel, IUdpSocket, ReadBuf,
+    TcpStream, NOT_IMPLEMENTED,
+};
+use socks5_protocol::{sync::FromIO, Address as S5Addr};
+
+use crate::wrapper::WrapAddress;
+
+pub const UDP_OVER_TCP_DOMAIN: &str = "sp.udp-over-tcp.arpa";
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+pub struct UdpOverTcp {
+    stream: TcpStream,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    flushing: bool,
+}
+
+/// Returns the length of the socks5 address at the beginning of `buf`,
+/// or `None` if more data is needed.
+fn address_len(buf: &[u8]) -> io::Result<Option<usize>> {
+    let len = match buf.first() {
+        None => return Ok(None),
+        Some(1) => 1 + 4 + 2,
+        Some(4) => 1 + 16 + 2,
+        Some(3) => match buf.get(1) {
+            Some(domain_len) => 1 + 1 + *domain_len as usize + 2,
+            None => return Ok(None),
+        },
+        Some(t) => {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unknown address type {}", t),
+            ))
+        }
+    };
+
+    Ok(Some(len))
+}
+
+impl UdpOverTcp {
+    pub fn new(stream: TcpStream) -> UdpOverTcp {
+        UdpOverTcp {
+            stream,
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            flushing: false,
+        }
+    }
+    /// Returns the address, header length and payload length of the first frame
+    /// if it's fully received.
+    fn parse_frame(&self) -> io::Result<Option<(S5Addr, usize, usize)>> {
+        let buf = &self.read_buf[..];
+        let addr_len = match address_len(buf)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+        if buf.len() < addr_len + 2 {
+            return Ok(None);
+        }
+        let payload_len = u16::from_be_bytes([buf[addr_len], buf[addr_len + 1]]) as usize;
+        if buf.len() < addr_len + 2 + payload_len {
+            return Ok(None);
+        }
+        let addr =
+            S5Addr::read_from(&mut Cursor::new(&buf[..addr_len])).map_err(|e| e.to_io_err())?;
+
+        Ok(Some((addr, addr_len + 2, payload_len)))
+    }
+    fn poll_read_frame(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<S5Addr>> {
+        loop {
+            if let Some((addr, header_len, payload_len)) = self.parse_frame()? {
+                self.read_buf.advance(header_len);
+                let payload = self.read_buf.split_to(payload_len);
+                if payload_len > buf.remaining() {
+                    tracing::warn!(
+                        "Drop a udp packet of {} bytes, the buffer is {} bytes",
+                        payload_len,
+                        buf.remaining()
+                    );
+                    continue;
+                }
+                buf.put_slice(&payload);
+
+                return Poll::Ready(Ok(addr));
+            }
+
+            let UdpOverTcp {
+                stream, read_buf, ..
+            } = self;
+            let len = read_buf.len();
+            read_buf.resize(len + READ_CHUNK_SIZE, 0);
+            let mut chunk = ReadBuf::new(&mut read_buf[len..]);
+            let result = Pin::new(stream).poll_read(cx, &mut chunk);
+            let n = chunk.filled().len();
+            read_buf.truncate(len + n);
+
+            ready!(result)?;
+            if n == 0 {
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+        }
+    }
+    fn poll_write_frame(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        addr: &S5Addr,
+        payload: &[u8],
+    ) -> Poll<io::Result<()>> {
+        if !self.flushing {
+            if self.write_buf.is_empty() {
+                if payload.len() > u16::MAX as usize {
+                    return Poll::Ready(Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        "udp packet too large",
+                    )));
+                }
+                let mut writer = (&mut self.write_buf).writer();
+                addr.write_to(&mut writer).map_err(|e| e.to_io_err())?;
+                self.write_buf.put_u16(payload.len() as u16);
+                self.write_buf.put_slice(payload);
+            }
+
+            while !self.write_buf.is_empty() {
+                let n = ready!(Pin::new(&mut self.stream).poll_write(cx, &self.write_buf))?;
+                if n == 0 {
+                    return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+                }
+                self.write_buf.advance(n);
+            }
+            self.flushing = true;
+        }
+
+        ready!(Pin::new(&mut self.stream).poll_flush(cx))?;
+        self.flushing = false;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+// Client side
+#[async_trait]
+impl IUdpSocket for UdpOverTcp {
+    async fn local_addr(&self) -> rd_interface::Result<SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        match ready!(self.poll_read_frame(cx, buf))? {
+            S5Addr::SocketAddr(s) => Poll::Ready(Ok(s)),
+            S5Addr::Domain(_, _) => Poll::Ready(Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Udp recv_from domain name",
+            ))),
+        }
+    }
+
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &RDAddress,
+    ) -> Poll<io::Result<usize>> {
+        let addr: S5Addr = WrapAddress::from(target.clone()).into();
+        ready!(self.poll_write_frame(cx, &addr, buf))?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+// Server side
+impl IUdpChannel for UdpOverTcp {
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<RDAddress>> {
+        let addr = ready!(self.poll_read_frame(cx, buf))?;
+
+        Poll::Ready(Ok(match addr {
+            S5Addr::Domain(d, p) => RDAddress::Domain(d, p),
+            S5Addr::SocketAddr(s) => RDAddress::SocketAddr(s),
+        }))
+    }
+
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.poll_write_frame(cx, &S5Addr::SocketAddr(*target), buf))?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::poll_fn;
+    use rd_interface::{Context, IntoAddress, IntoDyn};
+    use rd_std::tests::TestNet;
+
+    use super::*;
+
+    #[test]
+    fn test_address_len() {
+        assert_eq!(address_len(&[]).unwrap(), None);
+        assert_eq!(address_len(&[1]).unwrap(), Some(7));
+        assert_eq!(address_len(&[4]).unwrap(), Some(19));
+        assert_eq!(address_len(&[3]).unwrap(), None);
+        assert_eq!(address_len(&[3, 9]).unwrap(), Some(13));
+        assert!(address_len(&[2]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drop_large_packet() {
+        let net = TestNet::new().into_dyn();
+        let addr = "127.0.0.1:1234".into_address().unwrap();
+        let listener = net.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+        let client = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        let mut client = UdpOverTcp::new(client);
+        let mut server = UdpOverTcp::new(server);
+        let target = "1.1.1.1:53".into_address().unwrap();
+        for payload in [&[0xaa; 100][..], b"hello"] {
+            poll_fn(|cx| IUdpSocket::poll_send_to(&mut client, cx, payload, &target))
+                .await
+                .unwrap();
+        }
+
+        // the packet larger than the buffer is dropped instead of truncated
+        let mut buf = [0u8; 64];
+        let mut buf = ReadBuf::new(&mut buf);
+        let addr = poll_fn(|cx| IUdpChannel::poll_send_to(&mut server, cx, &mut buf))
+            .await
+            .unwrap();
+        assert_eq!(addr, target);
+        assert_eq!(buf.filled(), b"hello");
+    }
+}
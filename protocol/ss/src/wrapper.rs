@@ -239,6 +239,12 @@ impl<S> CryptoStream<S> {
             context,
         )
     }
+    pub fn get_mut(&mut self) -> &mut S {
+        self.0.get_mut()
+    }
+    pub fn into_inner(self) -> S {
+        self.0.into_inner()
+    }
 }
 
 impl<S> AsyncRead for CryptoStream<S>
@@ -10,7 +10,10 @@ use rd_interface::{
     registry::{Builder, NetRef},
     Address as RdAddress, Address, INet, IntoDyn, Net, Result, TcpStream, UdpSocket,
 };
-use rd_std::tls::{TlsNet, TlsNetConfig};
+use rd_std::{
+    tls::{default_session_cache, TlsNet, TlsNetConfig},
+    util::ResolveNet,
+};
 use sha2::{Digest, Sha224};
 use socks5_protocol::{sync::FromIO, Address as S5Addr};
 use tokio::time::timeout;
@@ -31,7 +34,13 @@ impl TrojanNet {
         let tls_config = TlsNetConfig {
             skip_cert_verify: config.skip_cert_verify,
             sni: config.sni,
-            net: config.net,
+            enable_early_data: config.enable_early_data,
+            alpn: Vec::new(),
+            session_cache: config.session_cache,
+            net: NetRef::new_with_value(
+                config.net.represent().clone(),
+                with_resolver(config.net.value_cloned(), config.resolver),
+            ),
         };
         let server = config.server.clone();
 
@@ -50,7 +59,7 @@ impl TrojanNet {
         let password = hex::encode(Sha224::digest(config.password.as_bytes()));
 
         Ok(TrojanNet {
-            net: (*config.net).clone(),
+            net: with_resolver(config.net.value_cloned(), config.resolver),
             server: config.server,
             password,
             websocket: config.websocket,
@@ -59,9 +68,19 @@ impl TrojanNet {
     }
 }
 
+/// Resolve the server hostname once by `resolver` if it's set.
+fn with_resolver(net: Net, resolver: Option<NetRef>) -> Net {
+    match resolver {
+        Some(resolver) => ResolveNet::new(net, resolver.value_cloned()).into_dyn(),
+        None => net,
+    }
+}
+
 #[rd_config]
 #[derive(Debug, Clone)]
 pub struct WebSocket {
+    /// `Host` of the websocket request, independent of `server`,
+    /// e.g. the domain fronted by a CDN.
     host: String,
     path: String,
 }
@@ -71,6 +90,10 @@ pub struct WebSocket {
 pub struct TrojanNetConfig {
     #[serde(default)]
     net: NetRef,
+    /// resolve the hostname of `server` by this net instead of `net`,
+    /// e.g. plain DNS while the traffic uses DoH.
+    #[serde(default)]
+    resolver: Option<NetRef>,
 
     /// hostname:port
     server: Address,
@@ -78,13 +101,24 @@ pub struct TrojanNetConfig {
     #[serde(skip_serializing_if = "rd_interface::config::detailed_field")]
     password: String,
 
-    /// sni
+    /// server name of TLS, the hostname of `server` if not set.
+    /// e.g. the domain of a CDN while `server` is its IP.
     #[serde(default)]
     sni: Option<String>,
     /// skip certificate verify
     #[serde(default)]
     skip_cert_verify: bool,
 
+    /// send trojan request header and the first payload as TLS early data (0-RTT)
+    /// on resumed sessions. Early data may be replayed by an attacker, only enable
+    /// it if the proxied requests are idempotent.
+    #[serde(default)]
+    enable_early_data: bool,
+
+    /// maximum TLS sessions remembered for resumption. 0 to disable. default is 256.
+    #[serde(default = "default_session_cache")]
+    session_cache: usize,
+
     /// enabled websocket support
     #[serde(default)]
     websocket: Option<WebSocket>,
@@ -97,6 +131,10 @@ pub struct TrojanNetConfig {
 pub struct TrojancNetConfig {
     #[serde(default)]
     net: NetRef,
+    /// resolve the hostname of `server` by this net instead of `net`,
+    /// e.g. plain DNS while the traffic uses DoH.
+    #[serde(default)]
+    resolver: Option<NetRef>,
 
     /// hostname:port
     server: RdAddress,
@@ -187,12 +225,17 @@ impl INet for TrojanNet {
     fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
         Some(self)
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use rd_interface::IntoAddress;
+    use rd_interface::{Context, IntoAddress, TcpConnect};
     use rd_std::tests::{assert_net_provider, ProviderCapability, TestNet};
+    use tokio::io::AsyncReadExt;
 
     use super::*;
 
@@ -202,10 +245,13 @@ mod tests {
 
         let trojan = TrojanNet::new_trojan(TrojanNetConfig {
             net: NetRef::new_with_value("test".into(), net),
+            resolver: None,
             server: "127.0.0.1:1234".into_address().unwrap(),
             password: "password".to_string(),
             sni: None,
             skip_cert_verify: false,
+            enable_early_data: false,
+            session_cache: default_session_cache(),
             websocket: None,
             handshake_timeout: None,
         })
@@ -221,4 +267,49 @@ mod tests {
             },
         );
     }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    #[tokio::test]
+    async fn test_sni_override() {
+        let net = TestNet::new().into_dyn();
+        let server = "127.0.0.1:26668".into_address().unwrap();
+
+        let listener = net.tcp_bind(&mut Context::new(), &server).await.unwrap();
+        let client_hello = tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let n = tcp.read(&mut buf).await.unwrap();
+            buf.truncate(n);
+            buf
+        });
+
+        let trojan = TrojanNet::new_trojan(TrojanNetConfig {
+            net: NetRef::new_with_value("test".into(), net),
+            resolver: None,
+            server,
+            password: "password".to_string(),
+            sni: Some("cdn.example.com".to_string()),
+            skip_cert_verify: false,
+            enable_early_data: false,
+            session_cache: default_session_cache(),
+            websocket: None,
+            handshake_timeout: Some(1),
+        })
+        .unwrap();
+        let destination = "example.org:443".into_address().unwrap();
+
+        // the handshake fails as the server closes the connection
+        let _ = trojan.tcp_connect(&mut Context::new(), &destination).await;
+
+        let client_hello = client_hello.await.unwrap();
+        assert!(contains(&client_hello, b"cdn.example.com"));
+        assert!(!contains(&client_hello, b"example.org"));
+
+        let head = trojan.make_head(1, ra2sa(destination)).unwrap();
+        assert!(contains(&head, b"example.org"));
+        assert!(!contains(&head, b"cdn.example.com"));
+    }
 }
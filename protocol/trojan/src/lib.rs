@@ -1,34 +1,34 @@
 use client::{TrojanNet, TrojanNetConfig, TrojancNetConfig};
-use rd_interface::{registry::Builder, Net, Registry, Result};
+use rd_interface::{init_registry, register_net, register_server};
+use server::{TrojanServer, TrojanServerConfig};
 
 mod client;
+mod server;
 mod stream;
 mod websocket;
 
-impl Builder<Net> for TrojanNet {
-    const NAME: &'static str = "trojan";
-    type Config = TrojanNetConfig;
-    type Item = Self;
-
-    fn build(config: Self::Config) -> Result<Self> {
-        TrojanNet::new_trojan(config)
-    }
-}
-
 pub struct TrojancNet;
-impl Builder<Net> for TrojancNet {
-    const NAME: &'static str = "trojanc";
-    type Config = TrojancNetConfig;
-    type Item = TrojanNet;
-
-    fn build(config: Self::Config) -> Result<TrojanNet> {
-        TrojanNet::new_trojanc(config)
-    }
-}
 
-pub fn init(registry: &mut Registry) -> Result<()> {
-    registry.add_net::<TrojanNet>();
-    registry.add_net::<TrojancNet>();
+register_net!(
+    TrojanNet,
+    "trojan",
+    TrojanNetConfig,
+    TrojanNet::new_trojan,
+    title = "Trojan Client",
+);
+register_net!(
+    TrojancNet => TrojanNet,
+    "trojanc",
+    TrojancNetConfig,
+    TrojanNet::new_trojanc,
+    title = "Trojan Client (cleartext)",
+);
+register_server!(
+    TrojanServer,
+    "trojan",
+    TrojanServerConfig,
+    TrojanServer::new,
+    title = "Trojan Server",
+);
 
-    Ok(())
-}
+init_registry!(net: [TrojanNet, TrojancNet], server: [TrojanServer]);
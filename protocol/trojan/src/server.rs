@@ -0,0 +1,292 @@
+use std::net::SocketAddr;
+
+use rd_interface::{
+    async_trait, prelude::*, registry::NetRef, Address, Error, IServer, Net, Result, TcpStream,
+};
+use rd_std::{
+    tls::{TlsAcceptor, TlsServerConfig},
+    util::{accept_with_backoff, ACCEPT_BACKOFF},
+    ContextExt,
+};
+use sha2::{Digest, Sha224};
+use socks5_protocol::Address as S5Addr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::instrument;
+
+/// Length of the hex encoded SHA224 password
+const HASH_LEN: usize = 56;
+const HEAD_LEN: usize = HASH_LEN + 2;
+
+#[rd_config]
+#[derive(Debug)]
+pub struct TrojanServerConfig {
+    bind: Address,
+    /// password in plain text
+    #[serde(skip_serializing_if = "rd_interface::config::detailed_field")]
+    password: String,
+    /// terminate TLS on the server. Omit it if TLS is terminated in front of the server.
+    #[serde(default)]
+    tls: Option<TlsServerConfig>,
+    /// relay the connections which are not trojan requests to this address,
+    /// e.g. a web server. They are closed if not set.
+    #[serde(default)]
+    fallback: Option<Address>,
+
+    #[serde(default)]
+    net: NetRef,
+    #[serde(default)]
+    listen: NetRef,
+}
+
+pub struct TrojanServer {
+    bind: Address,
+    password: Vec<u8>,
+    tls: Option<TlsAcceptor>,
+    fallback: Option<Address>,
+    net: Net,
+    listen: Net,
+}
+
+impl TrojanServer {
+    pub fn new(config: TrojanServerConfig) -> Result<Self> {
+        let password = hex::encode(Sha224::digest(config.password.as_bytes()));
+        let tls = config.tls.map(|tls| tls.build_acceptor()).transpose()?;
+
+        Ok(TrojanServer {
+            bind: config.bind,
+            password: password.into_bytes(),
+            tls,
+            fallback: config.fallback,
+            net: config.net.value_cloned(),
+            listen: config.listen.value_cloned(),
+        })
+    }
+}
+
+#[async_trait]
+impl IServer for TrojanServer {
+    async fn start(&self) -> Result<()> {
+        let listener = self
+            .listen
+            .tcp_bind(&mut rd_interface::Context::new(), &self.bind)
+            .await?;
+
+        loop {
+            let (socket, addr) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
+            let conn = Connection {
+                password: self.password.clone(),
+                tls: self.tls.clone(),
+                fallback: self.fallback.clone(),
+                net: self.net.clone(),
+            };
+            let _ = tokio::spawn(async move {
+                if let Err(e) = conn.serve(socket, addr).await {
+                    tracing::error!("Error when serve_connection: {:?}", e);
+                }
+            });
+        }
+    }
+}
+
+struct Connection {
+    password: Vec<u8>,
+    tls: Option<TlsAcceptor>,
+    fallback: Option<Address>,
+    net: Net,
+}
+
+impl Connection {
+    #[instrument(err, skip(self, socket))]
+    async fn serve(self, socket: TcpStream, addr: SocketAddr) -> Result<()> {
+        let mut socket = match &self.tls {
+            Some(tls) => TcpStream::from(tls.accept(socket).await?),
+            None => socket,
+        };
+        let ctx = &mut rd_interface::Context::from_socketaddr(addr);
+
+        let head = read_head(&mut socket).await?;
+        if head.len() != HEAD_LEN
+            || head[..HASH_LEN] != self.password[..]
+            || &head[HASH_LEN..] != b"\r\n"
+        {
+            return self.serve_fallback(ctx, socket, head).await;
+        }
+
+        let cmd = socket.read_u8().await?;
+        let target = S5Addr::read(&mut socket).await.map_err(|e| e.to_io_err())?;
+        let mut crlf = [0u8; 2];
+        socket.read_exact(&mut crlf).await?;
+
+        match cmd {
+            // Connect
+            1 => {
+                let target = self
+                    .net
+                    .tcp_connect(
+                        ctx,
+                        &match target {
+                            S5Addr::Domain(d, p) => Address::Domain(d, p),
+                            S5Addr::SocketAddr(s) => Address::SocketAddr(s),
+                        },
+                    )
+                    .await?;
+                ctx.connect_tcp(socket, target).await?;
+                Ok(())
+            }
+            // Udp associate
+            3 => Err(Error::other("UDP associate is not supported")),
+            cmd => Err(Error::other(format!("unknown trojan command: {}", cmd))),
+        }
+    }
+
+    async fn serve_fallback(
+        &self,
+        ctx: &mut rd_interface::Context,
+        socket: TcpStream,
+        head: Vec<u8>,
+    ) -> Result<()> {
+        let fallback = match &self.fallback {
+            Some(fallback) => fallback,
+            None => return Err(Error::other("invalid trojan request")),
+        };
+        tracing::debug!("fallback to {}", fallback);
+
+        let mut target = self.net.tcp_connect(ctx, fallback).await?;
+        target.write_all(&head).await?;
+        ctx.connect_tcp(socket, target).await?;
+        Ok(())
+    }
+}
+
+/// Read until the hashed password and CRLF are received, or the data can't be
+/// a trojan request. Never reads more than the head, so the rest can be relayed as is.
+async fn read_head(socket: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut head = vec![0u8; HEAD_LEN];
+    let mut len = 0;
+
+    while len < HEAD_LEN {
+        let n = socket.read(&mut head[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        // don't wait for more data if it's not a hex string, e.g. a HTTP request
+        if !head[..len.min(HASH_LEN)]
+            .iter()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        {
+            break;
+        }
+    }
+    head.truncate(len);
+
+    Ok(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rd_interface::{IntoAddress, IntoDyn};
+    use rd_std::tests::{spawn_echo_server, TestNet};
+    use socks5_protocol::sync::FromIO;
+    use tokio::time::sleep;
+
+    use super::*;
+
+    async fn start_server(net: &Net, bind: &str, fallback: Option<&str>) {
+        let server = TrojanServer::new(TrojanServerConfig {
+            bind: bind.into_address().unwrap(),
+            password: "password".to_string(),
+            tls: None,
+            fallback: fallback.map(|f| f.into_address().unwrap()),
+            net: NetRef::new_with_value("test".into(), net.clone()),
+            listen: NetRef::new_with_value("test".into(), net.clone()),
+        })
+        .unwrap();
+        tokio::spawn(async move { server.start().await });
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    async fn assert_relayed(net: &Net, server: &str, data: &[u8]) {
+        let mut tcp = net
+            .tcp_connect(
+                &mut rd_interface::Context::new(),
+                &server.into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        tcp.write_all(data).await.unwrap();
+
+        let mut buf = vec![0u8; data.len()];
+        tcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "127.0.0.1:26666").await;
+        start_server(&net, "127.0.0.1:16666", None).await;
+
+        let mut head = hex::encode(Sha224::digest(b"password")).into_bytes();
+        head.extend_from_slice(b"\r\n\x01");
+        S5Addr::SocketAddr("127.0.0.1:26666".parse().unwrap())
+            .write_to(&mut head)
+            .unwrap();
+        head.extend_from_slice(b"\r\n");
+
+        let mut tcp = net
+            .tcp_connect(
+                &mut rd_interface::Context::new(),
+                &"127.0.0.1:16666".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        tcp.write_all(&head).await.unwrap();
+        tcp.write_all(b"hello").await.unwrap();
+
+        let mut buf = [0u8; 5];
+        tcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "127.0.0.1:26666").await;
+        start_server(&net, "127.0.0.1:16666", Some("127.0.0.1:26666")).await;
+
+        // not a trojan request
+        assert_relayed(
+            &net,
+            "127.0.0.1:16666",
+            b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n",
+        )
+        .await;
+
+        // wrong password
+        let mut data = hex::encode(Sha224::digest(b"wrong")).into_bytes();
+        data.extend_from_slice(b"\r\n\x01\x01\x7f\x00\x00\x01\x00\x50\r\nhello");
+        assert_relayed(&net, "127.0.0.1:16666", &data).await;
+    }
+
+    #[tokio::test]
+    async fn test_no_fallback() {
+        let net = TestNet::new().into_dyn();
+        start_server(&net, "127.0.0.1:16666", None).await;
+
+        let mut tcp = net
+            .tcp_connect(
+                &mut rd_interface::Context::new(),
+                &"127.0.0.1:16666".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        tcp.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+
+        let mut buf = Vec::new();
+        tcp.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.is_empty());
+    }
+}
@@ -1,10 +1,23 @@
 pub mod default;
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    net::{IpAddr, SocketAddr},
+};
 
 use indexmap::IndexMap;
-use rd_interface::Value;
+use rd_interface::{Error, Value};
+use rd_std::{
+    rule::config::IpCidr,
+    util::{is_reserved, resolve_mapped_socket_addr, ConnectionLimitConfig},
+};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::registry::{Item, Registry};
+
+const REDACTED: &str = "<redacted>";
 
 pub type ConfigNet = IndexMap<String, Net>;
 pub type ConfigServer = IndexMap<String, Server>;
@@ -17,6 +30,33 @@ pub struct Config {
     pub net: ConfigNet,
     #[serde(default)]
     pub server: ConfigServer,
+    /// Skip the servers failing to start instead of stopping all of them. Default is true.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continue_on_server_error: Option<bool>,
+    /// Stop tracking the new connections when this many are tracked, their
+    /// traffic still flows but is not counted. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tracked_connections: Option<usize>,
+    /// How often the connections report their traffic, in milliseconds. A shorter
+    /// one updates the stats sooner with more wakeups. Default is 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_flush_interval: Option<u64>,
+    /// How many connection events are processed at most before the event task
+    /// yields to the others. Default is 32.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event_batch_size: Option<usize>,
+    /// How long a softly stopped connection waits for the peer to close before
+    /// it's aborted, in milliseconds. Default is 30000.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft_stop_timeout: Option<u64>,
+    /// Context keys hidden from the connections shown by the api and the logs, in
+    /// addition to the sensitive ones like `password` and `token`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_context: Vec<String>,
+    /// JSON pointers of the fields read from the secrets, e.g. `/net/proxy/password`.
+    /// They are redacted by [`Config::to_redacted_string`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secrets: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -28,7 +68,94 @@ pub struct Net {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
-pub struct ServerMetadata {}
+pub struct ServerMetadata {
+    /// Size of the buffer to relay UDP datagrams, larger datagrams are dropped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub udp_buffer_size: Option<usize>,
+    #[serde(flatten)]
+    pub source_filter: SourceFilter,
+    /// Close the TCP connections stopped by the user with a RST instead of a FIN,
+    /// so the resources of the peers are freed immediately.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reset_on_stop: bool,
+    /// Report the connect attempts of the TCP connections to the connection state while
+    /// they are being established. It has some overhead, so it's off by default.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub connect_events: bool,
+    /// Build the nets the server connects out by on its first connection instead of
+    /// at start, and drop them when nothing is connected for this many seconds, e.g.
+    /// for a tunnel used occasionally. They are built for this server only, so their
+    /// errors are reported on connecting, and the changes by the API don't apply to them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_shutdown: Option<u64>,
+    /// Limit of the in-flight TCP connections of the server, e.g. under a connection
+    /// flood. Unlimited if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<ConnectionLimitConfig>,
+}
+
+/// Source addresses of inbound connections, a CIDR like `10.0.0.0/8`, or `private`
+/// for the private, loopback and link-local addresses.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub enum SourceCidr {
+    Private,
+    Cidr(IpCidr),
+}
+
+impl SourceCidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match self {
+            SourceCidr::Private => is_reserved(ip),
+            SourceCidr::Cidr(cidr) => cidr.0.contains_addr(&ip.into()),
+        }
+    }
+}
+
+impl TryFrom<String> for SourceCidr {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "private" => Ok(SourceCidr::Private),
+            s => Ok(SourceCidr::Cidr(s.parse()?)),
+        }
+    }
+}
+
+impl From<SourceCidr> for String {
+    fn from(cidr: SourceCidr) -> Self {
+        match cidr {
+            SourceCidr::Private => "private".to_string(),
+            SourceCidr::Cidr(cidr) => cidr.to_string(),
+        }
+    }
+}
+
+/// Gates the inbound connections and UDP datagrams of a server by their source addresses.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SourceFilter {
+    /// Only accept the connections from these addresses. All are accepted if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<SourceCidr>,
+    /// Close the connections and drop the datagrams from these addresses, even if they
+    /// are allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<SourceCidr>,
+}
+
+impl SourceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+    pub fn is_allowed(&self, addr: SocketAddr) -> bool {
+        let ip = resolve_mapped_socket_addr(addr).ip();
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Server {
@@ -45,6 +172,138 @@ impl Config {
     pub fn merge(&mut self, other: Config) {
         self.net.extend(other.net);
         self.server.extend(other.server);
+        self.secrets.extend(other.secrets);
+    }
+    /// Check the referenced nets exist before building, all the missing ones are reported
+    /// with their locations. Nets and servers with invalid config are skipped here.
+    pub fn check_net_refs(&self, registry: &Registry) -> rd_interface::Result<()> {
+        let mut missing = Vec::new();
+
+        for (name, net) in &self.net {
+            if let Ok(item) = registry.get_net(&net.net_type) {
+                let location = format!("net/{}", name);
+                self.find_missing_nets(registry, item, &net.opt, &location, &mut missing);
+            }
+        }
+        for (name, server) in &self.server {
+            if let Ok(item) = registry.get_server(&server.server_type) {
+                let location = format!("server/{}", name);
+                self.find_missing_nets(registry, item, &server.opt, &location, &mut missing);
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+        Err(Error::NotFound(format!(
+            "Failed to find nets in config file:\n{}",
+            missing.join("\n")
+        )))
+    }
+    fn find_missing_nets<T>(
+        &self,
+        registry: &Registry,
+        item: &Item<T>,
+        opt: &Value,
+        location: &str,
+        missing: &mut Vec<String>,
+    ) {
+        let mut inline_nets = Vec::new();
+        let _ = item.visit_net_ref(opt, &mut |ctx, net_ref| {
+            let location = format!("{}/{}", location, ctx.path().join("/"));
+            match net_ref.represent() {
+                Value::String(name) => {
+                    // missing optional nets are replaced by blackhole
+                    if !ctx.is_optional() && !self.net.contains_key(name) {
+                        missing.push(format!("  {}: {:?}", location, name));
+                    }
+                }
+                net_cfg => inline_nets.push((location, net_cfg.clone())),
+            }
+        });
+
+        for (location, net_cfg) in inline_nets {
+            if let Ok(net) = serde_json::from_value::<Net>(net_cfg) {
+                if let Ok(item) = registry.get_net(&net.net_type) {
+                    self.find_missing_nets(registry, item, &net.opt, &location, missing);
+                }
+            }
+        }
+    }
+    /// Add the upstream servers of the nets of the `raw` servers to their `bypass_server`,
+    /// so the connections to them don't loop back into the tunnel.
+    pub fn fill_bypass_server(&mut self, registry: &Registry) {
+        let item = match registry.get_server("raw") {
+            Ok(item) => item,
+            Err(_) => return,
+        };
+        let upstreams = self
+            .server
+            .iter()
+            .filter(|(_, server)| server.server_type == "raw")
+            .map(|(name, server)| {
+                (
+                    name.clone(),
+                    self.upstream_servers(registry, item, &server.opt),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for (name, upstreams) in upstreams {
+            let opt = match self
+                .server
+                .get_mut(&name)
+                .and_then(|s| s.opt.as_object_mut())
+            {
+                Some(opt) => opt,
+                None => continue,
+            };
+            if let Value::Array(servers) = opt
+                .entry("bypass_server")
+                .or_insert_with(|| Value::Array(Vec::new()))
+            {
+                for upstream in upstreams {
+                    if !servers.contains(&upstream) {
+                        servers.push(upstream);
+                    }
+                }
+            }
+        }
+    }
+    /// The `server` of the nets referred by `opt`, directly or through other nets.
+    fn upstream_servers<T>(&self, registry: &Registry, item: &Item<T>, opt: &Value) -> Vec<Value> {
+        let mut servers = Vec::new();
+        self.collect_servers(registry, item, opt, &mut BTreeSet::new(), &mut servers);
+        servers
+    }
+    fn collect_servers<T>(
+        &self,
+        registry: &Registry,
+        item: &Item<T>,
+        opt: &Value,
+        visited: &mut BTreeSet<String>,
+        servers: &mut Vec<Value>,
+    ) {
+        let mut nets = Vec::new();
+        let _ = item.visit_net_ref(opt, &mut |_, net_ref| match net_ref.represent() {
+            Value::String(name) => {
+                if visited.insert(name.clone()) {
+                    nets.extend(self.net.get(name).cloned());
+                }
+            }
+            net_cfg => nets.extend(serde_json::from_value::<Net>(net_cfg.clone()).ok()),
+        });
+
+        for net in nets {
+            if let Some(server) = net.opt.get("server").filter(|s| s.is_string()) {
+                if !servers.contains(server) {
+                    servers.push(server.clone());
+                }
+            }
+            if let Ok(item) = registry.get_net(&net.net_type) {
+                self.collect_servers(registry, item, &net.opt, visited, servers);
+            }
+        }
     }
 }
 
@@ -80,6 +339,62 @@ impl Net {
     }
 }
 
+/// Sort the keys of the objects, so the serialized value doesn't depend on the order.
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map
+                .into_iter()
+                .map(|(k, v)| (k, canonicalize(v)))
+                .collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(entries.into_iter().collect())
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(canonicalize).collect()),
+        value => value,
+    }
+}
+
+impl Config {
+    /// The SHA-256 of the config in hex, excluding the `id`. The configs differing
+    /// only in the order of the keys, e.g. of the nets, have the same hash.
+    pub fn content_hash(&self) -> rd_interface::Result<String> {
+        let mut value = serde_json::to_value(self)?;
+        if let Value::Object(map) = &mut value {
+            map.remove("id");
+        }
+        let hash = Sha256::digest(serde_json::to_vec(&canonicalize(value))?);
+        Ok(hash.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+    /// Serialize the config with the values of the secrets replaced by
+    /// `"<redacted>"`, e.g. to be shown by the api.
+    pub fn to_redacted_string(&self) -> serde_json::Result<String> {
+        serde_json::to_string(&self.to_redacted_value()?)
+    }
+    /// The config with the values of the secrets replaced by `"<redacted>"`. The
+    /// `secrets` are left out, a `secrets` key is the secrets file when it's loaded.
+    pub fn to_redacted_value(&self) -> serde_json::Result<Value> {
+        let mut value = serde_json::to_value(self)?;
+        for pointer in &self.secrets {
+            if let Some(v) = value.pointer_mut(pointer) {
+                *v = Value::String(REDACTED.to_string());
+            }
+        }
+        if let Value::Object(map) = &mut value {
+            map.remove("secrets");
+        }
+        Ok(value)
+    }
+    /// The `id` if it's set, otherwise the hash of the content.
+    pub fn id_or_hash(&self) -> rd_interface::Result<String> {
+        if self.id.is_empty() {
+            self.content_hash()
+        } else {
+            Ok(self.id.clone())
+        }
+    }
+}
+
 impl Server {
     pub fn new(server_type: impl Into<String>, opt: Value) -> Server {
         Server {
@@ -126,3 +441,144 @@ pub fn init_default_net(config_net: &mut ConfigNet) -> rd_interface::Result<()>
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_hash() {
+        let config = |value: Value| serde_json::from_value::<Config>(value).unwrap();
+        let a = config(serde_json::json!({
+            "net": {
+                "proxy": { "type": "socks5", "server": "127.0.0.1:1080", "udp": true },
+                "direct": { "type": "local" },
+            },
+            "server": {
+                "mixed": { "type": "http+socks5", "bind": "127.0.0.1:10800", "net": "proxy" },
+            },
+        }));
+        let reordered = config(serde_json::json!({
+            "server": {
+                "mixed": { "net": "proxy", "bind": "127.0.0.1:10800", "type": "http+socks5" },
+            },
+            "net": {
+                "direct": { "type": "local" },
+                "proxy": { "udp": true, "server": "127.0.0.1:1080", "type": "socks5" },
+            },
+        }));
+        let changed = config(serde_json::json!({
+            "net": {
+                "proxy": { "type": "socks5", "server": "127.0.0.1:1081", "udp": true },
+                "direct": { "type": "local" },
+            },
+            "server": {
+                "mixed": { "type": "http+socks5", "bind": "127.0.0.1:10800", "net": "proxy" },
+            },
+        }));
+
+        let id = a.id_or_hash().unwrap();
+        assert_eq!(id.len(), 64);
+        assert_eq!(id, reordered.id_or_hash().unwrap());
+        assert_ne!(id, changed.id_or_hash().unwrap());
+
+        // the given id is kept, and it's not a part of the hash
+        let mut with_id = a.clone();
+        with_id.id = "given".to_string();
+        assert_eq!(with_id.id_or_hash().unwrap(), "given");
+        assert_eq!(with_id.content_hash().unwrap(), id);
+    }
+
+    #[test]
+    fn test_upstream_servers() {
+        let registry = Registry::new_with_builtin().unwrap();
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "net": {
+                "proxy": { "type": "socks5", "server": "1.2.3.4:1080" },
+                "backup": { "type": "http", "server": "example.com:8080" },
+                "alias": { "type": "alias", "net": "proxy" },
+                "rule": {
+                    "type": "rule",
+                    "rule": [
+                        { "type": "domain", "method": "match", "domain": "a.com", "target": "alias" },
+                        { "type": "domain", "method": "match", "domain": "b.com", "target": "proxy" },
+                        { "type": "any", "target": "backup" },
+                    ],
+                },
+            },
+        }))
+        .unwrap();
+
+        let item = registry.get_server("http+socks5").unwrap();
+        let opt = serde_json::json!({ "bind": "127.0.0.1:10800", "net": "rule" });
+        assert_eq!(
+            config.upstream_servers(&registry, item, &opt),
+            vec![Value::from("1.2.3.4:1080"), Value::from("example.com:8080")]
+        );
+
+        let opt = serde_json::json!({ "bind": "127.0.0.1:10800", "net": "local" });
+        assert!(config.upstream_servers(&registry, item, &opt).is_empty());
+    }
+
+    #[test]
+    fn test_redacted() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "net": {
+                "proxy": { "type": "shadowsocks", "server": "1.2.3.4:8388", "password": "p@ss" },
+            },
+            "secrets": ["/net/proxy/password", "/net/removed/password"],
+        }))
+        .unwrap();
+
+        let redacted: Value = serde_json::from_str(&config.to_redacted_string().unwrap()).unwrap();
+        assert_eq!(redacted["net"]["proxy"]["password"], "<redacted>");
+        assert_eq!(redacted["net"]["proxy"]["server"], "1.2.3.4:8388");
+        assert!(!config.to_redacted_string().unwrap().contains("p@ss"));
+        assert!(redacted.get("secrets").is_none());
+
+        // the secrets are kept in the full config
+        let full: Config = serde_json::from_str(&serde_json::to_string(&config).unwrap()).unwrap();
+        assert_eq!(full.net["proxy"].opt["password"], "p@ss");
+        assert_eq!(full.secrets, config.secrets);
+    }
+
+    fn filter(allow: &[&str], deny: &[&str]) -> SourceFilter {
+        serde_json::from_value(serde_json::json!({ "allow": allow, "deny": deny })).unwrap()
+    }
+
+    #[test]
+    fn test_source_filter() {
+        let allowed = |f: &SourceFilter, addr: &str| f.is_allowed(addr.parse().unwrap());
+
+        let f = SourceFilter::default();
+        assert!(f.is_empty());
+        assert!(allowed(&f, "1.2.3.4:1"));
+        assert!(allowed(&f, "[2001:db8::1]:1"));
+
+        let f = filter(&["10.0.0.0/8", "2001:db8::/32"], &["10.0.0.1/32"]);
+        assert!(allowed(&f, "10.1.2.3:1"));
+        assert!(allowed(&f, "[2001:db8::1]:1"));
+        // IPv4-mapped IPv6 addresses of dual stack sockets
+        assert!(allowed(&f, "[::ffff:10.1.2.3]:1"));
+        assert!(!allowed(&f, "10.0.0.1:1"));
+        assert!(!allowed(&f, "192.168.1.1:1"));
+        assert!(!allowed(&f, "[2001:db9::1]:1"));
+
+        let f = filter(&["private"], &[]);
+        assert!(allowed(&f, "127.0.0.1:1"));
+        assert!(allowed(&f, "192.168.1.1:1"));
+        assert!(allowed(&f, "[::1]:1"));
+        assert!(allowed(&f, "[fd00::1]:1"));
+        assert!(!allowed(&f, "1.2.3.4:1"));
+        assert!(!allowed(&f, "[2001:db8::1]:1"));
+
+        let f = filter(&[], &["private"]);
+        assert!(!allowed(&f, "10.0.0.1:1"));
+        assert!(allowed(&f, "1.2.3.4:1"));
+
+        assert!(
+            serde_json::from_value::<SourceFilter>(serde_json::json!({ "allow": ["local"] }))
+                .is_err()
+        );
+    }
+}
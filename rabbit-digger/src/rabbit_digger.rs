@@ -1,13 +1,13 @@
-use std::{cell::RefCell, collections::BTreeMap, fmt, mem::replace, time::Duration};
+use std::{cell::RefCell, collections::BTreeMap, fmt, mem::replace, path::PathBuf, time::Duration};
 
 use crate::{
     config::{self, init_default_net},
     rabbit_digger::running::{RunningNet, RunningServer, RunningServerNet},
-    registry::{Registry, RegistrySchema},
+    registry::{Registry, RegistrySchema, RegistryTypes},
 };
 use anyhow::{anyhow, Context, Result};
 use futures::{
-    future::{try_select, Either},
+    future::{join_all, try_select, Either},
     stream::FuturesUnordered,
     Stream, StreamExt, TryStreamExt,
 };
@@ -16,21 +16,50 @@ use rd_interface::{
         serialize_with_fields, CompactVecString, NetRef, VisitorContext, ALL_SERIALIZE_FIELDS,
     },
     registry::NetGetter,
-    Arc, Error, IntoDyn, Net, Server, Value,
+    schemars::{self, schema::RootSchema, schema_for, JsonSchema},
+    Address, Arc, Error, IntoDyn, Net, Server, Value,
 };
+use rd_std::{
+    builtin::dns::DnsNet,
+    rule::{config::RuleItem, RuleNet},
+    sniffer::DNSSnifferNet,
+    util::ConnectionLimit,
+};
+use serde::Serialize;
 use tokio::{
     pin,
-    sync::RwLock,
+    sync::{broadcast, RwLock},
     task::{yield_now, JoinError},
-    time::timeout,
+    time::{sleep, timeout},
 };
 use uuid::Uuid;
 
-use self::connection_manager::{ConnectionManager, ConnectionState};
+use self::{
+    connect_stats::NetConnectStats,
+    connection_manager::{ConnectionManager, ConnectionState},
+    on_demand::{NetBuilder, OnDemandNet},
+    provider_rules::ProviderSplit,
+    traffic::{TrafficHistory, TrafficSample},
+};
+
+pub use self::connection_manager::StopMode;
 
+mod connect_stats;
 mod connection_manager;
 mod event;
+mod on_demand;
+mod provider_rules;
 mod running;
+mod traffic;
+
+/// The responses of the api, only for their schema.
+#[allow(dead_code)]
+#[derive(JsonSchema)]
+struct ApiResponses {
+    connection: ConnectionState,
+    registry: RegistrySchema,
+    traffic_history: Vec<TrafficSample>,
+}
 
 struct RunningEntities {
     nets: BTreeMap<String, Arc<RunningNet>>,
@@ -38,6 +67,7 @@ struct RunningEntities {
 }
 
 struct SerializedConfig {
+    /// The `id` of the config, or the hash of its content if it's not set.
     id: String,
     all_fields: String,
     simple_fields: String,
@@ -46,9 +76,32 @@ struct SerializedConfig {
 #[allow(dead_code)]
 struct Running {
     config: RwLock<SerializedConfig>,
+    /// The config split by the rules of the rule providers, to find the configs
+    /// only changing them.
+    providers: RwLock<ProviderSplit>,
     entities: RunningEntities,
+    /// Errors of the servers skipped by `continue_on_server_error`.
+    server_errors: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ServerStatus {
+    Running,
+    Failed { error: String },
+}
+
+/// The result of a config applied by `start_stream`.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ConfigStatus {
+    /// The `id` of the config, or the hash of its content if it's not set.
+    pub id: String,
+    /// Why the config is rejected, `None` if it's running.
+    pub error: Option<String>,
 }
 
+const CONFIG_STATUS_CAPACITY: usize = 16;
+
 enum State {
     WaitConfig,
     Running(Running),
@@ -66,6 +119,7 @@ impl State {
 struct Inner {
     state: RwLock<State>,
     conn_mgr: ConnectionManager,
+    config_status: broadcast::Sender<ConfigStatus>,
 }
 
 impl Drop for Inner {
@@ -93,6 +147,7 @@ impl RabbitDigger {
         let inner = Inner {
             state: RwLock::new(State::WaitConfig),
             conn_mgr: manager,
+            config_status: broadcast::channel(CONFIG_STATUS_CAPACITY).0,
         };
 
         Ok(RabbitDigger {
@@ -122,6 +177,28 @@ impl RabbitDigger {
 
         Ok(())
     }
+    /// Stop the servers, then wait up to `drain_timeout` for the connections to finish.
+    /// The connections still alive after the timeout are stopped.
+    /// Returns whether all the connections finished in time.
+    pub async fn stop_graceful(&self, drain_timeout: Duration) -> Result<bool> {
+        self.stop().await?;
+
+        let conn_mgr = &self.inner.conn_mgr;
+        let drained = timeout(drain_timeout, async {
+            while conn_mgr.borrow_state(|s| s.connection_count()) > 0 {
+                sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .is_ok();
+
+        if !drained {
+            let count = conn_mgr.stop_connections(StopMode::Hard);
+            tracing::info!("{} connections are not finished in time, stopped.", count);
+        }
+
+        Ok(drained)
+    }
     pub async fn join(&self) -> Result<()> {
         let inner = &self.inner;
 
@@ -169,6 +246,29 @@ impl RabbitDigger {
         self.inner.conn_mgr.borrow_state(f)
     }
 
+    // get traffic history
+    pub async fn traffic_history<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&TrafficHistory) -> R,
+    {
+        self.inner.conn_mgr.borrow_traffic_history(f)
+    }
+
+    // get tcp_connect statistics of all running nets
+    pub async fn connect_stats(&self) -> Result<BTreeMap<String, NetConnectStats>> {
+        let state = self.inner.state.read().await;
+        match &*state {
+            State::Running(Running {
+                entities: RunningEntities { nets, .. },
+                ..
+            }) => Ok(nets
+                .iter()
+                .map(|(name, net)| (name.clone(), net.connect_stats()))
+                .collect()),
+            _ => Err(anyhow!("Not running")),
+        }
+    }
+
     // get state
     pub async fn state_str(&self) -> Result<&'static str> {
         let state = self.inner.state.read().await;
@@ -186,12 +286,54 @@ impl RabbitDigger {
         f(&self.registry.get_registry_schema())
     }
 
+    // list the net and server types with their titles
+    pub async fn registry_types(&self) -> RegistryTypes {
+        self.registry.get_registry_types()
+    }
+
+    // get schema of a single net type
+    pub async fn net_schema(&self, net_type: &str) -> Option<RootSchema> {
+        self.registry.get_net_schema(net_type).ok().cloned()
+    }
+
+    // get schema of a single server type
+    pub async fn server_schema(&self, server_type: &str) -> Option<RootSchema> {
+        self.registry.get_server_schema(server_type).ok().cloned()
+    }
+
+    // get schema of the responses of connection, registry and traffic_history
+    pub fn api_schema(&self) -> RootSchema {
+        schema_for!(ApiResponses)
+    }
+
+    /// Build the nets and servers of `config` without starting them, to find the
+    /// errors in the config before it's used.
+    pub fn validate_config(&self, mut config: config::Config) -> Result<()> {
+        let conn_mgr = ConnectionManager::new();
+        let result = self.registry.build_entities(&mut config, &conn_mgr);
+        conn_mgr.stop();
+
+        result.context("Failed to build server")?;
+        Ok(())
+    }
+
+    /// Build the net `name` of `config` alone, without the servers. The running
+    /// config is not changed, e.g. to benchmark a net of a config.
+    pub fn build_net(&self, mut config: config::Config, name: &str) -> Result<Net> {
+        self.registry
+            .build_net_by_name(&mut config, name)
+            .context(format!("Failed to build net {}", name))
+    }
+
     // start all server, all server run in background.
     pub async fn start(&self, mut config: config::Config) -> Result<()> {
         let inner = &self.inner;
 
         tracing::debug!("Registry:\n{}", self.registry);
 
+        // hashed before the default nets are added by the build
+        let id = config.id_or_hash()?;
+        let providers = ProviderSplit::new(&config)?;
         let entities = self
             .registry
             .build_entities(&mut config, &inner.conn_mgr)
@@ -206,9 +348,38 @@ impl RabbitDigger {
 
         self.stop().await?;
         let state = &mut *inner.state.write().await;
-
-        for ServerInfo { running_server, .. } in entities.servers.values() {
-            running_server.start().await?;
+        inner
+            .conn_mgr
+            .set_max_connections(config.max_tracked_connections);
+        inner
+            .conn_mgr
+            .set_flush_interval(config.event_flush_interval.map(Duration::from_millis));
+        inner.conn_mgr.set_event_batch_size(config.event_batch_size);
+        inner
+            .conn_mgr
+            .set_soft_stop_timeout(config.soft_stop_timeout.map(Duration::from_millis));
+        inner.conn_mgr.set_redact_context(&config.redact_context);
+
+        // the servers are started together, as each is awaited for a while
+        let results = join_all(
+            entities
+                .servers
+                .iter()
+                .map(|(name, i)| async move { (name, i.running_server.start().await) }),
+        )
+        .await;
+        let mut server_errors = BTreeMap::new();
+        for (name, result) in results {
+            if let Err(e) = result {
+                if !config.continue_on_server_error.unwrap_or(true) {
+                    for i in entities.servers.values() {
+                        i.running_server.stop().await?;
+                    }
+                    return Err(e.context(format!("Failed to start server {}", name)));
+                }
+                tracing::error!("Failed to start server {}, skipped: {:?}", name, e);
+                server_errors.insert(name.clone(), format!("{:?}", e));
+            }
         }
 
         *state = State::Running(Running {
@@ -216,19 +387,54 @@ impl RabbitDigger {
                 all_fields: serialize_with_fields(ALL_SERIALIZE_FIELDS.to_vec(), || {
                     serde_json::to_string(&config)
                 })?,
-                simple_fields: serde_json::to_string(&config)?,
-                id: config.id,
+                simple_fields: config.to_redacted_string()?,
+                id,
             }),
+            providers: RwLock::new(providers),
             entities,
+            server_errors,
         });
 
         Ok(())
     }
 
+    // get status of all servers
+    pub async fn server_status(&self) -> Result<BTreeMap<String, ServerStatus>> {
+        let state = self.inner.state.read().await;
+        match &*state {
+            State::Running(Running {
+                entities: RunningEntities { servers, .. },
+                server_errors,
+                ..
+            }) => Ok(servers
+                .keys()
+                .map(|name| {
+                    let status = match server_errors.get(name) {
+                        Some(error) => ServerStatus::Failed {
+                            error: error.clone(),
+                        },
+                        None => ServerStatus::Running,
+                    };
+                    (name.clone(), status)
+                })
+                .collect()),
+            _ => Err(anyhow!("Not running")),
+        }
+    }
+
     pub async fn is_running(&self) -> bool {
         matches!(*self.inner.state.read().await, State::Running { .. })
     }
 
+    /// Receives the status of each config applied by `start_stream` from now on, in order.
+    pub fn subscribe_config_status(&self) -> broadcast::Receiver<ConfigStatus> {
+        self.inner.config_status.subscribe()
+    }
+
+    /// Apply the configs of `config_stream` one by one. Each config is reported by
+    /// `subscribe_config_status`. A rejected first config is an error, while a rejected
+    /// later config waits for the next config. A config only changing the rules of
+    /// the rule providers updates them in place, without a restart.
     pub async fn start_stream<S>(self, config_stream: S) -> Result<()>
     where
         S: Stream<Item = Result<config::Config>>,
@@ -245,38 +451,76 @@ impl RabbitDigger {
             }
         };
 
-        let reason = loop {
+        let mut first = true;
+        let reason = 'run: loop {
             tracing::info!("rabbit digger is starting...");
 
-            self.start(config).await?;
+            let id = config.id_or_hash().unwrap_or_else(|_| config.id.clone());
+            let result = self.start(config).await;
+            // no one is subscribing
+            let _ = self.inner.config_status.send(ConfigStatus {
+                id: id.clone(),
+                error: result.as_ref().err().map(|e| format!("{:?}", e)),
+            });
+            match result {
+                Ok(()) => {}
+                Err(e) if first => return Err(e.context("Failed to apply first config.")),
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to apply config {}: {:?}, waiting for next config...",
+                        id,
+                        e
+                    );
+                }
+            }
+            first = false;
 
-            let new_config = {
-                let join_fut = self.join();
-                pin!(join_fut);
+            config = loop {
+                let new_config = {
+                    let join_fut = self.join();
+                    pin!(join_fut);
 
-                match try_select(join_fut, config_stream.try_next()).await {
-                    Ok(Either::Left((_, cfg_fut))) => {
-                        tracing::info!("Exited normally, waiting for next config...");
-                        cfg_fut.await
+                    match try_select(join_fut, config_stream.try_next()).await {
+                        Ok(Either::Left((_, cfg_fut))) => {
+                            tracing::info!("Exited normally, waiting for next config...");
+                            cfg_fut.await
+                        }
+                        Ok(Either::Right((cfg, _))) => Ok(cfg),
+                        Err(Either::Left((e, cfg_fut))) => {
+                            tracing::error!(
+                                "Rabbit digger went to error: {:?}, waiting for next config...",
+                                e
+                            );
+                            cfg_fut.await
+                        }
+                        Err(Either::Right((e, _))) => Err(e),
+                    }
+                };
+
+                let config = match new_config {
+                    Ok(Some(v)) => v,
+                    Ok(None) => break 'run Ok(()),
+                    Err(e) => break 'run Err(e),
+                };
+
+                match self.update_provider_rules(&config).await {
+                    Ok(Some(id)) => {
+                        let _ = self
+                            .inner
+                            .config_status
+                            .send(ConfigStatus { id, error: None });
                     }
-                    Ok(Either::Right((cfg, _))) => Ok(cfg),
-                    Err(Either::Left((e, cfg_fut))) => {
-                        tracing::error!(
-                            "Rabbit digger went to error: {:?}, waiting for next config...",
+                    Ok(None) => break config,
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to update the rules of the rule providers: {:?}, restarting...",
                             e
                         );
-                        cfg_fut.await
+                        break config;
                     }
-                    Err(Either::Right((e, _))) => Err(e),
                 }
             };
 
-            config = match new_config {
-                Ok(Some(v)) => v,
-                Ok(None) => break Ok(()),
-                Err(e) => break Err(e),
-            };
-
             self.stop().await?;
         };
 
@@ -291,7 +535,7 @@ impl RabbitDigger {
         let mut close_count = 0;
 
         while self.inner.conn_mgr.borrow_state(|s| s.connection_count()) > 0 {
-            close_count += self.inner.conn_mgr.stop_connections();
+            close_count += self.inner.conn_mgr.stop_connections(StopMode::Hard);
             // Wait connections to exit.
             yield_now().await;
         }
@@ -312,6 +556,11 @@ impl RabbitDigger {
         }
     }
 
+    /// Types of the nets a connection to `name` goes through, from the outermost to the leaf.
+    pub async fn net_chain(&self, name: &str) -> Result<Option<Vec<String>>> {
+        Ok(self.get_net(name).await?.map(|net| net.chain()))
+    }
+
     // Update net when running.
     pub async fn update_net<F>(&self, net_name: &str, update: F) -> Result<()>
     where
@@ -352,7 +601,7 @@ impl RabbitDigger {
                         serialize_with_fields(ALL_SERIALIZE_FIELDS.to_vec(), || {
                             serde_json::to_string(&config)
                         })?;
-                    serialized_config.simple_fields = serde_json::to_string(&config)?;
+                    serialized_config.simple_fields = config.to_redacted_string()?;
                 }
                 return Ok(());
             }
@@ -362,6 +611,189 @@ impl RabbitDigger {
         };
     }
 
+    /// Update the rules of the rule providers in place if only they are changed by
+    /// `config`, e.g. a clash `RULE-SET` is refreshed. Returns the id of `config` if
+    /// it's applied so.
+    async fn update_provider_rules(&self, config: &config::Config) -> Result<Option<String>> {
+        let split = ProviderSplit::new(config)?;
+        let changed = {
+            let state = self.inner.state.read().await;
+            let running = match state.running() {
+                Some(running) => running,
+                None => return Ok(None),
+            };
+            let providers = running.providers.read().await;
+            match providers.changed_rules(&split) {
+                Some(changed) => changed,
+                None => return Ok(None),
+            }
+        };
+
+        for ((net_name, provider), rules) in changed {
+            tracing::info!("Updating rule provider {} of {}", provider, net_name);
+            let rules = rules
+                .into_iter()
+                .map(serde_json::from_value)
+                .collect::<serde_json::Result<Vec<RuleItem>>>()?;
+            self.update_rule_provider(&net_name, &provider, rules)
+                .await?;
+        }
+
+        let id = config.id_or_hash()?;
+        let state = self.inner.state.read().await;
+        if let Some(running) = state.running() {
+            running.config.write().await.id = id.clone();
+            *running.providers.write().await = split;
+        }
+        Ok(Some(id))
+    }
+
+    /// Replace the rules derived from the rule provider `provider` of the rule net
+    /// `net_name`, without rebuilding the net, so the other rules and the open
+    /// connections are kept. The targets of `rules` are the names of running nets.
+    pub async fn update_rule_provider(
+        &self,
+        net_name: &str,
+        provider: &str,
+        mut rules: Vec<RuleItem>,
+    ) -> Result<()> {
+        let state = self.inner.state.read().await;
+        let running = state.running().ok_or_else(|| anyhow!("Not running"))?;
+        let nets = &running.entities.nets;
+
+        let rule_net = nets
+            .get(net_name)
+            .and_then(|net| net.net_as::<RuleNet>())
+            .ok_or_else(|| anyhow!("Rule net not found: {}", net_name))?;
+        let serialized_rules = serde_json::to_value(&rules)?;
+        for rule in &mut rules {
+            let name = rule
+                .target
+                .represent()
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("Net not found: {}", rule.target.represent()))?;
+            let target = nets
+                .get(&name)
+                .map(|i| i.as_net())
+                .ok_or_else(|| anyhow!("Net not found: {}", name))?;
+            rule.target = NetRef::new_with_value(name.into(), target);
+        }
+
+        let mut serialized_config = running.config.write().await;
+        let mut config: config::Config = serde_json::from_str(&serialized_config.all_fields)?;
+        let pos = rule_net.update_provider(provider, rules)?;
+
+        let new_items = match serialized_rules {
+            Value::Array(new_items) => new_items
+                .into_iter()
+                .map(|mut i| {
+                    i["provider"] = provider.into();
+                    i
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        if let Some(Value::Array(items)) = config
+            .net
+            .get_mut(net_name)
+            .and_then(|net| net.opt.get_mut("rule"))
+        {
+            items.retain(|i| i.get("provider").and_then(Value::as_str) != Some(provider));
+            items.splice(pos..pos, new_items.iter().cloned());
+        }
+        running
+            .providers
+            .write()
+            .await
+            .rules
+            .insert((net_name.to_string(), provider.to_string()), new_items);
+        serialized_config.all_fields =
+            serialize_with_fields(ALL_SERIALIZE_FIELDS.to_vec(), || {
+                serde_json::to_string(&config)
+            })?;
+        serialized_config.simple_fields = config.to_redacted_string()?;
+
+        Ok(())
+    }
+
+    /// Move the server `server_name` to listen on `bind` without a restart. The new
+    /// listener is opened before the old one is closed, and the connections accepted
+    /// before are kept.
+    pub async fn rebind_server(&self, server_name: &str, bind: Address) -> Result<()> {
+        let state = self.inner.state.read().await;
+        let (config, nets, server_info) = match &*state {
+            State::Running(Running {
+                config,
+                entities: RunningEntities { nets, servers },
+                ..
+            }) => (
+                config,
+                nets,
+                servers
+                    .get(server_name)
+                    .ok_or_else(|| anyhow!("Server not found: {}", server_name))?,
+            ),
+            _ => return Err(anyhow!("Not running")),
+        };
+
+        let mut serialized_config = config.write().await;
+        let mut config: config::Config = serde_json::from_str(&serialized_config.all_fields)?;
+        let cfg = config
+            .server
+            .get_mut(server_name)
+            .ok_or_else(|| anyhow!("Server not found: {}", server_name))?;
+
+        let mut new_cfg = cfg.clone();
+        match new_cfg.opt.get_mut("bind") {
+            Some(v) => *v = serde_json::to_value(&bind)?,
+            None => return Err(anyhow!("Server {} has no bind address", server_name)),
+        }
+        let metadata = new_cfg.metadata().into_owned();
+        let server = self
+            .registry
+            .build_server(server_name, &mut new_cfg, &|key, ctx| {
+                let name = key
+                    .represent()
+                    .as_str()
+                    .ok_or_else(|| Error::other("Net not found"))?;
+                let net = match metadata.idle_shutdown {
+                    Some(idle_shutdown) if !is_listen(ctx) => on_demand_net(
+                        self.registry.clone(),
+                        config.net.clone(),
+                        name.to_string(),
+                        idle_shutdown,
+                    ),
+                    _ => nets
+                        .get(name)
+                        .map(|i| i.as_net())
+                        .ok_or_else(|| Error::NotFound(name.to_string()))?,
+                };
+                Ok(server_net(
+                    server_name.to_string(),
+                    net,
+                    ctx,
+                    self.inner.conn_mgr.clone(),
+                    &metadata,
+                    server_info.limit.clone(),
+                ))
+            })?;
+        server_info
+            .running_server
+            .replace(server)
+            .await
+            .context(format!("Failed to rebind server {}", server_name))?;
+
+        *cfg = new_cfg;
+        serialized_config.all_fields =
+            serialize_with_fields(ALL_SERIALIZE_FIELDS.to_vec(), || {
+                serde_json::to_string(&config)
+            })?;
+        serialized_config.simple_fields = config.to_redacted_string()?;
+
+        Ok(())
+    }
+
     pub async fn get_id(&self) -> Option<String> {
         let state = self.inner.state.read().await;
         match &*state {
@@ -381,14 +813,64 @@ impl RabbitDigger {
         }
     }
 
+    /// Clear the DNS caches of the `dns` and `dns_sniffer` nets, returns the names of
+    /// the flushed nets.
+    pub async fn flush_dns(&self) -> Result<Vec<String>> {
+        let state = self.inner.state.read().await;
+        let running = state.running().ok_or_else(|| anyhow!("Not running"))?;
+
+        let mut flushed = Vec::new();
+        for (name, net) in &running.entities.nets {
+            if let Some(dns) = net.net_as::<DnsNet>() {
+                dns.flush()?;
+            } else if let Some(sniffer) = net.net_as::<DNSSnifferNet>() {
+                sniffer.flush();
+            } else {
+                continue;
+            }
+            flushed.push(name.clone());
+        }
+
+        Ok(flushed)
+    }
+
+    /// Make the `local` nets bound to an interface by `bind_addr` read its address
+    /// again for new connections, e.g. after a network change the OS didn't notify.
+    pub fn rebind(&self) {
+        rd_std::builtin::local::rebind()
+    }
+
+    /// Swap in the GeoIP database at `path`, or read the current one again, the
+    /// rules keep matching with the old one until it's loaded.
+    pub async fn reload_geoip(&self, path: Option<PathBuf>) -> Result<()> {
+        tokio::task::spawn_blocking(move || rd_std::rule::reload_geoip(path)).await??;
+        Ok(())
+    }
+
     // Stop the connection by uuid
-    pub async fn stop_connection(&self, uuid: Uuid) -> Result<bool> {
-        Ok(self.inner.conn_mgr.stop_connection(uuid))
+    pub async fn stop_connection(&self, uuid: Uuid, mode: StopMode) -> Result<bool> {
+        Ok(self.inner.conn_mgr.stop_connection(uuid, mode))
     }
 
     // Stop all connections
-    pub async fn stop_connections(&self) -> Result<usize> {
-        Ok(self.inner.conn_mgr.stop_connections())
+    pub async fn stop_connections(&self, mode: StopMode) -> Result<usize> {
+        Ok(self.inner.conn_mgr.stop_connections(mode))
+    }
+
+    /// Refuse the new connections of all servers until `resume`, the process and
+    /// config keep running. The existing connections are stopped if `stop_existing`,
+    /// returns how many are stopped.
+    pub fn pause(&self, stop_existing: bool) -> usize {
+        self.inner.conn_mgr.set_paused(true);
+        if stop_existing {
+            self.inner.conn_mgr.stop_connections(StopMode::Hard)
+        } else {
+            0
+        }
+    }
+
+    pub fn resume(&self) {
+        self.inner.conn_mgr.set_paused(false)
     }
 }
 
@@ -396,6 +878,8 @@ pub struct ServerInfo {
     name: String,
     running_server: RunningServer,
     config: Value,
+    /// Kept across the rebinds, so the connections accepted before still count.
+    limit: ConnectionLimit,
 }
 
 struct ServerList<'a>(&'a BTreeMap<String, ServerInfo>);
@@ -451,20 +935,32 @@ impl Registry {
         Ok(server)
     }
 
+    /// Build the net `name` and the nets it refers to.
+    fn build_net_by_name(self: &Arc<Self>, config: &mut config::Config, name: &str) -> Result<Net> {
+        init_default_net(&mut config.net)?;
+        config.check_net_refs(self)?;
+        let build_context = BuildContext::new(self, &mut config.net);
+        Ok(build_context.get_net_by_name(name)?)
+    }
+
     // Build all net and server, the nested net will be flatten. So the config may change.
     fn build_entities(
-        &self,
+        self: &Arc<Self>,
         config: &mut config::Config,
         conn_mgr: &ConnectionManager,
     ) -> Result<RunningEntities> {
+        init_default_net(&mut config.net)?;
+        config.check_net_refs(self)?;
+        config.fill_bypass_server(self);
         let config::Config { net, server, .. } = config;
-        init_default_net(net)?;
         let build_context = BuildContext::new(&self, net);
 
         let mut servers = BTreeMap::new();
 
         for (name, mut i) in server.iter_mut() {
             let server_name = &name;
+            let metadata = i.metadata().into_owned();
+            let limit = ConnectionLimit::new(metadata.limit.clone().unwrap_or_default());
 
             let mut load_server = || {
                 let server = self.build_server(server_name, &mut i, &|name, ctx| {
@@ -473,6 +969,8 @@ impl Registry {
                         ctx,
                         server_name.to_string(),
                         conn_mgr.clone(),
+                        &metadata,
+                        limit.clone(),
                     )
                 })?;
                 let server =
@@ -483,6 +981,7 @@ impl Registry {
                         name: server_name.to_string(),
                         running_server: server,
                         config: i.opt.clone(),
+                        limit: limit.clone(),
                     },
                 );
                 Ok(()) as Result<()>
@@ -500,13 +999,13 @@ impl Registry {
 
 struct BuildContext<'a> {
     config: RefCell<&'a mut config::ConfigNet>,
-    registry: &'a Registry,
+    registry: &'a Arc<Registry>,
     net_cache: RefCell<BTreeMap<String, Arc<RunningNet>>>,
     delimiter: &'a str,
 }
 
 impl<'a> BuildContext<'a> {
-    fn new(registry: &'a Registry, config: &'a mut config::ConfigNet) -> Self {
+    fn new(registry: &'a Arc<Registry>, config: &'a mut config::ConfigNet) -> Self {
         BuildContext {
             config: RefCell::new(config),
             registry,
@@ -517,13 +1016,42 @@ impl<'a> BuildContext<'a> {
     fn take_net(&self) -> BTreeMap<String, Arc<RunningNet>> {
         self.net_cache.replace(BTreeMap::new())
     }
-    fn get_net(
+    /// Generate the name of an inline net from its path in the config, e.g.
+    /// `net/my_net/net` or `server/my_server/listen`.
+    /// If the name is already taken, `#1`, `#2`... is appended. The name only
+    /// depends on the config, so building the same config again gives the same name.
+    fn generate_name(&self, key: &CompactVecString) -> String {
+        let base = key.join(self.delimiter);
+        let config = self.config.borrow();
+
+        let mut name = base.clone();
+        let mut index = 0;
+        while config.contains_key(&name) {
+            index += 1;
+            name = format!("{}#{}", base, index);
+        }
+
+        name
+    }
+    /// The name of the net referred by `net_ref`, an inline net is added to the
+    /// config with a generated name.
+    fn net_name(
         &self,
         net_ref: &mut NetRef,
         ctx: &VisitorContext,
         prefix: &CompactVecString,
-    ) -> rd_interface::Result<Net> {
-        let placeholder: config::Net = config::Net::new("circular reference", Value::Null);
+    ) -> rd_interface::Result<String> {
+        if ctx.is_optional() {
+            if let Value::String(name) = net_ref.represent() {
+                if !self.config.borrow().contains_key(name) {
+                    tracing::warn!(
+                        "Optional net {:?} is not found in config file, use blackhole instead",
+                        name
+                    );
+                    return Ok("blackhole".to_string());
+                }
+            }
+        }
 
         let name = match net_ref.represent() {
             Value::String(name) => name,
@@ -531,7 +1059,7 @@ impl<'a> BuildContext<'a> {
                 let mut key = prefix.clone();
                 key.extend(ctx.path());
 
-                let generated_name = key.join(self.delimiter);
+                let generated_name = self.generate_name(&key);
                 self.config.borrow_mut().insert(
                     generated_name.to_string(),
                     serde_json::from_value(net_cfg.clone())?,
@@ -542,6 +1070,20 @@ impl<'a> BuildContext<'a> {
                 net_ref.represent().as_str().expect("Impossible")
             }
         };
+        Ok(name.to_string())
+    }
+    fn get_net(
+        &self,
+        net_ref: &mut NetRef,
+        ctx: &VisitorContext,
+        prefix: &CompactVecString,
+    ) -> rd_interface::Result<Net> {
+        let name = self.net_name(net_ref, ctx, prefix)?;
+        self.get_net_by_name(&name)
+    }
+    fn get_net_by_name(&self, name: &str) -> rd_interface::Result<Net> {
+        let placeholder: config::Net = config::Net::new("circular reference", Value::Null);
+
         if let Some(net) = self.net_cache.borrow().get(name) {
             return Ok(net.as_net());
         }
@@ -559,6 +1101,7 @@ impl<'a> BuildContext<'a> {
         let prefix = ["net", name].iter().copied().collect();
         let net = RunningNet::new(
             name.to_string(),
+            cfg.net_type.clone(),
             self.registry.build_net(name, &mut cfg, &|name, ctx| {
                 self.get_net(name, ctx, &prefix)
             })?,
@@ -582,11 +1125,500 @@ impl<'a> BuildContext<'a> {
         ctx: &VisitorContext,
         server_name: String,
         conn_mgr: ConnectionManager,
+        metadata: &config::ServerMetadata,
+        limit: ConnectionLimit,
     ) -> rd_interface::Result<Net> {
         let prefix = ["server", &server_name].iter().copied().collect();
-        Ok(
-            RunningServerNet::new(server_name, self.get_net(net_ref, ctx, &prefix)?, conn_mgr)
-                .into_dyn(),
+        let net = match metadata.idle_shutdown {
+            // the listener is bound at start, only the nets used to connect out are on demand
+            Some(idle_shutdown) if !is_listen(ctx) => {
+                let name = self.net_name(net_ref, ctx, &prefix)?;
+                on_demand_net(
+                    self.registry.clone(),
+                    self.config.borrow().clone(),
+                    name,
+                    idle_shutdown,
+                )
+            }
+            _ => self.get_net(net_ref, ctx, &prefix)?,
+        };
+        Ok(server_net(server_name, net, ctx, conn_mgr, metadata, limit))
+    }
+}
+
+fn is_listen(ctx: &VisitorContext) -> bool {
+    ctx.path().iter().last() == Some("listen")
+}
+
+/// The net `name` built on its first use from a copy of `config`, so it and the
+/// nets it refers to are not shared with the other servers.
+fn on_demand_net(
+    registry: Arc<Registry>,
+    config: config::ConfigNet,
+    name: String,
+    idle_shutdown: u64,
+) -> Net {
+    let build: NetBuilder = Box::new({
+        let name = name.clone();
+        move || {
+            let mut config = config.clone();
+            BuildContext::new(&registry, &mut config).get_net_by_name(&name)
+        }
+    });
+    OnDemandNet::new(name, build, Duration::from_secs(idle_shutdown)).into_dyn()
+}
+
+/// Wrap the net used by a server, so its connections are tracked.
+fn server_net(
+    server_name: String,
+    net: Net,
+    ctx: &VisitorContext,
+    conn_mgr: ConnectionManager,
+    metadata: &config::ServerMetadata,
+    limit: ConnectionLimit,
+) -> Net {
+    // the clients accepted from `listen` are registered when they connect out
+    let is_listen = is_listen(ctx);
+    RunningServerNet::new(server_name, net, conn_mgr)
+        .udp_buffer_size(metadata.udp_buffer_size)
+        .source_filter(metadata.source_filter.clone())
+        .reset_on_stop(metadata.reset_on_stop)
+        .connect_events(metadata.connect_events)
+        .limit(limit)
+        .track_accepted(!is_listen)
+        .into_dyn()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INLINE_CONFIG: &str = r#"{
+        "net": {
+            "net/resolve/net": { "type": "local" },
+            "resolve": {
+                "type": "resolve",
+                "net": { "type": "local" },
+                "resolve_net": { "type": "local" }
+            }
+        },
+        "server": {
+            "echo": { "type": "echo", "bind": "127.0.0.1:0", "listen": "resolve" }
+        }
+    }"#;
+
+    fn build_inline_config() -> (Vec<String>, config::Config) {
+        let registry = Arc::new(Registry::new_with_builtin().unwrap());
+        let mut config: config::Config = serde_json::from_str(INLINE_CONFIG).unwrap();
+        let conn_mgr = ConnectionManager::new();
+
+        let entities = registry.build_entities(&mut config, &conn_mgr).unwrap();
+        conn_mgr.stop();
+
+        (entities.nets.keys().cloned().collect(), config)
+    }
+
+    fn two_servers_config(
+        taken: u16,
+        free: u16,
+        continue_on_server_error: Option<bool>,
+    ) -> config::Config {
+        serde_json::from_value(serde_json::json!({
+            "server": {
+                "taken": { "type": "echo", "bind": format!("127.0.0.1:{}", taken) },
+                "free": { "type": "echo", "bind": format!("127.0.0.1:{}", free) },
+            },
+            "continue_on_server_error": continue_on_server_error,
+        }))
+        .unwrap()
+    }
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[tokio::test]
+    async fn test_continue_on_server_error() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+
+        let rd = RabbitDigger::new(Registry::new_with_builtin().unwrap())
+            .await
+            .unwrap();
+        let free = free_port();
+        assert!(rd
+            .start(two_servers_config(taken_port, free, Some(false)))
+            .await
+            .is_err());
+        assert!(!rd.is_running().await);
+        assert!(tokio::net::TcpStream::connect(("127.0.0.1", free))
+            .await
+            .is_err());
+
+        // the others are started by default
+        let free = free_port();
+        rd.start(two_servers_config(taken_port, free, None))
+            .await
+            .unwrap();
+        let status = rd.server_status().await.unwrap();
+        assert_eq!(status["free"], ServerStatus::Running);
+        assert!(matches!(status["taken"], ServerStatus::Failed { .. }));
+
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", free))
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        rd.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_config_status() {
+        let rd = RabbitDigger::new(Registry::new_with_builtin().unwrap())
+            .await
+            .unwrap();
+        let mut status = rd.subscribe_config_status();
+
+        let bad: config::Config = serde_json::from_value(serde_json::json!({
+            "id": "bad",
+            "server": {
+                "echo": { "type": "echo", "bind": "127.0.0.1:0", "listen": "missing" },
+            },
+        }))
+        .unwrap();
+        let good_config: config::Config = serde_json::from_value(serde_json::json!({
+            "id": "good",
+            "server": {
+                "echo": { "type": "echo", "bind": "127.0.0.1:0" },
+            },
+        }))
+        .unwrap();
+        let good = ConfigStatus {
+            id: "good".to_string(),
+            error: None,
+        };
+
+        // a rejected first config is fatal
+        let configs = futures::stream::iter(vec![Ok(bad.clone()), Ok(good_config.clone())]);
+        timeout(Duration::from_secs(5), rd.clone().start_stream(configs))
+            .await
+            .unwrap()
+            .unwrap_err();
+        let rejected = status.recv().await.unwrap();
+        assert_eq!(rejected.id, "bad");
+        assert!(rejected.error.unwrap().contains("missing"));
+
+        // a rejected later config waits for the next config
+        let configs =
+            futures::stream::iter(vec![Ok(good_config.clone()), Ok(bad), Ok(good_config)]);
+        timeout(Duration::from_secs(5), rd.clone().start_stream(configs))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(status.recv().await.unwrap(), good);
+        let rejected = status.recv().await.unwrap();
+        assert_eq!(rejected.id, "bad");
+        assert!(rejected.error.is_some());
+        assert_eq!(status.recv().await.unwrap(), good);
+    }
+
+    #[tokio::test]
+    async fn test_update_provider_rules() {
+        let rd = RabbitDigger::new(Registry::new_with_builtin().unwrap())
+            .await
+            .unwrap();
+        let mut status = rd.subscribe_config_status();
+
+        let config = |domain: &str, any_target: &str| -> config::Config {
+            serde_json::from_value(serde_json::json!({
+                "net": {
+                    "rule": {
+                        "type": "rule",
+                        "rule": [
+                            {
+                                "type": "domain",
+                                "method": "match",
+                                "domain": domain,
+                                "target": "local",
+                                "provider": "set",
+                            },
+                            { "type": "any", "target": any_target },
+                        ],
+                    },
+                    "other": { "type": "local" },
+                },
+            }))
+            .unwrap()
+        };
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let task = tokio::spawn(rd.clone().start_stream(rx));
+
+        tx.unbounded_send(Ok(config("a.example.com", "local")))
+            .unwrap();
+        assert_eq!(status.recv().await.unwrap().error, None);
+        let net = rd.get_net("rule").await.unwrap().unwrap();
+
+        // only the rules of the provider are changed, the net is kept
+        tx.unbounded_send(Ok(config("b.example.com", "local")))
+            .unwrap();
+        assert_eq!(status.recv().await.unwrap().error, None);
+        let updated = rd.get_net("rule").await.unwrap().unwrap();
+        assert!(Arc::ptr_eq(&net, &updated));
+        let config_str = rd.get_config(|c| c.to_string()).await.unwrap();
+        assert!(config_str.contains("b.example.com"));
+        assert!(!config_str.contains("a.example.com"));
+
+        // the other rules are changed, the net is rebuilt
+        tx.unbounded_send(Ok(config("b.example.com", "other")))
+            .unwrap();
+        assert_eq!(status.recv().await.unwrap().error, None);
+        let rebuilt = rd.get_net("rule").await.unwrap().unwrap();
+        assert!(!Arc::ptr_eq(&net, &rebuilt));
+
+        drop(tx);
+        task.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_flush_dns() {
+        let rd = RabbitDigger::new(Registry::new_with_builtin().unwrap())
+            .await
+            .unwrap();
+        let config: config::Config = serde_json::from_value(serde_json::json!({
+            "net": {
+                "dns": { "type": "dns", "server": "google" },
+                "alias": { "type": "alias", "net": "dns" },
+                "proxy": { "type": "socks5", "server": "127.0.0.1:1080", "net": "dns" },
+                "rule": { "type": "rule", "rule": [{ "type": "any", "target": "dns" }] },
+                "transport": { "type": "socks5", "server": "127.0.0.1:1080", "net": "rule" },
+            },
+        }))
+        .unwrap();
+        rd.start(config).await.unwrap();
+
+        // the nets connecting through `dns` are not `dns` nets
+        assert_eq!(rd.flush_dns().await.unwrap(), ["dns"]);
+        // nor is a proxy over a rule net a rule net
+        let err = rd
+            .update_rule_provider("transport", "set", Vec::new())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Rule net not found"), "{}", err);
+
+        rd.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rebind_server() {
+        use rd_interface::IntoAddress;
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+        };
+
+        async fn assert_echo(stream: &mut TcpStream) {
+            stream.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+        }
+        let bind = |port: u16| ("127.0.0.1", port).into_address().unwrap();
+
+        let old_port = free_port();
+        let rd = RabbitDigger::new(Registry::new_with_builtin().unwrap())
+            .await
+            .unwrap();
+        let config = serde_json::from_value(serde_json::json!({
+            "server": {
+                "echo": { "type": "echo", "bind": format!("127.0.0.1:{}", old_port) },
+            },
+        }))
+        .unwrap();
+        rd.start(config).await.unwrap();
+
+        let mut old = TcpStream::connect(("127.0.0.1", old_port)).await.unwrap();
+        assert_echo(&mut old).await;
+
+        // the old listener is kept if the new one fails
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        assert!(rd.rebind_server("echo", bind(taken_port)).await.is_err());
+        assert!(rd
+            .rebind_server("missing", bind(free_port()))
+            .await
+            .is_err());
+        assert_echo(&mut TcpStream::connect(("127.0.0.1", old_port)).await.unwrap()).await;
+
+        let new_port = free_port();
+        rd.rebind_server("echo", bind(new_port)).await.unwrap();
+
+        assert_echo(&mut old).await;
+        assert_echo(&mut TcpStream::connect(("127.0.0.1", new_port)).await.unwrap()).await;
+        assert!(TcpStream::connect(("127.0.0.1", old_port)).await.is_err());
+        let config = rd.get_config(|c| c.to_string()).await.unwrap();
+        assert!(
+            config.contains(&format!("127.0.0.1:{}", new_port)),
+            "{}",
+            config
+        );
+
+        rd.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_net_refs() {
+        let config: config::Config = serde_json::from_str(
+            r#"{
+            "net": {
+                "my_rule": {
+                    "type": "rule",
+                    "rule": [
+                        { "type": "any", "target": { "type": "alias", "net": "missing_a" } }
+                    ]
+                }
+            },
+            "server": {
+                "forward": {
+                    "type": "forward",
+                    "bind": "127.0.0.1:0",
+                    "target": "127.0.0.1:1",
+                    "net": "missing_b",
+                    "listen": "local"
+                }
+            }
+        }"#,
         )
+        .unwrap();
+
+        let rd = RabbitDigger::new(Registry::new_with_builtin().unwrap())
+            .await
+            .unwrap();
+        let err = rd.start(config).await.unwrap_err().to_string();
+        assert!(
+            err.contains(r#"net/my_rule/rule/0/target/net: "missing_a""#),
+            "{}",
+            err
+        );
+        assert!(
+            err.contains(r#"server/forward/net: "missing_b""#),
+            "{}",
+            err
+        );
+        assert!(!rd.is_running().await);
+    }
+
+    const INLINE_RULE_CONFIG: &str = r#"{
+        "server": {
+            "forward": {
+                "type": "forward",
+                "bind": "127.0.0.1:0",
+                "target": "127.0.0.1:1",
+                "net": {
+                    "type": "rule",
+                    "rule": [
+                        {
+                            "type": "domain",
+                            "method": "match",
+                            "domain": "blocked.example",
+                            "target": { "type": "blackhole" }
+                        },
+                        { "type": "any", "target": { "type": "local" } }
+                    ]
+                }
+            }
+        }
+    }"#;
+
+    #[tokio::test]
+    async fn test_inline_rule_net() {
+        use rd_interface::{Context, IntoAddress};
+
+        let registry = Arc::new(Registry::new_with_builtin().unwrap());
+        let mut config: config::Config = serde_json::from_str(INLINE_RULE_CONFIG).unwrap();
+        let conn_mgr = ConnectionManager::new();
+        let entities = registry.build_entities(&mut config, &conn_mgr).unwrap();
+        conn_mgr.stop();
+
+        assert_eq!(
+            entities.nets.keys().collect::<Vec<_>>(),
+            vec![
+                "local",
+                "net/server/forward/net/rule/0/target",
+                "net/server/forward/net/rule/1/target",
+                "server/forward/net",
+            ]
+        );
+        assert_eq!(config.server["forward"].opt["net"], "server/forward/net");
+        let target_type = |name: &str| config.net[name].net_type.clone();
+        assert_eq!(target_type("server/forward/net"), "rule");
+        assert_eq!(
+            target_type("net/server/forward/net/rule/0/target"),
+            "blackhole"
+        );
+        assert_eq!(target_type("net/server/forward/net/rule/1/target"), "local");
+
+        let rule_net = entities.nets["server/forward/net"].as_net();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().into_address().unwrap();
+        assert!(rule_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .is_ok());
+        let blocked = "blocked.example:80".into_address().unwrap();
+        assert!(rule_net
+            .tcp_connect(&mut Context::new(), &blocked)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inline_net_name() {
+        let (names, config) = build_inline_config();
+
+        assert_eq!(
+            names,
+            vec!["net/resolve/net#1", "net/resolve/resolve_net", "resolve"]
+        );
+        let resolve = &config.net.get("resolve").unwrap().opt;
+        assert_eq!(resolve["net"], "net/resolve/net#1");
+        assert_eq!(resolve["resolve_net"], "net/resolve/resolve_net");
+
+        let (names2, _) = build_inline_config();
+        assert_eq!(names, names2);
+    }
+
+    #[test]
+    fn test_api_schema() {
+        let schema = schema_for!(ApiResponses);
+
+        let properties = &schema.schema.object.as_ref().unwrap().properties;
+        for name in ["connection", "registry", "traffic_history"] {
+            assert!(properties.contains_key(name), "{} is missing", name);
+        }
+        for name in [
+            "ConnectionState",
+            "ConnectionInfo",
+            "ConnectingInfo",
+            "RefusedStats",
+            "RegistrySchema",
+            "TrafficSample",
+        ] {
+            assert!(schema.definitions.contains_key(name), "{} is missing", name);
+        }
+
+        let value = serde_json::to_value(&schema).unwrap();
+        let info = &value["definitions"]["ConnectionInfo"]["properties"];
+        assert!(info.get("duration").is_some());
+        assert!(info.get("stop_sender").is_none());
     }
 }
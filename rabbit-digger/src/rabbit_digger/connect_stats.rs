@@ -0,0 +1,206 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use rd_interface::Error;
+use serde::Serialize;
+
+/// Counters of the `tcp_connect` outcomes of a net.
+#[derive(Debug, Default)]
+pub struct ConnectStats {
+    attempts: AtomicU64,
+    successes: AtomicU64,
+    /// Moving average of the connect latency, in microseconds.
+    latency: AtomicU64,
+    /// Failure count by the index of the code in `ERROR_CODES`.
+    failures: [AtomicU64; ERROR_CODES.len()],
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ConnectStatsSnapshot {
+    pub attempts: u64,
+    pub successes: u64,
+    /// Failure count by error code.
+    pub failures: BTreeMap<String, u64>,
+    /// Moving average of the connect latency, in microseconds.
+    pub avg_latency_us: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NetConnectStats {
+    #[serde(rename = "type")]
+    pub net_type: String,
+    #[serde(flatten)]
+    pub stats: ConnectStatsSnapshot,
+}
+
+/// The codes returned by `error_code`.
+const ERROR_CODES: [&str; 28] = [
+    "NotFound",
+    "PermissionDenied",
+    "ConnectionRefused",
+    "ConnectionReset",
+    "ConnectionAborted",
+    "NotConnected",
+    "AddrInUse",
+    "AddrNotAvailable",
+    "BrokenPipe",
+    "AlreadyExists",
+    "WouldBlock",
+    "InvalidInput",
+    "InvalidData",
+    "TimedOut",
+    "WriteZero",
+    "Interrupted",
+    "Unsupported",
+    "UnexpectedEof",
+    "OutOfMemory",
+    "Io",
+    "NotMatched",
+    "NotEnabled",
+    "NotImplemented",
+    "Config",
+    "AbortedByUser",
+    "Context",
+    "Timeout",
+    "Other",
+];
+
+pub(super) fn error_code(e: &Error) -> &'static str {
+    match e {
+        Error::IO(e) => io_error_code(e.kind()),
+        Error::NotMatched => "NotMatched",
+        Error::NotEnabled => "NotEnabled",
+        Error::NotImplemented => "NotImplemented",
+        Error::Config(_) => "Config",
+        Error::AbortedByUser => "AbortedByUser",
+        Error::Context(_) => "Context",
+        Error::NotFound(_) => "NotFound",
+        Error::Timeout(_) => "Timeout",
+        Error::Other(_) | Error::WithContext(_) => "Other",
+    }
+}
+
+/// The name of the kind, `Io` for the ones not in `ERROR_CODES`.
+fn io_error_code(kind: io::ErrorKind) -> &'static str {
+    use io::ErrorKind::*;
+
+    match kind {
+        NotFound => "NotFound",
+        PermissionDenied => "PermissionDenied",
+        ConnectionRefused => "ConnectionRefused",
+        ConnectionReset => "ConnectionReset",
+        ConnectionAborted => "ConnectionAborted",
+        NotConnected => "NotConnected",
+        AddrInUse => "AddrInUse",
+        AddrNotAvailable => "AddrNotAvailable",
+        BrokenPipe => "BrokenPipe",
+        AlreadyExists => "AlreadyExists",
+        WouldBlock => "WouldBlock",
+        InvalidInput => "InvalidInput",
+        InvalidData => "InvalidData",
+        TimedOut => "TimedOut",
+        WriteZero => "WriteZero",
+        Interrupted => "Interrupted",
+        Unsupported => "Unsupported",
+        UnexpectedEof => "UnexpectedEof",
+        OutOfMemory => "OutOfMemory",
+        Other => "Other",
+        _ => "Io",
+    }
+}
+
+impl ConnectStats {
+    pub fn attempt(&self) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn success(&self, latency: Duration) {
+        let sample = latency.as_micros() as u64;
+        let first = self.successes.fetch_add(1, Ordering::Relaxed) == 0;
+        // the weight of the new sample is 1/8
+        let _ = self
+            .latency
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |avg| {
+                Some(if first {
+                    sample
+                } else {
+                    avg - avg / 8 + sample / 8
+                })
+            });
+    }
+    pub fn failure(&self, e: &Error) {
+        let code = error_code(e);
+        if let Some(i) = ERROR_CODES.iter().position(|c| *c == code) {
+            self.failures[i].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    pub fn snapshot(&self) -> ConnectStatsSnapshot {
+        ConnectStatsSnapshot {
+            attempts: self.attempts.load(Ordering::Relaxed),
+            successes: self.successes.load(Ordering::Relaxed),
+            failures: ERROR_CODES
+                .iter()
+                .zip(&self.failures)
+                .map(|(code, count)| (code.to_string(), count.load(Ordering::Relaxed)))
+                .filter(|(_, count)| *count > 0)
+                .collect(),
+            avg_latency_us: self.latency.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_stats_latency() {
+        let stats = ConnectStats::default();
+
+        stats.success(Duration::from_micros(800));
+        assert_eq!(stats.snapshot().avg_latency_us, 800);
+
+        stats.success(Duration::from_micros(1600));
+        assert_eq!(stats.snapshot().avg_latency_us, 900);
+    }
+
+    #[test]
+    fn test_error_code() {
+        assert_eq!(
+            error_code(&Error::IO(io::ErrorKind::ConnectionRefused.into())),
+            "ConnectionRefused"
+        );
+        assert_eq!(error_code(&Error::NotImplemented), "NotImplemented");
+        assert_eq!(error_code(&Error::other("oops")), "Other");
+    }
+
+    #[test]
+    fn test_connect_stats_failures() {
+        let stats = ConnectStats::default();
+        let errors = [
+            Error::IO(io::ErrorKind::ConnectionRefused.into()),
+            Error::IO(io::ErrorKind::ConnectionRefused.into()),
+            Error::IO(io::ErrorKind::Other.into()),
+            Error::NotMatched,
+            Error::other("oops"),
+        ];
+        for e in &errors {
+            stats.failure(e);
+            assert!(ERROR_CODES.contains(&error_code(e)));
+        }
+
+        assert_eq!(
+            stats.snapshot().failures,
+            [
+                ("ConnectionRefused".to_string(), 2),
+                ("NotMatched".to_string(), 1),
+                ("Other".to_string(), 2),
+            ]
+            .into_iter()
+            .collect()
+        );
+    }
+}
@@ -1,27 +1,56 @@
 use std::{
     collections::HashMap,
+    future::Future,
     io,
-    sync::{atomic::Ordering, Arc},
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
     task::{Context, Poll},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
-use super::event::{Event, EventType};
+use super::{
+    event::{Event, EventType},
+    traffic::{TrafficHistory, TRAFFIC_HISTORY_SIZE},
+};
 use atomic_shim::AtomicU64;
-use dashmap::DashMap;
-use futures::{FutureExt, StreamExt};
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures::StreamExt;
 use parking_lot::Mutex;
-use rd_interface::{Address, Value};
-use serde::{Serialize, Serializer};
+use rd_interface::{
+    context::{common_field::ResolvedSocketAddr, CommonField},
+    schemars::{self, JsonSchema},
+    Address, CanonicalAddress, Value,
+};
+use serde::{Deserialize, Serialize, Serializer};
 use tokio::{
-    sync::{broadcast, mpsc, oneshot},
+    sync::{broadcast, mpsc},
     task::JoinHandle,
-    time::interval,
+    time::{sleep, Sleep},
 };
 use tokio_stream::wrappers::BroadcastStream;
 use uuid::Uuid;
 
 const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(500);
+/// Events processed at most before the event task yields.
+const EVENT_BATCH_SIZE: usize = 32;
+/// How long a softly stopped connection waits for the peer to close.
+const SOFT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the connections whose close event is missed are removed.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+/// Context keys containing any of these are never shown by the api or the logs.
+const SENSITIVE_CONTEXT_KEYS: &[&str] = &[
+    "password",
+    "passwd",
+    "secret",
+    "token",
+    "auth",
+    "cookie",
+    "credential",
+];
 
 fn ts(time: &SystemTime) -> u64 {
     time.duration_since(SystemTime::UNIX_EPOCH)
@@ -29,6 +58,28 @@ fn ts(time: &SystemTime) -> u64 {
         .as_secs()
 }
 
+fn is_sensitive(key: &str, deny_list: &[String]) -> bool {
+    let key = key.to_ascii_lowercase();
+    SENSITIVE_CONTEXT_KEYS.iter().any(|k| key.contains(k)) || deny_list.contains(&key)
+}
+
+/// A copy of the context `ctx` without the sensitive keys, in the nested objects as
+/// well. `deny_list` is the lowercase keys removed in addition to the builtin ones.
+fn redact_context(ctx: &Value, deny_list: &[String]) -> Value {
+    match ctx {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(k, _)| !is_sensitive(k, deny_list))
+                .map(|(k, v)| (k.clone(), redact_context(v, deny_list)))
+                .collect(),
+        ),
+        Value::Array(list) => {
+            Value::Array(list.iter().map(|v| redact_context(v, deny_list)).collect())
+        }
+        v => v.clone(),
+    }
+}
+
 fn serialize_atomicu64<S>(a: &AtomicU64, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -36,76 +87,309 @@ where
     serializer.serialize_u64(a.load(Ordering::Relaxed))
 }
 
-#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, JsonSchema, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum Protocol {
     Tcp,
     Udp,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct ConnectionInfo {
     protocol: Protocol,
     addr: Address,
+    /// The socket address actually connected, which may differ from the resolved `addr`.
+    resolved_addr: Option<SocketAddr>,
     start_time: u64,
+    /// Timestamp of the last byte event, in seconds.
+    last_active: AtomicU64,
+    /// Not redacted, only the serialized one is.
     ctx: Value,
-    #[serde(serialize_with = "serialize_atomicu64")]
+    /// Context keys removed from the serialized `ctx`.
+    redact_context: Arc<Vec<String>>,
     upload: AtomicU64,
-    #[serde(serialize_with = "serialize_atomicu64")]
     download: AtomicU64,
-    #[serde(skip)]
-    stop_sender: Mutex<Option<oneshot::Sender<()>>>,
+    stop_sender: Mutex<Option<mpsc::UnboundedSender<StopMode>>>,
 }
 
-#[derive(Debug, Serialize)]
+impl ConnectionInfo {
+    fn new(
+        protocol: Protocol,
+        addr: Address,
+        ctx: Value,
+        redact_context: Arc<Vec<String>>,
+        time: &SystemTime,
+    ) -> Self {
+        let start_time = ts(time);
+        let resolved_addr = ctx
+            .get(ResolvedSocketAddr::KEY)
+            .and_then(|v| serde_json::from_value(v.clone()).ok());
+        ConnectionInfo {
+            protocol,
+            addr,
+            resolved_addr,
+            start_time,
+            last_active: AtomicU64::new(start_time),
+            ctx,
+            redact_context,
+            upload: AtomicU64::new(0),
+            download: AtomicU64::new(0),
+            stop_sender: Mutex::new(None),
+        }
+    }
+    fn touch(&self, time: &SystemTime) {
+        self.last_active.store(ts(time), Ordering::Relaxed);
+    }
+    /// Seconds elapsed since the connection was created.
+    pub fn duration(&self) -> u64 {
+        ts(&SystemTime::now()).saturating_sub(self.start_time)
+    }
+    fn stop(&self, mode: StopMode) -> bool {
+        let mut stop_sender = self.stop_sender.lock();
+        let stopped = matches!(&*stop_sender, Some(sender) if sender.send(mode).is_ok());
+        // a soft stop can be followed by a hard one
+        if mode == StopMode::Hard {
+            *stop_sender = None;
+        }
+        stopped
+    }
+}
+
+/// How a connection is stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StopMode {
+    /// Abort at once, the bytes in flight are cut.
+    #[default]
+    Hard,
+    /// Close the write side of a TCP connection and keep reading until the peer
+    /// closes, so the response being received is not truncated. It's aborted if
+    /// the peer doesn't close in time. A UDP one is stopped at once.
+    Soft,
+}
+
+/// A [`ConnectionInfo`] as it's serialized, so the schema is derived from it.
+#[derive(Serialize, JsonSchema)]
+#[schemars(rename = "ConnectionInfo")]
+struct ConnectionInfoView<'a> {
+    protocol: &'a Protocol,
+    addr: &'a Address,
+    /// The socket address actually connected, which may differ from the resolved `addr`.
+    resolved_addr: Option<SocketAddr>,
+    start_time: u64,
+    /// Timestamp of the last byte event, in seconds.
+    last_active: u64,
+    /// Seconds elapsed since the connection was created.
+    duration: u64,
+    /// The context without the sensitive keys, e.g. the passwords.
+    ctx: Value,
+    upload: u64,
+    download: u64,
+}
+
+impl Serialize for ConnectionInfo {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        ConnectionInfoView {
+            protocol: &self.protocol,
+            addr: &self.addr,
+            resolved_addr: self.resolved_addr,
+            start_time: self.start_time,
+            last_active: self.last_active.load(Ordering::Relaxed),
+            duration: self.duration(),
+            ctx: redact_context(&self.ctx, &self.redact_context),
+            upload: self.upload.load(Ordering::Relaxed),
+            download: self.download.load(Ordering::Relaxed),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// An attempt to connect to a resolved address.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct AttemptInfo {
+    addr: SocketAddr,
+    /// `None` if the attempt succeeded.
+    error: Option<String>,
+}
+
+/// A TCP connection being established, tracked only if the server reports the connect events.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct ConnectingInfo {
+    addr: Address,
+    start_time: u64,
+    attempts: Vec<AttemptInfo>,
+}
+
+/// Why a TCP connection never became established.
+#[derive(Debug, Clone, Copy)]
+pub enum Refusal {
+    /// No rule matched the destination.
+    NotMatched,
+    /// The source address is not allowed by the server.
+    SourceFiltered,
+    /// Failed to connect to the destination, e.g. through the upstream proxy.
+    ConnectFailed,
+    /// The new connections are paused.
+    Paused,
+    /// The server has too many connections.
+    Limited,
+}
+
+/// Counters of the refused TCP connections.
+#[derive(Debug, Default, Serialize, JsonSchema)]
+pub struct RefusedStats {
+    #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
+    not_matched: AtomicU64,
+    #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
+    source_filtered: AtomicU64,
+    #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
+    connect_failed: AtomicU64,
+    #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
+    paused: AtomicU64,
+    #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
+    limited: AtomicU64,
+}
+
+impl RefusedStats {
+    fn counter(&self, refusal: Refusal) -> &AtomicU64 {
+        match refusal {
+            Refusal::NotMatched => &self.not_matched,
+            Refusal::SourceFiltered => &self.source_filtered,
+            Refusal::ConnectFailed => &self.connect_failed,
+            Refusal::Paused => &self.paused,
+            Refusal::Limited => &self.limited,
+        }
+    }
+    pub fn get(&self, refusal: Refusal) -> u64 {
+        self.counter(refusal).load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct ConnectionState {
+    #[schemars(with = "HashMap<String, ConnectionInfoView<'static>>")]
     connections: DashMap<Uuid, ConnectionInfo>,
+    /// The TCP connections being established, by the uuid they are tracked with once connected.
+    #[serde(skip_serializing_if = "DashMap::is_empty")]
+    #[schemars(with = "HashMap<String, ConnectingInfo>")]
+    connecting: DashMap<Uuid, ConnectingInfo>,
     #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
     total_upload: AtomicU64,
     #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
     total_download: AtomicU64,
+    refused: RefusedStats,
+    /// New connections are not tracked when this many are tracked.
+    #[serde(skip)]
+    max_connections: AtomicUsize,
+    /// Count of the connections not tracked because of `max_connections`.
+    /// Their traffic still flows, but is not counted.
+    #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
+    untracked: AtomicU64,
+    /// Count of the connections removed by the sweep, as they are gone without
+    /// the close event, e.g. the event is lost when the owning task panics.
+    #[serde(serialize_with = "serialize_atomicu64")]
+    #[schemars(with = "u64")]
+    reconciled: AtomicU64,
+    #[serde(skip)]
+    redact_context: Mutex<Arc<Vec<String>>>,
+    /// Count of the tracked TCP connections to each destination.
+    #[serde(skip)]
+    destinations: DashMap<CanonicalAddress, usize>,
 }
 
 impl ConnectionState {
     fn new() -> Self {
         ConnectionState {
             connections: DashMap::new(),
+            connecting: DashMap::new(),
             total_upload: AtomicU64::new(0),
             total_download: AtomicU64::new(0),
+            refused: RefusedStats::default(),
+            max_connections: AtomicUsize::new(usize::MAX),
+            untracked: AtomicU64::new(0),
+            reconciled: AtomicU64::new(0),
+            redact_context: Default::default(),
+            destinations: DashMap::new(),
+        }
+    }
+    fn track(&self, uuid: Uuid, conn: ConnectionInfo) {
+        if self.connections.len() >= self.max_connections.load(Ordering::Relaxed) {
+            if self.untracked.fetch_add(1, Ordering::Relaxed) == 0 {
+                tracing::warn!("Too many connections, the new ones are not tracked.");
+            }
+            return;
+        }
+        self.count_destination(&conn, false);
+        self.connections.insert(uuid, conn);
+    }
+    /// Count the TCP connection `conn` to its destination, or uncount it if `closed`.
+    fn count_destination(&self, conn: &ConnectionInfo, closed: bool) {
+        if conn.protocol != Protocol::Tcp {
+            return;
+        }
+        let addr = CanonicalAddress::new(conn.addr.clone());
+        if closed {
+            if let Entry::Occupied(mut count) = self.destinations.entry(addr) {
+                *count.get_mut() -= 1;
+                if *count.get() == 0 {
+                    count.remove();
+                }
+            }
+        } else {
+            *self.destinations.entry(addr).or_insert(0) += 1;
         }
     }
+    /// Remove the connections which are gone without the close event,
+    /// returns how many are removed. A connection is gone if its stopper is
+    /// dropped, and it's removed only if it's idle for `SWEEP_INTERVAL`, so the
+    /// close event on the way is not raced.
+    fn sweep(&self) -> usize {
+        // the connect events are missed if the connecting task is cancelled
+        let now = ts(&SystemTime::now());
+        self.connecting
+            .retain(|_, conn| now.saturating_sub(conn.start_time) < SWEEP_INTERVAL.as_secs());
+
+        let before = self.connections.len();
+        self.connections.retain(|_, conn| {
+            let idle = now.saturating_sub(conn.last_active.load(Ordering::Relaxed));
+            let stop_sender = conn.stop_sender.lock();
+            let keep = idle < SWEEP_INTERVAL.as_secs()
+                || !matches!(&*stop_sender, Some(sender) if sender.is_closed());
+            if !keep {
+                self.count_destination(conn, true);
+            }
+            keep
+        });
+        let removed = before.saturating_sub(self.connections.len());
+        self.reconciled.fetch_add(removed as u64, Ordering::Relaxed);
+        removed
+    }
     fn input_event(&self, event: Event) {
         let Event { uuid, events, time } = event;
 
         for event in events {
             match event {
                 EventType::NewTcp(addr, ctx) => {
-                    self.connections.insert(
-                        uuid,
-                        ConnectionInfo {
-                            protocol: Protocol::Tcp,
-                            addr: addr.clone(),
-                            ctx: ctx.clone(),
-                            start_time: ts(&time),
-                            upload: AtomicU64::new(0),
-                            download: AtomicU64::new(0),
-                            stop_sender: Mutex::new(None),
-                        },
-                    );
+                    self.connecting.remove(&uuid);
+                    let redact = self.redact_context.lock().clone();
+                    let conn = ConnectionInfo::new(Protocol::Tcp, addr, ctx, redact, &time);
+                    self.track(uuid, conn);
                 }
                 EventType::NewUdp(addr, ctx) => {
-                    self.connections.insert(
-                        uuid,
-                        ConnectionInfo {
-                            protocol: Protocol::Udp,
-                            addr: addr.clone(),
-                            ctx: ctx.clone(),
-                            start_time: ts(&time),
-                            upload: AtomicU64::new(0),
-                            download: AtomicU64::new(0),
-                            stop_sender: Mutex::new(None),
-                        },
-                    );
+                    let redact = self.redact_context.lock().clone();
+                    let conn = ConnectionInfo::new(Protocol::Udp, addr, ctx, redact, &time);
+                    self.track(uuid, conn);
                 }
                 EventType::SetStopper(sender) => {
                     if let Some(conn) = self.connections.get(&uuid) {
@@ -116,29 +400,61 @@ impl ConnectionState {
                 EventType::Read(download) => {
                     if let Some(conn) = self.connections.get(&uuid) {
                         conn.download.fetch_add(download, Ordering::Relaxed);
+                        conn.touch(&time);
                         self.total_download.fetch_add(download, Ordering::Relaxed);
                     }
                 }
                 EventType::Write(upload) => {
                     if let Some(conn) = self.connections.get(&uuid) {
                         conn.upload.fetch_add(upload, Ordering::Relaxed);
+                        conn.touch(&time);
                         self.total_upload.fetch_add(upload, Ordering::Relaxed);
                     }
                 }
                 EventType::RecvFrom(_, download) => {
                     if let Some(conn) = self.connections.get(&uuid) {
                         conn.download.fetch_add(download, Ordering::Relaxed);
+                        conn.touch(&time);
                         self.total_download.fetch_add(download, Ordering::Relaxed);
                     }
                 }
                 EventType::SendTo(_, upload) => {
                     if let Some(conn) = self.connections.get(&uuid) {
                         conn.upload.fetch_add(upload, Ordering::Relaxed);
+                        conn.touch(&time);
                         self.total_upload.fetch_add(upload, Ordering::Relaxed);
                     }
                 }
                 EventType::CloseConnection => {
-                    self.connections.remove(&uuid);
+                    if let Some((_, conn)) = self.connections.remove(&uuid) {
+                        self.count_destination(&conn, true);
+                    }
+                }
+                EventType::ConnectStarted(addr) => {
+                    self.connecting.insert(
+                        uuid,
+                        ConnectingInfo {
+                            addr,
+                            start_time: ts(&time),
+                            attempts: Vec::new(),
+                        },
+                    );
+                }
+                EventType::ConnectAttemptFailed(addr, error) => {
+                    if let Some(mut conn) = self.connecting.get_mut(&uuid) {
+                        conn.attempts.push(AttemptInfo {
+                            addr,
+                            error: Some(error),
+                        });
+                    }
+                }
+                EventType::ConnectAttemptSucceeded(addr) => {
+                    if let Some(mut conn) = self.connecting.get_mut(&uuid) {
+                        conn.attempts.push(AttemptInfo { addr, error: None });
+                    }
+                }
+                EventType::ConnectFailed(_) => {
+                    self.connecting.remove(&uuid);
                 }
             };
         }
@@ -146,13 +462,36 @@ impl ConnectionState {
     pub fn connection_count(&self) -> usize {
         self.connections.len()
     }
+    /// The TCP connections open to `addr`.
+    pub fn active_connections(&self, addr: &Address) -> usize {
+        self.destinations
+            .get(&CanonicalAddress::new(addr.clone()))
+            .map(|count| *count)
+            .unwrap_or(0)
+    }
+    pub fn refused(&self) -> &RefusedStats {
+        &self.refused
+    }
+    pub fn untracked(&self) -> u64 {
+        self.untracked.load(Ordering::Relaxed)
+    }
+    pub fn reconciled(&self) -> u64 {
+        self.reconciled.load(Ordering::Relaxed)
+    }
 }
 
 struct ManagerInner {
     state: ConnectionState,
+    traffic_history: Mutex<TrafficHistory>,
     heartbeat_interval: broadcast::Sender<()>,
     sender: mpsc::UnboundedSender<Event>,
     heartbeat_handle: JoinHandle<()>,
+    /// The interval the connections flush their traffic events at, in milliseconds.
+    flush_interval: Arc<AtomicU64>,
+    batch_size: AtomicUsize,
+    paused: AtomicBool,
+    /// How long a softly stopped connection waits for the peer, in milliseconds.
+    soft_stop_timeout: AtomicU64,
 }
 
 impl ManagerInner {
@@ -167,28 +506,76 @@ impl ManagerInner {
         let (sender, rx) = mpsc::unbounded_channel();
         let (heartbeat_interval, _) = broadcast::channel(1);
         let tx = heartbeat_interval.clone();
+        let flush_interval = Arc::new(AtomicU64::new(HEARTBEAT_INTERVAL.as_millis() as u64));
 
-        let heartbeat_handle = tokio::spawn(async move {
-            let mut interval = interval(HEARTBEAT_INTERVAL);
-            loop {
-                let _ = tx.send(());
-                interval.tick().await;
-            }
-        });
+        let this = Arc::new_cyclic(|weak: &Weak<Self>| {
+            let weak = weak.clone();
+            let interval = flush_interval.clone();
+            let heartbeat_handle = tokio::spawn(async move {
+                let mut last_sweep = Instant::now();
+                loop {
+                    let _ = tx.send(());
+                    sleep(Duration::from_millis(interval.load(Ordering::Relaxed))).await;
+                    let inner = match weak.upgrade() {
+                        Some(inner) => inner,
+                        None => break,
+                    };
+                    inner.sample_traffic();
+                    if last_sweep.elapsed() >= SWEEP_INTERVAL {
+                        last_sweep = Instant::now();
+                        let removed = inner.state.sweep();
+                        if removed > 0 {
+                            tracing::warn!(
+                                "Removed {} connections missing the close event",
+                                removed
+                            );
+                        }
+                    }
+                }
+            });
 
-        (
-            Arc::new(Self {
+            Self {
                 state: ConnectionState::new(),
+                traffic_history: Mutex::new(TrafficHistory::new(TRAFFIC_HISTORY_SIZE)),
                 heartbeat_interval,
                 sender,
                 heartbeat_handle,
-            }),
-            rx,
-        )
+                flush_interval,
+                batch_size: AtomicUsize::new(EVENT_BATCH_SIZE),
+                paused: AtomicBool::new(false),
+                soft_stop_timeout: AtomicU64::new(SOFT_STOP_TIMEOUT.as_millis() as u64),
+            }
+        });
+
+        (this, rx)
+    }
+    fn sample_traffic(&self) {
+        self.traffic_history.lock().sample(
+            SystemTime::now(),
+            self.state.total_upload.load(Ordering::Relaxed),
+            self.state.total_download.load(Ordering::Relaxed),
+        );
+    }
+    /// Process the pending events without waiting, at most `max` of them.
+    /// Returns how many are processed.
+    fn drain_events(&self, rx: &mut mpsc::UnboundedReceiver<Event>, max: usize) -> usize {
+        let mut processed = 0;
+        while processed < max {
+            match rx.try_recv() {
+                Ok(event) => self.state.input_event(event),
+                Err(_) => break,
+            }
+            processed += 1;
+        }
+        processed
     }
     async fn recv_event(mut rx: mpsc::UnboundedReceiver<Event>, inner: Arc<ManagerInner>) {
         while let Some(event) = rx.recv().await {
             inner.state.input_event(event);
+            // the pending ones are processed at once, then the others get a chance to run
+            let batch_size = inner.batch_size.load(Ordering::Relaxed);
+            inner.drain_events(&mut rx, batch_size.saturating_sub(1));
+            tokio::task::yield_now().await;
         }
         tracing::warn!("recv_event task exited");
     }
@@ -214,6 +601,53 @@ impl ConnectionManager {
     pub fn stop(&self) {
         self.inner.heartbeat_handle.abort()
     }
+    /// Stop tracking the new connections when this many are tracked, `None` for unlimited.
+    pub fn set_max_connections(&self, max_connections: Option<usize>) {
+        self.inner
+            .state
+            .max_connections
+            .store(max_connections.unwrap_or(usize::MAX), Ordering::Relaxed);
+    }
+    /// Set how often the connections flush their traffic events, `None` for 500ms.
+    /// A shorter one updates the stats sooner with more wakeups.
+    pub fn set_flush_interval(&self, flush_interval: Option<Duration>) {
+        let flush_interval = flush_interval.unwrap_or(HEARTBEAT_INTERVAL);
+        self.inner
+            .flush_interval
+            .store(flush_interval.as_millis().max(1) as u64, Ordering::Relaxed);
+    }
+    /// Set how many events are processed at most per wakeup, `None` for 32.
+    pub fn set_event_batch_size(&self, batch_size: Option<usize>) {
+        self.inner.batch_size.store(
+            batch_size.unwrap_or(EVENT_BATCH_SIZE).max(1),
+            Ordering::Relaxed,
+        );
+    }
+    /// Refuse the new connections while paused, the existing ones keep running.
+    pub fn set_paused(&self, paused: bool) {
+        self.inner.paused.store(paused, Ordering::Relaxed);
+    }
+    /// Set how long a softly stopped connection waits for the peer to close before
+    /// it's aborted, `None` for 30s. It applies to the new connections.
+    pub fn set_soft_stop_timeout(&self, timeout: Option<Duration>) {
+        let timeout = timeout.unwrap_or(SOFT_STOP_TIMEOUT);
+        self.inner
+            .soft_stop_timeout
+            .store(timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+    pub fn is_paused(&self) -> bool {
+        self.inner.paused.load(Ordering::Relaxed)
+    }
+    /// Remove the context keys `keys` from the connections shown by the api, in
+    /// addition to the sensitive ones. It applies to the new connections.
+    pub fn set_redact_context(&self, keys: &[String]) {
+        let keys = keys.iter().map(|k| k.to_ascii_lowercase()).collect();
+        *self.inner.state.redact_context.lock() = Arc::new(keys);
+    }
+    /// The context `ctx` without the sensitive keys, e.g. to be logged.
+    pub fn redact_context(&self, ctx: &Value) -> Value {
+        redact_context(ctx, &self.inner.state.redact_context.lock())
+    }
     pub fn borrow_state<F, R>(&self, f: F) -> R
     where
         F: FnOnce(&ConnectionState) -> R,
@@ -221,41 +655,70 @@ impl ConnectionManager {
         let conn = &self.inner.state;
         f(conn)
     }
-    pub fn stop_connection(&self, uuid: Uuid) -> bool {
+    pub fn borrow_traffic_history<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&TrafficHistory) -> R,
+    {
+        f(&self.inner.traffic_history.lock())
+    }
+    pub fn stop_connection(&self, uuid: Uuid, mode: StopMode) -> bool {
         self.inner
             .state
             .connections
             .get(&uuid)
-            .and_then(|conn| conn.stop_sender.lock().take())
-            .map(|sender| sender.send(()).is_ok())
+            .map(|conn| conn.stop(mode))
             .unwrap_or_default()
     }
-    pub fn stop_connections(&self) -> usize {
-        let mut stopped = 0;
-        for conn in &self.inner.state.connections {
-            if let Some(sender) = conn.stop_sender.lock().take() {
-                if sender.send(()).is_ok() {
-                    stopped += 1;
-                }
-            }
+    pub fn stop_connections(&self, mode: StopMode) -> usize {
+        self.inner
+            .state
+            .connections
+            .iter()
+            .filter(|conn| conn.stop(mode))
+            .count()
+    }
+    /// Count a TCP connection which never became established.
+    pub fn refuse(&self, refusal: Refusal) {
+        self.inner
+            .state
+            .refused
+            .counter(refusal)
+            .fetch_add(1, Ordering::Relaxed);
+    }
+    /// Send the events of a connection not created yet, e.g. the connect attempts.
+    pub fn send_events(&self, uuid: Uuid, events: Vec<EventType>) {
+        if !events.is_empty() && self.inner.sender.send(Event::new(uuid, events)).is_err() {
+            tracing::warn!("Failed to send event");
         }
-        stopped
     }
     pub fn new_connection<T: ConnType>(
         &self,
         addr: Address,
         ctx: &rd_interface::Context,
+    ) -> Connection<T> {
+        self.new_connection_with_uuid(Uuid::new_v4(), addr, ctx)
+    }
+    /// Create the connection with the `uuid` its earlier events are sent with.
+    pub fn new_connection_with_uuid<T: ConnType>(
+        &self,
+        uuid: Uuid,
+        addr: Address,
+        ctx: &rd_interface::Context,
     ) -> Connection<T> {
         Connection::<T>::new(
+            uuid,
             addr,
             ctx,
             self.inner.heartbeat_interval.subscribe(),
             self.inner.sender.clone(),
+            Duration::from_millis(self.inner.soft_stop_timeout.load(Ordering::Relaxed)),
         )
     }
 }
 
 pub trait ConnType: Default {
+    /// Whether a soft stop half-closes the connection, otherwise it's stopped at once.
+    const HALF_CLOSE: bool;
     fn event_type(addr: Address, ctx: Value) -> EventType;
     fn get_events(&mut self) -> Vec<EventType>;
 }
@@ -266,6 +729,8 @@ pub struct Tcp {
     write: u64,
 }
 impl ConnType for Tcp {
+    const HALF_CLOSE: bool = true;
+
     fn event_type(addr: Address, ctx: Value) -> EventType {
         EventType::NewTcp(addr, ctx)
     }
@@ -285,10 +750,12 @@ impl ConnType for Tcp {
 }
 #[derive(Default)]
 pub struct Udp {
-    recv_from: HashMap<Address, u64>,
-    send_to: HashMap<Address, u64>,
+    recv_from: HashMap<CanonicalAddress, u64>,
+    send_to: HashMap<CanonicalAddress, u64>,
 }
 impl ConnType for Udp {
+    const HALF_CLOSE: bool = false;
+
     fn event_type(addr: Address, ctx: Value) -> EventType {
         EventType::NewUdp(addr, ctx)
     }
@@ -296,10 +763,10 @@ impl ConnType for Udp {
     fn get_events(&mut self) -> Vec<EventType> {
         let mut ret = Vec::with_capacity(self.recv_from.len() + self.send_to.len());
         for (addr, download) in self.recv_from.drain() {
-            ret.push(EventType::RecvFrom(addr, download));
+            ret.push(EventType::RecvFrom(addr.into_inner(), download));
         }
         for (addr, upload) in self.send_to.drain() {
-            ret.push(EventType::SendTo(addr, upload));
+            ret.push(EventType::SendTo(addr.into_inner(), upload));
         }
         ret
     }
@@ -312,7 +779,11 @@ pub struct Connection<T: ConnType> {
 
     heartbeat_interval: BroadcastStream<()>,
     sender: mpsc::UnboundedSender<Event>,
-    stopped: oneshot::Receiver<()>,
+    /// `None` if the stopper is dropped, e.g. the connection is not tracked.
+    stopped: Option<mpsc::UnboundedReceiver<StopMode>>,
+    soft_stop_timeout: Duration,
+    /// Set by a soft stop, the connection is aborted when it's reached.
+    soft_stop_deadline: Option<Pin<Box<Sleep>>>,
 }
 
 impl<T> Connection<T>
@@ -320,19 +791,22 @@ where
     T: ConnType,
 {
     fn new(
+        uuid: Uuid,
         addr: Address,
         ctx: &rd_interface::Context,
         heartbeat_interval: broadcast::Receiver<()>,
         sender: mpsc::UnboundedSender<Event>,
+        soft_stop_timeout: Duration,
     ) -> Self {
-        let (stopper, stopped) = oneshot::channel();
-        let uuid = Uuid::new_v4();
+        let (stopper, stopped) = mpsc::unbounded_channel();
         let this = Connection {
             state: T::default(),
             uuid,
             heartbeat_interval: BroadcastStream::new(heartbeat_interval),
             sender,
-            stopped,
+            stopped: Some(stopped),
+            soft_stop_timeout,
+            soft_stop_deadline: None,
         };
         this.send(vec![
             T::event_type(addr, ctx.to_value()),
@@ -345,15 +819,37 @@ where
             let events = self.state.get_events();
             self.send(events);
         }
-        if let Poll::Ready(r) = self.stopped.poll_unpin(cx) {
-            eprintln!("err {:?}", r);
-            return Err(io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "Aborted by user",
-            ));
+        while let Some(stopped) = &mut self.stopped {
+            match stopped.poll_recv(cx) {
+                Poll::Ready(Some(StopMode::Soft)) if T::HALF_CLOSE => {
+                    if self.soft_stop_deadline.is_none() {
+                        self.soft_stop_deadline = Some(Box::pin(sleep(self.soft_stop_timeout)));
+                    }
+                }
+                Poll::Ready(Some(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "Aborted by user",
+                    ));
+                }
+                Poll::Ready(None) => self.stopped = None,
+                Poll::Pending => break,
+            }
+        }
+        if let Some(deadline) = &mut self.soft_stop_deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "The peer is not closed after the soft stop",
+                ));
+            }
         }
         Ok(())
     }
+    /// Whether the connection is stopped softly, its write side should be closed.
+    pub fn soft_stopped(&self) -> bool {
+        self.soft_stop_deadline.is_some()
+    }
     #[cfg(test)]
     pub async fn poll_async(&mut self) -> io::Result<()> {
         futures::future::poll_fn(|cx| {
@@ -382,14 +878,14 @@ impl Connection<Udp> {
     pub fn recv_from(&mut self, addr: Address, size: u64) {
         self.state
             .recv_from
-            .entry(addr)
+            .entry(addr.into())
             .and_modify(|e| *e += size)
             .or_insert(size);
     }
     pub fn send_to(&mut self, addr: Address, size: u64) {
         self.state
             .send_to
-            .entry(addr)
+            .entry(addr.into())
             .and_modify(|e| *e += size)
             .or_insert(size);
     }
@@ -405,7 +901,10 @@ impl<T: ConnType> Drop for Connection<T> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeSet;
+
     use rd_interface::IntoAddress;
+    use serde_json::json;
     use tokio::{task::yield_now, time::sleep};
 
     use super::*;
@@ -477,6 +976,83 @@ mod tests {
         assert!(conn_mgr.inner.state.connections.is_empty());
     }
 
+    #[tokio::test]
+    async fn test_resolved_addr() {
+        let conn_mgr = ConnectionManager::new();
+        let addr = "localhost:1234".into_address().unwrap();
+        let resolved: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+        let mut ctx = rd_interface::Context::new();
+        ctx.insert_common(ResolvedSocketAddr(resolved)).unwrap();
+
+        let _tcp = conn_mgr.new_connection::<Tcp>(addr, &ctx);
+        yield_now().await;
+
+        conn_mgr.borrow_state(|s| {
+            let entry = s.connections.iter().next().unwrap();
+            assert_eq!(entry.value().resolved_addr, Some(resolved));
+
+            let value = serde_json::to_value(entry.value()).unwrap();
+            assert_eq!(value["addr"], "localhost:1234");
+            assert_eq!(value["resolved_addr"], "127.0.0.1:1234");
+        });
+    }
+
+    #[test]
+    fn test_connection_info_schema() {
+        let info = ConnectionInfo::new(
+            Protocol::Tcp,
+            "localhost:1234".into_address().unwrap(),
+            json!({}),
+            Arc::new(vec![]),
+            &SystemTime::now(),
+        );
+        let value = serde_json::to_value(&info).unwrap();
+        let schema = schemars::schema_for!(ConnectionInfoView);
+
+        // the schema can't miss a serialized field
+        let keys = value.as_object().unwrap().keys().collect::<BTreeSet<_>>();
+        let properties = &schema.schema.object.as_ref().unwrap().properties;
+        assert_eq!(keys, properties.keys().collect::<BTreeSet<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_redact_context() {
+        let conn_mgr = ConnectionManager::new();
+        conn_mgr.set_redact_context(&["X_Session".to_string()]);
+        let addr = "localhost:1234".into_address().unwrap();
+        let mut ctx = rd_interface::Context::new();
+        ctx.insert("socks5_password".to_string(), "hunter2")
+            .unwrap();
+        ctx.insert("x_session".to_string(), "abc").unwrap();
+        ctx.insert(
+            "upstream".to_string(),
+            serde_json::json!({ "Token": "t", "id": 1 }),
+        )
+        .unwrap();
+        ctx.insert("user".to_string(), "alice").unwrap();
+
+        let _tcp = conn_mgr.new_connection::<Tcp>(addr, &ctx);
+        yield_now().await;
+
+        conn_mgr.borrow_state(|s| {
+            let entry = s.connections.iter().next().unwrap();
+            // kept for matching
+            assert_eq!(entry.value().ctx["socks5_password"], "hunter2");
+
+            let value = serde_json::to_value(entry.value()).unwrap();
+            let ctx = value["ctx"].as_object().unwrap();
+            assert!(!ctx.contains_key("socks5_password"));
+            assert!(!ctx.contains_key("x_session"));
+            assert_eq!(ctx["upstream"], serde_json::json!({ "id": 1 }));
+            assert_eq!(ctx["user"], "alice");
+            assert!(!value.to_string().contains("hunter2"));
+        });
+        assert!(conn_mgr
+            .redact_context(&ctx.to_value())
+            .get("x_session")
+            .is_none());
+    }
+
     #[tokio::test]
     async fn test_connection_manager_udp() {
         let conn_mgr = ConnectionManager::new();
@@ -524,4 +1100,307 @@ mod tests {
 
         assert_eq!(conn_mgr.inner.state.connections.len(), 0);
     }
+
+    #[tokio::test]
+    async fn test_udp_canonical_address() {
+        let conn_mgr = ConnectionManager::new();
+        let addr = "[::1]:53".into_address().unwrap();
+
+        let mut udp = conn_mgr.new_connection::<Udp>(addr.clone(), &rd_interface::Context::new());
+        udp.send_to(addr.clone(), 1);
+        udp.send_to(Address::Domain("[::1]".to_string(), 53), 1);
+        udp.send_to(Address::Domain("::1".to_string(), 53), 1);
+
+        let events = udp.state.get_events();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], EventType::SendTo(a, 3) if a == &addr));
+    }
+
+    #[test]
+    fn test_connection_last_active() {
+        let state = ConnectionState::new();
+        let uuid = Uuid::new_v4();
+        let addr = "localhost:1234".into_address().unwrap();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let event_at = |secs: u64, events: Vec<EventType>| Event {
+            uuid,
+            events,
+            time: start + Duration::from_secs(secs),
+        };
+
+        state.input_event(event_at(
+            0,
+            vec![EventType::NewTcp(addr.clone(), Value::Null)],
+        ));
+        state.input_event(event_at(5, vec![EventType::Read(1), EventType::Write(1)]));
+        {
+            let conn = state.connections.get(&uuid).unwrap();
+            assert_eq!(conn.start_time, 1000);
+            assert_eq!(conn.last_active.load(Ordering::Relaxed), 1005);
+        }
+
+        state.input_event(event_at(8, vec![EventType::SendTo(addr.clone(), 1)]));
+        state.input_event(event_at(9, vec![EventType::RecvFrom(addr, 1)]));
+        let conn = state.connections.get(&uuid).unwrap();
+        assert_eq!(conn.start_time, 1000);
+        assert_eq!(conn.last_active.load(Ordering::Relaxed), 1009);
+
+        let value = serde_json::to_value(&*conn).unwrap();
+        assert_eq!(value["last_active"], 1009);
+        assert!(value["duration"].as_u64().unwrap() >= 1009 - 1000);
+    }
+
+    #[tokio::test]
+    async fn test_stop_mode() {
+        let conn_mgr = ConnectionManager::new();
+        let addr = "localhost:1234".into_address().unwrap();
+        let ctx = rd_interface::Context::new();
+
+        let mut tcp = conn_mgr.new_connection::<Tcp>(addr.clone(), &ctx);
+        let mut udp = conn_mgr.new_connection::<Udp>(addr, &ctx);
+        yield_now().await;
+
+        // a soft stop half-closes a TCP connection, and stops a UDP one at once
+        assert_eq!(conn_mgr.stop_connections(StopMode::Soft), 2);
+        tcp.poll_async().await.unwrap();
+        assert!(tcp.soft_stopped());
+        assert!(udp.poll_async().await.is_err());
+
+        // then it can be stopped hard
+        assert!(conn_mgr.stop_connection(tcp.uuid, StopMode::Hard));
+        assert!(tcp.poll_async().await.is_err());
+        assert!(!conn_mgr.stop_connection(tcp.uuid, StopMode::Hard));
+    }
+
+    #[tokio::test]
+    async fn test_soft_stop_timeout() {
+        let conn_mgr = ConnectionManager::new();
+        conn_mgr.set_soft_stop_timeout(Some(Duration::from_millis(50)));
+        let addr = "localhost:1234".into_address().unwrap();
+
+        let mut tcp = conn_mgr.new_connection::<Tcp>(addr, &rd_interface::Context::new());
+        yield_now().await;
+
+        assert_eq!(conn_mgr.stop_connections(StopMode::Soft), 1);
+        tcp.poll_async().await.unwrap();
+        assert!(tcp.soft_stopped());
+
+        // the peer doesn't close in time
+        sleep(Duration::from_millis(100)).await;
+        let err = tcp.poll_async().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let conn_mgr = ConnectionManager::new();
+        conn_mgr.set_max_connections(Some(2));
+        let addr = "localhost:1234".into_address().unwrap();
+        let ctx = rd_interface::Context::new();
+
+        let tcp1 = conn_mgr.new_connection::<Tcp>(addr.clone(), &ctx);
+        let _tcp2 = conn_mgr.new_connection::<Tcp>(addr.clone(), &ctx);
+        let mut untracked = conn_mgr.new_connection::<Tcp>(addr.clone(), &ctx);
+        yield_now().await;
+
+        conn_mgr.borrow_state(|s| {
+            assert_eq!(s.connection_count(), 2);
+            assert_eq!(s.untracked(), 1);
+            assert!(!s.connections.contains_key(&untracked.uuid));
+        });
+
+        // the traffic of the untracked connection still flows
+        untracked.write(1);
+        sleep(Duration::from_secs(1)).await;
+        untracked.poll_async().await.unwrap();
+        untracked.poll_async().await.unwrap();
+        yield_now().await;
+        conn_mgr.borrow_state(|s| {
+            assert_eq!(s.total_upload.load(Ordering::Relaxed), 0);
+        });
+
+        drop(tcp1);
+        yield_now().await;
+        let tcp3 = conn_mgr.new_connection::<Tcp>(addr, &ctx);
+        yield_now().await;
+        conn_mgr.borrow_state(|s| {
+            assert_eq!(s.connection_count(), 2);
+            assert!(s.connections.contains_key(&tcp3.uuid));
+            assert_eq!(s.untracked(), 1);
+            assert_eq!(serde_json::to_value(s).unwrap()["untracked"], 1);
+        });
+    }
+
+    #[test]
+    fn test_connecting() {
+        let state = ConnectionState::new();
+        let addr = "localhost:1234".into_address().unwrap();
+        let refused: SocketAddr = "[::1]:1234".parse().unwrap();
+        let connected: SocketAddr = "127.0.0.1:1234".parse().unwrap();
+
+        let uuid = Uuid::new_v4();
+        state.input_event(Event::new(
+            uuid,
+            vec![EventType::ConnectStarted(addr.clone())],
+        ));
+        state.input_event(Event::new(
+            uuid,
+            vec![
+                EventType::ConnectAttemptFailed(refused, "Connection refused".to_string()),
+                EventType::ConnectAttemptSucceeded(connected),
+            ],
+        ));
+        {
+            let value = serde_json::to_value(&state).unwrap();
+            let conn = &value["connecting"][uuid.to_string()];
+            assert_eq!(conn["addr"], "localhost:1234");
+            assert_eq!(
+                conn["attempts"],
+                json!([
+                    { "addr": "[::1]:1234", "error": "Connection refused" },
+                    { "addr": "127.0.0.1:1234", "error": null },
+                ])
+            );
+        }
+
+        // it's moved to the connections once connected
+        state.input_event(Event::new(
+            uuid,
+            vec![EventType::NewTcp(addr.clone(), Value::Null)],
+        ));
+        assert!(state.connecting.is_empty());
+        assert!(state.connections.contains_key(&uuid));
+        assert!(serde_json::to_value(&state)
+            .unwrap()
+            .get("connecting")
+            .is_none());
+
+        let uuid = Uuid::new_v4();
+        state.input_event(Event::new(uuid, vec![EventType::ConnectStarted(addr)]));
+        state.input_event(Event::new(
+            uuid,
+            vec![EventType::ConnectFailed("ConnectionRefused".to_string())],
+        ));
+        assert!(state.connecting.is_empty());
+        assert!(!state.connections.contains_key(&uuid));
+    }
+
+    #[tokio::test]
+    async fn test_flush_interval() {
+        let conn_mgr = ConnectionManager::new();
+        conn_mgr.set_flush_interval(Some(Duration::from_millis(50)));
+        let addr = "localhost:1234".into_address().unwrap();
+
+        let mut tcp = conn_mgr.new_connection::<Tcp>(addr, &rd_interface::Context::new());
+        tcp.write(10);
+        // flushed on the next heartbeat, sooner than the default 500ms
+        for _ in 0..3 {
+            sleep(Duration::from_millis(60)).await;
+            tcp.poll_async().await.unwrap();
+        }
+        conn_mgr.borrow_state(|s| {
+            assert_eq!(s.total_upload.load(Ordering::Relaxed), 10);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_event_batch_size() {
+        let (conn_mgr, mut rx) = ConnectionManager::new_for_test();
+        let addr = "localhost:1234".into_address().unwrap();
+        let ctx = rd_interface::Context::new();
+
+        let _conns = (0..10)
+            .map(|_| conn_mgr.new_connection::<Tcp>(addr.clone(), &ctx))
+            .collect::<Vec<_>>();
+        assert_eq!(conn_mgr.inner.drain_events(&mut rx, 4), 4);
+        assert_eq!(conn_mgr.borrow_state(|s| s.connection_count()), 4);
+        assert_eq!(conn_mgr.inner.drain_events(&mut rx, 32), 6);
+        assert_eq!(conn_mgr.inner.drain_events(&mut rx, 32), 0);
+        assert_eq!(conn_mgr.borrow_state(|s| s.connection_count()), 10);
+
+        conn_mgr.set_event_batch_size(Some(0));
+        assert_eq!(conn_mgr.inner.batch_size.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_sweep() {
+        let state = ConnectionState::new();
+        let addr = "localhost:1234".into_address().unwrap();
+        let new_conn = |closed: bool| {
+            let uuid = Uuid::new_v4();
+            let (sender, receiver) = mpsc::unbounded_channel();
+            state.input_event(Event::new(
+                uuid,
+                vec![
+                    EventType::NewTcp(addr.clone(), Value::Null),
+                    EventType::SetStopper(sender),
+                ],
+            ));
+            (uuid, (!closed).then_some(receiver))
+        };
+
+        let (alive, _receiver) = new_conn(false);
+        let (gone, _) = new_conn(true);
+        // its close event may be on the way
+        let (closing, _) = new_conn(true);
+        assert_eq!(state.connection_count(), 3);
+
+        // a leaked connection, e.g. its owning task panicked long ago
+        for uuid in [alive, gone] {
+            state
+                .connections
+                .get(&uuid)
+                .unwrap()
+                .last_active
+                .store(0, Ordering::Relaxed);
+        }
+
+        assert_eq!(state.sweep(), 1);
+        assert!(state.connections.contains_key(&alive));
+        assert!(!state.connections.contains_key(&gone));
+        assert!(state.connections.contains_key(&closing));
+        assert_eq!(state.reconciled(), 1);
+        assert_eq!(serde_json::to_value(&state).unwrap()["reconciled"], 1);
+        assert_eq!(state.active_connections(&addr), 2);
+
+        state.input_event(Event::new(closing, vec![EventType::CloseConnection]));
+        assert_eq!(state.sweep(), 0);
+        assert_eq!(state.connection_count(), 1);
+        assert_eq!(state.active_connections(&addr), 1);
+    }
+
+    #[test]
+    fn test_active_connections() {
+        let state = ConnectionState::new();
+        let addr = "127.0.0.1:1234".into_address().unwrap();
+        let new_conn = |event: EventType| {
+            let uuid = Uuid::new_v4();
+            state.input_event(Event::new(uuid, vec![event]));
+            uuid
+        };
+
+        let first = new_conn(EventType::NewTcp(addr.clone(), Value::Null));
+        // the same destination in another form
+        new_conn(EventType::NewTcp(
+            Address::Domain("127.0.0.1".to_string(), 1234),
+            Value::Null,
+        ));
+        // not counted
+        new_conn(EventType::NewUdp(addr.clone(), Value::Null));
+        new_conn(EventType::NewTcp(
+            "127.0.0.1:1235".into_address().unwrap(),
+            Value::Null,
+        ));
+        assert_eq!(state.active_connections(&addr), 2);
+
+        state.input_event(Event::new(first, vec![EventType::CloseConnection]));
+        assert_eq!(state.active_connections(&addr), 1);
+        // closed twice
+        state.input_event(Event::new(first, vec![EventType::CloseConnection]));
+        assert_eq!(state.active_connections(&addr), 1);
+        assert_eq!(
+            state.active_connections(&"localhost:1234".into_address().unwrap()),
+            0
+        );
+    }
 }
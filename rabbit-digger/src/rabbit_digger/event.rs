@@ -1,15 +1,17 @@
 use core::mem::discriminant;
-use std::time::SystemTime;
+use std::{net::SocketAddr, time::SystemTime};
 
 use rd_interface::{Address, Value};
-use tokio::sync::oneshot;
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use super::connection_manager::StopMode;
+
 #[derive(Debug)]
 pub enum EventType {
     NewTcp(Address, Value),
     NewUdp(Address, Value),
-    SetStopper(oneshot::Sender<()>),
+    SetStopper(mpsc::UnboundedSender<StopMode>),
     CloseConnection,
     Write(u64),
     Read(u64),
@@ -17,6 +19,14 @@ pub enum EventType {
     SendTo(Address, u64),
     #[allow(dead_code)]
     RecvFrom(Address, u64),
+    /// Started connecting to the address, before `NewTcp`.
+    ConnectStarted(Address),
+    /// The attempt to a resolved address failed with the error.
+    ConnectAttemptFailed(SocketAddr, String),
+    /// The attempt to a resolved address succeeded, e.g. the one chosen by happy eyeballs.
+    ConnectAttemptSucceeded(SocketAddr),
+    /// Failed to connect with the error code, no `NewTcp` follows.
+    ConnectFailed(String),
 }
 
 impl PartialEq for EventType {
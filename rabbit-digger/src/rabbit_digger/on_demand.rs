@@ -0,0 +1,326 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{self, Poll},
+    time::Duration,
+};
+
+use parking_lot::Mutex;
+use rd_interface::{
+    async_trait, Address, Arc, AsyncRead, AsyncWrite, Context, INet, ITcpListener, ITcpStream,
+    IUdpSocket, IntoDyn, Net, ReadBuf, Result, TcpListener, TcpStream, UdpSocket,
+};
+use tokio::{runtime::Handle, time::sleep};
+
+pub type NetBuilder = Box<dyn Fn() -> Result<Net> + Send + Sync>;
+
+struct State {
+    net: Option<Net>,
+    /// The connections, listeners and lookups using `net`.
+    active: usize,
+    /// Bumped on each use, so the idle timers started before it are ignored.
+    generation: u64,
+}
+
+/// Builds the net on its first use, and drops it when nothing made by it is
+/// open for `idle_timeout`.
+pub struct OnDemandNet {
+    name: String,
+    build: NetBuilder,
+    idle_timeout: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+/// Keeps the net of an [`OnDemandNet`] in use while it's alive.
+struct InUse {
+    name: String,
+    idle_timeout: Duration,
+    state: Arc<Mutex<State>>,
+}
+
+impl Drop for InUse {
+    fn drop(&mut self) {
+        let generation = {
+            let mut state = self.state.lock();
+            state.active -= 1;
+            if state.active > 0 {
+                return;
+            }
+            state.generation
+        };
+
+        let name = self.name.clone();
+        let idle_timeout = self.idle_timeout;
+        let state = self.state.clone();
+        if let Ok(handle) = Handle::try_current() {
+            handle.spawn(async move {
+                sleep(idle_timeout).await;
+                let mut state = state.lock();
+                if state.active == 0 && state.generation == generation && state.net.is_some() {
+                    tracing::info!("On-demand net {} is idle, dropped", name);
+                    state.net = None;
+                }
+            });
+        }
+    }
+}
+
+impl OnDemandNet {
+    pub fn new(name: String, build: NetBuilder, idle_timeout: Duration) -> OnDemandNet {
+        OnDemandNet {
+            name,
+            build,
+            idle_timeout,
+            state: Arc::new(Mutex::new(State {
+                net: None,
+                active: 0,
+                generation: 0,
+            })),
+        }
+    }
+    fn acquire(&self) -> Result<(Net, InUse)> {
+        let mut state = self.state.lock();
+        let net = match &state.net {
+            Some(net) => net.clone(),
+            None => {
+                tracing::info!("Building on-demand net {}", self.name);
+                let net = (self.build)()?;
+                state.net = Some(net.clone());
+                net
+            }
+        };
+        state.active += 1;
+        state.generation += 1;
+
+        Ok((
+            net,
+            InUse {
+                name: self.name.clone(),
+                idle_timeout: self.idle_timeout,
+                state: self.state.clone(),
+            },
+        ))
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for OnDemandNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let (net, in_use) = self.acquire()?;
+        let tcp = net.tcp_connect(ctx, addr).await?;
+        Ok(Held {
+            inner: tcp,
+            _in_use: in_use,
+        }
+        .into_dyn())
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpBind for OnDemandNet {
+    async fn tcp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<TcpListener> {
+        let (net, in_use) = self.acquire()?;
+        let listener = net.tcp_bind(ctx, addr).await?;
+        Ok(Held {
+            inner: listener,
+            _in_use: in_use,
+        }
+        .into_dyn())
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for OnDemandNet {
+    async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
+        let (net, in_use) = self.acquire()?;
+        let udp = net.udp_bind(ctx, addr).await?;
+        Ok(Held {
+            inner: udp,
+            _in_use: in_use,
+        }
+        .into_dyn())
+    }
+}
+
+#[async_trait]
+impl rd_interface::LookupHost for OnDemandNet {
+    async fn lookup_host(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+        let (net, _in_use) = self.acquire()?;
+        net.lookup_host(ctx, addr).await
+    }
+}
+
+impl INet for OnDemandNet {
+    // the net is not built yet, the unsupported ones fail when they are used
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        Some(self)
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        Some(self)
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        Some(self)
+    }
+
+    // looking into the net doesn't build it
+    fn get_inner(&self) -> Option<Net> {
+        self.state.lock().net.clone()
+    }
+}
+
+/// Something made by the net of an [`OnDemandNet`], which keeps it in use.
+struct Held<T> {
+    inner: T,
+    _in_use: InUse,
+}
+
+#[async_trait]
+impl ITcpStream for Held<TcpStream> {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+
+    fn poll_write(&mut self, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        self.inner.peer_addr().await
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr().await
+    }
+
+    fn set_reset_on_drop(&mut self) -> bool {
+        self.inner.set_reset_on_drop()
+    }
+}
+
+#[async_trait]
+impl ITcpListener for Held<TcpListener> {
+    async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        self.inner.accept().await
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr().await
+    }
+}
+
+#[async_trait]
+impl IUdpSocket for Held<UdpSocket> {
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        self.inner.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &Address,
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_send_to(cx, buf, target)
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr().await
+    }
+
+    fn recv_buffer_size(&self) -> Option<usize> {
+        self.inner.recv_buffer_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rd_interface::IntoAddress;
+    use rd_std::tests::{spawn_echo_server, TestNet};
+
+    use super::*;
+
+    /// Counts how many times it's dropped.
+    struct DropNet {
+        net: Net,
+        dropped: Arc<AtomicUsize>,
+    }
+
+    impl INet for DropNet {
+        fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+            self.net.provide_tcp_connect()
+        }
+    }
+
+    impl Drop for DropNet {
+        fn drop(&mut self) {
+            self.dropped.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_on_demand() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:12347").await;
+        let addr = "127.0.0.1:12347".into_address().unwrap();
+
+        let built = Arc::new(AtomicUsize::new(0));
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let build = {
+            let (built, dropped) = (built.clone(), dropped.clone());
+            Box::new(move || -> Result<Net> {
+                built.fetch_add(1, Ordering::SeqCst);
+                Ok(DropNet {
+                    net: test_net.clone(),
+                    dropped: dropped.clone(),
+                }
+                .into_dyn())
+            })
+        };
+        let net = OnDemandNet::new("test".to_string(), build, Duration::from_millis(100));
+        assert_eq!(built.load(Ordering::SeqCst), 0);
+
+        let tcp1 = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        let tcp2 = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        assert_eq!(built.load(Ordering::SeqCst), 1);
+
+        // not idle while a connection is open
+        drop(tcp1);
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(dropped.load(Ordering::SeqCst), 0);
+
+        drop(tcp2);
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(dropped.load(Ordering::SeqCst), 0);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(dropped.load(Ordering::SeqCst), 1);
+
+        // built again on the next connection
+        let _tcp = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        assert_eq!(built.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use rd_interface::Value;
+use serde_json::json;
+
+use crate::config;
+
+/// The rules derived from the rule providers of the rule nets, by the name of the
+/// net and the provider.
+pub type ProviderRules = BTreeMap<(String, String), Vec<Value>>;
+
+/// A config with the rules of the rule providers taken out, so the configs only
+/// differing in them are equal.
+#[derive(Debug, PartialEq)]
+pub struct ProviderSplit {
+    /// The config, with a slot left for each rule provider.
+    pub base: Value,
+    pub rules: ProviderRules,
+}
+
+impl ProviderSplit {
+    pub fn new(config: &config::Config) -> Result<ProviderSplit> {
+        let mut base = serde_json::to_value(config)?;
+        let mut rules = ProviderRules::new();
+
+        if let Some(Value::Object(nets)) = base.get_mut("net") {
+            for (name, net) in nets.iter_mut() {
+                if net.get("type").and_then(Value::as_str) != Some("rule") {
+                    continue;
+                }
+                let items = match net.get_mut("rule") {
+                    Some(Value::Array(items)) => items,
+                    _ => continue,
+                };
+                let mut slots = Vec::with_capacity(items.len());
+                for item in items.drain(..) {
+                    let provider = match item.get("provider").and_then(Value::as_str) {
+                        Some(provider) => provider.to_string(),
+                        None => {
+                            slots.push(item);
+                            continue;
+                        }
+                    };
+                    let slot = json!({ "provider": provider });
+                    if slots.last() != Some(&slot) {
+                        slots.push(slot);
+                    }
+                    rules
+                        .entry((name.clone(), provider))
+                        .or_default()
+                        .push(item);
+                }
+                *items = slots;
+            }
+        }
+
+        Ok(ProviderSplit { base, rules })
+    }
+    /// The rules changed in `other`, if the rest of the configs are the same.
+    pub fn changed_rules(&self, other: &ProviderSplit) -> Option<ProviderRules> {
+        if self.base != other.base {
+            return None;
+        }
+        Some(
+            other
+                .rules
+                .iter()
+                .filter(|(key, rules)| self.rules.get(key) != Some(rules))
+                .map(|(key, rules)| (key.clone(), rules.clone()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(rule: Value) -> config::Config {
+        serde_json::from_value(json!({
+            "net": {
+                "rule": { "type": "rule", "rule": rule },
+            },
+        }))
+        .unwrap()
+    }
+
+    fn item(domain: &str, provider: Option<&str>) -> Value {
+        json!({
+            "type": "domain",
+            "method": "match",
+            "domain": domain,
+            "target": "local",
+            "provider": provider,
+        })
+    }
+
+    #[test]
+    fn test_changed_rules() {
+        let old = ProviderSplit::new(&config(json!([
+            item("a.com", None),
+            item("b.com", Some("set")),
+            item("c.com", Some("set")),
+            item("d.com", Some("other")),
+        ])))
+        .unwrap();
+        assert_eq!(old.rules.len(), 2);
+        assert_eq!(old.rules[&("rule".to_string(), "set".to_string())].len(), 2);
+
+        let new = ProviderSplit::new(&config(json!([
+            item("a.com", None),
+            item("e.com", Some("set")),
+            item("d.com", Some("other")),
+        ])))
+        .unwrap();
+        let changed = old.changed_rules(&new).unwrap();
+        assert_eq!(
+            changed,
+            BTreeMap::from([(
+                ("rule".to_string(), "set".to_string()),
+                vec![item("e.com", Some("set"))]
+            )])
+        );
+
+        // the other rules are changed
+        let new = ProviderSplit::new(&config(json!([
+            item("f.com", None),
+            item("b.com", Some("set")),
+            item("d.com", Some("other")),
+        ])))
+        .unwrap();
+        assert_eq!(old.changed_rules(&new), None);
+
+        // a provider is removed
+        let new = ProviderSplit::new(&config(json!([
+            item("a.com", None),
+            item("b.com", Some("set")),
+        ])))
+        .unwrap();
+        assert_eq!(old.changed_rules(&new), None);
+    }
+}
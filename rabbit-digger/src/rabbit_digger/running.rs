@@ -1,43 +1,88 @@
 use std::{
+    collections::HashSet,
     fmt::Debug,
     io,
     mem::replace,
     net::SocketAddr,
     pin::Pin,
     task::{self, Poll},
+    time::{Duration, Instant},
 };
 
-use futures::{ready, TryFutureExt};
+use anyhow::anyhow;
+use futures::{future::BoxFuture, ready, TryFutureExt};
 use parking_lot::RwLock as SyncRwLock;
 use rd_interface::{
     async_trait,
-    context::common_field::{DestDomain, DestSocketAddr},
-    Address, AddressDomain, Arc, AsyncRead, AsyncWrite, Context, INet, IUdpSocket, IntoDyn, Net,
-    ReadBuf, Result, Server, TcpListener, TcpStream, UdpSocket,
+    context::common_field::{
+        ActiveConnections, ConnectTrace, DestDomain, DestSocketAddr, ResolvedSocketAddr,
+        SrcSocketAddr,
+    },
+    Address, AddressDomain, Arc, AsyncRead, AsyncWrite, Context, INet, ITcpListener, IUdpSocket,
+    IntoDyn, Net, ReadBuf, Result, Server, TcpListener, TcpStream, UdpSocket,
 };
+use rd_std::util::{ConnectionLimit, ConnectionPermit};
 use tokio::{
-    sync::{RwLock, Semaphore},
+    select,
+    sync::{mpsc, oneshot, RwLock, Semaphore},
     task::JoinHandle,
+    time::timeout,
 };
 use tracing::instrument;
+use uuid::Uuid;
 
-use super::connection_manager::{Connection, ConnectionManager, Tcp, Udp};
+use super::{
+    connect_stats::{error_code, ConnectStats, NetConnectStats},
+    connection_manager::{Connection, ConnectionManager, Refusal, StopMode, Tcp, Udp},
+    event::EventType,
+};
+use crate::config::SourceFilter;
 
 pub struct RunningNet {
     name: String,
+    net_type: String,
     net: SyncRwLock<Net>,
+    connect_stats: ConnectStats,
 }
 
 impl RunningNet {
-    pub fn new(name: String, net: Net) -> Arc<RunningNet> {
+    pub fn new(name: String, net_type: String, net: Net) -> Arc<RunningNet> {
         Arc::new(RunningNet {
             name,
+            net_type,
             net: SyncRwLock::new(net),
+            connect_stats: ConnectStats::default(),
         })
     }
+    pub fn connect_stats(&self) -> NetConnectStats {
+        NetConnectStats {
+            net_type: self.net_type.clone(),
+            stats: self.connect_stats.snapshot(),
+        }
+    }
     pub fn update_net(&self, net: Net) {
         *self.net.write() = net;
     }
+    /// Types of the running nets a connection goes through, from this one to the leaf.
+    /// Stops at a net seen before, in case the nets refer to each other.
+    pub fn chain(self: &Arc<Self>) -> Vec<String> {
+        let mut chain = Vec::new();
+        let mut visited = HashSet::new();
+        let mut current = Some(self.clone());
+        while let Some(running) = current {
+            if !visited.insert(Arc::as_ptr(&running)) {
+                break;
+            }
+            chain.push(running.net_type.clone());
+            current = running.net().get_inner_net_by::<RunningNet>();
+        }
+        chain
+    }
+    /// The net built from the config of this one if it's a `T`. The nets it connects
+    /// through, e.g. the transport of a proxy, are not searched.
+    pub fn net_as<T: INet + 'static>(&self) -> Option<Arc<T>> {
+        self.net().downcast()
+    }
     pub fn as_net(self: &Arc<Self>) -> Net {
         Net::from(self.clone() as Arc<dyn INet>)
     }
@@ -56,16 +101,25 @@ impl Debug for RunningNet {
 
 #[async_trait]
 impl rd_interface::TcpConnect for RunningNet {
-    #[instrument]
+    #[instrument(skip(ctx))]
     async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
         ctx.append_net(&self.name);
-        self.net().tcp_connect(ctx, addr).await
+
+        self.connect_stats.attempt();
+        let start = Instant::now();
+        let result = self.net().tcp_connect(ctx, addr).await;
+        match &result {
+            Ok(_) => self.connect_stats.success(start.elapsed()),
+            Err(e) => self.connect_stats.failure(e),
+        }
+
+        result
     }
 }
 
 #[async_trait]
 impl rd_interface::TcpBind for RunningNet {
-    #[instrument]
+    #[instrument(skip(ctx))]
     async fn tcp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<TcpListener> {
         ctx.append_net(&self.name);
         self.net().tcp_bind(ctx, addr).await
@@ -74,7 +128,7 @@ impl rd_interface::TcpBind for RunningNet {
 
 #[async_trait]
 impl rd_interface::UdpBind for RunningNet {
-    #[instrument]
+    #[instrument(skip(ctx))]
     async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
         ctx.append_net(&self.name);
         self.net().udp_bind(ctx, addr).await
@@ -84,8 +138,8 @@ impl rd_interface::UdpBind for RunningNet {
 #[async_trait]
 impl rd_interface::LookupHost for RunningNet {
     #[instrument]
-    async fn lookup_host(&self, addr: &Address) -> Result<Vec<SocketAddr>> {
-        self.net().lookup_host(addr).await
+    async fn lookup_host(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+        self.net().lookup_host(ctx, addr).await
     }
 }
 
@@ -115,6 +169,12 @@ pub struct RunningServerNet {
     server_name: String,
     net: Net,
     manager: ConnectionManager,
+    udp_buffer_size: Option<usize>,
+    source_filter: Arc<SourceFilter>,
+    reset_on_stop: bool,
+    track_accepted: bool,
+    connect_events: bool,
+    limit: ConnectionLimit,
 }
 
 impl RunningServerNet {
@@ -123,8 +183,88 @@ impl RunningServerNet {
             server_name,
             net,
             manager,
+            udp_buffer_size: None,
+            source_filter: Default::default(),
+            reset_on_stop: false,
+            track_accepted: false,
+            connect_events: false,
+            limit: ConnectionLimit::unlimited(),
+        }
+    }
+    /// Size of the buffer to relay UDP datagrams of this server.
+    pub fn udp_buffer_size(mut self, udp_buffer_size: Option<usize>) -> RunningServerNet {
+        self.udp_buffer_size = udp_buffer_size;
+        self
+    }
+    /// Close the accepted TCP connections whose source addresses are not allowed.
+    pub fn source_filter(mut self, source_filter: SourceFilter) -> RunningServerNet {
+        self.source_filter = Arc::new(source_filter);
+        self
+    }
+    /// Close the TCP connections with a RST when they are stopped by the connection manager.
+    pub fn reset_on_stop(mut self, reset_on_stop: bool) -> RunningServerNet {
+        self.reset_on_stop = reset_on_stop;
+        self
+    }
+    /// Register the connections accepted by `tcp_bind` in the connection manager,
+    /// e.g. the ones of a reverse tunnel. It's off for the net a server listens on,
+    /// whose clients are registered when the server connects to their destinations.
+    pub fn track_accepted(mut self, track_accepted: bool) -> RunningServerNet {
+        self.track_accepted = track_accepted;
+        self
+    }
+    /// Send the connect attempts of the TCP connections to the connection manager,
+    /// so they are shown while being established.
+    pub fn connect_events(mut self, connect_events: bool) -> RunningServerNet {
+        self.connect_events = connect_events;
+        self
+    }
+    /// Limit the in-flight TCP connections, shared by the nets of the same server.
+    pub fn limit(mut self, limit: ConnectionLimit) -> RunningServerNet {
+        self.limit = limit;
+        self
+    }
+}
+
+fn paused_error() -> rd_interface::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionRefused,
+        "new connections are paused",
+    )
+    .into()
+}
+
+fn limited_error() -> rd_interface::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, "too many connections").into()
+}
+
+/// The attempts recorded by the outbound net, and the error if it failed. The attempts
+/// to the resolved addresses are known only if they are traced, e.g. by `trace_connect`
+/// of the `local` net.
+fn connect_result_events(ctx: &Context, result: &Result<TcpStream>) -> Vec<EventType> {
+    let mut events = Vec::new();
+    match ctx.get_common::<ConnectTrace>() {
+        Ok(Some(trace)) => {
+            events.extend(
+                trace
+                    .attempts
+                    .into_iter()
+                    .map(|attempt| match attempt.error {
+                        Some(error) => EventType::ConnectAttemptFailed(attempt.addr, error),
+                        None => EventType::ConnectAttemptSucceeded(attempt.addr),
+                    }),
+            );
+        }
+        _ => {
+            if let (Ok(_), Ok(Some(resolved))) = (result, ctx.get_common::<ResolvedSocketAddr>()) {
+                events.push(EventType::ConnectAttemptSucceeded(resolved.0));
+            }
         }
     }
+    if let Err(e) = result {
+        events.push(EventType::ConnectFailed(error_code(e).to_string()));
+    }
+    events
 }
 
 impl Debug for RunningServerNet {
@@ -144,6 +284,18 @@ impl rd_interface::TcpConnect for RunningServerNet {
         addr: &Address,
     ) -> Result<TcpStream> {
         ctx.append_net(self.server_name.clone());
+        if self.manager.is_paused() {
+            self.manager.refuse(Refusal::Paused);
+            return Err(paused_error());
+        }
+        // waits for a slot in `Delay` mode
+        let permit = match self.limit.admit(self.limit.reserve().await) {
+            Some(permit) => permit,
+            None => {
+                self.manager.refuse(Refusal::Limited);
+                return Err(limited_error());
+            }
+        };
         // prepare context
         match addr {
             Address::Domain(domain, port) => ctx.insert_common(DestDomain(AddressDomain {
@@ -152,19 +304,47 @@ impl rd_interface::TcpConnect for RunningServerNet {
             }))?,
             Address::SocketAddr(addr) => ctx.insert_common(DestSocketAddr(*addr))?,
         };
+        let active = self.manager.borrow_state(|s| s.active_connections(addr));
+        ctx.insert_common(ActiveConnections(active))?;
+
+        let uuid = Uuid::new_v4();
+        if self.connect_events {
+            self.manager
+                .send_events(uuid, vec![EventType::ConnectStarted(addr.clone())]);
+        }
+        let result = self.net.tcp_connect(ctx, &addr).await;
+        if self.connect_events {
+            self.manager
+                .send_events(uuid, connect_result_events(ctx, &result));
+        }
 
-        let tcp = self.net.tcp_connect(ctx, &addr).await?;
+        let tcp = match result {
+            Ok(tcp) => tcp,
+            Err(e) => {
+                match e {
+                    rd_interface::Error::NotMatched => self.manager.refuse(Refusal::NotMatched),
+                    ref e if e.is_aborted() => {}
+                    _ => self.manager.refuse(Refusal::ConnectFailed),
+                }
+                return Err(e);
+            }
+        };
 
-        tracing::info!(target: "rabbit_digger", ?ctx, "Connected");
-        let tcp = WrapTcpStream::new(tcp, &self.manager, addr.clone(), ctx);
+        tracing::info!(
+            target: "rabbit_digger",
+            ctx = %self.manager.redact_context(&ctx.to_value()),
+            "Connected"
+        );
+        let tcp = WrapTcpStream::new(tcp, &self.manager, uuid, addr.clone(), ctx)
+            .reset_on_stop(self.reset_on_stop)
+            .permit(permit);
         Ok(tcp.into_dyn())
     }
 }
 
 #[async_trait]
 impl rd_interface::TcpBind for RunningServerNet {
-    // TODO: wrap TcpListener
-    #[instrument(err)]
+    #[instrument(err, skip(ctx))]
     async fn tcp_bind(
         &self,
         ctx: &mut rd_interface::Context,
@@ -172,26 +352,156 @@ impl rd_interface::TcpBind for RunningServerNet {
     ) -> Result<TcpListener> {
         ctx.append_net(self.server_name.clone());
 
-        self.net.tcp_bind(ctx, addr).await
+        let mut listener = self.net.tcp_bind(ctx, addr).await?;
+        if !self.source_filter.is_empty() {
+            listener = FilterTcpListener {
+                server_name: self.server_name.clone(),
+                listener,
+                manager: self.manager.clone(),
+                source_filter: self.source_filter.clone(),
+            }
+            .into_dyn();
+        }
+        if self.track_accepted {
+            listener = TrackTcpListener {
+                listener,
+                manager: self.manager.clone(),
+                ctx: ctx.clone(),
+                reset_on_stop: self.reset_on_stop,
+            }
+            .into_dyn();
+        }
+        Ok(listener)
+    }
+}
+
+/// Registers the accepted connections in the connection manager. The peer is the
+/// `addr` of the connection, so writing to it is counted as upload like `tcp_connect`.
+struct TrackTcpListener {
+    listener: TcpListener,
+    manager: ConnectionManager,
+    ctx: Context,
+    reset_on_stop: bool,
+}
+
+#[async_trait]
+impl ITcpListener for TrackTcpListener {
+    async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        let (tcp, addr) = self.listener.accept().await?;
+
+        let mut ctx = self.ctx.clone();
+        ctx.insert_common(SrcSocketAddr(addr))?;
+        tracing::info!(
+            target: "rabbit_digger",
+            ctx = %self.manager.redact_context(&ctx.to_value()),
+            "Accepted"
+        );
+        let tcp = WrapTcpStream::new(tcp, &self.manager, Uuid::new_v4(), addr.into(), &ctx)
+            .reset_on_stop(self.reset_on_stop);
+        Ok((tcp.into_dyn(), addr))
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().await
+    }
+}
+
+/// Drops the connections rejected by the source filter before the server sees them.
+struct FilterTcpListener {
+    server_name: String,
+    listener: TcpListener,
+    manager: ConnectionManager,
+    source_filter: Arc<SourceFilter>,
+}
+
+#[async_trait]
+impl ITcpListener for FilterTcpListener {
+    async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        loop {
+            let (tcp, addr) = self.listener.accept().await?;
+            if self.source_filter.is_allowed(addr) {
+                return Ok((tcp, addr));
+            }
+            tracing::debug!(server = %self.server_name, %addr, "Rejected by source filter");
+            self.manager.refuse(Refusal::SourceFiltered);
+        }
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.listener.local_addr().await
     }
 }
 
 #[async_trait]
 impl rd_interface::UdpBind for RunningServerNet {
-    #[instrument(err)]
+    #[instrument(err, skip(ctx))]
     async fn udp_bind(&self, ctx: &mut rd_interface::Context, addr: &Address) -> Result<UdpSocket> {
         ctx.append_net(self.server_name.clone());
+        if self.manager.is_paused() {
+            return Err(paused_error());
+        }
 
-        let udp = WrapUdpSocket::new(
-            self.net.udp_bind(ctx, addr).await?,
-            self.manager.clone(),
-            addr.clone(),
-            ctx,
-        );
+        let mut udp = self.net.udp_bind(ctx, addr).await?;
+        if !self.source_filter.is_empty() {
+            udp = FilterUdpSocket {
+                server_name: self.server_name.clone(),
+                inner: udp,
+                manager: self.manager.clone(),
+                source_filter: self.source_filter.clone(),
+            }
+            .into_dyn();
+        }
+        let udp = WrapUdpSocket::new(udp, self.manager.clone(), addr.clone(), ctx)
+            .buffer_size(self.udp_buffer_size);
         Ok(udp.into_dyn())
     }
 }
 
+/// Drops the datagrams rejected by the source filter before the server sees them.
+struct FilterUdpSocket {
+    server_name: String,
+    inner: UdpSocket,
+    manager: ConnectionManager,
+    source_filter: Arc<SourceFilter>,
+}
+
+#[async_trait]
+impl IUdpSocket for FilterUdpSocket {
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr().await
+    }
+
+    fn recv_buffer_size(&self) -> Option<usize> {
+        self.inner.recv_buffer_size()
+    }
+
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        let before = buf.filled().len();
+        loop {
+            let addr = ready!(self.inner.poll_recv_from(cx, buf)?);
+            if self.source_filter.is_allowed(addr) {
+                return Poll::Ready(Ok(addr));
+            }
+            tracing::debug!(server = %self.server_name, %addr, "Rejected by source filter");
+            self.manager.refuse(Refusal::SourceFiltered);
+            buf.set_filled(before);
+        }
+    }
+
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &Address,
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_send_to(cx, buf, target)
+    }
+}
+
 impl INet for RunningServerNet {
     fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
         Some(self)
@@ -214,9 +524,15 @@ impl INet for RunningServerNet {
     }
 }
 
+/// Datagrams are received into a buffer of this size when the caller's buffer is
+/// smaller, so the oversized ones can be detected instead of truncated.
+const MAX_DATAGRAM_SIZE: usize = 64 * 1024;
+
 pub struct WrapUdpSocket {
     inner: UdpSocket,
     conn: Connection<Udp>,
+    buffer_size: Option<usize>,
+    recv_buf: Vec<u8>,
 }
 
 impl WrapUdpSocket {
@@ -229,8 +545,15 @@ impl WrapUdpSocket {
         WrapUdpSocket {
             inner,
             conn: conn_mgr.new_connection(addr, &ctx),
+            buffer_size: None,
+            recv_buf: Vec::new(),
         }
     }
+    /// The buffer size suggested to the caller of `poll_recv_from`.
+    pub fn buffer_size(mut self, buffer_size: Option<usize>) -> WrapUdpSocket {
+        self.buffer_size = buffer_size;
+        self
+    }
 }
 
 #[async_trait]
@@ -239,16 +562,52 @@ impl IUdpSocket for WrapUdpSocket {
         self.inner.local_addr().await
     }
 
+    fn recv_buffer_size(&self) -> Option<usize> {
+        self.buffer_size
+    }
+
     fn poll_recv_from(
         &mut self,
         cx: &mut task::Context<'_>,
         buf: &mut ReadBuf,
     ) -> Poll<io::Result<SocketAddr>> {
-        let WrapUdpSocket { inner, conn } = &mut *self;
+        let WrapUdpSocket {
+            inner,
+            conn,
+            recv_buf,
+            ..
+        } = &mut *self;
         conn.poll(cx)?;
-        let addr = ready!(inner.poll_recv_from(cx, buf)?);
-        conn.recv_from(addr.into(), buf.filled().len() as u64);
-        Poll::Ready(Ok(addr))
+
+        if buf.remaining() >= MAX_DATAGRAM_SIZE {
+            let before = buf.filled().len();
+            let addr = ready!(inner.poll_recv_from(cx, buf)?);
+            conn.recv_from(addr.into(), (buf.filled().len() - before) as u64);
+            return Poll::Ready(Ok(addr));
+        }
+
+        if recv_buf.is_empty() {
+            recv_buf.resize(MAX_DATAGRAM_SIZE, 0);
+        }
+        loop {
+            let mut recv = ReadBuf::new(recv_buf);
+            let addr = ready!(inner.poll_recv_from(cx, &mut recv)?);
+            let datagram = recv.filled();
+
+            if datagram.len() > buf.remaining() {
+                tracing::warn!(
+                    "Dropped a UDP datagram of {} bytes from {}, the buffer is {} bytes",
+                    datagram.len(),
+                    addr,
+                    buf.remaining()
+                );
+                continue;
+            }
+
+            buf.put_slice(datagram);
+            conn.recv_from(addr.into(), datagram.len() as u64);
+            return Poll::Ready(Ok(addr));
+        }
     }
 
     fn poll_send_to(
@@ -267,20 +626,38 @@ impl IUdpSocket for WrapUdpSocket {
 pub struct WrapTcpStream {
     inner: TcpStream,
     conn: Connection<Tcp>,
+    reset_on_stop: bool,
+    /// The write side is closed by a soft stop.
+    write_closed: bool,
+    _permit: Option<ConnectionPermit>,
 }
 
 impl WrapTcpStream {
     pub fn new(
         inner: TcpStream,
         conn_mgr: &ConnectionManager,
+        uuid: Uuid,
         addr: Address,
         ctx: &Context,
     ) -> WrapTcpStream {
         WrapTcpStream {
             inner,
-            conn: conn_mgr.new_connection(addr, &ctx),
+            conn: conn_mgr.new_connection_with_uuid(uuid, addr, &ctx),
+            reset_on_stop: false,
+            write_closed: false,
+            _permit: None,
         }
     }
+    /// Close the inner stream with a RST when it's stopped, if it's supported.
+    pub fn reset_on_stop(mut self, reset_on_stop: bool) -> WrapTcpStream {
+        self.reset_on_stop = reset_on_stop;
+        self
+    }
+    /// Keep the slot of the server's connection limit until the stream is dropped.
+    pub fn permit(mut self, permit: ConnectionPermit) -> WrapTcpStream {
+        self._permit = Some(permit);
+        self
+    }
 }
 
 #[async_trait]
@@ -293,12 +670,30 @@ impl rd_interface::ITcpStream for WrapTcpStream {
         self.inner.local_addr().await
     }
 
+    fn set_reset_on_drop(&mut self) -> bool {
+        self.inner.set_reset_on_drop()
+    }
+
     fn poll_read(
         &mut self,
         cx: &mut task::Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
-        self.conn.poll(cx)?;
+        if let Err(e) = self.conn.poll(cx) {
+            if self.reset_on_stop && !self.inner.set_reset_on_drop() {
+                tracing::debug!("RST is not supported by the stopped connection");
+            }
+            return Poll::Ready(Err(e));
+        }
+        if self.conn.soft_stopped() && !self.write_closed {
+            if let Err(e) = ready!(Pin::new(&mut self.inner).poll_shutdown(cx)) {
+                tracing::debug!(
+                    "Failed to close the write side of the stopped connection: {:?}",
+                    e
+                );
+            }
+            self.write_closed = true;
+        }
         let before = buf.filled().len();
         match Pin::new(&mut self.inner).poll_read(cx, buf) {
             Poll::Ready(Ok(())) => {
@@ -311,6 +706,10 @@ impl rd_interface::ITcpStream for WrapTcpStream {
     }
 
     fn poll_write(&mut self, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        // the write side is closed by the soft stop
+        if self.conn.soft_stopped() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
         match Pin::new(&mut self.inner).poll_write(cx, buf) {
             Poll::Ready(Ok(s)) => {
                 self.conn.write(s as u64);
@@ -325,15 +724,24 @@ impl rd_interface::ITcpStream for WrapTcpStream {
     }
 
     fn poll_shutdown(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        if self.write_closed {
+            return Poll::Ready(Ok(()));
+        }
         Pin::new(&mut self.inner).poll_shutdown(cx)
     }
 }
 
+type ServerTask = BoxFuture<'static, anyhow::Result<()>>;
+/// A new task for the server, and the sender to acknowledge after the old one is dropped.
+type Replace = (ServerTask, oneshot::Sender<()>);
+
 enum State {
     Idle,
     Running {
         handle: JoinHandle<anyhow::Result<()>>,
         semaphore: Arc<Semaphore>,
+        /// Swaps the server the task runs.
+        replace: mpsc::UnboundedSender<Replace>,
     },
     Finished {
         result: anyhow::Result<()>,
@@ -345,7 +753,7 @@ pub struct RunningServer {
     name: String,
     #[allow(dead_code)]
     server_type: String,
-    server: Server,
+    server: SyncRwLock<Server>,
     state: RwLock<State>,
 }
 
@@ -358,12 +766,27 @@ async fn server_start(name: String, server: &Server) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// How long a starting server is awaited for an error, e.g. the port is in use.
+/// The bind may resolve the address in background, so it's not done on the first poll.
+const START_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Run the server for `START_TIMEOUT`, so an error at startup is returned here.
+/// `None` if the server finished by then.
+async fn start_task(name: String, server: Server) -> anyhow::Result<Option<ServerTask>> {
+    let mut task: ServerTask = Box::pin(async move { server_start(name, &server).await });
+    match timeout(START_TIMEOUT, &mut task).await {
+        Ok(Err(e)) => Err(e),
+        Ok(Ok(())) => Ok(None),
+        Err(_) => Ok(Some(task)),
+    }
+}
+
 impl RunningServer {
     pub fn new(name: String, server_type: String, server: Server) -> Self {
         RunningServer {
             name,
             server_type,
-            server,
+            server: SyncRwLock::new(server),
             state: RwLock::new(State::Idle),
         }
     }
@@ -371,22 +794,66 @@ impl RunningServer {
     pub fn server_type(&self) -> &str {
         &self.server_type
     }
+    /// Start the server in background. The server runs for `START_TIMEOUT` before it's
+    /// spawned, so an error at startup, e.g. the port is in use, is returned here.
     pub async fn start(&self) -> anyhow::Result<()> {
         self.stop().await?;
 
-        let name = self.name.clone();
+        let server = self.server.read().clone();
+        let mut task = match start_task(self.name.clone(), server).await? {
+            Some(task) => task,
+            None => {
+                *self.state.write().await = State::Finished { result: Ok(()) };
+                return Ok(());
+            }
+        };
         let semaphore = Arc::new(Semaphore::new(0));
-        let server = self.server.clone();
+        let (replace, mut replaced) = mpsc::unbounded_channel::<Replace>();
         let s2 = semaphore.clone();
-        let task = async move {
-            let r = server_start(name, &server).await;
+        let handle = tokio::spawn(async move {
+            let r = loop {
+                select! {
+                    r = &mut task => break r,
+                    Some((next, done)) = replaced.recv() => {
+                        task = next;
+                        let _ = done.send(());
+                    }
+                }
+            };
             // TODO: is it safe to drop?
             s2.close();
             r
+        });
+
+        *self.state.write().await = State::Running {
+            handle,
+            semaphore,
+            replace,
         };
-        let handle = tokio::spawn(task);
 
-        *self.state.write().await = State::Running { handle, semaphore };
+        Ok(())
+    }
+    /// Replace the running server with `server`, e.g. one listening on another address.
+    /// The new server is started before the old one is dropped, so the old one keeps
+    /// running if it fails to start. The connections accepted by the old one are kept.
+    pub async fn replace(&self, server: Server) -> anyhow::Result<()> {
+        let state = self.state.read().await;
+        let replace = match &*state {
+            State::Running { replace, .. } => replace,
+            _ => return Err(anyhow!("Server {} is not running", self.name)),
+        };
+
+        let task = start_task(self.name.clone(), server.clone())
+            .await?
+            .unwrap_or_else(|| Box::pin(async { Ok(()) }));
+        let (done, replaced) = oneshot::channel();
+        replace
+            .send((task, done))
+            .map_err(|_| anyhow!("Server {} is stopped", self.name))?;
+        replaced
+            .await
+            .map_err(|_| anyhow!("Server {} is stopped", self.name))?;
+        *self.server.write() = server;
 
         Ok(())
     }
@@ -444,13 +911,20 @@ impl RunningServer {
 mod tests {
     use rd_interface::{context::common_field, IServer, IntoAddress};
     use rd_std::{
+        builtin::overflow::OverflowNet,
         tests::{
             assert_echo, assert_echo_udp, assert_net_provider, spawn_echo_server,
             spawn_echo_server_udp, ProviderCapability, TestNet,
         },
         util::NotImplementedNet,
     };
-    use tokio::task::JoinError;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        task::JoinError,
+        time::{sleep, timeout},
+    };
 
     use crate::rabbit_digger::event::EventType;
 
@@ -459,7 +933,7 @@ mod tests {
     #[test]
     fn test_running_net_provider() {
         let test_net = TestNet::new().into_dyn();
-        let net = RunningNet::new("test".to_string(), test_net).as_net();
+        let net = RunningNet::new("test".to_string(), "test".to_string(), test_net).as_net();
 
         assert_net_provider(
             &net,
@@ -495,7 +969,11 @@ mod tests {
         let test_net = TestNet::new().into_dyn();
         spawn_echo_server(&test_net, "127.0.0.1:12345").await;
 
-        let running_net = RunningNet::new("test".to_string(), NotImplementedNet.into_dyn());
+        let running_net = RunningNet::new(
+            "test".to_string(),
+            "test".to_string(),
+            NotImplementedNet.into_dyn(),
+        );
         let _ = format!("{:?}", running_net);
         let net = running_net.as_net();
         running_net.update_net(test_net.clone());
@@ -511,7 +989,8 @@ mod tests {
     #[tokio::test]
     async fn test_running_net_append() {
         let test_net = TestNet::new().into_dyn();
-        let running_net = RunningNet::new("test".to_string(), test_net).as_net();
+        let running_net =
+            RunningNet::new("test".to_string(), "test".to_string(), test_net).as_net();
 
         let addr = "127.0.0.1:12345".into_address().unwrap();
         let expected_list = vec!["test".to_string()];
@@ -529,11 +1008,77 @@ mod tests {
         assert_eq!(ctx.net_list(), &expected_list);
 
         assert_eq!(
-            running_net.lookup_host(&addr).await.unwrap(),
+            running_net
+                .lookup_host(&mut Context::new(), &addr)
+                .await
+                .unwrap(),
             vec!["127.0.0.1:12345".parse().unwrap()]
         );
     }
 
+    #[test]
+    fn test_running_net_chain() {
+        let leaf = RunningNet::new(
+            "leaf".to_string(),
+            "local".to_string(),
+            TestNet::new().into_dyn(),
+        );
+        let outer = RunningNet::new("outer".to_string(), "alias".to_string(), leaf.as_net());
+        assert_eq!(
+            outer.chain(),
+            vec!["alias".to_string(), "local".to_string()]
+        );
+
+        // the nets refer to each other
+        leaf.update_net(outer.as_net());
+        assert_eq!(
+            outer.chain(),
+            vec!["alias".to_string(), "local".to_string()]
+        );
+        leaf.update_net(NotImplementedNet.into_dyn());
+
+        // through the transport of a proxy
+        leaf.update_net(TestNet::new().into_dyn());
+        let proxy = RunningNet::new(
+            "proxy".to_string(),
+            "socks5".to_string(),
+            rd_std::socks5::Socks5Client::new(
+                leaf.as_net(),
+                "127.0.0.1:1080".into_address().unwrap(),
+            )
+            .into_dyn(),
+        );
+        assert_eq!(
+            proxy.chain(),
+            vec!["socks5".to_string(), "local".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_running_net_connect_stats() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:12345").await;
+
+        let running_net = RunningNet::new("test".to_string(), "test".to_string(), test_net.clone());
+        let net = running_net.as_net();
+
+        assert_echo(&net, "127.0.0.1:12345").await;
+        assert_echo(&net, "127.0.0.1:12345").await;
+        assert!(net
+            .tcp_connect(&mut Context::new(), &"127.0.0.1:1".into_address().unwrap())
+            .await
+            .is_err());
+
+        let stats = running_net.connect_stats();
+        assert_eq!(stats.net_type, "test");
+        assert_eq!(stats.stats.attempts, 3);
+        assert_eq!(stats.stats.successes, 2);
+        assert_eq!(
+            stats.stats.failures.into_iter().collect::<Vec<_>>(),
+            vec![("ConnectionRefused".to_string(), 1)]
+        );
+    }
+
     #[tokio::test]
     async fn test_running_server_net() {
         let test_net = TestNet::new().into_dyn();
@@ -574,6 +1119,180 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_track_accepted() {
+        let test_net = TestNet::new().into_dyn();
+        let manager = ConnectionManager::new();
+        let accept = |track_accepted: bool, port: u16| {
+            let server_net =
+                RunningServerNet::new("server_name".to_string(), test_net.clone(), manager.clone())
+                    .track_accepted(track_accepted)
+                    .into_dyn();
+            let test_net = test_net.clone();
+            let addr = ("127.0.0.1", port).into_address().unwrap();
+            async move {
+                let listener = server_net
+                    .tcp_bind(&mut Context::new(), &addr)
+                    .await
+                    .unwrap();
+                let mut client = test_net
+                    .tcp_connect(&mut Context::new(), &addr)
+                    .await
+                    .unwrap();
+                let (mut accepted, _) = listener.accept().await.unwrap();
+                client.write_all(b"hello").await.unwrap();
+                let mut buf = [0u8; 5];
+                accepted.read_exact(&mut buf).await.unwrap();
+                accepted.write_all(b"hi").await.unwrap();
+                accepted
+            }
+        };
+        let connections =
+            || manager.borrow_state(|s| serde_json::to_value(s).unwrap()["connections"].clone());
+
+        let _accepted = accept(false, 12345).await;
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(connections(), json!({}));
+
+        let accepted = accept(true, 12346).await;
+        sleep(Duration::from_millis(50)).await;
+        let connections = connections();
+        let conns = connections.as_object().unwrap();
+        assert_eq!(conns.len(), 1);
+        let conn = conns.values().next().unwrap();
+        assert_eq!(conn["protocol"], "tcp");
+        assert_eq!(conn["ctx"]["net_list"], json!(["server_name"]));
+        assert_eq!(conn["ctx"]["src_socket_addr"], conn["addr"]);
+
+        // the traffic is reported when it's closed
+        drop(accepted);
+        sleep(Duration::from_millis(50)).await;
+        let state = manager.borrow_state(|s| serde_json::to_value(s).unwrap());
+        assert_eq!(state["connections"], json!({}));
+        assert_eq!(state["total_download"], 5);
+        assert_eq!(state["total_upload"], 2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_reset_on_stop() {
+        use rd_std::builtin::local::{LocalNet, LocalNetConfig};
+
+        // returns the error the peer reads after the connection is stopped
+        let stop = |reset_on_stop: bool| async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let manager = ConnectionManager::new();
+            let server_net = RunningServerNet::new(
+                "server_name".to_string(),
+                LocalNet::new(LocalNetConfig::default()).into_dyn(),
+                manager.clone(),
+            )
+            .reset_on_stop(reset_on_stop)
+            .into_dyn();
+
+            let mut tcp = server_net
+                .tcp_connect(&mut Context::new(), &addr.into())
+                .await
+                .unwrap();
+            let (mut peer, _) = listener.accept().await.unwrap();
+            sleep(Duration::from_millis(50)).await;
+
+            assert_eq!(manager.stop_connections(StopMode::Hard), 1);
+            let err = tcp.read(&mut [0u8; 16]).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+            drop(tcp);
+
+            peer.read(&mut [0u8; 16]).await.err().map(|e| e.kind())
+        };
+
+        assert_eq!(stop(false).await, None);
+        assert_eq!(stop(true).await, Some(io::ErrorKind::ConnectionReset));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_stop_mode() {
+        use rd_std::builtin::local::{LocalNet, LocalNetConfig};
+
+        // the peer sends the rest of the response once the request side is closed,
+        // returns what the client reads after the connection is stopped
+        let stop = |mode: StopMode| async move {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let manager = ConnectionManager::new();
+            let server_net = RunningServerNet::new(
+                "server_name".to_string(),
+                LocalNet::new(LocalNetConfig::default()).into_dyn(),
+                manager.clone(),
+            )
+            .into_dyn();
+
+            let mut tcp = server_net
+                .tcp_connect(&mut Context::new(), &addr.into())
+                .await
+                .unwrap();
+            let (mut peer, _) = listener.accept().await.unwrap();
+            sleep(Duration::from_millis(50)).await;
+
+            let peer = tokio::spawn(async move {
+                peer.write_all(b"partial ").await.unwrap();
+                peer.read_to_end(&mut Vec::new()).await.unwrap();
+                let _ = peer.write_all(b"response").await;
+            });
+            let mut buf = [0u8; 8];
+            tcp.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"partial ");
+
+            assert_eq!(manager.stop_connections(mode), 1);
+            let mut rest = Vec::new();
+            let result = tcp.read_to_end(&mut rest).await;
+            drop(tcp);
+            peer.await.unwrap();
+
+            result.map(|_| rest).map_err(|e| e.kind())
+        };
+
+        assert_eq!(stop(StopMode::Soft).await, Ok(b"response".to_vec()));
+        assert_eq!(
+            stop(StopMode::Hard).await,
+            Err(io::ErrorKind::ConnectionAborted)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_soft_stop_timeout() {
+        use rd_std::builtin::local::{LocalNet, LocalNetConfig};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let manager = ConnectionManager::new();
+        manager.set_soft_stop_timeout(Some(Duration::from_millis(100)));
+        let server_net = RunningServerNet::new(
+            "server_name".to_string(),
+            LocalNet::new(LocalNetConfig::default()).into_dyn(),
+            manager.clone(),
+        )
+        .into_dyn();
+
+        let mut tcp = server_net
+            .tcp_connect(&mut Context::new(), &addr.into())
+            .await
+            .unwrap();
+        // the peer keeps the connection open after the request side is closed
+        let (mut peer, _) = listener.accept().await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+
+        assert_eq!(manager.stop_connections(StopMode::Soft), 1);
+        let err = tcp.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let err = tcp.write_all(b"more").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+
+        assert_eq!(peer.read(&mut [0u8; 16]).await.unwrap(), 0);
+    }
+
     #[tokio::test]
     async fn test_event() {
         let test_net = TestNet::new().into_dyn();
@@ -623,6 +1342,370 @@ mod tests {
         ));
     }
 
+    /// Connects to `connected` after the attempt to `refused` failed, like happy eyeballs
+    /// with a traced connect.
+    struct MultiAddressNet {
+        net: Net,
+        refused: SocketAddr,
+        connected: SocketAddr,
+    }
+
+    #[async_trait]
+    impl rd_interface::TcpConnect for MultiAddressNet {
+        async fn tcp_connect(&self, ctx: &mut Context, _addr: &Address) -> Result<TcpStream> {
+            let tcp = self.net.tcp_connect(ctx, &self.connected.into()).await?;
+            let attempt = |addr, error: Option<&str>| common_field::ConnectAttempt {
+                addr,
+                start_ms: 0,
+                elapsed_ms: 0,
+                error: error.map(ToString::to_string),
+            };
+            ctx.insert_common(ConnectTrace {
+                resolved: vec![self.refused, self.connected],
+                resolve_ms: 0,
+                attempts: vec![
+                    attempt(self.refused, Some("Connection refused")),
+                    attempt(self.connected, None),
+                ],
+                connected: Some(self.connected),
+            })?;
+            Ok(tcp)
+        }
+    }
+
+    impl INet for MultiAddressNet {
+        fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+            Some(self)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_events() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:12346").await;
+        let refused: SocketAddr = "[::1]:12346".parse().unwrap();
+        let connected: SocketAddr = "127.0.0.1:12346".parse().unwrap();
+        let addr = "localhost:12346".into_address().unwrap();
+
+        let (manager, mut rx) = ConnectionManager::new_for_test();
+        manager.stop();
+        let net = MultiAddressNet {
+            net: test_net.clone(),
+            refused,
+            connected,
+        }
+        .into_dyn();
+        let server_net = RunningServerNet::new("server_name".to_string(), net, manager)
+            .connect_events(true)
+            .into_dyn();
+        let _tcp = server_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+
+        let started = rx.recv().await.unwrap();
+        assert_eq!(
+            started.events,
+            vec![EventType::ConnectStarted(addr.clone())]
+        );
+        let attempts = rx.recv().await.unwrap();
+        assert_eq!(attempts.uuid, started.uuid);
+        assert!(matches!(
+            &attempts.events[..],
+            [
+                EventType::ConnectAttemptFailed(a, _),
+                EventType::ConnectAttemptSucceeded(b),
+            ] if a == &refused && b == &connected
+        ));
+        let new_tcp = rx.recv().await.unwrap();
+        assert_eq!(new_tcp.uuid, started.uuid);
+        assert!(matches!(&new_tcp.events[0], EventType::NewTcp(a, _) if a == &addr));
+
+        // the failure ends the connecting
+        let (manager, mut rx) = ConnectionManager::new_for_test();
+        manager.stop();
+        let server_net =
+            RunningServerNet::new("server_name".to_string(), test_net.clone(), manager)
+                .connect_events(true)
+                .into_dyn();
+        assert!(server_net
+            .tcp_connect(&mut Context::new(), &"127.0.0.1:1".into_address().unwrap())
+            .await
+            .is_err());
+        assert!(matches!(
+            &rx.recv().await.unwrap().events[..],
+            [EventType::ConnectStarted(_)]
+        ));
+        assert!(matches!(
+            &rx.recv().await.unwrap().events[..],
+            [EventType::ConnectFailed(_)]
+        ));
+
+        // off by default
+        let (manager, mut rx) = ConnectionManager::new_for_test();
+        manager.stop();
+        let server_net =
+            RunningServerNet::new("server_name".to_string(), test_net, manager).into_dyn();
+        let _tcp = server_net
+            .tcp_connect(&mut Context::new(), &connected.into())
+            .await
+            .unwrap();
+        assert!(matches!(
+            rx.recv().await.unwrap().events[0],
+            EventType::NewTcp(_, _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_udp_large_datagram() {
+        let test_net = TestNet::new().into_dyn();
+        let manager = ConnectionManager::new();
+        let server_net =
+            RunningServerNet::new("server_name".to_string(), test_net.clone(), manager)
+                .udp_buffer_size(Some(32 * 1024))
+                .into_dyn();
+
+        let addr = "127.0.0.1:12345".into_address().unwrap();
+        let mut udp = server_net
+            .udp_bind(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+        assert_eq!(udp.recv_buffer_size(), Some(32 * 1024));
+
+        let mut peer = test_net
+            .udp_bind(
+                &mut Context::new(),
+                &"127.0.0.1:12346".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        let large = (0..20000).map(|i| i as u8).collect::<Vec<_>>();
+
+        // arrives intact with the suggested buffer size
+        peer.send_to(&large, &addr).await.unwrap();
+        let mut buf = vec![0; udp.recv_buffer_size().unwrap()];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        let from = udp.recv_from(&mut read_buf).await.unwrap();
+        assert_eq!(from, "127.0.0.1:12346".parse().unwrap());
+        assert_eq!(read_buf.filled(), &large[..]);
+
+        // dropped instead of truncated with a small buffer
+        peer.send_to(&large, &addr).await.unwrap();
+        peer.send_to(b"small", &addr).await.unwrap();
+        let mut buf = [0u8; 1024];
+        let mut read_buf = ReadBuf::new(&mut buf);
+        udp.recv_from(&mut read_buf).await.unwrap();
+        assert_eq!(read_buf.filled(), b"small");
+    }
+
+    #[tokio::test]
+    async fn test_source_filter() {
+        let test_net = TestNet::new().into_dyn();
+        // connections of `TestNet` are from 127.0.0.1
+        let is_accepted = |filter: serde_json::Value, port: u16| {
+            let test_net = test_net.clone();
+            async move {
+                let server_net = RunningServerNet::new(
+                    "server_name".to_string(),
+                    test_net.clone(),
+                    ConnectionManager::new(),
+                )
+                .source_filter(serde_json::from_value(filter).unwrap())
+                .into_dyn();
+                let addr = ("127.0.0.1", port).into_address().unwrap();
+                let listener = server_net
+                    .tcp_bind(&mut Context::new(), &addr)
+                    .await
+                    .unwrap();
+                let _tcp = test_net
+                    .tcp_connect(&mut Context::new(), &addr)
+                    .await
+                    .unwrap();
+                timeout(Duration::from_millis(100), listener.accept())
+                    .await
+                    .is_ok()
+            }
+        };
+
+        assert!(is_accepted(json!({}), 12345).await);
+        assert!(is_accepted(json!({ "allow": ["127.0.0.0/8"] }), 12346).await);
+        assert!(is_accepted(json!({ "allow": ["private"] }), 12347).await);
+        assert!(!is_accepted(json!({ "allow": ["10.0.0.0/8"] }), 12348).await);
+        assert!(
+            !is_accepted(
+                json!({ "allow": ["private"], "deny": ["127.0.0.1/32"] }),
+                12349
+            )
+            .await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_udp_source_filter() {
+        let test_net = TestNet::new().into_dyn();
+        let manager = ConnectionManager::new();
+        let is_received = |filter: serde_json::Value, port: u16| {
+            let test_net = test_net.clone();
+            let manager = manager.clone();
+            async move {
+                let server_net =
+                    RunningServerNet::new("server_name".to_string(), test_net.clone(), manager)
+                        .source_filter(serde_json::from_value(filter).unwrap())
+                        .into_dyn();
+                let addr = ("127.0.0.1", port).into_address().unwrap();
+                let mut udp = server_net
+                    .udp_bind(&mut Context::new(), &addr)
+                    .await
+                    .unwrap();
+                let mut peer = test_net
+                    .udp_bind(
+                        &mut Context::new(),
+                        &("127.0.0.1", port + 1).into_address().unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                peer.send_to(b"hello", &addr).await.unwrap();
+
+                let mut buf = [0u8; 1024];
+                let mut read_buf = ReadBuf::new(&mut buf);
+                timeout(Duration::from_millis(100), udp.recv_from(&mut read_buf))
+                    .await
+                    .is_ok()
+            }
+        };
+
+        assert!(is_received(json!({}), 12360).await);
+        assert!(is_received(json!({ "allow": ["127.0.0.0/8"] }), 12362).await);
+        assert!(!is_received(json!({ "allow": ["10.0.0.0/8"] }), 12364).await);
+        manager.borrow_state(|s| assert_eq!(s.refused().get(Refusal::SourceFiltered), 1));
+    }
+
+    #[tokio::test]
+    async fn test_refused_stats() {
+        let manager = ConnectionManager::new();
+        let addr = "1.1.1.1:53".into_address().unwrap();
+
+        let rule_net = rd_std::rule::RuleNet::new(rd_std::rule::config::RuleNetConfig {
+            rule: vec![],
+            lru_cache_size: 10,
+            lru_cache_ttl: None,
+        })
+        .unwrap()
+        .into_dyn();
+        let server_net =
+            RunningServerNet::new("server_name".to_string(), rule_net, manager.clone()).into_dyn();
+        let result = server_net.tcp_connect(&mut Context::new(), &addr).await;
+        assert!(matches!(result, Err(rd_interface::Error::NotMatched)));
+
+        let server_net = RunningServerNet::new(
+            "server_name".to_string(),
+            NotImplementedNet.into_dyn(),
+            manager.clone(),
+        )
+        .into_dyn();
+        assert!(server_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .is_err());
+
+        manager.borrow_state(|s| {
+            assert_eq!(s.refused().get(Refusal::NotMatched), 1);
+            assert_eq!(s.refused().get(Refusal::SourceFiltered), 0);
+            assert_eq!(s.refused().get(Refusal::ConnectFailed), 1);
+        });
+    }
+
+    #[tokio::test]
+    async fn test_paused() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:12350").await;
+        let manager = ConnectionManager::new();
+        let server_net =
+            RunningServerNet::new("server_name".to_string(), test_net, manager.clone()).into_dyn();
+        let addr = "127.0.0.1:12350".into_address().unwrap();
+
+        let mut existing = server_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+
+        manager.set_paused(true);
+        let err = server_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_io_err().kind(), io::ErrorKind::ConnectionRefused);
+        assert!(server_net
+            .udp_bind(&mut Context::new(), &"0.0.0.0:0".into_address().unwrap())
+            .await
+            .is_err());
+        manager.borrow_state(|s| assert_eq!(s.refused().get(Refusal::Paused), 1));
+
+        // the existing connection keeps running
+        existing.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        existing.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        manager.set_paused(false);
+        assert_echo(&server_net, "127.0.0.1:12350").await;
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit() {
+        use rd_std::util::{ConnectionLimitConfig, LimitMode};
+
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:12351").await;
+        let manager = ConnectionManager::new();
+        let addr = "127.0.0.1:12351".into_address().unwrap();
+        let limit = |mode| {
+            ConnectionLimit::new(ConnectionLimitConfig {
+                max_connections: Some(1),
+                mode,
+            })
+        };
+
+        let server_net =
+            RunningServerNet::new("server_name".to_string(), test_net.clone(), manager.clone())
+                .limit(limit(LimitMode::Close))
+                .into_dyn();
+        let first = server_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+        let err = server_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_io_err().kind(), io::ErrorKind::ConnectionRefused);
+        manager.borrow_state(|s| assert_eq!(s.refused().get(Refusal::Limited), 1));
+
+        // the slot is freed when the connection is closed
+        drop(first);
+        assert_echo(&server_net, "127.0.0.1:12351").await;
+
+        let server_net = RunningServerNet::new("server_name".to_string(), test_net, manager)
+            .limit(limit(LimitMode::Delay))
+            .into_dyn();
+        let first = server_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .unwrap();
+        let mut ctx = Context::new();
+        let second = server_net.tcp_connect(&mut ctx, &addr);
+        tokio::pin!(second);
+        assert!(timeout(Duration::from_millis(50), &mut second)
+            .await
+            .is_err());
+
+        drop(first);
+        timeout(Duration::from_secs(1), second)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
     #[tokio::test]
     async fn test_running_server() {
         struct ForeverServer;
@@ -651,4 +1734,36 @@ mod tests {
         let err = server.take_result().await.unwrap().unwrap_err();
         assert!(err.downcast_ref::<JoinError>().unwrap().is_cancelled());
     }
+
+    #[tokio::test]
+    async fn test_overflow_counts_other_servers() {
+        // the destination is only reachable through the primary net
+        let primary = TestNet::new().into_dyn();
+        let overflow = TestNet::new().into_dyn();
+        spawn_echo_server(&primary, "127.0.0.1:26666").await;
+
+        let manager = ConnectionManager::new();
+        let other =
+            RunningServerNet::new("other".to_string(), primary.clone(), manager.clone()).into_dyn();
+        let server_net = RunningServerNet::new(
+            "server".to_string(),
+            OverflowNet::new(primary, overflow, 1).into_dyn(),
+            manager.clone(),
+        )
+        .into_dyn();
+
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+        let tcp = other.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(manager.borrow_state(|s| s.active_connections(&addr)), 1);
+        // the connection of the other server is counted
+        assert!(server_net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .is_err());
+
+        drop(tcp);
+        sleep(Duration::from_millis(10)).await;
+        assert_echo(&server_net, "127.0.0.1:26666").await;
+    }
 }
@@ -0,0 +1,117 @@
+use std::{collections::VecDeque, time::SystemTime};
+
+use rd_interface::schemars::{self, JsonSchema};
+use serde::Serialize;
+
+/// Number of samples kept, one per heartbeat.
+pub const TRAFFIC_HISTORY_SIZE: usize = 120;
+
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TrafficSample {
+    /// Timestamp of the sample, in milliseconds.
+    pub ts: u64,
+    /// Upload speed in bytes per second.
+    pub up_bps: u64,
+    /// Download speed in bytes per second.
+    pub down_bps: u64,
+}
+
+#[derive(Debug)]
+struct LastTotal {
+    ts: u64,
+    upload: u64,
+    download: u64,
+}
+
+/// A fixed-size history of total throughput.
+#[derive(Debug)]
+pub struct TrafficHistory {
+    samples: VecDeque<TrafficSample>,
+    last: Option<LastTotal>,
+    capacity: usize,
+}
+
+fn ts_millis(time: &SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl TrafficHistory {
+    pub fn new(capacity: usize) -> Self {
+        TrafficHistory {
+            samples: VecDeque::with_capacity(capacity),
+            last: None,
+            capacity,
+        }
+    }
+    /// Record the current totals. The first call only sets the baseline.
+    pub fn sample(&mut self, time: SystemTime, total_upload: u64, total_download: u64) {
+        let ts = ts_millis(&time);
+        let current = LastTotal {
+            ts,
+            upload: total_upload,
+            download: total_download,
+        };
+        let last = match self.last.replace(current) {
+            Some(last) => last,
+            None => return,
+        };
+        let elapsed = ts.saturating_sub(last.ts);
+        if elapsed == 0 {
+            return;
+        }
+        let bps = |now: u64, before: u64| now.saturating_sub(before) * 1000 / elapsed;
+
+        if self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(TrafficSample {
+            ts,
+            up_bps: bps(total_upload, last.upload),
+            down_bps: bps(total_download, last.download),
+        });
+    }
+    pub fn samples(&self) -> &VecDeque<TrafficSample> {
+        &self.samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_traffic_history() {
+        let mut history = TrafficHistory::new(3);
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let at = |ms: u64| start + Duration::from_millis(ms);
+
+        history.sample(at(0), 0, 0);
+        assert!(history.samples().is_empty());
+
+        history.sample(at(500), 500, 1000);
+        assert_eq!(
+            history.samples().back(),
+            Some(&TrafficSample {
+                ts: 1_000_500,
+                up_bps: 1000,
+                down_bps: 2000,
+            })
+        );
+
+        history.sample(at(1000), 500, 1500);
+        history.sample(at(1500), 1000, 1500);
+        assert_eq!(history.samples().len(), 3);
+
+        history.sample(at(2000), 1000, 1500);
+        let samples = history.samples();
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples.front().unwrap().ts, 1_001_000);
+        assert_eq!(samples.front().unwrap().down_bps, 1000);
+        assert_eq!(samples.back().unwrap().ts, 1_002_000);
+        assert_eq!(samples.back().unwrap().up_bps, 0);
+    }
+}
@@ -2,8 +2,8 @@
 
 use rd_interface::{
     error::ErrorContext,
-    registry::{NetGetter, Resolver},
-    schemars::schema::RootSchema,
+    registry::{NetGetter, NetRefVisitor, Resolver},
+    schemars::{self, schema::RootSchema, JsonSchema},
     Net, Result, Server, Value,
 };
 use serde::{Deserialize, Serialize};
@@ -21,6 +21,18 @@ impl<T> Item<T> {
     pub fn schema(&self) -> &RootSchema {
         self.resolver.schema()
     }
+    pub fn visit_net_ref(&self, config: &Value, f: NetRefVisitor) -> rd_interface::Result<()> {
+        self.resolver.visit_net_ref(config, f)
+    }
+    /// The type name with the title and description of its schema.
+    pub fn type_info(&self) -> TypeInfo {
+        let metadata = self.schema().schema.metadata.as_deref();
+        TypeInfo {
+            name: self.id.clone(),
+            title: metadata.and_then(|m| m.title.clone()),
+            description: metadata.and_then(|m| m.description.clone()),
+        }
+    }
 }
 
 impl Item<Net> {
@@ -152,6 +164,24 @@ impl Registry {
             rd_interface::Error::other(format!("Server type is not loaded: {}", server_type))
         })
     }
+    pub fn get_net_schema(&self, net_type: &str) -> Result<&RootSchema> {
+        self.get_net(net_type).map(Item::schema)
+    }
+    pub fn get_server_schema(&self, server_type: &str) -> Result<&RootSchema> {
+        self.get_server(server_type).map(Item::schema)
+    }
+    pub fn list_net_types(&self) -> Vec<TypeInfo> {
+        self.net.values().map(Item::type_info).collect()
+    }
+    pub fn list_server_types(&self) -> Vec<TypeInfo> {
+        self.server.values().map(Item::type_info).collect()
+    }
+    pub fn get_registry_types(&self) -> RegistryTypes {
+        RegistryTypes {
+            net: self.list_net_types(),
+            server: self.list_server_types(),
+        }
+    }
     pub fn get_registry_schema(&self) -> RegistrySchema {
         let mut r = RegistrySchema {
             net: BTreeMap::new(),
@@ -170,12 +200,27 @@ impl Registry {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct RegistrySchema {
+    #[schemars(with = "BTreeMap<String, Value>")]
     net: BTreeMap<String, RootSchema>,
+    #[schemars(with = "BTreeMap<String, Value>")]
     server: BTreeMap<String, RootSchema>,
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TypeInfo {
+    pub name: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct RegistryTypes {
+    pub net: Vec<TypeInfo>,
+    pub server: Vec<TypeInfo>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -207,6 +252,48 @@ mod tests {
         assert!(registry.get_server("_NOT_EXISTED").is_err());
     }
 
+    #[test]
+    fn test_registry_single_schema() {
+        let registry = Registry::new_with_builtin().unwrap();
+        let schema = registry.get_registry_schema();
+
+        assert_eq!(
+            registry.get_net_schema("local").unwrap(),
+            schema.net.get("local").unwrap()
+        );
+        assert_eq!(
+            registry.get_server_schema("socks5").unwrap(),
+            schema.server.get("socks5").unwrap()
+        );
+
+        assert!(registry.get_net_schema("_NOT_EXISTED").is_err());
+        assert!(registry.get_server_schema("_NOT_EXISTED").is_err());
+    }
+
+    #[test]
+    fn test_registry_types() {
+        let registry = Registry::new_with_builtin().unwrap();
+        let types = registry.get_registry_types();
+        assert_eq!(types.net.len(), registry.net().len());
+        assert_eq!(types.server.len(), registry.server().len());
+
+        let local = types.net.iter().find(|t| t.name == "local").unwrap();
+        assert_eq!(local.title.as_deref(), Some("Local"));
+        assert_eq!(local.description.as_deref(), Some("A local network."));
+
+        let socks5 = registry
+            .list_server_types()
+            .into_iter()
+            .find(|t| t.name == "socks5")
+            .unwrap();
+        assert_eq!(socks5.title.as_deref(), Some("SOCKS5 Server"));
+
+        // the types sharing a config have their own titles
+        let title = |name: &str| types.net.iter().find(|t| t.name == name)?.title.clone();
+        assert_eq!(title("blackhole").as_deref(), Some("Blackhole"));
+        assert_eq!(title("noop").as_deref(), Some("No-op"));
+    }
+
     #[test]
     fn test_registry_debug() {
         let registry = Registry::new_with_builtin().unwrap();
@@ -4,42 +4,215 @@ use darling::{
 };
 use proc_macro2::{Literal, Span, TokenStream};
 use quote::{quote, ToTokens};
-use syn::{parse_macro_input, DeriveInput, Ident};
+use syn::{ext::IdentExt, parse_macro_input, DeriveInput, Ident};
 
 #[derive(Debug, FromField)]
+#[darling(forward_attrs(serde))]
 struct MyFieldReceiver {
     ident: Option<syn::Ident>,
+    attrs: Vec<syn::Attribute>,
+}
+
+/// The `rename_all` rules of serde.
+#[derive(Debug, Clone, Copy)]
+enum RenameRule {
+    LowerCase,
+    UpperCase,
+    PascalCase,
+    CamelCase,
+    SnakeCase,
+    ScreamingSnakeCase,
+    KebabCase,
+    ScreamingKebabCase,
+}
+
+impl RenameRule {
+    fn from_str(rule: &str) -> Option<RenameRule> {
+        Some(match rule {
+            "lowercase" => RenameRule::LowerCase,
+            "UPPERCASE" => RenameRule::UpperCase,
+            "PascalCase" => RenameRule::PascalCase,
+            "camelCase" => RenameRule::CamelCase,
+            "snake_case" => RenameRule::SnakeCase,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnakeCase,
+            "kebab-case" => RenameRule::KebabCase,
+            "SCREAMING-KEBAB-CASE" => RenameRule::ScreamingKebabCase,
+            _ => return None,
+        })
+    }
+    /// Apply the rule to a snake_case field name, same as serde does.
+    fn apply_to_field(self, field: &str) -> String {
+        match self {
+            RenameRule::LowerCase | RenameRule::SnakeCase => field.to_string(),
+            RenameRule::UpperCase | RenameRule::ScreamingSnakeCase => field.to_ascii_uppercase(),
+            RenameRule::PascalCase => {
+                let mut pascal = String::new();
+                let mut capitalize = true;
+                for ch in field.chars() {
+                    if ch == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        pascal.push(ch.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        pascal.push(ch);
+                    }
+                }
+                pascal
+            }
+            RenameRule::CamelCase => {
+                let pascal = RenameRule::PascalCase.apply_to_field(field);
+                pascal[..1].to_ascii_lowercase() + &pascal[1..]
+            }
+            RenameRule::KebabCase => field.replace('_', "-"),
+            RenameRule::ScreamingKebabCase => field.to_ascii_uppercase().replace('_', "-"),
+        }
+    }
+}
+
+/// Serde attributes that change the serialized path of a field.
+#[derive(Debug, Default)]
+struct SerdeAttrs {
+    rename: Option<String>,
+    rename_all: Option<RenameRule>,
+    rename_all_fields: Option<RenameRule>,
+    flatten: bool,
+}
+
+/// Parse `name = "..."` or `name(serialize = "...", deserialize = "...")`,
+/// returns the serialize one.
+fn parse_serialize_name(meta: &syn::meta::ParseNestedMeta) -> syn::Result<Option<syn::LitStr>> {
+    if meta.input.peek(syn::Token![=]) {
+        return Ok(Some(meta.value()?.parse()?));
+    }
+
+    let mut result = None;
+    meta.parse_nested_meta(|meta| {
+        let name: syn::LitStr = meta.value()?.parse()?;
+        if meta.path.is_ident("serialize") {
+            result = Some(name);
+        }
+        Ok(())
+    })?;
+    Ok(result)
+}
+
+fn parse_rename_rule(meta: &syn::meta::ParseNestedMeta) -> syn::Result<Option<RenameRule>> {
+    parse_serialize_name(meta)?
+        .map(|rule| {
+            RenameRule::from_str(&rule.value())
+                .ok_or_else(|| syn::Error::new(rule.span(), "unknown rename rule"))
+        })
+        .transpose()
+}
+
+impl SerdeAttrs {
+    fn from_attrs(attrs: &[syn::Attribute]) -> syn::Result<SerdeAttrs> {
+        let mut result = SerdeAttrs::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("serde")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("flatten") {
+                    result.flatten = true;
+                } else if meta.path.is_ident("rename") {
+                    if let Some(name) = parse_serialize_name(&meta)? {
+                        result.rename = Some(name.value());
+                    }
+                } else if meta.path.is_ident("rename_all") {
+                    result.rename_all = parse_rename_rule(&meta)?;
+                } else if meta.path.is_ident("rename_all_fields") {
+                    result.rename_all_fields = parse_rename_rule(&meta)?;
+                } else {
+                    skip_meta(&meta)?;
+                }
+                Ok(())
+            })?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Skip the value of a serde attribute we don't care about.
+fn skip_meta(meta: &syn::meta::ParseNestedMeta) -> syn::Result<()> {
+    if meta.input.peek(syn::Token![=]) {
+        let _: syn::Expr = meta.value()?.parse()?;
+    } else if meta.input.peek(syn::token::Paren) {
+        meta.parse_nested_meta(|meta| skip_meta(&meta))?;
+    }
+    Ok(())
 }
 
 #[derive(Debug, FromVariant)]
+#[darling(forward_attrs(serde))]
 struct MyVariantReceiver {
     ident: syn::Ident,
+    attrs: Vec<syn::Attribute>,
 
     fields: ast::Fields<MyFieldReceiver>,
 }
 
 #[derive(Debug, FromDeriveInput)]
+#[darling(forward_attrs(serde))]
 struct RDConfigReceiver {
     ident: syn::Ident,
     generics: syn::Generics,
+    attrs: Vec<syn::Attribute>,
     data: ast::Data<MyVariantReceiver, MyFieldReceiver>,
 }
 
 impl MyFieldReceiver {
-    fn to_token(&self, method_path: &TokenStream, args: &TokenStream) -> TokenStream {
-        let field_name = &self.ident.clone().unwrap();
-        let name = Literal::string(&field_name.to_string());
+    fn serde_attrs(&self) -> SerdeAttrs {
+        SerdeAttrs::from_attrs(&self.attrs).expect("Failed to parse serde attributes")
+    }
+    /// Visit the field. The path pushed to `ctx` is the serialized name of the field,
+    /// flattened fields don't push anything.
+    fn visit_token(
+        &self,
+        field: TokenStream,
+        rename_all: Option<RenameRule>,
+        method_path: &TokenStream,
+        args: &TokenStream,
+    ) -> TokenStream {
+        let serde_attrs = self.serde_attrs();
+        if serde_attrs.flatten {
+            return quote! {
+                #method_path(#field, #args)?;
+            };
+        }
+
+        let field_name = self.ident.clone().unwrap().unraw().to_string();
+        let name = Literal::string(&serde_attrs.rename.unwrap_or_else(|| match rename_all {
+            Some(rule) => rule.apply_to_field(&field_name),
+            None => field_name,
+        }));
         quote! {
             ctx.push(#name);
-            #method_path(&mut self.#field_name, #args)?;
+            #method_path(#field, #args)?;
             ctx.pop();
         }
     }
+    fn to_token(
+        &self,
+        rename_all: Option<RenameRule>,
+        method_path: &TokenStream,
+        args: &TokenStream,
+    ) -> TokenStream {
+        let field_name = &self.ident.clone().unwrap();
+        self.visit_token(
+            quote! { &mut self.#field_name },
+            rename_all,
+            method_path,
+            args,
+        )
+    }
 }
 
 impl RDConfigReceiver {
     fn call_all(&self, method_path: TokenStream, args: TokenStream) -> TokenStream {
         let ident = &self.ident;
+        let container_attrs =
+            SerdeAttrs::from_attrs(&self.attrs).expect("Failed to parse serde attributes");
         let mut body = quote! {};
 
         match &self.data {
@@ -47,12 +220,17 @@ impl RDConfigReceiver {
                 let fields = &s.fields;
 
                 for field in fields {
-                    body.extend(field.to_token(&method_path, &args));
+                    body.extend(field.to_token(container_attrs.rename_all, &method_path, &args));
                 }
             }
             Data::Enum(variants) => {
                 for variant in variants {
                     let variant_name = &variant.ident;
+                    let variant_attrs = SerdeAttrs::from_attrs(&variant.attrs)
+                        .expect("Failed to parse serde attributes");
+                    let rename_all = variant_attrs
+                        .rename_all
+                        .or(container_attrs.rename_all_fields);
                     let mut inner = TokenStream::new();
                     let mut head = TokenStream::new();
 
@@ -60,13 +238,13 @@ impl RDConfigReceiver {
                         ast::Style::Struct => {
                             for field in &variant.fields.fields {
                                 let field_name = &field.ident.clone().unwrap();
-                                let name = Literal::string(&field_name.to_string());
                                 head.extend(quote! { #field_name, });
-                                inner.extend(quote! {
-                                    ctx.push(#name);
-                                    #method_path(#field_name, #args)?;
-                                    ctx.pop();
-                                });
+                                inner.extend(field.visit_token(
+                                    quote! { #field_name },
+                                    rename_all,
+                                    &method_path,
+                                    &args,
+                                ));
                             }
                             head = quote! { { #head } };
                         }
@@ -129,7 +307,7 @@ impl ToTokens for RDConfigReceiver {
     }
 }
 
-#[proc_macro_derive(Config)]
+#[proc_macro_derive(Config, attributes(serde))]
 pub fn config(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let receiver = RDConfigReceiver::from_derive_input(&input).unwrap();
@@ -2,7 +2,7 @@ use serde::{de, ser};
 use std::{
     fmt,
     io::{Error, ErrorKind, Result},
-    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6},
     str::FromStr,
 };
 
@@ -69,6 +69,41 @@ pub enum Address {
     Domain(String, u16),
 }
 
+/// An [`Address`] in its canonical form, so domains holding an IP address,
+/// e.g. `[::1]`, compare and hash equal to the same socket address.
+#[derive(Debug, PartialEq, Clone, PartialOrd, Eq, Ord, Hash)]
+pub struct CanonicalAddress(Address);
+
+impl CanonicalAddress {
+    pub fn new(addr: Address) -> Self {
+        CanonicalAddress(addr.into_normalized())
+    }
+    pub fn as_address(&self) -> &Address {
+        &self.0
+    }
+    pub fn into_inner(self) -> Address {
+        self.0
+    }
+}
+
+impl From<Address> for CanonicalAddress {
+    fn from(addr: Address) -> Self {
+        CanonicalAddress::new(addr)
+    }
+}
+
+impl From<CanonicalAddress> for Address {
+    fn from(addr: CanonicalAddress) -> Self {
+        addr.0
+    }
+}
+
+impl fmt::Display for CanonicalAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 /// Converts to address value.
 pub trait IntoAddress: Send {
     fn into_address(self) -> Result<Address>;
@@ -90,13 +125,42 @@ fn strip_brackets(host: &str) -> &str {
         .unwrap_or(host)
 }
 
-fn host_to_address(host: &str, port: u16) -> Address {
-    match strip_brackets(host).parse::<IpAddr>() {
-        Ok(ip) => {
-            let addr = SocketAddr::new(ip, port);
-            addr.into()
+/// Parse `host` as an IP address. IPv6 may be in brackets and carry a zone id,
+/// e.g. `[fe80::1%eth0]`, the zone is either an interface name or an index.
+fn parse_ip_host(host: &str, port: u16) -> Option<SocketAddr> {
+    let host = strip_brackets(host);
+    let (ip, zone) = match host.split_once('%') {
+        Some((ip, zone)) => (ip, Some(zone)),
+        None => (host, None),
+    };
+    match (ip.parse::<IpAddr>().ok()?, zone) {
+        (ip, None) => Some(SocketAddr::new(ip, port)),
+        (IpAddr::V6(ip), Some(zone)) => {
+            let scope_id = zone.parse().ok().or_else(|| interface_index(zone))?;
+            Some(SocketAddrV6::new(ip, port, 0, scope_id).into())
         }
-        Err(_) => Address::Domain(host.to_string(), port),
+        (IpAddr::V4(_), Some(_)) => None,
+    }
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    let name = std::ffi::CString::new(name).ok()?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+fn host_to_address(host: &str, port: u16) -> Address {
+    match parse_ip_host(host, port) {
+        Some(addr) => addr.into(),
+        None => Address::Domain(host.to_string(), port),
     }
 }
 
@@ -223,6 +287,9 @@ impl Address {
     pub fn host(&self) -> String {
         match self {
             Address::SocketAddr(SocketAddr::V4(s)) => s.ip().to_string(),
+            Address::SocketAddr(SocketAddr::V6(s)) if s.scope_id() != 0 => {
+                format!("[{}%{}]", s.ip(), s.scope_id())
+            }
             Address::SocketAddr(SocketAddr::V6(s)) => format!("[{}]", s.ip()),
             Address::Domain(d, _) => d.to_string(),
         }
@@ -241,9 +308,9 @@ impl Address {
     fn normalize(&self) -> Either<SocketAddr, (&String, u16)> {
         match self {
             Address::SocketAddr(s) => Either::Left(*s),
-            Address::Domain(d, p) => match strip_brackets(d).parse::<IpAddr>() {
-                Ok(ip) => Either::Left(SocketAddr::new(ip, *p)),
-                Err(_) => Either::Right((d, *p)),
+            Address::Domain(d, p) => match parse_ip_host(d, *p) {
+                Some(s) => Either::Left(s),
+                None => Either::Right((d, *p)),
             },
         }
     }
@@ -262,9 +329,9 @@ impl Address {
     pub fn into_normalized(self) -> Address {
         match self {
             Address::SocketAddr(_) => self,
-            Address::Domain(d, p) => match strip_brackets(&d).parse::<IpAddr>() {
-                Ok(ip) => Address::SocketAddr(SocketAddr::new(ip, p)),
-                Err(_) => Address::Domain(d, p),
+            Address::Domain(d, p) => match parse_ip_host(&d, p) {
+                Some(s) => Address::SocketAddr(s),
+                None => Address::Domain(d, p),
             },
         }
     }
@@ -477,6 +544,99 @@ mod tests {
         assert_eq!(domain_ip_addr.port(), 1234);
     }
 
+    #[test]
+    fn test_canonical_address() {
+        use std::collections::HashMap;
+
+        let socket_addr = Address::SocketAddr(SocketAddr::new(IPV6_ADDR, 1234));
+        let domain_ip_addr = Address::Domain(IP_DOMAIN.to_string(), 1234);
+        let bare_ip_addr = Address::Domain("1:2:3:4:5:6:7:8".to_string(), 1234);
+        assert_ne!(socket_addr, domain_ip_addr);
+
+        let canonical = CanonicalAddress::from(socket_addr.clone());
+        assert_eq!(canonical, CanonicalAddress::from(domain_ip_addr.clone()));
+        assert_eq!(canonical, CanonicalAddress::from(bare_ip_addr));
+        assert_eq!(canonical.as_address(), &socket_addr);
+        assert_ne!(
+            canonical,
+            CanonicalAddress::from(Address::Domain(DOMAIN.to_string(), 1234))
+        );
+
+        let mut map = HashMap::new();
+        *map.entry(CanonicalAddress::from(socket_addr)).or_insert(0) += 1;
+        *map.entry(CanonicalAddress::from(domain_ip_addr))
+            .or_insert(0) += 1;
+        assert_eq!(map.len(), 1);
+        assert_eq!(map[&canonical], 2);
+    }
+
+    #[test]
+    fn test_zone_id() {
+        let link_local: Ipv6Addr = "fe80::1".parse().unwrap();
+        let zoned = Address::SocketAddr(SocketAddrV6::new(link_local, 1234, 0, 2).into());
+
+        assert_eq!(zoned, "[fe80::1%2]:1234".into_address().unwrap());
+        assert_eq!(zoned, ("fe80::1%2", 1234).into_address().unwrap());
+        assert_eq!(
+            Address::Domain("[fe80::1%2]".to_string(), 1234).to_normalized(),
+            zoned
+        );
+        assert_eq!(zoned.host(), "[fe80::1%2]");
+        assert_eq!(zoned.to_string(), "[fe80::1%2]:1234");
+
+        // zones are only for IPv6
+        assert!("1.2.3.4%2:1234".into_address().unwrap().is_domain());
+        // unknown interfaces are kept as is
+        let unknown = "[fe80::1%no-such-if0]:1234".into_address().unwrap();
+        assert_eq!(unknown.to_string(), "[fe80::1%no-such-if0]:1234");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_zone_interface_name() {
+        let addr = "[fe80::1%lo]:1234".into_socket_addr().unwrap();
+        let SocketAddr::V6(v6) = addr else {
+            panic!("expected IPv6 address");
+        };
+        assert_ne!(v6.scope_id(), 0);
+    }
+
+    #[test]
+    fn test_zone_serde() {
+        let zoned = "[fe80::1%3]:1234".into_address().unwrap();
+        let json = serde_json::to_string(&zoned).unwrap();
+        assert_eq!(json, "\"[fe80::1%3]:1234\"");
+        assert_eq!(serde_json::from_str::<Address>(&json).unwrap(), zoned);
+    }
+
+    #[tokio::test]
+    async fn test_zone_connect() {
+        use tokio::net::{TcpListener, TcpStream};
+
+        // IPv6 may be unavailable
+        let listener = match TcpListener::bind("[::1]:0").await {
+            Ok(l) => l,
+            Err(_) => return,
+        };
+        let port = listener.local_addr().unwrap().port();
+        let addr = format!("[::1%{}]:{}", interface_index_of_loopback(), port)
+            .into_socket_addr()
+            .unwrap();
+        let (accepted, connected) = tokio::join!(listener.accept(), TcpStream::connect(addr));
+        accepted.unwrap();
+        connected.unwrap();
+    }
+
+    fn interface_index_of_loopback() -> u32 {
+        #[cfg(unix)]
+        for name in ["lo", "lo0"] {
+            if let Some(index) = interface_index(name) {
+                return index;
+            }
+        }
+        1
+    }
+
     async fn dummy_resolve(_host: String, _port: u16) -> Result<Vec<SocketAddr>> {
         panic!("dummy_resolve shouldn't be called")
     }
@@ -1,4 +1,8 @@
-use std::cell::RefCell;
+use std::{
+    cell::RefCell,
+    mem::replace,
+    ops::{Deref, DerefMut},
+};
 
 use crate::{self as rd_interface, Address, Net};
 pub use resolvable::{Resolvable, ResolvableSchema};
@@ -58,6 +62,32 @@ impl Default for NetRef {
     }
 }
 
+/// A `NetRef` that doesn't fail the build when its target is missing.
+/// The missing net is resolved to `blackhole` with a warning.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(transparent)]
+pub struct OptionalNetRef(NetRef);
+
+impl OptionalNetRef {
+    pub fn new(net_ref: NetRef) -> Self {
+        OptionalNetRef(net_ref)
+    }
+}
+
+impl Deref for OptionalNetRef {
+    type Target = NetRef;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for OptionalNetRef {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
 pub trait Visitor {
     #[allow(unused_variables)]
     fn visit_net_ref(&mut self, ctx: &mut VisitorContext, net_ref: &mut NetRef) -> Result<()> {
@@ -67,12 +97,14 @@ pub trait Visitor {
 
 pub struct VisitorContext {
     path: CompactVecString,
+    optional: bool,
 }
 
 impl VisitorContext {
     pub(crate) fn new() -> VisitorContext {
         VisitorContext {
             path: CompactVecString::new(),
+            optional: false,
         }
     }
     pub fn push(&mut self, field: impl AsRef<str>) -> &mut Self {
@@ -85,6 +117,10 @@ impl VisitorContext {
     pub fn path(&self) -> &CompactVecString {
         &self.path
     }
+    /// Whether the `NetRef` being visited is an `OptionalNetRef`.
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
 }
 
 pub trait Config {
@@ -97,6 +133,15 @@ impl Config for NetRef {
     }
 }
 
+impl Config for OptionalNetRef {
+    fn visit(&mut self, ctx: &mut VisitorContext, visitor: &mut dyn Visitor) -> Result<()> {
+        let optional = replace(&mut ctx.optional, true);
+        let result = visitor.visit_net_ref(ctx, &mut self.0);
+        ctx.optional = optional;
+        result
+    }
+}
+
 #[macro_export]
 macro_rules! impl_empty_config {
     ($($x:ident),+ $(,)?) => ($(
@@ -112,7 +157,7 @@ macro_rules! impl_empty_config {
 mod impl_std {
     use super::Config;
     use crate as rd_interface;
-    use crate::{Address, Result};
+    use crate::{context::common_field::Priority, Address, Result};
     use std::collections::{BTreeMap, HashMap, LinkedList, VecDeque};
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
     use std::path::PathBuf;
@@ -152,6 +197,7 @@ mod impl_std {
     impl_empty_config! { String, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, bool, f32, f64 }
     impl_empty_config! { IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6 }
     impl_empty_config! { PathBuf }
+    impl_empty_config! { Priority }
     impl_container_config! { Vec, Option, VecDeque, Result, LinkedList }
     impl_key_container_config! { HashMap, BTreeMap }
 
@@ -160,8 +160,14 @@ pub mod common_field {
     use crate::address::AddressDomain;
 
     use super::CommonField;
+    use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
-    use std::net::SocketAddr;
+    use std::{
+        collections::hash_map::RandomState,
+        hash::{BuildHasher, Hasher},
+        net::SocketAddr,
+        sync::atomic::{AtomicU64, Ordering},
+    };
 
     #[derive(Debug, Deserialize, Serialize)]
     pub struct ProcessInfo {
@@ -187,18 +193,157 @@ pub mod common_field {
         const KEY: &'static str = "dest_socket_addr";
     }
 
+    /// The socket address actually connected by the outbound net,
+    /// e.g. the one chosen by happy eyeballs from the resolved addresses
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ResolvedSocketAddr(pub SocketAddr);
+
+    impl CommonField for ResolvedSocketAddr {
+        const KEY: &'static str = "resolved_socket_addr";
+    }
+
+    /// The addresses of `domain` resolved before connecting, e.g. by a sniffer
+    /// or a previous net, so the outbound net may skip the lookup.
+    /// Ignored if `domain` is not the destination.
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ResolvedAddrs {
+        pub domain: AddressDomain,
+        pub addrs: Vec<SocketAddr>,
+    }
+
+    impl CommonField for ResolvedAddrs {
+        const KEY: &'static str = "resolved_addrs";
+    }
+
     #[derive(Debug, Deserialize, Serialize)]
     pub struct SrcSocketAddr(pub SocketAddr);
 
     impl CommonField for SrcSocketAddr {
         const KEY: &'static str = "src_socket_addr";
     }
+
+    /// The id to correlate the logs of a request, supplied by the client,
+    /// e.g. the `X-Request-Id` header of http, or generated by the server
+    #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+    pub struct RequestId(pub String);
+
+    impl RequestId {
+        /// A random id, unique in the process.
+        pub fn generate() -> RequestId {
+            static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+            RequestId(format!("{:016x}", hasher.finish()))
+        }
+    }
+
+    impl CommonField for RequestId {
+        const KEY: &'static str = "request_id";
+    }
+
+    /// The protocol sniffed from the first bytes of a connection, e.g. `tls`
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct SniffedProtocol(pub String);
+
+    impl CommonField for SniffedProtocol {
+        const KEY: &'static str = "sniffed_protocol";
+    }
+
+    /// The ALPN protocol negotiated on an inbound TLS connection, e.g. `h2`
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct TlsAlpn(pub String);
+
+    impl CommonField for TlsAlpn {
+        const KEY: &'static str = "tls_alpn";
+    }
+
+    /// The JA3 fingerprint of the TLS ClientHello sniffed by the `sni_sniffer` net
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct TlsJa3(pub String);
+
+    impl CommonField for TlsJa3 {
+        const KEY: &'static str = "tls_ja3";
+    }
+
+    /// Hints that the connection is a bulk transfer, e.g. downloads,
+    /// so nets may tune it for throughput
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct BulkTransfer(pub bool);
+
+    impl CommonField for BulkTransfer {
+        const KEY: &'static str = "bulk_transfer";
+    }
+
+    /// The TCP connections open to the destination when connecting, counted by
+    /// the connection manager of the server. The ones being established are not counted.
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ActiveConnections(pub usize);
+
+    impl CommonField for ActiveConnections {
+        const KEY: &'static str = "active_connections";
+    }
+
+    /// The priority of the connection when the bandwidth is shared,
+    /// e.g. set by the `priority` of a rule
+    #[derive(
+        Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema,
+    )]
+    #[serde(rename_all = "lowercase")]
+    pub enum Priority {
+        Low,
+        #[default]
+        Normal,
+        High,
+    }
+
+    impl CommonField for Priority {
+        const KEY: &'static str = "priority";
+    }
+
+    /// How the outbound net connected to the destination, recorded only when
+    /// enabled, e.g. by `trace_connect` of the `local` net
+    #[derive(Debug, Default, Deserialize, Serialize)]
+    pub struct ConnectTrace {
+        /// The resolved addresses, in the order of attempts
+        pub resolved: Vec<SocketAddr>,
+        /// Time spent on resolving, in milliseconds
+        pub resolve_ms: u64,
+        /// The finished attempts, in the order of completion
+        pub attempts: Vec<ConnectAttempt>,
+        /// The address of the attempt which won
+        pub connected: Option<SocketAddr>,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    pub struct ConnectAttempt {
+        pub addr: SocketAddr,
+        /// Time from the start of connecting to the start of this attempt, in milliseconds
+        pub start_ms: u64,
+        /// Time spent on this attempt, in milliseconds
+        pub elapsed_ms: u64,
+        pub error: Option<String>,
+    }
+
+    impl CommonField for ConnectTrace {
+        const KEY: &'static str = "connect_trace";
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_request_id_generate() {
+        use common_field::RequestId;
+
+        let a = RequestId::generate();
+        let b = RequestId::generate();
+        assert_eq!(a.0.len(), 16);
+        assert_ne!(a, b);
+    }
+
     #[test]
     fn test_context_new() {
         let ctx = Context::new();
@@ -69,6 +69,21 @@ impl Error {
             _ => false,
         }
     }
+    /// The connection failed, e.g. refused, reset or timed out,
+    /// which may succeed through another route.
+    pub fn is_connection_error(&self) -> bool {
+        match self {
+            Error::IO(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::TimedOut
+            ),
+            Error::Timeout(_) => true,
+            _ => false,
+        }
+    }
     pub fn other(string: impl Into<String>) -> Error {
         Error::Other(string.into().into())
     }
@@ -159,6 +174,10 @@ mod tests {
 
         let error = Error::from(io::Error::new(io::ErrorKind::AddrInUse, ""));
         assert!(error.is_addr_in_use());
+        assert!(!error.is_connection_error());
+
+        let error = Error::from(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert!(error.is_connection_error());
 
         let error = Error::other("Other error");
         assert_eq!(error.to_string(), "\"Other error\"");
@@ -50,6 +50,11 @@ pub trait ITcpStream: Unpin + Send + Sync {
 
     async fn peer_addr(&self) -> Result<SocketAddr>;
     async fn local_addr(&self) -> Result<SocketAddr>;
+    /// Close the connection with a RST instead of a FIN when it's dropped.
+    /// Returns `false` if it's not supported, e.g. the stream is tunneled.
+    fn set_reset_on_drop(&mut self) -> bool {
+        false
+    }
 }
 pub struct TcpStream(Box<dyn ITcpStream>);
 
@@ -106,6 +111,9 @@ impl TcpStream {
     pub async fn local_addr(&self) -> Result<SocketAddr> {
         self.0.local_addr().await
     }
+    pub fn set_reset_on_drop(&mut self) -> bool {
+        self.0.set_reset_on_drop()
+    }
 }
 
 impl AsyncRead for TcpStream {
@@ -166,6 +174,11 @@ pub trait IUdpSocket: Unpin + Send + Sync {
         target: &Address,
     ) -> Poll<io::Result<usize>>;
     async fn local_addr(&self) -> Result<SocketAddr>;
+    /// The buffer size to receive a datagram without truncation,
+    /// `None` if the socket has no preference.
+    fn recv_buffer_size(&self) -> Option<usize> {
+        None
+    }
 }
 pub struct UdpSocket(Box<dyn IUdpSocket>);
 
@@ -192,6 +205,9 @@ impl UdpSocket {
     pub async fn local_addr(&self) -> Result<SocketAddr> {
         self.0.local_addr().await
     }
+    pub fn recv_buffer_size(&self) -> Option<usize> {
+        self.0.recv_buffer_size()
+    }
     pub fn poll_recv_from(
         &mut self,
         cx: &mut task::Context<'_>,
@@ -239,7 +255,7 @@ pub trait UdpBind: Sync {
 
 #[async_trait]
 pub trait LookupHost: Sync {
-    async fn lookup_host(&self, addr: &Address) -> Result<Vec<SocketAddr>>;
+    async fn lookup_host(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>>;
 }
 
 /// A Net.
@@ -257,7 +273,7 @@ pub trait INet: Downcast + Unpin + Send + Sync {
     fn provide_lookup_host(&self) -> Option<&dyn LookupHost> {
         None
     }
-    // It's used to downcast. Don't implement it.
+    /// The net this one forwards to, if any. It's used to downcast and to list the chain of nets.
     fn get_inner(&self) -> Option<Net> {
         None
     }
@@ -324,13 +340,17 @@ impl Net {
             .udp_bind(ctx, addr)
             .await
     }
-    pub async fn lookup_host(&self, addr: &Address) -> Result<Vec<SocketAddr>> {
+    pub async fn lookup_host(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
         self.0
             .provide_lookup_host()
             .ok_or(Error::NotImplemented)?
-            .lookup_host(addr)
+            .lookup_host(ctx, addr)
             .await
     }
+    /// The net itself as a `T`, unlike `get_inner_net_by` the nets it wraps are not searched.
+    pub fn downcast<T: INet + 'static>(self) -> Option<Arc<T>> {
+        self.0.into_any_arc().downcast().ok()
+    }
     pub fn get_inner_net_by<T: INet + 'static>(self) -> Option<Arc<T>> {
         let mut net = self.0;
         loop {
@@ -1,4 +1,4 @@
-pub use address::{Address, AddressDomain, IntoAddress};
+pub use address::{Address, AddressDomain, CanonicalAddress, IntoAddress};
 pub use context::Context;
 pub use error::{Error, ErrorContext, Result, NOT_IMPLEMENTED};
 pub use interface::*;
@@ -45,3 +45,67 @@ macro_rules! impl_async_write {
         }
     };
 }
+
+/// Implements `Builder<Net>` for a type, which can be registered by `add_net`.
+/// The constructor takes the config and returns `Result<Item>`. The title shown to
+/// the users defaults to the name.
+///
+/// ```ignore
+/// register_net!(MyNet, "my_net", MyNetConfig, MyNet::new, title = "My Net");
+/// // build another type, e.g. the same net with different config
+/// register_net!(MyNetAlias => MyNet, "my_net_alias", MyNetAliasConfig, MyNet::new_alias);
+/// ```
+#[macro_export]
+macro_rules! register_net {
+    (@impl $kind:ty, $ty:ty, $item:ty, $name:literal, $config:ty, $build:expr $(, $title:literal)?) => {
+        impl $crate::registry::Builder<$kind> for $ty {
+            const NAME: &'static str = $name;
+            $(const TITLE: &'static str = $title;)?
+            type Config = $config;
+            type Item = $item;
+
+            fn build(config: Self::Config) -> $crate::Result<Self::Item> {
+                ($build)(config)
+            }
+        }
+    };
+    ($ty:ty => $item:ty, $name:literal, $config:ty, $build:expr $(, title = $title:literal)? $(,)?) => {
+        $crate::register_net!(@impl $crate::Net, $ty, $item, $name, $config, $build $(, $title)?);
+    };
+    ($ty:ty, $name:literal, $config:ty, $build:expr $(, title = $title:literal)? $(,)?) => {
+        $crate::register_net!(@impl $crate::Net, $ty, $ty, $name, $config, $build $(, $title)?);
+    };
+}
+
+/// Implements `Builder<Server>` for a type, same as `register_net`.
+#[macro_export]
+macro_rules! register_server {
+    ($ty:ty => $item:ty, $name:literal, $config:ty, $build:expr $(, title = $title:literal)? $(,)?) => {
+        $crate::register_net!(@impl $crate::Server, $ty, $item, $name, $config, $build $(, $title)?);
+    };
+    ($ty:ty, $name:literal, $config:ty, $build:expr $(, title = $title:literal)? $(,)?) => {
+        $crate::register_net!(@impl $crate::Server, $ty, $ty, $name, $config, $build $(, $title)?);
+    };
+}
+
+/// Generates `pub fn init(registry: &mut Registry) -> Result<()>` of a plugin,
+/// which registers the nets and servers.
+///
+/// ```ignore
+/// init_registry!(net: [MyNet, MyNetAlias], server: [MyServer]);
+/// ```
+#[macro_export]
+macro_rules! init_registry {
+    (@add $registry:ident, net, $item:ty) => {
+        $registry.add_net::<$item>();
+    };
+    (@add $registry:ident, server, $item:ty) => {
+        $registry.add_server::<$item>();
+    };
+    ($($kind:ident: [$($item:ty),* $(,)?]),* $(,)?) => {
+        pub fn init(registry: &mut $crate::Registry) -> $crate::Result<()> {
+            $($($crate::init_registry!(@add registry, $kind, $item);)*)*
+            Ok(())
+        }
+    };
+}
@@ -1,6 +1,6 @@
 use std::{collections::BTreeMap, fmt};
 
-pub use crate::config::NetRef;
+pub use crate::config::{NetRef, OptionalNetRef};
 use crate::{
     config::{Config, Visitor, VisitorContext},
     IntoDyn, Net, Result, Server,
@@ -11,6 +11,7 @@ use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 
 pub type NetGetter<'a> = &'a dyn Fn(&mut NetRef, &VisitorContext) -> Result<Net>;
+pub type NetRefVisitor<'a> = &'a mut dyn FnMut(&VisitorContext, &NetRef);
 
 pub struct Registry {
     pub net: BTreeMap<String, NetResolver>,
@@ -50,6 +51,8 @@ impl Registry {
 
 pub trait Builder<ItemType> {
     const NAME: &'static str;
+    /// The name shown to the users, used as the title of the schema.
+    const TITLE: &'static str = Self::NAME;
     type Config: Serialize + DeserializeOwned + JsonSchema + Config + 'static;
     type Item: IntoDyn<ItemType> + Sized + 'static;
 
@@ -66,10 +69,28 @@ trait BuilderExt<ItemType>: Builder<ItemType> {
 
         Ok(Self::build(config)?.into_dyn())
     }
+    fn visit_net_ref_dyn(cfg: &Value, f: NetRefVisitor) -> Result<()> {
+        struct NetRefVisitorImpl<'a>(NetRefVisitor<'a>);
+
+        impl<'a> Visitor for NetRefVisitorImpl<'a> {
+            fn visit_net_ref(
+                &mut self,
+                ctx: &mut VisitorContext,
+                net_ref: &mut NetRef,
+            ) -> Result<()> {
+                (self.0)(ctx, net_ref);
+                Ok(())
+            }
+        }
+
+        let mut config: Self::Config = serde_json::from_value(cfg.clone())?;
+        config.visit(&mut VisitorContext::new(), &mut NetRefVisitorImpl(f))
+    }
 }
 
 pub struct Resolver<ItemType> {
     build: fn(getter: NetGetter, cfg: &mut Value) -> Result<ItemType>,
+    visit_net_ref: fn(cfg: &Value, f: NetRefVisitor) -> Result<()>,
     schema: RootSchema,
 }
 pub type NetResolver = Resolver<Net>;
@@ -77,15 +98,22 @@ pub type ServerResolver = Resolver<Server>;
 
 impl<ItemType> Resolver<ItemType> {
     pub fn new<N: Builder<ItemType>>() -> Self {
-        let schema = schema_for!(N::Config);
+        let mut schema = schema_for!(N::Config);
+        // the title derived from the config is its Rust type name, and may be shared
+        schema.schema.metadata().title = Some(N::TITLE.to_string());
         Self {
             build: N::build_dyn,
+            visit_net_ref: N::visit_net_ref_dyn,
             schema,
         }
     }
     pub fn build(&self, getter: NetGetter, cfg: &mut Value) -> Result<ItemType> {
         (self.build)(getter, cfg)
     }
+    /// Visit the `NetRef`s in the config without building it.
+    pub fn visit_net_ref(&self, cfg: &Value, f: NetRefVisitor) -> Result<()> {
+        (self.visit_net_ref)(cfg, f)
+    }
     pub fn schema(&self) -> &RootSchema {
         &self.schema
     }
@@ -147,4 +175,145 @@ mod tests {
 
         assert_eq!(test.net[0].as_ptr(), noop.as_ptr())
     }
+
+    #[test]
+    fn test_optional_net_ref() {
+        #[rd_config]
+        struct TestConfig {
+            net: NetRef,
+            list: Vec<OptionalNetRef>,
+        }
+
+        let mut test: TestConfig =
+            serde_json::from_str(r#"{ "net": "a", "list": ["b"] }"#).unwrap();
+        assert_eq!(
+            serde_json::to_value(&test).unwrap(),
+            serde_json::json!({ "net": "a", "list": ["b"] })
+        );
+
+        let optional = std::cell::RefCell::new(Vec::new());
+        let noop = NotImplementedNet.into_dyn();
+        resolve_net(&mut test, &|_, ctx| {
+            optional.borrow_mut().push(ctx.is_optional());
+            Ok(noop.clone())
+        })
+        .unwrap();
+
+        assert_eq!(optional.into_inner(), vec![false, true]);
+        assert_eq!(test.list[0].as_ptr(), noop.as_ptr());
+    }
+
+    fn collect_net_paths(config: &mut dyn Config) -> Vec<String> {
+        let paths = std::cell::RefCell::new(Vec::new());
+        let noop = NotImplementedNet.into_dyn();
+
+        resolve_net(config, &|_, ctx| {
+            paths.borrow_mut().push(ctx.path().join("/"));
+            Ok(noop.clone())
+        })
+        .unwrap();
+
+        paths.into_inner()
+    }
+
+    #[test]
+    fn test_visit_path_flatten() {
+        #[rd_config]
+        struct Inner {
+            net: NetRef,
+        }
+
+        #[rd_config]
+        struct TestConfig {
+            #[serde(flatten)]
+            inner: Inner,
+            #[serde(rename = "remote_net")]
+            remote: NetRef,
+        }
+
+        let mut test: TestConfig =
+            serde_json::from_str(r#"{ "net": "a", "remote_net": "b" }"#).unwrap();
+        let value = serde_json::to_value(&test).unwrap();
+        assert_eq!(value["net"], "a");
+        assert_eq!(value["remote_net"], "b");
+
+        assert_eq!(collect_net_paths(&mut test), vec!["net", "remote_net"]);
+    }
+
+    #[test]
+    fn test_visit_path_rename_all() {
+        #[rd_config]
+        #[serde(rename_all = "kebab-case")]
+        struct TestConfig {
+            remote_net: NetRef,
+            #[serde(rename = "net")]
+            local_net: NetRef,
+        }
+
+        #[rd_config]
+        #[serde(tag = "type", rename_all = "lowercase")]
+        enum TestEnum {
+            #[serde(rename_all = "camelCase")]
+            Remote { remote_net: NetRef },
+        }
+
+        let mut test: TestConfig =
+            serde_json::from_str(r#"{ "remote-net": "a", "net": "b" }"#).unwrap();
+        assert_eq!(collect_net_paths(&mut test), vec!["remote-net", "net"]);
+
+        let mut test: TestEnum =
+            serde_json::from_str(r#"{ "type": "remote", "remoteNet": "a" }"#).unwrap();
+        assert_eq!(collect_net_paths(&mut test), vec!["remoteNet"]);
+    }
+
+    #[test]
+    fn test_visit_net_ref() {
+        #[rd_config]
+        struct TestConfig {
+            net: NetRef,
+            list: Vec<OptionalNetRef>,
+        }
+
+        struct TestNet;
+        impl Builder<Net> for TestNet {
+            const NAME: &'static str = "test";
+            type Config = TestConfig;
+            type Item = NotImplementedNet;
+
+            fn build(_config: Self::Config) -> Result<NotImplementedNet> {
+                Ok(NotImplementedNet)
+            }
+        }
+
+        let resolver = NetResolver::new::<TestNet>();
+        let mut refs = Vec::new();
+        resolver
+            .visit_net_ref(
+                &serde_json::json!({ "net": "a", "list": ["b", { "type": "local" }] }),
+                &mut |ctx, net_ref| {
+                    refs.push((
+                        ctx.path().join("/"),
+                        ctx.is_optional(),
+                        net_ref.represent().clone(),
+                    ))
+                },
+            )
+            .unwrap();
+
+        assert_eq!(
+            refs,
+            vec![
+                ("net".to_string(), false, serde_json::json!("a")),
+                ("list/0".to_string(), true, serde_json::json!("b")),
+                (
+                    "list/1".to_string(),
+                    true,
+                    serde_json::json!({ "type": "local" })
+                ),
+            ]
+        );
+        assert!(resolver
+            .visit_net_ref(&serde_json::json!({}), &mut |_, _| {})
+            .is_err());
+    }
 }
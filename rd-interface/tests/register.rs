@@ -0,0 +1,70 @@
+use rd_interface::{
+    async_trait, config::EmptyConfig, init_registry, prelude::*, register_net, register_server,
+    registry::Builder, Error, INet, IServer, Net, Registry, Result, Value,
+};
+
+#[rd_config]
+struct MyNetConfig {
+    port: u16,
+}
+
+struct MyNet;
+
+impl MyNet {
+    fn new(config: MyNetConfig) -> Result<Self> {
+        if config.port == 0 {
+            return Err(Error::other("port is 0"));
+        }
+        Ok(MyNet)
+    }
+}
+
+impl INet for MyNet {}
+
+struct MyNetAlias;
+
+struct MyServer;
+
+#[async_trait]
+impl IServer for MyServer {
+    async fn start(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+register_net!(MyNet, "my_net", MyNetConfig, MyNet::new, title = "My Net");
+register_net!(MyNetAlias => MyNet, "my_net_alias", EmptyConfig, |_| Ok(MyNet));
+register_server!(MyServer, "my_server", EmptyConfig, |_| Ok(MyServer));
+
+init_registry!(net: [MyNet, MyNetAlias], server: [MyServer]);
+
+fn build_net(registry: &Registry, name: &str, mut config: Value) -> Result<Net> {
+    registry.net[name].build(&|_, _| unreachable!(), &mut config)
+}
+
+#[test]
+fn test_register() {
+    let mut registry = Registry::new();
+    init(&mut registry).unwrap();
+
+    assert_eq!(
+        format!("{registry:?}"),
+        r#"Registry { net: ["my_net", "my_net_alias"], server: ["my_server"] }"#
+    );
+    assert_eq!(<MyNetAlias as Builder<Net>>::NAME, "my_net_alias");
+    assert_eq!(<MyNet as Builder<Net>>::TITLE, "My Net");
+    assert_eq!(<MyNetAlias as Builder<Net>>::TITLE, "my_net_alias");
+    let title = |name: &str| {
+        let schema = registry.net[name].schema();
+        schema.schema.metadata.as_ref()?.title.clone()
+    };
+    assert_eq!(title("my_net").as_deref(), Some("My Net"));
+    assert_eq!(title("my_net_alias").as_deref(), Some("my_net_alias"));
+
+    assert!(build_net(&registry, "my_net", serde_json::json!({ "port": 1 })).is_ok());
+    assert!(build_net(&registry, "my_net", serde_json::json!({ "port": 0 })).is_err());
+    assert!(build_net(&registry, "my_net_alias", serde_json::json!({})).is_ok());
+    assert!(registry.server["my_server"]
+        .build(&|_, _| unreachable!(), &mut serde_json::json!({}))
+        .is_ok());
+}
@@ -2,23 +2,53 @@ use rd_interface::{Registry, Result};
 
 pub mod alias;
 pub mod blackhole;
+#[cfg(feature = "chaos")]
+pub mod chaos;
 pub mod combine;
 pub mod dns;
+pub mod dns_pool;
+pub mod dns_server;
+pub mod drop;
 pub mod echo;
 pub mod forward;
 pub mod local;
+pub mod mem;
+pub mod mirror;
 pub mod noop;
+pub mod overflow;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+pub mod proxy_protocol;
+pub mod rate_limit;
+pub mod reject;
 pub mod resolve;
+pub mod rule_resolver;
+pub mod tarpit;
 
 pub fn init(registry: &mut Registry) -> Result<()> {
     registry.add_net::<alias::AliasNet>();
     registry.add_net::<blackhole::BlackholeNet>();
+    #[cfg(feature = "chaos")]
+    registry.add_net::<chaos::ChaosNet>();
     registry.add_net::<combine::CombineNet>();
     registry.add_net::<dns::DnsNet>();
+    registry.add_net::<dns_pool::DnsPoolNet>();
+    registry.add_net::<drop::DropNet>();
     registry.add_net::<local::LocalNet>();
+    registry.add_net::<mem::MemNet>();
+    registry.add_net::<mirror::MirrorNet>();
     registry.add_net::<noop::NoopNet>();
+    registry.add_net::<overflow::OverflowNet>();
+    #[cfg(feature = "pcap")]
+    registry.add_net::<pcap::PcapNet>();
+    registry.add_net::<proxy_protocol::ProxyProtocolNet>();
+    registry.add_net::<rate_limit::RateLimitNet>();
+    registry.add_net::<reject::RejectNet>();
     registry.add_net::<resolve::ResolveNet>();
+    registry.add_net::<rule_resolver::RuleResolverNet>();
+    registry.add_net::<tarpit::TarpitNet>();
 
+    registry.add_server::<dns_server::DnsServer>();
     registry.add_server::<echo::EchoServer>();
     registry.add_server::<forward::ForwardServer>();
 
@@ -1,28 +1,113 @@
-use rd_interface::{config::NetRef, prelude::*, registry::Builder, INet, Net, Result};
-
-pub struct AliasNet(rd_interface::Net);
+use std::collections::BTreeMap;
+
+use rd_interface::{
+    async_trait,
+    config::NetRef,
+    context::{
+        common_field::{BulkTransfer, DestDomain, DestSocketAddr, ProcessInfo, SrcSocketAddr},
+        CommonField,
+    },
+    prelude::*,
+    registry::Builder,
+    Address, Context, Error, INet, Net, Result, TcpListener, TcpStream, UdpSocket, Value,
+};
+use serde::Deserialize;
+
+pub struct AliasNet {
+    net: Net,
+    context: Vec<(String, Value)>,
+}
 
 impl AliasNet {
-    fn new(net: rd_interface::Net) -> AliasNet {
-        AliasNet(net)
+    fn new(net: Net) -> AliasNet {
+        AliasNet {
+            net,
+            context: Vec::new(),
+        }
+    }
+    fn with_context(net: Net, context: BTreeMap<String, Value>) -> Result<AliasNet> {
+        for (key, value) in &context {
+            check_common_field(key, value)?;
+        }
+        let mut alias = AliasNet::new(net);
+        alias.context = context.into_iter().collect();
+        Ok(alias)
+    }
+    fn apply_context(&self, ctx: &mut Context) {
+        for (key, value) in &self.context {
+            ctx.insert_value(key.clone(), value.clone());
+        }
+    }
+}
+
+fn check_field<T: CommonField>(value: &Value) -> Result<()> {
+    T::deserialize(value)?;
+    Ok(())
+}
+
+fn check_common_field(key: &str, value: &Value) -> Result<()> {
+    match key {
+        ProcessInfo::KEY => check_field::<ProcessInfo>(value),
+        DestDomain::KEY => check_field::<DestDomain>(value),
+        DestSocketAddr::KEY => check_field::<DestSocketAddr>(value),
+        SrcSocketAddr::KEY => check_field::<SrcSocketAddr>(value),
+        BulkTransfer::KEY => check_field::<BulkTransfer>(value),
+        _ => Err(Error::other(format!("Unknown context field: {}", key))),
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for AliasNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        self.apply_context(ctx);
+        self.net.tcp_connect(ctx, addr).await
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpBind for AliasNet {
+    async fn tcp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<TcpListener> {
+        self.apply_context(ctx);
+        self.net.tcp_bind(ctx, addr).await
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for AliasNet {
+    async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
+        self.apply_context(ctx);
+        self.net.udp_bind(ctx, addr).await
     }
 }
 
 impl INet for AliasNet {
     fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
-        self.0.provide_tcp_connect()
+        match self.net.provide_tcp_connect() {
+            Some(_) if !self.context.is_empty() => Some(self),
+            p => p,
+        }
     }
 
     fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
-        self.0.provide_tcp_bind()
+        match self.net.provide_tcp_bind() {
+            Some(_) if !self.context.is_empty() => Some(self),
+            p => p,
+        }
     }
 
     fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
-        self.0.provide_udp_bind()
+        match self.net.provide_udp_bind() {
+            Some(_) if !self.context.is_empty() => Some(self),
+            p => p,
+        }
     }
 
     fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
-        self.0.provide_lookup_host()
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
     }
 }
 
@@ -31,15 +116,19 @@ impl INet for AliasNet {
 #[derive(Debug)]
 pub struct AliasNetConfig {
     net: NetRef,
+    /// Context fields to set on connections passing through, e.g. `src_socket_addr`.
+    #[serde(default)]
+    context: BTreeMap<String, Value>,
 }
 
 impl Builder<Net> for AliasNet {
     const NAME: &'static str = "alias";
+    const TITLE: &'static str = "Alias";
     type Config = AliasNetConfig;
     type Item = Self;
 
     fn build(config: Self::Config) -> Result<Self> {
-        Ok(AliasNet::new(config.net.value_cloned()))
+        AliasNet::with_context(config.net.value_cloned(), config.context)
     }
 }
 
@@ -48,9 +137,12 @@ mod tests {
     use rd_interface::IntoDyn;
 
     use super::*;
-    use crate::tests::{
-        assert_echo, assert_echo_udp, assert_net_provider, spawn_echo_server,
-        spawn_echo_server_udp, ProviderCapability, TestNet,
+    use crate::{
+        rule::{config as rule_config, RuleNet},
+        tests::{
+            assert_echo, assert_echo_udp, assert_net_provider, spawn_echo_server,
+            spawn_echo_server_udp, ProviderCapability, TestNet,
+        },
     };
 
     #[test]
@@ -81,4 +173,58 @@ mod tests {
         spawn_echo_server_udp(&parent_net, "127.0.0.1:26666").await;
         assert_echo_udp(&net, "127.0.0.1:26666").await;
     }
+
+    #[tokio::test]
+    async fn test_alias_context() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "127.0.0.1:26667").await;
+        spawn_echo_server_udp(&net, "127.0.0.1:26667").await;
+
+        let rule_net = RuleNet::new(rule_config::RuleNetConfig {
+            rule: vec![rule_config::RuleItem {
+                matcher: rule_config::Matcher::SrcIpCidr(rule_config::SrcIpCidrMatcher {
+                    ipcidr: vec!["10.0.0.1/32".parse().unwrap()].into(),
+                }),
+                target: NetRef::new_with_value("net".into(), net),
+                priority: None,
+                bulk_transfer: false,
+                provider: None,
+            }],
+            lru_cache_size: 10,
+            lru_cache_ttl: None,
+        })
+        .unwrap()
+        .into_dyn();
+
+        let alias = AliasNet::with_context(
+            rule_net,
+            BTreeMap::from([(SrcSocketAddr::KEY.to_string(), Value::from("10.0.0.1:1234"))]),
+        )
+        .unwrap()
+        .into_dyn();
+
+        assert_echo(&alias, "127.0.0.1:26667").await;
+        // the rule of UDP is matched with the context too
+        assert_echo_udp(&alias, "127.0.0.1:26667").await;
+    }
+
+    #[test]
+    fn test_alias_context_unknown_field() {
+        let net = TestNet::new().into_dyn();
+
+        let result = AliasNet::with_context(
+            net.clone(),
+            BTreeMap::from([("unknown".to_string(), Value::from("value"))]),
+        );
+        assert!(result.is_err());
+
+        let result = AliasNet::with_context(
+            net,
+            BTreeMap::from([(
+                SrcSocketAddr::KEY.to_string(),
+                Value::from("not an address"),
+            )]),
+        );
+        assert!(result.is_err());
+    }
 }
@@ -1,13 +1,17 @@
-use std::{future::pending, io, net::SocketAddr, task::Poll};
+use std::io;
 
 use rd_interface::{
-    async_trait, config::EmptyConfig, registry::Builder, Address, INet, ITcpListener, ITcpStream,
-    IUdpSocket, IntoDyn, Net, ReadBuf, Result, TcpBind, TcpConnect, UdpBind, NOT_IMPLEMENTED,
+    async_trait, config::EmptyConfig, registry::Builder, Address, INet, Net, Result, TcpBind,
+    TcpConnect, UdpBind,
 };
+
+/// Refuses everything with `ConnectionRefused`, so clients fail fast.
+/// Use `drop` to accept connections and discard the data instead.
 pub struct BlackholeNet;
 
 impl Builder<Net> for BlackholeNet {
     const NAME: &'static str = "blackhole";
+    const TITLE: &'static str = "Blackhole";
     type Config = EmptyConfig;
     type Item = BlackholeNet;
 
@@ -16,76 +20,8 @@ impl Builder<Net> for BlackholeNet {
     }
 }
 
-struct BlackItem;
-
-#[async_trait]
-impl ITcpStream for BlackItem {
-    fn poll_read(
-        &mut self,
-        _cx: &mut std::task::Context<'_>,
-        _buf: &mut ReadBuf<'_>,
-    ) -> Poll<io::Result<()>> {
-        Poll::Ready(Ok(()))
-    }
-
-    fn poll_write(
-        &mut self,
-        _cx: &mut std::task::Context<'_>,
-        buf: &[u8],
-    ) -> Poll<Result<usize, io::Error>> {
-        Poll::Ready(Ok(buf.len()))
-    }
-
-    fn poll_flush(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), io::Error>> {
-        Poll::Ready(Ok(()))
-    }
-
-    fn poll_shutdown(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), io::Error>> {
-        Poll::Ready(Ok(()))
-    }
-
-    async fn peer_addr(&self) -> Result<std::net::SocketAddr> {
-        Err(NOT_IMPLEMENTED)
-    }
-
-    async fn local_addr(&self) -> Result<std::net::SocketAddr> {
-        Err(NOT_IMPLEMENTED)
-    }
-}
-
-#[async_trait]
-impl ITcpListener for BlackItem {
-    async fn accept(&self) -> Result<(rd_interface::TcpStream, std::net::SocketAddr)> {
-        pending().await
-    }
-
-    async fn local_addr(&self) -> Result<std::net::SocketAddr> {
-        Err(NOT_IMPLEMENTED)
-    }
-}
-
-#[async_trait]
-impl IUdpSocket for BlackItem {
-    async fn local_addr(&self) -> Result<std::net::SocketAddr> {
-        Err(NOT_IMPLEMENTED)
-    }
-
-    fn poll_recv_from(
-        &mut self,
-        _cx: &mut std::task::Context<'_>,
-        _buf: &mut ReadBuf,
-    ) -> Poll<io::Result<SocketAddr>> {
-        Poll::Pending
-    }
-
-    fn poll_send_to(
-        &mut self,
-        _cx: &mut std::task::Context<'_>,
-        _buf: &[u8],
-        _target: &Address,
-    ) -> Poll<io::Result<usize>> {
-        Poll::Pending
-    }
+fn refused() -> rd_interface::Error {
+    io::Error::from(io::ErrorKind::ConnectionRefused).into()
 }
 
 #[async_trait]
@@ -95,7 +31,7 @@ impl TcpConnect for BlackholeNet {
         _ctx: &mut rd_interface::Context,
         _addr: &Address,
     ) -> Result<rd_interface::TcpStream> {
-        Ok(BlackItem.into_dyn())
+        Err(refused())
     }
 }
 
@@ -106,7 +42,7 @@ impl TcpBind for BlackholeNet {
         _ctx: &mut rd_interface::Context,
         _addr: &Address,
     ) -> Result<rd_interface::TcpListener> {
-        Ok(BlackItem.into_dyn())
+        Err(refused())
     }
 }
 
@@ -117,7 +53,7 @@ impl UdpBind for BlackholeNet {
         _ctx: &mut rd_interface::Context,
         _addr: &Address,
     ) -> Result<rd_interface::UdpSocket> {
-        Ok(BlackItem.into_dyn())
+        Err(refused())
     }
 }
 
@@ -136,7 +72,7 @@ impl INet for BlackholeNet {
 #[cfg(test)]
 mod tests {
     use crate::tests::{assert_net_provider, ProviderCapability};
-    use rd_interface::IntoDyn;
+    use rd_interface::{Context, Error, IntoAddress, IntoDyn};
 
     use super::*;
 
@@ -154,4 +90,18 @@ mod tests {
             },
         );
     }
+
+    fn is_refused<T>(r: Result<T>) -> bool {
+        matches!(r, Err(Error::IO(e)) if e.kind() == io::ErrorKind::ConnectionRefused)
+    }
+
+    #[tokio::test]
+    async fn test_refused() {
+        let bh = BlackholeNet.into_dyn();
+        let addr = "127.0.0.1:1234".into_address().unwrap();
+
+        assert!(is_refused(bh.tcp_connect(&mut Context::new(), &addr).await));
+        assert!(is_refused(bh.tcp_bind(&mut Context::new(), &addr).await));
+        assert!(is_refused(bh.udp_bind(&mut Context::new(), &addr).await));
+    }
 }
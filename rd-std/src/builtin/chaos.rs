@@ -0,0 +1,345 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    task::{self, Poll},
+    time::Duration,
+};
+
+use futures::{ready, Future};
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rd_interface::{
+    async_trait,
+    prelude::*,
+    registry::{Builder, NetRef},
+    Address, AsyncRead, AsyncWrite, Context, INet, ITcpStream, IntoDyn, Net, ReadBuf, Result,
+    TcpStream, UdpSocket,
+};
+use tokio::time::{sleep, Sleep};
+
+/// Injects latency, failures and bandwidth limit into `net`, for testing the
+/// retry and fallback configs.
+#[rd_config]
+#[derive(Debug)]
+pub struct ChaosNetConfig {
+    #[serde(default)]
+    net: NetRef,
+    /// latency added before each connection, in milliseconds.
+    #[serde(default)]
+    latency: u64,
+    /// random latency added on top of `latency`, up to this, in milliseconds.
+    #[serde(default)]
+    jitter: u64,
+    /// probability of a connection to fail, from 0 to 1.
+    #[serde(default)]
+    failure_rate: f64,
+    /// bandwidth of each TCP connection in bytes per second, per direction. 0 for unlimited.
+    #[serde(default)]
+    bandwidth: u64,
+    /// seed of the random number generator. Random if not set.
+    seed: Option<u64>,
+}
+
+pub struct ChaosNet {
+    net: Net,
+    latency: Duration,
+    jitter: Duration,
+    failure_rate: f64,
+    bandwidth: u64,
+    rng: Mutex<StdRng>,
+}
+
+impl ChaosNet {
+    pub fn new(net: Net, seed: Option<u64>) -> ChaosNet {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        ChaosNet {
+            net,
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            failure_rate: 0.0,
+            bandwidth: 0,
+            rng: Mutex::new(rng),
+        }
+    }
+
+    pub fn latency(mut self, latency: Duration, jitter: Duration) -> Self {
+        self.latency = latency;
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn failure_rate(mut self, failure_rate: f64) -> Self {
+        self.failure_rate = failure_rate;
+        self
+    }
+
+    pub fn bandwidth(mut self, bandwidth: u64) -> Self {
+        self.bandwidth = bandwidth;
+        self
+    }
+
+    /// Waits for the injected latency, then fails the connection by chance.
+    async fn chaos(&self) -> Result<()> {
+        let (delay, fail) = {
+            let mut rng = self.rng.lock();
+            let jitter = rng.gen_range(Duration::ZERO..=self.jitter);
+            (self.latency + jitter, rng.gen_bool(self.failure_rate))
+        };
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        if fail {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "chaos").into());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for ChaosNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        self.chaos().await?;
+        let tcp = self.net.tcp_connect(ctx, addr).await?;
+        if self.bandwidth == 0 {
+            return Ok(tcp);
+        }
+        Ok(ThrottledTcp::new(tcp, self.bandwidth).into_dyn())
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for ChaosNet {
+    async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
+        self.chaos().await?;
+        self.net.udp_bind(ctx, addr).await
+    }
+}
+
+impl INet for ChaosNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        Some(self)
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+impl Builder<Net> for ChaosNet {
+    const NAME: &'static str = "chaos";
+    const TITLE: &'static str = "Chaos";
+    type Config = ChaosNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        if !(0.0..=1.0).contains(&config.failure_rate) {
+            return Err(rd_interface::Error::other(
+                "failure_rate must be between 0 and 1",
+            ));
+        }
+        Ok(ChaosNet::new(config.net.value_cloned(), config.seed)
+            .latency(
+                Duration::from_millis(config.latency),
+                Duration::from_millis(config.jitter),
+            )
+            .failure_rate(config.failure_rate)
+            .bandwidth(config.bandwidth))
+    }
+}
+
+/// Waits after each read and write as long as sending the bytes takes at `bandwidth`.
+struct ThrottledTcp {
+    tcp: TcpStream,
+    bandwidth: u64,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl ThrottledTcp {
+    fn new(tcp: TcpStream, bandwidth: u64) -> ThrottledTcp {
+        ThrottledTcp {
+            tcp,
+            bandwidth,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+
+    fn delay(&self, bytes: usize) -> Option<Pin<Box<Sleep>>> {
+        if bytes == 0 {
+            return None;
+        }
+        let duration = Duration::from_secs_f64(bytes as f64 / self.bandwidth as f64);
+        Some(Box::pin(sleep(duration)))
+    }
+}
+
+fn poll_delay(delay: &mut Option<Pin<Box<Sleep>>>, cx: &mut task::Context<'_>) -> Poll<()> {
+    if let Some(sleep) = delay {
+        ready!(sleep.as_mut().poll(cx));
+        *delay = None;
+    }
+    Poll::Ready(())
+}
+
+#[async_trait]
+impl ITcpStream for ThrottledTcp {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        ready!(poll_delay(&mut self.read_delay, cx));
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.tcp).poll_read(cx, buf))?;
+        self.read_delay = self.delay(buf.filled().len() - filled);
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write(&mut self, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(poll_delay(&mut self.write_delay, cx));
+
+        let written = ready!(Pin::new(&mut self.tcp).poll_write(cx, buf))?;
+        self.write_delay = self.delay(written);
+
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.tcp).poll_flush(cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.tcp).poll_shutdown(cx)
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        self.tcp.peer_addr().await
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.tcp.local_addr().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::IntoAddress;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        task::yield_now,
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::tests::{assert_net_provider, spawn_echo_server, ProviderCapability, TestNet};
+
+    #[test]
+    fn test_provider() {
+        let net = ChaosNet::new(TestNet::new().into_dyn(), Some(0)).into_dyn();
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+    }
+
+    async fn outcomes(net: &Net, addr: &Address, count: usize) -> Vec<bool> {
+        let mut outcomes = Vec::with_capacity(count);
+        for _ in 0..count {
+            let result = net.tcp_connect(&mut Context::new(), addr).await;
+            outcomes.push(result.is_ok());
+        }
+        outcomes
+    }
+
+    #[tokio::test]
+    async fn test_failure_rate() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:26666").await;
+        yield_now().await;
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+
+        let net = ChaosNet::new(test_net.clone(), Some(42))
+            .failure_rate(0.3)
+            .into_dyn();
+        let results = outcomes(&net, &addr, 1000).await;
+        let failed = results.iter().filter(|ok| !**ok).count();
+        assert!((250..=350).contains(&failed), "{}", failed);
+
+        // the same seed gives the same failures
+        let net = ChaosNet::new(test_net, Some(42))
+            .failure_rate(0.3)
+            .into_dyn();
+        assert_eq!(outcomes(&net, &addr, 1000).await, results);
+    }
+
+    #[tokio::test]
+    async fn test_latency() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:26667").await;
+        yield_now().await;
+        let addr = "127.0.0.1:26667".into_address().unwrap();
+
+        let net = ChaosNet::new(test_net, Some(42))
+            .latency(Duration::from_millis(20), Duration::from_millis(20))
+            .into_dyn();
+
+        let start = Instant::now();
+        for _ in 0..10 {
+            let start = Instant::now();
+            net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(20));
+        }
+        let average = start.elapsed() / 10;
+        assert!(average <= Duration::from_millis(60), "{:?}", average);
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:26668").await;
+        yield_now().await;
+        let addr = "127.0.0.1:26668".into_address().unwrap();
+
+        let net = ChaosNet::new(test_net, Some(42))
+            .bandwidth(10_000)
+            .into_dyn();
+        let mut tcp = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+
+        let start = Instant::now();
+        let data = [1u8; 1000];
+        for _ in 0..5 {
+            tcp.write_all(&data).await.unwrap();
+        }
+        let mut buf = [0u8; 5000];
+        tcp.read_exact(&mut buf).await.unwrap();
+        let elapsed = start.elapsed();
+
+        // 4 writes wait for 100ms each
+        assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
+        assert!(elapsed <= Duration::from_secs(2), "{:?}", elapsed);
+    }
+}
@@ -37,6 +37,7 @@ pub struct CombineNetConfig {
 
 impl Builder<Net> for CombineNet {
     const NAME: &'static str = "combine";
+    const TITLE: &'static str = "Combine";
     type Config = CombineNetConfig;
     type Item = Self;
 
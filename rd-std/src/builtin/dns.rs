@@ -1,18 +1,27 @@
-use std::net::SocketAddr;
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use lru_time_cache::LruCache;
+use parking_lot::{Mutex, RwLock};
 use rd_derive::rd_config;
 use rd_interface::{
-    async_trait, config::NetRef, prelude::*, registry::Builder, Address, Error, INet, IntoDyn, Net,
-    Result,
+    async_trait, config::NetRef, prelude::*, registry::Builder, Address, Context, Error, INet,
+    IntoDyn, Net, Result,
 };
 use trust_dns_resolver::{
     config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts},
+    error::ResolveErrorKind,
     AsyncResolver,
 };
 
 use self::rd_runtime::{RDConnection, RDConnectionProvider, RDHandle};
 
 use super::local::{LocalNet, LocalNetConfig};
+use crate::{rule::config::IpCidr, util::drop_blocked};
 
 /// A net refering to another net.
 #[rd_config]
@@ -30,27 +39,102 @@ pub struct DnsConfig {
     server: DnsServer,
     #[serde(default)]
     net: Option<NetRef>,
+    /// seconds to remember a lookup without records, e.g. NXDOMAIN,
+    /// so it's not sent to the nameservers again. 0 to disable.
+    #[serde(default = "default_negative_ttl")]
+    negative_ttl: u64,
+    /// drop the answered addresses in these ranges, e.g. `127.0.0.0/8` against
+    /// DNS rebinding. the lookup fails if all of them are dropped.
+    #[serde(default)]
+    block: Vec<IpCidr>,
 }
 
+fn default_negative_ttl() -> u64 {
+    5
+}
+
+const NEGATIVE_CACHE_CAPACITY: usize = 1024;
+
+type Resolver = AsyncResolver<RDConnection, RDConnectionProvider>;
+
 pub struct DnsNet {
     net: Net,
-    resolver: AsyncResolver<RDConnection, RDConnectionProvider>,
+    resolver_config: ResolverConfig,
+    resolver: RwLock<Resolver>,
+    negative_ttl: Duration,
+    /// The errors of the lookups without records by host, with their expiry.
+    negative: Option<Mutex<LruCache<String, (Instant, String)>>>,
+    block: Arc<Vec<IpCidr>>,
+}
+
+impl DnsNet {
+    fn new_resolver(
+        resolver_config: &ResolverConfig,
+        negative_ttl: Duration,
+        net: &Net,
+    ) -> Result<Resolver> {
+        let mut opts = ResolverOpts::default();
+        if !negative_ttl.is_zero() {
+            // don't keep the negative responses longer than the negative cache
+            opts.negative_max_ttl = Some(negative_ttl);
+        }
+        AsyncResolver::new(resolver_config.clone(), opts, RDHandle(net.clone()))
+            .map_err(|e| Error::other(format!("Failed to build resolver: {e:?}")))
+    }
+    /// Clear the cached records, the following lookups are sent to the nameservers.
+    pub fn flush(&self) -> Result<()> {
+        let resolver = Self::new_resolver(&self.resolver_config, self.negative_ttl, &self.net)?;
+        *self.resolver.write() = resolver;
+        if let Some(negative) = &self.negative {
+            negative.lock().clear();
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
 impl rd_interface::LookupHost for DnsNet {
-    async fn lookup_host(&self, addr: &Address) -> Result<Vec<SocketAddr>> {
+    async fn lookup_host(&self, _ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
         // TODO: is it cheap?
-        let r = self.resolver.clone();
+        let r = self.resolver.read().clone();
+        let negative = self.negative.as_ref();
+        let negative_ttl = self.negative_ttl;
+        let block = self.block.clone();
         rd_runtime::NET
             .scope(self.net.clone(), async move {
                 addr.resolve(move |host, port| async move {
-                    let response = r.lookup_ip(host).await?;
+                    // `peek` doesn't refresh the entry, so it expires after `negative_ttl`
+                    // even if it's looked up all the time.
+                    let cached = negative.and_then(|n| {
+                        n.lock()
+                            .peek(&host)
+                            .filter(|(expiry, _)| *expiry > Instant::now())
+                            .map(|(_, error)| error.clone())
+                    });
+                    if let Some(error) = cached {
+                        return Err(io::Error::new(io::ErrorKind::Other, error));
+                    }
 
-                    Ok(response
+                    let response = match r.lookup_ip(host.as_str()).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            // timeouts and the other transient errors are not cached
+                            if let (Some(negative), ResolveErrorKind::NoRecordsFound { .. }) =
+                                (negative, e.kind())
+                            {
+                                negative
+                                    .lock()
+                                    .insert(host, (Instant::now() + negative_ttl, e.to_string()));
+                            }
+                            return Err(e.into());
+                        }
+                    };
+
+                    let addrs = response
                         .into_iter()
                         .map(|ip| SocketAddr::new(ip, port))
-                        .collect())
+                        .collect();
+                    drop_blocked(&host, addrs, &block)
                 })
                 .await
                 .map_err(Into::into)
@@ -63,10 +147,15 @@ impl INet for DnsNet {
     fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
         Some(self)
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 impl Builder<Net> for DnsNet {
     const NAME: &'static str = "dns";
+    const TITLE: &'static str = "DNS Resolver";
     type Config = DnsConfig;
     type Item = Self;
 
@@ -87,14 +176,19 @@ impl Builder<Net> for DnsNet {
                     .collect::<Vec<_>>(),
             ),
         };
-        let resolver = AsyncResolver::new(
-            resolver_config,
-            ResolverOpts::default(),
-            RDHandle(net.clone()),
-        )
-        .map_err(|e| Error::other(format!("Failed to build resolver: {e:?}")))?;
+        let negative_ttl = Duration::from_secs(config.negative_ttl);
+        let resolver = Self::new_resolver(&resolver_config, negative_ttl, &net)?;
+        let negative = (!negative_ttl.is_zero())
+            .then(|| Mutex::new(LruCache::with_capacity(NEGATIVE_CACHE_CAPACITY)));
 
-        Ok(Self { resolver, net })
+        Ok(Self {
+            net,
+            resolver_config,
+            resolver: RwLock::new(resolver),
+            negative_ttl,
+            negative,
+            block: Arc::new(config.block),
+        })
     }
 }
 
@@ -225,16 +319,88 @@ mod rd_runtime {
 
 #[cfg(test)]
 mod tests {
-    use crate::tests::{assert_net_provider, ProviderCapability};
-    use rd_interface::IntoDyn;
+    use std::{
+        net::Ipv4Addr,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use crate::tests::{assert_net_provider, ProviderCapability, TestNet};
+    use rd_interface::{
+        constant::UDP_BUFFER_SIZE, Context, IntoAddress, IntoDyn, LookupHost, ReadBuf,
+    };
+    use tokio::time::sleep;
+    use trust_dns_proto::{
+        op::{Message, MessageType, ResponseCode},
+        rr::{RData, Record},
+    };
 
     use super::*;
 
+    /// A nameserver answers 127.0.0.1 to every query, returns the number of queries.
+    async fn spawn_nameserver(net: &Net, addr: &str) -> Arc<AtomicUsize> {
+        spawn_nameserver_with(net, addr, Arc::new(AtomicBool::new(true))).await
+    }
+
+    /// Like `spawn_nameserver`, but answers NXDOMAIN while `alive` is false.
+    async fn spawn_nameserver_with(
+        net: &Net,
+        addr: &str,
+        alive: Arc<AtomicBool>,
+    ) -> Arc<AtomicUsize> {
+        let queries = Arc::new(AtomicUsize::new(0));
+        let mut udp = net
+            .udp_bind(&mut Context::new(), &addr.into_address().unwrap())
+            .await
+            .unwrap();
+
+        let counter = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0; UDP_BUFFER_SIZE];
+            loop {
+                let mut buf = ReadBuf::new(&mut buf);
+                let from = udp.recv_from(&mut buf).await.unwrap();
+                counter.fetch_add(1, Ordering::Relaxed);
+
+                let request = Message::from_vec(buf.filled()).unwrap();
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_recursion_available(true)
+                    .add_queries(request.queries().iter().cloned());
+                if !alive.load(Ordering::Relaxed) {
+                    response.set_response_code(ResponseCode::NXDomain);
+                }
+                for query in request
+                    .queries()
+                    .iter()
+                    .filter(|_| alive.load(Ordering::Relaxed))
+                {
+                    response.add_answer(Record::from_rdata(
+                        query.name().clone(),
+                        60,
+                        RData::A(Ipv4Addr::LOCALHOST),
+                    ));
+                }
+                udp.send_to(&response.to_vec().unwrap(), &from.into())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        queries
+    }
+
     #[test]
     fn test_provider() {
         let dns = DnsNet::build(DnsConfig {
             server: DnsServer::Google,
             net: None,
+            negative_ttl: default_negative_ttl(),
+            block: vec![],
         })
         .unwrap()
         .into_dyn();
@@ -247,4 +413,109 @@ mod tests {
             },
         );
     }
+
+    #[tokio::test]
+    async fn test_flush() {
+        let net = TestNet::new().into_dyn();
+        let queries = spawn_nameserver(&net, "127.0.0.1:5353").await;
+
+        let dns = DnsNet::build(DnsConfig {
+            server: DnsServer::Custom {
+                nameserver: vec!["127.0.0.1:5353".parse().unwrap()],
+            },
+            net: Some(NetRef::new_with_value("test".into(), net.clone())),
+            negative_ttl: default_negative_ttl(),
+            block: vec![],
+        })
+        .unwrap();
+        let addr = Address::Domain("example.com".to_string(), 443);
+        let expected: Vec<SocketAddr> = vec!["127.0.0.1:443".parse().unwrap()];
+
+        assert_eq!(
+            dns.lookup_host(&mut Context::new(), &addr).await.unwrap(),
+            expected
+        );
+        let sent = queries.load(Ordering::Relaxed);
+        assert!(sent > 0);
+
+        // answered by the cache
+        assert_eq!(
+            dns.lookup_host(&mut Context::new(), &addr).await.unwrap(),
+            expected
+        );
+        assert_eq!(queries.load(Ordering::Relaxed), sent);
+
+        dns.flush().unwrap();
+        assert_eq!(
+            dns.lookup_host(&mut Context::new(), &addr).await.unwrap(),
+            expected
+        );
+        assert!(queries.load(Ordering::Relaxed) > sent);
+    }
+
+    #[tokio::test]
+    async fn test_negative_cache() {
+        let net = TestNet::new().into_dyn();
+        let alive = Arc::new(AtomicBool::new(false));
+        let queries = spawn_nameserver_with(&net, "127.0.0.1:5354", alive.clone()).await;
+
+        let dns = DnsNet::build(DnsConfig {
+            server: DnsServer::Custom {
+                nameserver: vec!["127.0.0.1:5354".parse().unwrap()],
+            },
+            net: Some(NetRef::new_with_value("test".into(), net.clone())),
+            negative_ttl: 1,
+            block: vec![],
+        })
+        .unwrap();
+        let addr = Address::Domain("dead.example.com".to_string(), 443);
+
+        assert!(dns.lookup_host(&mut Context::new(), &addr).await.is_err());
+        let sent = queries.load(Ordering::Relaxed);
+        assert!(sent > 0);
+
+        // the failure is cached
+        assert!(dns.lookup_host(&mut Context::new(), &addr).await.is_err());
+        assert_eq!(queries.load(Ordering::Relaxed), sent);
+
+        // looking it up again doesn't extend the negative ttl
+        sleep(Duration::from_millis(600)).await;
+        assert!(dns.lookup_host(&mut Context::new(), &addr).await.is_err());
+        assert_eq!(queries.load(Ordering::Relaxed), sent);
+
+        // the domain is back after the negative ttl
+        alive.store(true, Ordering::Relaxed);
+        sleep(Duration::from_millis(600)).await;
+        let expected: Vec<SocketAddr> = vec!["127.0.0.1:443".parse().unwrap()];
+        assert_eq!(
+            dns.lookup_host(&mut Context::new(), &addr).await.unwrap(),
+            expected
+        );
+        assert!(queries.load(Ordering::Relaxed) > sent);
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let net = TestNet::new().into_dyn();
+        spawn_nameserver(&net, "127.0.0.1:5355").await;
+
+        // the nameserver answers 127.0.0.1
+        let dns = DnsNet::build(DnsConfig {
+            server: DnsServer::Custom {
+                nameserver: vec!["127.0.0.1:5355".parse().unwrap()],
+            },
+            net: Some(NetRef::new_with_value("test".into(), net.clone())),
+            negative_ttl: default_negative_ttl(),
+            block: vec!["127.0.0.0/8".parse().unwrap()],
+        })
+        .unwrap();
+        let err = dns
+            .lookup_host(
+                &mut Context::new(),
+                &Address::Domain("rebind.example.com".to_string(), 443),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("blocked"), "{err}");
+    }
 }
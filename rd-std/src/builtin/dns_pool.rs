@@ -0,0 +1,307 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use futures::{
+    future::{BoxFuture, Shared},
+    stream::FuturesUnordered,
+    FutureExt, StreamExt,
+};
+use parking_lot::Mutex;
+use rd_derive::rd_config;
+use rd_interface::{
+    async_trait, config::NetRef, prelude::*, registry::Builder, Address, Context, Error, INet, Net,
+    Result,
+};
+
+use crate::rule::config::IpCidr;
+
+/// Which answer of the resolvers is returned.
+#[rd_config]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsPoolStrategy {
+    /// the first non-empty answer, an error is returned only if all of the resolvers fail
+    #[default]
+    Fastest,
+    /// the first response, even if it's an error
+    First,
+}
+
+#[rd_config]
+#[derive(Debug)]
+pub struct DnsPoolConfig {
+    /// the nets to lookup the hosts by, queried concurrently.
+    /// they can be of different kinds, e.g. a plain DNS and one through a proxy.
+    resolvers: Vec<NetRef>,
+    #[serde(default)]
+    strategy: DnsPoolStrategy,
+    /// the answers with any address in these ranges are poisoned, they are ignored
+    /// and the answers of the other resolvers are waited for
+    #[serde(default)]
+    reject: Vec<IpCidr>,
+}
+
+type Lookup = Shared<BoxFuture<'static, std::result::Result<Vec<SocketAddr>, String>>>;
+
+/// Sends each lookup to all of the resolvers at once. The concurrent lookups of
+/// the same address share a single query.
+pub struct DnsPoolNet {
+    resolvers: Arc<Vec<Net>>,
+    strategy: DnsPoolStrategy,
+    reject: Arc<Vec<IpCidr>>,
+    in_flight: Arc<Mutex<HashMap<Address, Lookup>>>,
+}
+
+impl DnsPoolNet {
+    pub fn new(resolvers: Vec<Net>, strategy: DnsPoolStrategy) -> Self {
+        DnsPoolNet {
+            resolvers: Arc::new(resolvers),
+            strategy,
+            reject: Arc::new(Vec::new()),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    /// Ignore the answers with any address in `reject`.
+    pub fn reject(mut self, reject: Vec<IpCidr>) -> Self {
+        self.reject = Arc::new(reject);
+        self
+    }
+}
+
+fn is_rejected(reject: &[IpCidr], addrs: &[SocketAddr]) -> bool {
+    addrs
+        .iter()
+        .any(|addr| reject.iter().any(|r| r.0.contains_addr(&addr.ip().into())))
+}
+
+async fn lookup(
+    resolvers: Arc<Vec<Net>>,
+    strategy: DnsPoolStrategy,
+    reject: Arc<Vec<IpCidr>>,
+    ctx: Context,
+    addr: Address,
+) -> std::result::Result<Vec<SocketAddr>, String> {
+    let mut lookups = resolvers
+        .iter()
+        .map(|resolver| {
+            let mut ctx = ctx.clone();
+            let addr = &addr;
+            async move { resolver.lookup_host(&mut ctx, addr).await }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    let mut last = Err("no resolver".to_string());
+    while let Some(result) = lookups.next().await {
+        let result = match result {
+            // not a response of either strategy
+            Ok(addrs) if is_rejected(&reject, &addrs) => {
+                tracing::debug!("Rejected answer of {}: {:?}", addr, addrs);
+                if last.is_err() {
+                    last = Err(format!("the answer of {} is rejected", addr));
+                }
+                continue;
+            }
+            result => result.map_err(|e| e.to_string()),
+        };
+        match (strategy, &result) {
+            (DnsPoolStrategy::First, _) => return result,
+            (DnsPoolStrategy::Fastest, Ok(addrs)) if !addrs.is_empty() => return result,
+            (DnsPoolStrategy::Fastest, Ok(_)) => last = result,
+            // an empty answer is preferred to an error
+            (DnsPoolStrategy::Fastest, Err(_)) if last.is_err() => last = result,
+            (DnsPoolStrategy::Fastest, Err(_)) => {}
+        }
+    }
+    last
+}
+
+#[async_trait]
+impl rd_interface::LookupHost for DnsPoolNet {
+    async fn lookup_host(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+        // the concurrent lookups share the query of the first one, with its context
+        let shared = self
+            .in_flight
+            .lock()
+            .entry(addr.clone())
+            .or_insert_with(|| {
+                let in_flight = self.in_flight.clone();
+                let lookup = lookup(
+                    self.resolvers.clone(),
+                    self.strategy,
+                    self.reject.clone(),
+                    ctx.clone(),
+                    addr.clone(),
+                );
+                let addr = addr.clone();
+                async move {
+                    let result = lookup.await;
+                    in_flight.lock().remove(&addr);
+                    result
+                }
+                .boxed()
+                .shared()
+            })
+            .clone();
+
+        shared.await.map_err(Error::other)
+    }
+}
+
+impl INet for DnsPoolNet {
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        Some(self)
+    }
+}
+
+impl Builder<Net> for DnsPoolNet {
+    const NAME: &'static str = "dns_pool";
+    const TITLE: &'static str = "DNS Pool";
+    type Config = DnsPoolConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        if config.resolvers.is_empty() {
+            return Err(Error::other("resolvers must not be empty"));
+        }
+        Ok(DnsPoolNet::new(
+            config.resolvers.iter().map(|r| r.value_cloned()).collect(),
+            config.strategy,
+        )
+        .reject(config.reject))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use rd_interface::{IntoAddress, IntoDyn};
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::tests::{assert_net_provider, ProviderCapability};
+
+    /// Answers `answer` after `delay`, or an error if `answer` is `None`.
+    struct TestResolver {
+        delay: Duration,
+        answer: Option<&'static str>,
+        count: Arc<AtomicUsize>,
+    }
+
+    fn resolver(delay_ms: u64, answer: Option<&'static str>) -> (Net, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let net = TestResolver {
+            delay: Duration::from_millis(delay_ms),
+            answer,
+            count: count.clone(),
+        }
+        .into_dyn();
+        (net, count)
+    }
+
+    #[async_trait]
+    impl rd_interface::LookupHost for TestResolver {
+        async fn lookup_host(
+            &self,
+            _ctx: &mut Context,
+            _addr: &Address,
+        ) -> Result<Vec<SocketAddr>> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            sleep(self.delay).await;
+            match self.answer {
+                Some(answer) => Ok(vec![answer.parse().unwrap()]),
+                None => Err(Error::other("lookup failed")),
+            }
+        }
+    }
+
+    impl INet for TestResolver {
+        fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+            Some(self)
+        }
+    }
+
+    fn addr() -> Address {
+        "example.com:443".into_address().unwrap()
+    }
+
+    #[test]
+    fn test_provider() {
+        let (r, _) = resolver(0, Some("1.1.1.1:443"));
+        let net = DnsPoolNet::new(vec![r], DnsPoolStrategy::Fastest).into_dyn();
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                lookup_host: true,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fastest() {
+        let (slow, slow_count) = resolver(200, Some("1.1.1.1:443"));
+        let (fast, fast_count) = resolver(10, Some("2.2.2.2:443"));
+        let net = DnsPoolNet::new(vec![slow, fast], DnsPoolStrategy::Fastest).into_dyn();
+
+        // the concurrent lookups share a single query
+        let addr = addr();
+        let (mut ctx_a, mut ctx_b, mut ctx_c) = (Context::new(), Context::new(), Context::new());
+        let (a, b, c) = tokio::join!(
+            net.lookup_host(&mut ctx_a, &addr),
+            net.lookup_host(&mut ctx_b, &addr),
+            net.lookup_host(&mut ctx_c, &addr)
+        );
+        for result in [a, b, c] {
+            assert_eq!(result.unwrap(), vec!["2.2.2.2:443".parse().unwrap()]);
+        }
+        assert_eq!(slow_count.load(Ordering::SeqCst), 1);
+        assert_eq!(fast_count.load(Ordering::SeqCst), 1);
+
+        // the finished lookup is not cached
+        net.lookup_host(&mut Context::new(), &addr).await.unwrap();
+        assert_eq!(fast_count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fastest_skips_errors() {
+        let (slow, _) = resolver(50, Some("1.1.1.1:443"));
+        let (failing, _) = resolver(0, None);
+        let net = DnsPoolNet::new(vec![slow, failing], DnsPoolStrategy::Fastest).into_dyn();
+        assert_eq!(
+            net.lookup_host(&mut Context::new(), &addr()).await.unwrap(),
+            vec!["1.1.1.1:443".parse().unwrap()]
+        );
+
+        let (slow, _) = resolver(50, Some("1.1.1.1:443"));
+        let (failing, _) = resolver(0, None);
+        let net = DnsPoolNet::new(vec![slow, failing], DnsPoolStrategy::First).into_dyn();
+        assert!(net.lookup_host(&mut Context::new(), &addr()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reject_poisoned() {
+        let reject = || vec!["243.185.187.0/24".parse().unwrap()];
+
+        for strategy in [DnsPoolStrategy::Fastest, DnsPoolStrategy::First] {
+            // e.g. a plain DNS answered by a middlebox, and a slower DoH
+            let (poisoned, _) = resolver(0, Some("243.185.187.39:443"));
+            let (clean, _) = resolver(50, Some("1.1.1.1:443"));
+            let net = DnsPoolNet::new(vec![poisoned, clean], strategy)
+                .reject(reject())
+                .into_dyn();
+            assert_eq!(
+                net.lookup_host(&mut Context::new(), &addr()).await.unwrap(),
+                vec!["1.1.1.1:443".parse().unwrap()]
+            );
+        }
+
+        let (poisoned, _) = resolver(0, Some("243.185.187.39:443"));
+        let net = DnsPoolNet::new(vec![poisoned], DnsPoolStrategy::Fastest)
+            .reject(reject())
+            .into_dyn();
+        assert!(net.lookup_host(&mut Context::new(), &addr()).await.is_err());
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use rd_interface::{
+    async_trait, config::NetRef, constant::UDP_BUFFER_SIZE, error::map_other, prelude::*,
+    registry::Builder, Address, Context, IServer, Net, ReadBuf, Result, Server, TcpListener,
+    TcpStream, UdpSocket,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    select,
+    sync::mpsc,
+    time::timeout,
+};
+use trust_dns_proto::{
+    op::{Message, MessageType, ResponseCode},
+    rr::{RData, Record, RecordType},
+};
+
+use crate::util::{accept_with_backoff, bind_tcp_udp, ACCEPT_BACKOFF};
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn default_ttl() -> u32 {
+    60
+}
+
+/// A DNS server answers A and AAAA queries by the `resolver` net.
+#[rd_config]
+#[derive(Debug)]
+pub struct DnsServerConfig {
+    bind: Address,
+    /// net used to resolve A and AAAA queries
+    resolver: NetRef,
+    /// upstream DNS server for other query types
+    #[serde(default)]
+    upstream: Option<SocketAddr>,
+    /// net used to connect to the upstream
+    #[serde(default)]
+    net: NetRef,
+    #[serde(default)]
+    listen: NetRef,
+    /// TTL of the answers, in seconds
+    #[serde(default = "default_ttl")]
+    ttl: u32,
+}
+
+#[derive(Clone)]
+struct Handler {
+    resolver: Net,
+    upstream: Option<SocketAddr>,
+    net: Net,
+    ttl: u32,
+}
+
+impl Handler {
+    async fn handle(&self, ctx: &mut Context, packet: &[u8]) -> Result<Vec<u8>> {
+        let request = Message::from_vec(packet).map_err(map_other)?;
+
+        let response = match request.queries() {
+            [query] if matches!(query.query_type(), RecordType::A | RecordType::AAAA) => {
+                self.resolve(ctx, &request).await
+            }
+            _ => match self.upstream {
+                Some(upstream) => return self.forward(packet, upstream).await,
+                None => response_of(&request, ResponseCode::NotImp),
+            },
+        };
+
+        response.to_vec().map_err(map_other)
+    }
+    async fn resolve(&self, ctx: &mut Context, request: &Message) -> Message {
+        let query = &request.queries()[0];
+        let domain = query.name().to_utf8();
+        let domain = domain.trim_end_matches('.');
+
+        let addrs = match self
+            .resolver
+            .lookup_host(ctx, &Address::Domain(domain.to_string(), 0))
+            .await
+        {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                tracing::warn!("Failed to resolve {}: {:?}", domain, e);
+                return response_of(request, ResponseCode::ServFail);
+            }
+        };
+
+        let mut response = response_of(request, ResponseCode::NoError);
+        for addr in addrs {
+            let rdata = match (query.query_type(), addr.ip()) {
+                (RecordType::A, IpAddr::V4(ip)) => RData::A(ip),
+                (RecordType::AAAA, IpAddr::V6(ip)) => RData::AAAA(ip),
+                _ => continue,
+            };
+            response.add_answer(Record::from_rdata(query.name().clone(), self.ttl, rdata));
+        }
+
+        response
+    }
+    async fn forward(&self, packet: &[u8], upstream: SocketAddr) -> Result<Vec<u8>> {
+        let mut udp = self
+            .net
+            .udp_bind(&mut Context::new(), &Address::any_addr_port(&upstream))
+            .await?;
+        udp.send_to(packet, &upstream.into()).await?;
+
+        let mut buf = vec![0; UDP_BUFFER_SIZE];
+        let mut buf = ReadBuf::new(&mut buf);
+        timeout(UPSTREAM_TIMEOUT, udp.recv_from(&mut buf)).await??;
+
+        Ok(buf.filled().to_vec())
+    }
+}
+
+fn response_of(request: &Message, code: ResponseCode) -> Message {
+    let mut response = Message::new();
+    response
+        .set_id(request.id())
+        .set_message_type(MessageType::Response)
+        .set_op_code(request.op_code())
+        .set_recursion_desired(request.recursion_desired())
+        .set_recursion_available(true)
+        .set_response_code(code)
+        .add_queries(request.queries().iter().cloned());
+    response
+}
+
+pub struct DnsServer {
+    bind: Address,
+    listen: Net,
+    handler: Handler,
+}
+
+#[async_trait]
+impl IServer for DnsServer {
+    async fn start(&self) -> Result<()> {
+        let (listener, udp) = bind_tcp_udp(&self.listen, &mut Context::new(), &self.bind).await?;
+
+        select! {
+            r = self.serve_tcp(listener) => r,
+            r = self.serve_udp(udp) => r,
+        }
+    }
+}
+
+impl DnsServer {
+    fn new(cfg: DnsServerConfig) -> DnsServer {
+        DnsServer {
+            bind: cfg.bind,
+            listen: cfg.listen.value_cloned(),
+            handler: Handler {
+                resolver: cfg.resolver.value_cloned(),
+                upstream: cfg.upstream,
+                net: cfg.net.value_cloned(),
+                ttl: cfg.ttl,
+            },
+        }
+    }
+    async fn serve_udp(&self, mut udp: UdpSocket) -> Result<()> {
+        let (tx, mut rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(64);
+        let buf = &mut vec![0; UDP_BUFFER_SIZE];
+
+        loop {
+            let mut buf = ReadBuf::new(buf);
+            let reply = select! {
+                r = udp.recv_from(&mut buf) => {
+                    let addr = r?;
+                    let packet = buf.filled().to_vec();
+                    let handler = self.handler.clone();
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        let ctx = &mut Context::from_socketaddr(addr);
+                        match handler.handle(ctx, &packet).await {
+                            Ok(response) => {
+                                let _ = tx.send((response, addr)).await;
+                            }
+                            Err(e) => tracing::error!("Error when handle dns query: {:?}", e),
+                        }
+                    });
+                    continue;
+                }
+                Some(reply) = rx.recv() => reply,
+            };
+
+            let (response, addr) = reply;
+            if let Err(e) = udp.send_to(&response, &addr.into()).await {
+                tracing::error!("Error when send dns response to {}: {:?}", addr, e);
+            }
+        }
+    }
+    async fn serve_tcp(&self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (socket, addr) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
+            let handler = self.handler.clone();
+            let _ = tokio::spawn(async move {
+                if let Err(e) = Self::serve_connection(handler, socket, addr).await {
+                    tracing::error!("Error when serve_connection: {:?}", e);
+                }
+            });
+        }
+    }
+    async fn serve_connection(
+        handler: Handler,
+        mut socket: TcpStream,
+        addr: SocketAddr,
+    ) -> Result<()> {
+        let ctx = &mut Context::from_socketaddr(addr);
+        loop {
+            let len = match socket.read_u16().await {
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+            let mut packet = vec![0; len as usize];
+            socket.read_exact(&mut packet).await?;
+
+            let response = handler.handle(ctx, &packet).await?;
+            socket.write_u16(response.len() as u16).await?;
+            socket.write_all(&response).await?;
+        }
+    }
+}
+
+impl Builder<Server> for DnsServer {
+    const NAME: &'static str = "dns";
+    const TITLE: &'static str = "DNS Server";
+    type Config = DnsServerConfig;
+    type Item = Self;
+
+    fn build(cfg: Self::Config) -> Result<Self> {
+        Ok(DnsServer::new(cfg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use rd_interface::{IntoAddress, IntoDyn};
+    use tokio::time::sleep;
+    use trust_dns_proto::{
+        op::{OpCode, Query},
+        rr::Name,
+    };
+
+    use super::*;
+    use crate::tests::TestNet;
+
+    #[tokio::test]
+    async fn test_dns_server() {
+        let net = TestNet::new().into_dyn();
+
+        let server = DnsServer::build(DnsServerConfig {
+            bind: "127.0.0.1:5353".into_address().unwrap(),
+            resolver: NetRef::new_with_value("test".into(), net.clone()),
+            upstream: None,
+            net: NetRef::new_with_value("test".into(), net.clone()),
+            listen: NetRef::new_with_value("test".into(), net.clone()),
+            ttl: 60,
+        })
+        .unwrap();
+        tokio::spawn(async move { server.start().await.unwrap() });
+
+        sleep(Duration::from_millis(1)).await;
+
+        let name = Name::from_ascii("localhost.").unwrap();
+        let mut request = Message::new();
+        request
+            .set_id(1234)
+            .set_op_code(OpCode::Query)
+            .set_recursion_desired(true)
+            .add_query(Query::query(name.clone(), RecordType::A));
+
+        let mut udp = net
+            .udp_bind(&mut Context::new(), &"127.0.0.1:0".into_address().unwrap())
+            .await
+            .unwrap();
+        udp.send_to(
+            &request.to_vec().unwrap(),
+            &"127.0.0.1:5353".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+
+        let mut buf = vec![0; UDP_BUFFER_SIZE];
+        let mut buf = ReadBuf::new(&mut buf);
+        udp.recv_from(&mut buf).await.unwrap();
+
+        let response = Message::from_vec(buf.filled()).unwrap();
+        assert_eq!(response.id(), 1234);
+        assert_eq!(response.message_type(), MessageType::Response);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(
+            response.answers(),
+            &[Record::from_rdata(
+                name,
+                60,
+                RData::A(Ipv4Addr::new(127, 0, 0, 1))
+            )]
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use std::{future::pending, io, net::SocketAddr, task::Poll};
+
+use rd_interface::{
+    async_trait, config::EmptyConfig, registry::Builder, Address, INet, ITcpListener, ITcpStream,
+    IUdpSocket, IntoDyn, Net, ReadBuf, Result, TcpBind, TcpConnect, UdpBind, NOT_IMPLEMENTED,
+};
+
+/// Accepts everything and discards it. Writes succeed, reads return EOF,
+/// listeners never accept and UDP sockets never receive.
+/// Use `blackhole` to refuse connections instead.
+pub struct DropNet;
+
+impl Builder<Net> for DropNet {
+    const NAME: &'static str = "drop";
+    const TITLE: &'static str = "Drop";
+    type Config = EmptyConfig;
+    type Item = DropNet;
+
+    fn build(_config: Self::Config) -> Result<Self::Item> {
+        Ok(DropNet)
+    }
+}
+
+struct DropItem;
+
+#[async_trait]
+impl ITcpStream for DropItem {
+    fn poll_read(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+        _buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(&mut self, _cx: &mut std::task::Context<'_>) -> Poll<Result<(), io::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    async fn peer_addr(&self) -> Result<std::net::SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+
+    async fn local_addr(&self) -> Result<std::net::SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+}
+
+#[async_trait]
+impl ITcpListener for DropItem {
+    async fn accept(&self) -> Result<(rd_interface::TcpStream, std::net::SocketAddr)> {
+        pending().await
+    }
+
+    async fn local_addr(&self) -> Result<std::net::SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+}
+
+#[async_trait]
+impl IUdpSocket for DropItem {
+    async fn local_addr(&self) -> Result<std::net::SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+
+    fn poll_recv_from(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+        _buf: &mut ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        Poll::Pending
+    }
+
+    fn poll_send_to(
+        &mut self,
+        _cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+        _target: &Address,
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+#[async_trait]
+impl TcpConnect for DropNet {
+    async fn tcp_connect(
+        &self,
+        _ctx: &mut rd_interface::Context,
+        _addr: &Address,
+    ) -> Result<rd_interface::TcpStream> {
+        Ok(DropItem.into_dyn())
+    }
+}
+
+#[async_trait]
+impl TcpBind for DropNet {
+    async fn tcp_bind(
+        &self,
+        _ctx: &mut rd_interface::Context,
+        _addr: &Address,
+    ) -> Result<rd_interface::TcpListener> {
+        Ok(DropItem.into_dyn())
+    }
+}
+
+#[async_trait]
+impl UdpBind for DropNet {
+    async fn udp_bind(
+        &self,
+        _ctx: &mut rd_interface::Context,
+        _addr: &Address,
+    ) -> Result<rd_interface::UdpSocket> {
+        Ok(DropItem.into_dyn())
+    }
+}
+
+impl INet for DropNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn TcpConnect> {
+        Some(self)
+    }
+    fn provide_tcp_bind(&self) -> Option<&dyn TcpBind> {
+        Some(self)
+    }
+    fn provide_udp_bind(&self) -> Option<&dyn UdpBind> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::{assert_net_provider, ProviderCapability};
+    use rd_interface::{Context, IntoAddress, IntoDyn};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn test_provider() {
+        let net = DropNet.into_dyn();
+
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discard() {
+        let net = DropNet.into_dyn();
+        let mut tcp = net
+            .tcp_connect(
+                &mut Context::new(),
+                &"127.0.0.1:1234".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+
+        tcp.write_all(b"discarded").await.unwrap();
+        let mut buf = Vec::new();
+        assert_eq!(tcp.read_to_end(&mut buf).await.unwrap(), 0);
+    }
+}
@@ -1,28 +1,36 @@
+use std::future::pending;
+
 use rd_interface::{
-    async_trait, config::NetRef, prelude::*, registry::Builder, Address, Context, IServer, Net,
-    Result, Server, TcpListener, TcpStream,
+    async_trait, config::NetRef, constant::UDP_BUFFER_SIZE, prelude::*, registry::Builder, Address,
+    Context, IServer, Net, ReadBuf, Result, Server, TcpListener, TcpStream,
 };
-use tokio::io;
+use tokio::{io, select};
 use tracing::instrument;
 
-/// A echo server.
+use crate::util::{accept_with_backoff, ACCEPT_BACKOFF};
+
+/// A echo server. Useful for smoke-testing a proxy chain.
 #[rd_config]
 #[derive(Debug)]
 pub struct EchoServerConfig {
     bind: Address,
     #[serde(default)]
     listen: NetRef,
+    /// echo UDP packets on the same address
+    #[serde(default)]
+    udp: bool,
 }
 
 pub struct EchoServer {
     listen: Net,
     bind: Address,
+    udp: bool,
 }
 
 impl EchoServer {
-    fn new(EchoServerConfig { bind, listen }: EchoServerConfig) -> EchoServer {
+    fn new(EchoServerConfig { bind, listen, udp }: EchoServerConfig) -> EchoServer {
         let listen = listen.value_cloned();
-        EchoServer { listen, bind }
+        EchoServer { listen, bind, udp }
     }
 }
 #[async_trait]
@@ -32,7 +40,11 @@ impl IServer for EchoServer {
             .listen
             .tcp_bind(&mut Context::new(), &self.bind)
             .await?;
-        self.serve_listener(listener).await
+
+        select! {
+            r = self.serve_listener(listener) => r,
+            r = self.serve_udp() => r,
+        }
     }
 }
 
@@ -45,7 +57,7 @@ impl EchoServer {
     }
     pub async fn serve_listener(&self, listener: TcpListener) -> Result<()> {
         loop {
-            let (socket, _) = listener.accept().await?;
+            let (socket, _) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
             let _ = tokio::spawn(async move {
                 if let Err(e) = Self::serve_connection(socket).await {
                     tracing::error!("Error when serve_connection: {:?}", e);
@@ -53,10 +65,29 @@ impl EchoServer {
             });
         }
     }
+    async fn serve_udp(&self) -> Result<()> {
+        if !self.udp {
+            return pending().await;
+        }
+
+        let mut udp = self
+            .listen
+            .udp_bind(&mut Context::new(), &self.bind)
+            .await?;
+        let buf = &mut vec![0; UDP_BUFFER_SIZE];
+        loop {
+            let mut buf = ReadBuf::new(buf);
+            let addr = udp.recv_from(&mut buf).await?;
+            if let Err(e) = udp.send_to(buf.filled(), &addr.into()).await {
+                tracing::error!("Error when echo udp packet to {}: {:?}", addr, e);
+            }
+        }
+    }
 }
 
 impl Builder<Server> for EchoServer {
     const NAME: &'static str = "echo";
+    const TITLE: &'static str = "Echo Server";
     type Config = EchoServerConfig;
     type Item = Self;
 
@@ -67,13 +98,17 @@ impl Builder<Server> for EchoServer {
 
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
+    use std::{
+        net::SocketAddr,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
 
-    use rd_interface::{IntoAddress, IntoDyn};
+    use rd_interface::{ITcpListener, IntoAddress, IntoDyn};
     use tokio::time::sleep;
 
     use super::*;
-    use crate::tests::{assert_echo, TestNet};
+    use crate::tests::{assert_echo, assert_echo_udp, TestNet};
 
     #[tokio::test]
     async fn test_echo_server() {
@@ -82,6 +117,7 @@ mod tests {
         let server = EchoServer {
             listen: net.clone(),
             bind: "127.0.0.1:1234".into_address().unwrap(),
+            udp: false,
         };
         tokio::spawn(async move { server.start().await.unwrap() });
 
@@ -89,4 +125,73 @@ mod tests {
 
         assert_echo(&net, "127.0.0.1:1234").await;
     }
+
+    #[tokio::test]
+    async fn test_echo_server_from_config() {
+        let net = TestNet::new().into_dyn();
+
+        let server = EchoServer::build(EchoServerConfig {
+            bind: "127.0.0.1:1235".into_address().unwrap(),
+            listen: NetRef::new_with_value("test".into(), net.clone()),
+            udp: true,
+        })
+        .unwrap();
+        tokio::spawn(async move { server.start().await.unwrap() });
+
+        sleep(Duration::from_millis(1)).await;
+
+        assert_echo(&net, "127.0.0.1:1235").await;
+        assert_echo_udp(&net, "127.0.0.1:1235").await;
+    }
+    /// Fails the first `errors` accepts, as if the connections were aborted.
+    struct FlakyListener {
+        listener: TcpListener,
+        errors: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl ITcpListener for FlakyListener {
+        async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+            if self
+                .errors
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                return Err(std::io::Error::from(std::io::ErrorKind::ConnectionAborted).into());
+            }
+            self.listener.accept().await
+        }
+
+        async fn local_addr(&self) -> Result<SocketAddr> {
+            self.listener.local_addr().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_echo_server_accept_error() {
+        let net = TestNet::new().into_dyn();
+        let listener = net
+            .tcp_bind(
+                &mut Context::new(),
+                &"127.0.0.1:1236".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        let listener = FlakyListener {
+            listener,
+            errors: AtomicUsize::new(3),
+        };
+
+        let server = EchoServer {
+            listen: net.clone(),
+            bind: "127.0.0.1:1236".into_address().unwrap(),
+            udp: false,
+        };
+        let task = tokio::spawn(async move { server.serve_listener(listener.into_dyn()).await });
+
+        sleep(Duration::from_millis(1)).await;
+
+        assert_echo(&net, "127.0.0.1:1236").await;
+        assert!(!task.is_finished());
+    }
 }
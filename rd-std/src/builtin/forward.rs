@@ -8,8 +8,9 @@ use std::{
 
 use crate::{
     util::{
+        accept_with_backoff,
         forward_udp::{forward_udp, RawUdpSource, UdpEndpoint},
-        PollFuture,
+        PollFuture, ACCEPT_BACKOFF,
     },
     ContextExt,
 };
@@ -111,7 +112,7 @@ impl ForwardServer {
             .tcp_bind(&mut Context::new(), &self.bind)
             .await?;
         loop {
-            let (socket, addr) = listener.accept().await?;
+            let (socket, addr) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
             let net = self.net.clone();
             let target = self.target.clone();
             let _ = tokio::spawn(async move {
@@ -144,6 +145,7 @@ impl ForwardServer {
 
 impl Builder<Server> for ForwardServer {
     const NAME: &'static str = "forward";
+    const TITLE: &'static str = "Port Forward";
     type Config = ForwardServerConfig;
     type Item = Self;
 
@@ -156,7 +158,7 @@ async fn resolve_target(resolve_net: Net, target: Address) -> Result<SocketAddr,
     let addrs = target
         .resolve(move |d, p| async move {
             resolve_net
-                .lookup_host(&Address::Domain(d, p))
+                .lookup_host(&mut Context::new(), &Address::Domain(d, p))
                 .map_err(|e| e.to_io_err())
                 .await
         })
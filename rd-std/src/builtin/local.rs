@@ -1,7 +1,9 @@
 use std::{
+    collections::HashMap,
     io,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     pin::Pin,
+    sync::Arc,
     task::{self, Poll},
     time::Duration,
 };
@@ -10,24 +12,178 @@ use futures::{ready, stream::FuturesUnordered, Future, FutureExt, StreamExt};
 use itertools::Itertools;
 use parking_lot::Mutex;
 use rd_interface::{
-    async_trait, config::NetRef, impl_async_read_write, prelude::*, registry::Builder, Address,
-    INet, IntoDyn, Net, ReadBuf, Result, TcpListener, TcpStream, UdpSocket,
+    async_trait,
+    config::NetRef,
+    context::common_field::{
+        BulkTransfer, ConnectAttempt, ConnectTrace, ResolvedAddrs, ResolvedSocketAddr,
+    },
+    error::map_other,
+    impl_async_read_write,
+    prelude::*,
+    registry::Builder,
+    Address, INet, IntoDyn, Net, ReadBuf, Result, TcpListener, TcpStream, UdpSocket,
 };
 use socket2::{Domain, SockRef, Socket, Type};
 use tokio::{
     net,
-    time::{sleep, timeout},
+    sync::Semaphore,
+    time::{sleep, timeout, Instant},
 };
 use tracing::instrument;
 
+use self::bind_pool::BindPool;
+pub use self::bind_pool::BindStrategy;
+pub use self::fd_budget::FdBudgetConfig;
+use self::fd_budget::{budgeted, FdBudget, FdPermit};
+pub use self::interface::rebind;
+use self::interface::Interface;
+use crate::{
+    rule::config::IpCidr,
+    util::{drop_blocked, resolve_mapped_socket_addr},
+};
+
+mod bind_pool;
+mod fd_budget;
+mod interface;
+
+/// A source port, or an inclusive range of them, e.g. `{ start: 40000, end: 40100 }`.
+#[rd_config]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum SourcePort {
+    Port(u16),
+    Range { start: u16, end: u16 },
+}
+
+impl SourcePort {
+    fn start_end(&self) -> (u16, u16) {
+        match *self {
+            SourcePort::Port(port) => (port, port),
+            SourcePort::Range { start, end } => (start, end),
+        }
+    }
+}
+
+/// An address, or the name of an interface to bind to its address, e.g. `en0`.
+#[rd_config]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum BindAddr {
+    Ip(IpAddr),
+    Interface(String),
+}
+
+impl BindAddr {
+    fn ip(&self) -> Option<IpAddr> {
+        match *self {
+            BindAddr::Ip(ip) => Some(ip),
+            BindAddr::Interface(_) => None,
+        }
+    }
+}
+
+/// TCP keepalive, the idle time in seconds, or the idle time, the interval between
+/// the probes in seconds and the number of probes before the connection is dropped.
+#[rd_config]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum TcpKeepalive {
+    Idle(f64),
+    Probes {
+        /// default is 600s. 0 means disable.
+        #[serde(default)]
+        idle: Option<f64>,
+        /// by default it's decided by the system.
+        #[serde(default)]
+        interval: Option<f64>,
+        /// by default it's decided by the system.
+        #[serde(default)]
+        count: Option<u32>,
+    },
+}
+
+impl TcpKeepalive {
+    fn check(self) -> Result<()> {
+        let (idle, interval, count) = match self {
+            TcpKeepalive::Idle(idle) => (Some(idle), None, None),
+            TcpKeepalive::Probes {
+                idle,
+                interval,
+                count,
+            } => (idle, interval, count),
+        };
+        if let Some(idle) = idle {
+            if !idle.is_finite() || idle < 0.0 {
+                return Err(rd_interface::Error::other(format!(
+                    "tcp_keepalive idle should be a non-negative number, got {}",
+                    idle
+                )));
+            }
+        }
+        if let Some(interval) = interval {
+            if !interval.is_finite() || interval <= 0.0 {
+                return Err(rd_interface::Error::other(format!(
+                    "tcp_keepalive interval should be greater than 0, got {}",
+                    interval
+                )));
+            }
+        }
+        if count == Some(0) {
+            return Err(rd_interface::Error::other(
+                "tcp_keepalive count should be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+    /// `None` if it's disabled.
+    #[cfg_attr(
+        not(any(target_os = "linux", target_os = "macos")),
+        allow(unused_variables, unused_mut)
+    )]
+    fn to_socket2(self) -> Option<socket2::TcpKeepalive> {
+        let (idle, interval, count) = match self {
+            TcpKeepalive::Idle(idle) => (Some(idle), None, None),
+            TcpKeepalive::Probes {
+                idle,
+                interval,
+                count,
+            } => (idle, interval, count),
+        };
+        let idle = idle.unwrap_or(600.0);
+        if idle <= 0.0 {
+            return None;
+        }
+
+        let mut keepalive = socket2::TcpKeepalive::new().with_time(Duration::from_secs_f64(idle));
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            if let Some(interval) = interval {
+                keepalive = keepalive.with_interval(Duration::from_secs_f64(interval));
+            }
+            if let Some(count) = count {
+                keepalive = keepalive.with_retries(count);
+            }
+        }
+        Some(keepalive)
+    }
+}
+
 /// A local network.
 #[rd_config]
 #[derive(Debug, Clone, Default)]
 pub struct LocalNetConfig {
-    /// set ttl
+    /// deprecated, set both `ip_ttl` and `ip_hop_limit`
     #[serde(default)]
     pub ttl: Option<u32>,
 
+    /// set TTL of IPv4 packets
+    #[serde(default)]
+    pub ip_ttl: Option<u32>,
+
+    /// set hop limit of IPv6 packets
+    #[serde(default)]
+    pub ip_hop_limit: Option<u32>,
+
     /// set nodelay. default is true
     #[serde(default)]
     pub nodelay: Option<bool>,
@@ -35,19 +191,88 @@ pub struct LocalNetConfig {
     /// set SO_MARK on linux
     pub mark: Option<u32>,
 
+    /// set DSCP value (0-63) of outgoing packets via IP_TOS or IPV6_TCLASS
+    #[serde(default)]
+    pub dscp: Option<u8>,
+
+    /// set TCP_MAXSEG (536-1460) of TCP sockets on linux and macos.
+    /// useful when the path MTU is reduced, e.g. in a tunnel.
+    #[serde(default)]
+    pub mss: Option<u32>,
+
     /// bind to device
     pub bind_device: Option<String>,
 
-    /// bind to address
-    pub bind_addr: Option<IpAddr>,
+    /// bind to address, or to the address of an interface by its name. unlike
+    /// `bind_device`, it needs no privilege. the address of the interface is read
+    /// again when the network changes.
+    pub bind_addr: Option<BindAddr>,
+
+    /// bind outbound sockets to one of these addresses, chosen by `bind_strategy`,
+    /// to spread the connections across the source addresses. they must be
+    /// assigned to the host unless `freebind` is set.
+    #[serde(default)]
+    pub bind_addrs: Vec<IpAddr>,
+
+    /// how the address is chosen from `bind_addrs`: `round_robin`, `random`, or
+    /// `per_destination` to keep the same one for a destination IP.
+    /// default is `round_robin`.
+    #[serde(default)]
+    pub bind_strategy: BindStrategy,
+
+    /// set IP_FREEBIND on linux, so `bind_addr` can be an address not assigned
+    /// to the host, e.g. for source NAT.
+    #[serde(default)]
+    pub freebind: bool,
+
+    /// set IP_BIND_ADDRESS_NO_PORT on linux, the port of `bind_addr` is allocated
+    /// at TCP connect instead of at bind. the connections to different destinations
+    /// can share a port, so the ephemeral ports are not exhausted.
+    #[serde(default)]
+    pub bind_address_no_port: bool,
+
+    /// bind outbound sockets to this source port, or the first free port of the range,
+    /// for the protocols or firewalls keyed on the source port.
+    /// the address is `bind_addr` if set.
+    #[serde(default)]
+    pub source_port: Option<SourcePort>,
 
     /// timeout of TCP connect, in seconds.
     pub connect_timeout: Option<u64>,
 
-    /// enable keepalive on TCP socket, in seconds.
+    /// maximum number of TCP connects in progress at the same time, including
+    /// resolving the domain. the others wait for a slot.
+    /// established connections are not counted.
+    #[serde(default)]
+    pub max_concurrent_connect: Option<usize>,
+
+    /// queue the new sockets instead of failing with "too many open files" when this
+    /// many are open. each connection, listener and UDP socket takes one.
+    /// the budget is shared by all the local nets. `true` is 3/4 of the open file limit.
+    #[serde(default)]
+    pub fd_budget: FdBudgetConfig,
+
+    /// record the resolved addresses, the attempts of happy eyeballs and their timings
+    /// of each TCP connect, in the debug log and the `connect_trace` of the connection.
+    #[serde(default)]
+    pub trace_connect: bool,
+
+    /// enable keepalive on TCP socket, the idle time in seconds, or
+    /// `{ idle, interval, count }` to also tune the probes on linux and macos.
     /// default is 600s. 0 means disable.
     #[serde(default)]
-    pub tcp_keepalive: Option<f64>,
+    pub tcp_keepalive: Option<TcpKeepalive>,
+
+    /// connect UDP socket to the first peer if it is an IP address.
+    /// It falls back to unconnected socket once sending to another peer.
+    #[serde(default)]
+    pub udp_connect: bool,
+
+    /// send the first datagrams to a domain to all of its resolved addresses, and
+    /// stick to the first one replying, e.g. for QUIC to a domain of several IPs.
+    /// by default only the first address is used.
+    #[serde(default)]
+    pub udp_race: bool,
 
     /// change the system receive buffer size of the socket.
     /// by default it remains unchanged.
@@ -55,71 +280,200 @@ pub struct LocalNetConfig {
     /// change the system send buffer size of the socket.
     /// by default it remains unchanged.
     pub send_buffer_size: Option<usize>,
+    /// only apply `recv_buffer_size` and `send_buffer_size` to connections hinted as
+    /// bulk transfers, e.g. by a rule with `bulk_transfer: true`, or an `alias` net with it in
+    /// its `context`. others keep the kernel autotuning.
+    #[serde(default)]
+    pub auto_buffer: bool,
 
     /// Change the default system DNS resolver to custom one.
     #[serde(default)]
     pub lookup_host: Option<NetRef>,
+
+    /// drop the resolved addresses in these ranges, e.g. `127.0.0.0/8` and
+    /// `169.254.0.0/16` against DNS rebinding. connecting to a domain fails if all of
+    /// its addresses are dropped. the IP destinations are not checked.
+    #[serde(default)]
+    pub block_resolved: Vec<IpCidr>,
 }
 
 type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
 enum UdpState {
     Idle,
-    LookupHost(Mutex<BoxFuture<io::Result<Vec<SocketAddr>>>>),
+    /// The domain is raced if it's given.
+    LookupHost(
+        Mutex<BoxFuture<io::Result<Vec<SocketAddr>>>>,
+        Option<(String, u16)>,
+    ),
     Sending(SocketAddr),
+    /// Sending to each of `addrs`, `error` is the first error of them.
+    Racing {
+        addrs: Vec<SocketAddr>,
+        next: usize,
+        sent: bool,
+        error: Option<io::Error>,
+    },
+}
+
+impl UdpState {
+    fn racing(addrs: Vec<SocketAddr>) -> UdpState {
+        UdpState::Racing {
+            addrs,
+            next: 0,
+            sent: false,
+            error: None,
+        }
+    }
+}
+
+/// The addresses of a domain sent to by a UDP socket with `udp_race`.
+enum UdpRace {
+    /// Waiting for the first reply of any of them.
+    Racing(Vec<SocketAddr>),
+    /// Sticking to the first one replying.
+    Won(SocketAddr),
+}
+
+/// Maximum domains raced by a UDP socket, they are forgotten when it's exceeded.
+const UDP_RACE_DOMAINS: usize = 64;
+
+#[derive(Default)]
+struct UdpRaces {
+    domains: HashMap<(String, u16), UdpRace>,
+    /// The number of domains in `UdpRace::Racing`.
+    racing: usize,
+}
+
+impl UdpRaces {
+    fn get(&self, domain: &str, port: u16) -> Option<&UdpRace> {
+        self.domains.get(&(domain.to_string(), port))
+    }
+    fn start(&mut self, key: (String, u16), addrs: Vec<SocketAddr>) {
+        if self.domains.len() >= UDP_RACE_DOMAINS {
+            self.domains.clear();
+            self.racing = 0;
+        }
+        if let Some(UdpRace::Racing(_)) = self.domains.insert(key, UdpRace::Racing(addrs)) {
+            return;
+        }
+        self.racing += 1;
+    }
+    /// `from` wins the races it's in.
+    fn reply(&mut self, from: SocketAddr) {
+        if self.racing == 0 {
+            return;
+        }
+        let from = resolve_mapped_socket_addr(from);
+        for ((domain, _), race) in self.domains.iter_mut() {
+            if matches!(race, UdpRace::Racing(addrs) if addrs.contains(&from)) {
+                tracing::debug!("UDP race of {} is won by {}", domain, from);
+                *race = UdpRace::Won(from);
+                self.racing -= 1;
+            }
+        }
+    }
 }
 
 #[derive(Default)]
 pub struct LocalNet {
     cfg: LocalNetConfig,
     resolver: Resolver,
+    connect_limit: Option<Semaphore>,
+    interface: Option<Interface>,
+    fd_budget: Option<FdBudget>,
+    bind_pool: Option<BindPool>,
 }
 pub struct CompatTcp(pub(crate) net::TcpStream);
-pub struct Listener(net::TcpListener, LocalNetConfig);
+pub struct Listener(net::TcpListener, LocalNetConfig, Option<FdBudget>);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UdpPeer {
+    /// Connect to the first peer.
+    Auto,
+    Connected(SocketAddr),
+    Unconnected,
+}
 pub struct Udp {
     inner: net::UdpSocket,
     state: UdpState,
+    peer: UdpPeer,
     resolver: Resolver,
+    /// the context of `udp_bind`, the domains sent to are resolved with it
+    ctx: rd_interface::Context,
+    races: Option<UdpRaces>,
 }
 
 #[derive(Clone, Default)]
 struct Resolver {
     net: Option<Net>,
+    block: Arc<Vec<IpCidr>>,
 }
 
 impl LocalNetConfig {
+    #[cfg_attr(not(unix), allow(unused_variables))]
     fn set_socket(
         &self,
         socket: SockRef,
-        _addr: SocketAddr,
+        addr: SocketAddr,
         is_tcp: bool,
         is_accept: bool,
+        is_bulk: bool,
     ) -> Result<()> {
         socket.set_nonblocking(true)?;
 
-        if let Some(size) = self.recv_buffer_size {
-            socket.set_recv_buffer_size(size)?;
+        if !self.auto_buffer || is_bulk {
+            if let Some(size) = self.recv_buffer_size {
+                socket.set_recv_buffer_size(size)?;
+            }
+            if let Some(size) = self.send_buffer_size {
+                socket.set_send_buffer_size(size)?;
+            }
         }
-        if let Some(size) = self.send_buffer_size {
-            socket.set_send_buffer_size(size)?;
+
+        #[cfg(target_os = "linux")]
+        if self.freebind {
+            match addr {
+                SocketAddr::V4(_) => socket.set_freebind(true)?,
+                SocketAddr::V6(_) => socket.set_freebind_ipv6(true)?,
+            }
         }
 
-        if let (Some(local_addr), false) = (self.bind_addr, is_accept) {
-            socket.bind(&SocketAddr::new(local_addr, 0).into())?;
+        #[cfg(target_os = "linux")]
+        if self.bind_address_no_port && is_tcp && !is_accept {
+            set_bind_address_no_port(&socket)?;
         }
 
-        if let Some(ttl) = self.ttl {
-            socket.set_ttl(ttl)?;
+        // bound with the port later
+        if let (Some(BindAddr::Ip(local_addr)), false, None) =
+            (&self.bind_addr, is_accept, self.source_port)
+        {
+            socket.bind(&SocketAddr::new(*local_addr, 0).into())?;
+        }
+
+        match addr {
+            SocketAddr::V4(_) => {
+                if let Some(ttl) = self.ip_ttl.or(self.ttl) {
+                    socket.set_ttl(ttl)?;
+                }
+            }
+            SocketAddr::V6(_) => {
+                if let Some(hop_limit) = self.ip_hop_limit.or(self.ttl) {
+                    socket.set_unicast_hops_v6(hop_limit)?;
+                }
+            }
         }
 
         if is_tcp {
             socket.set_nodelay(self.nodelay.unwrap_or(true))?;
 
-            let keepalive_duration = self.tcp_keepalive.unwrap_or(600.0);
-            if keepalive_duration > 0.0 {
-                let keepalive = socket2::TcpKeepalive::new()
-                    .with_time(Duration::from_secs_f64(keepalive_duration));
+            let keepalive = self.tcp_keepalive.unwrap_or(TcpKeepalive::Idle(600.0));
+            if let Some(keepalive) = keepalive.to_socket2() {
                 socket.set_tcp_keepalive(&keepalive)?;
             }
+
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            if let Some(mss) = self.mss {
+                socket.set_mss(mss)?;
+            }
         }
 
         #[cfg(target_os = "linux")]
@@ -127,6 +481,11 @@ impl LocalNetConfig {
             socket.set_mark(mark)?;
         }
 
+        #[cfg(unix)]
+        if let Some(dscp) = self.dscp {
+            set_dscp(&socket, addr, dscp)?;
+        }
+
         #[cfg(target_os = "linux")]
         if let (Some(device), false) = (&self.bind_device, is_accept) {
             socket.bind_device(Some(device.as_bytes()))?;
@@ -150,34 +509,264 @@ impl LocalNetConfig {
     }
 }
 
+#[cfg(unix)]
+fn set_dscp(socket: &SockRef, addr: SocketAddr, dscp: u8) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let tos = (dscp << 2) as u32;
+    match addr {
+        SocketAddr::V4(_) => socket.set_tos(tos),
+        SocketAddr::V6(_) => {
+            let tclass = tos as libc::c_int;
+            let ret = unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::IPPROTO_IPV6,
+                    libc::IPV6_TCLASS,
+                    &tclass as *const _ as *const libc::c_void,
+                    std::mem::size_of_val(&tclass) as libc::socklen_t,
+                )
+            };
+            if ret == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_bind_address_no_port(socket: &SockRef) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let on: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_BIND_ADDRESS_NO_PORT,
+            &on as *const _ as *const libc::c_void,
+            std::mem::size_of_val(&on) as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Queue ICMP errors even if the UDP socket is not connected.
+#[cfg(target_os = "linux")]
+fn set_recv_err(socket: &SockRef, addr: SocketAddr) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let opts: &[_] = match addr {
+        SocketAddr::V4(_) => &[(libc::IPPROTO_IP, libc::IP_RECVERR)],
+        // IPv4-mapped peers are reported by IP_RECVERR
+        SocketAddr::V6(_) => &[
+            (libc::IPPROTO_IP, libc::IP_RECVERR),
+            (libc::IPPROTO_IPV6, libc::IPV6_RECVERR),
+        ],
+    };
+    let on: libc::c_int = 1;
+    for (level, name) in opts {
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                *level,
+                *name,
+                &on as *const _ as *const libc::c_void,
+                std::mem::size_of_val(&on) as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Drain the error queue, returns `ConnectionRefused` if there is a port unreachable,
+/// otherwise the last error.
+#[cfg(target_os = "linux")]
+fn take_recv_err(socket: &net::UdpSocket) -> Option<io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut result: Option<io::Error> = None;
+    loop {
+        let mut control = [0u64; 64];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+
+        let ret = unsafe {
+            libc::recvmsg(
+                socket.as_raw_fd(),
+                &mut msg,
+                libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT,
+            )
+        };
+        if ret == -1 {
+            return result;
+        }
+
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let (level, ty) = unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type) };
+            if (level, ty) == (libc::SOL_IP, libc::IP_RECVERR)
+                || (level, ty) == (libc::SOL_IPV6, libc::IPV6_RECVERR)
+            {
+                let err = unsafe {
+                    std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err)
+                };
+                let err = io::Error::from_raw_os_error(err.ee_errno as i32);
+                if !matches!(&result, Some(e) if e.kind() == io::ErrorKind::ConnectionRefused) {
+                    result = Some(err);
+                }
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+    }
+}
+
+/// Binds the socket to the first free port of `source_port`, the ports in use are skipped.
+fn bind_source_port(socket: &Socket, ip: IpAddr, source_port: SourcePort) -> io::Result<()> {
+    let (start, end) = source_port.start_end();
+    let mut last_err = None;
+
+    for port in start..=end {
+        match socket.bind(&SocketAddr::new(ip, port).into()) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => last_err = Some(e),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "source_port range is empty")
+    }))
+}
+
+fn is_bulk(ctx: &rd_interface::Context) -> Result<bool> {
+    Ok(ctx
+        .get_common::<BulkTransfer>()?
+        .map(|b| b.0)
+        .unwrap_or(false))
+}
+
+/// The addresses resolved before if they are of `addr`.
+fn resolved_hint(ctx: &rd_interface::Context, addr: &Address) -> Result<Option<Vec<SocketAddr>>> {
+    let (domain, port) = match addr {
+        Address::Domain(domain, port) => (domain, *port),
+        Address::SocketAddr(_) => return Ok(None),
+    };
+    Ok(ctx.get_common::<ResolvedAddrs>()?.and_then(|hint| {
+        let matched = hint.domain.domain.eq_ignore_ascii_case(domain)
+            && hint.domain.port == port
+            && !hint.addrs.is_empty();
+        if !matched {
+            tracing::debug!(
+                "Ignored resolved addresses of {}:{} for {}",
+                hint.domain.domain,
+                hint.domain.port,
+                addr
+            );
+        }
+        matched.then_some(hint.addrs)
+    }))
+}
+
 impl Resolver {
-    fn new(net: Option<Net>) -> Self {
-        Resolver { net }
+    fn new(net: Option<Net>, block: Vec<IpCidr>) -> Self {
+        Resolver {
+            net,
+            block: Arc::new(block),
+        }
     }
-    async fn lookup_host(self, domain: String, port: u16) -> io::Result<Vec<SocketAddr>> {
-        Ok(match self.net {
-            Some(net) => net.lookup_host(&Address::Domain(domain, port)).await?,
-            None => tokio::net::lookup_host((domain, port)).await?.collect(),
-        })
+    async fn lookup_host(
+        self,
+        mut ctx: rd_interface::Context,
+        domain: String,
+        port: u16,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let addrs = match self.net {
+            Some(net) => {
+                net.lookup_host(&mut ctx, &Address::Domain(domain.clone(), port))
+                    .await?
+            }
+            None => tokio::net::lookup_host((domain.as_str(), port))
+                .await?
+                .collect(),
+        };
+        drop_blocked(&domain, addrs, &self.block)
     }
 }
 
 impl LocalNet {
     pub fn new(cfg: LocalNetConfig) -> LocalNet {
-        let net = cfg.lookup_host.as_ref().map(|n| n.value_cloned());
+        let resolver = Resolver::new(
+            cfg.lookup_host.as_ref().map(|n| n.value_cloned()),
+            cfg.block_resolved.clone(),
+        );
+        let connect_limit = cfg.max_concurrent_connect.map(Semaphore::new);
+        let interface = match &cfg.bind_addr {
+            Some(BindAddr::Interface(name)) => Some(Interface::new(name.clone())),
+            _ => None,
+        };
+        let fd_budget = cfg.fd_budget.size().map(FdBudget::global);
+        let bind_pool = match cfg.bind_addrs.is_empty() {
+            true => None,
+            false => Some(BindPool::new(&cfg.bind_addrs, cfg.bind_strategy)),
+        };
         LocalNet {
             cfg,
-            resolver: Resolver::new(net),
+            resolver,
+            connect_limit,
+            interface,
+            fd_budget,
+            bind_pool,
         }
     }
-    async fn tcp_connect_single(&self, addr: SocketAddr) -> Result<net::TcpStream> {
+    /// The address to bind outbound sockets to, set by `bind_addrs` or `bind_addr`.
+    fn local_ip(&self, addr: SocketAddr) -> Result<Option<IpAddr>> {
+        Ok(match (&self.interface, &self.bind_pool) {
+            (Some(interface), _) => Some(interface.addr(addr)?),
+            (None, Some(pool)) => Some(pool.pick(addr)?),
+            (None, None) => self.cfg.bind_addr.as_ref().and_then(BindAddr::ip),
+        })
+    }
+    async fn tcp_connect_single(&self, addr: SocketAddr, is_bulk: bool) -> Result<net::TcpStream> {
         let socket = match addr {
             SocketAddr::V4(_) => Socket::new(Domain::IPV4, Type::STREAM, None)?,
             SocketAddr::V6(_) => Socket::new(Domain::IPV6, Type::STREAM, None)?,
         };
 
         self.cfg
-            .set_socket(SockRef::from(&socket), addr, true, false)?;
+            .set_socket(SockRef::from(&socket), addr, true, false, is_bulk)?;
+        match (self.cfg.source_port, &self.interface) {
+            (Some(source_port), _) => {
+                let ip = match self.local_ip(addr)? {
+                    Some(ip) => ip,
+                    None => match addr {
+                        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+                        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+                    },
+                };
+                // the last connection from a pinned port may be in TIME_WAIT
+                if let SourcePort::Port(_) = source_port {
+                    socket.set_reuse_address(true)?;
+                }
+                bind_source_port(&socket, ip, source_port)?;
+            }
+            (None, Some(interface)) => {
+                socket.bind(&SocketAddr::new(interface.addr(addr)?, 0).into())?
+            }
+            (None, None) => {
+                if let Some(pool) = &self.bind_pool {
+                    socket.bind(&SocketAddr::new(pool.pick(addr)?, 0).into())?
+                }
+            }
+        }
 
         let socket = net::TcpSocket::from_std_stream(socket.into());
 
@@ -188,29 +777,64 @@ impl LocalNet {
 
         Ok(tcp)
     }
-    async fn tcp_connect_happy_eyeballs(&self, addr: &Address) -> Result<TcpStream> {
+    /// Returns the stream and the address connected, `addr` is not resolved if
+    /// `resolved` is given. The steps are recorded to `trace` if it's given.
+    async fn tcp_connect_happy_eyeballs(
+        &self,
+        ctx: &rd_interface::Context,
+        addr: &Address,
+        resolved: Option<Vec<SocketAddr>>,
+        is_bulk: bool,
+        mut trace: Option<&mut ConnectTrace>,
+    ) -> Result<(TcpStream, SocketAddr)> {
+        let begin = Instant::now();
         // TODO: resolve A, AAAA separately
-        let addrs = addr
-            .resolve(|d, p| self.resolver.clone().lookup_host(d, p))
-            .await?;
+        let addrs = match resolved {
+            Some(addrs) => drop_blocked(&addr.to_string(), addrs, &self.resolver.block)?,
+            None => {
+                addr.resolve(|d, p| self.resolver.clone().lookup_host(ctx.clone(), d, p))
+                    .await?
+            }
+        };
         let mut last_err = None;
 
         // interleave the addresses, v6 first
         let v4_addrs = addrs.iter().filter(|addr| addr.is_ipv4());
         let v6_addrs = addrs.iter().filter(|addr| addr.is_ipv6());
-        let addrs = v6_addrs.interleave(v4_addrs);
+        let addrs = v6_addrs.interleave(v4_addrs).copied().collect::<Vec<_>>();
+
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.resolved = addrs.clone();
+            trace.resolve_ms = begin.elapsed().as_millis() as u64;
+        }
 
         let mut unordered = addrs
+            .into_iter()
             .enumerate()
             .map(|(i, addr)| async move {
                 sleep(Duration::from_millis(i as u64 * 250)).await;
-                self.tcp_connect_single(*addr).await
+                let start = Instant::now();
+                let res = self.tcp_connect_single(addr, is_bulk).await;
+                (addr, start, res)
             })
             .collect::<FuturesUnordered<_>>();
 
-        while let Some(res) = unordered.next().await {
+        while let Some((addr, start, res)) = unordered.next().await {
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.attempts.push(ConnectAttempt {
+                    addr,
+                    start_ms: start.duration_since(begin).as_millis() as u64,
+                    elapsed_ms: start.elapsed().as_millis() as u64,
+                    error: res.as_ref().err().map(ToString::to_string),
+                });
+            }
             match res {
-                Ok(stream) => return Ok(CompatTcp::new(stream).into_dyn()),
+                Ok(stream) => {
+                    if let Some(trace) = trace {
+                        trace.connected = Some(addr);
+                    }
+                    return Ok((CompatTcp::new(stream).into_dyn(), addr));
+                }
                 Err(err) => last_err = Some(err),
             }
         }
@@ -228,19 +852,33 @@ impl LocalNet {
 
         Ok(listener)
     }
-    async fn udp_bind_single(&self, addr: SocketAddr) -> Result<net::UdpSocket> {
+    async fn udp_bind_single(&self, addr: SocketAddr, is_bulk: bool) -> Result<net::UdpSocket> {
         let udp = match addr {
             SocketAddr::V4(_) => Socket::new(Domain::IPV4, Type::DGRAM, None)?,
             SocketAddr::V6(_) => Socket::new(Domain::IPV6, Type::DGRAM, None)?,
         };
 
         self.cfg
-            .set_socket(SockRef::from(&udp), addr, false, false)?;
+            .set_socket(SockRef::from(&udp), addr, false, false, is_bulk)?;
 
-        if self.cfg.bind_addr.is_none() {
-            udp.bind(&addr.into())?;
+        match (self.cfg.source_port, &self.interface) {
+            (Some(source_port), _) => {
+                let ip = self.local_ip(addr)?.unwrap_or_else(|| addr.ip());
+                bind_source_port(&udp, ip, source_port)?;
+            }
+            (None, Some(interface)) => {
+                udp.bind(&SocketAddr::new(interface.addr(addr)?, addr.port()).into())?
+            }
+            (None, None) => match &self.bind_pool {
+                Some(pool) => udp.bind(&SocketAddr::new(pool.pick(addr)?, addr.port()).into())?,
+                None if self.cfg.bind_addr.is_none() => udp.bind(&addr.into())?,
+                None => {}
+            },
         }
 
+        #[cfg(target_os = "linux")]
+        set_recv_err(&SockRef::from(&udp), addr)?;
+
         let udp = net::UdpSocket::from_std(udp.into())?;
 
         Ok(udp)
@@ -261,6 +899,11 @@ impl rd_interface::ITcpStream for CompatTcp {
     async fn local_addr(&self) -> Result<SocketAddr> {
         self.0.local_addr().map_err(Into::into)
     }
+    fn set_reset_on_drop(&mut self) -> bool {
+        SockRef::from(&self.0)
+            .set_linger(Some(Duration::ZERO))
+            .is_ok()
+    }
 
     impl_async_read_write!(0);
 }
@@ -270,15 +913,23 @@ impl CompatTcp {
     }
 }
 
+async fn acquire_fd(budget: &Option<FdBudget>) -> Option<FdPermit> {
+    match budget {
+        Some(budget) => Some(budget.acquire().await),
+        None => None,
+    }
+}
+
 #[async_trait]
 impl rd_interface::ITcpListener for Listener {
     async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        let permit = acquire_fd(&self.2).await;
         let (socket, addr) = self.0.accept().await?;
 
         self.1
-            .set_socket(SockRef::from(&socket), addr, true, true)?;
+            .set_socket(SockRef::from(&socket), addr, true, true, false)?;
 
-        Ok((CompatTcp::new(socket).into_dyn(), addr))
+        Ok((budgeted(CompatTcp::new(socket).into_dyn(), permit), addr))
     }
 
     async fn local_addr(&self) -> Result<SocketAddr> {
@@ -286,32 +937,134 @@ impl rd_interface::ITcpListener for Listener {
     }
 }
 
+#[cfg(unix)]
+fn disconnect_udp(socket: &net::UdpSocket) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut addr: libc::sockaddr = unsafe { std::mem::zeroed() };
+    addr.sa_family = libc::AF_UNSPEC as libc::sa_family_t;
+    let ret = unsafe {
+        libc::connect(
+            socket.as_raw_fd(),
+            &addr,
+            std::mem::size_of_val(&addr) as libc::socklen_t,
+        )
+    };
+    // Some systems return EAFNOSUPPORT even though the socket is disconnected.
+    if ret == -1 && io::Error::last_os_error().raw_os_error() != Some(libc::EAFNOSUPPORT) {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Send `buf` to `addr`, by `send` if the socket is connected to it, otherwise
+/// it's disconnected first, as `send_to` fails with EISCONN on some systems.
+fn poll_send_udp(
+    inner: &net::UdpSocket,
+    peer: &mut UdpPeer,
+    cx: &mut task::Context<'_>,
+    buf: &[u8],
+    addr: SocketAddr,
+) -> Poll<io::Result<usize>> {
+    #[cfg(unix)]
+    if matches!(peer, UdpPeer::Connected(p) if *p != addr) {
+        disconnect_udp(inner)?;
+        *peer = UdpPeer::Unconnected;
+    }
+    match peer {
+        UdpPeer::Connected(_) => inner.poll_send(cx, buf),
+        _ => match ready!(inner.poll_send_to(cx, buf, addr)) {
+            // The pending error is caused by a previous peer,
+            // it's reported by recv from the error queue.
+            #[cfg(target_os = "linux")]
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                inner.poll_send_to(cx, buf, addr)
+            }
+            r => Poll::Ready(r),
+        },
+    }
+}
+
 impl Udp {
-    fn new(socket: net::UdpSocket, resolver: Resolver) -> Udp {
+    fn new(
+        socket: net::UdpSocket,
+        resolver: Resolver,
+        ctx: rd_interface::Context,
+        connect: bool,
+    ) -> Udp {
         Udp {
             inner: socket,
             state: UdpState::Idle,
+            peer: if connect && cfg!(unix) {
+                UdpPeer::Auto
+            } else {
+                UdpPeer::Unconnected
+            },
             resolver,
+            ctx,
+            races: None,
         }
     }
+    /// Race the resolved addresses of the domains sent to, see `udp_race`.
+    fn race(mut self, race: bool) -> Udp {
+        self.races = race.then(UdpRaces::default);
+        self
+    }
     fn poll_send_to_ready(
         &mut self,
         cx: &mut task::Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<()>> {
-        let Udp { inner, state, .. } = self;
+        let Udp {
+            inner,
+            state,
+            peer,
+            races,
+            ..
+        } = self;
 
         loop {
             match state {
                 UdpState::Idle => return Poll::Ready(Ok(())),
-                UdpState::LookupHost(fut) => {
-                    let addr = *ready!(fut.get_mut().poll_unpin(cx))?
+                UdpState::LookupHost(fut, domain) => {
+                    let addrs = ready!(fut.get_mut().poll_unpin(cx))?;
+                    let addr = *addrs
                         .first()
                         .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?;
-                    *state = UdpState::Sending(addr)
+                    *state = match (races.as_mut(), domain.take()) {
+                        (Some(races), Some(domain)) if addrs.len() > 1 => {
+                            races.start(domain, addrs.clone());
+                            UdpState::racing(addrs)
+                        }
+                        _ => UdpState::Sending(addr),
+                    }
+                }
+                UdpState::Racing {
+                    addrs,
+                    next,
+                    sent,
+                    error,
+                } => {
+                    // fails only if all of them fail, e.g. IPv6 is unreachable
+                    while let Some(addr) = addrs.get(*next) {
+                        match ready!(poll_send_udp(inner, peer, cx, buf, *addr)) {
+                            Ok(_) => *sent = true,
+                            Err(e) => {
+                                tracing::trace!("Failed to send to {}: {:?}", addr, e);
+                                error.get_or_insert(e);
+                            }
+                        }
+                        *next += 1;
+                    }
+                    let error = error.take();
+                    let sent = *sent;
+                    *state = UdpState::Idle;
+                    if let (false, Some(e)) = (sent, error) {
+                        return Poll::Ready(Err(e));
+                    }
                 }
                 UdpState::Sending(addr) => {
-                    ready!(inner.poll_send_to(cx, buf, *addr)?);
+                    ready!(poll_send_udp(inner, peer, cx, buf, *addr))?;
                     *state = UdpState::Idle;
                 }
             }
@@ -330,9 +1083,38 @@ impl rd_interface::IUdpSocket for Udp {
         cx: &mut task::Context<'_>,
         buf: &mut ReadBuf,
     ) -> Poll<io::Result<SocketAddr>> {
-        let Udp { inner, .. } = &mut *self;
+        let Udp { inner, races, .. } = &mut *self;
+
+        #[cfg(target_os = "linux")]
+        loop {
+            // the addresses losing the race may be unreachable
+            let racing = matches!(races, Some(r) if r.racing > 0);
+            let result = inner.poll_recv_from(cx, buf);
+            if let Poll::Ready(Ok(from)) = result {
+                if let Some(races) = races {
+                    races.reply(from);
+                }
+                return result;
+            }
+            // Only port unreachable is reported, so clients know the peer is down.
+            // Other ICMP errors may be taken by recv as the pending error of the socket.
+            match take_recv_err(inner) {
+                Some(e) if e.kind() == io::ErrorKind::ConnectionRefused && !racing => {
+                    return Poll::Ready(Err(e))
+                }
+                Some(_) if result.is_ready() => continue,
+                _ => return result,
+            }
+        }
 
-        inner.poll_recv_from(cx, buf)
+        #[cfg(not(target_os = "linux"))]
+        {
+            let from = ready!(inner.poll_recv_from(cx, buf))?;
+            if let Some(races) = races {
+                races.reply(from);
+            }
+            Poll::Ready(Ok(from))
+        }
     }
 
     fn poll_send_to(
@@ -344,16 +1126,32 @@ impl rd_interface::IUdpSocket for Udp {
         match self.state {
             UdpState::Idle => match target {
                 Address::SocketAddr(s) => {
+                    if self.peer == UdpPeer::Auto {
+                        SockRef::from(&self.inner).connect(&(*s).into())?;
+                        self.peer = UdpPeer::Connected(*s);
+                    }
                     self.state = UdpState::Sending(*s);
                 }
                 Address::Domain(domain, port) => {
-                    let fut = Mutex::new(
-                        self.resolver
-                            .clone()
-                            .lookup_host(domain.clone(), *port)
-                            .boxed(),
-                    );
-                    self.state = UdpState::LookupHost(fut);
+                    if self.peer == UdpPeer::Auto {
+                        self.peer = UdpPeer::Unconnected;
+                    }
+                    match self.races.as_ref().map(|r| r.get(domain, *port)) {
+                        Some(Some(UdpRace::Won(addr))) => self.state = UdpState::Sending(*addr),
+                        Some(Some(UdpRace::Racing(addrs))) => {
+                            self.state = UdpState::racing(addrs.clone())
+                        }
+                        races => {
+                            let fut = Mutex::new(
+                                self.resolver
+                                    .clone()
+                                    .lookup_host(self.ctx.clone(), domain.clone(), *port)
+                                    .boxed(),
+                            );
+                            let race = races.is_some().then(|| (domain.clone(), *port));
+                            self.state = UdpState::LookupHost(fut, race);
+                        }
+                    }
                 }
             },
             _ => {}
@@ -368,10 +1166,33 @@ impl rd_interface::TcpConnect for LocalNet {
     #[instrument(err)]
     async fn tcp_connect(
         &self,
-        _ctx: &mut rd_interface::Context,
+        ctx: &mut rd_interface::Context,
         addr: &Address,
     ) -> Result<TcpStream> {
-        self.tcp_connect_happy_eyeballs(addr).await
+        let _permit = match &self.connect_limit {
+            Some(limit) => Some(limit.acquire().await.map_err(map_other)?),
+            None => None,
+        };
+        let is_bulk = is_bulk(ctx)?;
+        let hint = resolved_hint(ctx, addr)?;
+        let permit = acquire_fd(&self.fd_budget).await;
+        if !self.cfg.trace_connect {
+            let (tcp, resolved) = self
+                .tcp_connect_happy_eyeballs(ctx, addr, hint, is_bulk, None)
+                .await?;
+            ctx.insert_common(ResolvedSocketAddr(resolved))?;
+            return Ok(budgeted(tcp, permit));
+        }
+
+        let mut trace = ConnectTrace::default();
+        let result = self
+            .tcp_connect_happy_eyeballs(ctx, addr, hint, is_bulk, Some(&mut trace))
+            .await;
+        tracing::debug!(%addr, ?trace, "connect trace");
+        ctx.insert_common(trace)?;
+        let (tcp, resolved) = result?;
+        ctx.insert_common(ResolvedSocketAddr(resolved))?;
+        Ok(budgeted(tcp, permit))
     }
 }
 
@@ -380,17 +1201,22 @@ impl rd_interface::TcpBind for LocalNet {
     #[instrument(err)]
     async fn tcp_bind(
         &self,
-        _ctx: &mut rd_interface::Context,
+        ctx: &mut rd_interface::Context,
         addr: &Address,
     ) -> Result<TcpListener> {
         let addrs = addr
-            .resolve(|d, p| self.resolver.clone().lookup_host(d, p))
+            .resolve(|d, p| self.resolver.clone().lookup_host(ctx.clone(), d, p))
             .await?;
+        let permit = acquire_fd(&self.fd_budget).await;
         let mut last_err = None;
 
         for addr in addrs {
             match self.tcp_bind_single(addr).await {
-                Ok(listener) => return Ok(Listener(listener, self.cfg.clone()).into_dyn()),
+                Ok(listener) => {
+                    let listener =
+                        Listener(listener, self.cfg.clone(), self.fd_budget.clone()).into_dyn();
+                    return Ok(budgeted(listener, permit));
+                }
                 Err(e) => last_err = Some(e),
             }
         }
@@ -408,19 +1234,27 @@ impl rd_interface::TcpBind for LocalNet {
 #[async_trait]
 impl rd_interface::UdpBind for LocalNet {
     #[instrument(err)]
-    async fn udp_bind(
-        &self,
-        _ctx: &mut rd_interface::Context,
-        addr: &Address,
-    ) -> Result<UdpSocket> {
+    async fn udp_bind(&self, ctx: &mut rd_interface::Context, addr: &Address) -> Result<UdpSocket> {
+        let is_bulk = is_bulk(ctx)?;
         let addrs = addr
-            .resolve(|d, p| self.resolver.clone().lookup_host(d, p))
+            .resolve(|d, p| self.resolver.clone().lookup_host(ctx.clone(), d, p))
             .await?;
+        let permit = acquire_fd(&self.fd_budget).await;
         let mut last_err = None;
 
         for addr in addrs {
-            match self.udp_bind_single(addr).await {
-                Ok(udp) => return Ok(Udp::new(udp, self.resolver.clone()).into_dyn()),
+            match self.udp_bind_single(addr, is_bulk).await {
+                Ok(udp) => {
+                    let udp = Udp::new(
+                        udp,
+                        self.resolver.clone(),
+                        ctx.clone(),
+                        self.cfg.udp_connect,
+                    )
+                    .race(self.cfg.udp_race)
+                    .into_dyn();
+                    return Ok(budgeted(udp, permit));
+                }
                 Err(e) => last_err = Some(e),
             }
         }
@@ -438,9 +1272,13 @@ impl rd_interface::UdpBind for LocalNet {
 #[async_trait]
 impl rd_interface::LookupHost for LocalNet {
     #[instrument(err)]
-    async fn lookup_host(&self, addr: &Address) -> Result<Vec<SocketAddr>> {
+    async fn lookup_host(
+        &self,
+        ctx: &mut rd_interface::Context,
+        addr: &Address,
+    ) -> Result<Vec<SocketAddr>> {
         let addr = addr
-            .resolve(|d, p| self.resolver.clone().lookup_host(d, p))
+            .resolve(|d, p| self.resolver.clone().lookup_host(ctx.clone(), d, p))
             .await?;
         Ok(addr)
     }
@@ -466,11 +1304,62 @@ impl INet for LocalNet {
 
 impl Builder<Net> for LocalNet {
     const NAME: &'static str = "local";
+    const TITLE: &'static str = "Local";
     type Config = LocalNetConfig;
     type Item = Self;
 
     fn build(config: Self::Config) -> Result<Self> {
-        Ok(LocalNet::new(config))
+        if config.ttl.is_some() {
+            tracing::warn!("`ttl` of local net is deprecated, use `ip_ttl` and `ip_hop_limit`");
+        }
+        if let Some(dscp) = config.dscp {
+            if dscp > 63 {
+                return Err(rd_interface::Error::other(format!(
+                    "dscp should be in 0-63, got {}",
+                    dscp
+                )));
+            }
+        }
+        if !config.bind_addrs.is_empty() && config.bind_addr.is_some() {
+            return Err(rd_interface::Error::other(
+                "`bind_addrs` can't be set with `bind_addr`",
+            ));
+        }
+        if let Some(mss) = config.mss {
+            if !(536..=1460).contains(&mss) {
+                return Err(rd_interface::Error::other(format!(
+                    "mss should be in 536-1460, got {}",
+                    mss
+                )));
+            }
+        }
+        if let Some(source_port) = config.source_port {
+            let (start, end) = source_port.start_end();
+            if start > end {
+                return Err(rd_interface::Error::other(format!(
+                    "source_port range {}-{} is empty",
+                    start, end
+                )));
+            }
+        }
+        if let Some(keepalive) = config.tcp_keepalive {
+            keepalive.check()?;
+        }
+        if config.max_concurrent_connect == Some(0) {
+            return Err(rd_interface::Error::other(
+                "max_concurrent_connect should be greater than 0",
+            ));
+        }
+        if config.fd_budget.size() == Some(0) {
+            return Err(rd_interface::Error::other(
+                "fd_budget should be greater than 0",
+            ));
+        }
+        let net = LocalNet::new(config);
+        if let (Some(pool), false) = (&net.bind_pool, net.cfg.freebind) {
+            pool.check_assigned()?;
+        }
+        Ok(net)
     }
 }
 
@@ -493,6 +1382,974 @@ mod tests {
         assert_echo_udp(&net, "127.0.0.1:26666").await;
     }
 
+    #[tokio::test]
+    async fn test_resolved_addr() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let net = LocalNet::new(LocalNetConfig::default()).into_dyn();
+        let mut ctx = rd_interface::Context::new();
+        let tcp = net
+            .tcp_connect(&mut ctx, &Address::Domain("localhost".to_string(), port))
+            .await
+            .unwrap();
+
+        let resolved = ctx.get_common::<ResolvedSocketAddr>().unwrap().unwrap();
+        assert_eq!(resolved.0, tcp.peer_addr().await.unwrap());
+        assert_eq!(resolved.0, listener.local_addr().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_resolved_hint() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use rd_interface::{config::NetRef, AddressDomain};
+
+        /// Fails all the lookups, and counts them.
+        struct CountResolver(Arc<AtomicUsize>);
+
+        #[async_trait]
+        impl rd_interface::LookupHost for CountResolver {
+            async fn lookup_host(
+                &self,
+                _ctx: &mut rd_interface::Context,
+                _addr: &Address,
+            ) -> Result<Vec<SocketAddr>> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Err(rd_interface::Error::other("lookup failed"))
+            }
+        }
+
+        impl INet for CountResolver {
+            fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+                Some(self)
+            }
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let net = LocalNet::new(LocalNetConfig {
+            lookup_host: Some(NetRef::new_with_value(
+                "resolver".into(),
+                CountResolver(lookups.clone()).into_dyn(),
+            )),
+            ..Default::default()
+        })
+        .into_dyn();
+        let connect = |domain: &str| {
+            let mut ctx = rd_interface::Context::new();
+            ctx.insert_common(ResolvedAddrs {
+                domain: AddressDomain {
+                    domain: domain.to_string(),
+                    port: addr.port(),
+                },
+                addrs: vec!["[::1]:1".parse().unwrap(), addr],
+            })
+            .unwrap();
+            let net = net.clone();
+            async move {
+                let result = net
+                    .tcp_connect(
+                        &mut ctx,
+                        &Address::Domain("example.com".to_string(), addr.port()),
+                    )
+                    .await;
+                (result, ctx)
+            }
+        };
+
+        let (tcp, ctx) = connect("example.com").await;
+        assert_eq!(tcp.unwrap().peer_addr().await.unwrap(), addr);
+        let resolved = ctx.get_common::<ResolvedSocketAddr>().unwrap().unwrap();
+        assert_eq!(resolved.0, addr);
+        assert_eq!(lookups.load(Ordering::SeqCst), 0);
+
+        // the hint of another domain is not used
+        let (tcp, _) = connect("example.org").await;
+        assert!(tcp.is_err());
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_block_resolved() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let net = LocalNet::new(LocalNetConfig {
+            block_resolved: vec!["127.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()],
+            ..Default::default()
+        })
+        .into_dyn();
+        let localhost = Address::Domain("localhost".to_string(), addr.port());
+        let err = net
+            .tcp_connect(&mut rd_interface::Context::new(), &localhost)
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("blocked"), "{err}");
+        assert!(net
+            .lookup_host(&mut rd_interface::Context::new(), &localhost)
+            .await
+            .is_err());
+
+        // the IP destinations are not checked
+        net.tcp_connect(&mut rd_interface::Context::new(), &addr.into())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_trace_connect() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let net = LocalNet::new(LocalNetConfig::default()).into_dyn();
+        let mut ctx = rd_interface::Context::new();
+        net.tcp_connect(&mut ctx, &addr.into()).await.unwrap();
+        assert!(ctx.get_common::<ConnectTrace>().unwrap().is_none());
+
+        let net = LocalNet::new(LocalNetConfig {
+            trace_connect: true,
+            ..Default::default()
+        })
+        .into_dyn();
+        let mut ctx = rd_interface::Context::new();
+        net.tcp_connect(&mut ctx, &addr.into()).await.unwrap();
+        let trace = ctx.get_common::<ConnectTrace>().unwrap().unwrap();
+        assert_eq!(trace.resolved, vec![addr]);
+        assert_eq!(trace.attempts.len(), 1);
+        assert_eq!(trace.attempts[0].addr, addr);
+        assert!(trace.attempts[0].error.is_none());
+        assert_eq!(trace.connected, Some(addr));
+
+        // the trace is kept when it fails
+        drop(listener);
+        let mut ctx = rd_interface::Context::new();
+        assert!(net.tcp_connect(&mut ctx, &addr.into()).await.is_err());
+        let trace = ctx.get_common::<ConnectTrace>().unwrap().unwrap();
+        assert_eq!(trace.attempts.len(), 1);
+        assert!(trace.attempts[0].error.is_some());
+        assert_eq!(trace.connected, None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_interface() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let net = LocalNet::build(LocalNetConfig {
+            bind_addr: Some(BindAddr::Interface("lo".to_string())),
+            ..Default::default()
+        })
+        .unwrap()
+        .into_dyn();
+        let tcp = net
+            .tcp_connect(&mut rd_interface::Context::new(), &addr.into())
+            .await
+            .unwrap();
+        assert_eq!(tcp.local_addr().await.unwrap().ip(), addr.ip());
+
+        let udp = net
+            .udp_bind(
+                &mut rd_interface::Context::new(),
+                &SocketAddr::from(([0, 0, 0, 0], 0)).into(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(udp.local_addr().await.unwrap().ip(), addr.ip());
+    }
+
+    #[test]
+    fn test_bind_addr_config() {
+        let parse = |v: serde_json::Value| serde_json::from_value::<BindAddr>(v).unwrap();
+        assert_eq!(
+            parse(serde_json::json!("127.0.0.1")),
+            BindAddr::Ip("127.0.0.1".parse().unwrap())
+        );
+        assert_eq!(
+            parse(serde_json::json!("::1")),
+            BindAddr::Ip("::1".parse().unwrap())
+        );
+        assert_eq!(
+            parse(serde_json::json!("en0")),
+            BindAddr::Interface("en0".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_connect() {
+        use rd_interface::TcpConnect;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().into();
+        let net = LocalNet::build(LocalNetConfig {
+            max_concurrent_connect: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+
+        // the only slot is taken by another connect
+        let permit = net.connect_limit.as_ref().unwrap().acquire().await.unwrap();
+        let mut ctx = rd_interface::Context::new();
+        let connect = net.tcp_connect(&mut ctx, &addr);
+        tokio::pin!(connect);
+        assert!(timeout(Duration::from_millis(100), &mut connect)
+            .await
+            .is_err());
+
+        drop(permit);
+        timeout(Duration::from_secs(5), connect)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(LocalNet::build(LocalNetConfig {
+            max_concurrent_connect: Some(0),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fd_budget() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().into();
+        // not the global budget, which is shared by the other tests
+        let mut net = LocalNet::new(LocalNetConfig::default());
+        net.fd_budget = Some(FdBudget::new(1));
+        let net = net.into_dyn();
+
+        let tcp = net
+            .tcp_connect(&mut rd_interface::Context::new(), &addr)
+            .await
+            .unwrap();
+
+        // queued until the socket is closed
+        let mut ctx = rd_interface::Context::new();
+        let connect = net.tcp_connect(&mut ctx, &addr);
+        tokio::pin!(connect);
+        assert!(timeout(Duration::from_millis(100), &mut connect)
+            .await
+            .is_err());
+
+        drop(tcp);
+        let tcp = timeout(Duration::from_secs(5), connect)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // UDP sockets are counted too
+        let mut ctx = rd_interface::Context::new();
+        let udp_addr = SocketAddr::from(([127, 0, 0, 1], 0)).into();
+        let udp = net.udp_bind(&mut ctx, &udp_addr);
+        tokio::pin!(udp);
+        assert!(timeout(Duration::from_millis(100), &mut udp).await.is_err());
+        drop(tcp);
+        timeout(Duration::from_secs(5), udp).await.unwrap().unwrap();
+
+        assert!(LocalNet::build(LocalNetConfig {
+            fd_budget: FdBudgetConfig::Size(0),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(FdBudgetConfig::Enabled(true).size().unwrap() > 0);
+    }
+
+    // the whole 127.0.0.0/8 is assigned to the loopback on linux
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_bind_addrs() {
+        use rd_interface::TcpConnect;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().into();
+        let bind_addrs = ["127.0.0.2", "127.0.0.3", "127.0.0.4"]
+            .map(|ip| ip.parse::<IpAddr>().unwrap())
+            .to_vec();
+        let net = LocalNet::build(LocalNetConfig {
+            bind_addrs: bind_addrs.clone(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let mut sources = Vec::new();
+        for _ in 0..4 {
+            let tcp = net
+                .tcp_connect(&mut rd_interface::Context::new(), &addr)
+                .await
+                .unwrap();
+            let (_, peer) = listener.accept().unwrap();
+            assert_eq!(tcp.local_addr().await.unwrap().ip(), peer.ip());
+            sources.push(peer.ip());
+        }
+        assert_eq!(
+            sources,
+            [bind_addrs[0], bind_addrs[1], bind_addrs[2], bind_addrs[0]]
+        );
+
+        assert!(LocalNet::build(LocalNetConfig {
+            bind_addrs: vec!["192.0.2.1".parse().unwrap()],
+            ..Default::default()
+        })
+        .is_err());
+        assert!(LocalNet::build(LocalNetConfig {
+            bind_addrs,
+            bind_addr: Some(BindAddr::Ip("127.0.0.1".parse().unwrap())),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_connect_cancelled() {
+        // the SYNs are dropped once the accept queue is full
+        let socket = tokio::net::TcpSocket::new_v4().unwrap();
+        socket.bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let full = socket.listen(1).unwrap();
+        let full_addr = full.local_addr().unwrap();
+        let mut queued = Vec::new();
+        loop {
+            let connect = tokio::net::TcpStream::connect(full_addr);
+            match timeout(Duration::from_millis(200), connect).await {
+                Ok(tcp) => queued.push(tcp.unwrap()),
+                Err(_) => break,
+            }
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().into();
+        let mut net = LocalNet::new(LocalNetConfig::default());
+        net.fd_budget = Some(FdBudget::new(1));
+        let net = net.into_dyn();
+
+        let mut ctx = rd_interface::Context::new();
+        let full_addr = full_addr.into();
+        let connect = net.tcp_connect(&mut ctx, &full_addr);
+        assert!(timeout(Duration::from_millis(100), connect).await.is_err());
+
+        // the socket of the dropped connect is closed and back in the budget
+        timeout(
+            Duration::from_secs(5),
+            net.tcp_connect(&mut rd_interface::Context::new(), &addr),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_udp_race() {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use futures::future::poll_fn;
+        use rd_interface::{config::NetRef, IUdpSocket};
+
+        /// Answers `addrs` to all the lookups, and counts them.
+        struct StubResolver(Vec<SocketAddr>, Arc<AtomicUsize>);
+
+        #[async_trait]
+        impl rd_interface::LookupHost for StubResolver {
+            async fn lookup_host(
+                &self,
+                _ctx: &mut rd_interface::Context,
+                _addr: &Address,
+            ) -> Result<Vec<SocketAddr>> {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                Ok(self.0.clone())
+            }
+        }
+
+        impl INet for StubResolver {
+            fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+                Some(self)
+            }
+        }
+
+        // only the second address replies
+        let silent = net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo = net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 64];
+            loop {
+                let (n, from) = echo.recv_from(&mut buf).await.unwrap();
+                echo.send_to(&buf[..n], from).await.unwrap();
+            }
+        });
+
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let local = LocalNet::new(LocalNetConfig {
+            lookup_host: Some(NetRef::new_with_value(
+                "resolver".into(),
+                StubResolver(
+                    vec![silent.local_addr().unwrap(), echo_addr],
+                    lookups.clone(),
+                )
+                .into_dyn(),
+            )),
+            ..Default::default()
+        });
+        let socket = local
+            .udp_bind_single("127.0.0.1:0".parse().unwrap(), false)
+            .await
+            .unwrap();
+        let mut udp = Udp::new(
+            socket,
+            local.resolver.clone(),
+            rd_interface::Context::new(),
+            false,
+        )
+        .race(true);
+        let target = Address::Domain("quic.example.com".to_string(), 443);
+        let buf = &mut vec![0; 64];
+
+        for data in [b"hello", b"world"] {
+            poll_fn(|cx| udp.poll_send_to(cx, data, &target))
+                .await
+                .unwrap();
+            let mut read_buf = ReadBuf::new(buf);
+            let from = poll_fn(|cx| udp.poll_recv_from(cx, &mut read_buf))
+                .await
+                .unwrap();
+            assert_eq!(from, echo_addr);
+            assert_eq!(read_buf.filled(), data);
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        // the first datagram is sent to both, the second one only to the winner
+        let mut buf = [0; 64];
+        let (n, _) = silent.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+        assert!(silent.try_recv_from(&mut buf).is_err());
+
+        // a connected socket is disconnected to race the addresses
+        let socket = local
+            .udp_bind_single("127.0.0.1:0".parse().unwrap(), false)
+            .await
+            .unwrap();
+        let mut udp = Udp::new(
+            socket,
+            local.resolver.clone(),
+            rd_interface::Context::new(),
+            true,
+        )
+        .race(true);
+        let buf = &mut vec![0; 64];
+        for target in [Address::SocketAddr(echo_addr), target] {
+            poll_fn(|cx| udp.poll_send_to(cx, b"hello", &target))
+                .await
+                .unwrap();
+            let mut read_buf = ReadBuf::new(buf);
+            let from = poll_fn(|cx| udp.poll_recv_from(cx, &mut read_buf))
+                .await
+                .unwrap();
+            assert_eq!(from, echo_addr);
+        }
+        assert_eq!(udp.peer, UdpPeer::Unconnected);
+        let (n, _) = silent.recv_from(&mut buf[..]).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_udp_connect() {
+        use futures::future::poll_fn;
+        use rd_interface::IUdpSocket;
+
+        let net = LocalNet::new(LocalNetConfig::default()).into_dyn();
+        spawn_echo_server_udp(&net, "127.0.0.1:26667").await;
+        spawn_echo_server_udp(&net, "127.0.0.1:26668").await;
+
+        let local = LocalNet::new(LocalNetConfig {
+            udp_connect: true,
+            ..Default::default()
+        });
+        let socket = local
+            .udp_bind_single("0.0.0.0:0".parse().unwrap(), false)
+            .await
+            .unwrap();
+        let mut udp = Udp::new(
+            socket,
+            local.resolver.clone(),
+            rd_interface::Context::new(),
+            true,
+        );
+        let buf = &mut vec![0; 64];
+
+        let peer: SocketAddr = "127.0.0.1:26667".parse().unwrap();
+        for _ in 0..2 {
+            poll_fn(|cx| udp.poll_send_to(cx, b"hello", &peer.into()))
+                .await
+                .unwrap();
+            assert_eq!(udp.peer, UdpPeer::Connected(peer));
+
+            let mut read_buf = ReadBuf::new(buf);
+            let from = poll_fn(|cx| udp.poll_recv_from(cx, &mut read_buf))
+                .await
+                .unwrap();
+            assert_eq!(from, peer);
+            assert_eq!(read_buf.filled(), b"hello");
+        }
+
+        let other: SocketAddr = "127.0.0.1:26668".parse().unwrap();
+        poll_fn(|cx| udp.poll_send_to(cx, b"world", &other.into()))
+            .await
+            .unwrap();
+        assert_eq!(udp.peer, UdpPeer::Unconnected);
+
+        let mut read_buf = ReadBuf::new(buf);
+        let from = poll_fn(|cx| udp.poll_recv_from(cx, &mut read_buf))
+            .await
+            .unwrap();
+        assert_eq!(from, other);
+        assert_eq!(read_buf.filled(), b"world");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_udp_port_unreachable() {
+        let closed = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let closed_addr: Address = closed.local_addr().unwrap().into();
+        drop(closed);
+
+        let net = LocalNet::new(LocalNetConfig::default()).into_dyn();
+        let mut udp = net
+            .udp_bind(
+                &mut rd_interface::Context::new(),
+                &"127.0.0.1:0".parse().unwrap(),
+            )
+            .await
+            .unwrap();
+        udp.send_to(b"hello", &closed_addr).await.unwrap();
+        sleep(Duration::from_millis(50)).await;
+        // the pending error doesn't fail sending
+        udp.send_to(b"hello", &closed_addr).await.unwrap();
+
+        let buf = &mut vec![0; 64];
+        let err = timeout(
+            Duration::from_secs(1),
+            udp.recv_from(&mut ReadBuf::new(buf)),
+        )
+        .await
+        .unwrap()
+        .unwrap_err();
+        assert_eq!(err.to_io_err().kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let cfg = LocalNetConfig {
+            ip_ttl: Some(32),
+            ip_hop_limit: Some(48),
+            ..Default::default()
+        };
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        cfg.set_socket(
+            SockRef::from(&socket),
+            "127.0.0.1:1".parse().unwrap(),
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(socket.ttl().unwrap(), 32);
+
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, None).unwrap();
+        cfg.set_socket(
+            SockRef::from(&socket),
+            "[::1]:1".parse().unwrap(),
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(socket.unicast_hops_v6().unwrap(), 48);
+
+        // the deprecated `ttl` applies to both
+        let cfg = LocalNetConfig {
+            ttl: Some(16),
+            ..Default::default()
+        };
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        cfg.set_socket(
+            SockRef::from(&socket),
+            "127.0.0.1:1".parse().unwrap(),
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(socket.ttl().unwrap(), 16);
+
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, None).unwrap();
+        cfg.set_socket(
+            SockRef::from(&socket),
+            "[::1]:1".parse().unwrap(),
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(socket.unicast_hops_v6().unwrap(), 16);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_tcp_keepalive() {
+        let set_keepalive = |config: &str| {
+            let cfg = LocalNetConfig {
+                tcp_keepalive: serde_json::from_str(config).unwrap(),
+                ..Default::default()
+            };
+            let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+            cfg.set_socket(
+                SockRef::from(&socket),
+                "127.0.0.1:1".parse().unwrap(),
+                true,
+                false,
+                false,
+            )
+            .unwrap();
+            socket
+        };
+
+        let socket = set_keepalive(r#"{ "idle": 30, "interval": 5, "count": 3 }"#);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(5));
+        assert_eq!(socket.keepalive_retries().unwrap(), 3);
+
+        // the scalar is the idle time
+        let socket = set_keepalive("45");
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(45));
+
+        let socket = set_keepalive(r#"{ "interval": 5 }"#);
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(600));
+        assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(5));
+
+        assert!(!set_keepalive("0").keepalive().unwrap());
+    }
+
+    #[test]
+    fn test_tcp_keepalive_range() {
+        let build = |config: &str| {
+            LocalNet::build(LocalNetConfig {
+                tcp_keepalive: serde_json::from_str(config).unwrap(),
+                ..Default::default()
+            })
+        };
+        assert!(build("0").is_ok());
+        assert!(build(r#"{ "idle": 30, "interval": 0.5, "count": 1 }"#).is_ok());
+        assert!(build("-1").is_err());
+        assert!(build(r#"{ "idle": -1 }"#).is_err());
+        assert!(build(r#"{ "interval": 0 }"#).is_err());
+        assert!(build(r#"{ "interval": -5 }"#).is_err());
+        assert!(build(r#"{ "count": 0 }"#).is_err());
+
+        let nan = TcpKeepalive::Probes {
+            idle: None,
+            interval: Some(f64::NAN),
+            count: None,
+        };
+        assert!(nan.check().is_err());
+        assert!(TcpKeepalive::Idle(f64::NAN).check().is_err());
+    }
+
+    #[test]
+    fn test_dscp_range() {
+        assert!(LocalNet::build(LocalNetConfig {
+            dscp: Some(63),
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(LocalNet::build(LocalNetConfig {
+            dscp: Some(64),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_dscp() {
+        use std::os::unix::io::AsRawFd;
+
+        let cfg = LocalNetConfig {
+            dscp: Some(46),
+            ..Default::default()
+        };
+
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        cfg.set_socket(
+            SockRef::from(&socket),
+            "127.0.0.1:1".parse().unwrap(),
+            true,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(socket.tos().unwrap(), 46 << 2);
+
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, None).unwrap();
+        cfg.set_socket(
+            SockRef::from(&socket),
+            "[::1]:1".parse().unwrap(),
+            false,
+            false,
+            false,
+        )
+        .unwrap();
+        let mut tclass: libc::c_int = 0;
+        let mut len = std::mem::size_of_val(&tclass) as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_TCLASS,
+                &mut tclass as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(ret, 0);
+        assert_eq!(tclass, 46 << 2);
+    }
+
+    #[test]
+    fn test_mss_range() {
+        assert!(LocalNet::build(LocalNetConfig {
+            mss: Some(1400),
+            ..Default::default()
+        })
+        .is_ok());
+        assert!(LocalNet::build(LocalNetConfig {
+            mss: Some(100),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(LocalNet::build(LocalNetConfig {
+            mss: Some(9000),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mss() {
+        let cfg = LocalNetConfig {
+            mss: Some(1200),
+            ..Default::default()
+        };
+
+        let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+        cfg.set_socket(
+            SockRef::from(&socket),
+            "127.0.0.1:1".parse().unwrap(),
+            true,
+            false,
+            false,
+        )
+        .unwrap();
+        assert_eq!(socket.mss().unwrap(), 1200);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_freebind() {
+        use std::os::unix::io::AsRawFd;
+
+        // TEST-NET-1, not assigned to the host
+        let set_socket = |cfg: &LocalNetConfig| {
+            let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+            cfg.set_socket(
+                SockRef::from(&socket),
+                "127.0.0.1:1".parse().unwrap(),
+                true,
+                false,
+                false,
+            )
+            .map(|_| socket)
+        };
+        let cfg = LocalNetConfig {
+            bind_addr: Some(BindAddr::Ip("192.0.2.1".parse().unwrap())),
+            ..Default::default()
+        };
+        assert!(set_socket(&cfg).is_err());
+
+        let cfg = LocalNetConfig {
+            freebind: true,
+            bind_address_no_port: true,
+            ..cfg
+        };
+        let socket = set_socket(&cfg).unwrap();
+        assert!(socket.freebind().unwrap());
+
+        let mut no_port: libc::c_int = 0;
+        let mut len = std::mem::size_of_val(&no_port) as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_BIND_ADDRESS_NO_PORT,
+                &mut no_port as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(ret, 0);
+        assert_eq!(no_port, 1);
+        // the port is allocated at connect
+        assert_eq!(socket.local_addr().unwrap().as_socket().unwrap().port(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_source_port() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let pinned = LocalNet::build(LocalNetConfig {
+            source_port: Some(SourcePort::Port(port)),
+            ..Default::default()
+        })
+        .unwrap()
+        .into_dyn();
+        let tcp = pinned
+            .tcp_connect(&mut rd_interface::Context::new(), &addr.into())
+            .await
+            .unwrap();
+        assert_eq!(tcp.local_addr().await.unwrap().port(), port);
+
+        // the port is in use
+        assert!(pinned
+            .tcp_connect(&mut rd_interface::Context::new(), &addr.into())
+            .await
+            .is_err());
+
+        // the next free one of the range is chosen
+        let range = LocalNet::build(LocalNetConfig {
+            source_port: Some(SourcePort::Range {
+                start: port,
+                end: port.saturating_add(10),
+            }),
+            ..Default::default()
+        })
+        .unwrap()
+        .into_dyn();
+        let tcp2 = range
+            .tcp_connect(&mut rd_interface::Context::new(), &addr.into())
+            .await
+            .unwrap();
+        let port2 = tcp2.local_addr().await.unwrap().port();
+        assert!(port2 > port && port2 <= port.saturating_add(10));
+
+        drop(tcp);
+        let udp = pinned
+            .udp_bind(
+                &mut rd_interface::Context::new(),
+                &SocketAddr::from(([127, 0, 0, 1], 0)).into(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(udp.local_addr().await.unwrap().port(), port);
+
+        assert!(LocalNet::build(LocalNetConfig {
+            source_port: Some(SourcePort::Range { start: 2, end: 1 }),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_source_port_time_wait() {
+        use tokio::io::AsyncReadExt;
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let pinned = LocalNet::build(LocalNetConfig {
+            source_port: Some(SourcePort::Port(port)),
+            ..Default::default()
+        })
+        .unwrap()
+        .into_dyn();
+        let tcp = pinned
+            .tcp_connect(&mut rd_interface::Context::new(), &addr.into())
+            .await
+            .unwrap();
+        let (mut accepted, _) = listener.accept().await.unwrap();
+
+        // closed by this side first, so the port is left in TIME_WAIT
+        drop(tcp);
+        assert_eq!(accepted.read(&mut [0u8; 1]).await.unwrap(), 0);
+        drop(accepted);
+
+        let listener2 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = pinned
+            .tcp_connect(
+                &mut rd_interface::Context::new(),
+                &listener2.local_addr().unwrap().into(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(tcp.local_addr().await.unwrap().port(), port);
+    }
+
+    #[test]
+    fn test_auto_buffer() {
+        let cfg = LocalNetConfig {
+            recv_buffer_size: Some(100_000),
+            auto_buffer: true,
+            ..Default::default()
+        };
+        let recv_buffer_size = |is_bulk: bool| {
+            let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+            cfg.set_socket(
+                SockRef::from(&socket),
+                "127.0.0.1:1".parse().unwrap(),
+                true,
+                false,
+                is_bulk,
+            )
+            .unwrap();
+            socket.recv_buffer_size().unwrap()
+        };
+        let default_size = Socket::new(Domain::IPV4, Type::STREAM, None)
+            .unwrap()
+            .recv_buffer_size()
+            .unwrap();
+
+        // kernel autotuning is kept without the hint
+        assert_eq!(recv_buffer_size(false), default_size);
+        let bulk_size = recv_buffer_size(true);
+        assert_ne!(bulk_size, default_size);
+        assert!(bulk_size >= 100_000);
+
+        let mut ctx = rd_interface::Context::new();
+        assert!(!is_bulk(&ctx).unwrap());
+        ctx.insert_common(BulkTransfer(true)).unwrap();
+        assert!(is_bulk(&ctx).unwrap());
+    }
+
     #[test]
     fn test_provider() {
         let net = LocalNet::new(LocalNetConfig::default()).into_dyn();
@@ -0,0 +1,111 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rand::Rng;
+use rd_interface::{prelude::*, Result};
+
+/// How the source address is chosen from `bind_addrs`.
+#[rd_config]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BindStrategy {
+    #[default]
+    RoundRobin,
+    Random,
+    /// The same source address for a destination IP.
+    PerDestination,
+}
+
+/// The source addresses outbound sockets are bound to in turn.
+#[derive(Debug)]
+pub struct BindPool {
+    v4: Vec<IpAddr>,
+    v6: Vec<IpAddr>,
+    strategy: BindStrategy,
+    next: AtomicUsize,
+    hasher: RandomState,
+}
+
+impl BindPool {
+    pub fn new(addrs: &[IpAddr], strategy: BindStrategy) -> BindPool {
+        let (v4, v6) = addrs.iter().partition(|ip| ip.is_ipv4());
+        BindPool {
+            v4,
+            v6,
+            strategy,
+            next: AtomicUsize::new(0),
+            hasher: RandomState::new(),
+        }
+    }
+    /// Fails if an address is not assigned to the host.
+    pub fn check_assigned(&self) -> Result<()> {
+        for ip in self.v4.iter().chain(&self.v6) {
+            UdpSocket::bind(SocketAddr::new(*ip, 0)).map_err(|e| {
+                rd_interface::Error::other(format!(
+                    "bind_addrs: {} is not assigned to the host: {}",
+                    ip, e
+                ))
+            })?;
+        }
+        Ok(())
+    }
+    /// The source address to connect to `addr` from, of the same family.
+    pub fn pick(&self, addr: SocketAddr) -> io::Result<IpAddr> {
+        let addrs = match addr {
+            SocketAddr::V4(_) => &self.v4,
+            SocketAddr::V6(_) => &self.v6,
+        };
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("no address in bind_addrs to connect to {}", addr),
+            ));
+        }
+        let index = match self.strategy {
+            BindStrategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed),
+            BindStrategy::Random => rand::thread_rng().gen(),
+            BindStrategy::PerDestination => self.hasher.hash_one(addr.ip()) as usize,
+        };
+        Ok(addrs[index % addrs.len()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 127.0.0.0/8 is only assigned to the loopback on linux
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pick() {
+        let addrs = ["127.0.0.2", "127.0.0.3", "::1"]
+            .map(|ip| ip.parse::<IpAddr>().unwrap())
+            .to_vec();
+        let v4 = |port: u16| SocketAddr::from(([127, 0, 0, 1], port));
+
+        let pool = BindPool::new(&addrs, BindStrategy::RoundRobin);
+        let picked = (0..4)
+            .map(|i| pool.pick(v4(i)).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(picked, [addrs[0], addrs[1], addrs[0], addrs[1]]);
+        assert_eq!(pool.pick("[::1]:80".parse().unwrap()).unwrap(), addrs[2]);
+
+        let pool = BindPool::new(&addrs, BindStrategy::PerDestination);
+        let first = pool.pick(v4(80)).unwrap();
+        assert!((0..8).all(|i| pool.pick(v4(i)).unwrap() == first));
+
+        let pool = BindPool::new(&addrs, BindStrategy::Random);
+        assert!((0..8).all(|i| addrs[..2].contains(&pool.pick(v4(i)).unwrap())));
+
+        let pool = BindPool::new(&addrs[..2], BindStrategy::RoundRobin);
+        assert!(pool.pick("[::1]:80".parse().unwrap()).is_err());
+        assert!(pool.check_assigned().is_ok());
+        let pool = BindPool::new(&["192.0.2.1".parse().unwrap()], BindStrategy::Random);
+        assert!(pool.check_assigned().is_err());
+    }
+}
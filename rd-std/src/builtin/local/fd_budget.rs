@@ -0,0 +1,222 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{self, Poll},
+};
+
+use once_cell::sync::OnceCell;
+use rd_interface::{
+    async_trait, prelude::*, Address, AsyncRead, AsyncWrite, ITcpListener, ITcpStream, IUdpSocket,
+    IntoDyn, ReadBuf, Result, TcpListener, TcpStream, UdpSocket,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// `true` for 3/4 of the open file limit, or the number of sockets.
+#[rd_config]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FdBudgetConfig {
+    Enabled(bool),
+    Size(usize),
+}
+
+impl Default for FdBudgetConfig {
+    fn default() -> Self {
+        FdBudgetConfig::Enabled(false)
+    }
+}
+
+impl FdBudgetConfig {
+    /// The number of sockets, `None` if it's disabled.
+    pub fn size(self) -> Option<usize> {
+        match self {
+            FdBudgetConfig::Enabled(false) => None,
+            FdBudgetConfig::Enabled(true) => Some(default_size()),
+            FdBudgetConfig::Size(size) => Some(size),
+        }
+    }
+}
+
+/// 3/4 of the soft limit of the open files, the rest is left for the others,
+/// e.g. the files and the sockets of the servers.
+#[cfg(unix)]
+fn default_size() -> usize {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    let ret = unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+    if ret != 0 || limit.rlim_cur == libc::RLIM_INFINITY {
+        return DEFAULT_SIZE;
+    }
+    (limit.rlim_cur as usize / 4 * 3).max(1)
+}
+
+#[cfg(not(unix))]
+fn default_size() -> usize {
+    DEFAULT_SIZE
+}
+
+/// Used if the open file limit is unknown.
+const DEFAULT_SIZE: usize = 8192;
+
+/// Limits the sockets opened at the same time, the new ones wait for a socket to
+/// be closed instead of failing with `EMFILE`.
+#[derive(Debug, Clone)]
+pub struct FdBudget {
+    semaphore: Arc<Semaphore>,
+    size: usize,
+    saturated: Arc<AtomicBool>,
+}
+
+pub type FdPermit = OwnedSemaphorePermit;
+
+impl FdBudget {
+    pub fn new(size: usize) -> FdBudget {
+        FdBudget {
+            semaphore: Arc::new(Semaphore::new(size)),
+            size,
+            saturated: Arc::new(AtomicBool::new(false)),
+        }
+    }
+    /// The budget shared by all the local nets, its size is decided by the first one.
+    pub fn global(size: usize) -> FdBudget {
+        static GLOBAL: OnceCell<FdBudget> = OnceCell::new();
+
+        let budget = GLOBAL.get_or_init(|| {
+            tracing::info!("Socket budget of local nets: {}", size);
+            FdBudget::new(size)
+        });
+        if budget.size != size {
+            tracing::warn!(
+                "Socket budget is already {}, {} is ignored",
+                budget.size,
+                size
+            );
+        }
+        budget.clone()
+    }
+    /// Takes a socket from the budget, waits until one is closed if it's used up.
+    pub async fn acquire(&self) -> FdPermit {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            self.saturated.store(false, Ordering::Relaxed);
+            return permit;
+        }
+        if !self.saturated.swap(true, Ordering::Relaxed) {
+            tracing::warn!(
+                "Socket budget of {} is used up, the new sockets are queued",
+                self.size
+            );
+        }
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed")
+    }
+}
+
+/// A socket keeping its permit of the budget until it's closed.
+pub struct Budgeted<T> {
+    inner: T,
+    _permit: FdPermit,
+}
+
+impl<T> Budgeted<T> {
+    pub fn new(inner: T, permit: FdPermit) -> Budgeted<T> {
+        Budgeted {
+            inner,
+            _permit: permit,
+        }
+    }
+}
+
+#[async_trait]
+impl ITcpStream for Budgeted<TcpStream> {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+
+    fn poll_write(&mut self, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        self.inner.peer_addr().await
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr().await
+    }
+
+    fn set_reset_on_drop(&mut self) -> bool {
+        self.inner.set_reset_on_drop()
+    }
+}
+
+#[async_trait]
+impl ITcpListener for Budgeted<TcpListener> {
+    async fn accept(&self) -> Result<(TcpStream, SocketAddr)> {
+        self.inner.accept().await
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr().await
+    }
+}
+
+#[async_trait]
+impl IUdpSocket for Budgeted<UdpSocket> {
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        self.inner.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &Address,
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_send_to(cx, buf, target)
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.inner.local_addr().await
+    }
+
+    fn recv_buffer_size(&self) -> Option<usize> {
+        self.inner.recv_buffer_size()
+    }
+}
+
+/// Keeps `permit` of the budget until `socket` is closed.
+pub fn budgeted<T: 'static>(socket: T, permit: Option<FdPermit>) -> T
+where
+    Budgeted<T>: IntoDyn<T>,
+{
+    match permit {
+        Some(permit) => Budgeted::new(socket, permit).into_dyn(),
+        None => socket,
+    }
+}
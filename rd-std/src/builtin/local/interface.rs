@@ -0,0 +1,261 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use parking_lot::Mutex;
+
+/// Bumped on network changes, the interface addresses read before are stale.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Makes the local nets read the address of their `bind_addr` interface again for new
+/// connections, e.g. after switching Wi-Fi. Existing connections are left as is.
+pub fn rebind() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    tracing::debug!("Rebind local nets");
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct InterfaceAddrs {
+    v4: Option<Ipv4Addr>,
+    v6: Option<Ipv6Addr>,
+}
+
+/// The addresses of an interface, cached until [`rebind`].
+pub(super) struct Interface {
+    name: String,
+    cached: Mutex<Option<(u64, InterfaceAddrs)>>,
+}
+
+impl Interface {
+    pub(super) fn new(name: String) -> Interface {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        watch::start();
+
+        Interface {
+            name,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The address to bind for connecting to `target`.
+    pub(super) fn addr(&self, target: SocketAddr) -> io::Result<IpAddr> {
+        self.addr_at(GENERATION.load(Ordering::SeqCst), target)
+    }
+
+    fn addr_at(&self, generation: u64, target: SocketAddr) -> io::Result<IpAddr> {
+        let mut cached = self.cached.lock();
+
+        let addrs = match *cached {
+            Some((g, addrs)) if g == generation && has_family(addrs, target) => addrs,
+            _ => {
+                let addrs = interface_addrs(&self.name)?;
+                *cached = Some((generation, addrs));
+                addrs
+            }
+        };
+
+        match target {
+            SocketAddr::V4(_) => addrs.v4.map(IpAddr::V4),
+            SocketAddr::V6(_) => addrs.v6.map(IpAddr::V6),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                format!("no address for {} on interface {}", target, self.name),
+            )
+        })
+    }
+}
+
+fn has_family(addrs: InterfaceAddrs, target: SocketAddr) -> bool {
+    match target {
+        SocketAddr::V4(_) => addrs.v4.is_some(),
+        SocketAddr::V6(_) => addrs.v6.is_some(),
+    }
+}
+
+#[cfg(unix)]
+fn interface_addrs(name: &str) -> io::Result<InterfaceAddrs> {
+    use std::ffi::CStr;
+
+    let mut addrs = InterfaceAddrs::default();
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut found = false;
+    let mut cur = ifap;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+
+        if unsafe { CStr::from_ptr(ifa.ifa_name) }.to_bytes() != name.as_bytes() {
+            continue;
+        }
+        found = true;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        match unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_INET => {
+                let sa = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(sa.sin_addr.s_addr));
+                addrs.v4.get_or_insert(ip);
+            }
+            libc::AF_INET6 => {
+                let sa = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(sa.sin6_addr.s6_addr);
+                // link-local addresses can't be bound without the scope id
+                if ip.segments()[0] & 0xffc0 != 0xfe80 {
+                    addrs.v6.get_or_insert(ip);
+                }
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+
+    if !found {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("interface not found: {}", name),
+        ));
+    }
+    Ok(addrs)
+}
+
+#[cfg(not(unix))]
+fn interface_addrs(_name: &str) -> io::Result<InterfaceAddrs> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface is not supported on this platform",
+    ))
+}
+
+/// Calls [`rebind`] on the notifications of network changes from the OS,
+/// netlink on linux and the routing socket on macos.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod watch {
+    use std::{io, sync::Once};
+
+    pub(super) fn start() {
+        static START: Once = Once::new();
+        START.call_once(|| {
+            let socket = match open() {
+                Ok(socket) => socket,
+                Err(e) => {
+                    tracing::warn!("Failed to watch network changes: {:?}", e);
+                    return;
+                }
+            };
+            let result = std::thread::Builder::new()
+                .name("network-watch".to_string())
+                .spawn(move || watch(socket));
+            if let Err(e) = result {
+                tracing::warn!("Failed to watch network changes: {:?}", e);
+            }
+        });
+    }
+
+    fn watch(socket: libc::c_int) {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n =
+                unsafe { libc::recv(socket, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                tracing::warn!("Stop watching network changes: {:?}", e);
+                break;
+            }
+            super::rebind();
+        }
+        unsafe { libc::close(socket) };
+    }
+
+    #[cfg(target_os = "linux")]
+    fn open() -> io::Result<libc::c_int> {
+        let socket = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_ROUTE,
+            )
+        };
+        if socket < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups =
+            (libc::RTMGRP_LINK | libc::RTMGRP_IPV4_IFADDR | libc::RTMGRP_IPV6_IFADDR) as u32;
+        let ret = unsafe {
+            libc::bind(
+                socket,
+                &addr as *const _ as *const libc::sockaddr,
+                std::mem::size_of_val(&addr) as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            let e = io::Error::last_os_error();
+            unsafe { libc::close(socket) };
+            return Err(e);
+        }
+        Ok(socket)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn open() -> io::Result<libc::c_int> {
+        let socket = unsafe { libc::socket(libc::PF_ROUTE, libc::SOCK_RAW, libc::AF_UNSPEC) };
+        if socket < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_refresh() {
+        let lo = Interface::new("lo".to_string());
+        let target = SocketAddr::from(([127, 0, 0, 1], 80));
+        assert_eq!(lo.addr_at(0, target).unwrap(), IpAddr::from([127, 0, 0, 1]));
+
+        // pretend the address changed
+        let stale = InterfaceAddrs {
+            v4: Some(Ipv4Addr::new(192, 0, 2, 1)),
+            v6: None,
+        };
+        lo.cached.lock().as_mut().unwrap().1 = stale;
+        assert_eq!(lo.addr_at(0, target).unwrap(), IpAddr::from([192, 0, 2, 1]));
+
+        // read again after a network change
+        assert_eq!(lo.addr_at(1, target).unwrap(), IpAddr::from([127, 0, 0, 1]));
+    }
+
+    #[test]
+    fn test_rebind() {
+        // other tests and the network watcher may bump it too
+        let before = GENERATION.load(Ordering::SeqCst);
+        rebind();
+        assert!(GENERATION.load(Ordering::SeqCst) > before);
+    }
+
+    #[test]
+    fn test_interface_not_found() {
+        let interface = Interface::new("rd-not-exist".to_string());
+        assert!(interface
+            .addr(SocketAddr::from(([127, 0, 0, 1], 80)))
+            .is_err());
+    }
+}
@@ -0,0 +1,19 @@
+use rd_interface::{config::EmptyConfig, registry::Builder, Net, Result};
+
+use crate::tests::TestNet;
+
+/// An in-memory network. Every `mem` net has its own address space on
+/// localhost, so a client and a server can be wired together without
+/// touching real sockets.
+pub struct MemNet;
+
+impl Builder<Net> for MemNet {
+    const NAME: &'static str = "mem";
+    const TITLE: &'static str = "In-memory";
+    type Config = EmptyConfig;
+    type Item = TestNet;
+
+    fn build(_config: Self::Config) -> Result<Self::Item> {
+        Ok(TestNet::new())
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{self, Poll},
+};
+
+use futures::ready;
+use rd_interface::{
+    async_trait, config::NetRef, prelude::*, registry::Builder, Address, AsyncRead, AsyncWrite,
+    Context, INet, ITcpStream, IntoDyn, Net, ReadBuf, Result, TcpStream,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+};
+
+/// Which bytes of a connection are copied to the mirror.
+#[rd_config]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorDirection {
+    /// the bytes sent to the destination
+    #[default]
+    Outbound,
+    /// the bytes received from the destination
+    Inbound,
+    /// both of them, in the order they are transferred
+    Both,
+}
+
+impl MirrorDirection {
+    fn outbound(self) -> bool {
+        matches!(self, MirrorDirection::Outbound | MirrorDirection::Both)
+    }
+    fn inbound(self) -> bool {
+        matches!(self, MirrorDirection::Inbound | MirrorDirection::Both)
+    }
+}
+
+/// Copies the bytes of each TCP connection to a connection made through `mirror`,
+/// e.g. for debugging or analytics. The errors of the mirror are ignored, and a
+/// mirror falling more than `buffer` bytes behind is cut off, so the connection
+/// through `net` is never affected by it.
+#[rd_config]
+#[derive(Debug)]
+pub struct MirrorNetConfig {
+    #[serde(default)]
+    net: NetRef,
+    /// the net to connect to the mirror by
+    #[serde(default)]
+    mirror: NetRef,
+    /// the address of the mirror
+    mirror_addr: Address,
+    #[serde(default)]
+    direction: MirrorDirection,
+    /// maximum bytes waiting to be sent to the mirror. default is 256KB.
+    #[serde(default = "default_buffer")]
+    buffer: usize,
+}
+
+fn default_buffer() -> usize {
+    256 * 1024
+}
+
+pub struct MirrorNet {
+    net: Net,
+    mirror: Net,
+    mirror_addr: Address,
+    direction: MirrorDirection,
+    buffer: usize,
+}
+
+impl MirrorNet {
+    pub fn new(net: Net, mirror: Net, mirror_addr: Address) -> MirrorNet {
+        MirrorNet {
+            net,
+            mirror,
+            mirror_addr,
+            direction: MirrorDirection::default(),
+            buffer: default_buffer(),
+        }
+    }
+    pub fn direction(mut self, direction: MirrorDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+    /// Cut off the mirror when more than `bytes` are waiting to be sent to it.
+    pub fn buffer(mut self, bytes: usize) -> Self {
+        self.buffer = bytes;
+        self
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for MirrorNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let tcp = self.net.tcp_connect(ctx, addr).await?;
+
+        let (tx, rx) = unbounded_channel();
+        let buffered = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(send_to_mirror(
+            self.mirror.clone(),
+            self.mirror_addr.clone(),
+            rx,
+            buffered.clone(),
+        ));
+
+        Ok(MirrorTcp {
+            tcp,
+            mirror: Some(Mirror {
+                tx,
+                buffered,
+                limit: self.buffer,
+            }),
+            direction: self.direction,
+        }
+        .into_dyn())
+    }
+}
+
+impl INet for MirrorNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        self.net.provide_udp_bind()
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+impl Builder<Net> for MirrorNet {
+    const NAME: &'static str = "mirror";
+    const TITLE: &'static str = "Mirror";
+    type Config = MirrorNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        Ok(MirrorNet::new(
+            config.net.value_cloned(),
+            config.mirror.value_cloned(),
+            config.mirror_addr,
+        )
+        .direction(config.direction)
+        .buffer(config.buffer))
+    }
+}
+
+/// Sends the copied bytes to the mirror until the connection is closed or it fails.
+async fn send_to_mirror(
+    net: Net,
+    addr: Address,
+    mut rx: UnboundedReceiver<Vec<u8>>,
+    buffered: Arc<AtomicUsize>,
+) {
+    let mut tcp = match net.tcp_connect(&mut Context::new(), &addr).await {
+        Ok(tcp) => tcp,
+        Err(e) => {
+            tracing::debug!("Failed to connect to mirror {}: {:?}", addr, e);
+            return;
+        }
+    };
+    while let Some(data) = rx.recv().await {
+        if let Err(e) = tcp.write_all(&data).await {
+            tracing::debug!("Failed to send to mirror {}: {:?}", addr, e);
+            return;
+        }
+        buffered.fetch_sub(data.len(), Ordering::Relaxed);
+    }
+    let _ = tcp.shutdown().await;
+}
+
+struct Mirror {
+    tx: UnboundedSender<Vec<u8>>,
+    buffered: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl Mirror {
+    /// Returns false if the mirror is gone or too far behind.
+    fn send(&self, data: &[u8]) -> bool {
+        let buffered = self.buffered.fetch_add(data.len(), Ordering::Relaxed) + data.len();
+        if buffered > self.limit {
+            tracing::debug!("Mirror is {} bytes behind, cut off", buffered);
+            return false;
+        }
+        self.tx.send(data.to_vec()).is_ok()
+    }
+}
+
+struct MirrorTcp {
+    tcp: TcpStream,
+    mirror: Option<Mirror>,
+    direction: MirrorDirection,
+}
+
+impl MirrorTcp {
+    fn copy(&mut self, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        if let Some(mirror) = &self.mirror {
+            if !mirror.send(data) {
+                self.mirror = None;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ITcpStream for MirrorTcp {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.tcp).poll_read(cx, buf))?;
+        if self.direction.inbound() {
+            self.copy(&buf.filled()[filled..]);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write(&mut self, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.tcp).poll_write(cx, buf))?;
+        if self.direction.outbound() {
+            self.copy(&buf[..written]);
+        }
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.tcp).poll_flush(cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.tcp).poll_shutdown(cx)
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        self.tcp.peer_addr().await
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.tcp.local_addr().await
+    }
+
+    fn set_reset_on_drop(&mut self) -> bool {
+        self.tcp.set_reset_on_drop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rd_interface::IntoAddress;
+    use tokio::{io::AsyncReadExt, time::timeout};
+
+    use super::*;
+    use crate::tests::{
+        assert_echo, assert_net_provider, spawn_echo_server, ProviderCapability, TestNet,
+    };
+
+    #[test]
+    fn test_provider() {
+        let net = TestNet::new().into_dyn();
+        assert_net_provider(
+            &MirrorNet::new(net.clone(), net, "127.0.0.1:26680".into_address().unwrap()).into_dyn(),
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mirror() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "127.0.0.1:26666").await;
+        let mirror_addr = "127.0.0.1:26680".into_address().unwrap();
+        let listener = net
+            .tcp_bind(&mut Context::new(), &mirror_addr)
+            .await
+            .unwrap();
+
+        let mirror = MirrorNet::new(net.clone(), net.clone(), mirror_addr)
+            .direction(MirrorDirection::Both)
+            .into_dyn();
+        let mut tcp = mirror
+            .tcp_connect(
+                &mut Context::new(),
+                &"127.0.0.1:26666".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        tcp.write_all(b"hello").await.unwrap();
+        let mut buf = [0; 5];
+        tcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        drop(tcp);
+
+        let (mut mirrored, _) = timeout(Duration::from_secs(1), listener.accept())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut buf = Vec::new();
+        timeout(Duration::from_secs(1), mirrored.read_to_end(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buf, b"hellohello");
+    }
+
+    #[tokio::test]
+    async fn test_broken_mirror() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "127.0.0.1:26666").await;
+
+        // nothing is listening on the mirror
+        let mirror = MirrorNet::new(
+            net.clone(),
+            net.clone(),
+            "127.0.0.1:26680".into_address().unwrap(),
+        )
+        .into_dyn();
+        assert_echo(&mirror, "127.0.0.1:26666").await;
+
+        // the mirror is cut off before the first write
+        let mirror = MirrorNet::new(
+            net.clone(),
+            net.clone(),
+            "127.0.0.1:26666".into_address().unwrap(),
+        )
+        .buffer(1)
+        .into_dyn();
+        assert_echo(&mirror, "127.0.0.1:26666").await;
+    }
+}
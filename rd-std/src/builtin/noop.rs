@@ -1,14 +1,17 @@
-use crate::util::NotImplementedNet;
+use super::drop::DropNet;
 use rd_interface::{config::EmptyConfig, registry::Builder, Net, Result};
 
+/// Same as `drop`, accepts everything and discards it.
+/// Unlike `blackhole`, which refuses everything.
 pub struct NoopNet;
 
 impl Builder<Net> for NoopNet {
     const NAME: &'static str = "noop";
+    const TITLE: &'static str = "No-op";
     type Config = EmptyConfig;
-    type Item = NotImplementedNet;
+    type Item = DropNet;
 
     fn build(_config: Self::Config) -> Result<Self::Item> {
-        Ok(NotImplementedNet)
+        Ok(DropNet)
     }
 }
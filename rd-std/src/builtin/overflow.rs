@@ -0,0 +1,140 @@
+use rd_interface::{
+    async_trait, config::NetRef, context::common_field::ActiveConnections, prelude::*,
+    registry::Builder, Address, Context, Error, INet, Net, Result, TcpStream,
+};
+
+/// Sends the TCP connections to a destination through `overflow` when it already
+/// has `max_connections` open, and the others through `net`, e.g. to get around a
+/// destination throttling each connection. The open connections are counted by the
+/// connection manager, through any net or server. Without it, e.g. when the net
+/// is not used by a server, all connections go through `net`.
+#[rd_config]
+#[derive(Debug)]
+pub struct OverflowNetConfig {
+    #[serde(default)]
+    net: NetRef,
+    /// the net of the connections over `max_connections`
+    overflow: NetRef,
+    /// maximum open connections to each destination before overflowing
+    max_connections: usize,
+}
+
+pub struct OverflowNet {
+    net: Net,
+    overflow: Net,
+    max_connections: usize,
+}
+
+impl OverflowNet {
+    pub fn new(net: Net, overflow: Net, max_connections: usize) -> OverflowNet {
+        OverflowNet {
+            net,
+            overflow,
+            max_connections,
+        }
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for OverflowNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let active = ctx
+            .get_common::<ActiveConnections>()?
+            .map(|ActiveConnections(active)| active)
+            .unwrap_or(0);
+        if active >= self.max_connections {
+            tracing::debug!("Connections to {} overflow, {} open", addr, active);
+            return self.overflow.tcp_connect(ctx, addr).await;
+        }
+        self.net.tcp_connect(ctx, addr).await
+    }
+}
+
+impl INet for OverflowNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        self.net.provide_udp_bind()
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+impl Builder<Net> for OverflowNet {
+    const NAME: &'static str = "overflow";
+    const TITLE: &'static str = "Overflow";
+    type Config = OverflowNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        if config.max_connections == 0 {
+            return Err(Error::other("max_connections must be greater than 0"));
+        }
+        Ok(OverflowNet::new(
+            config.net.value_cloned(),
+            config.overflow.value_cloned(),
+            config.max_connections,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::{IntoAddress, IntoDyn};
+
+    use super::*;
+    use crate::tests::{
+        assert_echo, assert_net_provider, spawn_echo_server, ProviderCapability, TestNet,
+    };
+
+    async fn connect(net: &Net, addr: &str, active: Option<usize>) -> Result<TcpStream> {
+        let mut ctx = Context::new();
+        if let Some(active) = active {
+            ctx.insert_common(ActiveConnections(active)).unwrap();
+        }
+        net.tcp_connect(&mut ctx, &addr.into_address().unwrap())
+            .await
+    }
+
+    #[test]
+    fn test_provider() {
+        let net = TestNet::new().into_dyn();
+        assert_net_provider(
+            &OverflowNet::new(net.clone(), net, 1).into_dyn(),
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_overflow() {
+        // the destination is only reachable through the primary net
+        let primary = TestNet::new().into_dyn();
+        let overflow = TestNet::new().into_dyn();
+        spawn_echo_server(&primary, "127.0.0.1:26666").await;
+
+        let net = OverflowNet::new(primary, overflow, 2).into_dyn();
+
+        assert!(connect(&net, "127.0.0.1:26666", Some(1)).await.is_ok());
+        assert!(connect(&net, "127.0.0.1:26666", Some(2)).await.is_err());
+        // not counted
+        assert!(connect(&net, "127.0.0.1:26666", None).await.is_ok());
+        assert_echo(&net, "127.0.0.1:26666").await;
+    }
+}
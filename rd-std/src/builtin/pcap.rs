@@ -0,0 +1,599 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+    task::{self, Poll},
+    time::SystemTime,
+};
+
+use futures::ready;
+use parking_lot::Mutex;
+use rd_interface::{
+    async_trait, prelude::*, registry::Builder, registry::NetRef, Address, AsyncRead, AsyncWrite,
+    Context, INet, ITcpStream, IUdpSocket, IntoDyn, Net, ReadBuf, Result, TcpStream, UdpSocket,
+};
+
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+const MSS: usize = 1460;
+
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Records the data of the TCP connections and UDP packets through `net` to a pcap
+/// file, with made up IP, TCP and UDP headers, to be opened in Wireshark.
+///
+/// The capture contains the plain data before encryption by the proxies, which may
+/// include passwords, cookies and other private data. Only use it for debugging,
+/// don't share the file, and remove it afterwards.
+#[rd_config]
+#[derive(Debug)]
+pub struct PcapNetConfig {
+    #[serde(default)]
+    net: NetRef,
+    /// path of the pcap file, overwritten if it exists.
+    path: String,
+    /// stop recording when the file reaches this size, in bytes. 0 for unlimited.
+    #[serde(default = "default_max_size")]
+    max_size: u64,
+}
+
+fn default_max_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+pub struct PcapNet {
+    net: Net,
+    writer: Arc<Mutex<PcapWriter>>,
+}
+
+impl PcapNet {
+    pub fn new(net: Net, path: &str, max_size: u64) -> Result<PcapNet> {
+        Ok(PcapNet {
+            net,
+            writer: Arc::new(Mutex::new(PcapWriter::create(path, max_size)?)),
+        })
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for PcapNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let tcp = self.net.tcp_connect(ctx, addr).await?;
+
+        let local = tcp.local_addr().await.ok();
+        let peer = match addr {
+            Address::SocketAddr(addr) => Some(*addr),
+            Address::Domain(_, _) => tcp.peer_addr().await.ok(),
+        };
+        let client = local.unwrap_or_else(unknown_endpoint);
+        let server = peer.unwrap_or_else(|| SocketAddr::new(unspecified(), addr.port()));
+
+        Ok(PcapTcp::new(tcp, TcpFlow::new(self.writer.clone(), client, server)).into_dyn())
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for PcapNet {
+    async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
+        let udp = self.net.udp_bind(ctx, addr).await?;
+        let local = udp
+            .local_addr()
+            .await
+            .unwrap_or_else(|_| unknown_endpoint());
+
+        Ok(PcapUdp {
+            udp,
+            writer: self.writer.clone(),
+            local,
+        }
+        .into_dyn())
+    }
+}
+
+impl INet for PcapNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        Some(self)
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+impl Builder<Net> for PcapNet {
+    const NAME: &'static str = "pcap";
+    const TITLE: &'static str = "Packet Capture";
+    type Config = PcapNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        tracing::warn!(
+            "Recording the plain data of the connections to {}, it may contain private data.",
+            config.path
+        );
+        PcapNet::new(config.net.value_cloned(), &config.path, config.max_size)
+    }
+}
+
+fn unspecified() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+/// An endpoint with a unique port, for the sockets without a local address,
+/// so their packets aren't mixed up in the capture.
+fn unknown_endpoint() -> SocketAddr {
+    static NEXT_PORT: AtomicU16 = AtomicU16::new(10000);
+    SocketAddr::new(unspecified(), NEXT_PORT.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Writes the packets in the classic pcap format, stops when `max_size` is reached
+/// or on the first error.
+struct PcapWriter {
+    file: BufWriter<File>,
+    size: u64,
+    max_size: u64,
+    stopped: bool,
+}
+
+impl PcapWriter {
+    fn create(path: &str, max_size: u64) -> io::Result<PcapWriter> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        file.write_all(&0xa1b2c3d4u32.to_le_bytes())?;
+        file.write_all(&2u16.to_le_bytes())?;
+        file.write_all(&4u16.to_le_bytes())?;
+        // thiszone and sigfigs
+        file.write_all(&[0u8; 8])?;
+        file.write_all(&SNAPLEN.to_le_bytes())?;
+        file.write_all(&LINKTYPE_RAW.to_le_bytes())?;
+        file.flush()?;
+
+        Ok(PcapWriter {
+            file,
+            size: 24,
+            max_size,
+            stopped: false,
+        })
+    }
+
+    fn write_packet(&mut self, packet: &[u8]) {
+        if self.stopped {
+            return;
+        }
+        let record_size = 16 + packet.len() as u64;
+        if self.max_size != 0 && self.size + record_size > self.max_size {
+            tracing::warn!("The pcap file reaches max_size, stop recording.");
+            self.stopped = true;
+            return;
+        }
+        if let Err(e) = self.write_record(packet) {
+            tracing::warn!("Failed to write the pcap file, stop recording: {:?}", e);
+            self.stopped = true;
+            return;
+        }
+        self.size += record_size;
+    }
+
+    fn write_record(&mut self, packet: &[u8]) -> io::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let len = packet.len() as u32;
+
+        self.file.write_all(&(now.as_secs() as u32).to_le_bytes())?;
+        self.file.write_all(&now.subsec_micros().to_le_bytes())?;
+        self.file.write_all(&len.to_le_bytes())?;
+        self.file.write_all(&len.to_le_bytes())?;
+        self.file.write_all(packet)?;
+        self.file.flush()
+    }
+}
+
+/// Builds an IP packet. IPv4 addresses are mapped if the other one is IPv6.
+/// Only the IPv4 header checksum is filled, Wireshark doesn't check the others by default.
+fn ip_packet(src: IpAddr, dst: IpAddr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            let mut packet = Vec::with_capacity(20 + payload.len());
+            packet.extend_from_slice(&[0x45, 0]);
+            packet.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+            // id, don't fragment
+            packet.extend_from_slice(&[0, 0, 0x40, 0]);
+            packet.extend_from_slice(&[64, protocol, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+
+            let checksum = ipv4_checksum(&packet);
+            packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+            packet.extend_from_slice(payload);
+            packet
+        }
+        (src, dst) => {
+            let v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let mut packet = Vec::with_capacity(40 + payload.len());
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            packet.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[protocol, 64]);
+            packet.extend_from_slice(&v6(src).octets());
+            packet.extend_from_slice(&v6(dst).octets());
+            packet.extend_from_slice(payload);
+            packet
+        }
+    }
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let mut sum = header
+        .chunks(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn udp_packet(src: SocketAddr, dst: SocketAddr, data: &[u8]) -> Vec<u8> {
+    let mut udp = Vec::with_capacity(8 + data.len());
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&((8 + data.len()) as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(data);
+    ip_packet(src.ip(), dst.ip(), IPPROTO_UDP, &udp)
+}
+
+/// Makes up the TCP segments of a connection, from the handshake to the FINs.
+struct TcpFlow {
+    writer: Arc<Mutex<PcapWriter>>,
+    client: SocketAddr,
+    server: SocketAddr,
+    client_seq: u32,
+    server_seq: u32,
+    client_fin: bool,
+    server_fin: bool,
+}
+
+impl TcpFlow {
+    fn new(writer: Arc<Mutex<PcapWriter>>, client: SocketAddr, server: SocketAddr) -> TcpFlow {
+        let mut flow = TcpFlow {
+            writer,
+            client,
+            server,
+            client_seq: 0,
+            server_seq: 0,
+            client_fin: false,
+            server_fin: false,
+        };
+        flow.segment(true, TCP_SYN, &[]);
+        flow.segment(false, TCP_SYN | TCP_ACK, &[]);
+        flow.segment(true, TCP_ACK, &[]);
+        flow
+    }
+
+    fn data(&mut self, from_client: bool, data: &[u8]) {
+        for chunk in data.chunks(MSS) {
+            self.segment(from_client, TCP_PSH | TCP_ACK, chunk);
+        }
+    }
+
+    fn fin(&mut self, from_client: bool) {
+        let fin = match from_client {
+            true => &mut self.client_fin,
+            false => &mut self.server_fin,
+        };
+        if !*fin {
+            *fin = true;
+            self.segment(from_client, TCP_FIN | TCP_ACK, &[]);
+        }
+    }
+
+    fn segment(&mut self, from_client: bool, flags: u8, data: &[u8]) {
+        let (src, dst, seq, ack) = match from_client {
+            true => (self.client, self.server, self.client_seq, self.server_seq),
+            false => (self.server, self.client, self.server_seq, self.client_seq),
+        };
+        let ack = if flags & TCP_ACK != 0 { ack } else { 0 };
+
+        let mut tcp = Vec::with_capacity(20 + data.len());
+        tcp.extend_from_slice(&src.port().to_be_bytes());
+        tcp.extend_from_slice(&dst.port().to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&ack.to_be_bytes());
+        tcp.extend_from_slice(&[5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend_from_slice(data);
+        self.writer
+            .lock()
+            .write_packet(&ip_packet(src.ip(), dst.ip(), IPPROTO_TCP, &tcp));
+
+        // SYN and FIN take a sequence number
+        let len = data.len() as u32 + (flags & (TCP_SYN | TCP_FIN) != 0) as u32;
+        let seq = match from_client {
+            true => &mut self.client_seq,
+            false => &mut self.server_seq,
+        };
+        *seq = seq.wrapping_add(len);
+    }
+}
+
+impl Drop for TcpFlow {
+    fn drop(&mut self) {
+        self.fin(true);
+        self.fin(false);
+    }
+}
+
+struct PcapTcp {
+    tcp: TcpStream,
+    flow: TcpFlow,
+}
+
+impl PcapTcp {
+    fn new(tcp: TcpStream, flow: TcpFlow) -> PcapTcp {
+        PcapTcp { tcp, flow }
+    }
+}
+
+#[async_trait]
+impl ITcpStream for PcapTcp {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.tcp).poll_read(cx, buf))?;
+
+        let read = &buf.filled()[filled..];
+        if read.is_empty() {
+            self.flow.fin(false);
+        } else {
+            self.flow.data(false, read);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write(&mut self, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.tcp).poll_write(cx, buf))?;
+        self.flow.data(true, &buf[..written]);
+
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.tcp).poll_flush(cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        ready!(Pin::new(&mut self.tcp).poll_shutdown(cx))?;
+        self.flow.fin(true);
+
+        Poll::Ready(Ok(()))
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        self.tcp.peer_addr().await
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.tcp.local_addr().await
+    }
+}
+
+struct PcapUdp {
+    udp: UdpSocket,
+    writer: Arc<Mutex<PcapWriter>>,
+    local: SocketAddr,
+}
+
+#[async_trait]
+impl IUdpSocket for PcapUdp {
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        let filled = buf.filled().len();
+        let from = ready!(self.udp.poll_recv_from(cx, buf))?;
+
+        let packet = udp_packet(from, self.local, &buf.filled()[filled..]);
+        self.writer.lock().write_packet(&packet);
+
+        Poll::Ready(Ok(from))
+    }
+
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &Address,
+    ) -> Poll<io::Result<usize>> {
+        let sent = ready!(self.udp.poll_send_to(cx, buf, target))?;
+
+        let to = match target {
+            Address::SocketAddr(addr) => *addr,
+            Address::Domain(_, port) => SocketAddr::new(unspecified(), *port),
+        };
+        let packet = udp_packet(self.local, to, &buf[..sent]);
+        self.writer.lock().write_packet(&packet);
+
+        Poll::Ready(Ok(sent))
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.udp.local_addr().await
+    }
+
+    fn recv_buffer_size(&self) -> Option<usize> {
+        self.udp.recv_buffer_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::IntoAddress;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        task::yield_now,
+    };
+
+    use super::*;
+    use crate::tests::{
+        assert_net_provider, spawn_echo_server, spawn_echo_server_udp, ProviderCapability, TestNet,
+    };
+
+    /// The IP payloads of the packets in a pcap file.
+    fn read_pcap(path: &str) -> Vec<(u8, Vec<u8>)> {
+        let file = std::fs::read(path).unwrap();
+        assert_eq!(&file[0..4], &0xa1b2c3d4u32.to_le_bytes());
+        assert_eq!(&file[20..24], &LINKTYPE_RAW.to_le_bytes());
+
+        let mut packets = Vec::new();
+        let mut rest = &file[24..];
+        while !rest.is_empty() {
+            let len = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+            let packet = &rest[16..16 + len];
+            assert_eq!(packet[0], 0x45);
+            assert_eq!(u16::from_be_bytes([packet[2], packet[3]]) as usize, len);
+            assert_eq!(ipv4_checksum(&packet[..20]), 0);
+
+            packets.push((packet[9], packet[20..].to_vec()));
+            rest = &rest[16 + len..];
+        }
+        packets
+    }
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("rd-{}-{}.pcap", name, std::process::id()))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn test_provider() {
+        let path = temp_path("pcap-provider");
+        let net = PcapNet::new(TestNet::new().into_dyn(), &path, 0)
+            .unwrap()
+            .into_dyn();
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_pcap() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:26669").await;
+        spawn_echo_server_udp(&test_net, "127.0.0.1:26669").await;
+        yield_now().await;
+
+        let path = temp_path("pcap");
+        let net = PcapNet::new(test_net, &path, 0).unwrap().into_dyn();
+        let addr = "127.0.0.1:26669".into_address().unwrap();
+
+        let mut tcp = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        tcp.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        tcp.read_exact(&mut buf).await.unwrap();
+        drop(tcp);
+
+        let mut udp = net
+            .udp_bind(&mut Context::new(), &"0.0.0.0:0".into_address().unwrap())
+            .await
+            .unwrap();
+        udp.send_to(b"world", &addr).await.unwrap();
+        let mut buf = [0u8; 5];
+        udp.recv_from(&mut ReadBuf::new(&mut buf)).await.unwrap();
+
+        let packets = read_pcap(&path);
+        let tcp_flags = packets
+            .iter()
+            .filter(|(protocol, _)| *protocol == IPPROTO_TCP)
+            .map(|(_, tcp)| (tcp[13], tcp[20..].to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tcp_flags,
+            vec![
+                (TCP_SYN, vec![]),
+                (TCP_SYN | TCP_ACK, vec![]),
+                (TCP_ACK, vec![]),
+                (TCP_PSH | TCP_ACK, b"hello".to_vec()),
+                (TCP_PSH | TCP_ACK, b"hello".to_vec()),
+                (TCP_FIN | TCP_ACK, vec![]),
+                (TCP_FIN | TCP_ACK, vec![]),
+            ]
+        );
+        // the server port
+        assert_eq!(&packets[0].1[2..4], &26669u16.to_be_bytes());
+
+        let udp = packets
+            .iter()
+            .filter(|(protocol, _)| *protocol == IPPROTO_UDP)
+            .map(|(_, udp)| udp[8..].to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(udp, vec![b"world".to_vec(), b"world".to_vec()]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_size() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_echo_server(&test_net, "127.0.0.1:26670").await;
+        yield_now().await;
+
+        let path = temp_path("pcap-max-size");
+        let net = PcapNet::new(test_net, &path, 1000).unwrap().into_dyn();
+        let mut tcp = net
+            .tcp_connect(
+                &mut Context::new(),
+                &"127.0.0.1:26670".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        tcp.write_all(&[1u8; 2000]).await.unwrap();
+        drop(tcp);
+
+        // the handshake fits, the data doesn't
+        let packets = read_pcap(&path);
+        assert_eq!(packets.len(), 3);
+        assert!(std::fs::metadata(&path).unwrap().len() <= 1000);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
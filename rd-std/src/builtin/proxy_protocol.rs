@@ -0,0 +1,141 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use rd_interface::{
+    async_trait, config::NetRef, context::common_field::SrcSocketAddr, prelude::*,
+    registry::Builder, Address, Context, INet, Net, Result, TcpStream,
+};
+use tokio::io::AsyncWriteExt;
+
+use crate::util::proxy_protocol::{encode_header, ProxyProtocolVersion};
+
+/// Sends the PROXY protocol header on outbound connections, so the next hop
+/// can get the source address of the client.
+#[rd_config]
+#[derive(Debug)]
+pub struct ProxyProtocolNetConfig {
+    #[serde(default)]
+    net: NetRef,
+    #[serde(default)]
+    version: ProxyProtocolVersion,
+}
+
+pub struct ProxyProtocolNet {
+    net: Net,
+    version: ProxyProtocolVersion,
+}
+
+impl ProxyProtocolNet {
+    pub fn new(net: Net, version: ProxyProtocolVersion) -> ProxyProtocolNet {
+        ProxyProtocolNet { net, version }
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for ProxyProtocolNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let src = ctx.get_common::<SrcSocketAddr>()?.map(|i| i.0);
+        let dst = match addr {
+            Address::SocketAddr(s) => *s,
+            // the destination is unknown yet, use the unspecified address
+            Address::Domain(_, port) => {
+                let ip: IpAddr = match src {
+                    Some(SocketAddr::V6(_)) => Ipv6Addr::UNSPECIFIED.into(),
+                    _ => Ipv4Addr::UNSPECIFIED.into(),
+                };
+                SocketAddr::new(ip, *port)
+            }
+        };
+
+        let mut tcp = self.net.tcp_connect(ctx, addr).await?;
+        tcp.write_all(&encode_header(self.version, src, dst))
+            .await?;
+
+        Ok(tcp)
+    }
+}
+
+impl INet for ProxyProtocolNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        self.net.provide_udp_bind()
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+impl Builder<Net> for ProxyProtocolNet {
+    const NAME: &'static str = "proxy_protocol";
+    const TITLE: &'static str = "PROXY Protocol";
+    type Config = ProxyProtocolNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        Ok(ProxyProtocolNet::new(
+            config.net.value_cloned(),
+            config.version,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::{IntoAddress, IntoDyn};
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::{
+        tests::{assert_net_provider, ProviderCapability, TestNet},
+        util::proxy_protocol::read_header,
+    };
+
+    #[test]
+    fn test_provider() {
+        let net = TestNet::new().into_dyn();
+        let net = ProxyProtocolNet::new(net, ProxyProtocolVersion::V2).into_dyn();
+
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_net() {
+        let test_net = TestNet::new().into_dyn();
+        let addr = "127.0.0.1:1234".into_address().unwrap();
+        let listener = test_net.tcp_bind(&mut Context::new(), &addr).await.unwrap();
+
+        let net = ProxyProtocolNet::new(test_net.clone(), ProxyProtocolVersion::V2).into_dyn();
+        let src: SocketAddr = "192.168.0.1:56324".parse().unwrap();
+        let mut client = net
+            .tcp_connect(&mut Context::from_socketaddr(src), &addr)
+            .await
+            .unwrap();
+        client.write_all(b"hello").await.unwrap();
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        assert_eq!(read_header(&mut server).await.unwrap(), Some(src));
+
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{self, Poll},
+    time::Duration,
+};
+
+use futures::{ready, Future};
+use parking_lot::Mutex;
+use rd_interface::{
+    async_trait,
+    context::common_field::Priority,
+    prelude::*,
+    registry::{Builder, NetRef},
+    Address, AsyncRead, AsyncWrite, Context, Error, INet, ITcpStream, IntoDyn, Net, ReadBuf,
+    Result, TcpStream,
+};
+use tokio::time::{sleep_until, Instant, Sleep};
+
+/// A class is sharing the bandwidth if it transferred in this window.
+const ACTIVE_WINDOW: Duration = Duration::from_millis(100);
+
+/// Limits the total bandwidth of the TCP connections through `net`. When the
+/// bandwidth is shared, the connections of a higher `priority` get a larger part
+/// of it, 4:2:1 for `high`, `normal` and `low`. The priority is set by the rules.
+#[rd_config]
+#[derive(Debug)]
+pub struct RateLimitNetConfig {
+    #[serde(default)]
+    net: NetRef,
+    /// bytes per second of all the uploads.
+    upload: u64,
+    /// bytes per second of all the downloads.
+    download: u64,
+}
+
+fn weight(priority: Priority) -> u32 {
+    match priority {
+        Priority::High => 4,
+        Priority::Normal => 2,
+        Priority::Low => 1,
+    }
+}
+
+fn class(priority: Priority) -> usize {
+    match priority {
+        Priority::Low => 0,
+        Priority::Normal => 1,
+        Priority::High => 2,
+    }
+}
+
+const PRIORITIES: [Priority; 3] = [Priority::Low, Priority::Normal, Priority::High];
+
+/// Schedules the transfers of one direction. Each priority class sends at its
+/// weighted share of the bandwidth among the active classes, and the connections
+/// of the same class queue up behind each other.
+struct Limiter {
+    bandwidth: f64,
+    /// When each class finishes sending its queued bytes.
+    ready_at: Mutex<[Option<Instant>; 3]>,
+}
+
+impl Limiter {
+    fn new(bandwidth: u64) -> Arc<Limiter> {
+        Arc::new(Limiter {
+            bandwidth: bandwidth as f64,
+            ready_at: Mutex::new([None; 3]),
+        })
+    }
+
+    /// Queues `bytes` of `priority`, returns when the following transfer can start.
+    fn schedule(&self, priority: Priority, bytes: usize) -> Instant {
+        let now = Instant::now();
+        let mut ready_at = self.ready_at.lock();
+
+        let active_weight = PRIORITIES
+            .iter()
+            .filter(|p| {
+                **p == priority
+                    || matches!(ready_at[class(**p)], Some(t) if t + ACTIVE_WINDOW > now)
+            })
+            .map(|p| weight(*p))
+            .sum::<u32>();
+        let rate = self.bandwidth * weight(priority) as f64 / active_weight as f64;
+
+        let start = ready_at[class(priority)].map_or(now, |t| t.max(now));
+        let end = start + Duration::from_secs_f64(bytes as f64 / rate);
+        ready_at[class(priority)] = Some(end);
+        end
+    }
+}
+
+pub struct RateLimitNet {
+    net: Net,
+    upload: Arc<Limiter>,
+    download: Arc<Limiter>,
+}
+
+impl RateLimitNet {
+    pub fn new(net: Net, upload: u64, download: u64) -> RateLimitNet {
+        RateLimitNet {
+            net,
+            upload: Limiter::new(upload),
+            download: Limiter::new(download),
+        }
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for RateLimitNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let priority = ctx.get_common::<Priority>()?.unwrap_or_default();
+        let tcp = self.net.tcp_connect(ctx, addr).await?;
+        Ok(RateLimitTcp {
+            tcp,
+            priority,
+            upload: self.upload.clone(),
+            download: self.download.clone(),
+            read_delay: None,
+            write_delay: None,
+        }
+        .into_dyn())
+    }
+}
+
+impl INet for RateLimitNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        self.net.provide_udp_bind()
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+impl Builder<Net> for RateLimitNet {
+    const NAME: &'static str = "rate_limit";
+    const TITLE: &'static str = "Rate Limit";
+    type Config = RateLimitNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        if config.upload == 0 || config.download == 0 {
+            return Err(Error::other("upload and download must be greater than 0"));
+        }
+        Ok(RateLimitNet::new(
+            config.net.value_cloned(),
+            config.upload,
+            config.download,
+        ))
+    }
+}
+
+struct RateLimitTcp {
+    tcp: TcpStream,
+    priority: Priority,
+    upload: Arc<Limiter>,
+    download: Arc<Limiter>,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+fn poll_delay(delay: &mut Option<Pin<Box<Sleep>>>, cx: &mut task::Context<'_>) -> Poll<()> {
+    if let Some(sleep) = delay {
+        ready!(sleep.as_mut().poll(cx));
+        *delay = None;
+    }
+    Poll::Ready(())
+}
+
+fn delay(limiter: &Limiter, priority: Priority, bytes: usize) -> Option<Pin<Box<Sleep>>> {
+    if bytes == 0 {
+        return None;
+    }
+    Some(Box::pin(sleep_until(limiter.schedule(priority, bytes))))
+}
+
+#[async_trait]
+impl ITcpStream for RateLimitTcp {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        ready!(poll_delay(&mut self.read_delay, cx));
+
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut self.tcp).poll_read(cx, buf))?;
+        self.read_delay = delay(&self.download, self.priority, buf.filled().len() - filled);
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write(&mut self, cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(poll_delay(&mut self.write_delay, cx));
+
+        let written = ready!(Pin::new(&mut self.tcp).poll_write(cx, buf))?;
+        self.write_delay = delay(&self.upload, self.priority, written);
+
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.tcp).poll_flush(cx)
+    }
+
+    fn poll_shutdown(&mut self, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.tcp).poll_shutdown(cx)
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        self.tcp.peer_addr().await
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.tcp.local_addr().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::IntoAddress;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        task::yield_now,
+        time::{sleep, timeout},
+    };
+
+    use super::*;
+    use crate::tests::{assert_net_provider, ProviderCapability, TestNet};
+
+    /// A server discards everything it receives.
+    async fn spawn_discard_server(net: &Net, addr: &str) {
+        let listener = net
+            .tcp_bind(&mut Context::new(), &addr.into_address().unwrap())
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut tcp, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while tcp.read(&mut buf).await.unwrap_or(0) > 0 {}
+                });
+            }
+        });
+    }
+
+    /// Bytes uploaded in `duration`.
+    async fn upload(net: &Net, priority: Priority, duration: Duration) -> usize {
+        let mut ctx = Context::new();
+        ctx.insert_common(priority).unwrap();
+        let mut tcp = net
+            .tcp_connect(&mut ctx, &"127.0.0.1:26671".into_address().unwrap())
+            .await
+            .unwrap();
+
+        let mut sent = 0;
+        let _ = timeout(duration, async {
+            loop {
+                tcp.write_all(&[0u8; 1000]).await.unwrap();
+                sent += 1000;
+            }
+        })
+        .await;
+        sent
+    }
+
+    #[test]
+    fn test_provider() {
+        let net = RateLimitNet::new(TestNet::new().into_dyn(), 1000, 1000).into_dyn();
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_priority() {
+        let test_net = TestNet::new().into_dyn();
+        spawn_discard_server(&test_net, "127.0.0.1:26671").await;
+        yield_now().await;
+
+        let net = RateLimitNet::new(test_net, 100_000, 100_000).into_dyn();
+        let duration = Duration::from_millis(500);
+        let (high, low) = tokio::join!(
+            upload(&net, Priority::High, duration),
+            upload(&net, Priority::Low, duration)
+        );
+
+        assert!(high > low * 2, "high: {}, low: {}", high, low);
+        // the shared cap is kept
+        assert!(high + low <= 60_000, "high: {}, low: {}", high, low);
+
+        // the whole bandwidth is used by a single class once the others are idle
+        sleep(ACTIVE_WINDOW * 2).await;
+        let low = upload(&net, Priority::Low, duration).await;
+        assert!(low >= 40_000, "low: {}", low);
+    }
+}
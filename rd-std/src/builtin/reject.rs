@@ -0,0 +1,259 @@
+use std::{
+    io,
+    net::SocketAddr,
+    task::{self, Poll, Waker},
+};
+
+use hyper::StatusCode;
+use rd_interface::{
+    async_trait, context::common_field::SniffedProtocol, prelude::*, registry::Builder, Address,
+    Context, Error, INet, ITcpStream, IntoDyn, Net, ReadBuf, Result, TcpStream, UdpSocket,
+    NOT_IMPLEMENTED,
+};
+
+const HTTP_METHODS: &[&[u8]] = &[
+    b"GET", b"POST", b"HEAD", b"PUT", b"DELETE", b"OPTIONS", b"PATCH", b"CONNECT", b"TRACE",
+];
+
+/// Reject connections like `blackhole`, but HTTP requests can be answered with
+/// an empty response, so clients don't retry aggressively.
+#[rd_config]
+#[derive(Debug)]
+pub struct RejectNetConfig {
+    /// Respond to HTTP requests with this status instead of refusing them, e.g. 204.
+    /// Other traffic is always refused.
+    #[serde(default)]
+    http_status: Option<u16>,
+}
+
+pub struct RejectNet {
+    http_status: Option<StatusCode>,
+}
+
+impl RejectNet {
+    pub fn new(http_status: Option<StatusCode>) -> RejectNet {
+        RejectNet { http_status }
+    }
+}
+
+fn refused() -> io::Error {
+    io::ErrorKind::ConnectionRefused.into()
+}
+
+fn is_http_request(buf: &[u8]) -> bool {
+    match buf.iter().position(|b| *b == b' ') {
+        Some(pos) => HTTP_METHODS.contains(&&buf[..pos]),
+        None => false,
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for RejectNet {
+    async fn tcp_connect(&self, ctx: &mut Context, _addr: &Address) -> Result<TcpStream> {
+        let sniffed = ctx.get_common::<SniffedProtocol>()?.map(|p| p.0);
+        match (self.http_status, sniffed.as_deref()) {
+            // the protocol is unknown yet, wait for the request
+            (Some(status), None | Some("http")) => Ok(RejectTcp::new(status).into_dyn()),
+            _ => Err(refused().into()),
+        }
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for RejectNet {
+    async fn udp_bind(&self, _ctx: &mut Context, _addr: &Address) -> Result<UdpSocket> {
+        Err(refused().into())
+    }
+}
+
+impl INet for RejectNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        Some(self)
+    }
+}
+
+impl Builder<Net> for RejectNet {
+    const NAME: &'static str = "reject";
+    const TITLE: &'static str = "Reject";
+    type Config = RejectNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        let http_status = config
+            .http_status
+            .map(StatusCode::from_u16)
+            .transpose()
+            .map_err(|e| Error::other(format!("invalid http_status: {}", e)))?;
+        Ok(RejectNet::new(http_status))
+    }
+}
+
+enum State {
+    WaitingRequest(Option<Waker>),
+    Responding { response: Vec<u8>, pos: usize },
+    Refused,
+}
+
+/// Responds to an HTTP request with an empty response, refuses anything else.
+struct RejectTcp {
+    status: StatusCode,
+    state: State,
+}
+
+impl RejectTcp {
+    fn new(status: StatusCode) -> RejectTcp {
+        RejectTcp {
+            status,
+            state: State::WaitingRequest(None),
+        }
+    }
+}
+
+#[async_trait]
+impl ITcpStream for RejectTcp {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match &mut self.state {
+            State::WaitingRequest(waker) => {
+                *waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Responding { response, pos } => {
+                let to_copy = (response.len() - *pos).min(buf.remaining());
+                buf.put_slice(&response[*pos..*pos + to_copy]);
+                *pos += to_copy;
+                Poll::Ready(Ok(()))
+            }
+            State::Refused => Poll::Ready(Err(refused())),
+        }
+    }
+
+    fn poll_write(&mut self, _cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if let State::WaitingRequest(waker) = &mut self.state {
+            let waker = waker.take();
+            self.state = if is_http_request(buf) {
+                let response = format!(
+                    "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    self.status.as_u16(),
+                    self.status.canonical_reason().unwrap_or_default()
+                );
+                State::Responding {
+                    response: response.into_bytes(),
+                    pos: 0,
+                }
+            } else {
+                State::Refused
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+
+        match self.state {
+            // discard the request
+            State::Responding { .. } => Poll::Ready(Ok(buf.len())),
+            _ => Poll::Ready(Err(refused())),
+        }
+    }
+
+    fn poll_flush(&mut self, _cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(&mut self, _cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::IntoAddress;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::tests::{assert_net_provider, ProviderCapability};
+
+    fn reject_net(http_status: Option<u16>) -> Net {
+        RejectNet::build(RejectNetConfig { http_status })
+            .unwrap()
+            .into_dyn()
+    }
+
+    #[test]
+    fn test_provider() {
+        assert_net_provider(
+            &reject_net(None),
+            ProviderCapability {
+                tcp_connect: true,
+                udp_bind: true,
+                ..Default::default()
+            },
+        );
+        assert!(RejectNet::build(RejectNetConfig {
+            http_status: Some(1000)
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_response() {
+        let net = reject_net(Some(204));
+        let addr = "ads.example.com:80".into_address().unwrap();
+
+        let mut tcp = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        tcp.write_all(b"GET /ad.js HTTP/1.1\r\nHost: ads.example.com\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        tcp.read_to_string(&mut response).await.unwrap();
+        assert_eq!(
+            response,
+            "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refused() {
+        let addr = "ads.example.com:443".into_address().unwrap();
+        let is_refused = |r: Result<TcpStream>| match r {
+            Err(Error::IO(e)) => e.kind() == io::ErrorKind::ConnectionRefused,
+            _ => false,
+        };
+
+        // no HTTP response configured
+        let net = reject_net(None);
+        assert!(is_refused(
+            net.tcp_connect(&mut Context::new(), &addr).await
+        ));
+        assert!(net.udp_bind(&mut Context::new(), &addr).await.is_err());
+
+        // sniffed as TLS
+        let net = reject_net(Some(204));
+        let mut ctx = Context::new();
+        ctx.insert_common(SniffedProtocol("tls".to_string()))
+            .unwrap();
+        assert!(is_refused(net.tcp_connect(&mut ctx, &addr).await));
+
+        // not an HTTP request
+        let mut tcp = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        assert!(tcp.write_all(b"\x16\x03\x01\x02\x00").await.is_err());
+        let mut buf = [0u8; 16];
+        let err = tcp.read(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -1,16 +1,12 @@
 use std::{io, net::SocketAddr};
 
-use futures::{future::BoxFuture, FutureExt};
 use rd_interface::{
     async_trait,
     prelude::*,
     registry::{Builder, NetRef},
-    Address, Arc, INet, Net, Result, TcpStream, UdpSocket,
+    Address, Context, INet, Net, Result, TcpStream, UdpSocket,
 };
 
-type Resolver =
-    Arc<dyn Fn(String, u16) -> BoxFuture<'static, io::Result<Vec<SocketAddr>>> + Send + Sync>;
-
 // Resolves domain names to IP addresses before connecting.
 #[rd_config]
 #[derive(Debug)]
@@ -29,35 +25,38 @@ fn bool_true() -> bool {
 
 pub struct ResolveNet {
     net: Net,
-    resolver: Resolver,
+    resolve_net: Net,
+    ipv4: bool,
+    ipv6: bool,
 }
 
 impl ResolveNet {
     pub fn new(net: Net, resolve_net: Net, ipv4: bool, ipv6: bool) -> ResolveNet {
-        let resolver: Resolver = Arc::new(move |domain: String, port: u16| {
-            let resolve_net = resolve_net.clone();
-            async move {
-                Ok(resolve_net
-                    .lookup_host(&Address::Domain(domain, port))
-                    .await?
-                    .into_iter()
-                    .filter(|i| (ipv4 && i.is_ipv4()) || (ipv6 && i.is_ipv6()))
-                    .collect())
-            }
-            .boxed()
-        });
-        ResolveNet { net, resolver }
+        ResolveNet {
+            net,
+            resolve_net,
+            ipv4,
+            ipv6,
+        }
+    }
+    async fn resolve(&self, ctx: &mut Context, addr: &Address) -> io::Result<Vec<SocketAddr>> {
+        addr.resolve(|domain, port| async move {
+            Ok(self
+                .resolve_net
+                .lookup_host(ctx, &Address::Domain(domain, port))
+                .await?
+                .into_iter()
+                .filter(|i| (self.ipv4 && i.is_ipv4()) || (self.ipv6 && i.is_ipv6()))
+                .collect())
+        })
+        .await
     }
 }
 
 #[async_trait]
 impl rd_interface::TcpConnect for ResolveNet {
-    async fn tcp_connect(
-        &self,
-        ctx: &mut rd_interface::Context,
-        addr: &Address,
-    ) -> Result<TcpStream> {
-        let addrs = addr.resolve(&*self.resolver).await?;
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let addrs = self.resolve(ctx, addr).await?;
         let mut last_err = None;
 
         for addr in addrs {
@@ -75,8 +74,8 @@ impl rd_interface::TcpConnect for ResolveNet {
 
 #[async_trait]
 impl rd_interface::UdpBind for ResolveNet {
-    async fn udp_bind(&self, ctx: &mut rd_interface::Context, addr: &Address) -> Result<UdpSocket> {
-        let addrs = addr.resolve(&*self.resolver).await?;
+    async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
+        let addrs = self.resolve(ctx, addr).await?;
         let mut last_err = None;
 
         for addr in addrs {
@@ -108,10 +107,15 @@ impl INet for ResolveNet {
     fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
         self.net.provide_lookup_host()
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 impl Builder<Net> for ResolveNet {
     const NAME: &'static str = "resolve";
+    const TITLE: &'static str = "Resolve Before Connecting";
     type Config = ResolveConfig;
     type Item = Self;
 
@@ -141,7 +145,7 @@ mod tests {
         let net = ResolveNet::new(test_net.clone(), test_net, true, true).into_dyn();
 
         let addr = Address::Domain("localhost".to_string(), 80);
-        let addrs = net.lookup_host(&addr).await.unwrap();
+        let addrs = net.lookup_host(&mut Context::new(), &addr).await.unwrap();
         let wanted = vec![SocketAddr::from(([127, 0, 0, 1], 80))];
 
         assert_eq!(addrs, wanted);
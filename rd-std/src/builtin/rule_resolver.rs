@@ -0,0 +1,181 @@
+use std::net::SocketAddr;
+
+use rd_interface::{
+    async_trait, config::NetRef, prelude::*, registry::Builder, Address, Context, Error, INet, Net,
+    Result,
+};
+
+#[rd_config]
+#[derive(Debug)]
+pub struct ResolverRule {
+    /// the domain suffixes, e.g. `corp` matches `corp` and `git.corp` but not `notcorp`.
+    /// a leading `*.` or `.` is ignored.
+    suffix: Vec<String>,
+    /// the net to lookup the matched domains by
+    resolver: NetRef,
+}
+
+/// Looks up each domain by the resolver of the first rule matching it, e.g. the
+/// internal domains by an internal DNS and the others by a public one.
+#[rd_config]
+#[derive(Debug)]
+pub struct RuleResolverConfig {
+    rule: Vec<ResolverRule>,
+    /// the net to lookup the domains not matched by any rule
+    default: NetRef,
+}
+
+pub struct RuleResolverNet {
+    rules: Vec<(Vec<String>, Net)>,
+    default: Net,
+}
+
+impl RuleResolverNet {
+    pub fn new(rules: Vec<(Vec<String>, Net)>, default: Net) -> Self {
+        let rules = rules
+            .into_iter()
+            .map(|(suffixes, net)| (suffixes.iter().map(|s| normalize(s)).collect(), net))
+            .collect();
+        RuleResolverNet { rules, default }
+    }
+    fn resolver(&self, domain: &str) -> &Net {
+        let domain = normalize(domain);
+        self.rules
+            .iter()
+            .find(|(suffixes, _)| suffixes.iter().any(|s| has_suffix(&domain, s)))
+            .map(|(_, net)| net)
+            .unwrap_or(&self.default)
+    }
+}
+
+fn normalize(domain: &str) -> String {
+    domain
+        .trim_start_matches("*.")
+        .trim_start_matches('.')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+/// Whether `suffix` is `domain` or its parent domain.
+fn has_suffix(domain: &str, suffix: &str) -> bool {
+    match domain.strip_suffix(suffix) {
+        Some(rest) => rest.is_empty() || rest.ends_with('.'),
+        None => false,
+    }
+}
+
+#[async_trait]
+impl rd_interface::LookupHost for RuleResolverNet {
+    async fn lookup_host(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+        let resolver = match addr {
+            Address::Domain(domain, _) => self.resolver(domain),
+            Address::SocketAddr(_) => &self.default,
+        };
+        resolver.lookup_host(ctx, addr).await
+    }
+}
+
+impl INet for RuleResolverNet {
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        Some(self)
+    }
+}
+
+impl Builder<Net> for RuleResolverNet {
+    const NAME: &'static str = "rule_resolver";
+    const TITLE: &'static str = "Resolver by Domain Rules";
+    type Config = RuleResolverConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        let mut rules = Vec::with_capacity(config.rule.len());
+        for rule in config.rule {
+            if rule.suffix.iter().any(|s| normalize(s).is_empty()) {
+                return Err(Error::other("suffix must not be empty"));
+            }
+            rules.push((rule.suffix, rule.resolver.value_cloned()));
+        }
+        Ok(RuleResolverNet::new(rules, config.default.value_cloned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use rd_interface::{IntoAddress, IntoDyn};
+
+    use super::*;
+    use crate::tests::{assert_net_provider, ProviderCapability};
+
+    /// Answers the IP with the port of the address.
+    struct TestResolver(&'static str);
+
+    #[async_trait]
+    impl rd_interface::LookupHost for TestResolver {
+        async fn lookup_host(&self, _ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+            let ip: IpAddr = self.0.parse().unwrap();
+            Ok(vec![SocketAddr::new(ip, addr.port())])
+        }
+    }
+
+    impl INet for TestResolver {
+        fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+            Some(self)
+        }
+    }
+
+    fn split_horizon() -> Net {
+        RuleResolverNet::new(
+            vec![
+                (
+                    vec!["*.corp".to_string()],
+                    TestResolver("10.0.0.1").into_dyn(),
+                ),
+                (
+                    vec!["svc.internal".to_string(), "lab".to_string()],
+                    TestResolver("10.0.0.2").into_dyn(),
+                ),
+            ],
+            TestResolver("1.1.1.1").into_dyn(),
+        )
+        .into_dyn()
+    }
+
+    async fn lookup(net: &Net, addr: &str) -> String {
+        let addrs = net
+            .lookup_host(&mut Context::new(), &addr.into_address().unwrap())
+            .await
+            .unwrap();
+        addrs[0].to_string()
+    }
+
+    #[test]
+    fn test_provider() {
+        assert_net_provider(
+            &split_horizon(),
+            ProviderCapability {
+                lookup_host: true,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rule_resolver() {
+        let net = split_horizon();
+
+        // internal
+        assert_eq!(lookup(&net, "git.corp:443").await, "10.0.0.1:443");
+        assert_eq!(lookup(&net, "corp:22").await, "10.0.0.1:22");
+        assert_eq!(lookup(&net, "Wiki.Corp.:80").await, "10.0.0.1:80");
+        assert_eq!(lookup(&net, "db.svc.internal:5432").await, "10.0.0.2:5432");
+        assert_eq!(lookup(&net, "lab:80").await, "10.0.0.2:80");
+
+        // external
+        assert_eq!(lookup(&net, "example.com:443").await, "1.1.1.1:443");
+        assert_eq!(lookup(&net, "notcorp:443").await, "1.1.1.1:443");
+        assert_eq!(lookup(&net, "internal:443").await, "1.1.1.1:443");
+        assert_eq!(lookup(&net, "10.0.0.3:443").await, "1.1.1.1:443");
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{self, Poll},
+    time::Duration,
+};
+
+use futures::{ready, Future};
+use rd_interface::{
+    async_trait, prelude::*, registry::Builder, Address, Context, Error, INet, ITcpStream, IntoDyn,
+    Net, ReadBuf, Result, TcpStream, UdpSocket, NOT_IMPLEMENTED,
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{sleep, Instant, Sleep},
+};
+
+/// Accepts connections and holds them, trickling a byte now and then, to tie up
+/// scanners. Connections over `max_connections` are refused like `blackhole`.
+#[rd_config]
+#[derive(Debug)]
+pub struct TarpitNetConfig {
+    /// how long a connection is held before it's closed, in seconds.
+    #[serde(default = "default_duration")]
+    duration: u64,
+    /// interval between the bytes sent to the client, in milliseconds.
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// maximum number of connections held at the same time.
+    #[serde(default = "default_max_connections")]
+    max_connections: usize,
+}
+
+fn default_duration() -> u64 {
+    60
+}
+
+fn default_interval() -> u64 {
+    5000
+}
+
+fn default_max_connections() -> usize {
+    64
+}
+
+pub struct TarpitNet {
+    duration: Duration,
+    interval: Duration,
+    semaphore: Arc<Semaphore>,
+}
+
+impl TarpitNet {
+    pub fn new(duration: Duration, interval: Duration, max_connections: usize) -> TarpitNet {
+        TarpitNet {
+            duration,
+            interval,
+            semaphore: Arc::new(Semaphore::new(max_connections)),
+        }
+    }
+}
+
+fn refused() -> Error {
+    io::Error::from(io::ErrorKind::ConnectionRefused).into()
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for TarpitNet {
+    async fn tcp_connect(&self, _ctx: &mut Context, _addr: &Address) -> Result<TcpStream> {
+        let permit = self
+            .semaphore
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| refused())?;
+        Ok(TarpitTcp::new(self.duration, self.interval, permit).into_dyn())
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for TarpitNet {
+    async fn udp_bind(&self, _ctx: &mut Context, _addr: &Address) -> Result<UdpSocket> {
+        Err(refused())
+    }
+}
+
+impl INet for TarpitNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        Some(self)
+    }
+}
+
+impl Builder<Net> for TarpitNet {
+    const NAME: &'static str = "tarpit";
+    const TITLE: &'static str = "Tarpit";
+    type Config = TarpitNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        if config.interval == 0 {
+            return Err(Error::other("interval must be greater than 0"));
+        }
+        Ok(TarpitNet::new(
+            Duration::from_secs(config.duration),
+            Duration::from_millis(config.interval),
+            config.max_connections,
+        ))
+    }
+}
+
+/// Sends a byte every `interval` until the deadline, then EOF. Writes are discarded.
+struct TarpitTcp {
+    deadline: Pin<Box<Sleep>>,
+    tick: Pin<Box<Sleep>>,
+    interval: Duration,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl TarpitTcp {
+    fn new(duration: Duration, interval: Duration, permit: OwnedSemaphorePermit) -> TarpitTcp {
+        TarpitTcp {
+            deadline: Box::pin(sleep(duration)),
+            tick: Box::pin(sleep(interval)),
+            interval,
+            _permit: permit,
+        }
+    }
+}
+
+#[async_trait]
+impl ITcpStream for TarpitTcp {
+    fn poll_read(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.deadline.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(self.tick.as_mut().poll(cx));
+
+        let next = Instant::now() + self.interval;
+        self.tick.as_mut().reset(next);
+        buf.put_slice(b"\n");
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write(&mut self, _cx: &mut task::Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if self.deadline.is_elapsed() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(&mut self, _cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(&mut self, _cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    async fn peer_addr(&self) -> Result<SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        Err(NOT_IMPLEMENTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::IntoAddress;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::tests::{assert_net_provider, ProviderCapability};
+
+    #[test]
+    fn test_provider() {
+        let net = TarpitNet::build(TarpitNetConfig {
+            duration: 1,
+            interval: 100,
+            max_connections: 1,
+        })
+        .unwrap()
+        .into_dyn();
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                tcp_connect: true,
+                udp_bind: true,
+                ..Default::default()
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tarpit() {
+        let net =
+            TarpitNet::new(Duration::from_millis(500), Duration::from_millis(100), 1).into_dyn();
+        let addr = "scanner.example.com:22".into_address().unwrap();
+
+        let start = Instant::now();
+        let mut tcp = net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+
+        // the slot is taken
+        assert!(net.tcp_connect(&mut Context::new(), &addr).await.is_err());
+
+        let mut received = Vec::new();
+        tcp.read_to_end(&mut received).await.unwrap();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(500), "{:?}", elapsed);
+        assert!(received.len() >= 3 && received.len() <= 5, "{:?}", received);
+
+        // the slot is freed after closed
+        drop(tcp);
+        assert!(net.tcp_connect(&mut Context::new(), &addr).await.is_ok());
+    }
+}
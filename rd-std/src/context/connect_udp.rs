@@ -27,9 +27,9 @@ impl fmt::Debug for PacketBuffer {
 }
 
 impl PacketBuffer {
-    fn new() -> Self {
+    fn new(packet_size: usize) -> Self {
         Self {
-            buf: vec![0; UDP_BUFFER_SIZE].into_boxed_slice(),
+            buf: vec![0; packet_size].into_boxed_slice(),
             filled: None,
         }
     }
@@ -44,14 +44,16 @@ struct CopyBuffer {
     pool: Vec<PacketBuffer>,
     queue: VecDeque<PacketBuffer>,
     max_buffer_size: usize,
+    packet_size: usize,
 }
 
 impl CopyBuffer {
-    fn new(buffer_size: usize, max_buffer_size: usize) -> Self {
+    fn new(buffer_size: usize, max_buffer_size: usize, packet_size: usize) -> Self {
         Self {
-            pool: vec![PacketBuffer::new(); buffer_size],
+            pool: vec![PacketBuffer::new(packet_size); buffer_size],
             queue: VecDeque::with_capacity(buffer_size),
             max_buffer_size,
+            packet_size,
         }
     }
     fn borrow_front(&mut self) -> Option<&mut PacketBuffer> {
@@ -77,7 +79,8 @@ impl CopyBuffer {
         if self.pool.is_empty() {
             let goal = self.max_buffer_size.min(self.queue.len() * 2);
             let to_add = goal - self.queue.len();
-            self.pool.append(&mut vec![PacketBuffer::new(); to_add]);
+            self.pool
+                .append(&mut vec![PacketBuffer::new(self.packet_size); to_add]);
         }
     }
 }
@@ -202,11 +205,12 @@ pub async fn connect_udp(
     b: UdpSocket,
 ) -> io::Result<()> {
     let _ = ctx;
+    let packet_size = b.recv_buffer_size().unwrap_or(UDP_BUFFER_SIZE);
     DropAbort::new(tokio::spawn(CopyBidirectional {
         a,
         b,
-        send_queue: CopyBuffer::new(1, 16),
-        recv_queue: CopyBuffer::new(1, 16),
+        send_queue: CopyBuffer::new(1, 16, packet_size),
+        recv_queue: CopyBuffer::new(1, 16, packet_size),
     }))
     .await??;
 
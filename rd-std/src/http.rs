@@ -1,9 +1,13 @@
 pub use self::{client::HttpClient, server::HttpServer};
 
+use crate::{
+    tls::{default_session_cache, TlsNet, TlsNetConfig},
+    util::default_max_handshake_bytes,
+};
 use rd_interface::{
     prelude::*,
     registry::{Builder, NetRef},
-    Address, Net, Registry, Result, Server,
+    Address, IntoDyn, Net, Registry, Result, Server,
 };
 
 mod client;
@@ -18,6 +22,24 @@ pub struct HttpNetConfig {
 
     #[serde(default)]
     net: NetRef,
+    /// username sent in `Proxy-Authorization` on CONNECT
+    #[serde(default)]
+    username: Option<String>,
+    /// password sent in `Proxy-Authorization` on CONNECT
+    #[serde(default)]
+    password: Option<String>,
+    /// connect to the server over TLS (https proxy)
+    #[serde(default)]
+    tls: bool,
+    /// Dangerous, but can be used to skip certificate verification.
+    #[serde(default)]
+    skip_cert_verify: bool,
+    /// Override domain with SNI
+    #[serde(default)]
+    sni: Option<String>,
+    /// ALPN protocols to offer over TLS
+    #[serde(default)]
+    alpn: Vec<String>,
 }
 
 #[rd_config]
@@ -28,29 +50,70 @@ pub struct HttpServerConfig {
     net: NetRef,
     #[serde(default)]
     listen: NetRef,
+    /// read the PROXY protocol header on inbound connections
+    #[serde(default)]
+    proxy_protocol: bool,
+    /// maximum size of the request head, the connection is rejected if it's exceeded.
+    /// at least 8KB. default is 16KB.
+    #[serde(default = "default_max_handshake_bytes")]
+    max_handshake_bytes: usize,
 }
 
 impl Builder<Net> for HttpClient {
     const NAME: &'static str = "http";
+    const TITLE: &'static str = "HTTP Client";
     type Config = HttpNetConfig;
     type Item = Self;
 
     fn build(config: Self::Config) -> Result<Self> {
-        Ok(HttpClient::new(config.net.value_cloned(), config.server))
+        let net = if config.tls {
+            TlsNet::build(TlsNetConfig {
+                skip_cert_verify: config.skip_cert_verify,
+                sni: config.sni,
+                enable_early_data: false,
+                alpn: config.alpn,
+                session_cache: default_session_cache(),
+                net: config.net,
+            })?
+            .into_dyn()
+        } else {
+            config.net.value_cloned()
+        };
+        let client = HttpClient::new(net, config.server);
+        Ok(match (config.username, config.password) {
+            (Some(username), password) => {
+                client.basic_auth(&username, password.as_deref().unwrap_or_default())
+            }
+            (None, Some(_)) => {
+                return Err(rd_interface::Error::other(
+                    "http net: password is set without username",
+                ))
+            }
+            (None, None) => client,
+        })
     }
 }
 
 impl Builder<Server> for server::Http {
     const NAME: &'static str = "http";
+    const TITLE: &'static str = "HTTP Server";
     type Config = HttpServerConfig;
     type Item = Self;
 
-    fn build(Self::Config { listen, net, bind }: Self::Config) -> Result<Self> {
-        Ok(server::Http::new(
-            listen.value_cloned(),
-            net.value_cloned(),
+    fn build(
+        Self::Config {
+            listen,
+            net,
             bind,
-        ))
+            proxy_protocol,
+            max_handshake_bytes,
+        }: Self::Config,
+    ) -> Result<Self> {
+        Ok(
+            server::Http::new(listen.value_cloned(), net.value_cloned(), bind)
+                .proxy_protocol(proxy_protocol)
+                .max_handshake_bytes(max_handshake_bytes),
+        )
     }
 }
 
@@ -1,19 +1,32 @@
 use std::net::SocketAddr;
 
-use hyper::{client::conn as client_conn, Body, Error, Request};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::{select, Either};
+use hyper::{client::conn as client_conn, header, Body, Error, Request, Response};
 
 use rd_interface::{
-    async_trait, impl_async_read_write, Address, INet, ITcpStream, IntoDyn, Net, Result, TcpStream,
-    NOT_IMPLEMENTED,
+    async_trait, error::map_other, impl_async_read_write, Address, INet, ITcpStream, IntoDyn, Net,
+    Result, TcpStream, NOT_IMPLEMENTED,
 };
 
 fn map_err(e: Error) -> rd_interface::Error {
     rd_interface::Error::Other(e.into())
 }
 
+fn check_status(resp: &Response<Body>) -> Result<()> {
+    if !resp.status().is_success() {
+        return Err(rd_interface::Error::other(format!(
+            "http proxy refused CONNECT: {}",
+            resp.status()
+        )));
+    }
+    Ok(())
+}
+
 pub struct HttpClient {
     server: Address,
     net: Net,
+    authorization: Option<String>,
 }
 
 pub struct HttpTcpStream(TcpStream);
@@ -41,15 +54,25 @@ impl rd_interface::TcpConnect for HttpClient {
         let socket = self.net.tcp_connect(ctx, &self.server).await?;
         let (mut request_sender, connection) =
             client_conn::handshake(socket).await.map_err(map_err)?;
-        let connect_req = Request::builder()
-            .method("CONNECT")
-            .uri(addr.to_string())
-            .body(Body::empty())
-            .unwrap();
+        let mut connect_req = Request::builder().method("CONNECT").uri(addr.to_string());
+        if let Some(authorization) = &self.authorization {
+            connect_req = connect_req.header(header::PROXY_AUTHORIZATION, authorization);
+        }
+        let connect_req = connect_req.body(Body::empty()).map_err(map_other)?;
         let connection = connection.without_shutdown();
-        let _connect_resp = request_sender.send_request(connect_req);
-        let io = connection.await.map_err(map_err)?.io;
-        let _connect_resp = _connect_resp.await.map_err(map_err)?;
+        let connect_resp = request_sender.send_request(connect_req);
+        // A refused CONNECT keeps the connection alive, so wait for whichever comes first.
+        let io = match select(connect_resp, connection).await {
+            Either::Left((connect_resp, connection)) => {
+                check_status(&connect_resp.map_err(map_err)?)?;
+                connection.await.map_err(map_err)?.io
+            }
+            Either::Right((parts, connect_resp)) => {
+                let io = parts.map_err(map_err)?.io;
+                check_status(&connect_resp.await.map_err(map_err)?)?;
+                io
+            }
+        };
         Ok(HttpTcpStream(io).into_dyn())
     }
 }
@@ -58,11 +81,25 @@ impl INet for HttpClient {
     fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
         Some(self)
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 impl HttpClient {
     pub fn new(net: Net, server: Address) -> Self {
-        Self { server, net }
+        Self {
+            server,
+            net,
+            authorization: None,
+        }
+    }
+    /// Send `Proxy-Authorization: Basic` with the given credentials on CONNECT.
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        let credentials = STANDARD.encode(format!("{}:{}", username, password));
+        self.authorization = Some(format!("Basic {}", credentials));
+        self
     }
 }
 
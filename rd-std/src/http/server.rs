@@ -1,16 +1,30 @@
 use hyper::{
-    client::conn as client_conn, http, server::conn as server_conn, service::service_fn, Body,
-    Method, Request, Response,
+    client::conn as client_conn, header::HeaderValue, http, server::conn as server_conn,
+    service::service_fn, Body, Method, Request, Response,
+};
+use rd_interface::{
+    async_trait, context::common_field::RequestId, Address, Context, IServer, IntoAddress, Net,
+    Result, TcpStream,
 };
-use rd_interface::{async_trait, Address, Context, IServer, IntoAddress, Net, Result, TcpStream};
 use std::net::SocketAddr;
-use tracing::instrument;
+use tokio::io::AsyncWriteExt;
+use tracing::{instrument, Instrument, Span};
+
+use crate::{
+    util::{
+        accept_with_backoff, cancel_on_close, default_max_handshake_bytes, proxy_protocol,
+        ACCEPT_BACKOFF,
+    },
+    ContextExt,
+};
 
-use crate::ContextExt;
+/// The minimum buffer size of hyper.
+const MIN_MAX_BUF_SIZE: usize = 8192;
 
 #[derive(Clone)]
 pub struct HttpServer {
     net: Net,
+    max_handshake_bytes: usize,
 }
 
 impl HttpServer {
@@ -22,6 +36,7 @@ impl HttpServer {
             .http1_preserve_header_case(true)
             .http1_title_case_headers(true)
             .http1_keep_alive(true)
+            .max_buf_size(self.max_handshake_bytes.max(MIN_MAX_BUF_SIZE))
             .serve_connection(socket, service_fn(move |req| proxy(net.clone(), req, addr)))
             .with_upgrades()
             .await?;
@@ -29,7 +44,15 @@ impl HttpServer {
         Ok(())
     }
     pub fn new(net: Net) -> Self {
-        Self { net }
+        Self {
+            net,
+            max_handshake_bytes: default_max_handshake_bytes(),
+        }
+    }
+    /// Reject the requests whose head is larger than `bytes`, at least 8KB.
+    pub fn max_handshake_bytes(mut self, bytes: usize) -> Self {
+        self.max_handshake_bytes = bytes;
+        self
     }
 }
 
@@ -37,6 +60,7 @@ pub struct Http {
     server: HttpServer,
     listen_net: Net,
     bind: Address,
+    proxy_protocol: bool,
 }
 
 #[async_trait]
@@ -48,10 +72,20 @@ impl IServer for Http {
             .await?;
 
         loop {
-            let (socket, addr) = listener.accept().await?;
+            let (mut socket, addr) =
+                accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
             let server = self.server.clone();
+            let proxy_protocol = self.proxy_protocol;
             tokio::spawn(async move {
-                if let Err(e) = server.serve_connection(socket, addr).await {
+                let serve = async {
+                    let addr = if proxy_protocol {
+                        proxy_protocol::accept(&mut socket, addr).await?
+                    } else {
+                        addr
+                    };
+                    server.serve_connection(socket, addr).await
+                };
+                if let Err(e) = serve.await {
                     tracing::error!("Error when serve_connection: {:?}", e);
                 }
             });
@@ -65,11 +99,46 @@ impl Http {
             server: HttpServer::new(net),
             listen_net,
             bind,
+            proxy_protocol: false,
         }
     }
+    /// Read the PROXY protocol header on inbound connections.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+    /// Reject the requests whose head is larger than `bytes`, at least 8KB.
+    pub fn max_handshake_bytes(mut self, bytes: usize) -> Self {
+        self.server = self.server.max_handshake_bytes(bytes);
+        self
+    }
+}
+
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// The `X-Request-Id` of the request, or a generated one if it's absent.
+fn request_id(req: &Request<Body>) -> RequestId {
+    req.headers()
+        .get(X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(|v| RequestId(v.to_string()))
+        .unwrap_or_else(RequestId::generate)
 }
 
+fn set_request_id(resp: &mut Response<Body>, request_id: &RequestId) {
+    if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+        resp.headers_mut().insert(X_REQUEST_ID, value);
+    }
+}
+
+#[instrument(skip(net, req), fields(request_id))]
 async fn proxy(net: Net, req: Request<Body>, addr: SocketAddr) -> anyhow::Result<Response<Body>> {
+    let request_id = request_id(&req);
+    Span::current().record("request_id", request_id.0.as_str());
+    let mut ctx = Context::from_socketaddr(addr);
+    ctx.insert_common(request_id.clone())?;
+
     if let Some(mut dst) = host_addr(req.uri()) {
         if !dst.contains(':') {
             dst += ":80"
@@ -77,25 +146,33 @@ async fn proxy(net: Net, req: Request<Body>, addr: SocketAddr) -> anyhow::Result
         let dst = dst.into_address()?;
 
         if req.method() == Method::CONNECT {
-            tokio::spawn(async move {
-                match hyper::upgrade::on(req).await {
-                    Ok(upgraded) => {
-                        let mut ctx = Context::from_socketaddr(addr);
-                        let stream = net.tcp_connect(&mut ctx, &dst).await?;
-                        if let Err(e) = ctx.connect_tcp(stream, upgraded).await {
-                            tracing::debug!("tunnel io error: {}", e);
-                        };
+            tokio::spawn(
+                async move {
+                    match hyper::upgrade::on(req).await {
+                        Ok(mut upgraded) => {
+                            // the connect is cancelled if the client leaves meanwhile,
+                            // the bytes sent by the client meanwhile are forwarded
+                            let mut buf = Vec::new();
+                            let connect = net.tcp_connect(&mut ctx, &dst);
+                            let mut stream =
+                                cancel_on_close(&mut upgraded, &mut buf, connect).await?;
+                            stream.write_all(&buf).await?;
+                            if let Err(e) = ctx.connect_tcp(stream, upgraded).await {
+                                tracing::debug!("tunnel io error: {}", e);
+                            };
+                        }
+                        Err(e) => tracing::debug!("upgrade error: {}", e),
                     }
-                    Err(e) => tracing::debug!("upgrade error: {}", e),
+                    Ok(()) as anyhow::Result<()>
                 }
-                Ok(()) as anyhow::Result<()>
-            });
+                .in_current_span(),
+            );
 
-            Ok(Response::new(Body::empty()))
+            let mut resp = Response::new(Body::empty());
+            set_request_id(&mut resp, &request_id);
+            Ok(resp)
         } else {
-            let stream = net
-                .tcp_connect(&mut Context::from_socketaddr(addr), &dst)
-                .await?;
+            let stream = net.tcp_connect(&mut ctx, &dst).await?;
 
             let (mut request_sender, connection) = client_conn::Builder::new()
                 .http1_preserve_header_case(true)
@@ -105,7 +182,8 @@ async fn proxy(net: Net, req: Request<Body>, addr: SocketAddr) -> anyhow::Result
 
             tokio::spawn(connection);
 
-            let resp = request_sender.send_request(req).await?;
+            let mut resp = request_sender.send_request(req).await?;
+            set_request_id(&mut resp, &request_id);
 
             Ok(resp)
         }
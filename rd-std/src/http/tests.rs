@@ -1,9 +1,15 @@
 use super::*;
 use crate::tests::{assert_echo, get_registry, spawn_echo_server, TestNet};
-use rd_interface::IntoAddress;
-use rd_interface::{IServer, IntoDyn};
-use std::time::Duration;
-use tokio::time::sleep;
+use parking_lot::Mutex;
+use rd_interface::{
+    async_trait, context::common_field::RequestId, Address, Context, INet, IServer, IntoAddress,
+    IntoDyn, Net, Result, TcpStream,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::sleep,
+};
 
 #[test]
 fn test_http_smoke() {
@@ -30,3 +36,242 @@ async fn test_http_server_client() {
 
     assert_echo(&client, "127.0.0.1:26667").await;
 }
+
+/// A CONNECT proxy which only accepts `user:pass`
+async fn spawn_auth_proxy(net: &Net, bind: &str) {
+    use hyper::{server::conn as server_conn, service::service_fn, Body, Response, StatusCode};
+    use rd_interface::Context;
+
+    let listener = net
+        .tcp_bind(&mut Context::new(), &bind.into_address().unwrap())
+        .await
+        .unwrap();
+    let net = net.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let net = net.clone();
+            let service = service_fn(move |req: hyper::Request<Body>| {
+                let net = net.clone();
+                async move {
+                    let authorized = req
+                        .headers()
+                        .get(hyper::header::PROXY_AUTHORIZATION)
+                        .map(|v| v == "Basic dXNlcjpwYXNz")
+                        .unwrap_or_default();
+                    if !authorized {
+                        let mut resp = Response::new(Body::empty());
+                        *resp.status_mut() = StatusCode::PROXY_AUTHENTICATION_REQUIRED;
+                        return Ok::<_, hyper::Error>(resp);
+                    }
+                    let dst = req.uri().to_string().into_address().unwrap();
+                    tokio::spawn(async move {
+                        let mut upgraded = hyper::upgrade::on(req).await.unwrap();
+                        let mut stream = net.tcp_connect(&mut Context::new(), &dst).await.unwrap();
+                        let _ = tokio::io::copy_bidirectional(&mut upgraded, &mut stream).await;
+                    });
+                    Ok(Response::new(Body::empty()))
+                }
+            });
+            tokio::spawn(
+                server_conn::Http::new()
+                    .serve_connection(socket, service)
+                    .with_upgrades(),
+            );
+        }
+    });
+}
+
+#[tokio::test]
+async fn test_http_client_basic_auth() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26668").await;
+    spawn_auth_proxy(&local, "127.0.0.1:16668").await;
+
+    let client = client::HttpClient::new(local.clone(), "127.0.0.1:16668".into_address().unwrap())
+        .basic_auth("user", "pass")
+        .into_dyn();
+    assert_echo(&client, "127.0.0.1:26668").await;
+
+    let client = client::HttpClient::new(local, "127.0.0.1:16668".into_address().unwrap())
+        .basic_auth("user", "wrong")
+        .into_dyn();
+    let result = client
+        .tcp_connect(
+            &mut rd_interface::Context::new(),
+            &"127.0.0.1:26668".into_address().unwrap(),
+        )
+        .await;
+    assert!(result.is_err());
+}
+
+/// Records the contexts of the connections.
+struct RecordNet {
+    net: Net,
+    contexts: Arc<Mutex<Vec<Context>>>,
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for RecordNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        self.contexts.lock().push(ctx.clone());
+        self.net.tcp_connect(ctx, addr).await
+    }
+}
+
+impl INet for RecordNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+}
+
+/// Sends a CONNECT with `headers` and echoes through the tunnel, returns the response head.
+async fn connect_with_headers(net: &Net, proxy: &str, dst: &str, headers: &str) -> String {
+    let mut tcp = net
+        .tcp_connect(&mut Context::new(), &proxy.into_address().unwrap())
+        .await
+        .unwrap();
+    let req = format!("CONNECT {dst} HTTP/1.1\r\nHost: {dst}\r\n{headers}\r\n");
+    tcp.write_all(req.as_bytes()).await.unwrap();
+
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        head.push(tcp.read_u8().await.unwrap());
+    }
+
+    tcp.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    tcp.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    String::from_utf8(head).unwrap().to_lowercase()
+}
+
+#[tokio::test]
+async fn test_http_server_request_id() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26669").await;
+
+    let contexts = Arc::new(Mutex::new(Vec::new()));
+    let net = RecordNet {
+        net: local.clone(),
+        contexts: contexts.clone(),
+    }
+    .into_dyn();
+    let server = server::Http::new(
+        local.clone(),
+        net,
+        "127.0.0.1:16669".into_address().unwrap(),
+    );
+    tokio::spawn(async move { server.start().await });
+    sleep(Duration::from_millis(100)).await;
+
+    let head = connect_with_headers(
+        &local,
+        "127.0.0.1:16669",
+        "127.0.0.1:26669",
+        "X-Request-Id: client-id\r\n",
+    )
+    .await;
+    assert!(head.contains("x-request-id: client-id"));
+
+    // generated if the client doesn't supply one
+    let head = connect_with_headers(&local, "127.0.0.1:16669", "127.0.0.1:26669", "").await;
+    let generated = head
+        .lines()
+        .find_map(|l| l.strip_prefix("x-request-id: "))
+        .unwrap()
+        .to_string();
+
+    let request_ids = contexts
+        .lock()
+        .iter()
+        .map(|ctx| ctx.get_common::<RequestId>().unwrap().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        request_ids,
+        vec![RequestId("client-id".to_string()), RequestId(generated)]
+    );
+}
+
+#[tokio::test]
+async fn test_http_server_max_handshake_bytes() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26670").await;
+
+    let server = server::Http::new(
+        local.clone(),
+        local.clone(),
+        "127.0.0.1:16670".into_address().unwrap(),
+    );
+    tokio::spawn(async move { server.start().await });
+    sleep(Duration::from_millis(100)).await;
+
+    // a head within the default limit is accepted
+    let padding = format!("X-Padding: {}\r\n", "a".repeat(8 * 1024));
+    connect_with_headers(&local, "127.0.0.1:16670", "127.0.0.1:26670", &padding).await;
+
+    let mut tcp = local
+        .tcp_connect(
+            &mut Context::new(),
+            &"127.0.0.1:16670".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+    let padding = "a".repeat(32 * 1024);
+    let req = format!(
+        "CONNECT 127.0.0.1:26670 HTTP/1.1\r\nHost: 127.0.0.1:26670\r\nX-Padding: {padding}\r\n\r\n"
+    );
+    // the server may close the connection before the whole request is written
+    let _ = tcp.write_all(req.as_bytes()).await;
+
+    let mut resp = Vec::new();
+    let _ = tcp.read_to_end(&mut resp).await;
+    let resp = String::from_utf8_lossy(&resp);
+    assert!(
+        resp.is_empty() || resp.starts_with("HTTP/1.1 431"),
+        "{resp}"
+    );
+}
+
+/// Connects after `delay`.
+struct SlowNet {
+    net: Net,
+    delay: Duration,
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for SlowNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        sleep(self.delay).await;
+        self.net.tcp_connect(ctx, addr).await
+    }
+}
+
+impl INet for SlowNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+}
+
+#[tokio::test]
+async fn test_http_server_slow_connect() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26671").await;
+
+    let net = SlowNet {
+        net: local.clone(),
+        delay: Duration::from_millis(200),
+    }
+    .into_dyn();
+    let server = server::Http::new(
+        local.clone(),
+        net,
+        "127.0.0.1:16671".into_address().unwrap(),
+    );
+    tokio::spawn(async move { server.start().await });
+    sleep(Duration::from_millis(100)).await;
+
+    // the bytes sent while connecting are forwarded
+    connect_with_headers(&local, "127.0.0.1:16671", "127.0.0.1:26671", "").await;
+}
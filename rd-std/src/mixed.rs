@@ -1,35 +1,51 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, time::Duration};
 
 use anyhow::Context as AnyhowContext;
 use rd_interface::{
     async_trait, config::NetRef, prelude::*, registry::Builder, Address, Context, IServer, IntoDyn,
     Net, Registry, Result, Server, TcpStream,
 };
+use tokio::time::timeout;
 use tracing::instrument;
 
-use crate::{http::HttpServer, socks5::Socks5Server, util::PeekableTcpStream};
+use crate::{
+    http::HttpServer,
+    socks5::Socks5Server,
+    util::{
+        accept_with_backoff, default_max_handshake_bytes, proxy_protocol, PeekableTcpStream,
+        ACCEPT_BACKOFF,
+    },
+};
 
 #[derive(Clone)]
 struct HttpSocks5Server {
     http_server: HttpServer,
     socks5_server: Socks5Server,
+    detect_timeout: Duration,
 }
 
 impl HttpSocks5Server {
-    fn new(listen_net: Net, net: Net) -> Self {
+    fn new(listen_net: Net, net: Net, max_handshake_bytes: usize) -> Self {
         Self {
-            http_server: HttpServer::new(net.clone()),
-            socks5_server: Socks5Server::new(listen_net, net),
+            http_server: HttpServer::new(net.clone()).max_handshake_bytes(max_handshake_bytes),
+            socks5_server: Socks5Server::new(listen_net, net)
+                .max_handshake_bytes(max_handshake_bytes),
+            detect_timeout: Duration::from_secs(default_detect_timeout()),
         }
     }
     #[instrument(err, skip(self, socket))]
     pub async fn serve_connection(self, socket: TcpStream, addr: SocketAddr) -> anyhow::Result<()> {
         let buf = &mut [0u8; 1];
         let mut socket = PeekableTcpStream::new(socket);
-        if let Err(_) = socket.peek_exact(buf).await {
+        match timeout(self.detect_timeout, socket.peek_exact(buf)).await {
+            Ok(Ok(())) => {}
             // The client has closed the connection before we could read the first byte.
             // This is not an error, so we just return.
-            return Ok(());
+            Ok(Err(_)) => return Ok(()),
+            Err(_) => {
+                tracing::debug!("{} sent nothing in {:?}, closed", addr, self.detect_timeout);
+                return Ok(());
+            }
         }
         let socket = socket.into_dyn();
 
@@ -51,6 +67,7 @@ impl HttpSocks5Server {
 pub struct HttpSocks5 {
     listen_net: Net,
     bind: Address,
+    proxy_protocol: bool,
 
     server: HttpSocks5Server,
 }
@@ -64,11 +81,21 @@ impl IServer for HttpSocks5 {
             .await?;
 
         loop {
-            let (socket, addr) = listener.accept().await?;
+            let (mut socket, addr) =
+                accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
 
             let server = self.server.clone();
+            let proxy_protocol = self.proxy_protocol;
             let _ = tokio::spawn(async move {
-                if let Err(e) = server.serve_connection(socket, addr).await {
+                let serve = async {
+                    let addr = if proxy_protocol {
+                        proxy_protocol::accept(&mut socket, addr).await?
+                    } else {
+                        addr
+                    };
+                    server.serve_connection(socket, addr).await
+                };
+                if let Err(e) = serve.await {
                     tracing::error!("Error when serve_connection: {:?}", e)
                 }
             });
@@ -77,13 +104,23 @@ impl IServer for HttpSocks5 {
 }
 
 impl HttpSocks5 {
-    fn new(listen_net: Net, net: Net, bind: Address) -> Self {
+    fn new(listen_net: Net, net: Net, bind: Address, max_handshake_bytes: usize) -> Self {
         HttpSocks5 {
-            server: HttpSocks5Server::new(listen_net.clone(), net),
+            server: HttpSocks5Server::new(listen_net.clone(), net, max_handshake_bytes),
             listen_net,
             bind,
+            proxy_protocol: false,
         }
     }
+    fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+    /// Close the connections sending nothing in `detect_timeout`.
+    fn detect_timeout(mut self, detect_timeout: Duration) -> Self {
+        self.server.detect_timeout = detect_timeout;
+        self
+    }
 }
 
 #[rd_config]
@@ -94,19 +131,47 @@ pub struct MixedServerConfig {
     listen: NetRef,
     #[serde(default)]
     net: NetRef,
+    /// read the PROXY protocol header on inbound connections
+    #[serde(default)]
+    proxy_protocol: bool,
+    /// maximum size of the SOCKS5 handshake or the HTTP request head, the connection
+    /// is rejected if it's exceeded. at least 8KB for HTTP. default is 16KB.
+    #[serde(default = "default_max_handshake_bytes")]
+    max_handshake_bytes: usize,
+    /// seconds to wait for the first byte of a connection to tell SOCKS5 from HTTP,
+    /// the connection is closed if it's not received. default is 10.
+    #[serde(default = "default_detect_timeout")]
+    detect_timeout: u64,
+}
+
+fn default_detect_timeout() -> u64 {
+    10
 }
 
 impl Builder<Server> for HttpSocks5 {
     const NAME: &'static str = "http+socks5";
+    const TITLE: &'static str = "HTTP and SOCKS5 Server";
     type Config = MixedServerConfig;
     type Item = Self;
 
-    fn build(Self::Config { listen, net, bind }: Self::Config) -> Result<Self> {
+    fn build(
+        Self::Config {
+            listen,
+            net,
+            bind,
+            proxy_protocol,
+            max_handshake_bytes,
+            detect_timeout,
+        }: Self::Config,
+    ) -> Result<Self> {
         Ok(HttpSocks5::new(
             listen.value_cloned(),
             net.value_cloned(),
             bind,
-        ))
+            max_handshake_bytes,
+        )
+        .proxy_protocol(proxy_protocol)
+        .detect_timeout(Duration::from_secs(detect_timeout)))
     }
 }
 
@@ -114,3 +179,77 @@ pub fn init(registry: &mut Registry) -> Result<()> {
     registry.add_server::<HttpSocks5>();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::IntoAddress;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::sleep,
+    };
+
+    use super::*;
+    use crate::{
+        http::HttpClient,
+        socks5::Socks5Client,
+        tests::{assert_echo, spawn_echo_server, TestNet},
+    };
+
+    async fn spawn_mixed(net: &Net, bind: &str) {
+        let server = HttpSocks5::new(
+            net.clone(),
+            net.clone(),
+            bind.into_address().unwrap(),
+            default_max_handshake_bytes(),
+        )
+        .detect_timeout(Duration::from_millis(200));
+        tokio::spawn(async move { server.start().await });
+        sleep(Duration::from_millis(10)).await;
+    }
+
+    async fn connect(net: &Net, addr: &str) -> TcpStream {
+        net.tcp_connect(&mut Context::new(), &addr.into_address().unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mixed() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "127.0.0.1:26666").await;
+        spawn_mixed(&net, "127.0.0.1:16680").await;
+
+        let server = "127.0.0.1:16680".into_address().unwrap();
+        let http = HttpClient::new(net.clone(), server.clone()).into_dyn();
+        assert_echo(&http, "127.0.0.1:26666").await;
+        let socks5 = Socks5Client::new(net.clone(), server).into_dyn();
+        assert_echo(&socks5, "127.0.0.1:26666").await;
+    }
+
+    #[tokio::test]
+    async fn test_detect_timeout() {
+        let net = TestNet::new().into_dyn();
+        spawn_mixed(&net, "127.0.0.1:16681").await;
+
+        // closed if nothing is sent
+        let mut silent = connect(&net, "127.0.0.1:16681").await;
+        let mut buf = [0u8; 2];
+        let read = timeout(Duration::from_secs(1), silent.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(read, 0);
+
+        // the greeting sent byte by byte, slower than the timeout in total
+        let mut slow = connect(&net, "127.0.0.1:16681").await;
+        for byte in [0x05, 0x01, 0x00] {
+            sleep(Duration::from_millis(100)).await;
+            slow.write_all(&[byte]).await.unwrap();
+        }
+        timeout(Duration::from_secs(1), slow.read_exact(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buf, [0x05, 0x00]);
+    }
+}
@@ -1,24 +1,33 @@
+mod alpn;
 mod any;
 pub mod config;
 mod domain;
+mod firewall;
 mod geoip;
 mod ipcidr;
+mod ja3;
 mod matcher;
 mod rule_net;
 
+pub use firewall::FirewallNet;
+pub use geoip::reload as reload_geoip;
+pub use rule_net::{RuleNet, RuleStat};
+
 use rd_interface::{registry::Builder, Net, Registry, Result};
 
-impl Builder<Net> for rule_net::RuleNet {
+impl Builder<Net> for RuleNet {
     const NAME: &'static str = "rule";
+    const TITLE: &'static str = "Rule";
     type Config = config::RuleNetConfig;
     type Item = Self;
 
     fn build(config: Self::Config) -> Result<Self> {
-        rule_net::RuleNet::new(config)
+        RuleNet::new(config)
     }
 }
 
 pub fn init(registry: &mut Registry) -> Result<()> {
-    registry.add_net::<rule_net::RuleNet>();
+    registry.add_net::<RuleNet>();
+    registry.add_net::<FirewallNet>();
     Ok(())
 }
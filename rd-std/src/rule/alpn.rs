@@ -0,0 +1,41 @@
+use super::config::AlpnMatcher;
+use super::matcher::{MatchContext, Matcher, MaybeAsync};
+
+impl Matcher for AlpnMatcher {
+    fn match_rule(&self, match_context: &MatchContext) -> MaybeAsync<bool> {
+        match match_context.alpn() {
+            Some(alpn) => self.alpn.iter().any(|i| i == alpn),
+            None => false,
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::{context::common_field::TlsAlpn, Context, IntoAddress};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_alpn_matcher() {
+        let matcher = AlpnMatcher {
+            alpn: vec!["h2".to_string()].into(),
+        };
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+
+        let mut ctx = Context::new();
+        ctx.insert_common(TlsAlpn("h2".to_string())).unwrap();
+        let match_context = MatchContext::from_context_address(&ctx, &addr).unwrap();
+        assert!(matcher.match_rule(&match_context).await);
+
+        let mut ctx = Context::new();
+        ctx.insert_common(TlsAlpn("http/1.1".to_string())).unwrap();
+        let match_context = MatchContext::from_context_address(&ctx, &addr).unwrap();
+        assert!(!matcher.match_rule(&match_context).await);
+
+        // no TLS
+        let match_context = MatchContext::from_context_address(&Context::new(), &addr).unwrap();
+        assert!(!matcher.match_rule(&match_context).await);
+    }
+}
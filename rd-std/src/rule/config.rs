@@ -3,6 +3,7 @@ use std::{fmt, str::FromStr};
 use super::matcher::{self, MatchContext};
 use rd_interface::{
     config::{CompactVecString, NetRef, SingleOrVec},
+    context::common_field::Priority,
     impl_empty_config,
     prelude::*,
     schemars::{
@@ -77,6 +78,14 @@ pub struct GeoIpMatcher {
     pub country: String,
 }
 
+/// Match the country of the source address, e.g. to route the clients
+/// of a server by their region.
+#[rd_config]
+#[derive(Debug, Clone)]
+pub struct SrcGeoIpMatcher {
+    pub country: String,
+}
+
 impl JsonSchema for IpCidr {
     fn schema_name() -> String {
         "IpCidr".to_string()
@@ -92,6 +101,25 @@ impl JsonSchema for IpCidr {
     }
 }
 
+/// Match the ALPN protocol negotiated on the inbound TLS connection.
+/// Never matches connections without TLS.
+#[rd_config]
+#[derive(Debug, Clone)]
+pub struct AlpnMatcher {
+    pub alpn: SingleOrVec<String>,
+}
+
+/// Match the JA3 fingerprint of the TLS ClientHello sniffed by the `sni_sniffer` net,
+/// e.g. to route a specific app. The fingerprint is shared by the clients using the
+/// same TLS library and can be changed at will, so it's heuristic.
+/// Never matches connections without a sniffed ClientHello.
+#[rd_config]
+#[derive(Debug, Clone)]
+pub struct Ja3Matcher {
+    /// the JA3 hashes in hex
+    pub ja3: SingleOrVec<String>,
+}
+
 #[rd_config]
 #[derive(Debug, Clone)]
 pub struct AnyMatcher {}
@@ -105,6 +133,10 @@ pub enum Matcher {
     #[serde(rename = "src_ipcidr")]
     SrcIpCidr(SrcIpCidrMatcher),
     GeoIp(GeoIpMatcher),
+    #[serde(rename = "src_geoip")]
+    SrcGeoIp(SrcGeoIpMatcher),
+    Alpn(AlpnMatcher),
+    Ja3(Ja3Matcher),
     Any(AnyMatcher),
 }
 
@@ -130,8 +162,17 @@ impl Matcher {
                     .extend(other_srcipcidr.ipcidr.iter().cloned());
                 true
             }
+            (Matcher::Alpn(ref mut self_alpn), Matcher::Alpn(ref other_alpn)) => {
+                self_alpn.alpn.extend(other_alpn.alpn.iter().cloned());
+                true
+            }
+            (Matcher::Ja3(ref mut self_ja3), Matcher::Ja3(ref other_ja3)) => {
+                self_ja3.ja3.extend(other_ja3.ja3.iter().cloned());
+                true
+            }
             (Matcher::Any(_), Matcher::Any(_)) => true,
             (Matcher::GeoIp(_), Matcher::GeoIp(_)) => false,
+            (Matcher::SrcGeoIp(_), Matcher::SrcGeoIp(_)) => false,
             _ => false,
         }
     }
@@ -143,11 +184,27 @@ pub struct RuleItem {
     pub target: NetRef,
     #[serde(flatten)]
     pub matcher: Matcher,
+    /// priority of the matched connections when the bandwidth is shared by the
+    /// `rate_limit` net, e.g. `high` for SSH and DNS, `low` for downloads.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<Priority>,
+    /// hint the matched connections as bulk transfers, e.g. downloads, so the
+    /// `local` net with `auto_buffer` applies its buffer sizes to them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bulk_transfer: bool,
+    /// the rule provider this rule is derived from, e.g. a clash `RULE-SET`.
+    /// the rules of a provider are replaced in place when it's updated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
 }
 
 impl RuleItem {
     pub fn merge(&mut self, other: &RuleItem) -> bool {
-        if self.target.represent() == other.target.represent() {
+        if self.target.represent() == other.target.represent()
+            && self.priority == other.priority
+            && self.bulk_transfer == other.bulk_transfer
+            && self.provider == other.provider
+        {
             self.matcher.merge(&other.matcher)
         } else {
             false
@@ -158,8 +215,14 @@ impl RuleItem {
 #[rd_config]
 #[derive(Debug)]
 pub struct RuleNetConfig {
+    /// how many routing decisions are cached, keyed by the destination.
+    /// the source is part of the key only when a rule matches the source.
+    /// the cache is dropped with the net, so reloading the config starts empty.
     #[serde(default = "default_lru_cache_size")]
     pub lru_cache_size: usize,
+    /// how long a cached decision is used, in seconds. never expires if unset.
+    #[serde(default)]
+    pub lru_cache_ttl: Option<u64>,
     #[serde(skip_serializing_if = "rd_interface::config::detailed_field")]
     pub rule: Vec<RuleItem>,
 }
@@ -175,12 +238,19 @@ impl matcher::Matcher for Matcher {
             Matcher::IpCidr(i) => i.match_rule(match_context),
             Matcher::SrcIpCidr(i) => i.match_rule(match_context),
             Matcher::GeoIp(i) => i.match_rule(match_context),
+            Matcher::SrcGeoIp(i) => i.match_rule(match_context),
+            Matcher::Alpn(i) => i.match_rule(match_context),
+            Matcher::Ja3(i) => i.match_rule(match_context),
             Matcher::Any(i) => i.match_rule(match_context),
         }
     }
 }
 
 impl Matcher {
+    /// Whether the result depends on the source address of the connection.
+    pub fn match_source(&self) -> bool {
+        matches!(self, Matcher::SrcIpCidr(_) | Matcher::SrcGeoIp(_))
+    }
     pub fn shrink_to_fit(&mut self) {
         match self {
             Matcher::Domain(i) => i.shrink_to_fit(),
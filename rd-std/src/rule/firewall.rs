@@ -0,0 +1,304 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    task::{self, Poll},
+};
+
+use futures::FutureExt;
+use rd_interface::{
+    async_trait, config::NetRef, context::common_field::SrcSocketAddr, prelude::*,
+    registry::Builder, Address, Context, INet, IUdpSocket, IntoDyn, Net, ReadBuf, Result,
+    TcpStream, UdpSocket,
+};
+
+use super::{
+    config::Matcher,
+    matcher::{self, MatchContext},
+};
+
+#[rd_config]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallAction {
+    Allow,
+    #[default]
+    Deny,
+}
+
+/// Refuses the destinations not allowed, regardless of the nets above it, e.g.
+/// the rules. The `deny` list is checked first, then the `allow` list, then
+/// `default` is applied. Domain destinations are not resolved, so an `ipcidr`
+/// matches them only after they are resolved by another net.
+#[rd_config]
+#[derive(Debug)]
+pub struct FirewallNetConfig {
+    #[serde(default)]
+    net: NetRef,
+    /// destinations always refused
+    #[serde(default)]
+    deny: Vec<Matcher>,
+    /// destinations allowed if not denied
+    #[serde(default)]
+    allow: Vec<Matcher>,
+    /// action of the destinations matched by neither list. default is deny.
+    #[serde(default)]
+    default: FirewallAction,
+}
+
+struct Firewall {
+    deny: Vec<Matcher>,
+    allow: Vec<Matcher>,
+    default: FirewallAction,
+}
+
+impl Firewall {
+    fn is_allowed(&self, ctx: &Context, addr: &Address) -> Result<bool> {
+        let match_context = MatchContext::from_context_address(ctx, addr)?;
+        // the matchers are synchronous, so it's checked for each datagram in place
+        let matches = |m: &Matcher| {
+            matcher::Matcher::match_rule(m, &match_context)
+                .now_or_never()
+                .unwrap_or(false)
+        };
+
+        Ok(if self.deny.iter().any(matches) {
+            false
+        } else if self.allow.iter().any(matches) {
+            true
+        } else {
+            self.default == FirewallAction::Allow
+        })
+    }
+}
+
+fn refused(addr: &Address) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionRefused,
+        format!("{} is not allowed by firewall", addr),
+    )
+}
+
+pub struct FirewallNet {
+    net: Net,
+    firewall: Arc<Firewall>,
+}
+
+impl FirewallNet {
+    pub fn new(config: FirewallNetConfig) -> FirewallNet {
+        FirewallNet {
+            net: config.net.value_cloned(),
+            firewall: Arc::new(Firewall {
+                deny: config.deny,
+                allow: config.allow,
+                default: config.default,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for FirewallNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        if !self.firewall.is_allowed(ctx, addr)? {
+            return Err(refused(addr).into());
+        }
+        self.net.tcp_connect(ctx, addr).await
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for FirewallNet {
+    async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
+        // only the source is kept, the destination is of each datagram
+        let match_ctx = match ctx.get_common::<SrcSocketAddr>()? {
+            Some(src) => Context::from_socketaddr(src.0),
+            None => Context::new(),
+        };
+        let udp = self.net.udp_bind(ctx, addr).await?;
+        Ok(FirewallUdp {
+            udp,
+            ctx: match_ctx,
+            firewall: self.firewall.clone(),
+        }
+        .into_dyn())
+    }
+}
+
+impl INet for FirewallNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        Some(self)
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+impl Builder<Net> for FirewallNet {
+    const NAME: &'static str = "firewall";
+    const TITLE: &'static str = "Firewall";
+    type Config = FirewallNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        Ok(FirewallNet::new(config))
+    }
+}
+
+/// Drops the datagrams to the destinations not allowed.
+struct FirewallUdp {
+    udp: UdpSocket,
+    ctx: Context,
+    firewall: Arc<Firewall>,
+}
+
+#[async_trait]
+impl IUdpSocket for FirewallUdp {
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        self.udp.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &Address,
+    ) -> Poll<io::Result<usize>> {
+        if !self.firewall.is_allowed(&self.ctx, target)? {
+            tracing::debug!("Datagram to {} is dropped by firewall", target);
+            return Poll::Ready(Ok(buf.len()));
+        }
+        self.udp.poll_send_to(cx, buf, target)
+    }
+
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.udp.local_addr().await
+    }
+
+    fn recv_buffer_size(&self) -> Option<usize> {
+        self.udp.recv_buffer_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rd_interface::IntoAddress;
+    use serde_json::json;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::tests::{
+        assert_echo, assert_echo_udp, assert_net_provider, spawn_echo_server,
+        spawn_echo_server_udp, ProviderCapability, TestNet,
+    };
+
+    fn firewall(net: &Net, config: serde_json::Value) -> Net {
+        let mut config: FirewallNetConfig = serde_json::from_value(config).unwrap();
+        config.net = NetRef::new_with_value("test".into(), net.clone());
+        FirewallNet::new(config).into_dyn()
+    }
+
+    async fn is_refused(net: &Net, addr: &str) -> bool {
+        let result = net
+            .tcp_connect(&mut Context::new(), &addr.into_address().unwrap())
+            .await;
+        matches!(result, Err(rd_interface::Error::IO(e)) if e.kind() == io::ErrorKind::ConnectionRefused)
+    }
+
+    #[test]
+    fn test_provider() {
+        let net = TestNet::new().into_dyn();
+        assert_net_provider(
+            &firewall(&net, json!({})),
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+    }
+
+    #[tokio::test]
+    async fn test_allow_and_deny() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "127.0.0.1:26670").await;
+
+        let fw = firewall(
+            &net,
+            json!({
+                "deny": [{ "type": "domain", "method": "keyword", "domain": "ads" }],
+                "allow": [
+                    { "type": "ipcidr", "ipcidr": "127.0.0.0/8" },
+                    { "type": "domain", "method": "suffix", "domain": "example.com" },
+                ],
+            }),
+        );
+
+        assert_echo(&fw, "127.0.0.1:26670").await;
+        // denied before allowed
+        assert!(is_refused(&fw, "ads.example.com:443").await);
+        // default deny
+        assert!(is_refused(&fw, "example.org:443").await);
+        assert!(is_refused(&fw, "10.0.0.1:443").await);
+
+        let fw = firewall(
+            &net,
+            json!({
+                "deny": [{ "type": "ipcidr", "ipcidr": "10.0.0.0/8" }],
+                "default": "allow",
+            }),
+        );
+        assert_echo(&fw, "127.0.0.1:26670").await;
+        assert!(is_refused(&fw, "10.0.0.1:443").await);
+    }
+
+    #[tokio::test]
+    async fn test_udp() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server_udp(&net, "127.0.0.1:26670").await;
+        spawn_echo_server_udp(&net, "127.0.0.1:26671").await;
+
+        let fw = firewall(
+            &net,
+            json!({
+                "deny": [{ "type": "ipcidr", "ipcidr": "127.0.0.1/32" }],
+                "allow": [{ "type": "any" }],
+            }),
+        );
+        let mut udp = fw
+            .udp_bind(&mut Context::new(), &"0.0.0.0:0".into_address().unwrap())
+            .await
+            .unwrap();
+        udp.send_to(b"hello", &"127.0.0.1:26670".into_address().unwrap())
+            .await
+            .unwrap();
+        let buf = &mut vec![0; 4096];
+        let mut buf = ReadBuf::new(buf);
+        assert!(timeout(Duration::from_millis(100), udp.recv_from(&mut buf))
+            .await
+            .is_err());
+
+        let fw = firewall(&net, json!({ "default": "allow" }));
+        assert_echo_udp(&fw, "127.0.0.1:26671").await;
+    }
+}
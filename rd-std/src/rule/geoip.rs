@@ -1,58 +1,84 @@
-use std::io::Read;
-use std::net::IpAddr;
+use std::{
+    fs,
+    io::Read,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use super::config::GeoIpMatcher;
+use super::config::{GeoIpMatcher, SrcGeoIpMatcher};
 use super::matcher::{MatchContext, Matcher, MaybeAsync};
+use arc_swap::ArcSwapOption;
 use flate2::read::GzDecoder;
 use maxminddb::{geoip2, MaxMindDBError};
-use once_cell::sync::OnceCell;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use rd_interface::{Error, Result};
 use tar::Archive;
 
 // Update this when blob is updated
 static GEOIP_TAR_GZ: &[u8] = include_bytes!("../../../blob/GeoLite2-Country.tar.gz");
 static MMDB_FILE_NAME: &str = "GeoLite2-Country.mmdb";
-static GEOIP_DB: OnceCell<maxminddb::Reader<Box<[u8]>>> = OnceCell::new();
-
-pub fn get_reader() -> &'static maxminddb::Reader<Box<[u8]>> {
-    // TODO: don't use expect
-    GEOIP_DB.get_or_init(|| {
-        let tar = GzDecoder::new(GEOIP_TAR_GZ);
-        let mut archive = Archive::new(tar);
-        let entries = archive.entries().expect("Failed to read tar");
-        let mut mmdb = entries
-            .filter_map(|i| i.ok())
-            .find(|i| {
-                i.path()
-                    .expect("Failed to read path")
-                    .as_os_str()
-                    .to_string_lossy()
-                    .ends_with(MMDB_FILE_NAME)
-            })
-            .expect("Failed to find mmdb in .tar.gz");
-        let mut mmdb_buf = Vec::new();
-        mmdb.read_to_end(&mut mmdb_buf)
-            .expect("Failed to read mmdb");
-        let mmdb_buf = mmdb_buf.into_boxed_slice();
-
-        maxminddb::Reader::from_source(mmdb_buf).expect("Failed to read mmdb from source")
-    })
+static GEOIP_DB: Lazy<GeoIpDb> = Lazy::new(GeoIpDb::default);
+
+type Reader = maxminddb::Reader<Box<[u8]>>;
+
+/// The GeoIP database used by the matchers. It is loaded on first use and can be
+/// swapped at runtime, the lookups never wait for a swap.
+#[derive(Default)]
+pub struct GeoIpDb {
+    reader: ArcSwapOption<Reader>,
+    /// The database file, the builtin database if `None`.
+    path: Mutex<Option<PathBuf>>,
 }
 
-impl GeoIpMatcher {
-    fn test(&self, ip: impl Into<IpAddr>) -> bool {
-        let ip = ip.into();
-        let reader = get_reader();
-        let result: Result<geoip2::Country, _> = reader.lookup(ip);
+impl GeoIpDb {
+    pub fn reader(&self) -> Arc<Reader> {
+        if let Some(reader) = self.reader.load_full() {
+            return reader;
+        }
+        let path = self.path.lock();
+        // the first users wait here for a single load
+        if let Some(reader) = self.reader.load_full() {
+            return reader;
+        }
+        // TODO: don't use expect
+        self.store(path.as_deref())
+            .expect("Failed to load GeoIP database")
+    }
+    /// Swap in the database at `path` (`.mmdb` or `.tar.gz`), or the builtin one
+    /// if `None`. Later reloads read the same file.
+    pub fn load(&self, path: Option<PathBuf>) -> Result<()> {
+        let mut current = self.path.lock();
+        let reader = read_database(path.as_deref())?;
+        self.reader.store(Some(Arc::new(reader)));
+        *current = path;
+        Ok(())
+    }
+    /// Read the database file again and swap it in.
+    pub fn reload(&self) -> Result<Arc<Reader>> {
+        let path = self.path.lock();
+        self.store(path.as_deref())
+    }
+    fn store(&self, path: Option<&Path>) -> Result<Arc<Reader>> {
+        let reader = Arc::new(read_database(path)?);
+        self.reader.store(Some(reader.clone()));
+        Ok(reader)
+    }
+    /// Returns whether `ip` is in `country`. An empty `country` matches the addresses not found.
+    fn match_country(&self, country: &str, ip: IpAddr) -> bool {
+        let reader = self.reader();
+        let result: std::result::Result<geoip2::Country, _> = reader.lookup(ip);
         match result {
             Ok(geoip2::Country {
                 country:
                     Some(geoip2::country::Country {
-                        iso_code: Some(country),
+                        iso_code: Some(iso_code),
                         ..
                     }),
                 ..
-            }) => country == self.country,
-            Err(MaxMindDBError::AddressNotFoundError(_)) => self.country.is_empty(),
+            }) => iso_code == country,
+            Err(MaxMindDBError::AddressNotFoundError(_)) => country.is_empty(),
             Err(e) => {
                 tracing::debug!("Failed to lookup country for ip: {}, reason: {:?}", ip, e);
                 false
@@ -65,10 +91,66 @@ impl GeoIpMatcher {
     }
 }
 
+pub fn get_reader() -> Arc<Reader> {
+    GEOIP_DB.reader()
+}
+
+/// Swap in the GeoIP database at `path`, or read the current database file again
+/// if `None`.
+pub fn reload(path: Option<PathBuf>) -> Result<()> {
+    match path {
+        Some(path) => GEOIP_DB.load(Some(path)),
+        None => GEOIP_DB.reload().map(|_| ()),
+    }
+}
+
+fn extract_mmdb(tar_gz: &[u8], file_name: &str) -> Result<Vec<u8>> {
+    let tar = GzDecoder::new(tar_gz);
+    let mut archive = Archive::new(tar);
+    let mut mmdb = archive
+        .entries()?
+        .filter_map(|i| i.ok())
+        .find(|i| match i.path() {
+            Ok(path) => path.to_string_lossy().ends_with(file_name),
+            Err(_) => false,
+        })
+        .ok_or_else(|| Error::other("Failed to find mmdb in .tar.gz"))?;
+    let mut mmdb_buf = Vec::new();
+    mmdb.read_to_end(&mut mmdb_buf)?;
+    Ok(mmdb_buf)
+}
+
+fn read_database(path: Option<&Path>) -> Result<Reader> {
+    let mmdb_buf = match path {
+        Some(path) => {
+            let buf = fs::read(path)?;
+            if path.to_string_lossy().ends_with(".gz") {
+                extract_mmdb(&buf, ".mmdb")?
+            } else {
+                buf
+            }
+        }
+        None => extract_mmdb(GEOIP_TAR_GZ, MMDB_FILE_NAME)?,
+    };
+
+    maxminddb::Reader::from_source(mmdb_buf.into_boxed_slice())
+        .map_err(|e| Error::other(format!("Failed to read mmdb: {}", e)))
+}
+
 impl Matcher for GeoIpMatcher {
     fn match_rule(&self, match_context: &MatchContext) -> MaybeAsync<bool> {
         match match_context.get_socket_addr() {
-            Some(addr) => self.test(addr.ip()),
+            Some(addr) => GEOIP_DB.match_country(&self.country, addr.ip()),
+            None => false,
+        }
+        .into()
+    }
+}
+
+impl Matcher for SrcGeoIpMatcher {
+    fn match_rule(&self, match_context: &MatchContext) -> MaybeAsync<bool> {
+        match match_context.src_ip_addr() {
+            Some(ip) => GEOIP_DB.match_country(&self.country, *ip),
             None => false,
         }
         .into()
@@ -80,6 +162,95 @@ mod tests {
     use super::*;
     use rd_interface::{Address, Context};
 
+    /// An IPv4 database with `1.0.0.0/8` in `country`.
+    fn test_mmdb(country: &str) -> Vec<u8> {
+        const NODE_COUNT: u32 = 8;
+        let record = |v: u32| v.to_be_bytes()[1..].to_vec();
+        let string = |s: &str| [&[0x40 | s.len() as u8][..], s.as_bytes()].concat();
+
+        // the search tree follows the first 8 bits of 1.0.0.0, the data is at offset 0
+        let mut buf = Vec::new();
+        for node in 0..NODE_COUNT {
+            let (left, right) = match node {
+                7 => (NODE_COUNT, NODE_COUNT + 16),
+                _ => (node + 1, NODE_COUNT),
+            };
+            buf.extend(record(left));
+            buf.extend(record(right));
+        }
+        buf.extend([0; 16]);
+
+        // {"country": {"iso_code": country}}
+        buf.push(0xe1);
+        buf.extend(string("country"));
+        buf.push(0xe1);
+        buf.extend(string("iso_code"));
+        buf.extend(string(country));
+
+        buf.extend(b"\xab\xcd\xefMaxMind.com");
+        let metadata = [
+            ("node_count", vec![0xc1, NODE_COUNT as u8]),
+            ("record_size", vec![0xa1, 24]),
+            ("ip_version", vec![0xa1, 4]),
+            ("database_type", string("GeoLite2-Country")),
+            ("languages", vec![0x00, 0x04]),
+            ("binary_format_major_version", vec![0xa1, 2]),
+            ("binary_format_minor_version", vec![0xa0]),
+            ("build_epoch", vec![0x00, 0x02]),
+            ("description", vec![0xe0]),
+        ];
+        buf.push(0xe0 | metadata.len() as u8);
+        for (key, value) in metadata {
+            buf.extend(string(key));
+            buf.extend(value);
+        }
+        buf
+    }
+
+    #[test]
+    fn test_reload() {
+        let dir = std::env::temp_dir().join(format!("rd-geoip-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.mmdb");
+        let one = "1.1.1.1".parse().unwrap();
+        let cn = "114.114.114.114".parse().unwrap();
+
+        let db = GeoIpDb::default();
+        assert!(!db.match_country("CN", one));
+        assert!(db.match_country("CN", cn));
+
+        fs::write(&path, test_mmdb("CN")).unwrap();
+        db.load(Some(path.clone())).unwrap();
+        assert!(db.match_country("CN", one));
+        assert!(db.match_country("", cn));
+
+        // a reload reads the modified file
+        fs::write(&path, test_mmdb("JP")).unwrap();
+        db.reload().unwrap();
+        assert!(db.match_country("JP", one));
+
+        // a failed reload keeps the current database
+        fs::write(&path, b"broken").unwrap();
+        assert!(db.reload().is_err());
+        assert!(db.match_country("JP", one));
+
+        db.load(None).unwrap();
+        assert!(!db.match_country("JP", one));
+        assert!(db.match_country("CN", cn));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_once() {
+        let db = GeoIpDb::default();
+        let readers: Vec<_> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4).map(|_| s.spawn(|| db.reader())).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(readers.iter().all(|r| Arc::ptr_eq(r, &readers[0])));
+    }
+
     #[tokio::test]
     async fn test_cn() {
         let matcher = GeoIpMatcher {
@@ -108,4 +279,24 @@ mod tests {
                 .await
         );
     }
+    #[tokio::test]
+    async fn test_src_cn() {
+        let matcher = SrcGeoIpMatcher {
+            country: "CN".to_string(),
+        };
+        let dest = Address::SocketAddr("1.1.1.1:53".parse().unwrap());
+        let match_src = |src: &str| {
+            let ctx = Context::from_socketaddr(src.parse().unwrap());
+            matcher.match_rule(&MatchContext::from_context_address(&ctx, &dest).unwrap())
+        };
+
+        assert!(match_src("114.114.114.114:12345").await);
+        assert!(!match_src("1.1.1.1:12345").await);
+        // no source address
+        assert!(
+            !matcher
+                .match_rule(&MatchContext::from_context_address(&Context::new(), &dest).unwrap())
+                .await
+        );
+    }
 }
@@ -0,0 +1,43 @@
+use super::config::Ja3Matcher;
+use super::matcher::{MatchContext, Matcher, MaybeAsync};
+
+impl Matcher for Ja3Matcher {
+    fn match_rule(&self, match_context: &MatchContext) -> MaybeAsync<bool> {
+        match match_context.ja3() {
+            Some(ja3) => self.ja3.iter().any(|i| i.eq_ignore_ascii_case(ja3)),
+            None => false,
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::{context::common_field::TlsJa3, Context, IntoAddress};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_ja3_matcher() {
+        let matcher = Ja3Matcher {
+            ja3: vec!["579CCEF312D18482FC42E2B822CA2430".to_string()].into(),
+        };
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+
+        let mut ctx = Context::new();
+        ctx.insert_common(TlsJa3("579ccef312d18482fc42e2b822ca2430".to_string()))
+            .unwrap();
+        let match_context = MatchContext::from_context_address(&ctx, &addr).unwrap();
+        assert!(matcher.match_rule(&match_context).await);
+
+        let mut ctx = Context::new();
+        ctx.insert_common(TlsJa3("cd08e31494f9531f560d64c695473da9".to_string()))
+            .unwrap();
+        let match_context = MatchContext::from_context_address(&ctx, &addr).unwrap();
+        assert!(!matcher.match_rule(&match_context).await);
+
+        // not sniffed
+        let match_context = MatchContext::from_context_address(&Context::new(), &addr).unwrap();
+        assert!(!matcher.match_rule(&match_context).await);
+    }
+}
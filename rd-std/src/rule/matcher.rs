@@ -1,6 +1,6 @@
 use futures::{future::BoxFuture, Future, FutureExt};
 use rd_interface::{
-    context::common_field::{DestDomain, DestSocketAddr, SrcSocketAddr},
+    context::common_field::{DestDomain, DestSocketAddr, SrcSocketAddr, TlsAlpn, TlsJa3},
     Address, AddressDomain, Result,
 };
 use std::{
@@ -47,6 +47,8 @@ pub(super) struct MatchContext {
     src_ip_addr: Option<IpAddr>,
     dest_socket_addr: Option<SocketAddr>,
     dest_domain: Option<AddressDomain>,
+    alpn: Option<String>,
+    ja3: Option<String>,
 }
 
 impl MatchContext {
@@ -59,8 +61,15 @@ impl MatchContext {
             src_ip_addr: ctx.get_common::<SrcSocketAddr>()?.map(|v| v.0.ip()),
             dest_socket_addr: ctx.get_common::<DestSocketAddr>()?.map(|v| v.0),
             dest_domain: ctx.get_common::<DestDomain>()?.map(|v| v.0),
+            alpn: ctx.get_common::<TlsAlpn>()?.map(|v| v.0),
+            ja3: ctx.get_common::<TlsJa3>()?.map(|v| v.0),
         })
     }
+    /// Forget the source, so contexts from different sources are equal.
+    pub fn without_src(mut self) -> MatchContext {
+        self.src_ip_addr = None;
+        self
+    }
     pub fn address(&self) -> &Address {
         &self.address
     }
@@ -73,6 +82,12 @@ impl MatchContext {
     pub fn dest_domain(&self) -> Option<&AddressDomain> {
         self.dest_domain.as_ref()
     }
+    pub fn alpn(&self) -> Option<&str> {
+        self.alpn.as_deref()
+    }
+    pub fn ja3(&self) -> Option<&str> {
+        self.ja3.as_deref()
+    }
     pub fn get_domain(&self) -> Option<(&String, &u16)> {
         match self.address() {
             Address::Domain(d, p) => return Some((d, p)),
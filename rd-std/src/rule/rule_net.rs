@@ -1,85 +1,246 @@
+use std::{
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
 use crate::{rule::matcher::MatchContext, util::UdpConnector};
 
 use super::config;
 use super::matcher::Matcher;
 
 use lru_time_cache::LruCache;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use rd_interface::{
-    async_trait, Address, Arc, Context, INet, IntoDyn, Net, Result, TcpStream, UdpSocket,
+    async_trait,
+    context::common_field::{BulkTransfer, Priority},
+    Address, Arc, Context, Error, INet, IntoDyn, Net, Result, TcpStream, UdpSocket,
 };
+use serde::Serialize;
 use tracing::instrument;
 
 pub struct RuleItem {
-    #[allow(dead_code)]
     pub target_name: String,
     pub target: Net,
-    matcher: config::Matcher,
+    matcher: Arc<config::Matcher>,
+    priority: Option<Priority>,
+    bulk_transfer: bool,
+    provider: Option<String>,
+    hits: AtomicUsize,
+}
+
+impl RuleItem {
+    fn new(item: config::RuleItem) -> RuleItem {
+        let config::RuleItem {
+            target,
+            mut matcher,
+            priority,
+            bulk_transfer,
+            provider,
+        } = item;
+        matcher.shrink_to_fit();
+        RuleItem {
+            matcher: Arc::new(matcher),
+            priority,
+            bulk_transfer,
+            provider,
+            target: target.value_cloned(),
+            target_name: target.represent().to_string(),
+            hits: AtomicUsize::new(0),
+        }
+    }
+    /// Marks the connection with the priority and the bulk transfer hint of the rule.
+    fn set_hints(&self, ctx: &mut Context) -> Result<()> {
+        if let Some(priority) = self.priority {
+            ctx.insert_common(priority)?;
+        }
+        if self.bulk_transfer {
+            ctx.insert_common(BulkTransfer(true))?;
+        }
+        Ok(())
+    }
+}
+
+/// How many times a rule is matched.
+#[derive(Debug, Serialize)]
+pub struct RuleStat {
+    pub index: usize,
+    #[serde(flatten)]
+    pub matcher: Arc<config::Matcher>,
+    pub target: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    pub hits: usize,
+}
+
+type RuleItems = Arc<Vec<Arc<RuleItem>>>;
+
+fn init_geoip(rule: &[config::RuleItem]) {
+    if rule.iter().any(|i| {
+        matches!(
+            i.matcher,
+            config::Matcher::GeoIp(_) | config::Matcher::SrcGeoIp(_)
+        )
+    }) {
+        // if used geoip, init reader first.
+        super::geoip::get_reader();
+    }
+}
+
+/// Where the rules of a rule provider are in the rules, kept when it has no rules
+/// so they can be added back.
+struct ProviderSlot {
+    provider: String,
+    start: usize,
+    len: usize,
+}
+
+/// The slots of the providers of `rule`, in order. The providers whose rules are
+/// not contiguous, e.g. a clash `RULE-SET` used twice, have no slot and can't be
+/// updated in place.
+fn provider_slots(rule: &[Arc<RuleItem>]) -> Vec<ProviderSlot> {
+    let mut slots = Vec::<ProviderSlot>::new();
+    let mut split = Vec::new();
+    for (i, provider) in rule
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| Some((i, item.provider.as_deref()?)))
+    {
+        match slots.last_mut() {
+            Some(last) if last.provider == provider && last.start + last.len == i => last.len += 1,
+            _ if slots.iter().any(|s| s.provider == provider) => split.push(provider),
+            _ => slots.push(ProviderSlot {
+                provider: provider.to_string(),
+                start: i,
+                len: 1,
+            }),
+        }
+    }
+    slots.retain(|s| !split.contains(&s.provider.as_str()));
+    slots
 }
 
 #[derive(Clone)]
 pub struct Rule {
-    rule: Arc<Vec<RuleItem>>,
+    /// Replaced as a whole when the rules of a provider are updated, while the
+    /// `cache` is locked, so a cached index always refers to the current rules.
+    rule: Arc<RwLock<RuleItems>>,
+    slots: Arc<Mutex<Vec<ProviderSlot>>>,
     cache: Arc<Mutex<LruCache<MatchContext, usize>>>,
+    cache_ttl: bool,
+    match_source: Arc<AtomicBool>,
 }
 
 impl Rule {
     fn new(config: config::RuleNetConfig) -> Result<Rule> {
-        if config
-            .rule
-            .iter()
-            .any(|i| matches!(i.matcher, config::Matcher::GeoIp(_)))
-        {
-            // if used geoip, init reader first.
-            super::geoip::get_reader();
-        }
+        init_geoip(&config.rule);
         let mut rule = config
             .rule
             .into_iter()
-            .map(
-                |config::RuleItem {
-                     target,
-                     mut matcher,
-                 }| {
-                    matcher.shrink_to_fit();
-                    Ok(RuleItem {
-                        matcher,
-                        target: target.value_cloned(),
-                        target_name: target.represent().to_string(),
-                    })
-                },
-            )
-            .collect::<Result<Vec<_>>>()?;
+            .map(|item| Arc::new(RuleItem::new(item)))
+            .collect::<Vec<_>>();
 
         rule.shrink_to_fit();
 
-        let rule = Arc::new(rule);
-        let cache = Arc::new(Mutex::new(LruCache::with_capacity(config.lru_cache_size)));
+        let match_source = rule.iter().any(|i| i.matcher.match_source());
+        let slots = provider_slots(&rule);
+        let rule = Arc::new(RwLock::new(Arc::new(rule)));
+        let cache = match config.lru_cache_ttl {
+            Some(ttl) => LruCache::with_expiry_duration_and_capacity(
+                Duration::from_secs(ttl),
+                config.lru_cache_size,
+            ),
+            None => LruCache::with_capacity(config.lru_cache_size),
+        };
 
-        Ok(Rule { rule, cache })
+        Ok(Rule {
+            rule,
+            slots: Arc::new(Mutex::new(slots)),
+            cache: Arc::new(Mutex::new(cache)),
+            cache_ttl: config.lru_cache_ttl.is_some(),
+            match_source: Arc::new(AtomicBool::new(match_source)),
+        })
+    }
+    fn items(&self) -> RuleItems {
+        self.rule.read().clone()
     }
     #[instrument(skip(self), err)]
-    pub async fn get_rule(&self, ctx: &Context, target: &Address) -> Result<&RuleItem> {
-        let match_context = MatchContext::from_context_address(ctx, target)?;
+    pub async fn get_rule(&self, ctx: &Context, target: &Address) -> Result<Arc<RuleItem>> {
+        let mut match_context = MatchContext::from_context_address(ctx, target)?;
+        if !self.match_source.load(Ordering::Relaxed) {
+            match_context = match_context.without_src();
+        }
 
-        // hit cache
-        if let Some(i) = self.cache.lock().get(&match_context).copied() {
-            let rule = &self.rule[i];
+        // hit cache. with a TTL, hits don't refresh the entry,
+        // otherwise a busy destination would never expire.
+        let (rules, cached) = {
+            let mut cache = self.cache.lock();
+            let cached = if self.cache_ttl {
+                cache.peek(&match_context).copied()
+            } else {
+                cache.get(&match_context).copied()
+            };
+            (self.items(), cached)
+        };
+        if let Some(i) = cached {
+            let rule = &rules[i];
+            rule.hits.fetch_add(1, Ordering::Relaxed);
             tracing::trace!(matcher = ?rule.matcher, hit_cache = true, "matched rule");
-            return Ok(rule);
+            return Ok(rule.clone());
         }
 
-        for (i, rule) in self.rule.iter().enumerate() {
+        for (i, rule) in rules.iter().enumerate() {
             if rule.matcher.match_rule(&match_context).await {
-                self.cache.lock().insert(match_context, i);
+                rule.hits.fetch_add(1, Ordering::Relaxed);
+                let mut cache = self.cache.lock();
+                // the rules may be updated while matching
+                if Arc::ptr_eq(&rules, &self.rule.read()) {
+                    cache.insert(match_context, i);
+                }
                 tracing::trace!(matcher = ?rule.matcher, hit_cache = false, "matched rule");
-                return Ok(rule);
+                return Ok(rule.clone());
             }
         }
 
         tracing::trace!("Not matched");
         Err(rd_interface::Error::NotMatched)
     }
+    fn update_provider(&self, provider: &str, rules: Vec<config::RuleItem>) -> Result<usize> {
+        init_geoip(&rules);
+        let new_len = rules.len();
+        let new_rules = rules.into_iter().map(|mut item| {
+            item.provider = Some(provider.to_string());
+            Arc::new(RuleItem::new(item))
+        });
+
+        let mut cache = self.cache.lock();
+        let mut slots = self.slots.lock();
+        let index = slots
+            .iter()
+            .position(|s| s.provider == provider)
+            .ok_or_else(|| Error::NotFound(format!("rule provider {}", provider)))?;
+        let ProviderSlot { start, len, .. } = slots[index];
+
+        let mut rule = self.rule.write();
+        let mut items = Vec::with_capacity(rule.len() - len + new_len);
+        items.extend(rule[..start].iter().cloned());
+        items.extend(new_rules);
+        items.extend(rule[start + len..].iter().cloned());
+
+        slots[index].len = new_len;
+        for slot in &mut slots[index + 1..] {
+            slot.start = slot.start - len + new_len;
+        }
+
+        self.match_source.store(
+            items.iter().any(|i| i.matcher.match_source()),
+            Ordering::Relaxed,
+        );
+        *rule = Arc::new(items);
+        cache.clear();
+
+        Ok(start)
+    }
 }
 
 pub struct RuleNet {
@@ -92,17 +253,37 @@ impl RuleNet {
             rule: Rule::new(config)?,
         })
     }
+    /// Hit counts of the rules, in the order of the config.
+    pub fn rule_stats(&self) -> Vec<RuleStat> {
+        self.rule
+            .items()
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| RuleStat {
+                index,
+                matcher: rule.matcher.clone(),
+                target: rule.target_name.clone(),
+                provider: rule.provider.clone(),
+                hits: rule.hits.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+    /// Replace the rules derived from the rule provider `provider` with `rules`, at
+    /// the position of the old ones, and returns the position. The other rules and
+    /// their hit counts are kept, and the open connections are not affected. The
+    /// targets of `rules` must be resolved. A provider updated with no rules keeps
+    /// its position for the next update.
+    pub fn update_provider(&self, provider: &str, rules: Vec<config::RuleItem>) -> Result<usize> {
+        self.rule.update_provider(provider, rules)
+    }
 }
 
 #[async_trait]
 impl rd_interface::TcpConnect for RuleNet {
     async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
-        self.rule
-            .get_rule(ctx, addr)
-            .await?
-            .target
-            .tcp_connect(ctx, addr)
-            .await
+        let rule = self.rule.get_rule(ctx, addr).await?;
+        rule.set_hints(ctx)?;
+        rule.target.tcp_connect(ctx, addr).await
     }
 }
 
@@ -117,6 +298,7 @@ impl rd_interface::UdpBind for RuleNet {
             let target_addr = target_addr.clone();
             Box::pin(async move {
                 let rule_item = rule.get_rule(&ctx, &target_addr).await?;
+                rule_item.set_hints(&mut ctx)?;
                 let mut udp = rule_item.target.udp_bind(&mut ctx, &bind_addr).await?;
                 udp.send_to(&buf, &target_addr).await?;
                 Ok(udp)
@@ -126,6 +308,15 @@ impl rd_interface::UdpBind for RuleNet {
     }
 }
 
+#[async_trait]
+impl rd_interface::LookupHost for RuleNet {
+    async fn lookup_host(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+        let rule = self.rule.get_rule(ctx, addr).await?;
+        rule.set_hints(ctx)?;
+        rule.target.lookup_host(ctx, addr).await
+    }
+}
+
 impl INet for RuleNet {
     fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
         Some(self)
@@ -134,6 +325,10 @@ impl INet for RuleNet {
     fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
         Some(self)
     }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        Some(self)
+    }
 }
 
 #[cfg(test)]
@@ -156,6 +351,7 @@ mod tests {
         let rule_config = config::RuleNetConfig {
             rule: vec![],
             lru_cache_size: 10,
+            lru_cache_ttl: None,
         };
         let rule_net = RuleNet::new(rule_config).unwrap().into_dyn();
 
@@ -164,6 +360,7 @@ mod tests {
             ProviderCapability {
                 tcp_connect: true,
                 udp_bind: true,
+                lookup_host: true,
                 ..Default::default()
             },
         );
@@ -183,13 +380,20 @@ mod tests {
                         country: "CN".to_string(),
                     }),
                     target: NetRef::new_with_value("noop".into(), noop.clone()),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
                 },
                 config::RuleItem {
                     matcher: config::Matcher::Any(config::AnyMatcher {}),
                     target: NetRef::new_with_value("test".into(), net.clone()),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
                 },
             ],
             lru_cache_size: 10,
+            lru_cache_ttl: None,
         };
         let rule_net = RuleNet::new(rule_config).unwrap().into_dyn();
 
@@ -211,6 +415,7 @@ mod tests {
         let rule_config = config::RuleNetConfig {
             rule: vec![],
             lru_cache_size: 10,
+            lru_cache_ttl: None,
         };
         let rule_net = RuleNet::new(rule_config).unwrap().into_dyn();
 
@@ -223,6 +428,73 @@ mod tests {
         assert!(matches!(err, Err(rd_interface::Error::NotMatched)));
     }
 
+    #[tokio::test]
+    async fn test_rule_lookup_host() {
+        let net = TestNet::new().into_dyn();
+        let noop = NotImplementedNet.into_dyn();
+
+        let rule_net = RuleNet::new(config::RuleNetConfig {
+            rule: vec![
+                config::RuleItem {
+                    matcher: config::Matcher::SrcIpCidr(config::SrcIpCidrMatcher {
+                        ipcidr: vec!["10.0.0.1/32".parse().unwrap()].into(),
+                    }),
+                    target: NetRef::new_with_value("noop".into(), noop.clone()),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                },
+                config::RuleItem {
+                    matcher: config::Matcher::Domain(config::DomainMatcher {
+                        method: config::DomainMatcherMethod::Suffix,
+                        domain: vec!["example.com".to_string()].into(),
+                    }),
+                    target: NetRef::new_with_value("net".into(), net),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                },
+                config::RuleItem {
+                    matcher: config::Matcher::Any(config::AnyMatcher {}),
+                    target: NetRef::new_with_value("noop".into(), noop),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                },
+            ],
+            lru_cache_size: 10,
+            lru_cache_ttl: None,
+        })
+        .unwrap()
+        .into_dyn();
+
+        let addrs = rule_net
+            .lookup_host(
+                &mut Context::new(),
+                &"www.example.com:80".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:80".parse().unwrap()]);
+
+        let err = rule_net
+            .lookup_host(
+                &mut Context::new(),
+                &"www.example.org:80".into_address().unwrap(),
+            )
+            .await;
+        assert!(matches!(err, Err(rd_interface::Error::NotImplemented)));
+
+        // the rules are matched with the caller's context
+        let err = rule_net
+            .lookup_host(
+                &mut Context::from_socketaddr("10.0.0.1:1234".parse().unwrap()),
+                &"www.example.com:80".into_address().unwrap(),
+            )
+            .await;
+        assert!(matches!(err, Err(rd_interface::Error::NotImplemented)));
+    }
+
     #[tokio::test]
     async fn test_rule_types() {
         let net = TestNet::new().into_dyn();
@@ -235,8 +507,12 @@ mod tests {
                     ipcidr: vec!["127.0.0.1/32".parse().unwrap()].into(),
                 }),
                 target: NetRef::new_with_value("net".into(), net.clone()),
+                priority: None,
+                bulk_transfer: false,
+                provider: None,
             }],
             lru_cache_size: 10,
+            lru_cache_ttl: None,
         })
         .unwrap()
         .into_dyn();
@@ -249,8 +525,12 @@ mod tests {
                     ipcidr: vec!["127.0.0.1/32".parse().unwrap()].into(),
                 }),
                 target: NetRef::new_with_value("net".into(), net.clone()),
+                priority: None,
+                bulk_transfer: false,
+                provider: None,
             }],
             lru_cache_size: 10,
+            lru_cache_ttl: None,
         })
         .unwrap()
         .into_dyn();
@@ -277,8 +557,12 @@ mod tests {
                     domain: vec!["localhost".to_string()].into(),
                 }),
                 target: NetRef::new_with_value("net".into(), net.clone()),
+                priority: None,
+                bulk_transfer: false,
+                provider: None,
             }],
             lru_cache_size: 10,
+            lru_cache_ttl: None,
         })
         .unwrap()
         .into_dyn();
@@ -286,6 +570,279 @@ mod tests {
         assert_echo(&rule_net, "localhost:12345").await;
     }
 
+    #[tokio::test]
+    async fn test_rule_priority() {
+        struct PriorityNet;
+        #[async_trait]
+        impl rd_interface::TcpConnect for PriorityNet {
+            async fn tcp_connect(&self, ctx: &mut Context, _addr: &Address) -> Result<TcpStream> {
+                let priority = ctx.get_common::<Priority>()?;
+                let bulk = ctx.get_common::<BulkTransfer>()?.map(|b| b.0);
+                Err(rd_interface::Error::other(format!(
+                    "{:?} {:?}",
+                    priority, bulk
+                )))
+            }
+        }
+        impl INet for PriorityNet {
+            fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+                Some(self)
+            }
+        }
+        let net = PriorityNet.into_dyn();
+
+        let rule_net = RuleNet::new(config::RuleNetConfig {
+            rule: vec![
+                config::RuleItem {
+                    matcher: config::Matcher::Domain(config::DomainMatcher {
+                        method: config::DomainMatcherMethod::Match,
+                        domain: vec!["localhost".to_string()].into(),
+                    }),
+                    target: NetRef::new_with_value("net".into(), net.clone()),
+                    priority: Some(Priority::High),
+                    bulk_transfer: true,
+                    provider: None,
+                },
+                config::RuleItem {
+                    matcher: config::Matcher::Any(config::AnyMatcher {}),
+                    target: NetRef::new_with_value("net".into(), net.clone()),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                },
+            ],
+            lru_cache_size: 10,
+            lru_cache_ttl: None,
+        })
+        .unwrap()
+        .into_dyn();
+
+        let err = rule_net
+            .tcp_connect(&mut Context::new(), &"localhost:80".into_address().unwrap())
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Some(High) Some(true)"));
+
+        let err = rule_net
+            .tcp_connect(
+                &mut Context::new(),
+                &"example.com:80".into_address().unwrap(),
+            )
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("None None"));
+    }
+
+    #[tokio::test]
+    async fn test_rule_stats() {
+        let net = TestNet::new().into_dyn();
+
+        spawn_echo_server(&net, "127.0.0.1:12345").await;
+        spawn_echo_server_udp(&net, "127.0.0.1:12345").await;
+
+        let rule_net = RuleNet::new(config::RuleNetConfig {
+            rule: vec![
+                config::RuleItem {
+                    matcher: config::Matcher::IpCidr(config::IpCidrMatcher {
+                        ipcidr: vec!["127.0.0.1/32".parse().unwrap()].into(),
+                    }),
+                    target: NetRef::new_with_value("ip".into(), net.clone()),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                },
+                config::RuleItem {
+                    matcher: config::Matcher::Domain(config::DomainMatcher {
+                        method: config::DomainMatcherMethod::Match,
+                        domain: vec!["localhost".to_string()].into(),
+                    }),
+                    target: NetRef::new_with_value("domain".into(), net.clone()),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                },
+                config::RuleItem {
+                    matcher: config::Matcher::GeoIp(config::GeoIpMatcher {
+                        country: "CN".to_string(),
+                    }),
+                    target: NetRef::new_with_value("geoip".into(), net.clone()),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                },
+            ],
+            lru_cache_size: 10,
+            lru_cache_ttl: None,
+        })
+        .unwrap();
+        let hits = |rule_net: &RuleNet| {
+            rule_net
+                .rule_stats()
+                .iter()
+                .map(|s| s.hits)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(hits(&rule_net), vec![0, 0, 0]);
+
+        let rule_net = Arc::new(rule_net);
+        let net = Net::from(rule_net.clone() as Arc<dyn INet>);
+
+        // the second one hits the cache
+        assert_echo(&net, "127.0.0.1:12345").await;
+        assert_echo(&net, "127.0.0.1:12345").await;
+        assert_echo_udp(&net, "127.0.0.1:12345").await;
+        assert_echo(&net, "localhost:12345").await;
+        // not matched
+        assert!(net
+            .tcp_connect(&mut Context::new(), &"1.1.1.1:53".into_address().unwrap())
+            .await
+            .is_err());
+
+        assert_eq!(hits(&rule_net), vec![3, 1, 0]);
+
+        let stats = rule_net.rule_stats();
+        assert_eq!(stats[1].index, 1);
+        assert_eq!(stats[1].target, "domain");
+        assert!(matches!(*stats[1].matcher, config::Matcher::Domain(_)));
+    }
+
+    #[tokio::test]
+    async fn test_update_provider() {
+        let net = TestNet::new().into_dyn();
+        let noop = NotImplementedNet.into_dyn();
+
+        spawn_echo_server(&net, "127.0.0.1:12345").await;
+
+        let item = |matcher, target: &str, net: &Net, provider: Option<&str>| config::RuleItem {
+            matcher,
+            target: NetRef::new_with_value(target.into(), net.clone()),
+            priority: None,
+            bulk_transfer: false,
+            provider: provider.map(ToString::to_string),
+        };
+        let ipcidr = |cidr: &str| {
+            config::Matcher::IpCidr(config::IpCidrMatcher {
+                ipcidr: vec![cidr.parse().unwrap()].into(),
+            })
+        };
+        let rule_net = Arc::new(
+            RuleNet::new(config::RuleNetConfig {
+                rule: vec![
+                    item(
+                        config::Matcher::Domain(config::DomainMatcher {
+                            method: config::DomainMatcherMethod::Match,
+                            domain: vec!["localhost".to_string()].into(),
+                        }),
+                        "domain",
+                        &net,
+                        None,
+                    ),
+                    item(ipcidr("10.0.0.0/8"), "noop", &noop, Some("set")),
+                    item(ipcidr("10.0.0.0/8"), "noop", &noop, Some("other")),
+                    item(
+                        config::Matcher::Any(config::AnyMatcher {}),
+                        "any",
+                        &net,
+                        None,
+                    ),
+                ],
+                lru_cache_size: 10,
+                lru_cache_ttl: None,
+            })
+            .unwrap(),
+        );
+        let dyn_net = Net::from(rule_net.clone() as Arc<dyn INet>);
+
+        assert_echo(&dyn_net, "localhost:12345").await;
+        // an open connection, matched by the last rule and cached
+        let mut tcp = dyn_net
+            .tcp_connect(
+                &mut Context::new(),
+                &"127.0.0.1:12345".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+
+        rule_net
+            .update_provider(
+                "set",
+                vec![
+                    item(ipcidr("127.0.0.1/32"), "set", &net, None),
+                    item(ipcidr("127.0.0.2/32"), "set", &net, None),
+                ],
+            )
+            .unwrap();
+        assert!(rule_net.update_provider("missing", vec![]).is_err());
+
+        let stats = rule_net.rule_stats();
+        let summary = stats
+            .iter()
+            .map(|s| (s.target.as_str(), s.provider.as_deref(), s.hits))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec![
+                ("domain", None, 1),
+                ("set", Some("set"), 0),
+                ("set", Some("set"), 0),
+                ("noop", Some("other"), 0),
+                ("any", None, 1),
+            ]
+        );
+
+        // the open connection is untouched
+        tcp.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        tcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // the cached decision is dropped, the new rules are matched
+        assert_echo(&dyn_net, "127.0.0.1:12345").await;
+        let hits = rule_net
+            .rule_stats()
+            .iter()
+            .map(|s| s.hits)
+            .collect::<Vec<_>>();
+        assert_eq!(hits, vec![1, 1, 0, 0, 1]);
+
+        // a provider without rules keeps its position
+        assert_eq!(rule_net.update_provider("set", vec![]).unwrap(), 1);
+        assert_eq!(rule_net.rule_stats().len(), 3);
+        let rules = vec![item(ipcidr("127.0.0.3/32"), "set", &net, None)];
+        assert_eq!(rule_net.update_provider("set", rules).unwrap(), 1);
+        assert_eq!(rule_net.update_provider("other", vec![]).unwrap(), 2);
+        let targets = rule_net
+            .rule_stats()
+            .iter()
+            .map(|s| s.target.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(targets, vec!["domain", "set", "any"]);
+    }
+
+    #[test]
+    fn test_split_provider() {
+        let noop = NotImplementedNet.into_dyn();
+        let item = |provider: &str| config::RuleItem {
+            matcher: config::Matcher::Any(config::AnyMatcher {}),
+            target: NetRef::new_with_value("noop".into(), noop.clone()),
+            priority: None,
+            bulk_transfer: false,
+            provider: Some(provider.to_string()),
+        };
+        let rule_net = RuleNet::new(config::RuleNetConfig {
+            rule: vec![item("a"), item("b"), item("a")],
+            lru_cache_size: 10,
+            lru_cache_ttl: None,
+        })
+        .unwrap();
+
+        // the rules of `a` are not contiguous
+        assert!(rule_net.update_provider("a", vec![]).is_err());
+        assert_eq!(rule_net.update_provider("b", vec![]).unwrap(), 1);
+    }
+
     #[tokio::test]
     async fn test_normalize() {
         let net = TestNet::new().into_dyn();
@@ -298,8 +855,12 @@ mod tests {
                     ipcidr: vec!["127.0.0.1/32".parse().unwrap()].into(),
                 }),
                 target: NetRef::new_with_value("net".into(), net.clone()),
+                priority: None,
+                bulk_transfer: false,
+                provider: None,
             }],
             lru_cache_size: 10,
+            lru_cache_ttl: None,
         })
         .unwrap()
         .into_dyn();
@@ -307,4 +868,57 @@ mod tests {
         let addr = Address::Domain("127.0.0.1".to_string(), 12345);
         assert_echo(&rule_net, addr).await;
     }
+
+    #[tokio::test]
+    async fn test_rule_cache() {
+        let net = TestNet::new().into_dyn();
+        let build = |matcher| {
+            RuleNet::new(config::RuleNetConfig {
+                rule: vec![config::RuleItem {
+                    matcher,
+                    target: NetRef::new_with_value("net".into(), net.clone()),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                }],
+                lru_cache_size: 10,
+                lru_cache_ttl: Some(1),
+            })
+            .unwrap()
+        };
+        let addr = "127.0.0.1:12345".into_address().unwrap();
+        let ctx1 = Context::from_socketaddr("127.0.0.1:1".parse().unwrap());
+        let ctx2 = Context::from_socketaddr("127.0.0.2:2".parse().unwrap());
+
+        // no rule matches the source, connections from both sources share the entry
+        let rule_net = build(config::Matcher::IpCidr(config::IpCidrMatcher {
+            ipcidr: vec!["127.0.0.1/32".parse().unwrap()].into(),
+        }));
+        let rule = &rule_net.rule;
+        rule.get_rule(&ctx1, &addr).await.unwrap();
+        rule.get_rule(&ctx2, &addr).await.unwrap();
+        let key = MatchContext::from_context_address(&ctx1, &addr)
+            .unwrap()
+            .without_src();
+        assert_eq!(rule.cache.lock().len(), 1);
+        assert_eq!(rule.cache.lock().peek(&key), Some(&0));
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(rule.cache.lock().peek(&key), None);
+
+        // the source is part of the key once a rule matches it
+        let rule_net = build(config::Matcher::SrcIpCidr(config::SrcIpCidrMatcher {
+            ipcidr: vec!["127.0.0.1/32".parse().unwrap()].into(),
+        }));
+        let rule = &rule_net.rule;
+        rule.get_rule(&ctx1, &addr).await.unwrap();
+        assert!(rule.get_rule(&ctx2, &addr).await.is_err());
+        assert_eq!(rule.cache.lock().len(), 1);
+        assert_eq!(
+            rule.cache
+                .lock()
+                .peek(&MatchContext::from_context_address(&ctx1, &addr).unwrap()),
+            Some(&0)
+        );
+    }
 }
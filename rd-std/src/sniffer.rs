@@ -1,4 +1,6 @@
+pub use block_quic::{is_quic_initial, BlockQuicNet};
 pub use dns_sniffer::DNSSnifferNet;
+pub(crate) use sni_sniffer::get_sni;
 pub use sni_sniffer::SNISnifferNet;
 
 use rd_interface::{
@@ -8,6 +10,7 @@ use rd_interface::{
     Net, Registry, Result,
 };
 
+mod block_quic;
 mod dns_sniffer;
 mod service;
 mod sni_sniffer;
@@ -21,6 +24,7 @@ pub struct DNSNetConfig {
 
 impl Builder<Net> for DNSSnifferNet {
     const NAME: &'static str = "dns_sniffer";
+    const TITLE: &'static str = "DNS Sniffer";
     type Config = DNSNetConfig;
     type Item = Self;
 
@@ -47,6 +51,7 @@ pub struct SNINetConfig {
 
 impl Builder<Net> for SNISnifferNet {
     const NAME: &'static str = "sni_sniffer";
+    const TITLE: &'static str = "SNI Sniffer";
     type Config = SNINetConfig;
     type Item = Self;
 
@@ -59,7 +64,30 @@ impl Builder<Net> for SNISnifferNet {
     }
 }
 
+#[rd_config]
+#[derive(Debug)]
+pub struct BlockQuicNetConfig {
+    #[serde(default)]
+    net: NetRef,
+    /// Ports to block QUIC to.
+    /// If not set, only 443 port will be blocked.
+    #[serde(default)]
+    ports: Option<Vec<u16>>,
+}
+
+impl Builder<Net> for BlockQuicNet {
+    const NAME: &'static str = "block_quic";
+    const TITLE: &'static str = "Block QUIC";
+    type Config = BlockQuicNetConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        Ok(BlockQuicNet::new(config.net.value_cloned(), config.ports))
+    }
+}
+
 pub fn init(registry: &mut Registry) -> Result<()> {
+    registry.add_net::<BlockQuicNet>();
     registry.add_net::<DNSSnifferNet>();
     registry.add_net::<SNISnifferNet>();
     Ok(())
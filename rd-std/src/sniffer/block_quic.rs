@@ -0,0 +1,190 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    task::{self, Poll},
+};
+
+use rd_interface::{
+    async_trait, Address, Context, INet, IUdpSocket, IntoDyn, Net, Result, UdpSocket,
+};
+
+/// Clients pad the datagrams of the QUIC Initial packets to at least 1200 bytes.
+const QUIC_INITIAL_MIN_SIZE: usize = 1200;
+
+/// Whether the datagram looks like a QUIC Initial packet, a long header packet
+/// of a non-zero version, padded to the minimum size.
+pub fn is_quic_initial(packet: &[u8]) -> bool {
+    if packet.len() < QUIC_INITIAL_MIN_SIZE {
+        return false;
+    }
+    // the header form and fixed bits are set
+    if packet[0] & 0xc0 != 0xc0 {
+        return false;
+    }
+    // version 0 is the version negotiation
+    packet[1..5] != [0, 0, 0, 0]
+}
+
+/// This net drops the QUIC handshakes to `ports`, so the browsers fall back to
+/// HTTP/2 over TCP. The other UDP datagrams pass.
+pub struct BlockQuicNet {
+    net: Net,
+    ports: Arc<Vec<u16>>,
+}
+
+impl BlockQuicNet {
+    pub fn new(net: Net, ports: Option<Vec<u16>>) -> Self {
+        Self {
+            net,
+            ports: Arc::new(ports.unwrap_or_else(|| vec![443])),
+        }
+    }
+}
+
+#[async_trait]
+impl rd_interface::UdpBind for BlockQuicNet {
+    async fn udp_bind(&self, ctx: &mut Context, addr: &Address) -> Result<UdpSocket> {
+        let udp = self.net.udp_bind(ctx, addr).await?;
+        Ok(BlockQuicUdp(udp, self.ports.clone()).into_dyn())
+    }
+}
+
+impl INet for BlockQuicNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        self.net.provide_tcp_connect()
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        Some(self)
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
+struct BlockQuicUdp(UdpSocket, Arc<Vec<u16>>);
+
+#[async_trait]
+impl IUdpSocket for BlockQuicUdp {
+    async fn local_addr(&self) -> Result<SocketAddr> {
+        self.0.local_addr().await
+    }
+
+    fn poll_recv_from(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &mut rd_interface::ReadBuf,
+    ) -> Poll<io::Result<SocketAddr>> {
+        self.0.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(
+        &mut self,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+        target: &Address,
+    ) -> Poll<io::Result<usize>> {
+        // dropped silently like a lost packet, an error would close the whole
+        // UDP association
+        if self.1.contains(&target.port()) && is_quic_initial(buf) {
+            tracing::debug!("QUIC to {} is blocked", target);
+            return Poll::Ready(Ok(buf.len()));
+        }
+        self.0.poll_send_to(cx, buf, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::{IntoAddress, ReadBuf};
+
+    use crate::tests::{assert_net_provider, ProviderCapability, TestNet};
+
+    use super::*;
+
+    fn quic_initial() -> Vec<u8> {
+        let mut packet = vec![0u8; QUIC_INITIAL_MIN_SIZE];
+        // long header, Initial, QUIC v1
+        packet[..5].copy_from_slice(&[0xc3, 0x00, 0x00, 0x00, 0x01]);
+        packet
+    }
+
+    #[test]
+    fn test_provider() {
+        let net = BlockQuicNet::new(TestNet::new().into_dyn(), None).into_dyn();
+
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+    }
+
+    #[test]
+    fn test_is_quic_initial() {
+        assert!(is_quic_initial(&quic_initial()));
+
+        let mut short = quic_initial();
+        short[0] = 0x43;
+        assert!(!is_quic_initial(&short));
+        let mut negotiation = quic_initial();
+        negotiation[1..5].copy_from_slice(&[0, 0, 0, 0]);
+        assert!(!is_quic_initial(&negotiation));
+        assert!(!is_quic_initial(&quic_initial()[..100]));
+    }
+
+    #[tokio::test]
+    async fn test_block_quic() {
+        let test_net = TestNet::new().into_dyn();
+        let net = BlockQuicNet::new(test_net.clone(), None).into_dyn();
+
+        let mut ctx = Context::new();
+        let mut https = test_net
+            .udp_bind(&mut ctx, &"127.0.0.1:443".into_address().unwrap())
+            .await
+            .unwrap();
+        let mut client = net
+            .udp_bind(&mut ctx, &"127.0.0.1:0".into_address().unwrap())
+            .await
+            .unwrap();
+        let target = "127.0.0.1:443".into_address().unwrap();
+
+        // QUIC to 443 is dropped
+        assert_eq!(
+            client.send_to(&quic_initial(), &target).await.unwrap(),
+            QUIC_INITIAL_MIN_SIZE
+        );
+
+        // the other UDP on the same socket passes
+        client.send_to(b"hello", &target).await.unwrap();
+        let buf = &mut vec![0; 2048];
+        let mut buf = ReadBuf::new(buf);
+        https.recv_from(&mut buf).await.unwrap();
+        assert_eq!(buf.filled(), b"hello");
+
+        let mut other = test_net
+            .udp_bind(&mut ctx, &"127.0.0.1:8443".into_address().unwrap())
+            .await
+            .unwrap();
+        let target = "127.0.0.1:8443".into_address().unwrap();
+        client.send_to(&quic_initial(), &target).await.unwrap();
+        let mut buf = vec![0; 2048];
+        let mut buf = ReadBuf::new(&mut buf);
+        other.recv_from(&mut buf).await.unwrap();
+        assert_eq!(buf.filled().len(), QUIC_INITIAL_MIN_SIZE);
+    }
+}
@@ -6,8 +6,9 @@ use std::{
 use super::service::ReverseLookup;
 use futures::ready;
 use rd_interface::{
-    async_trait, context::common_field::DestDomain, Address, AddressDomain, Context, INet,
-    IUdpSocket, IntoDyn, Net, Result, UdpSocket,
+    async_trait,
+    context::common_field::{DestDomain, ResolvedAddrs},
+    Address, AddressDomain, Context, INet, IUdpSocket, IntoDyn, Net, Result, UdpSocket,
 };
 
 /// This net is used for reverse lookup.
@@ -15,7 +16,8 @@ use rd_interface::{
 /// When a UDP packet recv from port 53, the DNS response will be recorded in this net.
 /// And the DNS response will be sent to the client.
 /// The tcp_connect to recorded IP will be recovered to domain name.
-/// If the domain name is in the cache, this net will add "DestDomain" to the context.
+/// If the domain name is in the cache, this net will add "DestDomain" to the context,
+/// and the IP as "ResolvedAddrs" so the outbound net doesn't resolve it again.
 pub struct DNSSnifferNet {
     net: Net,
     rl: ReverseLookup,
@@ -28,6 +30,10 @@ impl DNSSnifferNet {
             rl: ReverseLookup::new(),
         }
     }
+    /// Forget the recorded DNS responses. The established connections are not affected.
+    pub fn flush(&self) {
+        self.rl.clear();
+    }
     fn reverse_lookup(&self, ctx: &mut Context, addr: &Address) -> Address {
         match addr {
             Address::SocketAddr(sa) => self
@@ -35,11 +41,17 @@ impl DNSSnifferNet {
                 .reverse_lookup(sa.ip())
                 .map(|name| {
                     let domain = Address::Domain(name.clone(), sa.port());
-                    ctx.insert_common(DestDomain(AddressDomain {
+                    let dest = AddressDomain {
                         domain: name,
                         port: sa.port(),
-                    }))
-                    .expect("Failed to insert domain");
+                    };
+                    ctx.insert_common(ResolvedAddrs {
+                        domain: dest.clone(),
+                        addrs: vec![*sa],
+                    })
+                    .expect("Failed to insert resolved addrs");
+                    ctx.insert_common(DestDomain(dest))
+                        .expect("Failed to insert domain");
                     tracing::trace!(?domain, "recovered domain");
                     domain
                 })
@@ -85,6 +97,10 @@ impl INet for DNSSnifferNet {
     fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
         self.net.provide_lookup_host()
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 struct MitmUdp(UdpSocket, ReverseLookup);
@@ -362,4 +378,31 @@ mod tests {
             "www.google.com:443".into_address().unwrap()
         )
     }
+
+    #[test]
+    fn test_flush() {
+        let net = DNSSnifferNet::new(TestNet::new().into_dyn());
+
+        // dns response to baidu.com. 220.181.38.148, 220.181.38.251
+        net.rl.record_packet(&[
+            0x00, 0x02, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x05, 0x62,
+            0x61, 0x69, 0x64, 0x75, 0x03, 0x63, 0x6F, 0x6D, 0x00, 0x00, 0x01, 0x00, 0x01, 0xC0,
+            0x0C, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0xFA, 0x00, 0x04, 0xDC, 0xB5, 0x26,
+            0x94, 0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x01, 0xFA, 0x00, 0x04, 0xDC,
+            0xB5, 0x26, 0xFB,
+        ]);
+        let addr = "220.181.38.148:443".into_address().unwrap();
+        let mut ctx = Context::new();
+        assert_eq!(
+            net.reverse_lookup(&mut ctx, &addr),
+            "baidu.com:443".into_address().unwrap()
+        );
+        // the client resolved it, so the outbound net connects to the same IP
+        let hint = ctx.get_common::<ResolvedAddrs>().unwrap().unwrap();
+        assert_eq!(hint.domain.domain, "baidu.com");
+        assert_eq!(hint.addrs, vec!["220.181.38.148:443".parse().unwrap()]);
+
+        net.flush();
+        assert_eq!(net.reverse_lookup(&mut Context::new(), &addr), addr);
+    }
 }
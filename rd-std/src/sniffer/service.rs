@@ -62,6 +62,11 @@ impl ReverseLookup {
             }
         }
     }
+    pub fn clear(&self) {
+        let Inner { records, cname_map } = &mut *self.inner.lock();
+        records.clear();
+        cname_map.clear();
+    }
     pub fn reverse_lookup(&self, addr: IpAddr) -> Option<String> {
         let Inner { records, cname_map } = &mut *self.inner.lock();
         match records.get(&addr) {
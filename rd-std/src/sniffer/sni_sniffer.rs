@@ -8,8 +8,12 @@ use std::{
 };
 
 use futures::{task::AtomicWaker, FutureExt};
+use itertools::Itertools;
+use md5::{Digest, Md5};
 use rd_interface::{
-    async_trait, Address, AsyncRead, AsyncWrite, INet, IntoDyn, Net, Result, NOT_IMPLEMENTED,
+    async_trait,
+    context::common_field::{SniffedProtocol, TlsJa3},
+    Address, AsyncRead, AsyncWrite, INet, IntoDyn, Net, Result, NOT_IMPLEMENTED,
 };
 use tls_parser::{
     parse_tls_client_hello_extensions, parse_tls_plaintext, SNIType, TlsExtension, TlsMessage,
@@ -55,6 +59,10 @@ impl INet for SNISnifferNet {
     fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
         self.net.provide_lookup_host()
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 #[async_trait]
@@ -186,9 +194,14 @@ impl rd_interface::ITcpStream for SnifferTcp {
                     timeout,
                 } => {
                     if let Some(sni) = get_sni(&param.buffer) {
+                        let mut ctx = param.ctx.clone();
+                        let _ = ctx.insert_common(SniffedProtocol("tls".to_string()));
+                        if let Some(ja3) = get_ja3(&param.buffer) {
+                            let _ = ctx.insert_common(TlsJa3(ja3));
+                        }
                         let future = spawn(connect_send(
                             param.net.clone(),
-                            param.ctx.clone(),
+                            ctx,
                             Address::Domain(sni, addr.port()).into_normalized(),
                             replace(&mut param.buffer, Vec::new()),
                         ));
@@ -273,7 +286,7 @@ fn is_valid_domain(domain: &str) -> bool {
         .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
 }
 
-fn get_sni(bytes: &[u8]) -> Option<String> {
+pub(crate) fn get_sni(bytes: &[u8]) -> Option<String> {
     let (_, res) = parse_tls_plaintext(&bytes).ok()?;
 
     res.msg
@@ -303,9 +316,61 @@ fn get_sni(bytes: &[u8]) -> Option<String> {
         .next()
 }
 
+/// The JA3 fingerprint of the TLS ClientHello, the MD5 of
+/// `version,ciphers,extensions,curves,point_formats` without the GREASE values.
+/// It identifies the TLS library of the client rather than the app, and can be
+/// changed by the client at will, so it's only a hint.
+pub(crate) fn get_ja3(bytes: &[u8]) -> Option<String> {
+    let (_, res) = parse_tls_plaintext(bytes).ok()?;
+    let ch = res.msg.into_iter().find_map(|m| match m {
+        TlsMessage::Handshake(TlsMessageHandshake::ClientHello(ch)) => Some(ch),
+        _ => None,
+    })?;
+
+    let mut extensions = Vec::new();
+    let mut curves = Vec::new();
+    let mut point_formats = Vec::new();
+    let mut ext = ch.ext.unwrap_or_default();
+    while ext.len() >= 4 {
+        let ext_type = u16::from_be_bytes([ext[0], ext[1]]);
+        let len = u16::from_be_bytes([ext[2], ext[3]]) as usize;
+        let data = ext.get(4..4 + len)?;
+        ext = &ext[4 + len..];
+
+        extensions.push(ext_type);
+        match ext_type {
+            // supported_groups, after the length of the list
+            10 => {
+                curves = data
+                    .get(2..)?
+                    .chunks_exact(2)
+                    .map(|i| u16::from_be_bytes([i[0], i[1]]))
+                    .collect()
+            }
+            // ec_point_formats, after the length of the list
+            11 => point_formats = data.get(1..)?.iter().map(|i| *i as u16).collect(),
+            _ => {}
+        }
+    }
+    let ciphers = ch.ciphers.iter().map(|i| i.0).collect::<Vec<_>>();
+
+    let ja3 = [ciphers, extensions, curves, point_formats]
+        .iter()
+        .map(|values| values.iter().filter(|i| !is_grease(**i)).join("-"))
+        .join(",");
+    let ja3 = format!("{},{}", ch.version.0, ja3);
+
+    Some(format!("{:x}", Md5::digest(ja3.as_bytes())))
+}
+
+/// The reserved values clients send to keep the servers tolerant, e.g. `0x0a0a`.
+fn is_grease(value: u16) -> bool {
+    value & 0x0f0f == 0x0a0a && value >> 8 == value & 0xff
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{get_sni, is_valid_domain};
+    use super::{get_ja3, get_sni, is_grease, is_valid_domain};
 
     const TLS_CLIENT_HELLO: &[u8] = &[
         0x16u8, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00, 0x01, 0xfc, 0x03, 0x03, 0xad, 0x1a, 0xb0, 0x9a,
@@ -358,6 +423,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_ja3() {
+        // the ClientHello of Firefox
+        assert_eq!(
+            get_ja3(TLS_CLIENT_HELLO),
+            Some("579ccef312d18482fc42e2b822ca2430".to_string())
+        );
+        assert_eq!(get_ja3(&[]), None);
+
+        assert!(is_grease(0x0a0a));
+        assert!(is_grease(0xfafa));
+        assert!(!is_grease(0x0a1a));
+    }
+
     #[test]
     fn test_mijia_cloud_invalid() {
         assert!(!is_valid_domain("Mijia Cloud"))
@@ -1,9 +1,10 @@
 pub use self::{client::Socks5Client, server::Socks5Server};
 
+use crate::tls::{default_session_cache, TlsNet, TlsNetConfig, TlsServerConfig};
 use rd_interface::{
     prelude::*,
     registry::{Builder, NetRef},
-    Address, Net, Registry, Result, Server,
+    Address, IntoDyn, Net, Registry, Result, Server,
 };
 
 mod client;
@@ -19,6 +20,18 @@ pub struct Socks5NetConfig {
 
     #[serde(default)]
     net: NetRef,
+    /// connect to the server over TLS (socks5s), UDP packets are not encrypted
+    #[serde(default)]
+    tls: bool,
+    /// Dangerous, but can be used to skip certificate verification.
+    #[serde(default)]
+    skip_cert_verify: bool,
+    /// Override domain with SNI
+    #[serde(default)]
+    sni: Option<String>,
+    /// ALPN protocols to offer over TLS
+    #[serde(default)]
+    alpn: Vec<String>,
 }
 
 #[rd_config]
@@ -30,29 +43,65 @@ pub struct Socks5ServerConfig {
     net: NetRef,
     #[serde(default)]
     listen: NetRef,
+    /// read the PROXY protocol header on inbound connections
+    #[serde(default)]
+    proxy_protocol: bool,
+    /// terminate TLS before the SOCKS5 handshake (socks5s)
+    #[serde(default)]
+    tls: Option<TlsServerConfig>,
+    /// maximum bytes read in the SOCKS5 handshake, the connection is closed
+    /// if it's exceeded. default is 16KB.
+    #[serde(default = "default_max_handshake_bytes")]
+    max_handshake_bytes: usize,
 }
 
 impl Builder<Net> for Socks5Client {
     const NAME: &'static str = "socks5";
+    const TITLE: &'static str = "SOCKS5 Client";
     type Config = Socks5NetConfig;
     type Item = Self;
 
     fn build(config: Self::Config) -> Result<Self> {
-        Ok(Socks5Client::new(config.net.value_cloned(), config.server))
+        let client = Socks5Client::new(config.net.value_cloned(), config.server);
+        if !config.tls {
+            return Ok(client);
+        }
+
+        let tls_net = TlsNet::build(TlsNetConfig {
+            skip_cert_verify: config.skip_cert_verify,
+            sni: config.sni,
+            enable_early_data: false,
+            alpn: config.alpn,
+            session_cache: default_session_cache(),
+            net: config.net,
+        })?;
+        Ok(client.tcp_net(tls_net.into_dyn()))
     }
 }
 
 impl Builder<Server> for server::Socks5 {
     const NAME: &'static str = "socks5";
+    const TITLE: &'static str = "SOCKS5 Server";
     type Config = Socks5ServerConfig;
     type Item = Self;
 
-    fn build(Self::Config { listen, net, bind }: Self::Config) -> Result<Self> {
-        Ok(server::Socks5::new(
-            listen.value_cloned(),
-            net.value_cloned(),
+    fn build(
+        Self::Config {
+            listen,
+            net,
             bind,
-        ))
+            proxy_protocol,
+            tls,
+            max_handshake_bytes,
+        }: Self::Config,
+    ) -> Result<Self> {
+        let tls = tls.map(|tls| tls.build_acceptor()).transpose()?;
+        Ok(
+            server::Socks5::new(listen.value_cloned(), net.value_cloned(), bind)
+                .proxy_protocol(proxy_protocol)
+                .tls(tls)
+                .max_handshake_bytes(max_handshake_bytes),
+        )
     }
 }
 
@@ -20,6 +20,8 @@ use tokio::io::{AsyncWriteExt, BufWriter};
 pub struct Socks5Client {
     server: Address,
     net: Net,
+    /// net of the TCP connections to the server, may be wrapped in TLS
+    tcp_net: Net,
 }
 
 pub struct Socks5TcpStream(TcpStream);
@@ -98,7 +100,7 @@ impl rd_interface::TcpConnect for Socks5Client {
         ctx: &mut rd_interface::Context,
         addr: &rd_interface::Address,
     ) -> Result<TcpStream> {
-        let mut socket = self.net.tcp_connect(ctx, &self.server).await?;
+        let mut socket = self.tcp_net.tcp_connect(ctx, &self.server).await?;
 
         let req = CommandRequest::connect(ra2sa(addr.clone().into_address()?));
         let _resp = self.send_command(&mut socket, req).await?;
@@ -116,7 +118,7 @@ impl rd_interface::UdpBind for Socks5Client {
     ) -> Result<UdpSocket> {
         let server_addr = self
             .net
-            .lookup_host(&self.server)
+            .lookup_host(ctx, &self.server)
             .await?
             .into_iter()
             .next()
@@ -125,7 +127,8 @@ impl rd_interface::UdpBind for Socks5Client {
                 "Failed to lookup domain",
             ))?;
 
-        let mut socket = self.net.tcp_connect(ctx, &server_addr.into()).await?;
+        // by the name of the server, it's the SNI when connecting over TLS
+        let mut socket = self.tcp_net.tcp_connect(ctx, &self.server).await?;
 
         let req = CommandRequest::udp_associate(ra2sa(addr.clone().into_address()?));
         let resp = self.send_command(&mut socket, req).await?;
@@ -154,11 +157,25 @@ impl INet for Socks5Client {
     fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
         Some(self)
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.tcp_net.clone())
+    }
 }
 
 impl Socks5Client {
     pub fn new(net: Net, server: Address) -> Self {
-        Self { server, net }
+        Self {
+            server,
+            tcp_net: net.clone(),
+            net,
+        }
+    }
+    /// Use `tcp_net` to connect to the server, e.g. a `TlsNet` for SOCKS5 over TLS.
+    /// UDP packets are still sent by `net`.
+    pub fn tcp_net(mut self, tcp_net: Net) -> Self {
+        self.tcp_net = tcp_net;
+        self
     }
     async fn send_command(
         &self,
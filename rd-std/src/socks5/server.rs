@@ -1,10 +1,20 @@
 use super::common::{pack_udp, parse_udp, sa2ra};
-use crate::ContextExt;
+use crate::{
+    tls::{alpn_protocol, TlsAcceptor},
+    util::{
+        accept_with_backoff, default_max_handshake_bytes, proxy_protocol, PeekableTcpStream,
+        ACCEPT_BACKOFF,
+    },
+    ContextExt,
+};
 use anyhow::Context as AnyhowContext;
 use futures::ready;
 use rd_interface::{
-    async_trait, constant::UDP_BUFFER_SIZE, Address as RdAddr, Address as RDAddr, AsyncRead,
-    Context, IServer, IUdpChannel, IntoDyn, Net, ReadBuf, Result, TcpStream, UdpSocket,
+    async_trait,
+    constant::UDP_BUFFER_SIZE,
+    context::common_field::{RequestId, TlsAlpn},
+    Address as RdAddr, Address as RDAddr, AsyncRead, AsyncWrite, Context, IServer, IUdpChannel,
+    IntoDyn, Net, ReadBuf, Result, TcpStream, UdpSocket,
 };
 use socks5_protocol::{
     Address, AuthMethod, AuthRequest, AuthResponse, Command, CommandReply, CommandRequest,
@@ -18,7 +28,7 @@ use std::{
     task::{self, Poll},
 };
 use tokio::io::{AsyncWriteExt, BufWriter};
-use tracing::instrument;
+use tracing::{instrument, Span};
 
 struct Socks5ServerConfig {
     net: Net,
@@ -28,13 +38,66 @@ struct Socks5ServerConfig {
 #[derive(Clone)]
 pub struct Socks5Server {
     cfg: Arc<Socks5ServerConfig>,
+    max_handshake_bytes: usize,
+}
+
+/// Fails the reads once more than `remaining` bytes are read, the writes are passed through.
+struct LimitHandshake<'a, S> {
+    inner: &'a mut S,
+    remaining: usize,
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for LimitHandshake<'_, S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(Pin::new(&mut *self.inner).poll_read(cx, buf))?;
+        let read = buf.filled().len() - filled;
+
+        match self.remaining.checked_sub(read) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                Poll::Ready(Ok(()))
+            }
+            None => Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "socks5 handshake is too large",
+            ))),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for LimitHandshake<'_, S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.inner).poll_shutdown(cx)
+    }
 }
 
 impl Socks5Server {
     async fn handle_command_request(
         &self,
-        mut socket: &mut BufWriter<TcpStream>,
+        socket: &mut BufWriter<TcpStream>,
     ) -> anyhow::Result<CommandRequest> {
+        let mut socket = LimitHandshake {
+            inner: socket,
+            remaining: self.max_handshake_bytes,
+        };
+
         let version = Version::read(&mut socket).await?;
         let auth_req = AuthRequest::read(&mut socket).await?;
 
@@ -59,8 +122,29 @@ impl Socks5Server {
         socket.flush().await?;
         Ok(())
     }
-    #[instrument(err, skip(self, socket))]
     pub async fn serve_connection(self, socket: TcpStream, addr: SocketAddr) -> anyhow::Result<()> {
+        self.serve_connection_with_context(socket, addr, Context::from_socketaddr(addr))
+            .await
+    }
+    /// Serve a connection, `ctx` carries the fields known before the SOCKS5 handshake,
+    /// e.g. the negotiated TLS ALPN. A request id is generated if `ctx` has none,
+    /// as SOCKS5 has no field for it.
+    #[instrument(err, skip(self, socket, ctx), fields(request_id))]
+    pub async fn serve_connection_with_context(
+        self,
+        socket: TcpStream,
+        addr: SocketAddr,
+        mut ctx: Context,
+    ) -> anyhow::Result<()> {
+        let request_id = match ctx.get_common::<RequestId>()? {
+            Some(request_id) => request_id,
+            None => {
+                let request_id = RequestId::generate();
+                ctx.insert_common(request_id.clone())?;
+                request_id
+            }
+        };
+        Span::current().record("request_id", request_id.0.as_str());
         let mut socket = BufWriter::with_capacity(512, socket);
 
         let default_addr: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
@@ -75,8 +159,12 @@ impl Socks5Server {
         match cmd_req.command {
             Command::Connect => {
                 let dst = sa2ra(cmd_req.address);
-                let ctx = &mut Context::from_socketaddr(addr);
-                let out = match net.tcp_connect(ctx, &dst).await {
+                let ctx = &mut ctx;
+                // the connect is cancelled if the client leaves meanwhile
+                let mut client = PeekableTcpStream::new(socket.into_inner());
+                let connect = client.cancel_on_close(net.tcp_connect(ctx, &dst)).await;
+                let mut socket = BufWriter::with_capacity(512, client.into_dyn());
+                let out = match connect {
                     Ok(socket) => socket,
                     Err(e) => return self.response_command_error(&mut socket, e).await,
                 };
@@ -101,7 +189,7 @@ impl Socks5Server {
                         return Ok(());
                     }
                 };
-                let ctx = &mut Context::from_socketaddr(addr);
+                let ctx = &mut ctx;
                 let out = match net.udp_bind(ctx, &dst).await {
                     Ok(socket) => socket,
                     Err(e) => return self.response_command_error(&mut socket, e).await,
@@ -130,9 +218,11 @@ impl Socks5Server {
                     endpoint: None,
                     send_buf: Vec::with_capacity(UDP_BUFFER_SIZE),
                 };
-                ctx.connect_udp(udp_channel.into_dyn(), out)
-                    .await
-                    .context("connect udp")?;
+                match ctx.connect_udp(udp_channel.into_dyn(), out).await {
+                    // the client closed the control connection, which ends the association
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => {}
+                    r => r.context("connect udp")?,
+                }
             }
             _ => {
                 return Ok(());
@@ -144,8 +234,14 @@ impl Socks5Server {
     pub fn new(listen_net: Net, net: Net) -> Self {
         Self {
             cfg: Arc::new(Socks5ServerConfig { net, listen_net }),
+            max_handshake_bytes: default_max_handshake_bytes(),
         }
     }
+    /// Close the connections reading more than `bytes` in the handshake.
+    pub fn max_handshake_bytes(mut self, bytes: usize) -> Self {
+        self.max_handshake_bytes = bytes;
+        self
+    }
 }
 
 pub struct Socks5UdpSocket {
@@ -157,19 +253,34 @@ pub struct Socks5UdpSocket {
 }
 
 impl Socks5UdpSocket {
-    fn poll_tcp_close(&mut self, cx: &mut task::Context<'_>) -> Poll<bool> {
-        let mut buf = [0; 1];
-        let mut buf = ReadBuf::new(&mut buf);
-        Poll::Ready(ready!(Pin::new(&mut self.tcp).poll_read(cx, &mut buf)).is_err())
+    /// Ready once the control connection is closed. The association must be
+    /// terminated then, so the relay socket is dropped at once instead of on timeout.
+    fn poll_tcp_close(&mut self, cx: &mut task::Context<'_>) -> Poll<()> {
+        loop {
+            let mut buf = [0; 1];
+            let mut buf = ReadBuf::new(&mut buf);
+            match ready!(Pin::new(&mut self.tcp).poll_read(cx, &mut buf)) {
+                // nothing is expected from the client, discard it
+                Ok(()) if !buf.filled().is_empty() => continue,
+                _ => return Poll::Ready(()),
+            }
+        }
     }
 }
 
+fn connection_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "connection closed")
+}
+
 impl IUdpChannel for Socks5UdpSocket {
     fn poll_send_to(
         &mut self,
         cx: &mut task::Context<'_>,
         buf: &mut ReadBuf,
     ) -> Poll<io::Result<RDAddr>> {
+        if self.poll_tcp_close(cx).is_ready() {
+            return Poll::Ready(Err(connection_closed()));
+        }
         let from_addr = ready!(self.udp.poll_recv_from(cx, buf))?;
         if self.endpoint.is_none() {
             self.endpoint = Some(from_addr);
@@ -186,11 +297,8 @@ impl IUdpChannel for Socks5UdpSocket {
         buf: &[u8],
         target: &SocketAddr,
     ) -> Poll<io::Result<usize>> {
-        if let Poll::Ready(true) = self.poll_tcp_close(cx) {
-            return Poll::Ready(Err(io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "connection closed",
-            )));
+        if self.poll_tcp_close(cx).is_ready() {
+            return Poll::Ready(Err(connection_closed()));
         }
         let Socks5UdpSocket {
             udp,
@@ -224,6 +332,8 @@ pub struct Socks5 {
     server: Socks5Server,
     listen_net: Net,
     bind: RdAddr,
+    proxy_protocol: bool,
+    tls: Option<TlsAcceptor>,
 }
 
 #[async_trait]
@@ -235,10 +345,34 @@ impl IServer for Socks5 {
             .await?;
 
         loop {
-            let (socket, addr) = listener.accept().await?;
+            let (mut socket, addr) =
+                accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
             let server = self.server.clone();
+            let proxy_protocol = self.proxy_protocol;
+            let tls = self.tls.clone();
             let _ = tokio::spawn(async move {
-                if let Err(e) = server.serve_connection(socket, addr).await {
+                let serve = async {
+                    let addr = if proxy_protocol {
+                        proxy_protocol::accept(&mut socket, addr).await?
+                    } else {
+                        addr
+                    };
+                    let mut ctx = Context::from_socketaddr(addr);
+                    let socket = match tls {
+                        Some(tls) => {
+                            let stream = tls.accept(socket).await?;
+                            if let Some(alpn) = alpn_protocol(&stream) {
+                                ctx.insert_common(TlsAlpn(alpn))?;
+                            }
+                            TcpStream::from(stream)
+                        }
+                        None => socket,
+                    };
+                    server
+                        .serve_connection_with_context(socket, addr, ctx)
+                        .await
+                };
+                if let Err(e) = serve.await {
                     tracing::error!("Error when serve_connection: {:?}", e)
                 }
             });
@@ -252,6 +386,23 @@ impl Socks5 {
             server: Socks5Server::new(listen_net.clone(), net),
             listen_net,
             bind,
+            proxy_protocol: false,
+            tls: None,
         }
     }
+    /// Read the PROXY protocol header on inbound connections.
+    pub fn proxy_protocol(mut self, enabled: bool) -> Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+    /// Terminate TLS before the SOCKS5 handshake.
+    pub fn tls(mut self, tls: Option<TlsAcceptor>) -> Self {
+        self.tls = tls;
+        self
+    }
+    /// Close the connections reading more than `bytes` in the handshake.
+    pub fn max_handshake_bytes(mut self, bytes: usize) -> Self {
+        self.server = self.server.max_handshake_bytes(bytes);
+        self
+    }
 }
@@ -1,8 +1,9 @@
 use super::*;
+use crate::builtin::mem::MemNet;
 use crate::tests::{
     assert_echo, assert_echo_udp, get_registry, spawn_echo_server, spawn_echo_server_udp, TestNet,
 };
-use rd_interface::IntoAddress;
+use rd_interface::{config::EmptyConfig, registry::Builder, IntoAddress};
 use rd_interface::{IServer, IntoDyn};
 use std::time::Duration;
 use tokio::time::sleep;
@@ -34,3 +35,356 @@ async fn test_socks5_server_client() {
     assert_echo(&client, "127.0.0.1:26666").await;
     assert_echo_udp(&client, "127.0.0.1:26666").await;
 }
+
+#[tokio::test]
+async fn test_socks5_over_mem_net() {
+    let net = MemNet::build(EmptyConfig::default()).unwrap().into_dyn();
+    spawn_echo_server(&net, "127.0.0.1:26666").await;
+    spawn_echo_server_udp(&net, "127.0.0.1:26666").await;
+
+    let server = server::Socks5::new(
+        net.clone(),
+        net.clone(),
+        "127.0.0.1:16666".into_address().unwrap(),
+    );
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(1)).await;
+
+    let client =
+        client::Socks5Client::new(net, "127.0.0.1:16666".into_address().unwrap()).into_dyn();
+
+    assert_echo(&client, "127.0.0.1:26666").await;
+    assert_echo_udp(&client, "127.0.0.1:26666").await;
+}
+
+#[tokio::test]
+async fn test_socks5_over_tls() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26666").await;
+
+    let testdata = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tls/testdata");
+    let server = server::Socks5::build(Socks5ServerConfig {
+        bind: "127.0.0.1:16666".into_address().unwrap(),
+        net: NetRef::new_with_value("test".into(), local.clone()),
+        listen: NetRef::new_with_value("test".into(), local.clone()),
+        proxy_protocol: false,
+        max_handshake_bytes: crate::util::default_max_handshake_bytes(),
+        tls: Some(TlsServerConfig {
+            cert: format!("{}/cert.pem", testdata),
+            key: format!("{}/key.pem", testdata),
+            alpn: Vec::new(),
+        }),
+    })
+    .unwrap();
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(1)).await;
+
+    let client = Socks5Client::build(Socks5NetConfig {
+        server: "127.0.0.1:16666".into_address().unwrap(),
+        net: NetRef::new_with_value("test".into(), local.clone()),
+        tls: true,
+        skip_cert_verify: true,
+        sni: Some("localhost".to_string()),
+        alpn: Vec::new(),
+    })
+    .unwrap()
+    .into_dyn();
+    assert_echo(&client, "127.0.0.1:26666").await;
+
+    // a plain SOCKS5 client can't talk to the TLS server
+    let plain =
+        client::Socks5Client::new(local, "127.0.0.1:16666".into_address().unwrap()).into_dyn();
+    assert!(plain
+        .tcp_connect(
+            &mut rd_interface::Context::new(),
+            &"127.0.0.1:26666".into_address().unwrap()
+        )
+        .await
+        .is_err());
+}
+
+/// Records the addresses of the TCP connections.
+struct RecordNet {
+    net: Net,
+    connected: std::sync::Mutex<Vec<Address>>,
+}
+
+#[rd_interface::async_trait]
+impl rd_interface::TcpConnect for RecordNet {
+    async fn tcp_connect(
+        &self,
+        ctx: &mut rd_interface::Context,
+        addr: &Address,
+    ) -> Result<rd_interface::TcpStream> {
+        self.connected.lock().unwrap().push(addr.clone());
+        self.net.tcp_connect(ctx, addr).await
+    }
+}
+
+impl rd_interface::INet for RecordNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        self.net.provide_udp_bind()
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        self.net.provide_lookup_host()
+    }
+}
+
+#[tokio::test]
+async fn test_socks5_udp_over_tls() {
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server_udp(&local, "127.0.0.1:26670").await;
+
+    let testdata = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tls/testdata");
+    let server = server::Socks5::build(Socks5ServerConfig {
+        bind: "127.0.0.1:16670".into_address().unwrap(),
+        net: NetRef::new_with_value("test".into(), local.clone()),
+        listen: NetRef::new_with_value("test".into(), local.clone()),
+        proxy_protocol: false,
+        max_handshake_bytes: crate::util::default_max_handshake_bytes(),
+        tls: Some(TlsServerConfig {
+            cert: format!("{}/cert.pem", testdata),
+            key: format!("{}/key.pem", testdata),
+            alpn: Vec::new(),
+        }),
+    })
+    .unwrap();
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(1)).await;
+
+    let record = rd_interface::Arc::new(RecordNet {
+        net: local,
+        connected: Default::default(),
+    });
+    let client = Socks5Client::build(Socks5NetConfig {
+        server: "localhost:16670".into_address().unwrap(),
+        net: NetRef::new_with_value(
+            "record".into(),
+            Net::from(record.clone() as rd_interface::Arc<dyn rd_interface::INet>),
+        ),
+        tls: true,
+        skip_cert_verify: true,
+        sni: None,
+        alpn: Vec::new(),
+    })
+    .unwrap()
+    .into_dyn();
+    assert_echo_udp(&client, "127.0.0.1:26670").await;
+
+    // the control connection goes to the name of the server, not to the resolved
+    // address, so the name is the SNI
+    assert_eq!(
+        *record.connected.lock().unwrap(),
+        ["localhost:16670".into_address().unwrap()]
+    );
+}
+
+// server side ALPN is not supported by native-tls
+#[cfg(any(feature = "rustls", feature = "openssl"))]
+#[tokio::test]
+async fn test_socks5_over_tls_route_by_alpn() {
+    use crate::rule::{config, RuleNet};
+    use rd_interface::Context;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26667").await;
+
+    // only h2 is routed, others are not matched
+    let rule_net = RuleNet::new(config::RuleNetConfig {
+        rule: vec![config::RuleItem {
+            matcher: config::Matcher::Alpn(config::AlpnMatcher {
+                alpn: vec!["h2".to_string()].into(),
+            }),
+            target: NetRef::new_with_value("local".into(), local.clone()),
+            priority: None,
+            bulk_transfer: false,
+            provider: None,
+        }],
+        lru_cache_size: 10,
+        lru_cache_ttl: None,
+    })
+    .unwrap()
+    .into_dyn();
+
+    let testdata = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tls/testdata");
+    let server = server::Socks5::build(Socks5ServerConfig {
+        bind: "127.0.0.1:16667".into_address().unwrap(),
+        net: NetRef::new_with_value("rule".into(), rule_net),
+        listen: NetRef::new_with_value("test".into(), local.clone()),
+        proxy_protocol: false,
+        max_handshake_bytes: crate::util::default_max_handshake_bytes(),
+        tls: Some(TlsServerConfig {
+            cert: format!("{}/cert.pem", testdata),
+            key: format!("{}/key.pem", testdata),
+            alpn: vec!["h2".to_string(), "http/1.1".to_string()],
+        }),
+    })
+    .unwrap();
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(1)).await;
+
+    let client = |alpn: Vec<&str>| {
+        Socks5Client::build(Socks5NetConfig {
+            server: "127.0.0.1:16667".into_address().unwrap(),
+            net: NetRef::new_with_value("test".into(), local.clone()),
+            tls: true,
+            skip_cert_verify: true,
+            sni: Some("localhost".to_string()),
+            alpn: alpn.into_iter().map(ToString::to_string).collect(),
+        })
+        .unwrap()
+        .into_dyn()
+    };
+
+    assert_echo(&client(vec!["h2"]), "127.0.0.1:26667").await;
+
+    for alpn in [vec!["http/1.1"], vec![]] {
+        let client = client(alpn);
+        let result = async {
+            let mut tcp = client
+                .tcp_connect(
+                    &mut Context::new(),
+                    &"127.0.0.1:26667".into_address().unwrap(),
+                )
+                .await?;
+            tcp.write_all(b"hello").await?;
+            let mut buf = [0u8; 5];
+            tcp.read_exact(&mut buf).await?;
+            rd_interface::Result::Ok(())
+        }
+        .await;
+        assert!(result.is_err());
+    }
+}
+
+#[tokio::test]
+async fn test_socks5_udp_closed_with_control_connection() {
+    use super::common::pack_udp;
+    use rd_interface::{Context, ReadBuf};
+    use socks5_protocol::{
+        AuthMethod, AuthRequest, AuthResponse, CommandRequest, CommandResponse, Version,
+    };
+    use tokio::{io::AsyncWriteExt, time::timeout};
+
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server_udp(&local, "127.0.0.1:26668").await;
+
+    let server = server::Socks5::new(
+        local.clone(),
+        local.clone(),
+        "127.0.0.1:16668".into_address().unwrap(),
+    );
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(1)).await;
+
+    let mut tcp = local
+        .tcp_connect(
+            &mut Context::new(),
+            &"127.0.0.1:16668".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+    Version::V5.write(&mut tcp).await.unwrap();
+    AuthRequest::new(vec![AuthMethod::Noauth])
+        .write(&mut tcp)
+        .await
+        .unwrap();
+    CommandRequest::udp_associate("0.0.0.0:0".parse::<std::net::SocketAddr>().unwrap().into())
+        .write(&mut tcp)
+        .await
+        .unwrap();
+    tcp.flush().await.unwrap();
+
+    Version::read(&mut tcp).await.unwrap();
+    AuthResponse::read(&mut tcp).await.unwrap();
+    let relay = CommandResponse::read(&mut tcp)
+        .await
+        .unwrap()
+        .address
+        .to_socket_addr()
+        .unwrap();
+
+    // the relay works while the control connection is open
+    let mut udp = local
+        .udp_bind(&mut Context::new(), &"0.0.0.0:0".into_address().unwrap())
+        .await
+        .unwrap();
+    let mut packet = Vec::new();
+    pack_udp(
+        "127.0.0.1:26668".into_address().unwrap(),
+        b"hello",
+        &mut packet,
+    )
+    .unwrap();
+    udp.send_to(&packet, &relay.into()).await.unwrap();
+    let buf = &mut vec![0; 4096];
+    let mut buf = ReadBuf::new(buf);
+    timeout(Duration::from_secs(5), udp.recv_from(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(buf.filled().ends_with(b"hello"));
+
+    drop(tcp);
+    sleep(Duration::from_millis(100)).await;
+
+    // the relay socket is closed, so its port can be bound again
+    local
+        .udp_bind(&mut Context::new(), &relay.into())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_socks5_max_handshake_bytes() {
+    use rd_interface::Context;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let local = TestNet::new().into_dyn();
+    spawn_echo_server(&local, "127.0.0.1:26669").await;
+
+    let server = server::Socks5::new(
+        local.clone(),
+        local.clone(),
+        "127.0.0.1:16669".into_address().unwrap(),
+    )
+    .max_handshake_bytes(64);
+    tokio::spawn(async move { server.start().await });
+
+    sleep(Duration::from_millis(1)).await;
+
+    // a small handshake is accepted
+    let client =
+        client::Socks5Client::new(local.clone(), "127.0.0.1:16669".into_address().unwrap())
+            .into_dyn();
+    assert_echo(&client, "127.0.0.1:26669").await;
+
+    // 255 auth methods exceed the limit
+    let mut tcp = local
+        .tcp_connect(
+            &mut Context::new(),
+            &"127.0.0.1:16669".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+    let mut handshake = vec![5, 255];
+    handshake.extend([0; 255]);
+    tcp.write_all(&handshake).await.unwrap();
+
+    let mut buf = Vec::new();
+    let read = tokio::time::timeout(Duration::from_secs(1), tcp.read_to_end(&mut buf))
+        .await
+        .unwrap();
+    assert!(read.is_err() || buf.is_empty());
+}
@@ -128,7 +128,7 @@ impl rd_interface::UdpBind for TestNet {
 
 #[async_trait]
 impl rd_interface::LookupHost for TestNet {
-    async fn lookup_host(&self, addr: &Address) -> Result<Vec<SocketAddr>> {
+    async fn lookup_host(&self, _ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
         Ok(vec![make_sa(addr.port())])
     }
 }
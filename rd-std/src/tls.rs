@@ -1,8 +1,10 @@
 use backend::*;
+pub use backend::{alpn_protocol, TlsAcceptor};
 use rd_interface::{
     async_trait, config::NetRef, prelude::*, rd_config, registry::Builder, Address, INet, Net,
     Registry, Result, TcpStream,
 };
+pub use server::{TlsTerminator, TlsTerminatorConfig};
 
 #[cfg(feature = "rustls")]
 #[path = "tls/rustls.rs"]
@@ -16,9 +18,14 @@ mod backend;
 #[path = "tls/native-tls.rs"]
 mod backend;
 
+mod server;
+
 #[derive(Clone)]
 pub(crate) struct TlsConnectorConfig {
     pub skip_cert_verify: bool,
+    pub enable_early_data: bool,
+    pub alpn: Vec<String>,
+    pub session_cache: usize,
 }
 
 #[rd_config]
@@ -31,10 +38,52 @@ pub struct TlsNetConfig {
     #[serde(default)]
     pub sni: Option<String>,
 
+    /// Send the first write as TLS 1.3 early data (0-RTT) on resumed sessions.
+    /// Early data can be replayed, only enable it for idempotent traffic.
+    /// Only supported by the rustls backend.
+    #[serde(default)]
+    pub enable_early_data: bool,
+
+    /// ALPN protocols to offer, e.g. `h2`
+    #[serde(default)]
+    pub alpn: Vec<String>,
+
+    /// maximum TLS sessions remembered by server name, so the following connections
+    /// resume them instead of a full handshake. 0 to disable. default is 256.
+    /// Not supported by the native-tls backend.
+    #[serde(default = "default_session_cache")]
+    pub session_cache: usize,
+
     #[serde(default)]
     pub net: NetRef,
 }
 
+pub fn default_session_cache() -> usize {
+    256
+}
+
+/// Certificate and private key of a TLS server, in PEM format.
+#[rd_config]
+#[derive(Debug, Clone)]
+pub struct TlsServerConfig {
+    /// path to the certificate chain
+    pub cert: String,
+    /// path to the PKCS#8 private key
+    pub key: String,
+    /// ALPN protocols supported by the server, in order of preference.
+    /// Rejected by the native-tls backend.
+    #[serde(default)]
+    pub alpn: Vec<String>,
+}
+
+impl TlsServerConfig {
+    pub fn build_acceptor(&self) -> Result<TlsAcceptor> {
+        let cert = std::fs::read(&self.cert)?;
+        let key = std::fs::read(&self.key)?;
+        TlsAcceptor::new(&cert, &key, &self.alpn)
+    }
+}
+
 pub struct TlsNet {
     connector: TlsConnector,
     sni: Option<String>,
@@ -62,10 +111,15 @@ impl INet for TlsNet {
     fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
         Some(self)
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
 }
 
 impl Builder<Net> for TlsNet {
     const NAME: &'static str = "tls";
+    const TITLE: &'static str = "TLS Client";
 
     type Config = TlsNetConfig;
 
@@ -75,6 +129,9 @@ impl Builder<Net> for TlsNet {
         Ok(TlsNet {
             connector: TlsConnector::new(TlsConnectorConfig {
                 skip_cert_verify: cfg.skip_cert_verify,
+                enable_early_data: cfg.enable_early_data,
+                alpn: cfg.alpn,
+                session_cache: cfg.session_cache,
             })?,
             sni: cfg.sni,
             net: cfg.net.value_cloned(),
@@ -84,6 +141,7 @@ impl Builder<Net> for TlsNet {
 
 pub fn init(registry: &mut Registry) -> Result<()> {
     registry.add_net::<TlsNet>();
+    registry.add_server::<TlsTerminator>();
     Ok(())
 }
 
@@ -101,6 +159,9 @@ mod tests {
         let tls = TlsNet {
             connector: TlsConnector::new(TlsConnectorConfig {
                 skip_cert_verify: false,
+                enable_early_data: false,
+                alpn: Vec::new(),
+                session_cache: default_session_cache(),
             })
             .unwrap(),
             sni: None,
@@ -116,4 +177,147 @@ mod tests {
             },
         );
     }
+
+    #[cfg(feature = "rustls")]
+    mod rustls {
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+
+        use crate::builtin::local::{LocalNet, LocalNetConfig};
+        use rd_interface::{Context, Net};
+        use tokio::io::{copy, split, AsyncReadExt, AsyncWriteExt};
+        use tokio_rustls::{
+            rustls::{
+                server::{ServerSessionMemoryCache, StoresServerSessions},
+                Certificate, PrivateKey, ServerConfig,
+            },
+            TlsAcceptor,
+        };
+
+        use super::*;
+
+        fn server_config() -> ServerConfig {
+            let cert = Certificate(include_bytes!("tls/testdata/cert.der").to_vec());
+            let key = PrivateKey(include_bytes!("tls/testdata/key.der").to_vec());
+            ServerConfig::builder()
+                .with_safe_defaults()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert], key)
+                .unwrap()
+        }
+
+        /// Returns the port of a TLS echo server.
+        async fn spawn_echo_server(server_config: ServerConfig) -> u16 {
+            let acceptor = TlsAcceptor::from(Arc::new(server_config));
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                loop {
+                    let (socket, _) = listener.accept().await.unwrap();
+                    let acceptor = acceptor.clone();
+                    tokio::spawn(async move {
+                        let stream = acceptor.accept(socket).await.unwrap();
+                        let (mut rx, mut tx) = split(stream);
+                        let _ = copy(&mut rx, &mut tx).await;
+                    });
+                }
+            });
+            port
+        }
+
+        fn tls_net(enable_early_data: bool, session_cache: usize) -> Net {
+            TlsNet {
+                connector: TlsConnector::new(TlsConnectorConfig {
+                    skip_cert_verify: true,
+                    enable_early_data,
+                    alpn: Vec::new(),
+                    session_cache,
+                })
+                .unwrap(),
+                sni: Some("localhost".to_string()),
+                net: LocalNet::new(LocalNetConfig::default()).into_dyn(),
+            }
+            .into_dyn()
+        }
+
+        async fn assert_echo(tls: &Net, port: u16) {
+            let mut stream = tls
+                .tcp_connect(
+                    &mut Context::new(),
+                    &Address::SocketAddr(([127, 0, 0, 1], port).into()),
+                )
+                .await
+                .unwrap();
+            stream.write_all(b"hello").await.unwrap();
+            let mut buf = [0u8; 5];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello");
+        }
+
+        #[tokio::test]
+        async fn test_early_data_rejected() {
+            // max_early_data_size is 0 by default, so early data is always rejected.
+            let port = spawn_echo_server(server_config()).await;
+            let tls = tls_net(true, default_session_cache());
+
+            // The second connection resumes the session and tries to send early data.
+            for _ in 0..2 {
+                assert_echo(&tls, port).await;
+            }
+        }
+
+        /// Counts the sessions resumed by the clients.
+        struct CountResumed {
+            inner: Arc<ServerSessionMemoryCache>,
+            resumed: Arc<AtomicUsize>,
+        }
+
+        impl CountResumed {
+            fn count(&self, value: Option<Vec<u8>>) -> Option<Vec<u8>> {
+                if value.is_some() {
+                    self.resumed.fetch_add(1, Ordering::SeqCst);
+                }
+                value
+            }
+        }
+
+        impl StoresServerSessions for CountResumed {
+            fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+                self.inner.put(key, value)
+            }
+
+            fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+                self.count(self.inner.get(key))
+            }
+
+            fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+                self.count(self.inner.take(key))
+            }
+
+            fn can_cache(&self) -> bool {
+                self.inner.can_cache()
+            }
+        }
+
+        #[tokio::test]
+        async fn test_session_resumption() {
+            for (session_cache, resumed) in [(default_session_cache(), 1), (0, 0)] {
+                let count = Arc::new(AtomicUsize::new(0));
+                let mut server_config = server_config();
+                server_config.session_storage = Arc::new(CountResumed {
+                    inner: ServerSessionMemoryCache::new(16),
+                    resumed: count.clone(),
+                });
+                let port = spawn_echo_server(server_config).await;
+                let tls = tls_net(false, session_cache);
+
+                assert_echo(&tls, port).await;
+                assert_eq!(count.load(Ordering::SeqCst), 0);
+                assert_echo(&tls, port).await;
+                assert_eq!(count.load(Ordering::SeqCst), resumed);
+            }
+        }
+    }
 }
@@ -5,16 +5,34 @@ use tokio_native_tls::native_tls;
 
 pub use tokio_native_tls::TlsStream;
 
+/// The negotiated ALPN protocol of `stream`.
+pub fn alpn_protocol<IO>(stream: &TlsStream<IO>) -> Option<String>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    stream
+        .get_ref()
+        .negotiated_alpn()
+        .ok()
+        .flatten()
+        .map(|p| String::from_utf8_lossy(&p).to_string())
+}
+
 pub struct TlsConnector {
     connector: tokio_native_tls::TlsConnector,
 }
 
 impl TlsConnector {
     pub(crate) fn new(config: TlsConnectorConfig) -> Result<TlsConnector> {
+        if config.enable_early_data {
+            tracing::warn!("TLS early data is not supported by this backend, ignored.");
+        }
         let mut builder = native_tls::TlsConnector::builder();
         if config.skip_cert_verify {
             builder.danger_accept_invalid_certs(true);
         }
+        let alpn = config.alpn.iter().map(String::as_str).collect::<Vec<_>>();
+        builder.request_alpns(&alpn);
         let connector = tokio_native_tls::TlsConnector::from(builder.build().map_err(map_other)?);
 
         Ok(TlsConnector { connector })
@@ -31,3 +49,31 @@ impl TlsConnector {
         Ok(stream)
     }
 }
+
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: tokio_native_tls::TlsAcceptor,
+}
+
+impl TlsAcceptor {
+    pub(crate) fn new(cert: &[u8], key: &[u8], alpn: &[String]) -> Result<TlsAcceptor> {
+        if !alpn.is_empty() {
+            return Err(rd_interface::Error::other(
+                "Server side ALPN is not supported by the native-tls backend",
+            ));
+        }
+        let identity = native_tls::Identity::from_pkcs8(cert, key).map_err(map_other)?;
+        let acceptor = native_tls::TlsAcceptor::new(identity).map_err(map_other)?;
+
+        Ok(TlsAcceptor {
+            acceptor: acceptor.into(),
+        })
+    }
+    pub async fn accept<IO>(&self, stream: IO) -> Result<TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let stream = self.acceptor.accept(stream).await.map_err(map_other)?;
+        Ok(stream)
+    }
+}
@@ -1,26 +1,108 @@
-use std::pin::Pin;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use super::TlsConnectorConfig;
-use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use lru_time_cache::LruCache;
+use openssl::{
+    ex_data::Index,
+    pkey::PKey,
+    ssl::{
+        select_next_proto, AlpnError, Ssl, SslAcceptor, SslConnector, SslMethod, SslSession,
+        SslSessionCacheMode, SslVerifyMode,
+    },
+    x509::X509,
+};
 use openssl_crate as openssl;
+use parking_lot::Mutex;
 use rd_interface::{error::map_other, AsyncRead, AsyncWrite, Result};
 
 pub use tokio_openssl::SslStream as TlsStream;
 
+/// Encode protocols as length-prefixed strings.
+fn alpn_wire_format(alpn: &[String]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for p in alpn {
+        buf.push(p.len() as u8);
+        buf.extend_from_slice(p.as_bytes());
+    }
+    buf
+}
+
+/// The negotiated ALPN protocol of `stream`.
+pub fn alpn_protocol<IO>(stream: &TlsStream<IO>) -> Option<String> {
+    stream
+        .ssl()
+        .selected_alpn_protocol()
+        .map(|p| String::from_utf8_lossy(p).to_string())
+}
+
+/// The sessions of the servers by server name, taken by the next connection to
+/// the server.
+struct SessionCache {
+    sessions: Mutex<LruCache<String, SslSession>>,
+    /// The server name of a connection.
+    domain: Index<Ssl, String>,
+}
+
+impl SessionCache {
+    fn new(capacity: usize) -> Result<SessionCache> {
+        Ok(SessionCache {
+            sessions: Mutex::new(LruCache::with_capacity(capacity)),
+            domain: Ssl::new_ex_index().map_err(map_other)?,
+        })
+    }
+    fn put(&self, domain: &str, session: SslSession) {
+        self.sessions.lock().insert(domain.to_string(), session);
+    }
+    /// The session of `domain` if it's not expired.
+    fn take(&self, domain: &str) -> Option<SslSession> {
+        let session = self.sessions.lock().remove(domain)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+        (now < session.time() as i64 + session.timeout() as i64).then_some(session)
+    }
+}
+
 pub struct TlsConnector {
     connector: SslConnector,
+    session_cache: Option<Arc<SessionCache>>,
 }
 
 impl TlsConnector {
     pub(crate) fn new(config: TlsConnectorConfig) -> Result<TlsConnector> {
+        if config.enable_early_data {
+            tracing::warn!("TLS early data is not supported by this backend, ignored.");
+        }
         let mut builder = SslConnector::builder(SslMethod::tls()).map_err(map_other)?;
 
         if config.skip_cert_verify {
             builder.set_verify(SslVerifyMode::NONE);
         }
+        if !config.alpn.is_empty() {
+            builder
+                .set_alpn_protos(&alpn_wire_format(&config.alpn))
+                .map_err(map_other)?;
+        }
+
+        let session_cache = if config.session_cache > 0 {
+            let cache = Arc::new(SessionCache::new(config.session_cache)?);
+            builder.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+            let c = cache.clone();
+            builder.set_new_session_callback(move |ssl, session| {
+                if let Some(domain) = ssl.ex_data(c.domain) {
+                    c.put(domain, session);
+                }
+            });
+            Some(cache)
+        } else {
+            None
+        };
 
         Ok(TlsConnector {
             connector: builder.build(),
+            session_cache,
         })
     }
 
@@ -28,12 +110,19 @@ impl TlsConnector {
     where
         IO: AsyncRead + AsyncWrite + Unpin,
     {
-        let ssl = self
+        let mut ssl = self
             .connector
             .configure()
             .map_err(map_other)?
             .into_ssl(domain)
             .map_err(map_other)?;
+        if let Some(cache) = &self.session_cache {
+            if let Some(session) = cache.take(domain) {
+                // Safety: the session is created by a connection of the same context.
+                unsafe { ssl.set_session(&session) }.map_err(map_other)?;
+            }
+            ssl.set_ex_data(cache.domain, domain.to_string());
+        }
 
         let mut stream = TlsStream::new(ssl, stream).map_err(map_other)?;
 
@@ -42,3 +131,50 @@ impl TlsConnector {
         Ok(stream)
     }
 }
+
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: SslAcceptor,
+}
+
+impl TlsAcceptor {
+    pub(crate) fn new(cert: &[u8], key: &[u8], alpn: &[String]) -> Result<TlsAcceptor> {
+        let mut certs = X509::stack_from_pem(cert).map_err(map_other)?.into_iter();
+        let leaf = certs
+            .next()
+            .ok_or_else(|| rd_interface::Error::other("no certificate found"))?;
+        let key = PKey::private_key_from_pem(key).map_err(map_other)?;
+
+        let mut builder =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).map_err(map_other)?;
+        builder.set_certificate(&leaf).map_err(map_other)?;
+        for cert in certs {
+            builder.add_extra_chain_cert(cert).map_err(map_other)?;
+        }
+        builder.set_private_key(&key).map_err(map_other)?;
+        builder.check_private_key().map_err(map_other)?;
+        if !alpn.is_empty() {
+            let protos = alpn_wire_format(alpn);
+            builder.set_alpn_select_callback(move |_, client| {
+                select_next_proto(&protos, client).ok_or(AlpnError::NOACK)
+            });
+        }
+
+        Ok(TlsAcceptor {
+            acceptor: builder.build(),
+        })
+    }
+
+    pub async fn accept<IO>(&self, stream: IO) -> Result<TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let ssl = Ssl::new(self.acceptor.context()).map_err(map_other)?;
+
+        let mut stream = TlsStream::new(ssl, stream).map_err(map_other)?;
+
+        Pin::new(&mut stream).accept().await.map_err(map_other)?;
+
+        Ok(stream)
+    }
+}
@@ -11,8 +11,9 @@ use rd_interface::{error::map_other, AsyncRead, AsyncWrite, Result};
 use std::sync::Arc;
 use tokio::io::ReadBuf;
 use tokio_rustls::rustls::{
-    client::{ServerCertVerified, ServerCertVerifier},
-    Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName,
+    client::{Resumption, ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerConfig,
+    ServerName,
 };
 
 pub type TlsStream<T> = PushingStream<tokio_rustls::TlsStream<T>>;
@@ -59,7 +60,21 @@ impl TlsConnector {
                 .set_certificate_verifier(Arc::new(AllowAnyCert));
         }
 
-        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        // Session tickets are cached in memory by server name, which is required by early data.
+        // The expired ones are not resumed.
+        client_config.resumption = if config.session_cache > 0 {
+            Resumption::in_memory_sessions(config.session_cache)
+        } else {
+            if config.enable_early_data {
+                tracing::warn!("TLS early data needs the session cache, ignored.");
+            }
+            Resumption::disabled()
+        };
+        client_config.enable_early_data = config.enable_early_data;
+        client_config.alpn_protocols = config.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config))
+            .early_data(config.enable_early_data);
 
         Ok(TlsConnector { connector })
     }
@@ -75,6 +90,51 @@ impl TlsConnector {
     }
 }
 
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: tokio_rustls::TlsAcceptor,
+}
+
+impl TlsAcceptor {
+    pub(crate) fn new(cert: &[u8], key: &[u8], alpn: &[String]) -> Result<TlsAcceptor> {
+        let certs = rustls_pemfile::certs(&mut &cert[..])?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+        let key = rustls_pemfile::pkcs8_private_keys(&mut &key[..])?
+            .into_iter()
+            .next()
+            .map(PrivateKey)
+            .ok_or_else(|| rd_interface::Error::other("no PKCS#8 private key found"))?;
+
+        let mut server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(map_other)?;
+        server_config.alpn_protocols = alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+
+        Ok(TlsAcceptor {
+            acceptor: tokio_rustls::TlsAcceptor::from(Arc::new(server_config)),
+        })
+    }
+    pub async fn accept<IO>(&self, stream: IO) -> Result<TlsStream<IO>>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin,
+    {
+        let stream = self.acceptor.accept(stream).await?;
+        Ok(PushingStream::new(stream.into()))
+    }
+}
+
+/// The negotiated ALPN protocol of `stream`.
+pub fn alpn_protocol<IO>(stream: &TlsStream<IO>) -> Option<String> {
+    let (_, state) = stream.inner.get_ref();
+    state
+        .alpn_protocol()
+        .map(|p| String::from_utf8_lossy(p).to_string())
+}
+
 enum State {
     Write,
     Flush(usize),
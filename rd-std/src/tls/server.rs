@@ -0,0 +1,438 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+};
+
+use rd_interface::{
+    async_trait, config::NetRef, context::common_field::TlsAlpn, prelude::*, registry::Builder,
+    Address, Arc, Context, IServer, IntoDyn, Net, Result, Server, TcpStream,
+};
+use tracing::instrument;
+
+use super::{alpn_protocol, TlsAcceptor, TlsServerConfig};
+use crate::{
+    sniffer::get_sni,
+    util::{accept_with_backoff, PeekableTcpStream, ACCEPT_BACKOFF},
+    ContextExt,
+};
+
+/// Max length of a TLS record
+const MAX_RECORD_LEN: usize = 16 * 1024 + 256;
+
+/// The certificate and the destination of a server name.
+#[rd_config]
+#[derive(Debug)]
+pub struct TlsSniConfig {
+    /// Certificate of this server name. The default one is used if not set.
+    #[serde(default)]
+    tls: Option<TlsServerConfig>,
+    /// Forward to this address instead of the default `target`.
+    #[serde(default)]
+    target: Option<Address>,
+    /// Forward through this net instead of the default `net`.
+    #[serde(default)]
+    net: Option<NetRef>,
+}
+
+/// The destination of an ALPN protocol.
+#[rd_config]
+#[derive(Debug)]
+pub struct TlsAlpnConfig {
+    /// The ALPN protocol, e.g. `h2`.
+    protocol: String,
+    /// Forward to this address.
+    target: Address,
+    /// Forward through this net instead of the one of the server name.
+    #[serde(default)]
+    net: Option<NetRef>,
+}
+
+/// A server that terminates TLS and forwards the decrypted streams to `target`.
+/// The certificate, target and net can be chosen by the SNI of the client,
+/// so several backends can be served on the same port. After the handshake,
+/// the negotiated ALPN protocol can choose the target too, e.g. a website for
+/// `h2` and `http/1.1`, and a proxy for a custom protocol.
+#[rd_config]
+#[derive(Debug)]
+pub struct TlsTerminatorConfig {
+    bind: Address,
+    target: Address,
+    /// The default certificate.
+    #[serde(flatten)]
+    tls: TlsServerConfig,
+    /// Routes by the server name, e.g. `example.com` or `*.example.com`.
+    #[serde(default)]
+    sni: BTreeMap<String, TlsSniConfig>,
+    /// Routes by the negotiated ALPN protocol. These protocols are offered
+    /// after the ones in `alpn`, in this order of preference. Connections
+    /// without a matched protocol go to the target of the server name.
+    /// Rejected by the native-tls backend.
+    #[serde(default)]
+    alpn_routes: Vec<TlsAlpnConfig>,
+
+    #[serde(default)]
+    net: NetRef,
+    #[serde(default)]
+    listen: NetRef,
+}
+
+struct Route {
+    acceptor: TlsAcceptor,
+    target: Address,
+    net: Net,
+}
+
+struct AlpnRoute {
+    target: Address,
+    net: Option<Net>,
+}
+
+struct Routes {
+    default: Route,
+    sni: HashMap<String, Route>,
+    alpn: HashMap<String, AlpnRoute>,
+}
+
+impl Routes {
+    fn get(&self, server_name: Option<&str>) -> &Route {
+        let server_name = match server_name {
+            Some(s) => s.to_ascii_lowercase(),
+            None => return &self.default,
+        };
+        let wildcard = server_name
+            .split_once('.')
+            .map(|(_, parent)| format!("*.{}", parent));
+
+        self.sni
+            .get(&server_name)
+            .or_else(|| wildcard.and_then(|w| self.sni.get(&w)))
+            .unwrap_or(&self.default)
+    }
+}
+
+pub struct TlsTerminator {
+    bind: Address,
+    listen: Net,
+    routes: Arc<Routes>,
+}
+
+/// Adds the protocols of the ALPN routes to the ones offered by `tls`.
+fn with_alpn_routes(mut tls: TlsServerConfig, alpn_routes: &[String]) -> TlsServerConfig {
+    for protocol in alpn_routes {
+        if !tls.alpn.contains(protocol) {
+            tls.alpn.push(protocol.clone());
+        }
+    }
+    tls
+}
+
+impl TlsTerminator {
+    pub fn new(config: TlsTerminatorConfig) -> Result<Self> {
+        let alpn_protocols = config
+            .alpn_routes
+            .iter()
+            .map(|route| route.protocol.clone())
+            .collect::<Vec<_>>();
+        let default = Route {
+            acceptor: with_alpn_routes(config.tls, &alpn_protocols).build_acceptor()?,
+            target: config.target,
+            net: config.net.value_cloned(),
+        };
+        let sni = config
+            .sni
+            .into_iter()
+            .map(|(server_name, route)| {
+                let route = Route {
+                    acceptor: match route.tls {
+                        Some(tls) => with_alpn_routes(tls, &alpn_protocols).build_acceptor()?,
+                        None => default.acceptor.clone(),
+                    },
+                    target: route.target.unwrap_or_else(|| default.target.clone()),
+                    net: match route.net {
+                        Some(net) => net.value_cloned(),
+                        None => default.net.clone(),
+                    },
+                };
+                Ok((server_name.to_ascii_lowercase(), route))
+            })
+            .collect::<Result<_>>()?;
+        let mut alpn = HashMap::new();
+        for route in config.alpn_routes {
+            let alpn_route = AlpnRoute {
+                target: route.target,
+                net: route.net.map(|net| net.value_cloned()),
+            };
+            if alpn.insert(route.protocol.clone(), alpn_route).is_some() {
+                return Err(rd_interface::Error::other(format!(
+                    "ALPN protocol {} is routed twice",
+                    route.protocol
+                )));
+            }
+        }
+
+        Ok(TlsTerminator {
+            bind: config.bind,
+            listen: config.listen.value_cloned(),
+            routes: Arc::new(Routes { default, sni, alpn }),
+        })
+    }
+}
+
+#[instrument(err, skip(routes, socket))]
+async fn serve_connection(routes: Arc<Routes>, socket: TcpStream, addr: SocketAddr) -> Result<()> {
+    let mut socket = PeekableTcpStream::new(socket);
+    let server_name = peek_sni(&mut socket).await?;
+    let route = routes.get(server_name.as_deref());
+
+    let socket = route.acceptor.accept(socket.into_dyn()).await?;
+    let ctx = &mut Context::from_socketaddr(addr);
+
+    let alpn = alpn_protocol(&socket);
+    let (target, net) = match alpn.as_ref().and_then(|p| routes.alpn.get(p)) {
+        Some(r) => (&r.target, r.net.as_ref().unwrap_or(&route.net)),
+        None => (&route.target, &route.net),
+    };
+    if let Some(alpn) = alpn {
+        ctx.insert_common(TlsAlpn(alpn))?;
+    }
+
+    let target = net.tcp_connect(ctx, target).await?;
+    ctx.connect_tcp(socket, target).await?;
+
+    Ok(())
+}
+
+/// Peek the SNI in the ClientHello, which is left in the stream for the handshake.
+async fn peek_sni(socket: &mut PeekableTcpStream) -> Result<Option<String>> {
+    let mut header = [0u8; 5];
+    socket.peek_exact(&mut header).await?;
+    // not a handshake record
+    if header[0] != 0x16 {
+        return Ok(None);
+    }
+    let len = u16::from_be_bytes([header[3], header[4]]) as usize;
+    if len > MAX_RECORD_LEN {
+        return Ok(None);
+    }
+
+    let mut record = vec![0u8; header.len() + len];
+    socket.peek_exact(&mut record).await?;
+    Ok(get_sni(&record))
+}
+
+#[async_trait]
+impl IServer for TlsTerminator {
+    async fn start(&self) -> Result<()> {
+        let listener = self
+            .listen
+            .tcp_bind(&mut Context::new(), &self.bind)
+            .await?;
+
+        loop {
+            let (socket, addr) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
+            let routes = self.routes.clone();
+            let _ = tokio::spawn(async move {
+                if let Err(e) = serve_connection(routes, socket, addr).await {
+                    tracing::error!("Error when serve_connection: {:?}", e);
+                }
+            });
+        }
+    }
+}
+
+impl Builder<Server> for TlsTerminator {
+    const NAME: &'static str = "tls";
+    const TITLE: &'static str = "TLS Terminator";
+    type Config = TlsTerminatorConfig;
+    type Item = Self;
+
+    fn build(config: Self::Config) -> Result<Self> {
+        TlsTerminator::new(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rd_interface::IntoAddress;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        time::sleep,
+    };
+
+    use super::*;
+    use crate::{
+        tests::TestNet,
+        tls::{default_session_cache, TlsNet, TlsNetConfig},
+    };
+
+    /// A server sends its name to the client.
+    async fn spawn_named_server(net: &Net, addr: &str, name: &'static str) {
+        let listener = net
+            .tcp_bind(&mut Context::new(), &addr.into_address().unwrap())
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut tcp, _) = listener.accept().await.unwrap();
+                tcp.write_all(name.as_bytes()).await.unwrap();
+            }
+        });
+    }
+
+    async fn assert_routed_to(net: &Net, server: &str, sni: &str, alpn: &[&str], name: &str) {
+        let client = TlsNet::build(TlsNetConfig {
+            skip_cert_verify: true,
+            sni: Some(sni.to_string()),
+            enable_early_data: false,
+            alpn: alpn.iter().map(|p| p.to_string()).collect(),
+            session_cache: default_session_cache(),
+            net: NetRef::new_with_value("test".into(), net.clone()),
+        })
+        .unwrap()
+        .into_dyn();
+        let mut tcp = client
+            .tcp_connect(&mut Context::new(), &server.into_address().unwrap())
+            .await
+            .unwrap();
+
+        let mut buf = vec![0u8; name.len()];
+        tcp.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, name.as_bytes(), "sni: {}, alpn: {:?}", sni, alpn);
+    }
+
+    async fn assert_routed(net: &Net, sni: &str, name: &str) {
+        assert_routed_to(net, "127.0.0.1:16443", sni, &[], name).await
+    }
+
+    fn test_tls() -> TlsServerConfig {
+        let testdata = concat!(env!("CARGO_MANIFEST_DIR"), "/src/tls/testdata");
+        TlsServerConfig {
+            cert: format!("{}/cert.pem", testdata),
+            key: format!("{}/key.pem", testdata),
+            alpn: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sni_routes() {
+        let net = TestNet::new().into_dyn();
+        spawn_named_server(&net, "127.0.0.1:26001", "default").await;
+        spawn_named_server(&net, "127.0.0.1:26002", "a").await;
+        spawn_named_server(&net, "127.0.0.1:26003", "b").await;
+
+        let server = TlsTerminator::build(TlsTerminatorConfig {
+            bind: "127.0.0.1:16443".into_address().unwrap(),
+            target: "127.0.0.1:26001".into_address().unwrap(),
+            tls: test_tls(),
+            sni: BTreeMap::from([
+                (
+                    "a.example.com".to_string(),
+                    TlsSniConfig {
+                        tls: Some(test_tls()),
+                        target: Some("127.0.0.1:26002".into_address().unwrap()),
+                        net: None,
+                    },
+                ),
+                (
+                    "*.b.example.com".to_string(),
+                    TlsSniConfig {
+                        tls: None,
+                        target: Some("127.0.0.1:26003".into_address().unwrap()),
+                        net: Some(NetRef::new_with_value("test".into(), net.clone())),
+                    },
+                ),
+            ]),
+            alpn_routes: Vec::new(),
+            net: NetRef::new_with_value("test".into(), net.clone()),
+            listen: NetRef::new_with_value("test".into(), net.clone()),
+        })
+        .unwrap();
+        tokio::spawn(async move { server.start().await });
+        sleep(Duration::from_millis(10)).await;
+
+        assert_routed(&net, "a.example.com", "a").await;
+        assert_routed(&net, "A.Example.com", "a").await;
+        assert_routed(&net, "www.b.example.com", "b").await;
+        assert_routed(&net, "b.example.com", "default").await;
+        assert_routed(&net, "localhost", "default").await;
+    }
+
+    #[cfg(feature = "native-tls")]
+    #[test]
+    fn test_alpn_routes_unsupported() {
+        let net = TestNet::new().into_dyn();
+        let build = |tls: TlsServerConfig, alpn_routes: Vec<TlsAlpnConfig>| {
+            TlsTerminator::build(TlsTerminatorConfig {
+                bind: "127.0.0.1:16445".into_address().unwrap(),
+                target: "127.0.0.1:26007".into_address().unwrap(),
+                tls,
+                sni: BTreeMap::new(),
+                alpn_routes,
+                net: NetRef::new_with_value("test".into(), net.clone()),
+                listen: NetRef::new_with_value("test".into(), net.clone()),
+            })
+        };
+        assert!(build(test_tls(), Vec::new()).is_ok());
+
+        let route = TlsAlpnConfig {
+            protocol: "h2".to_string(),
+            target: "127.0.0.1:26008".into_address().unwrap(),
+            net: None,
+        };
+        assert!(build(test_tls(), vec![route]).is_err());
+        let tls = TlsServerConfig {
+            alpn: vec!["h2".to_string()],
+            ..test_tls()
+        };
+        assert!(build(tls, Vec::new()).is_err());
+    }
+
+    #[cfg(not(feature = "native-tls"))]
+    #[tokio::test]
+    async fn test_alpn_routes() {
+        let net = TestNet::new().into_dyn();
+        spawn_named_server(&net, "127.0.0.1:26004", "website").await;
+        spawn_named_server(&net, "127.0.0.1:26005", "http").await;
+        spawn_named_server(&net, "127.0.0.1:26006", "tunnel").await;
+
+        let route = |protocol: &str, target: &str| TlsAlpnConfig {
+            protocol: protocol.to_string(),
+            target: target.into_address().unwrap(),
+            net: None,
+        };
+        let config = |alpn_routes| TlsTerminatorConfig {
+            bind: "127.0.0.1:16444".into_address().unwrap(),
+            target: "127.0.0.1:26004".into_address().unwrap(),
+            tls: test_tls(),
+            sni: BTreeMap::new(),
+            alpn_routes,
+            net: NetRef::new_with_value("test".into(), net.clone()),
+            listen: NetRef::new_with_value("test".into(), net.clone()),
+        };
+        // a protocol can't be routed twice
+        assert!(TlsTerminator::build(config(vec![
+            route("h2", "127.0.0.1:26005"),
+            route("h2", "127.0.0.1:26006"),
+        ]))
+        .is_err());
+
+        let server = TlsTerminator::build(config(vec![
+            route("rd-tunnel", "127.0.0.1:26006"),
+            route("h2", "127.0.0.1:26005"),
+            route("http/1.1", "127.0.0.1:26005"),
+        ]))
+        .unwrap();
+        tokio::spawn(async move { server.start().await });
+        sleep(Duration::from_millis(10)).await;
+
+        let server = "127.0.0.1:16444";
+        assert_routed_to(&net, server, "localhost", &["h2"], "http").await;
+        assert_routed_to(&net, server, "localhost", &["http/1.1"], "http").await;
+        assert_routed_to(&net, server, "localhost", &["rd-tunnel"], "tunnel").await;
+        assert_routed_to(&net, server, "localhost", &[], "website").await;
+        // the server prefers the protocols in the order of the routes
+        assert_routed_to(&net, server, "localhost", &["h2", "rd-tunnel"], "tunnel").await;
+    }
+}
@@ -1,4 +1,6 @@
 #[cfg(target_os = "linux")]
+mod nftables;
+#[cfg(target_os = "linux")]
 mod origin_addr;
 #[cfg(target_os = "linux")]
 mod redir;
@@ -0,0 +1,200 @@
+//! Generates and installs the nftables rules sending the traffic to a redir or tproxy
+//! server, as an alternative to hand-written iptables rules.
+//!
+//! The `redirect` statement is a NAT like iptables `REDIRECT`, so `SO_ORIGINAL_DST`
+//! still returns the original destination. `tproxy` keeps the destination as the local
+//! address of the accepted socket.
+
+use std::{io, process::Stdio};
+
+use rd_derive::rd_config;
+use rd_interface::{schemars, Error, Result};
+use tokio::{io::AsyncWriteExt, process::Command, runtime::Handle};
+
+/// The destinations never sent to the server, same as `util::is_reserved`.
+const RESERVED_V4: &str = "0.0.0.0/8, 10.0.0.0/8, 127.0.0.0/8, 169.254.0.0/16, \
+    172.16.0.0/12, 192.168.0.0/16, 224.0.0.0/4, 240.0.0.0/4";
+const RESERVED_V6: &str = "::1, fc00::/7, fe80::/10";
+
+fn default_table() -> String {
+    "rabbit_digger".to_string()
+}
+
+fn default_route_mark() -> u32 {
+    1
+}
+
+#[rd_config]
+#[derive(Debug, Clone)]
+pub struct NftablesConfig {
+    /// name of the `inet` table holding the rules, the table is replaced at start and
+    /// deleted at stop.
+    #[serde(default = "default_table")]
+    table: String,
+    /// mark set on the packets sent to a tproxy server. Packets with the mark must be
+    /// routed to the local host, e.g. `ip rule add fwmark 1 lookup 100` and
+    /// `ip route add local 0.0.0.0/0 dev lo table 100`.
+    #[serde(default = "default_route_mark")]
+    route_mark: u32,
+}
+
+impl NftablesConfig {
+    /// The packets of a tproxy server are sent with `mark`, they would be routed back
+    /// to the server if it's the `route_mark`.
+    pub fn check_tproxy(&self, mark: Option<u32>) -> Result<()> {
+        if mark == Some(self.route_mark) {
+            return Err(Error::other(format!(
+                "route_mark of nftables should differ from mark {}",
+                self.route_mark
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn ruleset(table: &str, chain: &str, statement: &str) -> String {
+    format!(
+        "table inet {table} {{
+    chain prerouting {{
+        {chain}; policy accept;
+        fib daddr type {{ local, broadcast, multicast }} return
+        ip daddr {{ {RESERVED_V4} }} return
+        ip6 daddr {{ {RESERVED_V6} }} return
+        {statement}
+    }}
+}}
+"
+    )
+}
+
+/// Rules redirecting the TCP traffic to a redir server listening on `port`.
+pub fn redirect_ruleset(table: &str, port: u16) -> String {
+    ruleset(
+        table,
+        "type nat hook prerouting priority dstnat",
+        &format!("meta l4proto tcp redirect to :{port}"),
+    )
+}
+
+/// Rules sending the TCP and UDP traffic to a tproxy server listening on `port`.
+pub fn tproxy_ruleset(table: &str, port: u16, route_mark: u32) -> String {
+    ruleset(
+        table,
+        "type filter hook prerouting priority mangle",
+        &format!("meta l4proto {{ tcp, udp }} tproxy to :{port} meta mark set {route_mark} accept"),
+    )
+}
+
+async fn nft(script: &str) -> io::Result<()> {
+    let mut child = Command::new("nft")
+        .args(["-f", "-"])
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // dropping stdin closes it, so nft reads the whole script
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(script.as_bytes())
+        .await?;
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The installed rules, deleted on drop.
+pub struct Nftables {
+    table: String,
+}
+
+impl Nftables {
+    async fn install(table: &str, ruleset: &str) -> Result<Nftables> {
+        // declaring the table first makes the delete succeed if it doesn't exist
+        nft(&format!(
+            "table inet {table}\ndelete table inet {table}\n{ruleset}"
+        ))
+        .await
+        .map_err(|e| Error::other(format!("Failed to install nftables rules: {}", e)))?;
+
+        Ok(Nftables {
+            table: table.to_string(),
+        })
+    }
+
+    pub async fn install_redirect(config: &NftablesConfig, port: u16) -> Result<Nftables> {
+        Self::install(&config.table, &redirect_ruleset(&config.table, port)).await
+    }
+
+    pub async fn install_tproxy(config: &NftablesConfig, port: u16) -> Result<Nftables> {
+        Self::install(
+            &config.table,
+            &tproxy_ruleset(&config.table, port, config.route_mark),
+        )
+        .await
+    }
+}
+
+impl Drop for Nftables {
+    fn drop(&mut self) {
+        let table = std::mem::take(&mut self.table);
+        let handle = match Handle::try_current() {
+            Ok(handle) => handle,
+            Err(_) => {
+                tracing::warn!("Failed to delete nftables table {}: no runtime", table);
+                return;
+            }
+        };
+        handle.spawn(async move {
+            if let Err(e) = nft(&format!("delete table inet {}", table)).await {
+                tracing::warn!("Failed to delete nftables table {}: {:?}", table, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_ruleset() {
+        assert_eq!(
+            redirect_ruleset("rd", 12345),
+            "table inet rd {
+    chain prerouting {
+        type nat hook prerouting priority dstnat; policy accept;
+        fib daddr type { local, broadcast, multicast } return
+        ip daddr { 0.0.0.0/8, 10.0.0.0/8, 127.0.0.0/8, 169.254.0.0/16, 172.16.0.0/12, 192.168.0.0/16, 224.0.0.0/4, 240.0.0.0/4 } return
+        ip6 daddr { ::1, fc00::/7, fe80::/10 } return
+        meta l4proto tcp redirect to :12345
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn test_check_tproxy() {
+        let config: NftablesConfig = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert!(config.check_tproxy(None).is_ok());
+        assert!(config.check_tproxy(Some(2)).is_ok());
+        assert!(config.check_tproxy(Some(1)).is_err());
+    }
+
+    #[test]
+    fn test_tproxy_ruleset() {
+        let ruleset = tproxy_ruleset("rd", 12345, 1);
+        assert!(ruleset.starts_with("table inet rd {\n"));
+        assert!(ruleset.contains("type filter hook prerouting priority mangle; policy accept;"));
+        assert!(
+            ruleset.contains("meta l4proto { tcp, udp } tproxy to :12345 meta mark set 1 accept\n")
+        );
+    }
+}
@@ -1,7 +1,14 @@
 use std::net::SocketAddr;
 
-use super::origin_addr::OriginAddrExt;
-use crate::{builtin::local::CompatTcp, ContextExt};
+use super::{
+    nftables::{Nftables, NftablesConfig},
+    origin_addr::OriginAddrExt,
+};
+use crate::{
+    builtin::local::CompatTcp,
+    util::{accept_with_backoff, ACCEPT_BACKOFF},
+    ContextExt,
+};
 use rd_derive::rd_config;
 use rd_interface::{
     async_trait, config::NetRef, registry::Builder, schemars, Address, Context, IServer,
@@ -16,29 +23,48 @@ pub struct RedirServerConfig {
     bind: Address,
     #[serde(default)]
     net: NetRef,
+    /// install the nftables rules redirecting the TCP traffic to this server
+    #[serde(default)]
+    nftables: Option<NftablesConfig>,
 }
 
 pub struct RedirServer {
     bind: Address,
     net: Net,
+    nftables: Option<NftablesConfig>,
 }
 
 #[async_trait]
 impl IServer for RedirServer {
     async fn start(&self) -> Result<()> {
         let listener = TcpListener::bind(&self.bind.to_string()).await?;
+        let _nftables = match &self.nftables {
+            Some(config) => {
+                Some(Nftables::install_redirect(config, listener.local_addr()?.port()).await?)
+            }
+            None => None,
+        };
         self.serve_listener(listener).await
     }
 }
 
 impl RedirServer {
     pub fn new(bind: Address, net: Net) -> Self {
-        RedirServer { bind, net }
+        RedirServer {
+            bind,
+            net,
+            nftables: None,
+        }
+    }
+
+    pub fn with_nftables(mut self, nftables: Option<NftablesConfig>) -> Self {
+        self.nftables = nftables;
+        self
     }
 
     pub async fn serve_listener(&self, listener: TcpListener) -> Result<()> {
         loop {
-            let (socket, addr) = listener.accept().await?;
+            let (socket, addr) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
             let net = self.net.clone();
             let _ = tokio::spawn(async move {
                 if let Err(e) = Self::serve_connection(net, socket, addr).await {
@@ -64,10 +90,17 @@ impl RedirServer {
 
 impl Builder<Server> for RedirServer {
     const NAME: &'static str = "redir";
+    const TITLE: &'static str = "Transparent Proxy (redirect)";
     type Config = RedirServerConfig;
     type Item = Self;
 
-    fn build(Self::Config { bind, net }: Self::Config) -> Result<Self> {
-        Ok(RedirServer::new(bind, net.value_cloned()))
+    fn build(
+        Self::Config {
+            bind,
+            net,
+            nftables,
+        }: Self::Config,
+    ) -> Result<Self> {
+        Ok(RedirServer::new(bind, net.value_cloned()).with_nftables(nftables))
     }
 }
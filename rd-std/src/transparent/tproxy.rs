@@ -7,12 +7,16 @@ use std::{
     time::Duration,
 };
 
-use super::socket::{create_tcp_listener, TransparentUdp};
+use super::{
+    nftables::{Nftables, NftablesConfig},
+    socket::{create_tcp_listener, TransparentUdp},
+};
 use crate::{
     builtin::local::CompatTcp,
     util::{
+        accept_with_backoff,
         forward_udp::{forward_udp, RawUdpSource, UdpEndpoint},
-        is_reserved, LruCache,
+        is_reserved, LruCache, ACCEPT_BACKOFF,
     },
     ContextExt,
 };
@@ -35,12 +39,16 @@ pub struct TProxyServerConfig {
     mark: Option<u32>,
     #[serde(default)]
     net: NetRef,
+    /// install the nftables rules sending the TCP and UDP traffic to this server
+    #[serde(default)]
+    nftables: Option<NftablesConfig>,
 }
 
 pub struct TProxyServer {
     bind: Address,
     mark: Option<u32>,
     net: Net,
+    nftables: Option<NftablesConfig>,
 }
 
 #[async_trait]
@@ -48,6 +56,12 @@ impl IServer for TProxyServer {
     async fn start(&self) -> Result<()> {
         let tcp_listener = create_tcp_listener(self.bind.to_socket_addr()?).await?;
         let udp_listener = TransparentUdp::listen(self.bind.to_socket_addr()?)?;
+        let _nftables = match &self.nftables {
+            Some(config) => {
+                Some(Nftables::install_tproxy(config, tcp_listener.local_addr()?.port()).await?)
+            }
+            None => None,
+        };
 
         select! {
             r = self.serve_listener(tcp_listener) => r,
@@ -57,11 +71,19 @@ impl IServer for TProxyServer {
 }
 
 impl TProxyServer {
-    pub fn new(TProxyServerConfig { bind, mark, net }: TProxyServerConfig) -> Self {
+    pub fn new(
+        TProxyServerConfig {
+            bind,
+            mark,
+            net,
+            nftables,
+        }: TProxyServerConfig,
+    ) -> Self {
         TProxyServer {
             bind,
             mark,
             net: net.value_cloned(),
+            nftables,
         }
     }
 
@@ -77,7 +99,7 @@ impl TProxyServer {
 
     async fn serve_listener(&self, listener: TcpListener) -> Result<()> {
         loop {
-            let (socket, addr) = listener.accept().await?;
+            let (socket, addr) = accept_with_backoff(ACCEPT_BACKOFF, || listener.accept()).await?;
 
             let net = self.net.clone();
             let _ = tokio::spawn(async move {
@@ -104,10 +126,14 @@ impl TProxyServer {
 
 impl Builder<Server> for TProxyServer {
     const NAME: &'static str = "tproxy";
+    const TITLE: &'static str = "Transparent Proxy (TPROXY)";
     type Config = TProxyServerConfig;
     type Item = Self;
 
     fn build(config: Self::Config) -> Result<Self> {
+        if let Some(nftables) = &config.nftables {
+            nftables.check_tproxy(config.mark)?;
+        }
         Ok(TProxyServer::new(config))
     }
 }
@@ -1,22 +1,39 @@
+pub use accept::{accept_with_backoff, is_transient_accept_error, ACCEPT_BACKOFF};
+pub use connection_limit::{ConnectionLimit, ConnectionLimitConfig, ConnectionPermit, LimitMode};
 pub use drop_abort::DropAbort;
+pub use dual_bind::bind_tcp_udp;
 pub use forward_udp::forward_udp;
 pub use lru_cache::LruCache;
-pub use net::{CombineNet, NotImplementedNet};
-pub use peekable_tcpstream::PeekableTcpStream;
+pub use net::{CombineNet, NotImplementedNet, ResolveNet};
+pub use peekable_tcpstream::{cancel_on_close, PeekableTcpStream};
 pub use poll_future::PollFuture;
 pub use udp_connector::UdpConnector;
 
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
 
+use crate::rule::config::IpCidr;
+
+mod accept;
 pub mod async_fn;
+mod connection_limit;
 mod drop_abort;
+mod dual_bind;
 pub mod forward_udp;
 mod lru_cache;
 mod net;
 mod peekable_tcpstream;
 mod poll_future;
+pub mod proxy_protocol;
 mod udp_connector;
 
+/// Default `max_handshake_bytes` of the proxy servers.
+pub fn default_max_handshake_bytes() -> usize {
+    16 * 1024
+}
+
 /// Helper function for converting IPv4 mapped IPv6 address
 ///
 /// This is the same as `Ipv6Addr::to_ipv4_mapped`, but it is still unstable in the current libstd
@@ -37,6 +54,36 @@ pub fn resolve_mapped_socket_addr(addr: SocketAddr) -> SocketAddr {
     addr
 }
 
+/// Drops the addresses in `block` from the addresses `host` is resolved to.
+/// IPv4-mapped IPv6 addresses are matched as IPv4. Fails if all of them are dropped.
+pub fn drop_blocked(
+    host: &str,
+    addrs: Vec<SocketAddr>,
+    block: &[IpCidr],
+) -> io::Result<Vec<SocketAddr>> {
+    if block.is_empty() || addrs.is_empty() {
+        return Ok(addrs);
+    }
+    let allowed = addrs
+        .iter()
+        .copied()
+        .filter(|addr| {
+            let ip = resolve_mapped_socket_addr(*addr).ip().into();
+            !block.iter().any(|b| b.0.contains_addr(&ip))
+        })
+        .collect::<Vec<_>>();
+    if allowed.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("all the addresses of {host} are blocked: {addrs:?}"),
+        ));
+    }
+    if allowed.len() < addrs.len() {
+        tracing::debug!("Dropped blocked addresses of {}: {:?}", host, addrs);
+    }
+    Ok(allowed)
+}
+
 /// If the given address is reserved.
 pub fn is_reserved(addr: IpAddr) -> bool {
     use smoltcp::wire::{Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr};
@@ -88,6 +135,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_drop_blocked() {
+        let block = vec!["127.0.0.0/8".parse().unwrap(), "fc00::/7".parse().unwrap()];
+        let addrs = vec![
+            "127.0.0.1:80".parse().unwrap(),
+            "1.1.1.1:80".parse().unwrap(),
+            "[fd00::1]:80".parse().unwrap(),
+        ];
+
+        assert_eq!(
+            drop_blocked("example.com", addrs.clone(), &[]).unwrap(),
+            addrs
+        );
+        assert_eq!(
+            drop_blocked("example.com", addrs.clone(), &block).unwrap(),
+            vec!["1.1.1.1:80".parse().unwrap()]
+        );
+
+        let err = drop_blocked("example.com", vec![addrs[0], addrs[2]], &block).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        // nothing to drop
+        assert!(drop_blocked("example.com", vec![], &block)
+            .unwrap()
+            .is_empty());
+
+        // IPv4-mapped IPv6 addresses are matched against the IPv4 ranges
+        let mapped = "[::ffff:127.0.0.1]:80".parse().unwrap();
+        let err = drop_blocked("example.com", vec![mapped], &block).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
     #[test]
     fn test_is_reserved() {
         assert!(is_reserved(IpAddr::from([0, 0, 0, 0])));
@@ -0,0 +1,87 @@
+use std::{io, time::Duration};
+
+use futures::Future;
+use rd_interface::{Error, Result};
+use tokio::time::sleep;
+
+/// The default wait after a transient accept error.
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// If the error of `accept()` goes away by itself, e.g. a connection reset before
+/// it's accepted, or running out of file descriptors.
+pub fn is_transient_accept_error(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if let Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM) = e.raw_os_error() {
+        return true;
+    }
+
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    )
+}
+
+/// Calls `accept` until it succeeds. Transient errors are logged and retried after
+/// `backoff`, which doubles on each error in a row. Other errors are returned.
+pub async fn accept_with_backoff<T, E, F, Fut>(backoff: Duration, mut accept: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Into<Error>,
+{
+    let mut delay = backoff;
+    loop {
+        match accept().await.map_err(Into::into) {
+            Ok(v) => return Ok(v),
+            Err(Error::IO(e)) if is_transient_accept_error(&e) => {
+                tracing::warn!("Failed to accept, retry in {:?}: {:?}", delay, e);
+                sleep(delay).await;
+                delay = (delay * 2).min(MAX_ACCEPT_BACKOFF);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_accept_with_backoff() {
+        let mut errors = vec![
+            io::Error::from(io::ErrorKind::ConnectionAborted),
+            io::Error::from(io::ErrorKind::ConnectionReset),
+        ];
+        let result = accept_with_backoff(Duration::from_millis(1), || {
+            let result = errors.pop().map_or(Ok(1), Err);
+            async move { result }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 1);
+        assert!(errors.is_empty());
+
+        let result = accept_with_backoff(Duration::from_millis(1), || async {
+            Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied))
+        })
+        .await;
+        assert!(matches!(result, Err(Error::IO(e)) if e.kind() == io::ErrorKind::PermissionDenied));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_transient_accept_error() {
+        assert!(is_transient_accept_error(&io::Error::from_raw_os_error(
+            libc::EMFILE
+        )));
+        assert!(!is_transient_accept_error(&io::Error::from_raw_os_error(
+            libc::EBADF
+        )));
+    }
+}
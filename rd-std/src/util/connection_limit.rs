@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use rd_interface::prelude::*;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// What to do with new connections when the limit is reached.
+#[rd_config]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitMode {
+    /// Stop accepting until a connection is finished.
+    Delay,
+    /// Accept and close excess connections immediately.
+    Close,
+}
+
+impl Default for LimitMode {
+    fn default() -> Self {
+        LimitMode::Delay
+    }
+}
+
+#[rd_config]
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionLimitConfig {
+    /// max in-flight connections, unlimited if not set
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+    #[serde(default)]
+    pub mode: LimitMode,
+}
+
+/// Limits the number of in-flight connections of a server.
+///
+/// ```ignore
+/// loop {
+///     let reserved = limit.reserve().await;
+///     let (socket, addr) = listener.accept().await?;
+///     let permit = match limit.admit(reserved) {
+///         Some(permit) => permit,
+///         None => continue,
+///     };
+///     tokio::spawn(async move {
+///         let _permit = permit;
+///         // serve socket
+///     });
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    semaphore: Option<Arc<Semaphore>>,
+    mode: LimitMode,
+}
+
+/// Keeps a slot of `ConnectionLimit` until dropped.
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl ConnectionLimit {
+    pub fn new(config: ConnectionLimitConfig) -> Self {
+        ConnectionLimit {
+            semaphore: config
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+            mode: config.mode,
+        }
+    }
+    pub fn unlimited() -> Self {
+        Self::new(ConnectionLimitConfig::default())
+    }
+    /// Called before accepting. Waits for a free slot in `Delay` mode.
+    pub async fn reserve(&self) -> Option<ConnectionPermit> {
+        match (&self.semaphore, self.mode) {
+            (Some(semaphore), LimitMode::Delay) => semaphore
+                .clone()
+                .acquire_owned()
+                .await
+                .ok()
+                .map(|p| ConnectionPermit { _permit: Some(p) }),
+            _ => None,
+        }
+    }
+    /// Called after accepting. Returns `None` if the connection should be closed.
+    pub fn admit(&self, reserved: Option<ConnectionPermit>) -> Option<ConnectionPermit> {
+        if reserved.is_some() {
+            return reserved;
+        }
+        match &self.semaphore {
+            Some(semaphore) => semaphore
+                .clone()
+                .try_acquire_owned()
+                .ok()
+                .map(|p| ConnectionPermit { _permit: Some(p) }),
+            None => Some(ConnectionPermit { _permit: None }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[test]
+    fn test_unlimited() {
+        let limit = ConnectionLimit::unlimited();
+        let permits = (0..100)
+            .map(|_| limit.admit(None))
+            .collect::<Option<Vec<_>>>();
+        assert!(permits.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_limit_close() {
+        let limit = ConnectionLimit::new(ConnectionLimitConfig {
+            max_connections: Some(1),
+            mode: LimitMode::Close,
+        });
+
+        let reserved = limit.reserve().await;
+        assert!(reserved.is_none());
+        let first = limit.admit(reserved);
+        assert!(first.is_some());
+        assert!(limit.admit(None).is_none());
+
+        drop(first);
+        assert!(limit.admit(None).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_limit_delay() {
+        let limit = ConnectionLimit::new(ConnectionLimitConfig {
+            max_connections: Some(1),
+            mode: LimitMode::Delay,
+        });
+
+        let reserved = limit.reserve().await;
+        let first = limit.admit(reserved);
+        assert!(first.is_some());
+
+        assert!(timeout(Duration::from_millis(10), limit.reserve())
+            .await
+            .is_err());
+
+        drop(first);
+        let reserved = timeout(Duration::from_millis(10), limit.reserve())
+            .await
+            .unwrap();
+        assert!(limit.admit(reserved).is_some());
+    }
+}
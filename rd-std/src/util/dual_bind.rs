@@ -0,0 +1,117 @@
+use rd_interface::{Address, Context, Net, Result, TcpListener, UdpSocket};
+
+/// Times to retry when the UDP port picked by the TCP listener is in use.
+const MAX_RETRY: usize = 8;
+
+fn with_port(addr: &Address, port: u16) -> Address {
+    match addr {
+        Address::SocketAddr(sa) => Address::SocketAddr((sa.ip(), port).into()),
+        Address::Domain(domain, _) => Address::Domain(domain.clone(), port),
+    }
+}
+
+/// Bind a TCP listener and a UDP socket on the same address.
+///
+/// If the port of `addr` is 0, the UDP socket is bound to the port picked by the
+/// TCP listener, and it's retried with another port if that one is taken by UDP.
+/// Nothing is left bound if it fails.
+pub async fn bind_tcp_udp(
+    net: &Net,
+    ctx: &mut Context,
+    addr: &Address,
+) -> Result<(TcpListener, UdpSocket)> {
+    let mut retry = 0;
+    loop {
+        let listener = net.tcp_bind(ctx, addr).await?;
+        let udp_addr = match addr.port() {
+            0 => with_port(addr, listener.local_addr().await?.port()),
+            _ => addr.clone(),
+        };
+
+        match net.udp_bind(ctx, &udp_addr).await {
+            Ok(udp) => return Ok((listener, udp)),
+            Err(e) if e.is_addr_in_use() && addr.port() == 0 && retry < MAX_RETRY => {
+                tracing::debug!("UDP port of {} is in use, retrying", udp_addr);
+                retry += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rd_interface::{IntoAddress, IntoDyn, ReadBuf};
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::tests::{assert_echo, TestNet};
+
+    #[tokio::test]
+    async fn test_bind_tcp_udp() {
+        let net = TestNet::new().into_dyn();
+
+        let (listener, mut udp) = bind_tcp_udp(
+            &net,
+            &mut Context::new(),
+            &"127.0.0.1:0".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+        let addr = listener.local_addr().await.unwrap();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(udp.local_addr().await.unwrap(), addr);
+
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let (mut rx, mut tx) = tokio::io::split(tcp);
+            tokio::io::copy(&mut rx, &mut tx).await.unwrap();
+        });
+        assert_echo(&net, addr).await;
+
+        let mut client = net
+            .udp_bind(&mut Context::new(), &"127.0.0.1:0".into_address().unwrap())
+            .await
+            .unwrap();
+        client.send_to(b"hello", &addr.into()).await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let mut buf = ReadBuf::new(&mut buf);
+        let from = udp.recv_from(&mut buf).await.unwrap();
+        assert_eq!(buf.filled(), b"hello");
+        assert_eq!(from, client.local_addr().await.unwrap());
+
+        // the port is taken by both
+        let bind = bind_tcp_udp(&net, &mut Context::new(), &addr.into()).await;
+        assert!(bind.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retry_on_udp_in_use() {
+        let net = TestNet::new().into_dyn();
+        // the port picked by the first TCP listener
+        let _taken = net
+            .udp_bind(&mut Context::new(), &"127.0.0.1:1".into_address().unwrap())
+            .await
+            .unwrap();
+
+        let (listener, udp) = bind_tcp_udp(
+            &net,
+            &mut Context::new(),
+            &"127.0.0.1:0".into_address().unwrap(),
+        )
+        .await
+        .unwrap();
+        let addr = listener.local_addr().await.unwrap();
+        assert_ne!(addr.port(), 1);
+        assert_eq!(udp.local_addr().await.unwrap(), addr);
+
+        // the TCP listener of the failed attempt is released
+        sleep(Duration::from_millis(10)).await;
+        net.tcp_bind(&mut Context::new(), &"127.0.0.1:1".into_address().unwrap())
+            .await
+            .unwrap();
+    }
+}
@@ -16,6 +16,7 @@ mod connection;
 mod send_back;
 
 const TIME_TO_LIVE: Duration = Duration::from_secs(30);
+const CAPACITY: usize = 256;
 
 pub struct UdpEndpoint {
     pub from: SocketAddr,
@@ -66,13 +67,13 @@ impl<S> ForwardUdp<S>
 where
     S: RawUdpSource,
 {
-    fn new(s: S, net: Net, channel_size: usize) -> Self {
+    fn new(s: S, net: Net, channel_size: usize, ttl: Duration, capacity: usize) -> Self {
         let (tx, rx) = channel(channel_size);
 
         ForwardUdp {
             s,
             net,
-            conn: LruCache::with_expiry_duration_and_capacity(TIME_TO_LIVE, 256),
+            conn: LruCache::with_expiry_duration_and_capacity(ttl, capacity),
             send_back: tx,
             recv_back: rx,
             channel_size,
@@ -159,7 +160,22 @@ pub async fn forward_udp<S>(s: S, net: Net, channel_size: Option<usize>) -> io::
 where
     S: RawUdpSource,
 {
-    ForwardUdp::new(s, net, channel_size.unwrap_or(128)).await
+    forward_udp_with_ttl(s, net, channel_size, TIME_TO_LIVE, CAPACITY).await
+}
+
+/// Like [`forward_udp`], but a flow is closed after `ttl` without any packet, and at
+/// most `capacity` flows are kept.
+pub async fn forward_udp_with_ttl<S>(
+    s: S,
+    net: Net,
+    channel_size: Option<usize>,
+    ttl: Duration,
+    capacity: usize,
+) -> io::Result<()>
+where
+    S: RawUdpSource,
+{
+    ForwardUdp::new(s, net, channel_size.unwrap_or(128), ttl, capacity).await
 }
 
 #[cfg(test)]
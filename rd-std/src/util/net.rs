@@ -1,4 +1,8 @@
-use rd_interface::{INet, Net};
+use std::{io, net::SocketAddr, time::Duration};
+
+use lru_time_cache::LruCache;
+use parking_lot::Mutex;
+use rd_interface::{async_trait, Address, Context, Error, INet, Net, Result, TcpStream};
 
 /// A no-op Net returns [`Error::NotImplemented`](crate::Error::NotImplemented) for every method.
 pub struct NotImplementedNet;
@@ -31,9 +35,110 @@ impl INet for CombineNet {
     }
 }
 
+/// A Net resolves the domain by `resolver` before [`tcp_connect()`](crate::INet::tcp_connect()) on `net`,
+/// and provides [`lookup_host()`](crate::INet::lookup_host()) of `resolver`. Others are from `net`.
+///
+/// The resolved addresses are cached for `RESOLVE_CACHE_TTL`, so a burst of connections to
+/// the same server only resolves it once. If `resolver` can't resolve at all, the domain is
+/// passed to `net` as is.
+pub struct ResolveNet {
+    net: Net,
+    resolver: Net,
+    cache: Mutex<LruCache<Address, Vec<SocketAddr>>>,
+}
+
+const RESOLVE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+impl ResolveNet {
+    pub fn new(net: Net, resolver: Net) -> ResolveNet {
+        ResolveNet {
+            net,
+            resolver,
+            cache: Mutex::new(LruCache::with_expiry_duration(RESOLVE_CACHE_TTL)),
+        }
+    }
+
+    async fn resolve(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+        if let Some(addrs) = self.cache.lock().peek(addr) {
+            return Ok(addrs.clone());
+        }
+
+        // not locked while resolving, a slow lookup doesn't block the cached ones.
+        let addrs = self.resolver.lookup_host(ctx, addr).await?;
+        if !addrs.is_empty() {
+            self.cache.lock().insert(addr.clone(), addrs.clone());
+        }
+        Ok(addrs)
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for ResolveNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        if let Address::SocketAddr(_) = addr {
+            return self.net.tcp_connect(ctx, addr).await;
+        }
+
+        let addrs = match self.resolve(ctx, addr).await {
+            Ok(addrs) => addrs,
+            Err(Error::NotImplemented) => return self.net.tcp_connect(ctx, addr).await,
+            Err(e) => return Err(e),
+        };
+
+        let mut last_err = None;
+        for addr in addrs {
+            match self.net.tcp_connect(ctx, &addr.into()).await {
+                Ok(tcp) => return Ok(tcp),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        // the server may have moved, resolve it again next time.
+        self.cache.lock().remove(addr);
+
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+            .into()
+        }))
+    }
+}
+
+#[async_trait]
+impl rd_interface::LookupHost for ResolveNet {
+    async fn lookup_host(&self, ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+        self.resolve(ctx, addr).await
+    }
+}
+
+impl INet for ResolveNet {
+    fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        Some(self)
+    }
+
+    fn provide_tcp_bind(&self) -> Option<&dyn rd_interface::TcpBind> {
+        self.net.provide_tcp_bind()
+    }
+
+    fn provide_udp_bind(&self) -> Option<&dyn rd_interface::UdpBind> {
+        self.net.provide_udp_bind()
+    }
+
+    fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+        Some(self)
+    }
+
+    fn get_inner(&self) -> Option<Net> {
+        Some(self.net.clone())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use rd_interface::IntoDyn;
+    use std::sync::{Arc, Mutex};
+
+    use rd_interface::{IntoAddress, IntoDyn};
     use tokio::task::yield_now;
 
     use crate::tests::{
@@ -96,4 +201,76 @@ mod tests {
         yield_now().await;
         assert_echo(&tcp_bind2, "127.0.0.1:12346").await;
     }
+
+    /// Resolves every domain to 127.0.0.1 and records them.
+    #[derive(Default)]
+    struct FakeResolver(Arc<Mutex<Vec<Address>>>);
+
+    #[async_trait]
+    impl rd_interface::LookupHost for FakeResolver {
+        async fn lookup_host(&self, _ctx: &mut Context, addr: &Address) -> Result<Vec<SocketAddr>> {
+            self.0.lock().unwrap().push(addr.clone());
+            Ok(vec![SocketAddr::from(([127, 0, 0, 1], addr.port()))])
+        }
+    }
+
+    impl INet for FakeResolver {
+        fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
+            Some(self)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_net() {
+        let net = TestNet::new().into_dyn();
+        let resolver = FakeResolver::default();
+        let resolved = resolver.0.clone();
+        spawn_echo_server(&net, "127.0.0.1:12345").await;
+        yield_now().await;
+
+        let net = ResolveNet::new(net, resolver.into_dyn()).into_dyn();
+        assert_net_provider(
+            &net,
+            ProviderCapability {
+                tcp_connect: true,
+                tcp_bind: true,
+                udp_bind: true,
+                lookup_host: true,
+            },
+        );
+
+        assert_echo(&net, "example.com:12345").await;
+        assert_echo(&net, "127.0.0.1:12345").await;
+        assert_eq!(
+            *resolved.lock().unwrap(),
+            vec!["example.com:12345".into_address().unwrap()]
+        );
+    }
+    #[tokio::test]
+    async fn test_resolve_net_cache() {
+        let net = TestNet::new().into_dyn();
+        let resolver = FakeResolver::default();
+        let resolved = resolver.0.clone();
+        spawn_echo_server(&net, "127.0.0.1:12347").await;
+        yield_now().await;
+
+        let net = ResolveNet::new(net, resolver.into_dyn()).into_dyn();
+        let addr = "example.com:12347".into_address().unwrap();
+        for _ in 0..5 {
+            net.tcp_connect(&mut Context::new(), &addr).await.unwrap();
+        }
+        assert_echo(&net, "example.com:12347").await;
+        assert_eq!(*resolved.lock().unwrap(), vec![addr]);
+
+        // falls back to the domain if the resolver can't resolve
+        let net =
+            ResolveNet::new(TestNet::new().into_dyn(), NotImplementedNet.into_dyn()).into_dyn();
+        let result = net
+            .tcp_connect(
+                &mut Context::new(),
+                &"example.com:80".into_address().unwrap(),
+            )
+            .await;
+        assert!(!matches!(result, Ok(_) | Err(Error::NotImplemented)));
+    }
 }
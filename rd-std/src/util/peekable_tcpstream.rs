@@ -1,3 +1,4 @@
+use futures::Future;
 use rd_interface::{async_trait, impl_async_write, AsyncRead, ITcpStream, TcpStream};
 use std::{
     collections::VecDeque,
@@ -50,13 +51,18 @@ impl PeekableTcpStream {
             buf: VecDeque::new(),
         }
     }
-    // Fill self.buf to size using self.tcp.read_exact
+    // Fill self.buf to size. The bytes read are kept if it's cancelled, e.g. by a timeout.
     async fn fill_buf(&mut self, size: usize) -> crate::Result<()> {
         if size > self.buf.len() {
-            let to_read = size - self.buf.len();
-            let mut buf = vec![0u8; to_read];
-            self.tcp.read_exact(&mut buf).await?;
-            self.buf.append(&mut buf.into());
+            let mut buf = vec![0u8; size - self.buf.len()];
+            while size > self.buf.len() {
+                let to_read = size - self.buf.len();
+                let read = self.tcp.read(&mut buf[..to_read]).await?;
+                if read == 0 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                self.buf.extend(&buf[..read]);
+            }
         }
         Ok(())
     }
@@ -67,11 +73,41 @@ impl PeekableTcpStream {
 
         Ok(())
     }
+    /// Run `fut` until it's done, it's dropped if the stream is reset first,
+    /// e.g. a client leaving before the upstream is connected. See `cancel_on_close`.
+    pub async fn cancel_on_close<T>(
+        &mut self,
+        fut: impl Future<Output = crate::Result<T>>,
+    ) -> crate::Result<T> {
+        cancel_on_close(&mut self.tcp, &mut self.buf, fut).await
+    }
     pub fn into_inner(self) -> (TcpStream, VecDeque<u8>) {
         (self.tcp, self.buf)
     }
 }
 
+/// Run `fut` until it's done, it's dropped if reading `stream` fails first, e.g.
+/// it's reset by the peer. A half-close is not a reason to cancel, the peer may
+/// still wait for the reply. The bytes received meanwhile are appended to `buf`,
+/// and the stream is no longer watched after that.
+pub async fn cancel_on_close<S, T>(
+    stream: &mut S,
+    buf: &mut impl for<'a> Extend<&'a u8>,
+    fut: impl Future<Output = crate::Result<T>>,
+) -> crate::Result<T>
+where
+    S: AsyncRead + Unpin,
+{
+    tokio::pin!(fut);
+    let mut read_buf = [0u8; 1024];
+    let read = tokio::select! {
+        result = &mut fut => return result,
+        read = stream.read(&mut read_buf) => read?,
+    };
+    buf.extend(&read_buf[..read]);
+    fut.await
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
@@ -156,4 +192,123 @@ mod tests {
         tcp.read_exact(&mut buf).await.unwrap();
         assert_eq!(&buf, b"5678");
     }
+
+    #[tokio::test]
+    async fn test_peek_cancelled() {
+        let net = TestNet::new().into_dyn();
+        let listener = net
+            .tcp_bind(
+                &mut Context::new(),
+                &"127.0.0.1:1235".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            tcp.write_all(b"12").await.unwrap();
+            sleep(Duration::from_millis(100)).await;
+            tcp.write_all(b"34").await.unwrap();
+            sleep(Duration::from_secs(1)).await;
+        });
+
+        let mut tcp = net
+            .tcp_connect(
+                &mut Context::new(),
+                &"127.0.0.1:1235".into_address().unwrap(),
+            )
+            .await
+            .map(PeekableTcpStream::new)
+            .unwrap();
+
+        // the bytes read before the timeout are kept
+        let mut buf = [0u8; 4];
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), tcp.peek_exact(&mut buf))
+                .await
+                .is_err()
+        );
+        tcp.peek_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"1234");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_on_close() {
+        let net = TestNet::new().into_dyn();
+        let listener = net
+            .tcp_bind(
+                &mut Context::new(),
+                &"127.0.0.1:1236".into_address().unwrap(),
+            )
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            tcp.write_all(b"12").await.unwrap();
+            let (tcp, _) = listener.accept().await.unwrap();
+            drop(tcp);
+        });
+        let addr = "127.0.0.1:1236".into_address().unwrap();
+
+        // the bytes received while waiting are kept
+        let mut tcp = net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .map(PeekableTcpStream::new)
+            .unwrap();
+        let result = tcp
+            .cancel_on_close(async {
+                sleep(Duration::from_millis(100)).await;
+                Ok(1)
+            })
+            .await;
+        assert_eq!(result.unwrap(), 1);
+        let mut buf = [0u8; 2];
+        tcp.peek_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"12");
+
+        // a half-close is not a reason to cancel
+        let mut tcp = net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .map(PeekableTcpStream::new)
+            .unwrap();
+        let result = tcp
+            .cancel_on_close(async {
+                sleep(Duration::from_millis(100)).await;
+                Ok(2)
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_on_reset() {
+        use crate::builtin::local::{LocalNet, LocalNetConfig};
+
+        let net = LocalNet::new(LocalNetConfig::default()).into_dyn();
+        let listener = net
+            .tcp_bind(&mut Context::new(), &"127.0.0.1:0".into_address().unwrap())
+            .await
+            .unwrap();
+        let addr = listener.local_addr().await.unwrap().into_address().unwrap();
+        tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            tcp.set_reset_on_drop();
+            drop(tcp);
+        });
+
+        // the future is dropped once the peer is gone
+        let mut tcp = net
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .map(PeekableTcpStream::new)
+            .unwrap();
+        let result = tokio::time::timeout(
+            Duration::from_secs(5),
+            tcp.cancel_on_close(futures::future::pending::<crate::Result<()>>()),
+        )
+        .await
+        .unwrap();
+        assert!(result.is_err());
+    }
 }
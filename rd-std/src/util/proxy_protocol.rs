@@ -0,0 +1,313 @@
+//! HAProxy PROXY protocol v1 and v2.
+//!
+//! <https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt>
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use rd_interface::{prelude::*, AsyncRead, Error, Result, TcpStream};
+use tokio::io::AsyncReadExt;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+#[rd_config]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+impl Default for ProxyProtocolVersion {
+    fn default() -> Self {
+        ProxyProtocolVersion::V1
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::IO(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid PROXY protocol header: {}", reason),
+    ))
+}
+
+/// Parse a v1 header without the trailing CRLF.
+fn parse_v1(line: &[u8]) -> Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("not utf-8"))?;
+    let parts = line.split(' ').collect::<Vec<_>>();
+
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", proto @ ("TCP4" | "TCP6"), src_ip, dst_ip, src_port, dst_port] => {
+            let src_ip: IpAddr = src_ip.parse().map_err(|_| invalid("bad source address"))?;
+            let dst_ip: IpAddr = dst_ip
+                .parse()
+                .map_err(|_| invalid("bad destination address"))?;
+            let src_port: u16 = src_port.parse().map_err(|_| invalid("bad source port"))?;
+            dst_port
+                .parse::<u16>()
+                .map_err(|_| invalid("bad destination port"))?;
+
+            let is_v4 = *proto == "TCP4";
+            if src_ip.is_ipv4() != is_v4 || dst_ip.is_ipv4() != is_v4 {
+                return Err(invalid("address family mismatch"));
+            }
+
+            Ok(Some(SocketAddr::new(src_ip, src_port)))
+        }
+        _ => Err(invalid("malformed v1 header")),
+    }
+}
+
+/// Parse a v2 header, `header` is the fixed 16 bytes and `body` is the rest.
+fn parse_v2(header: &[u8; 16], body: &[u8]) -> Result<Option<SocketAddr>> {
+    if &header[..12] != V2_SIGNATURE {
+        return Err(invalid("bad v2 signature"));
+    }
+    if header[12] >> 4 != 2 {
+        return Err(invalid("unsupported version"));
+    }
+    match header[12] & 0x0f {
+        // LOCAL, e.g. health check from the proxy itself
+        0 => return Ok(None),
+        1 => {}
+        _ => return Err(invalid("unknown command")),
+    }
+
+    match header[13] >> 4 {
+        // AF_UNSPEC or AF_UNIX
+        0 | 3 => Ok(None),
+        // AF_INET
+        1 => {
+            if body.len() < 12 {
+                return Err(invalid("v2 header too short"));
+            }
+            let ip = Ipv4Addr::new(body[0], body[1], body[2], body[3]);
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        }
+        // AF_INET6
+        2 => {
+            if body.len() < 36 {
+                return Err(invalid("v2 header too short"));
+            }
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&body[..16]);
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        _ => Err(invalid("unknown address family")),
+    }
+}
+
+/// Read the PROXY protocol header from `stream`, returns the source address in it.
+/// `None` means the header carries no address.
+pub async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<SocketAddr>> {
+    let mut prefix = [0u8; 6];
+    stream.read_exact(&mut prefix).await?;
+
+    if prefix == V1_PREFIX {
+        let mut line = prefix.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid("v1 header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+        parse_v1(&line[..line.len() - 2])
+    } else if prefix == V2_SIGNATURE[..6] {
+        let mut header = [0u8; 16];
+        header[..6].copy_from_slice(&prefix);
+        stream.read_exact(&mut header[6..]).await?;
+
+        let len = u16::from_be_bytes([header[14], header[15]]) as usize;
+        let mut body = vec![0; len];
+        stream.read_exact(&mut body).await?;
+
+        parse_v2(&header, &body)
+    } else {
+        Err(invalid("header not found"))
+    }
+}
+
+/// Read the PROXY protocol header of an inbound connection. Returns the real source
+/// address, or `peer` if the header carries no address.
+pub async fn accept(socket: &mut TcpStream, peer: SocketAddr) -> Result<SocketAddr> {
+    Ok(read_header(socket).await?.unwrap_or(peer))
+}
+
+fn to_ipv6(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) => SocketAddr::new(v4.ip().to_ipv6_mapped().into(), v4.port()),
+        v6 => v6,
+    }
+}
+
+/// Encode a PROXY protocol header. If `src` is `None`, the header carries no address.
+pub fn encode_header(
+    version: ProxyProtocolVersion,
+    src: Option<SocketAddr>,
+    dst: SocketAddr,
+) -> Vec<u8> {
+    let addrs = src.map(|src| {
+        if src.is_ipv4() == dst.is_ipv4() {
+            (src, dst)
+        } else {
+            (to_ipv6(src), to_ipv6(dst))
+        }
+    });
+
+    match version {
+        ProxyProtocolVersion::V1 => match addrs {
+            Some((src, dst)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if src.is_ipv4() { "TCP4" } else { "TCP6" },
+                src.ip(),
+                dst.ip(),
+                src.port(),
+                dst.port()
+            )
+            .into_bytes(),
+            None => b"PROXY UNKNOWN\r\n".to_vec(),
+        },
+        ProxyProtocolVersion::V2 => {
+            let mut buf = V2_SIGNATURE.to_vec();
+            let mut body = Vec::new();
+            let (command, family) = match addrs {
+                Some((src, dst)) => {
+                    let family = match (src.ip(), dst.ip()) {
+                        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+                            body.extend_from_slice(&src_ip.octets());
+                            body.extend_from_slice(&dst_ip.octets());
+                            0x11
+                        }
+                        (IpAddr::V6(src_ip), IpAddr::V6(dst_ip)) => {
+                            body.extend_from_slice(&src_ip.octets());
+                            body.extend_from_slice(&dst_ip.octets());
+                            0x21
+                        }
+                        _ => unreachable!("address family is unified"),
+                    };
+                    body.extend_from_slice(&src.port().to_be_bytes());
+                    body.extend_from_slice(&dst.port().to_be_bytes());
+                    (0x21, family)
+                }
+                None => (0x20, 0x00),
+            };
+            buf.push(command);
+            buf.push(family);
+            buf.extend_from_slice(&(body.len() as u16).to_be_bytes());
+            buf.extend_from_slice(&body);
+            buf
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn read(mut header: &[u8]) -> Result<Option<SocketAddr>> {
+        read_header(&mut header).await
+    }
+
+    #[tokio::test]
+    async fn test_parse_v1() {
+        assert_eq!(
+            read(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n")
+                .await
+                .unwrap(),
+            Some("192.168.0.1:56324".parse().unwrap())
+        );
+        assert_eq!(
+            read(b"PROXY TCP6 ::1 ::2 56324 443\r\n").await.unwrap(),
+            Some("[::1]:56324".parse().unwrap())
+        );
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+
+        // header is consumed, payload is left
+        let mut stream = &b"PROXY UNKNOWN\r\nGET /"[..];
+        read_header(&mut stream).await.unwrap();
+        assert_eq!(stream, b"GET /");
+
+        assert!(read(b"PROXY TCP4 192.168.0.1 ::1 56324 443\r\n")
+            .await
+            .is_err());
+        assert!(read(b"PROXY TCP4 192.168.0.1 192.168.0.11 56324\r\n")
+            .await
+            .is_err());
+        assert!(read(b"PROXY TCP4 192.168.0.1 192.168.0.11 65536 443\r\n")
+            .await
+            .is_err());
+        assert!(read(&[&b"PROXY "[..], &[b'a'; 200][..]].concat())
+            .await
+            .is_err());
+        assert!(read(b"GET / HTTP/1.1\r\n").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_v2() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0x00, 0x0c]);
+        header.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 11, 0xdc, 0x04, 0x01, 0xbb]);
+        assert_eq!(
+            read(&header).await.unwrap(),
+            Some("192.168.0.1:56324".parse().unwrap())
+        );
+
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x21, 0x00, 0x24]);
+        header.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        header.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        header.extend_from_slice(&[0xdc, 0x04, 0x01, 0xbb]);
+        assert_eq!(
+            read(&header).await.unwrap(),
+            Some("[::1]:56324".parse().unwrap())
+        );
+
+        // LOCAL command
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(read(&header).await.unwrap(), None);
+
+        // bad version
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x11, 0x11, 0x00, 0x00]);
+        assert!(read(&header).await.is_err());
+
+        // too short
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0x00, 0x04, 192, 168, 0, 1]);
+        assert!(read(&header).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_encode_header() {
+        let src: SocketAddr = "192.168.0.1:56324".parse().unwrap();
+        let dst: SocketAddr = "[::1]:443".parse().unwrap();
+
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let header = encode_header(version, Some(src), dst);
+            assert_eq!(
+                read(&header).await.unwrap(),
+                Some(to_ipv6(src)),
+                "{:?}",
+                version
+            );
+            let header = encode_header(version, None, dst);
+            assert_eq!(read(&header).await.unwrap(), None, "{:?}", version);
+        }
+
+        assert_eq!(
+            encode_header(
+                ProxyProtocolVersion::V1,
+                Some(src),
+                "1.1.1.1:443".parse().unwrap()
+            ),
+            b"PROXY TCP4 192.168.0.1 1.1.1.1 56324 443\r\n"
+        );
+    }
+}
@@ -1,28 +1,123 @@
-use std::net::SocketAddr;
-
 use anyhow::Result;
+use axum::Router;
 use rabbit_digger::RabbitDigger;
 
 use crate::config::ConfigManager;
 
+mod config_test;
+mod export;
 mod handlers;
+mod net_status;
 mod routes;
+mod web_ui;
+
+/// Prefix of `bind` to listen on a unix domain socket.
+const UNIX_PREFIX: &str = "unix:";
 
 pub struct ApiServer {
     pub rabbit_digger: RabbitDigger,
     pub config_manager: ConfigManager,
     pub access_token: Option<String>,
     pub web_ui: Option<String>,
+    /// Don't serve `index.html` for unknown paths of the web UI.
+    pub web_ui_no_fallback: bool,
 }
 
 impl ApiServer {
-    pub async fn run(self, bind: &str) -> Result<SocketAddr> {
+    /// Listen on `bind`, which is a `host:port` or `unix:/path/to.sock`.
+    pub async fn run(self, bind: &str) -> Result<()> {
         let app = self.routes().await?;
 
-        let server = axum::Server::bind(&bind.parse()?).serve(app.into_make_service());
-        let local_addr = server.local_addr();
-        tokio::spawn(server);
+        match bind.strip_prefix(UNIX_PREFIX) {
+            Some(path) => run_unix(app, path)?,
+            None => {
+                let server = axum::Server::bind(&bind.parse()?).serve(app.into_make_service());
+                tracing::info!("API server listening on {}", server.local_addr());
+                tokio::spawn(server);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn run_unix(app: Router, path: &str) -> Result<()> {
+    use std::{
+        fs,
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+    };
+    use tokio::net::UnixListener;
+
+    // remove the socket left by the last run
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+    // only the owner can access the api
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    let accept = hyper::server::accept::poll_fn(move |cx| {
+        listener
+            .poll_accept(cx)
+            .map(|r| Some(r.map(|(stream, _)| stream)))
+    });
+    let server = axum::Server::builder(accept).serve(app.into_make_service());
+    tracing::info!("API server listening on {}{}", UNIX_PREFIX, path);
+    tokio::spawn(server);
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn run_unix(_app: Router, _path: &str) -> Result<()> {
+    anyhow::bail!("Unix domain socket is not supported on this platform")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unix_socket() {
+        let path = std::env::temp_dir().join(format!("rd-api-{}.sock", std::process::id()));
+        let server = ApiServer {
+            rabbit_digger: RabbitDigger::new(crate::get_registry().unwrap())
+                .await
+                .unwrap(),
+            config_manager: ConfigManager::new().await.unwrap(),
+            access_token: None,
+            web_ui: None,
+            web_ui_no_fallback: false,
+        };
+        server
+            .run(&format!("{}{}", UNIX_PREFIX, path.display()))
+            .await
+            .unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /api/state HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
 
-        Ok(local_addr)
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,263 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use rabbit_digger::{Config, RabbitDigger};
+use rd_interface::{Context, IntoAddress, Net, TcpStream};
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time::timeout,
+};
+use url::{Position, Url};
+
+/// Bytes of the response kept in [`ConfigTestResult::first_bytes`].
+const FIRST_BYTES: usize = 1024;
+
+/// Result of [`test_config`], the latencies are in milliseconds.
+#[derive(Debug, Default, Serialize)]
+pub struct ConfigTestResult {
+    pub success: bool,
+    pub error: Option<String>,
+    pub connect: Option<u64>,
+    pub first_byte: Option<u64>,
+    /// the beginning of the response, empty if the server sent nothing in time
+    pub first_bytes: String,
+}
+
+impl ConfigTestResult {
+    fn failed(error: anyhow::Error) -> Self {
+        ConfigTestResult {
+            error: Some(format!("{:?}", error)),
+            ..Default::default()
+        }
+    }
+}
+
+async fn connect(net: &Net, url: &Url) -> Result<TcpStream> {
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("No host in {}", url))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("No port in {}", url))?;
+    let tcp = net
+        .tcp_connect(&mut Context::new(), &(host, port).into_address()?)
+        .await?;
+    Ok(tcp)
+}
+
+/// Sends a request for `http` urls, for the others waits for the server to speak first.
+async fn read_first_bytes(tcp: &mut TcpStream, url: &Url) -> Result<Vec<u8>> {
+    if url.scheme() == "http" {
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            &url[Position::BeforePath..Position::AfterQuery],
+            &url[Position::BeforeHost..Position::AfterPort],
+        );
+        tcp.write_all(request.as_bytes()).await?;
+    }
+    let mut buf = vec![0u8; FIRST_BYTES];
+    let size = tcp.read(&mut buf).await?;
+    if size == 0 {
+        return Err(anyhow!("Connection closed before any response"));
+    }
+    buf.truncate(size);
+    Ok(buf)
+}
+
+/// Connect to `url` through `net` and read the first bytes of the response.
+pub async fn probe(net: &Net, url: &Url, timeout_duration: Duration) -> ConfigTestResult {
+    let start = Instant::now();
+    let mut tcp = match timeout(timeout_duration, connect(net, url)).await {
+        Ok(Ok(tcp)) => tcp,
+        Ok(Err(e)) => return ConfigTestResult::failed(e),
+        Err(_) => return ConfigTestResult::failed(anyhow!("Connect timed out")),
+    };
+    let mut result = ConfigTestResult {
+        success: true,
+        connect: Some(start.elapsed().as_millis() as u64),
+        ..Default::default()
+    };
+
+    let remaining = timeout_duration.saturating_sub(start.elapsed());
+    match timeout(remaining, read_first_bytes(&mut tcp, url)).await {
+        Ok(Ok(bytes)) => {
+            result.first_byte = Some(start.elapsed().as_millis() as u64);
+            result.first_bytes = String::from_utf8_lossy(&bytes).into_owned();
+        }
+        Ok(Err(e)) => {
+            result.success = false;
+            result.error = Some(format!("{:?}", e));
+        }
+        // e.g. a TLS server waiting for the client hello
+        Err(_) => tracing::debug!("No response from {} in time", url),
+    }
+
+    result
+}
+
+/// The net the traffic of `config` goes through: the one of its first server, or
+/// its first `rule` net if it has no server.
+pub fn default_probe_net(config: &Config) -> String {
+    if let Some(server) = config.server.values().next() {
+        return server
+            .opt
+            .get("net")
+            .and_then(|net| net.as_str())
+            .unwrap_or("local")
+            .to_string();
+    }
+    config
+        .net
+        .iter()
+        .find(|(_, net)| net.net_type == "rule")
+        .map(|(name, _)| name.clone())
+        .unwrap_or_else(|| "local".to_string())
+}
+
+/// Build `config` apart from the running one and probe `url` through its net
+/// `net_name`. Only `net_name` and the nets it uses are built, and they are dropped
+/// before it returns, whether the build or the probe fails or not.
+pub async fn test_config(
+    rd: &RabbitDigger,
+    config: Config,
+    net_name: &str,
+    url: &Url,
+    timeout_duration: Duration,
+) -> ConfigTestResult {
+    let net = match rd.build_net(config, net_name) {
+        Ok(net) => net,
+        Err(e) => return ConfigTestResult::failed(e),
+    };
+
+    probe(&net, url, timeout_duration).await
+}
+
+#[cfg(test)]
+mod tests {
+    use rabbit_digger::Registry;
+    use rd_interface::{
+        async_trait, config::EmptyConfig, registry::Builder, Address, INet, IntoDyn,
+    };
+    use tokio::io::{copy, duplex, split};
+
+    use super::*;
+
+    /// Echoes every TCP connection back in memory, whatever the address is.
+    struct EchoNet;
+
+    #[async_trait]
+    impl rd_interface::TcpConnect for EchoNet {
+        async fn tcp_connect(
+            &self,
+            _ctx: &mut Context,
+            _addr: &Address,
+        ) -> rd_interface::Result<TcpStream> {
+            let (client, server) = duplex(4096);
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = split(server);
+                copy(&mut reader, &mut writer).await
+            });
+            Ok(TcpStream::from(client))
+        }
+    }
+
+    impl INet for EchoNet {
+        fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+            Some(self)
+        }
+    }
+
+    impl Builder<Net> for EchoNet {
+        const NAME: &'static str = "test_echo";
+        type Config = EmptyConfig;
+        type Item = Self;
+
+        fn build(_config: Self::Config) -> rd_interface::Result<Self> {
+            Ok(EchoNet)
+        }
+    }
+
+    fn config(value: serde_json::Value) -> Config {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_config_probe() {
+        let mut registry = Registry::new_with_builtin().unwrap();
+        registry
+            .init_with_registry("test", |r| {
+                r.add_net::<EchoNet>();
+                Ok(())
+            })
+            .unwrap();
+        let rd = RabbitDigger::new(registry).await.unwrap();
+        let url = Url::parse("http://example.com:8080/hello?a=1").unwrap();
+        let timeout_duration = Duration::from_secs(5);
+
+        let echo = config(serde_json::json!({
+            "net": { "proxy": { "type": "test_echo" } },
+        }));
+        let result = test_config(&rd, echo, "proxy", &url, timeout_duration).await;
+        assert!(result.success, "{:?}", result);
+        assert!(result.error.is_none());
+        assert!(result.connect.unwrap() <= result.first_byte.unwrap());
+        assert!(result
+            .first_bytes
+            .starts_with("GET /hello?a=1 HTTP/1.1\r\nHost: example.com:8080\r\n"));
+
+        // the probe fails
+        let result = test_config(
+            &rd,
+            config(serde_json::json!({})),
+            "blackhole",
+            &url,
+            timeout_duration,
+        )
+        .await;
+        assert!(!result.success);
+        assert!(result.connect.is_none());
+
+        // the build fails
+        let missing = config(serde_json::json!({
+            "net": { "proxy": { "type": "alias", "net": "missing" } },
+        }));
+        let result = test_config(&rd, missing, "proxy", &url, timeout_duration).await;
+        assert!(!result.success);
+        assert!(result.error.unwrap().contains("missing"));
+
+        // the running instance is untouched
+        assert!(!rd.is_running().await);
+    }
+
+    #[test]
+    fn test_default_probe_net() {
+        let with_server = config(serde_json::json!({
+            "net": { "rule": { "type": "rule", "rule": [] } },
+            "server": { "mixed": { "type": "http", "bind": "127.0.0.1:0", "net": "proxy" } },
+        }));
+        assert_eq!(default_probe_net(&with_server), "proxy");
+
+        let rule_only = config(serde_json::json!({
+            "net": {
+                "proxy": { "type": "test_echo" },
+                "rule": { "type": "rule", "rule": [] },
+            },
+        }));
+        assert_eq!(default_probe_net(&rule_only), "rule");
+
+        assert_eq!(default_probe_net(&config(serde_json::json!({}))), "local");
+    }
+
+    #[tokio::test]
+    async fn test_probe_silent_server() {
+        // the server waits for the client to speak first
+        let net = EchoNet.into_dyn();
+        let url = Url::parse("https://example.com/").unwrap();
+        let result = probe(&net, &url, Duration::from_millis(100)).await;
+        assert!(result.success, "{:?}", result);
+        assert!(result.connect.is_some());
+        assert!(result.first_byte.is_none());
+        assert!(result.first_bytes.is_empty());
+    }
+}
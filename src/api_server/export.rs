@@ -0,0 +1,177 @@
+use std::borrow::Cow;
+
+use rd_interface::Value;
+use serde::Serialize;
+
+/// The columns of the exported connections in CSV.
+const CSV_HEADER: &str =
+    "id,start_time,duration,protocol,source,destination,net_chain,upload,download";
+
+/// Start times of the exported connections in unix seconds, both ends are inclusive.
+#[derive(Debug, Default)]
+pub struct TimeRange {
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl TimeRange {
+    fn contains(&self, time: u64) -> bool {
+        self.since.map_or(true, |since| time >= since)
+            && self.until.map_or(true, |until| time <= until)
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ConnectionRecord {
+    pub id: String,
+    pub start_time: u64,
+    /// seconds since the connection started
+    pub duration: u64,
+    pub protocol: String,
+    /// empty if the source is unknown
+    pub source: String,
+    pub destination: String,
+    /// the nets the connection went through, from the server to the outbound
+    pub net_chain: Vec<String>,
+    pub upload: u64,
+    pub download: u64,
+}
+
+/// The connections of `state`, the serialized connection state, which started in `range`,
+/// ordered by the start time.
+pub fn connection_records(state: &Value, range: &TimeRange) -> Vec<ConnectionRecord> {
+    let u64_field = |conn: &Value, key: &str| conn[key].as_u64().unwrap_or_default();
+    let str_field = |value: &Value| value.as_str().unwrap_or_default().to_string();
+
+    let mut records = state["connections"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(id, conn)| ConnectionRecord {
+            id: id.clone(),
+            start_time: u64_field(conn, "start_time"),
+            duration: u64_field(conn, "duration"),
+            protocol: str_field(&conn["protocol"]),
+            source: str_field(&conn["ctx"]["src_socket_addr"]),
+            destination: str_field(&conn["addr"]),
+            net_chain: conn["ctx"]["net_list"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(str_field)
+                .collect(),
+            upload: u64_field(conn, "upload"),
+            download: u64_field(conn, "download"),
+        })
+        .filter(|record| range.contains(record.start_time))
+        .collect::<Vec<_>>();
+    records.sort_by(|a, b| (a.start_time, &a.id).cmp(&(b.start_time, &b.id)));
+
+    records
+}
+
+/// Quote the field if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// The records in CSV with a header, the nets of `net_chain` are joined by `>`.
+pub fn to_csv(records: &[ConnectionRecord]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+
+    for record in records {
+        let row = [
+            record.id.clone(),
+            record.start_time.to_string(),
+            record.duration.to_string(),
+            record.protocol.clone(),
+            record.source.clone(),
+            record.destination.clone(),
+            record.net_chain.join(">"),
+            record.upload.to_string(),
+            record.download.to_string(),
+        ];
+        let row = row.iter().map(|f| csv_field(f)).collect::<Vec<_>>();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> Value {
+        serde_json::json!({
+            "connections": {
+                "a1b2": {
+                    "protocol": "tcp",
+                    "addr": "example.com:443",
+                    "start_time": 1700000000,
+                    "duration": 12,
+                    "ctx": {
+                        "src_socket_addr": "192.168.1.2:50000",
+                        "net_list": ["mixed", "rule", "proxy,1"]
+                    },
+                    "upload": 1024,
+                    "download": 4096
+                },
+                "c3d4": {
+                    "protocol": "udp",
+                    "addr": "8.8.8.8:53",
+                    "start_time": 1700000100,
+                    "duration": 1,
+                    "ctx": { "net_list": ["dns"] },
+                    "upload": 64,
+                    "download": 128
+                }
+            },
+            "total_upload": 1088,
+            "total_download": 4224
+        })
+    }
+
+    #[test]
+    fn test_csv() {
+        let records = connection_records(&state(), &TimeRange::default());
+        let csv = to_csv(&records);
+        let mut lines = csv.lines();
+
+        assert_eq!(lines.next(), Some(CSV_HEADER));
+        assert_eq!(
+            lines.next(),
+            Some("a1b2,1700000000,12,tcp,192.168.1.2:50000,example.com:443,\"mixed>rule>proxy,1\",1024,4096")
+        );
+        assert_eq!(
+            lines.next(),
+            Some("c3d4,1700000100,1,udp,,8.8.8.8:53,dns,64,128")
+        );
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    fn test_time_range() {
+        let range = TimeRange {
+            since: Some(1700000050),
+            until: None,
+        };
+        let records = connection_records(&state(), &range);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, "c3d4");
+        assert_eq!(records[0].net_chain, vec!["dns".to_string()]);
+
+        let range = TimeRange {
+            since: None,
+            until: Some(1700000000),
+        };
+        let records = connection_records(&state(), &range);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, "a1b2");
+    }
+}
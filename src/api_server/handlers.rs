@@ -1,6 +1,7 @@
 use std::{
     error::Error,
     future::ready,
+    path::PathBuf,
     str::from_utf8,
     sync::Arc,
     time::{Duration, Instant},
@@ -17,15 +18,22 @@ use axum::{
 };
 use futures::{Stream, StreamExt, TryStreamExt};
 use hyper::{header::HeaderName, HeaderMap, StatusCode};
-use rabbit_digger::{RabbitDigger, Uuid};
-use rd_interface::{IntoAddress, Value};
+use rabbit_digger::{RabbitDigger, StopMode, Uuid};
+use rd_interface::{Address, IntoAddress, Value};
+use rd_std::rule::RuleNet;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use tokio::{pin, time::interval};
 use tokio_stream::wrappers::IntervalStream;
 
+use super::{
+    config_test::{default_probe_net, test_config},
+    export::{connection_records, to_csv, TimeRange},
+    net_status::{net_status, Probes},
+};
 use crate::{
-    config::{ConfigManager, ImportSource, SelectMap},
+    config::{ConfigManager, ImportSource, SelectMap, Selection},
+    select::SelectNet,
     storage::{FileStorage, Storage},
 };
 
@@ -34,6 +42,7 @@ pub(super) struct Ctx {
     pub(super) rd: RabbitDigger,
     pub(super) cfg_mgr: ConfigManager,
     pub(super) userdata: Arc<FileStorage>,
+    pub(super) probes: Probes,
 }
 
 pub(super) enum ApiError {
@@ -93,12 +102,70 @@ pub(super) async fn post_config(
     Ok(Json(Value::Null))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct PostConfigTest {
+    config: ImportSource,
+    target_url: url::Url,
+    /// the net to connect by, the one the servers of the config use if unset.
+    net: Option<String>,
+    /// in milliseconds, 5000 if unset.
+    timeout: Option<u64>,
+}
+pub(super) async fn post_config_test(
+    Extension(Ctx { rd, cfg_mgr, .. }): Extension<Ctx>,
+    Json(PostConfigTest {
+        config,
+        target_url,
+        net,
+        timeout,
+    }): Json<PostConfigTest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = cfg_mgr.load_merged(&[config]).await?;
+    let net = net.unwrap_or_else(|| default_probe_net(&config));
+    let timeout = Duration::from_millis(timeout.unwrap_or(5000));
+    Ok(Json(
+        test_config(&rd, config, &net, &target_url, timeout).await,
+    ))
+}
+
 pub(super) async fn get_registry(
     Extension(Ctx { rd, .. }): Extension<Ctx>,
 ) -> Result<impl IntoResponse, ApiError> {
     Ok(rd.registry(|r| Json(&r).into_response()).await)
 }
 
+pub(super) async fn get_registry_types(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(rd.registry_types().await))
+}
+
+pub(super) async fn get_net_schema(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    Path(net_type): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let schema = rd.net_schema(&net_type).await.ok_or(ApiError::NotFound)?;
+    Ok(Json(schema))
+}
+
+pub(super) async fn get_server_schema(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    Path(server_type): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let schema = rd
+        .server_schema(&server_type)
+        .await
+        .ok_or(ApiError::NotFound)?;
+    Ok(Json(schema))
+}
+
+/// The schema of the responses of `/connection`, `/get` and `/traffic/history`.
+pub(super) async fn get_api_schema(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(rd.api_schema()))
+}
+
 #[derive(Deserialize)]
 pub struct ConnectionQuery {
     #[serde(default)]
@@ -113,10 +180,162 @@ pub(super) async fn get_connections(
     Ok(rd.connection(|c| Json(&c).into_response()).await)
 }
 
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+    /// only the connections started since, in unix seconds
+    since: Option<u64>,
+    /// only the connections started until, in unix seconds
+    until: Option<u64>,
+}
+
+/// Download the current connections as a CSV or JSON file.
+pub(super) async fn get_connections_export(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    Query(ExportQuery {
+        format,
+        since,
+        until,
+    }): Query<ExportQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let state = rd
+        .connection(|c| serde_json::to_value(c))
+        .await
+        .map_err(ApiError::other)?;
+    let records = connection_records(&state, &TimeRange { since, until });
+
+    let (content_type, filename, body) = match format {
+        ExportFormat::Csv => ("text/csv", "connections.csv", to_csv(&records)),
+        ExportFormat::Json => (
+            "application/json",
+            "connections.json",
+            serde_json::to_string(&records).map_err(ApiError::other)?,
+        ),
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        HeaderName::from_static("content-type"),
+        HeaderValue::from_static(content_type),
+    );
+    headers.insert(
+        HeaderName::from_static("content-disposition"),
+        HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
+            .map_err(ApiError::other)?,
+    );
+
+    Ok((headers, body))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StopQuery {
+    /// `soft` lets the responses being received finish, `hard` by default.
+    #[serde(default)]
+    mode: StopMode,
+}
+
 pub(super) async fn delete_connections(
     Extension(Ctx { rd, .. }): Extension<Ctx>,
+    Query(StopQuery { mode }): Query<StopQuery>,
+) -> Result<Response, ApiError> {
+    Ok(Json(&rd.stop_connections(mode).await?).into_response())
+}
+
+pub(super) async fn get_traffic_history(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+) -> Result<Response, ApiError> {
+    Ok(rd
+        .traffic_history(|h| Json(h.samples()).into_response())
+        .await)
+}
+
+pub(super) async fn get_connect_stats(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(rd.connect_stats().await?))
+}
+
+pub(super) async fn get_refused_stats(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
 ) -> Result<Response, ApiError> {
-    Ok(Json(&rd.stop_connections().await?).into_response())
+    Ok(rd.connection(|c| Json(c.refused()).into_response()).await)
+}
+
+pub(super) async fn get_server_status(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(rd.server_status().await?))
+}
+
+pub(super) async fn post_dns_flush(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+) -> Result<impl IntoResponse, ApiError> {
+    Ok(Json(rd.flush_dns().await?))
+}
+
+pub(super) async fn post_network_rebind(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+) -> Result<impl IntoResponse, ApiError> {
+    rd.rebind();
+    Ok(Json(Value::Null))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostPause {
+    /// stop the existing connections too, they keep running by default.
+    #[serde(default)]
+    stop_connections: bool,
+}
+pub(super) async fn post_pause(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    body: Option<Json<PostPause>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let stop_connections = body
+        .map(|Json(PostPause { stop_connections })| stop_connections)
+        .unwrap_or_default();
+    Ok(Json(rd.pause(stop_connections)))
+}
+
+pub(super) async fn post_resume(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+) -> Result<impl IntoResponse, ApiError> {
+    rd.resume();
+    Ok(Json(Value::Null))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostGeoReload {
+    /// the database file to use, the current one is read again if unset.
+    path: Option<PathBuf>,
+}
+pub(super) async fn post_geo_reload(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    body: Option<Json<PostGeoReload>>,
+) -> Result<impl IntoResponse, ApiError> {
+    let path = body.and_then(|Json(PostGeoReload { path })| path);
+    rd.reload_geoip(path).await?;
+    Ok(Json(Value::Null))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PutServerBind {
+    bind: Address,
+}
+pub(super) async fn put_server_bind(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    Path(server_name): Path<String>,
+    Json(PutServerBind { bind }): Json<PutServerBind>,
+) -> Result<impl IntoResponse, ApiError> {
+    rd.rebind_server(&server_name, bind).await?;
+    Ok(Json(Value::Null))
 }
 
 pub(super) async fn get_state(
@@ -128,16 +347,20 @@ pub(super) async fn get_state(
 #[derive(Debug, Deserialize)]
 pub struct PostSelect {
     selected: String,
+    /// keep using `selected` even if it fails the health check
+    #[serde(default)]
+    pinned: bool,
 }
 pub(super) async fn post_select(
     Extension(Ctx { rd, cfg_mgr, .. }): Extension<Ctx>,
     Path(net_name): Path<String>,
-    Json(PostSelect { selected }): Json<PostSelect>,
+    Json(PostSelect { selected, pinned }): Json<PostSelect>,
 ) -> Result<impl IntoResponse, ApiError> {
+    let selection = Selection::new(selected, pinned);
     rd.update_net(&net_name, |o| {
         if o.net_type == "select" {
             if let Some(o) = o.opt.as_object_mut() {
-                o.insert("selected".to_string(), selected.clone().into());
+                selection.apply(o);
             }
         } else {
             tracing::warn!("net_type is not select");
@@ -145,13 +368,13 @@ pub(super) async fn post_select(
     })
     .await?;
 
-    if let Some(id) = rd.get_id().await {
-        let mut select_map = SelectMap::from_cache(&id, cfg_mgr.select_storage()).await?;
+    if let Some(key) = cfg_mgr.select_key() {
+        let mut select_map = SelectMap::from_cache(&key, cfg_mgr.select_storage()).await?;
 
-        select_map.insert(net_name.to_string(), selected);
+        select_map.insert(net_name.to_string(), selection);
 
         select_map
-            .write_cache(&id, cfg_mgr.select_storage())
+            .write_cache(&key, cfg_mgr.select_storage())
             .await?;
     }
 
@@ -161,8 +384,9 @@ pub(super) async fn post_select(
 pub(super) async fn delete_conn(
     Extension(Ctx { rd, .. }): Extension<Ctx>,
     Path(uuid): Path<Uuid>,
+    Query(StopQuery { mode }): Query<StopQuery>,
 ) -> Result<impl IntoResponse, ApiError> {
-    let ok = rd.stop_connection(uuid).await?;
+    let ok = rd.stop_connection(uuid, mode).await?;
     Ok(Json(ok))
 }
 
@@ -177,7 +401,7 @@ pub struct DelayResponse {
     response: u64,
 }
 pub(super) async fn get_delay(
-    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    Extension(Ctx { rd, probes, .. }): Extension<Ctx>,
     Path(net_name): Path<String>,
     Query(DelayRequest { url, timeout }): Query<DelayRequest>,
 ) -> Result<impl IntoResponse, ApiError> {
@@ -208,6 +432,13 @@ pub(super) async fn get_delay(
                 anyhow::Result::<DelayResponse>::Ok(DelayResponse { connect, response })
             };
             let resp = tokio::time::timeout(Duration::from_millis(timeout), fut).await;
+            probes.record(
+                &net_name,
+                match &resp {
+                    Ok(Ok(r)) => Some(r.response),
+                    _ => None,
+                },
+            );
             let resp = match resp {
                 Ok(v) => Some(v?),
                 _ => None,
@@ -218,6 +449,45 @@ pub(super) async fn get_delay(
     })
 }
 
+pub(super) async fn get_net_status(
+    Extension(Ctx { rd, probes, .. }): Extension<Ctx>,
+    Path(net_name): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let config = rd
+        .get_config(|c| serde_json::from_str::<rabbit_digger::Config>(c))
+        .await?
+        .map_err(ApiError::other)?;
+    let member_health = rd
+        .get_net(&net_name)
+        .await?
+        .and_then(|net| net.net_as::<SelectNet>())
+        .and_then(|select| select.member_health());
+    let status = net_status(&net_name, &config, &probes, member_health.as_ref())
+        .ok_or(ApiError::NotFound)?;
+    Ok(Json(status))
+}
+
+pub(super) async fn get_rule_stats(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    Path(net_name): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let rule_net = rd
+        .get_net(&net_name)
+        .await?
+        .and_then(|net| net.net_as::<RuleNet>())
+        .ok_or(ApiError::NotFound)?;
+    let stats = serde_json::to_value(rule_net.rule_stats()).map_err(ApiError::other)?;
+    Ok(Json(stats))
+}
+
+pub(super) async fn get_net_chain(
+    Extension(Ctx { rd, .. }): Extension<Ctx>,
+    Path(net_name): Path<String>,
+) -> Result<impl IntoResponse, ApiError> {
+    let chain = rd.net_chain(&net_name).await?.ok_or(ApiError::NotFound)?;
+    Ok(Json(chain))
+}
+
 pub(super) async fn get_userdata(
     Extension(Ctx { userdata, .. }): Extension<Ctx>,
     Path(tail): Path<String>,
@@ -332,6 +602,50 @@ pub(super) async fn ws_conn(
     }))
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogFilter {
+    filter: String,
+    /// use this filter on next start
+    #[serde(default, skip_serializing)]
+    persist: bool,
+}
+
+pub(super) async fn get_log_filter() -> Result<impl IntoResponse, ApiError> {
+    let filter = crate::log::get_filter().ok_or(ApiError::NotFound)?;
+    Ok(Json(LogFilter {
+        filter,
+        persist: false,
+    }))
+}
+
+pub(super) async fn put_log_filter(
+    Json(LogFilter { filter, persist }): Json<LogFilter>,
+) -> Result<impl IntoResponse, ApiError> {
+    crate::log::set_filter(&filter)?;
+    if persist {
+        crate::log::save_filter(&filter).await?;
+    }
+
+    Ok(Json(Value::Null))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecentLogQuery {
+    level: Option<String>,
+    limit: Option<usize>,
+}
+
+pub(super) async fn get_recent_logs(
+    Query(RecentLogQuery { level, limit }): Query<RecentLogQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let level = level
+        .map(|l| l.parse::<tracing::Level>())
+        .transpose()
+        .map_err(ApiError::other)?;
+
+    Ok(Json(crate::log::recent_logs(level, limit.unwrap_or(100))))
+}
+
 pub(super) async fn ws_log(ws: WebSocketUpgrade) -> Result<impl IntoResponse, ApiError> {
     Ok(ws.on_upgrade(move |mut ws| async move {
         let mut recv = crate::log::get_sender().subscribe();
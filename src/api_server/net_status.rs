@@ -0,0 +1,192 @@
+use std::{collections::HashMap, sync::Arc};
+
+use parking_lot::Mutex;
+use rabbit_digger::Config;
+use serde::Serialize;
+
+/// Nets whose members are listed in `list`.
+const GROUP_TYPES: &[&str] = &["select"];
+
+/// Results of the last delay probe of each net.
+#[derive(Clone, Default)]
+pub struct Probes(Arc<Mutex<HashMap<String, Option<u64>>>>);
+
+impl Probes {
+    /// Record the latency of a probe in milliseconds, `None` if the probe failed.
+    pub fn record(&self, net_name: &str, latency: Option<u64>) {
+        self.0.lock().insert(net_name.to_string(), latency);
+    }
+    fn get(&self, net_name: &str) -> Option<Option<u64>> {
+        self.0.lock().get(net_name).copied()
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct MemberStatus {
+    pub name: String,
+    pub selected: bool,
+    /// latency of the last probe in milliseconds
+    pub latency: Option<u64>,
+    /// `false` if the last probe or the health check of the group failed,
+    /// nets never probed or checked are healthy
+    pub healthy: bool,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct NetStatus {
+    #[serde(rename = "type")]
+    pub net_type: String,
+    pub latency: Option<u64>,
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub members: Option<Vec<MemberStatus>>,
+}
+
+/// Status of the net `name`, members are only present for group nets.
+/// `member_health` is the result of the group's own health check, by member name.
+pub fn net_status(
+    name: &str,
+    config: &Config,
+    probes: &Probes,
+    member_health: Option<&HashMap<String, bool>>,
+) -> Option<NetStatus> {
+    let net = config.net.get(name)?;
+    let probe = probes.get(name);
+
+    let members = GROUP_TYPES.contains(&net.net_type.as_str()).then(|| {
+        let selected = net.opt.get("selected").and_then(|s| s.as_str());
+        net.opt
+            .get("list")
+            .and_then(|l| l.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|m| m.as_str())
+            .map(|member| {
+                let probe = probes.get(member);
+                let checked = member_health
+                    .and_then(|h| h.get(member))
+                    .copied()
+                    .unwrap_or(true);
+                MemberStatus {
+                    name: member.to_string(),
+                    selected: selected == Some(member),
+                    latency: probe.flatten(),
+                    healthy: checked && probe.map(|p| p.is_some()).unwrap_or(true),
+                }
+            })
+            .collect()
+    });
+
+    Some(NetStatus {
+        net_type: net.net_type.clone(),
+        latency: probe.flatten(),
+        healthy: probe.map(|p| p.is_some()).unwrap_or(true),
+        members,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rabbit_digger::RabbitDigger;
+    use tokio::time::sleep;
+
+    use super::*;
+    use crate::select::SelectNet;
+
+    fn config() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "net": {
+                "proxy": {
+                    "type": "select",
+                    "selected": "a",
+                    "list": ["a", "b", "c"]
+                },
+                "a": { "type": "local" },
+                "b": { "type": "blackhole" },
+                "c": { "type": "local" }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_group_status() {
+        // the health check of the group connects to `target`, which `b` refuses
+        let target = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut config = config();
+        config.net.get_mut("proxy").unwrap().opt["health_check"] = serde_json::json!({
+            "addr": target.local_addr().unwrap().to_string(),
+            "timeout": 1
+        });
+
+        let rd = RabbitDigger::new(crate::get_registry().unwrap())
+            .await
+            .unwrap();
+        rd.start(config.clone()).await.unwrap();
+        // the first check runs on start
+        sleep(Duration::from_millis(200)).await;
+        let member_health = rd
+            .get_net("proxy")
+            .await
+            .unwrap()
+            .and_then(|net| net.net_as::<SelectNet>())
+            .and_then(|select| select.member_health());
+
+        let probes = Probes::default();
+        probes.record("a", Some(120));
+
+        let status = net_status("proxy", &config, &probes, member_health.as_ref()).unwrap();
+        assert_eq!(status.net_type, "select");
+        assert_eq!(
+            status.members.unwrap(),
+            vec![
+                MemberStatus {
+                    name: "a".to_string(),
+                    selected: true,
+                    latency: Some(120),
+                    healthy: true,
+                },
+                MemberStatus {
+                    name: "b".to_string(),
+                    selected: false,
+                    latency: None,
+                    healthy: false,
+                },
+                MemberStatus {
+                    name: "c".to_string(),
+                    selected: false,
+                    latency: None,
+                    healthy: true,
+                },
+            ]
+        );
+
+        // a successful probe doesn't hide the failed health check
+        probes.record("b", Some(80));
+        let status = net_status("proxy", &config, &probes, member_health.as_ref()).unwrap();
+        assert!(!status.members.unwrap()[1].healthy);
+
+        rd.stop().await.unwrap();
+    }
+
+    #[test]
+    fn test_non_group_status() {
+        let config = config();
+        let probes = Probes::default();
+        probes.record("b", None);
+
+        let status = net_status("b", &config, &probes, None).unwrap();
+        assert_eq!(
+            status,
+            NetStatus {
+                net_type: "blackhole".to_string(),
+                latency: None,
+                healthy: false,
+                members: None,
+            }
+        );
+        assert!(net_status("missing", &config, &probes, None).is_none());
+    }
+}
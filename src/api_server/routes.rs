@@ -5,18 +5,17 @@ use axum::{
     middleware::{self, Next},
     response::IntoResponse,
     routing::get,
-    routing::{delete, get_service, post},
+    routing::{delete, post, put},
     Router,
 };
 use hyper::{
     header::{AUTHORIZATION, CONTENT_TYPE},
-    Method, Request, StatusCode,
+    HeaderMap, Method, Request, StatusCode, Uri,
 };
 use rd_interface::Arc;
 use serde::Deserialize;
 use tower_http::{
     cors::{Any, CorsLayer},
-    services::ServeDir,
     trace::TraceLayer,
 };
 
@@ -24,6 +23,7 @@ use crate::storage::{FileStorage, FolderType};
 
 use super::{
     handlers::{self, Ctx},
+    web_ui::WebUi,
     ApiServer,
 };
 
@@ -39,18 +39,34 @@ impl ApiServer {
             )
             .layer(TraceLayer::new_for_http());
 
-        if let Some(webui) = &self.web_ui {
-            router = router.fallback(get_service(ServeDir::new(webui)))
+        if let Some(web_ui) = self.web_ui() {
+            let web_ui = Arc::new(web_ui);
+            router = router.fallback(move |uri: Uri, headers: HeaderMap| {
+                let web_ui = web_ui.clone();
+                async move { web_ui.serve(uri, headers).await }
+            })
         }
 
         Ok(router)
     }
 
+    fn web_ui(&self) -> Option<WebUi> {
+        let spa_fallback = !self.web_ui_no_fallback;
+        match &self.web_ui {
+            Some(folder) => Some(WebUi::folder(folder, spa_fallback)),
+            #[cfg(feature = "embed_ui")]
+            None => Some(WebUi::embedded(spa_fallback)),
+            #[cfg(not(feature = "embed_ui"))]
+            None => None,
+        }
+    }
+
     async fn api(&self) -> Result<Router> {
         let ctx = Ctx {
             rd: self.rabbit_digger.clone(),
             cfg_mgr: self.config_manager.clone(),
             userdata: Arc::new(FileStorage::new(FolderType::Data, "userdata").await?),
+            probes: Default::default(),
         };
 
         let mut router = Router::new()
@@ -58,15 +74,40 @@ impl ApiServer {
                 "/config",
                 get(handlers::get_config).post(handlers::post_config),
             )
+            .route("/config/test", post(handlers::post_config_test))
             .route("/get", get(handlers::get_registry))
+            .route("/registry/types", get(handlers::get_registry_types))
+            .route(
+                "/registry/net/:net_type/schema",
+                get(handlers::get_net_schema),
+            )
+            .route(
+                "/registry/server/:server_type/schema",
+                get(handlers::get_server_schema),
+            )
+            .route("/schema/api", get(handlers::get_api_schema))
             .route("/state", get(handlers::get_state))
+            .route("/dns/flush", post(handlers::post_dns_flush))
+            .route("/network/rebind", post(handlers::post_network_rebind))
+            .route("/geo/reload", post(handlers::post_geo_reload))
+            .route("/pause", post(handlers::post_pause))
+            .route("/resume", post(handlers::post_resume))
+            .route("/traffic/history", get(handlers::get_traffic_history))
+            .route("/stats/connect", get(handlers::get_connect_stats))
+            .route("/stats/refused", get(handlers::get_refused_stats))
+            .route("/server/status", get(handlers::get_server_status))
+            .route("/servers/:server_name/bind", put(handlers::put_server_bind))
             .route("/connection/:uuid", delete(handlers::delete_conn))
+            .route("/connection/export", get(handlers::get_connections_export))
             .route(
                 "/connection",
                 get(handlers::get_connections).delete(handlers::delete_connections),
             )
             .route("/net/:net_name", post(handlers::post_select))
             .route("/net/:net_name/delay", get(handlers::get_delay))
+            .route("/net/:net_name/status", get(handlers::get_net_status))
+            .route("/net/:net_name/rule_stats", get(handlers::get_rule_stats))
+            .route("/net/:net_name/chain", get(handlers::get_net_chain))
             .route(
                 "/userdata/*path",
                 get(handlers::get_userdata)
@@ -76,6 +117,12 @@ impl ApiServer {
             .route("/userdata", get(handlers::list_userdata))
             .route("/stream/connection", get(handlers::get_connection))
             .route("/stream/logs", get(handlers::ws_log))
+            .route("/ws/log", get(handlers::ws_log))
+            .route("/log/recent", get(handlers::get_recent_logs))
+            .route(
+                "/log/filter",
+                get(handlers::get_log_filter).put(handlers::put_log_filter),
+            )
             .layer(Extension(ctx));
 
         if let Some(token) = &self.access_token {
@@ -0,0 +1,220 @@
+use std::{
+    borrow::Cow,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::{Component, Path, PathBuf},
+};
+
+use axum::response::{IntoResponse, Response};
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+    HeaderMap, StatusCode, Uri,
+};
+
+#[cfg(feature = "embed_ui")]
+static EMBEDDED: include_dir::Dir = include_dir::include_dir!("$RD_EMBED_WEB_UI");
+
+const INDEX: &str = "index.html";
+
+enum Source {
+    Folder(PathBuf),
+    #[cfg(feature = "embed_ui")]
+    Embedded,
+}
+
+/// Serves the static files of the web UI.
+pub struct WebUi {
+    source: Source,
+    /// Serve `index.html` for unknown paths, so the routes of a single-page app work.
+    spa_fallback: bool,
+}
+
+impl WebUi {
+    pub fn folder(path: impl Into<PathBuf>, spa_fallback: bool) -> WebUi {
+        WebUi {
+            source: Source::Folder(path.into()),
+            spa_fallback,
+        }
+    }
+    /// The web UI embedded at compile time, from the folder in `RD_EMBED_WEB_UI`.
+    #[cfg(feature = "embed_ui")]
+    pub fn embedded(spa_fallback: bool) -> WebUi {
+        WebUi {
+            source: Source::Embedded,
+            spa_fallback,
+        }
+    }
+
+    async fn read(&self, path: &Path) -> Option<Cow<'static, [u8]>> {
+        match &self.source {
+            Source::Folder(root) => {
+                let path = root.join(path);
+                if !tokio::fs::metadata(&path).await.ok()?.is_file() {
+                    return None;
+                }
+                tokio::fs::read(path).await.ok().map(Cow::Owned)
+            }
+            #[cfg(feature = "embed_ui")]
+            Source::Embedded => EMBEDDED.get_file(path).map(|f| Cow::Borrowed(f.contents())),
+        }
+    }
+
+    pub async fn serve(&self, uri: Uri, headers: HeaderMap) -> Response {
+        let path = uri.path();
+        // never shadow the api
+        if path == "/api" || path.starts_with("/api/") {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+
+        let path = match sanitize(path) {
+            Some(path) => path,
+            None => return StatusCode::BAD_REQUEST.into_response(),
+        };
+        let path = if path.as_os_str().is_empty() {
+            PathBuf::from(INDEX)
+        } else {
+            path
+        };
+
+        let (path, content) = match self.read(&path).await {
+            Some(content) => (path, content),
+            None if self.spa_fallback => match self.read(Path::new(INDEX)).await {
+                Some(content) => (PathBuf::from(INDEX), content),
+                None => return StatusCode::NOT_FOUND.into_response(),
+            },
+            None => return StatusCode::NOT_FOUND.into_response(),
+        };
+
+        let etag = etag(&content);
+        let cache_control = if path == Path::new(INDEX) {
+            // always revalidate the entry, assets may be renamed by a new build
+            "no-cache"
+        } else {
+            "public, max-age=3600"
+        };
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(ETAG, etag.parse().expect("etag is a valid header"));
+        response_headers.insert(CACHE_CONTROL, cache_control.parse().unwrap());
+
+        let not_modified = headers
+            .get(IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| {
+                v.split(',')
+                    .any(|tag| tag.trim() == etag || tag.trim() == "*")
+            })
+            .unwrap_or(false);
+        if not_modified {
+            return (StatusCode::NOT_MODIFIED, response_headers).into_response();
+        }
+
+        let mime = mime_guess::from_path(&path).first_or_octet_stream();
+        response_headers.insert(CONTENT_TYPE, mime.as_ref().parse().unwrap());
+
+        (response_headers, content.into_owned()).into_response()
+    }
+}
+
+/// Convert the request path to a relative path, reject anything escaping the root.
+fn sanitize(path: &str) -> Option<PathBuf> {
+    let path = percent_encoding::percent_decode_str(path)
+        .decode_utf8()
+        .ok()?;
+    let mut result = PathBuf::new();
+    for component in Path::new(path.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(c) => result.push(c),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(result)
+}
+
+fn etag(content: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(
+        web_ui: &WebUi,
+        path: &str,
+        headers: HeaderMap,
+    ) -> (StatusCode, HeaderMap, Vec<u8>) {
+        let response = web_ui.serve(path.parse().unwrap(), headers).await;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, headers, body.to_vec())
+    }
+
+    fn create_folder(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rd-web-ui-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join(INDEX), "<html></html>").unwrap();
+        std::fs::write(dir.join("assets/app.js"), "console.log(1)").unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn test_spa_fallback() {
+        let dir = create_folder("spa");
+        let web_ui = WebUi::folder(&dir, true);
+
+        let (status, headers, body) = get(&web_ui, "/assets/app.js", HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"console.log(1)");
+        assert_eq!(headers[CONTENT_TYPE], "application/javascript");
+        assert_eq!(headers[CACHE_CONTROL], "public, max-age=3600");
+
+        let (status, headers, body) = get(&web_ui, "/connections/detail", HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"<html></html>");
+        assert_eq!(headers[CACHE_CONTROL], "no-cache");
+
+        let (status, _, _) = get(&web_ui, "/api/unknown", HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _, _) = get(&web_ui, "/../secret", HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_no_fallback() {
+        let dir = create_folder("no-fallback");
+        let web_ui = WebUi::folder(&dir, false);
+
+        let (status, _, body) = get(&web_ui, "/", HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"<html></html>");
+
+        let (status, _, _) = get(&web_ui, "/assets/missing.js", HeaderMap::new()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_etag() {
+        let dir = create_folder("etag");
+        let web_ui = WebUi::folder(&dir, true);
+
+        let (_, headers, _) = get(&web_ui, "/assets/app.js", HeaderMap::new()).await;
+        let etag = headers[ETAG].clone();
+
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(IF_NONE_MATCH, etag);
+        let (status, _, body) = get(&web_ui, "/assets/app.js", request_headers).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert!(body.is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
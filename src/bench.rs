@@ -0,0 +1,203 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use futures::{stream, StreamExt};
+use rd_interface::{Address, Context, Net};
+use serde::Serialize;
+use tokio::{
+    io::{split, AsyncReadExt, AsyncWriteExt},
+    time::timeout,
+};
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// The address to connect to. It should echo the payload back to measure
+    /// the throughput.
+    pub target: Address,
+    /// Connections in total.
+    pub connections: usize,
+    /// Connections open at the same time.
+    pub concurrency: usize,
+    /// Bytes written to and read back from each connection, 0 to only connect.
+    pub payload: usize,
+    /// Timeout of each connection.
+    pub timeout: Duration,
+}
+
+/// Result of [`bench`], the latencies are in milliseconds.
+#[derive(Debug, Default, Serialize)]
+pub struct BenchSummary {
+    pub connections: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub connect_min: f64,
+    pub connect_avg: f64,
+    pub connect_p50: f64,
+    pub connect_p99: f64,
+    pub connect_max: f64,
+    /// bytes sent and received by the succeeded connections
+    pub bytes: u64,
+    pub elapsed: f64,
+    /// bytes per second
+    pub throughput: f64,
+}
+
+impl fmt::Display for BenchSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "connections: {} succeeded, {} failed, {} in total",
+            self.succeeded, self.failed, self.connections
+        )?;
+        writeln!(
+            f,
+            "connect (ms): min {:.2}, avg {:.2}, p50 {:.2}, p99 {:.2}, max {:.2}",
+            self.connect_min,
+            self.connect_avg,
+            self.connect_p50,
+            self.connect_p99,
+            self.connect_max
+        )?;
+        write!(
+            f,
+            "transferred: {} bytes in {:.2} ms, {:.2} MB/s",
+            self.bytes,
+            self.elapsed,
+            self.throughput / 1024.0 / 1024.0
+        )
+    }
+}
+
+/// Returns the connect latency and the bytes transferred.
+async fn bench_one(net: &Net, opts: &BenchOptions) -> Result<(Duration, u64)> {
+    let start = Instant::now();
+    let tcp = net.tcp_connect(&mut Context::new(), &opts.target).await?;
+    let connect = start.elapsed();
+    if opts.payload == 0 {
+        return Ok((connect, 0));
+    }
+
+    let (mut reader, mut writer) = split(tcp);
+    let send = async {
+        writer.write_all(&vec![0u8; opts.payload]).await?;
+        writer.flush().await
+    };
+    let mut buf = vec![0u8; opts.payload];
+    let (sent, received) = tokio::join!(send, reader.read_exact(&mut buf));
+    sent?;
+    received?;
+
+    Ok((connect, opts.payload as u64 * 2))
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[index]
+}
+
+/// Open `opts.connections` connections to `opts.target` through `net`, at most
+/// `opts.concurrency` of them at the same time.
+pub async fn bench(net: Net, opts: BenchOptions) -> Result<BenchSummary> {
+    if opts.connections == 0 || opts.concurrency == 0 {
+        return Err(anyhow!(
+            "connections and concurrency must be greater than 0"
+        ));
+    }
+
+    let start = Instant::now();
+    let results = stream::iter(0..opts.connections)
+        .map(|_| timeout(opts.timeout, bench_one(&net, &opts)))
+        .buffer_unordered(opts.concurrency)
+        .collect::<Vec<_>>()
+        .await;
+    let elapsed = start.elapsed();
+
+    let mut summary = BenchSummary {
+        connections: opts.connections,
+        elapsed: elapsed.as_secs_f64() * 1000.0,
+        ..Default::default()
+    };
+    let mut latencies = Vec::with_capacity(results.len());
+    for result in results {
+        match result {
+            Ok(Ok((connect, bytes))) => {
+                latencies.push(connect.as_secs_f64() * 1000.0);
+                summary.bytes += bytes;
+            }
+            Ok(Err(e)) => tracing::debug!("Bench connection failed: {:?}", e),
+            Err(_) => tracing::debug!("Bench connection timed out"),
+        }
+    }
+    summary.succeeded = latencies.len();
+    summary.failed = opts.connections - latencies.len();
+
+    if !latencies.is_empty() {
+        latencies.sort_by(|a, b| a.total_cmp(b));
+        summary.connect_min = latencies[0];
+        summary.connect_max = latencies[latencies.len() - 1];
+        summary.connect_avg = latencies.iter().sum::<f64>() / latencies.len() as f64;
+        summary.connect_p50 = percentile(&latencies, 0.5);
+        summary.connect_p99 = percentile(&latencies, 0.99);
+    }
+    summary.throughput = summary.bytes as f64 / elapsed.as_secs_f64();
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use rd_interface::{IntoAddress, IntoDyn};
+    use rd_std::tests::{spawn_echo_server, TestNet};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bench() {
+        let net = TestNet::new().into_dyn();
+        spawn_echo_server(&net, "127.0.0.1:26670").await;
+
+        let summary = bench(
+            net.clone(),
+            BenchOptions {
+                target: "127.0.0.1:26670".into_address().unwrap(),
+                connections: 20,
+                concurrency: 4,
+                payload: 64 * 1024,
+                timeout: Duration::from_secs(5),
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(summary.connections, 20);
+        assert_eq!(summary.succeeded, 20);
+        assert_eq!(summary.failed, 0);
+        assert_eq!(summary.bytes, 20 * 64 * 1024 * 2);
+        assert!(summary.connect_max > 0.0);
+        assert!(summary.connect_min <= summary.connect_p50);
+        assert!(summary.connect_p50 <= summary.connect_p99);
+        assert!(summary.connect_p99 <= summary.connect_max);
+        assert!(summary.elapsed > 0.0);
+        assert!(summary.throughput > 0.0);
+        assert!(summary.to_string().contains("20 succeeded"));
+
+        // nothing is listening
+        let summary = bench(
+            net,
+            BenchOptions {
+                target: "127.0.0.1:26671".into_address().unwrap(),
+                connections: 2,
+                concurrency: 2,
+                payload: 0,
+                timeout: Duration::from_secs(5),
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary.failed, 2);
+        assert_eq!(summary.bytes, 0);
+    }
+}
@@ -1,4 +1,9 @@
-pub use self::{importer::get_importer_registry, manager::ConfigManager, select_map::SelectMap};
+pub use self::{
+    importer::get_importer_registry,
+    manager::ConfigManager,
+    secret::{resolve_secrets, Secrets},
+    select_map::{SelectMap, Selection},
+};
 use anyhow::{anyhow, Context, Result};
 use futures::{Future, StreamExt};
 use notify_stream::{notify::RecursiveMode, notify_stream};
@@ -12,10 +17,15 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
     future::pending,
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::{Duration, SystemTime},
 };
-use tokio::{fs::read_to_string, sync::OnceCell, time::sleep};
+use tokio::{
+    fs::read_to_string,
+    io::{AsyncRead, AsyncReadExt},
+    sync::OnceCell,
+    time::sleep,
+};
 
 use crate::{
     storage::{FileStorage, FolderType, Storage},
@@ -24,6 +34,7 @@ use crate::{
 
 mod importer;
 mod manager;
+mod secret;
 mod select_map;
 
 static CONFIG_STORAGE: OnceCell<FileStorage> = OnceCell::const_new();
@@ -110,9 +121,32 @@ impl ImportSource {
     pub fn new_path(path: PathBuf) -> Self {
         ImportSource::Path(path)
     }
+    /// The directory the relative paths in a file are resolved against.
+    pub fn base_dir(&self) -> Option<&Path> {
+        match self {
+            ImportSource::Path(path) => path.parent(),
+            _ => None,
+        }
+    }
     pub fn new_poll(url: String, interval: Option<u64>) -> Self {
         ImportSource::Poll(ImportUrl { url, interval })
     }
+    /// Parse the `--config` argument. `-` reads the config from `stdin`,
+    /// `http://` or `https://` fetches it from the URL, others are file paths.
+    pub async fn from_arg(arg: &str, mut stdin: impl AsyncRead + Unpin) -> Result<Self> {
+        Ok(if arg == "-" {
+            let mut content = String::new();
+            stdin
+                .read_to_string(&mut content)
+                .await
+                .context("Failed to read config from stdin")?;
+            ImportSource::Text(content)
+        } else if arg.starts_with("http://") || arg.starts_with("https://") {
+            ImportSource::new_poll(arg.to_string(), None)
+        } else {
+            ImportSource::new_path(PathBuf::from(arg))
+        })
+    }
     pub fn cache_key(&self) -> String {
         match self {
             ImportSource::Path(path) => format!("path:{path:?}"),
@@ -254,10 +288,199 @@ pub(crate) struct ConfigImport {
     import: Vec<Import>,
 }
 
+/// Merge the config document `other` into `base`. Maps are merged by their keys
+/// recursively and the other values are replaced, so a later file can change a
+/// field of a net. A net or server is replaced as a whole if its `type` is changed.
+pub fn merge_config_value(base: &mut serde_yaml::Value, other: serde_yaml::Value) {
+    merge_value(base, other, "")
+}
+
+fn merge_value(base: &mut serde_yaml::Value, other: serde_yaml::Value, path: &str) {
+    use serde_yaml::Value;
+
+    match (base, other) {
+        (Value::Mapping(base), Value::Mapping(other)) => {
+            for (key, value) in other {
+                let key_path = match key.as_str() {
+                    Some(k) if path.is_empty() => k.to_string(),
+                    Some(k) => format!("{}/{}", path, k),
+                    None => format!("{}/{:?}", path, key),
+                };
+                match base.get_mut(&key) {
+                    Some(base_value)
+                        if matches!(path, "net" | "server")
+                            && base_value.get("type") != value.get("type") =>
+                    {
+                        tracing::debug!("{} is replaced by another type", key_path);
+                        *base_value = value;
+                    }
+                    Some(base_value) => merge_value(base_value, value, &key_path),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => {
+            if *base != other {
+                tracing::debug!("{} is overridden: {:?} -> {:?}", path, base, other);
+            }
+            *base = other;
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ConfigExt {
     #[serde(flatten)]
-    config: Config,
+    pub(crate) config: Config,
     #[serde(default, with = "serde_yaml::with::singleton_map_recursive")]
     import: Vec<Import>,
 }
+
+/// Write `config` to `path` as YAML with its secrets redacted, so it can be loaded
+/// again by `--config`.
+pub async fn write_config(path: impl AsRef<Path>, config: &Config) -> Result<()> {
+    let content = serde_yaml::to_string(&config.to_redacted_value()?)?;
+    tokio::fs::write(path, content).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::storage::MemoryCache;
+
+    const CONFIG: &str = "net:\n  local:\n    type: local\n";
+
+    #[tokio::test]
+    async fn test_config_from_stdin() {
+        let source = ImportSource::from_arg("-", CONFIG.as_bytes())
+            .await
+            .unwrap();
+        assert!(matches!(source, ImportSource::Text(_)));
+
+        let cache = MemoryCache::new().await.unwrap();
+        assert_eq!(source.get_content(&cache).await.unwrap(), CONFIG);
+    }
+
+    #[tokio::test]
+    async fn test_config_from_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = socket.read(&mut buf).await.unwrap();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                CONFIG.len(),
+                CONFIG
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let url = format!("http://{}/config.yaml", addr);
+        let source = ImportSource::from_arg(&url, tokio::io::empty())
+            .await
+            .unwrap();
+        assert!(
+            matches!(&source, ImportSource::Poll(ImportUrl { url: u, interval: None }) if u == &url)
+        );
+
+        let cache = MemoryCache::new().await.unwrap();
+        assert_eq!(source.get_content(&cache).await.unwrap(), CONFIG);
+    }
+
+    #[tokio::test]
+    async fn test_config_from_path() {
+        let source = ImportSource::from_arg("config.yaml", tokio::io::empty())
+            .await
+            .unwrap();
+        assert!(matches!(source, ImportSource::Path(p) if p == PathBuf::from("config.yaml")));
+    }
+
+    #[tokio::test]
+    async fn test_write_config() {
+        let dir = std::env::temp_dir().join(format!("rd-write-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("secrets.yaml"), "ss_password: p@ss\n").unwrap();
+        let content = r#"
+secrets: secrets.yaml
+net:
+  proxy:
+    type: shadowsocks
+    server: 1.2.3.4:8388
+    password: !secret ss_password
+"#;
+        let config = crate::deserialize_configs_in_dirs(&[(content, Some(dir.as_path()))])
+            .unwrap()
+            .config;
+        assert_eq!(config.net["proxy"].opt["password"], "p@ss");
+
+        let path = dir.join("written.yaml");
+        write_config(&path, &config).await.unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert!(!written.contains("p@ss"));
+
+        // the written config loads again
+        let loaded = crate::deserialize_config(&written).unwrap().config;
+        assert_eq!(loaded.net["proxy"].opt["password"], "<redacted>");
+        assert_eq!(loaded.net["proxy"].opt["server"], "1.2.3.4:8388");
+        assert!(loaded.secrets.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_merge_configs() {
+        let base = r#"
+net:
+  proxy:
+    type: socks5
+    address: 127.0.0.1:1080
+  other:
+    type: local
+    ttl: 64
+server:
+  mixed:
+    type: http+socks5
+    bind: 127.0.0.1:10800
+    net: proxy
+"#;
+        let host = r#"
+net:
+  proxy:
+    type: socks5
+    address: 10.0.0.1:1080
+  other:
+    type: blackhole
+server:
+  http:
+    type: http
+    bind: 127.0.0.1:8080
+    net: proxy
+"#;
+        let config = crate::deserialize_configs(&[base, host]).unwrap().config;
+        let to_json = |v| serde_json::to_value(v).unwrap();
+
+        // the field is overridden
+        assert_eq!(
+            to_json(&config.net["proxy"]),
+            serde_json::json!({ "type": "socks5", "address": "10.0.0.1:1080" })
+        );
+        // replaced by another type without the old fields
+        assert_eq!(
+            to_json(&config.net["other"]),
+            serde_json::json!({ "type": "blackhole" })
+        );
+        assert_eq!(config.server.len(), 2);
+        assert_eq!(config.server["mixed"].server_type, "http+socks5");
+        assert_eq!(config.server["http"].server_type, "http");
+    }
+}
@@ -1,6 +1,9 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, path::Path};
 
-use crate::{config::Import, storage::Storage};
+use crate::{
+    config::{Import, ImportSource},
+    storage::Storage,
+};
 use anyhow::{anyhow, Result};
 use once_cell::sync::OnceCell;
 use rabbit_digger::config::Config;
@@ -13,6 +16,7 @@ mod clash;
 mod merge;
 #[cfg(feature = "rhai")]
 mod rhai;
+mod transform;
 
 type Registry = BTreeMap<&'static str, Resolver<BoxImporter>>;
 
@@ -28,6 +32,7 @@ pub fn get_importer_registry() -> &'static Registry {
         add_importer::<merge::Merge>(&mut registry);
         #[cfg(feature = "rhai")]
         add_importer::<rhai::Rhai>(&mut registry);
+        add_importer::<transform::Transform>(&mut registry);
 
         registry
     })
@@ -53,5 +58,11 @@ pub trait Importer: Send + Sync {
         content: &str,
         cache: &dyn Storage,
     ) -> Result<()>;
+    /// Other sources read by `process`, the config is reloaded when they change.
+    fn sources(&self) -> Vec<ImportSource> {
+        Vec::new()
+    }
+    /// The directory of the imported file, if it's a file.
+    fn set_base_dir(&mut self, _dir: Option<&Path>) {}
 }
 pub type BoxImporter = Box<dyn Importer>;
@@ -10,7 +10,7 @@ use rabbit_digger::{
     config::{Config, Net},
     rd_std::rule::config::{
         self as rule_config, AnyMatcher, DomainMatcher, DomainMatcherMethod, GeoIpMatcher, IpCidr,
-        IpCidrMatcher, Matcher, SrcIpCidrMatcher,
+        IpCidrMatcher, Matcher, SrcGeoIpMatcher, SrcIpCidrMatcher,
     },
 };
 use rd_interface::{
@@ -31,6 +31,8 @@ pub struct Clash {
     prefix: Option<String>,
     direct: Option<String>,
     reject: Option<String>,
+    /// The net of `REJECT-DROP`, e.g. a `tarpit` net. Same as `reject` if not set
+    reject_drop: Option<String>,
 
     #[serde(default)]
     disable_proxy_group: bool,
@@ -39,9 +41,18 @@ pub struct Clash {
     #[serde(default)]
     select: Option<String>,
 
+    /// Refuse QUIC to port 443 so the browsers fall back to HTTP/2 over TCP.
+    /// Also enabled by the common rule `AND,((NETWORK,UDP),(DST-PORT,443)),REJECT`.
+    #[serde(default)]
+    block_quic: bool,
+
     // reverse map from clash name to net name
     #[serde(skip)]
     name_map: BTreeMap<String, String>,
+
+    // sources of the rule providers
+    #[serde(skip)]
+    sources: Vec<ImportSource>,
 }
 
 impl Builder<BoxImporter> for Clash {
@@ -100,11 +111,42 @@ struct RuleProvider {
     interval: u64,
 }
 
+impl RuleProvider {
+    fn source(&self) -> Result<ImportSource> {
+        Ok(match self.rule_type.as_ref() {
+            "http" => ImportSource::new_poll(self.url.to_string(), Some(self.interval)),
+            "file" => ImportSource::new_path(PathBuf::from(self.path.to_string())),
+            _ => return Err(anyhow!("Bad rule provider type: {}", self.rule_type)),
+        })
+    }
+}
+
 #[derive(Deserialize)]
 struct RuleSet {
     payload: Vec<String>,
 }
 
+/// Whether the rule is the common pattern to block QUIC, e.g.
+/// `AND,((NETWORK,UDP),(DST-PORT,443)),REJECT`.
+fn is_block_quic_rule(rule: &str) -> bool {
+    let rule = rule.replace(' ', "").to_uppercase();
+    let conditions = match rule
+        .strip_prefix("AND,(")
+        .and_then(|r| {
+            r.strip_suffix(",REJECT")
+                .or_else(|| r.strip_suffix(",REJECT-DROP"))
+        })
+        .and_then(|r| r.strip_suffix(')'))
+    {
+        Some(conditions) => conditions,
+        None => return false,
+    };
+    matches!(
+        conditions,
+        "(NETWORK,UDP),(DST-PORT,443)" | "(DST-PORT,443),(NETWORK,UDP)"
+    )
+}
+
 fn ghost_net() -> Net {
     Net::new(
         "alias",
@@ -216,6 +258,12 @@ impl Clash {
                 struct Param {
                     server: String,
                     port: u16,
+                    username: Option<String>,
+                    password: Option<String>,
+                    tls: Option<bool>,
+                    sni: Option<String>,
+                    #[serde(rename = "skip-cert-verify")]
+                    skip_cert_verify: Option<bool>,
                 }
                 let params: Param = serde_json::from_value(p.opt)?;
                 with_net(
@@ -223,6 +271,11 @@ impl Clash {
                         "http",
                         json!({
                             "server": format!("{}:{}", params.server, params.port),
+                            "username": params.username,
+                            "password": params.password,
+                            "tls": params.tls.unwrap_or_default(),
+                            "sni": params.sni,
+                            "skip_cert_verify": params.skip_cert_verify.unwrap_or_default(),
                         }),
                     ),
                     target_net,
@@ -254,7 +307,12 @@ impl Clash {
         if target == "DIRECT" {
             return Ok(self.direct.clone().unwrap_or_else(|| "local".to_string()));
         }
-        if target == "REJECT" {
+        if target == "REJECT-DROP" {
+            if let Some(reject_drop) = &self.reject_drop {
+                return Ok(reject_drop.clone());
+            }
+        }
+        if target == "REJECT" || target == "REJECT-DROP" {
             return Ok(self
                 .reject
                 .clone()
@@ -352,6 +410,9 @@ impl Clash {
                         method,
                         domain: domain.into(),
                     }),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
                 }
             }
             "IP-CIDR" | "IP-CIDR6" => {
@@ -362,6 +423,9 @@ impl Clash {
                     matcher: Matcher::IpCidr(IpCidrMatcher {
                         ipcidr: IpCidr::from_str(&ip_cidr)?.into(),
                     }),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
                 }
             }
             "SRC-IP-CIDR" => {
@@ -372,6 +436,9 @@ impl Clash {
                     matcher: Matcher::SrcIpCidr(SrcIpCidrMatcher {
                         ipcidr: IpCidr::from_str(&ip_cidr)?.into(),
                     }),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
                 }
             }
             "MATCH" => {
@@ -379,6 +446,9 @@ impl Clash {
                 rule_config::RuleItem {
                     target,
                     matcher: Matcher::Any(AnyMatcher {}),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
                 }
             }
             "GEOIP" => {
@@ -387,6 +457,20 @@ impl Clash {
                 rule_config::RuleItem {
                     target,
                     matcher: Matcher::GeoIp(GeoIpMatcher { country: region }),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
+                }
+            }
+            "SRC-GEOIP" => {
+                let region = ps_next()?.to_string();
+                let target = NetRef::new(self.get_target(ps_next()?)?.into());
+                rule_config::RuleItem {
+                    target,
+                    matcher: Matcher::SrcGeoIp(SrcGeoIpMatcher { country: region }),
+                    priority: None,
+                    bulk_transfer: false,
+                    provider: None,
                 }
             }
             "RULE-SET" => {
@@ -394,14 +478,7 @@ impl Clash {
                 let target = NetRef::new(self.get_target(ps_next()?)?.into());
                 let rule_provider = rule_providers.get(&set).ok_or_else(bad_rule)?;
 
-                let source = match rule_provider.rule_type.as_ref() {
-                    "http" => ImportSource::new_poll(
-                        rule_provider.url.to_string(),
-                        Some(rule_provider.interval),
-                    ),
-                    "file" => ImportSource::new_path(PathBuf::from(rule_provider.path.to_string())),
-                    _ => return Err(bad_rule()),
-                };
+                let source = rule_provider.source()?;
 
                 let source_str = source.get_content(cache).await?;
                 let _guard = oom_lock.lock().await;
@@ -414,6 +491,9 @@ impl Clash {
                             method: DomainMatcherMethod::Match,
                             domain: payload.into(),
                         }),
+                        priority: None,
+                        bulk_transfer: false,
+                        provider: Some(set.clone()),
                     },
                     "ipcidr" => rule_config::RuleItem {
                         target: target.clone(),
@@ -424,6 +504,9 @@ impl Clash {
                                 .collect::<rd_interface::Result<Vec<_>>>()?
                                 .into(),
                         }),
+                        priority: None,
+                        bulk_transfer: false,
+                        provider: Some(set.clone()),
                     },
                     // TODO: support classical behavior
                     _ => return Err(bad_rule()),
@@ -456,6 +539,11 @@ impl Importer for Clash {
         cache: &dyn Storage,
     ) -> Result<()> {
         let clash_config: ClashConfig = serde_yaml::from_str(content)?;
+        self.sources = clash_config
+            .rule_providers
+            .values()
+            .filter_map(|p| p.source().ok())
+            .collect();
         let mut added_proxies = Vec::new();
         let mut proxy_map = HashMap::new();
 
@@ -501,9 +589,15 @@ impl Importer for Clash {
             }
         }
 
+        let (quic_rules, rules): (Vec<_>, Vec<_>) = clash_config
+            .rules
+            .into_iter()
+            .partition(|r| is_block_quic_rule(r));
+        let block_quic = self.block_quic || !quic_rules.is_empty();
+
         if let Some(rule_name) = &self.rule_name {
             let oom_lock = Mutex::new(());
-            let rule = stream::iter(clash_config.rules)
+            let rule = stream::iter(rules)
                 .map(|r| self.rule_to_rule(r, cache, &clash_config.rule_providers, &oom_lock))
                 .buffered(10)
                 .flat_map(stream::iter)
@@ -522,9 +616,12 @@ impl Importer for Clash {
                 )
                 .await;
 
-            config
-                .net
-                .insert(rule_name.clone(), Net::new("rule", json!({ "rule": rule })));
+            let rule_net = Net::new("rule", json!({ "rule": rule }));
+            let rule_net = match block_quic {
+                true => with_net(Net::new("block_quic", json!({})), Some(rule_net)),
+                false => rule_net,
+            };
+            config.net.insert(rule_name.clone(), rule_net);
         }
 
         if let Some(select) = &self.select {
@@ -542,6 +639,10 @@ impl Importer for Clash {
 
         Ok(())
     }
+
+    fn sources(&self) -> Vec<ImportSource> {
+        self.sources.clone()
+    }
 }
 
 #[cfg(test)]
@@ -550,17 +651,96 @@ mod tests {
     use serde_yaml::from_str;
     use std::fs;
 
-    #[tokio::test]
-    async fn test_importer_clash_relay() {
-        let mut clash = Clash {
+    fn empty_clash() -> Clash {
+        Clash {
             rule_name: None,
             prefix: None,
             direct: None,
             reject: None,
+            reject_drop: None,
             disable_proxy_group: false,
             select: None,
+            block_quic: false,
             name_map: BTreeMap::new(),
+            sources: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_default_reject_target() {
+        let clash = empty_clash();
+        assert_eq!(clash.get_target("REJECT").unwrap(), "blackhole");
+        assert_eq!(clash.get_target("DIRECT").unwrap(), "local");
+        assert_eq!(clash.get_target("REJECT-DROP").unwrap(), "blackhole");
+
+        let clash = Clash {
+            reject_drop: Some("tarpit".to_string()),
+            ..empty_clash()
         };
+        assert_eq!(clash.get_target("REJECT-DROP").unwrap(), "tarpit");
+        assert_eq!(clash.get_target("REJECT").unwrap(), "blackhole");
+
+        // the default target is always present and refuses connections
+        let mut net = rabbit_digger::config::ConfigNet::default();
+        rabbit_digger::config::init_default_net(&mut net).unwrap();
+        assert_eq!(net["blackhole"].net_type, "blackhole");
+    }
+
+    #[test]
+    fn test_block_quic_rule() {
+        assert!(is_block_quic_rule(
+            "AND,((NETWORK,UDP),(DST-PORT,443)),REJECT"
+        ));
+        assert!(is_block_quic_rule(
+            "AND, ((DST-PORT,443), (NETWORK,UDP)), REJECT-DROP"
+        ));
+        assert!(!is_block_quic_rule(
+            "AND,((NETWORK,UDP),(DST-PORT,53)),REJECT"
+        ));
+        assert!(!is_block_quic_rule(
+            "AND,((NETWORK,UDP),(DST-PORT,443)),DIRECT"
+        ));
+        assert!(!is_block_quic_rule("MATCH,REJECT"));
+    }
+
+    #[tokio::test]
+    async fn test_importer_clash_block_quic() {
+        let content = r#"
+proxies: []
+proxy-groups: []
+rules:
+  - AND,((NETWORK,UDP),(DST-PORT,443)),REJECT
+  - MATCH,DIRECT
+"#;
+        let cache = crate::storage::MemoryCache::new().await.unwrap();
+        let import = |block_quic: bool, content: &'static str| {
+            let cache = &cache;
+            async move {
+                let mut clash = Clash {
+                    rule_name: Some("rule".to_string()),
+                    block_quic,
+                    ..empty_clash()
+                };
+                let mut config = Config::default();
+                clash.process(&mut config, content, cache).await.unwrap();
+                config.net.remove("rule").unwrap()
+            }
+        };
+
+        // the QUIC rule is mapped to the `block_quic` net in front of the rule net
+        let net = import(false, content).await;
+        assert_eq!(net.net_type, "block_quic");
+        assert_eq!(net.opt["net"]["type"], "rule");
+        assert_eq!(net.opt["net"]["rule"].as_array().unwrap().len(), 1);
+
+        let content = "proxies: []\nproxy-groups: []\nrules:\n  - MATCH,DIRECT\n";
+        assert_eq!(import(false, content).await.net_type, "rule");
+        assert_eq!(import(true, content).await.net_type, "block_quic");
+    }
+
+    #[tokio::test]
+    async fn test_importer_clash_relay() {
+        let mut clash = empty_clash();
 
         let content = fs::read_to_string("tests/relay_clash.yml").expect("Unable to read file");
         let wanted_content =
@@ -1,13 +1,18 @@
+use std::path::{Path, PathBuf};
+
 use anyhow::Result;
 use rabbit_digger::Config;
 use rd_interface::{async_trait, config::EmptyConfig, registry::Builder, IntoDyn};
+use yaml_merge_keys::merge_keys_serde;
 
-use crate::storage::Storage;
+use crate::{config::resolve_secrets, storage::Storage};
 
 use super::{BoxImporter, Importer};
 
 #[derive(Debug)]
-pub struct Merge;
+pub struct Merge {
+    base_dir: Option<PathBuf>,
+}
 
 #[async_trait]
 impl Importer for Merge {
@@ -17,10 +22,16 @@ impl Importer for Merge {
         content: &str,
         _cache: &dyn Storage,
     ) -> Result<()> {
-        let other_content: Config = serde_yaml::from_str(content)?;
+        let mut doc = serde_yaml::from_str(content)?;
+        let secrets = resolve_secrets(&mut doc, self.base_dir.as_deref())?;
+        let mut other_content: Config = serde_yaml::from_value(merge_keys_serde(doc)?)?;
+        other_content.secrets = secrets;
         config.merge(other_content);
         Ok(())
     }
+    fn set_base_dir(&mut self, dir: Option<&Path>) {
+        self.base_dir = dir.map(Path::to_path_buf);
+    }
 }
 
 impl Builder<BoxImporter> for Merge {
@@ -31,7 +42,7 @@ impl Builder<BoxImporter> for Merge {
     type Item = Merge;
 
     fn build(_config: Self::Config) -> rd_interface::Result<Self::Item> {
-        Ok(Merge)
+        Ok(Merge { base_dir: None })
     }
 }
 
@@ -40,3 +51,41 @@ impl IntoDyn<BoxImporter> for Merge {
         Box::new(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_merge_secret() {
+        let dir = env::temp_dir().join(format!("rd-merge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("secrets.yaml"), "ss_password: from-file\n").unwrap();
+
+        let mut config = Config::default();
+        let mut merge = Merge { base_dir: None };
+        merge.set_base_dir(Some(&dir));
+        let cache = crate::storage::MemoryCache::new().await.unwrap();
+        merge
+            .process(
+                &mut config,
+                r#"
+secrets: secrets.yaml
+net:
+  ss:
+    type: shadowsocks
+    password: !secret ss_password
+"#,
+                &cache,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(config.net["ss"].opt["password"], "from-file");
+        assert_eq!(config.secrets, ["/net/ss/password"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{anyhow, Result};
+use once_cell::sync::OnceCell;
+use rabbit_digger::{config::Net, registry::Item, Config, Registry};
+use rd_interface::{async_trait, config::EmptyConfig, registry::Builder, IntoDyn};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{storage::Storage, util::match_pattern};
+
+use super::{BoxImporter, Importer};
+
+/// Transforms the nets imported before it. The content is the transform in YAML,
+/// the patterns are the same as the `pattern` of select nets, see [`match_pattern`].
+#[derive(Debug)]
+pub struct Transform;
+
+#[derive(Debug, Deserialize)]
+struct TransformConfig {
+    /// Drop the nets matching any of the patterns, and remove them from the lists
+    /// referring to them, e.g. the `list` of a select net.
+    #[serde(default)]
+    drop: Vec<String>,
+    /// Rename the nets from the key to the value, the references to them are renamed too.
+    /// A net can't be renamed to the name of another net.
+    #[serde(default)]
+    rename: BTreeMap<String, String>,
+    /// Override the fields of the nets matching the pattern, applied after the renames.
+    #[serde(default)]
+    set: Vec<SetFields>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetFields {
+    pattern: String,
+    fields: Map<String, Value>,
+}
+
+fn registry() -> Result<&'static Registry> {
+    static REGISTRY: OnceCell<Registry> = OnceCell::new();
+    REGISTRY.get_or_try_init(crate::get_registry)
+}
+
+/// The JSON pointers of the nets referred by name in `opt`, including the ones in
+/// the inline nets.
+fn net_ref_pointers<T>(
+    registry: &Registry,
+    item: &Item<T>,
+    opt: &Value,
+    prefix: &str,
+    pointers: &mut Vec<String>,
+) {
+    let mut inline_nets = Vec::new();
+    let _ = item.visit_net_ref(opt, &mut |ctx, net_ref| {
+        let pointer = ctx.path().iter().fold(prefix.to_string(), |pointer, key| {
+            format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"))
+        });
+        match net_ref.represent() {
+            Value::String(_) => pointers.push(pointer),
+            net_cfg => inline_nets.push((pointer, net_cfg.clone())),
+        }
+    });
+
+    for (pointer, net_cfg) in inline_nets {
+        if let Ok(net) = serde_json::from_value::<Net>(net_cfg) {
+            if let Ok(item) = registry.get_net(&net.net_type) {
+                net_ref_pointers(registry, item, &net.opt, &pointer, pointers);
+            }
+        }
+    }
+}
+
+/// Call `f` with the names of the nets referred by `opt`. A name is removed if `f`
+/// returns false and it's an item of a list.
+fn visit_refs_in<T>(
+    registry: &Registry,
+    item: &Item<T>,
+    opt: &mut Value,
+    f: &mut impl FnMut(&mut String) -> bool,
+) {
+    let mut pointers = Vec::new();
+    net_ref_pointers(registry, item, opt, "", &mut pointers);
+
+    let mut removed = Vec::new();
+    for pointer in pointers {
+        if let Some(Value::String(name)) = opt.pointer_mut(&pointer) {
+            if !f(name) {
+                removed.push(pointer);
+            }
+        }
+    }
+    // the later items of a list are removed first, so the indexes stay valid
+    for pointer in removed.iter().rev() {
+        let (parent, index) = match pointer.rsplit_once('/') {
+            Some(split) => split,
+            None => continue,
+        };
+        if let (Some(Value::Array(items)), Ok(index)) =
+            (opt.pointer_mut(parent), index.parse::<usize>())
+        {
+            if index < items.len() {
+                items.remove(index);
+            }
+        }
+    }
+}
+
+/// Visit the net references in the options of the nets and the servers, see
+/// `visit_refs_in`. The unknown types are skipped.
+fn visit_net_refs(
+    config: &mut Config,
+    registry: &Registry,
+    f: &mut impl FnMut(&mut String) -> bool,
+) {
+    for net in config.net.values_mut() {
+        if let Ok(item) = registry.get_net(&net.net_type) {
+            visit_refs_in(registry, item, &mut net.opt, f);
+        }
+    }
+    for server in config.server.values_mut() {
+        if let Ok(item) = registry.get_server(&server.server_type) {
+            visit_refs_in(registry, item, &mut server.opt, f);
+        }
+    }
+}
+
+impl TransformConfig {
+    fn apply(&self, config: &mut Config, registry: &Registry) -> Result<()> {
+        let dropped = config
+            .net
+            .keys()
+            .filter(|name| self.drop.iter().any(|p| match_pattern(p, name)))
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        config.net.retain(|name, _| !dropped.contains(name));
+        // a dropped reference is removed from lists, and kept elsewhere to fail the build
+        visit_net_refs(config, registry, &mut |name| {
+            !dropped.contains(name.as_str())
+        });
+
+        let mut new_names = BTreeSet::new();
+        for (name, new_name) in &self.rename {
+            if !config.net.contains_key(name) {
+                continue;
+            }
+            let taken = config.net.contains_key(new_name) && !self.rename.contains_key(new_name);
+            if taken || !new_names.insert(new_name) {
+                return Err(anyhow!(
+                    "Failed to rename net {} to {}: the name is taken",
+                    name,
+                    new_name
+                ));
+            }
+        }
+
+        if !self.rename.is_empty() {
+            config.net = std::mem::take(&mut config.net)
+                .into_iter()
+                .map(|(name, net)| match self.rename.get(&name) {
+                    Some(new_name) => (new_name.clone(), net),
+                    None => (name, net),
+                })
+                .collect();
+            visit_net_refs(config, registry, &mut |name| {
+                if let Some(new_name) = self.rename.get(name.as_str()) {
+                    *name = new_name.clone();
+                }
+                true
+            });
+        }
+
+        for set in &self.set {
+            for (name, net) in config.net.iter_mut() {
+                if !match_pattern(&set.pattern, name) {
+                    continue;
+                }
+                if let Value::Object(opt) = &mut net.opt {
+                    opt.extend(set.fields.clone());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Importer for Transform {
+    async fn process(
+        &mut self,
+        config: &mut Config,
+        content: &str,
+        _cache: &dyn Storage,
+    ) -> Result<()> {
+        let transform: TransformConfig = serde_yaml::from_str(content)?;
+        transform.apply(config, registry()?)
+    }
+}
+
+impl Builder<BoxImporter> for Transform {
+    const NAME: &'static str = "transform";
+
+    type Config = EmptyConfig;
+
+    type Item = Transform;
+
+    fn build(_config: Self::Config) -> rd_interface::Result<Self::Item> {
+        Ok(Transform)
+    }
+}
+
+impl IntoDyn<BoxImporter> for Transform {
+    fn into_dyn(self) -> BoxImporter {
+        Box::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_yaml::from_str;
+
+    fn config() -> Config {
+        from_str(
+            r#"
+net:
+  "HK 01":
+    type: shadowsocks
+    server: hk.example.com:443
+  "US 01":
+    type: shadowsocks
+    server: us.example.com:443
+  "Expire: 2030-01-01":
+    type: shadowsocks
+    server: 127.0.0.1:1
+  proxy:
+    type: select
+    selected: "HK 01"
+    list: ["HK 01", "US 01", "Expire: 2030-01-01"]
+server:
+  mixed:
+    type: http+socks5
+    bind: 127.0.0.1:10800
+    net: proxy
+"#,
+        )
+        .unwrap()
+    }
+
+    async fn transform(config: &mut Config, content: &str) {
+        let cache = crate::storage::MemoryCache::new().await.unwrap();
+        Transform.process(config, content, &cache).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drop() {
+        let mut config = config();
+        transform(&mut config, "drop: ['Expire*', 'US*']").await;
+
+        assert_eq!(
+            config.net.keys().collect::<Vec<_>>(),
+            vec!["HK 01", "proxy"]
+        );
+        assert_eq!(
+            config.net["proxy"].opt["list"],
+            serde_json::json!(["HK 01"])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let mut config = config();
+        transform(&mut config, "rename: { 'HK 01': hk, proxy: select }").await;
+
+        assert_eq!(
+            config.net.keys().collect::<Vec<_>>(),
+            vec!["hk", "US 01", "Expire: 2030-01-01", "select"]
+        );
+        let select = &config.net["select"].opt;
+        assert_eq!(select["selected"], "hk");
+        assert_eq!(
+            select["list"],
+            serde_json::json!(["hk", "US 01", "Expire: 2030-01-01"])
+        );
+        assert_eq!(config.server["mixed"].opt["net"], "select");
+    }
+
+    #[tokio::test]
+    async fn test_rename_taken() {
+        let cache = crate::storage::MemoryCache::new().await.unwrap();
+        let mut config = config();
+        assert!(Transform
+            .process(&mut config, "rename: { 'HK 01': proxy }", &cache)
+            .await
+            .is_err());
+        assert!(Transform
+            .process(&mut config, "rename: { 'HK 01': us, 'US 01': us }", &cache)
+            .await
+            .is_err());
+
+        // swapping the names
+        transform(
+            &mut config,
+            "rename: { 'HK 01': 'US 01', 'US 01': 'HK 01' }",
+        )
+        .await;
+        assert_eq!(config.net["HK 01"].opt["server"], "us.example.com:443");
+        assert_eq!(config.net["proxy"].opt["selected"], "US 01");
+    }
+
+    #[tokio::test]
+    async fn test_set_fields() {
+        let mut config = config();
+        transform(
+            &mut config,
+            "set: [{ pattern: '* 01', fields: { udp: true } }]",
+        )
+        .await;
+
+        assert_eq!(config.net["HK 01"].opt["udp"], true);
+        assert_eq!(config.net["US 01"].opt["udp"], true);
+        assert_eq!(config.net["HK 01"].opt["server"], "hk.example.com:443");
+        assert!(config.net["proxy"].opt.get("udp").is_none());
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use crate::{
-    deserialize_config,
+    deserialize_configs_in_dirs,
     storage::{FileStorage, FolderType, Storage},
 };
 
@@ -9,15 +9,21 @@ use super::{importer::get_importer, select_map::SelectMap, Import, ImportSource}
 use anyhow::{Context, Result};
 use async_stream::stream;
 use futures::{stream::FuturesUnordered, Stream, StreamExt};
+use parking_lot::Mutex;
 use rabbit_digger::Config;
-use tokio::select;
+use std::time::Duration;
+use tokio::{select, time::sleep};
 
 const CFG_MGR_PREFIX: &str = "cfg_mgr";
 const SELECT_PREFIX: &str = "select";
+/// Wait for the changes of other files before reloading.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(200);
 
 struct Inner {
     file_cache: FileStorage,
     select_storage: FileStorage,
+    /// The key of the selections of the config last streamed.
+    select_key: Mutex<Option<String>>,
 }
 
 #[derive(Clone)]
@@ -34,6 +40,7 @@ impl ConfigManager {
             inner: Arc::new(Inner {
                 file_cache,
                 select_storage,
+                select_key: Mutex::new(None),
             }),
         };
 
@@ -42,17 +49,31 @@ impl ConfigManager {
     pub async fn config_stream(
         &self,
         source: ImportSource,
+    ) -> Result<impl Stream<Item = Result<Config>>> {
+        self.config_stream_merged(vec![source]).await
+    }
+    /// Like `config_stream`, but the configs of `sources` are merged in order,
+    /// e.g. a base config and the overrides of a host.
+    pub async fn config_stream_merged(
+        &self,
+        sources: Vec<ImportSource>,
     ) -> Result<impl Stream<Item = Result<Config>>> {
         let inner = self.inner.clone();
 
         Ok(stream! {
             loop {
-                let (config, import) = inner.deserialize_config_from_source(&source).await?;
+                let (config, imports) = inner.deserialize_config_from_sources(&sources).await?;
+                *inner.select_key.lock() = Some(select_key(&sources));
                 yield Ok(config);
-                inner.wait_source(&source, &import).await?;
+                inner.wait_source(&sources, &imports).await?;
             }
         })
     }
+    /// Load the configs of `sources` merged in order once, without watching them.
+    pub async fn load_merged(&self, sources: &[ImportSource]) -> Result<Config> {
+        let (config, _) = self.inner.deserialize_config_from_sources(sources).await?;
+        Ok(config)
+    }
     pub async fn config_stream_from_sources(
         &self,
         sources: impl Stream<Item = ImportSource>,
@@ -66,10 +87,12 @@ impl ConfigManager {
 
         Ok(stream! {
             loop {
-                let (config, import) = inner.deserialize_config_from_source(&source).await?;
+                let cfg_srcs = std::slice::from_ref(&source);
+                let (config, imports) = inner.deserialize_config_from_sources(cfg_srcs).await?;
+                *inner.select_key.lock() = Some(select_key(cfg_srcs));
                 yield Ok(config);
                 let r = select! {
-                    r = inner.wait_source(&source, &import) => r,
+                    r = inner.wait_source(cfg_srcs, &imports) => r,
                     r = sources.next() => {
                         source = match r {
                             Some(s) => s,
@@ -85,50 +108,152 @@ impl ConfigManager {
     pub fn select_storage(&self) -> &dyn Storage {
         &self.inner.select_storage
     }
+    /// The key of the selections in `select_storage` of the config last streamed.
+    pub fn select_key(&self) -> Option<String> {
+        self.inner.select_key.lock().clone()
+    }
+}
+
+/// The selections are kept by the sources, so they survive the changes of the content.
+fn select_key(cfg_srcs: &[ImportSource]) -> String {
+    cfg_srcs
+        .iter()
+        .map(ImportSource::cache_key)
+        .collect::<Vec<_>>()
+        .join(",")
 }
 
 impl Import {
-    async fn apply(&self, config: &mut Config, cache: &dyn Storage) -> Result<()> {
+    /// Returns the sources used by this import.
+    async fn apply(&self, config: &mut Config, cache: &dyn Storage) -> Result<Vec<ImportSource>> {
         let mut importer = get_importer(self)?;
+        importer.set_base_dir(self.source.base_dir());
         let content = self.source.get_content(cache).await?;
         importer.process(config, &content, cache).await?;
-        Ok(())
+
+        let mut sources = importer.sources();
+        sources.push(self.source.clone());
+        Ok(sources)
     }
 }
 
 impl Inner {
-    async fn deserialize_config_from_source(
+    async fn deserialize_config_from_sources(
         &self,
-        source: &ImportSource,
-    ) -> Result<(Config, Vec<Import>)> {
-        let mut config = deserialize_config(&source.get_content(&self.file_cache).await?)?;
-        config.config.id = source.cache_key();
-
-        let imports = config.import;
+        cfg_srcs: &[ImportSource],
+    ) -> Result<(Config, Vec<ImportSource>)> {
+        let mut contents = Vec::with_capacity(cfg_srcs.len());
+        for source in cfg_srcs {
+            let content = source.get_content(&self.file_cache).await?;
+            contents.push((content, source.base_dir()));
+        }
+        // the id is only set by the user, otherwise the hash of the content is used
+        let mut config = deserialize_configs_in_dirs(&contents)?;
 
-        for i in &imports {
-            i.apply(&mut config.config, &self.file_cache)
+        let mut sources = Vec::new();
+        for i in &config.import {
+            let import_sources = i
+                .apply(&mut config.config, &self.file_cache)
                 .await
                 .context(format!("applying import: {i:?}"))?;
+            sources.extend(import_sources);
         }
         let mut config = config.config;
+        crate::select::expand_patterns(&mut config)?;
 
         // restore patch
-        SelectMap::from_cache(&config.id, &self.select_storage)
+        SelectMap::from_cache(&select_key(cfg_srcs), &self.select_storage)
             .await?
             .apply_config(&mut config)
             .await;
 
-        Ok((config, imports))
+        Ok((config, sources))
     }
 
-    async fn wait_source(&self, cfg_src: &ImportSource, imports: &[Import]) -> Result<()> {
+    async fn wait_source(&self, cfg_srcs: &[ImportSource], sources: &[ImportSource]) -> Result<()> {
         let mut events = FuturesUnordered::new();
-        events.push(cfg_src.wait(&self.file_cache));
-        for i in imports {
-            events.push(i.source.wait(&self.file_cache));
+        for source in cfg_srcs.iter().chain(sources) {
+            events.push(source.wait(&self.file_cache));
         }
         events.next().await;
+        // several files may be changed at once, reload only once
+        sleep(RELOAD_DEBOUNCE).await;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reload_on_rule_provider_change() {
+        let dir = std::env::temp_dir().join(format!("rd-config-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let ruleset = dir.join("ruleset.yaml");
+        let clash = dir.join("clash.yaml");
+        let main = dir.join("config.yaml");
+
+        fs::write(&ruleset, "payload:\n  - a.example.com\n").unwrap();
+        fs::write(
+            &clash,
+            format!(
+                r#"proxies: []
+proxy-groups: []
+rules:
+  - RULE-SET,example,DIRECT
+rule-providers:
+  example:
+    type: file
+    behavior: domain
+    url: ""
+    path: {:?}
+    interval: 0
+"#,
+                ruleset
+            ),
+        )
+        .unwrap();
+        fs::write(
+            &main,
+            format!(
+                "import:\n  - type: clash\n    rule_name: clash_rule\n    source:\n      path: {:?}\n",
+                clash
+            ),
+        )
+        .unwrap();
+
+        let rule =
+            |config: &Config| serde_json::to_string(config.net.get("clash_rule").unwrap()).unwrap();
+
+        let mgr = ConfigManager::new().await.unwrap();
+        let source = ImportSource::new_path(main);
+        let stream = mgr.config_stream(source.clone()).await.unwrap();
+        futures::pin_mut!(stream);
+
+        let config = stream.next().await.unwrap().unwrap();
+        assert!(rule(&config).contains("a.example.com"));
+        // the id is left to the hash of the content, the selections are kept by the source
+        assert!(config.id.is_empty());
+        assert_eq!(mgr.select_key(), Some(source.cache_key()));
+
+        let path = ruleset.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(100)).await;
+            fs::write(path, "payload:\n  - b.example.com\n").unwrap();
+        });
+
+        let config = timeout(Duration::from_secs(10), stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(rule(&config).contains("b.example.com"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
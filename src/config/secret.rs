@@ -0,0 +1,167 @@
+use std::{collections::HashMap, env, fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use serde_yaml::Value;
+
+/// The top-level key of the path to the secrets file.
+const SECRETS_KEY: &str = "secrets";
+/// The YAML merge key, its values are moved to the mapping containing it.
+const MERGE_KEY: &str = "<<";
+const SECRET_TAG: &str = "secret";
+const SECRET_ENV_PREFIX: &str = "RD_SECRET_";
+
+/// The values of `!secret <name>`, looked up in the secrets file first, then in
+/// the environment variable `RD_SECRET_<NAME>`.
+#[derive(Debug, Default)]
+pub struct Secrets {
+    file: HashMap<String, String>,
+}
+
+impl Secrets {
+    /// Read the secrets from a YAML file of names to values.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Secrets> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read secrets file: {}", path.display()))?;
+        let file = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse secrets file: {}", path.display()))?;
+        Ok(Secrets { file })
+    }
+    pub fn get(&self, name: &str) -> Result<String> {
+        if let Some(value) = self.file.get(name) {
+            return Ok(value.clone());
+        }
+        let key = format!(
+            "{}{}",
+            SECRET_ENV_PREFIX,
+            name.to_uppercase().replace('-', "_")
+        );
+        env::var(&key).map_err(|_| anyhow!("Secret not found: {} (or env {})", name, key))
+    }
+}
+
+/// Replace the `!secret <name>` values of a config document with the secrets. The
+/// secrets file is given by the top-level `secrets` key of the document, a relative
+/// path is resolved against `base_dir`, the directory of the config file. Returns
+/// the JSON pointers of the replaced fields after the merge keys are applied, e.g.
+/// `/net/proxy/password`.
+pub fn resolve_secrets(doc: &mut Value, base_dir: Option<&Path>) -> Result<Vec<String>> {
+    let secrets = match doc.as_mapping_mut().and_then(|m| m.remove(SECRETS_KEY)) {
+        Some(Value::String(path)) => match base_dir {
+            Some(dir) => Secrets::from_file(dir.join(path))?,
+            None => Secrets::from_file(path)?,
+        },
+        Some(v) => return Err(anyhow!("secrets must be a path, got: {:?}", v)),
+        None => Secrets::default(),
+    };
+
+    let mut pointers = Vec::new();
+    replace_secrets(doc, &secrets, String::new(), &mut pointers)?;
+    Ok(pointers)
+}
+
+fn replace_secrets(
+    value: &mut Value,
+    secrets: &Secrets,
+    pointer: String,
+    pointers: &mut Vec<String>,
+) -> Result<()> {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == SECRET_TAG => {
+            let name = tagged
+                .value
+                .as_str()
+                .ok_or_else(|| anyhow!("{}: the name of a secret must be a string", pointer))?;
+            *value = Value::String(secrets.get(name)?);
+            pointers.push(pointer);
+        }
+        Value::Tagged(tagged) => replace_secrets(&mut tagged.value, secrets, pointer, pointers)?,
+        Value::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                // the merged values end up in this mapping
+                if key.as_str() == Some(MERGE_KEY) {
+                    match value {
+                        Value::Sequence(items) => {
+                            for item in items {
+                                replace_secrets(item, secrets, pointer.clone(), pointers)?;
+                            }
+                        }
+                        value => replace_secrets(value, secrets, pointer.clone(), pointers)?,
+                    }
+                    continue;
+                }
+                let key = match key {
+                    Value::String(k) => k.clone(),
+                    k => serde_yaml::to_string(k)?.trim_end().to_string(),
+                };
+                let pointer = format!("{}/{}", pointer, escape(&key));
+                replace_secrets(value, secrets, pointer, pointers)?;
+            }
+        }
+        Value::Sequence(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                replace_secrets(item, secrets, format!("{}/{}", pointer, i), pointers)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Escape a key as a reference token of JSON pointer.
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_secrets() {
+        let dir = env::temp_dir().join(format!("rd-secrets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let secrets = dir.join("secrets.yaml");
+        fs::write(&secrets, "ss_password: from-file\n").unwrap();
+        env::set_var("RD_SECRET_TROJAN_PASSWORD", "from-env");
+
+        let mut doc: Value = serde_yaml::from_str(
+            r#"
+secrets: secrets.yaml
+net:
+  ss:
+    type: shadowsocks
+    password: !secret ss_password
+  "a/b":
+    type: trojan
+    password: !secret trojan-password
+  merged:
+    <<:
+      - type: trojan
+        password: !secret ss_password
+"#,
+        )
+        .unwrap();
+        let pointers = resolve_secrets(&mut doc, Some(&dir)).unwrap();
+        assert_eq!(
+            pointers,
+            [
+                "/net/ss/password",
+                "/net/a~1b/password",
+                "/net/merged/password"
+            ]
+        );
+        assert_eq!(doc.get(SECRETS_KEY), None);
+        assert_eq!(doc["net"]["ss"]["password"], "from-file");
+        assert_eq!(doc["net"]["a/b"]["password"], "from-env");
+
+        // the file is relative to the config, not to the working directory
+        let mut doc: Value = serde_yaml::from_str("secrets: secrets.yaml\n").unwrap();
+        assert!(resolve_secrets(&mut doc, None).is_err());
+
+        let mut doc: Value = serde_yaml::from_str("password: !secret missing\n").unwrap();
+        assert!(resolve_secrets(&mut doc, None).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,48 +1,116 @@
-use std::collections::HashMap;
-
-use anyhow::Result;
-use rabbit_digger::Config;
-use serde::{Deserialize, Serialize};
-
-use crate::storage::Storage;
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct SelectMap(HashMap<String, String>);
-
-impl SelectMap {
-    pub async fn from_cache(id: &str, cache: &dyn Storage) -> Result<SelectMap> {
-        let select_map = cache
-            .get(id)
-            .await?
-            .map(|i| serde_json::from_str(&i.content).unwrap_or_default())
-            .unwrap_or_default();
-        Ok(SelectMap(select_map))
-    }
-    pub async fn write_cache(&self, id: &str, cache: &dyn Storage) -> Result<()> {
-        cache.set(id, &serde_json::to_string(&self.0)?).await
-    }
-    pub async fn apply_config(&self, config: &mut Config) {
-        for (net, selected) in &self.0 {
-            if let Some(n) = config.net.get_mut(net) {
-                if n.net_type == "select" {
-                    if let Some(o) = n.opt.as_object_mut() {
-                        if o.get("list")
-                            .into_iter()
-                            .filter_map(|v| v.as_array())
-                            .flatten()
-                            .flat_map(|v| v.as_str())
-                            .any(|i| i == selected)
-                        {
-                            o.insert("selected".to_string(), selected.to_string().into());
-                        } else {
-                            tracing::info!("The selected({}/{}) in the select map is not in the list, skip overriding.", selected, net);
-                        }
-                    }
-                }
-            }
-        }
-    }
-    pub fn insert(&mut self, key: String, value: String) -> Option<String> {
-        self.0.insert(key, value)
-    }
-}
+use std::collections::HashMap;
+
+use anyhow::Result;
+use rabbit_digger::Config;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Storage;
+
+/// A member chosen through the api. The unpinned ones are stored as the name,
+/// like the maps written before pinning.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Selection {
+    Selected(String),
+    Pinned { pinned: String },
+}
+
+impl Selection {
+    pub fn new(selected: String, pinned: bool) -> Selection {
+        if pinned {
+            Selection::Pinned { pinned: selected }
+        } else {
+            Selection::Selected(selected)
+        }
+    }
+    pub fn selected(&self) -> &str {
+        match self {
+            Selection::Selected(selected) => selected,
+            Selection::Pinned { pinned } => pinned,
+        }
+    }
+    pub fn pinned(&self) -> bool {
+        matches!(self, Selection::Pinned { .. })
+    }
+    /// Set `selected` and `pinned` of the select net `opt`.
+    pub fn apply(&self, opt: &mut serde_json::Map<String, serde_json::Value>) {
+        opt.insert("selected".to_string(), self.selected().into());
+        if self.pinned() {
+            opt.insert("pinned".to_string(), true.into());
+        } else {
+            opt.remove("pinned");
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SelectMap(HashMap<String, Selection>);
+
+impl SelectMap {
+    pub async fn from_cache(id: &str, cache: &dyn Storage) -> Result<SelectMap> {
+        let select_map = cache
+            .get(id)
+            .await?
+            .map(|i| serde_json::from_str(&i.content).unwrap_or_default())
+            .unwrap_or_default();
+        Ok(SelectMap(select_map))
+    }
+    pub async fn write_cache(&self, id: &str, cache: &dyn Storage) -> Result<()> {
+        cache.set(id, &serde_json::to_string(&self.0)?).await
+    }
+    pub async fn apply_config(&self, config: &mut Config) {
+        for (net, selection) in &self.0 {
+            let selected = selection.selected();
+            if let Some(n) = config.net.get_mut(net) {
+                if n.net_type == "select" {
+                    if let Some(o) = n.opt.as_object_mut() {
+                        if o.get("list")
+                            .into_iter()
+                            .filter_map(|v| v.as_array())
+                            .flatten()
+                            .flat_map(|v| v.as_str())
+                            .any(|i| i == selected)
+                        {
+                            selection.apply(o);
+                        } else {
+                            tracing::info!("The selected({}/{}) in the select map is not in the list, skip overriding.", selected, net);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    pub fn insert(&mut self, key: String, value: Selection) -> Option<Selection> {
+        self.0.insert(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_apply_pinned() {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "net": {
+                "a": { "type": "select", "selected": "x", "list": ["x", "y"] },
+                "b": { "type": "select", "selected": "x", "list": ["x", "y"], "pinned": true }
+            }
+        }))
+        .unwrap();
+        // written before pinning
+        let mut select_map: SelectMap = serde_json::from_str(r#"{ "a": "y", "b": "y" }"#).unwrap();
+        select_map.insert("a".to_string(), Selection::new("y".to_string(), true));
+
+        let select_map: SelectMap =
+            serde_json::from_str(&serde_json::to_string(&select_map).unwrap()).unwrap();
+        select_map.apply_config(&mut config).await;
+
+        let a = &config.net["a"].opt;
+        assert_eq!(a["selected"], "y");
+        assert_eq!(a["pinned"], true);
+        let b = &config.net["b"].opt;
+        assert_eq!(b["selected"], "y");
+        assert!(b.get("pinned").is_none());
+    }
+}
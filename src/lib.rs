@@ -1,11 +1,14 @@
+use std::path::Path;
+
 use anyhow::{Context, Result};
-use config::ConfigManager;
+use config::{ConfigManager, ImportSource};
 pub use rabbit_digger;
 use rabbit_digger::{RabbitDigger, Registry};
 use yaml_merge_keys::merge_keys_serde;
 
 #[cfg(feature = "api_server")]
 pub mod api_server;
+pub mod bench;
 pub mod config;
 pub mod log;
 pub mod schema;
@@ -26,6 +29,8 @@ pub fn get_registry() -> Result<Registry> {
     registry.init_with_registry("raw", raw::init)?;
     #[cfg(feature = "obfs")]
     registry.init_with_registry("obfs", obfs::init)?;
+    #[cfg(feature = "grpc")]
+    registry.init_with_registry("grpc", grpc::init)?;
 
     registry.init_with_registry("rabbit-digger-pro", select::init)?;
 
@@ -33,9 +38,38 @@ pub fn get_registry() -> Result<Registry> {
 }
 
 pub fn deserialize_config(s: &str) -> Result<config::ConfigExt> {
-    let raw_yaml = serde_yaml::from_str(s)?;
-    let merged = merge_keys_serde(raw_yaml)?;
-    Ok(serde_yaml::from_value(merged)?)
+    deserialize_configs(&[s])
+}
+
+/// Deserialize the configs merged in order, the later ones override the earlier ones.
+/// See [`config::merge_config_value`]. The `!secret` values are resolved before
+/// merging, see [`config::resolve_secrets`].
+pub fn deserialize_configs(contents: &[impl AsRef<str>]) -> Result<config::ConfigExt> {
+    let contents: Vec<(&str, Option<&Path>)> =
+        contents.iter().map(|c| (c.as_ref(), None)).collect();
+    deserialize_configs_in_dirs(&contents)
+}
+
+/// Like [`deserialize_configs`], the relative paths in each config, e.g. its secrets
+/// file, are resolved against the directory given with it.
+pub fn deserialize_configs_in_dirs(
+    contents: &[(impl AsRef<str>, Option<&Path>)],
+) -> Result<config::ConfigExt> {
+    let mut merged: Option<serde_yaml::Value> = None;
+    let mut secrets = Vec::new();
+    for (s, dir) in contents {
+        let mut raw_yaml = serde_yaml::from_str(s.as_ref())?;
+        secrets.extend(config::resolve_secrets(&mut raw_yaml, *dir)?);
+        let value = merge_keys_serde(raw_yaml)?;
+        match &mut merged {
+            Some(merged) => config::merge_config_value(merged, value),
+            None => merged = Some(value),
+        }
+    }
+    let merged = merged.ok_or_else(|| anyhow::anyhow!("No config"))?;
+    let mut config: config::ConfigExt = serde_yaml::from_value(merged)?;
+    config.config.secrets = secrets;
+    Ok(config)
 }
 
 pub struct App {
@@ -48,6 +82,7 @@ pub struct ApiServer {
     pub bind: Option<String>,
     pub access_token: Option<String>,
     pub web_ui: Option<String>,
+    pub web_ui_no_fallback: bool,
 }
 
 impl App {
@@ -57,6 +92,24 @@ impl App {
 
         Ok(Self { rd, cfg_mgr })
     }
+    /// Load and merge the configs of `sources`, then build them without starting,
+    /// e.g. to check a config before deploying it.
+    pub async fn check_config(&self, sources: &[ImportSource]) -> Result<()> {
+        let config = self.cfg_mgr.load_merged(sources).await?;
+        self.rd.validate_config(config)
+    }
+    /// Load and merge the configs of `sources`, then benchmark their net `net_name`
+    /// alone. The running config is not changed.
+    pub async fn bench(
+        &self,
+        sources: &[ImportSource],
+        net_name: &str,
+        opts: bench::BenchOptions,
+    ) -> Result<bench::BenchSummary> {
+        let config = self.cfg_mgr.load_merged(sources).await?;
+        let net = self.rd.build_net(config, net_name)?;
+        bench::bench(net, opts).await
+    }
     pub async fn run_api_server(&self, api_server: ApiServer) -> Result<()> {
         #[cfg(feature = "api_server")]
         if let Some(bind) = api_server.bind {
@@ -65,6 +118,7 @@ impl App {
                 config_manager: self.cfg_mgr.clone(),
                 access_token: api_server.access_token,
                 web_ui: api_server.web_ui,
+                web_ui_no_fallback: api_server.web_ui_no_fallback,
             }
             .run(&bind)
             .await
@@ -73,3 +127,42 @@ impl App {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_config() {
+        let dir = std::env::temp_dir().join(format!("rd-check-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let good = dir.join("good.yaml");
+        let bad = dir.join("bad.yaml");
+        fs::write(
+            &good,
+            "server:\n  echo:\n    type: echo\n    bind: 127.0.0.1:0\n",
+        )
+        .unwrap();
+        fs::write(
+            &bad,
+            "server:\n  echo:\n    type: echo\n    bind: 127.0.0.1:0\n    listen: missing\n",
+        )
+        .unwrap();
+
+        let app = App::new().await.unwrap();
+        app.check_config(&[ImportSource::new_path(good)])
+            .await
+            .unwrap();
+        let err = app
+            .check_config(&[ImportSource::new_path(bad)])
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", err).contains("missing"), "{:?}", err);
+        // nothing is started
+        assert!(!app.rd.is_running().await);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,8 +1,36 @@
-use once_cell::sync::OnceCell;
-use std::io::Write;
+use anyhow::Result;
+use once_cell::sync::{Lazy, OnceCell};
+use parking_lot::{const_mutex, Mutex};
+use serde_json::Value;
+use std::{collections::VecDeque, io::Write, str::FromStr};
 use tokio::sync::broadcast;
+use tracing::Level;
+use tracing_subscriber::{reload, EnvFilter};
+
+use crate::storage::{FileStorage, FolderType, Storage};
 
 static BROADCAST: OnceCell<broadcast::Sender<Box<[u8]>>> = OnceCell::new();
+static FILTER: Mutex<Option<LogFilter>> = const_mutex(None);
+static RECENT: Lazy<Mutex<VecDeque<LogRecord>>> =
+    Lazy::new(|| Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)));
+
+/// Max number of the recent log records kept in memory.
+const RECENT_CAPACITY: usize = 1000;
+
+const LOG_STORAGE: &str = "log";
+const FILTER_KEY: &str = "filter";
+
+type Reloader = Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>;
+
+struct LogRecord {
+    level: Level,
+    record: Value,
+}
+
+struct LogFilter {
+    current: String,
+    reload: Reloader,
+}
 
 pub fn get_sender() -> &'static broadcast::Sender<Box<[u8]>> {
     BROADCAST.get_or_init(|| {
@@ -17,6 +45,7 @@ pub struct LogWriter {
 
 impl Write for LogWriter {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        push_recent(buf);
         self.sender.send(buf.into()).ok();
         Ok(buf.len())
     }
@@ -39,3 +68,163 @@ impl Default for LogWriter {
         Self::new()
     }
 }
+
+fn push_recent(buf: &[u8]) {
+    let record: Value = match serde_json::from_slice(buf) {
+        Ok(record) => record,
+        Err(_) => return,
+    };
+    let level = match record
+        .get("level")
+        .and_then(Value::as_str)
+        .and_then(|l| Level::from_str(l).ok())
+    {
+        Some(level) => level,
+        None => return,
+    };
+
+    let mut recent = RECENT.lock();
+    if recent.len() >= RECENT_CAPACITY {
+        recent.pop_front();
+    }
+    recent.push_back(LogRecord { level, record });
+}
+
+/// Returns at most `limit` recent log records, oldest first.
+/// If `level` is set, only records at this level or more severe are returned.
+pub fn recent_logs(level: Option<Level>, limit: usize) -> Vec<Value> {
+    let recent = RECENT.lock();
+    let mut logs = recent
+        .iter()
+        .rev()
+        .filter(|r| level.map(|level| r.level <= level).unwrap_or(true))
+        .take(limit)
+        .map(|r| r.record.clone())
+        .collect::<Vec<_>>();
+    logs.reverse();
+    logs
+}
+
+/// Register the reload handle of the log filter, so it can be changed at runtime.
+pub fn set_filter_handle<S: 'static>(handle: reload::Handle<EnvFilter, S>, current: String) {
+    *FILTER.lock() = Some(LogFilter {
+        current,
+        reload: Box::new(move |filter| handle.reload(filter)),
+    });
+}
+
+/// Returns the active log filter.
+pub fn get_filter() -> Option<String> {
+    FILTER.lock().as_ref().map(|f| f.current.clone())
+}
+
+/// Replace the active log filter, e.g. `rd_std=trace`.
+pub fn set_filter(filter: &str) -> Result<()> {
+    let env_filter = EnvFilter::try_new(filter)?;
+    let mut guard = FILTER.lock();
+    let log_filter = guard
+        .as_mut()
+        .ok_or_else(|| anyhow::anyhow!("Log filter is not reloadable"))?;
+
+    (log_filter.reload)(env_filter)?;
+    log_filter.current = filter.to_string();
+
+    Ok(())
+}
+
+/// Returns the log filter saved by [`save_filter`].
+pub async fn load_filter() -> Result<Option<String>> {
+    let storage = FileStorage::new(FolderType::Data, LOG_STORAGE).await?;
+    Ok(storage.get(FILTER_KEY).await?.map(|i| i.content))
+}
+
+/// Save the log filter, it will be used on next start if `RUST_LOG` is not set.
+pub async fn save_filter(filter: &str) -> Result<()> {
+    let storage = FileStorage::new(FolderType::Data, LOG_STORAGE).await?;
+    storage.set(FILTER_KEY, filter).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tracing_subscriber::{fmt, layer::SubscriberExt, Layer};
+
+    use super::*;
+
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_recent_logs() {
+        let subscriber =
+            tracing_subscriber::registry().with(fmt::layer().json().with_writer(LogWriter::new));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("recent info");
+            tracing::warn!("recent warn");
+            tracing::error!("recent error");
+        });
+
+        let messages = |logs: Vec<Value>| {
+            logs.iter()
+                .filter_map(|l| l["fields"]["message"].as_str().map(ToString::to_string))
+                .filter(|m| m.starts_with("recent "))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            messages(recent_logs(Some(Level::WARN), 100)),
+            ["recent warn", "recent error"]
+        );
+        assert_eq!(
+            messages(recent_logs(None, 100)),
+            ["recent info", "recent warn", "recent error"]
+        );
+        assert_eq!(messages(recent_logs(None, 1)), ["recent error"]);
+
+        for i in 0..RECENT_CAPACITY + 10 {
+            push_recent(
+                format!(r#"{{"level":"TRACE","fields":{{"message":"{}"}}}}"#, i).as_bytes(),
+            );
+        }
+        assert_eq!(RECENT.lock().len(), RECENT_CAPACITY);
+        assert!(messages(recent_logs(None, RECENT_CAPACITY)).is_empty());
+    }
+
+    #[test]
+    fn test_set_filter() {
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+        set_filter_handle(handle, "info".to_string());
+
+        let writer = output.clone();
+        let subscriber = tracing_subscriber::registry().with(
+            fmt::layer()
+                .with_writer(move || Capture(writer.clone()))
+                .with_filter(filter),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("suppressed event");
+            set_filter("debug").unwrap();
+            tracing::debug!("passed event");
+        });
+
+        let output = String::from_utf8(output.lock().clone()).unwrap();
+        assert!(!output.contains("suppressed event"));
+        assert!(output.contains("passed event"));
+        assert_eq!(get_filter().as_deref(), Some("debug"));
+
+        assert!(set_filter("rd_std=nonsense").is_err());
+        assert_eq!(get_filter().as_deref(), Some("debug"));
+    }
+}
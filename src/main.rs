@@ -1,4 +1,4 @@
-use std::path::{Path, PathBuf};
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::{Context, Result};
 use cfg_if::cfg_if;
@@ -8,7 +8,14 @@ use futures::{
     stream::{select, TryStreamExt},
     StreamExt,
 };
-use rabbit_digger_pro::{config::ImportSource, schema, util::exit_stream, ApiServer, App};
+use rabbit_digger_pro::{
+    bench::BenchOptions,
+    config::{write_config, ImportSource},
+    schema,
+    util::exit_stream,
+    ApiServer, App,
+};
+use rd_interface::IntoAddress;
 use tracing_subscriber::filter::dynamic_filter_fn;
 
 #[cfg(feature = "telemetry")]
@@ -16,7 +23,7 @@ mod tracing_helper;
 
 #[derive(Parser)]
 struct ApiServerArgs {
-    /// HTTP endpoint bind address.
+    /// HTTP endpoint bind address. Use `unix:/path/to.sock` to listen on a unix domain socket.
     #[clap(short, long, env = "RD_BIND")]
     bind: Option<String>,
 
@@ -27,13 +34,18 @@ struct ApiServerArgs {
     /// Web UI. Folder path.
     #[structopt(long, env = "RD_WEB_UI")]
     web_ui: Option<String>,
+
+    /// Serve 404 instead of index.html for unknown paths of Web UI.
+    #[structopt(long, env = "RD_WEB_UI_NO_FALLBACK")]
+    web_ui_no_fallback: bool,
 }
 
 #[derive(Parser)]
 struct Args {
-    /// Path to config file
+    /// Path to config file. Use `-` to read from stdin, or a `http(s)://` URL to fetch it.
+    /// It can be repeated, the later configs are merged into the earlier ones.
     #[clap(short, long, env = "RD_CONFIG", default_value = "config.yaml")]
-    config: PathBuf,
+    config: Vec<String>,
 
     #[clap(flatten)]
     api_server: ApiServerArgs,
@@ -55,6 +67,37 @@ enum Command {
         #[clap(flatten)]
         api_server: ApiServerArgs,
     },
+    /// Check the config without starting it, exit with a nonzero code if it's invalid
+    Check {
+        /// Path to config file, same as the `--config` of running.
+        #[clap(short, long, env = "RD_CONFIG", default_value = "config.yaml")]
+        config: Vec<String>,
+    },
+    /// Open connections to an address through a net of the config, and print the
+    /// connect latency and the throughput
+    Bench {
+        /// Path to config file, same as the `--config` of running.
+        #[clap(short, long, env = "RD_CONFIG", default_value = "config.yaml")]
+        config: Vec<String>,
+        /// Name of the net to benchmark
+        #[clap(short, long)]
+        net: String,
+        /// Address to connect to, e.g. an echo server to measure the throughput
+        #[clap(short, long)]
+        target: String,
+        /// Connections in total
+        #[clap(long, default_value_t = 100)]
+        connections: usize,
+        /// Connections open at the same time
+        #[clap(long, default_value_t = 10)]
+        concurrency: usize,
+        /// Bytes written to and read back from each connection, 0 to only connect
+        #[clap(long, default_value_t = 0)]
+        payload: usize,
+        /// Timeout of each connection in seconds
+        #[clap(long, default_value_t = 10)]
+        timeout: u64,
+    },
 }
 
 impl ApiServerArgs {
@@ -63,13 +106,30 @@ impl ApiServerArgs {
             bind: self.bind.clone(),
             access_token: self.access_token.clone(),
             web_ui: self.web_ui.clone(),
+            web_ui_no_fallback: self.web_ui_no_fallback,
         }
     }
 }
 
-async fn write_config(path: impl AsRef<Path>, cfg: &rabbit_digger::Config) -> Result<()> {
-    let content = serde_yaml::to_string(cfg)?;
-    tokio::fs::write(path, content.as_bytes()).await?;
+async fn check(config: &[String]) -> Result<()> {
+    let app = App::new().await?;
+
+    let mut config_sources = Vec::with_capacity(config.len());
+    for arg in config {
+        config_sources.push(ImportSource::from_arg(arg, tokio::io::stdin()).await?);
+    }
+    app.check_config(&config_sources).await
+}
+
+async fn bench(config: &[String], net: &str, opts: BenchOptions) -> Result<()> {
+    let app = App::new().await?;
+
+    let mut config_sources = Vec::with_capacity(config.len());
+    for arg in config {
+        config_sources.push(ImportSource::from_arg(arg, tokio::io::stdin()).await?);
+    }
+    let summary = app.bench(&config_sources, net, opts).await?;
+    println!("{}", summary);
     Ok(())
 }
 
@@ -78,12 +138,15 @@ async fn real_main(args: Args) -> Result<()> {
 
     app.run_api_server(args.api_server.to_api_server()).await?;
 
-    let config_path = args.config.clone();
+    let mut config_sources = Vec::with_capacity(args.config.len());
+    for arg in &args.config {
+        config_sources.push(ImportSource::from_arg(arg, tokio::io::stdin()).await?);
+    }
     let write_config_path = args.write_config;
 
     let config_stream = app
         .cfg_mgr
-        .config_stream(ImportSource::Path(config_path))
+        .config_stream_merged(config_sources)
         .await?
         .and_then(|c: rabbit_digger::Config| async {
             if let Some(path) = &write_config_path {
@@ -114,12 +177,13 @@ async fn real_main(args: Args) -> Result<()> {
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    use tracing_subscriber::{layer::SubscriberExt, prelude::*, EnvFilter};
+    use tracing_subscriber::{layer::SubscriberExt, prelude::*, reload, EnvFilter};
     if std::env::var_os("RUST_LOG").is_none() {
-        std::env::set_var(
-            "RUST_LOG",
-            "rabbit_digger=debug,rabbit_digger_pro=debug,rd_std=debug,raw=debug,ss=debug,tower_http=info",
-        )
+        let filter = match rabbit_digger_pro::log::load_filter().await {
+            Ok(Some(filter)) => filter,
+            _ => "rabbit_digger=debug,rabbit_digger_pro=debug,rd_std=debug,raw=debug,ss=debug,tower_http=info".to_string(),
+        };
+        std::env::set_var("RUST_LOG", filter)
     }
     let tr = tracing_subscriber::registry();
 
@@ -149,7 +213,11 @@ async fn main() -> Result<()> {
         }
     }
 
-    let log_filter = EnvFilter::from_default_env();
+    let (log_filter, log_filter_handle) = reload::Layer::new(EnvFilter::from_default_env());
+    rabbit_digger_pro::log::set_filter_handle(
+        log_filter_handle,
+        std::env::var("RUST_LOG").unwrap_or_default(),
+    );
     let log_writer_filter = EnvFilter::new(
         "rabbit_digger=debug,rabbit_digger_pro=debug,rd_std=debug,raw=debug,ss=debug",
     );
@@ -165,9 +233,7 @@ async fn main() -> Result<()> {
     tr.with(
         tracing_subscriber::fmt::layer()
             .with_writer(std::io::stdout)
-            .with_filter(dynamic_filter_fn(move |metadata, ctx| {
-                log_filter.enabled(metadata, ctx.clone())
-            })),
+            .with_filter(log_filter),
     )
     .with(json_layer)
     .init();
@@ -191,6 +257,33 @@ async fn main() -> Result<()> {
 
             return Ok(());
         }
+        Some(Command::Check { config }) => {
+            if let Err(e) = check(config).await {
+                eprintln!("Config is invalid: {:?}", e);
+                std::process::exit(1);
+            }
+            println!("Config is valid");
+            return Ok(());
+        }
+        Some(Command::Bench {
+            config,
+            net,
+            target,
+            connections,
+            concurrency,
+            payload,
+            timeout,
+        }) => {
+            let opts = BenchOptions {
+                target: target.as_str().into_address()?,
+                connections: *connections,
+                concurrency: *concurrency,
+                payload: *payload,
+                timeout: Duration::from_secs(*timeout),
+            };
+            bench(config, net, opts).await?;
+            return Ok(());
+        }
         None => {}
     }
 
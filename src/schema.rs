@@ -175,7 +175,8 @@ pub async fn generate_schema() -> Result<RootSchema> {
         object: Some(
             ObjectValidation {
                 properties: BTreeMap::from_iter([
-                    ("id".to_string(), string_schema),
+                    ("id".to_string(), string_schema.clone()),
+                    ("secrets".to_string(), string_schema),
                     (
                         "net".to_string(),
                         record(Schema::new_ref("#/definitions/Net".into())),
@@ -1,19 +1,183 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use futures::future::join_all;
+use rabbit_digger::Config;
 use rd_interface::{
     async_trait,
     prelude::*,
-    registry::{Builder, NetRef},
-    Error, INet, Net, Registry, Result,
+    registry::{Builder, NetRef, OptionalNetRef},
+    Address, Context, Error, INet, Net, Registry, Result, TcpStream, Value,
+};
+use rd_std::util::DropAbort;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    time::timeout,
 };
 
+use crate::util::match_pattern;
+
 #[rd_config]
 #[derive(Debug, Clone)]
 pub struct SelectNetConfig {
     selected: NetRef,
-    list: Vec<NetRef>,
+    /// members that are missing in config are replaced by `blackhole`
+    #[serde(default)]
+    list: Vec<OptionalNetRef>,
+    /// glob (`*` and `?`) or prefix of net names, the matched nets are appended to `list`
+    /// when the config is loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pattern: Option<String>,
+    /// try the next members in `list` when `tcp_connect` of the selected one
+    /// fails to connect, e.g. refused, reset or timed out.
+    /// `udp_bind` and `lookup_host` always use the selected one.
+    #[serde(default)]
+    failover: bool,
+    /// maximum number of members to try when `failover` is enabled
+    #[serde(default = "default_max_attempts")]
+    max_attempts: usize,
+    /// check the members in the background, the unhealthy ones are skipped until
+    /// they recover. the selected one is used whenever it's healthy, otherwise the
+    /// first healthy member after it in `list`. `selected` is not changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    health_check: Option<HealthCheckConfig>,
+    /// use the selected one even if it's unhealthy, e.g. it's chosen by the user.
+    /// its health is still checked and shown.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pinned: bool,
+}
+
+#[rd_config]
+#[derive(Debug, Clone)]
+pub struct HealthCheckConfig {
+    /// the address connected to through each member, e.g. `www.gstatic.com:80`
+    addr: Address,
+    /// send a plain HTTP GET request of this path to `addr`, e.g. `/generate_204`,
+    /// and require the response to be `status`. only the connection is checked
+    /// if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    /// the status required for `path`. default is 204.
+    #[serde(default = "default_status")]
+    status: u16,
+    /// seconds between the checks. default is 300.
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// seconds to wait for the connection of a member. default is 5.
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+/// Bytes of the status line read at most by the HTTP check.
+const MAX_STATUS_LINE: u64 = 1024;
+
+fn default_max_attempts() -> usize {
+    3
+}
+
+fn default_interval() -> u64 {
+    300
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+fn default_status() -> u16 {
+    204
+}
+
+/// Sends a GET request of `path` to `addr` and returns the status of the response.
+async fn http_status(tcp: &mut TcpStream, addr: &Address, path: &str) -> Result<u16> {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    );
+    tcp.write_all(request.as_bytes()).await?;
+
+    // e.g. `HTTP/1.1 204 No Content`
+    let mut status_line = String::new();
+    BufReader::new(tcp.take(MAX_STATUS_LINE))
+        .read_line(&mut status_line)
+        .await?;
+    status_line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| Error::other(format!("Invalid status line: {:?}", status_line)))
+}
+
+/// The net name of a member, or the config if it's defined inline.
+fn member_name(represent: &Value) -> String {
+    match represent {
+        Value::String(name) => name.clone(),
+        represent => represent.to_string(),
+    }
+}
+
+/// Health of the members, in the order of `SelectNet::members`.
+struct Health {
+    names: Vec<String>,
+    healthy: Vec<AtomicBool>,
+    config: HealthCheckConfig,
+}
+
+impl Health {
+    /// Checks each member, the ones failed are unhealthy until the next check.
+    async fn check(&self, members: &[Net]) {
+        let check_timeout = Duration::from_secs(self.config.timeout);
+        let results = join_all(members.iter().map(|net| async {
+            matches!(
+                timeout(check_timeout, self.check_member(net)).await,
+                Ok(Ok(()))
+            )
+        }))
+        .await;
+        for ((name, healthy), ok) in self.names.iter().zip(&self.healthy).zip(results) {
+            if healthy.swap(ok, Ordering::Relaxed) != ok {
+                if ok {
+                    tracing::info!("Member {} of select net recovered", name);
+                } else {
+                    tracing::warn!("Member {} of select net is unhealthy, skipped", name);
+                }
+            }
+        }
+    }
+    /// Connects to `addr` through `net`, and checks the status of `path` if it's set.
+    async fn check_member(&self, net: &Net) -> Result<()> {
+        let HealthCheckConfig {
+            addr, path, status, ..
+        } = &self.config;
+        let mut tcp = net.tcp_connect(&mut Context::new(), addr).await?;
+        if let Some(path) = path {
+            let got = http_status(&mut tcp, addr, path).await?;
+            if got != *status {
+                return Err(Error::other(format!(
+                    "Unexpected status {}, expected {}",
+                    got, status
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 
 pub struct SelectNet {
-    selected: Net,
+    /// the selected one followed by the other members, in the order of `list`
+    members: Vec<Net>,
+    failover: bool,
+    max_attempts: usize,
+    /// the selected one, `members[0]`, is never skipped
+    pinned: bool,
+    health: Option<Arc<Health>>,
+    /// checks `health` every `interval` until the net is dropped
+    health_task: Option<DropAbort<()>>,
 }
 
 impl SelectNet {
@@ -22,18 +186,124 @@ impl SelectNet {
             return Err(Error::Other("select list is empty".into()));
         }
 
+        // start from the selected one, then the members after it
+        let pos = config
+            .list
+            .iter()
+            .position(|n| n.represent() == config.selected.represent())
+            .unwrap_or(0);
+        let mut members = vec![(
+            member_name(config.selected.represent()),
+            config.selected.value_cloned(),
+        )];
+        members.extend(
+            config
+                .list
+                .iter()
+                .cycle()
+                .skip(pos)
+                .take(config.list.len())
+                .filter(|n| n.represent() != config.selected.represent())
+                .map(|n| (member_name(n.represent()), n.value_cloned())),
+        );
+        let (names, members): (Vec<_>, Vec<_>) = members.into_iter().unzip();
+
+        let health = config.health_check.map(|health_check| {
+            Arc::new(Health {
+                healthy: names.iter().map(|_| AtomicBool::new(true)).collect(),
+                names,
+                config: health_check,
+            })
+        });
+
         Ok(SelectNet {
-            selected: config.selected.value_cloned(),
+            members,
+            failover: config.failover,
+            max_attempts: config.max_attempts.max(1),
+            pinned: config.pinned,
+            health,
+            health_task: None,
         })
     }
+    /// Start checking the health of the members in the background, if `health_check`
+    /// is set.
+    fn spawn_health_check(mut self) -> Self {
+        if let Some(health) = self.health.clone() {
+            let members = self.members.clone();
+            let interval = Duration::from_secs(health.config.interval.max(1));
+            self.health_task = Some(DropAbort::new(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(interval);
+                loop {
+                    interval.tick().await;
+                    health.check(&members).await;
+                }
+            })));
+        }
+        self
+    }
+    /// Whether each member passed the last health check, by name. `None` if
+    /// `health_check` is not set.
+    pub fn member_health(&self) -> Option<HashMap<String, bool>> {
+        let health = self.health.as_ref()?;
+        Some(
+            health
+                .names
+                .iter()
+                .cloned()
+                .zip(health.healthy.iter().map(|h| h.load(Ordering::Relaxed)))
+                .collect(),
+        )
+    }
+    fn is_healthy(&self, index: usize) -> bool {
+        if self.pinned && index == 0 {
+            return true;
+        }
+        match &self.health {
+            Some(health) => health.healthy[index].load(Ordering::Relaxed),
+            None => true,
+        }
+    }
+    /// The healthy members in order, or all of them if none is healthy. A pinned
+    /// selected one is always the first.
+    fn candidates(&self) -> impl Iterator<Item = &Net> {
+        let any_healthy = (0..self.members.len()).any(|i| self.is_healthy(i));
+        self.members
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| !any_healthy || self.is_healthy(*i))
+            .map(|(_, net)| net)
+    }
     fn net(&self) -> Option<&Net> {
-        Some(&self.selected)
+        self.candidates().next()
+    }
+}
+
+#[async_trait]
+impl rd_interface::TcpConnect for SelectNet {
+    async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+        let mut last_err = None;
+
+        for net in self.candidates().take(self.max_attempts) {
+            match net.tcp_connect(ctx, addr).await {
+                Ok(tcp) => return Ok(tcp),
+                Err(e) if e.is_connection_error() => {
+                    tracing::debug!("Failed to connect {}, try the next member: {:?}", addr, e);
+                    last_err = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| Error::other("select list is empty")))
     }
 }
 
 #[async_trait]
 impl INet for SelectNet {
     fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+        if self.failover {
+            return Some(self);
+        }
         self.net()?.provide_tcp_connect()
     }
 
@@ -48,16 +318,70 @@ impl INet for SelectNet {
     fn provide_lookup_host(&self) -> Option<&dyn rd_interface::LookupHost> {
         self.net()?.provide_lookup_host()
     }
+
+    fn get_inner(&self) -> Option<Net> {
+        self.net().cloned()
+    }
 }
 
 impl Builder<Net> for SelectNet {
     const NAME: &'static str = "select";
+    const TITLE: &'static str = "Select";
     type Config = SelectNetConfig;
     type Item = Self;
 
     fn build(config: Self::Config) -> Result<Self> {
-        SelectNet::new(config)
+        Ok(SelectNet::new(config)?.spawn_health_check())
+    }
+}
+
+/// Append the nets matching the `pattern` of select nets to their `list`.
+/// `selected` defaults to the first member if it's not set.
+pub fn expand_patterns(config: &mut Config) -> Result<()> {
+    let names: Vec<String> = config.net.keys().cloned().collect();
+
+    for (name, net) in config.net.iter_mut() {
+        if net.net_type != SelectNet::NAME {
+            continue;
+        }
+        let opt = match net.opt.as_object_mut() {
+            Some(opt) => opt,
+            None => continue,
+        };
+        let pattern = match opt.get("pattern").and_then(|p| p.as_str()) {
+            Some(pattern) => pattern.to_string(),
+            None => continue,
+        };
+
+        let matched: Vec<&String> = names
+            .iter()
+            .filter(|n| *n != name && match_pattern(&pattern, n))
+            .collect();
+        if matched.is_empty() {
+            return Err(Error::other(format!(
+                "pattern {:?} of select net {:?} matches no net",
+                pattern, name
+            )));
+        }
+
+        if let Value::Array(list) = opt
+            .entry("list")
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            for n in matched {
+                if !list.iter().any(|i| i.as_str() == Some(n)) {
+                    list.push(n.clone().into());
+                }
+            }
+        }
+        if !opt.contains_key("selected") {
+            if let Some(first) = opt["list"].get(0).cloned() {
+                opt.insert("selected".to_string(), first);
+            }
+        }
     }
+
+    Ok(())
 }
 
 pub fn init(registry: &mut Registry) -> Result<()> {
@@ -67,8 +391,16 @@ pub fn init(registry: &mut Registry) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use rd_interface::IntoDyn;
-    use rd_std::tests::{assert_net_provider, ProviderCapability, TestNet};
+    use std::sync::atomic::AtomicUsize;
+
+    use rd_interface::{IntoAddress, IntoDyn};
+    use rd_std::{
+        builtin::blackhole::BlackholeNet,
+        tests::{
+            assert_echo, assert_echo_udp, assert_net_provider, spawn_echo_server,
+            spawn_echo_server_udp, ProviderCapability, TestNet,
+        },
+    };
 
     use super::*;
 
@@ -78,7 +410,12 @@ mod tests {
 
         let select = SelectNet::new(SelectNetConfig {
             selected: net.clone(),
-            list: vec![net],
+            list: vec![OptionalNetRef::new(net)],
+            pattern: None,
+            pinned: false,
+            failover: false,
+            max_attempts: default_max_attempts(),
+            health_check: None,
         })
         .unwrap()
         .into_dyn();
@@ -93,4 +430,404 @@ mod tests {
             },
         );
     }
+
+    #[tokio::test]
+    async fn test_failover() {
+        let blackhole = NetRef::new_with_value("blackhole".into(), BlackholeNet.into_dyn());
+        let local = TestNet::new().into_dyn();
+        spawn_echo_server(&local, "127.0.0.1:26666").await;
+        let local = NetRef::new_with_value("local".into(), local);
+
+        let select = |failover, max_attempts| {
+            SelectNet::new(SelectNetConfig {
+                selected: blackhole.clone(),
+                list: vec![
+                    OptionalNetRef::new(local.clone()),
+                    OptionalNetRef::new(blackhole.clone()),
+                ],
+                pattern: None,
+                pinned: false,
+                failover,
+                max_attempts,
+                health_check: None,
+            })
+            .unwrap()
+            .into_dyn()
+        };
+        let addr = "127.0.0.1:26666".into_address().unwrap();
+
+        assert_echo(&select(true, 2), "127.0.0.1:26666").await;
+
+        let is_refused = |r: Result<TcpStream>| match r {
+            Err(e) => e.is_connection_error(),
+            Ok(_) => false,
+        };
+        assert!(is_refused(
+            select(false, 2)
+                .tcp_connect(&mut Context::new(), &addr)
+                .await
+        ));
+        assert!(is_refused(
+            select(true, 1)
+                .tcp_connect(&mut Context::new(), &addr)
+                .await
+        ));
+    }
+
+    /// Refuses the connections when it's down, counts the ones to `addr`.
+    struct SwitchNet {
+        net: Net,
+        addr: Address,
+        down: Arc<AtomicBool>,
+        count: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl rd_interface::TcpConnect for SwitchNet {
+        async fn tcp_connect(&self, ctx: &mut Context, addr: &Address) -> Result<TcpStream> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(Error::IO(std::io::ErrorKind::ConnectionRefused.into()));
+            }
+            if addr == &self.addr {
+                self.count.fetch_add(1, Ordering::SeqCst);
+            }
+            self.net.tcp_connect(ctx, addr).await
+        }
+    }
+
+    impl INet for SwitchNet {
+        fn provide_tcp_connect(&self) -> Option<&dyn rd_interface::TcpConnect> {
+            Some(self)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let local = TestNet::new().into_dyn();
+        spawn_echo_server(&local, "127.0.0.1:26668").await;
+        spawn_echo_server(&local, "127.0.0.1:26669").await;
+        let addr = "127.0.0.1:26668".into_address().unwrap();
+
+        let switch = |name: &str| {
+            let down = Arc::new(AtomicBool::new(false));
+            let count = Arc::new(AtomicUsize::new(0));
+            let net = SwitchNet {
+                net: local.clone(),
+                addr: addr.clone(),
+                down: down.clone(),
+                count: count.clone(),
+            }
+            .into_dyn();
+            (NetRef::new_with_value(name.into(), net), down, count)
+        };
+        let (a, a_down, a_count) = switch("a");
+        let (b, b_down, b_count) = switch("b");
+        let member_health =
+            |a, b| Some(HashMap::from([("a".to_string(), a), ("b".to_string(), b)]));
+
+        let select = SelectNet::new(SelectNetConfig {
+            selected: a.clone(),
+            list: vec![OptionalNetRef::new(a), OptionalNetRef::new(b)],
+            pattern: None,
+            pinned: false,
+            failover: false,
+            max_attempts: default_max_attempts(),
+            health_check: Some(HealthCheckConfig {
+                addr: "127.0.0.1:26669".into_address().unwrap(),
+                path: None,
+                status: default_status(),
+                interval: 1,
+                timeout: 1,
+            }),
+        })
+        .unwrap()
+        .into_dyn();
+        let health = select.clone().get_inner_net_by::<SelectNet>().unwrap();
+        let check = || health.health.as_ref().unwrap().check(&health.members);
+        let counts = || {
+            (
+                a_count.load(Ordering::SeqCst),
+                b_count.load(Ordering::SeqCst),
+            )
+        };
+
+        assert_echo(&select, "127.0.0.1:26668").await;
+        assert_eq!(counts(), (1, 0));
+
+        // the selected one fails, the next healthy member is used
+        a_down.store(true, Ordering::SeqCst);
+        check().await;
+        assert_eq!(health.member_health(), member_health(false, true));
+        assert_echo(&select, "127.0.0.1:26668").await;
+        assert_eq!(counts(), (1, 1));
+
+        // and it's used again once it recovers
+        a_down.store(false, Ordering::SeqCst);
+        check().await;
+        assert_eq!(health.member_health(), member_health(true, true));
+        assert_echo(&select, "127.0.0.1:26668").await;
+        assert_eq!(counts(), (2, 1));
+
+        // all of them are used if none is healthy
+        a_down.store(true, Ordering::SeqCst);
+        b_down.store(true, Ordering::SeqCst);
+        check().await;
+        b_down.store(false, Ordering::SeqCst);
+        assert!(select
+            .tcp_connect(&mut Context::new(), &addr)
+            .await
+            .is_err());
+        assert_eq!(counts(), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_pinned() {
+        let local = TestNet::new().into_dyn();
+        spawn_echo_server(&local, "127.0.0.1:26671").await;
+        let addr = "127.0.0.1:26671".into_address().unwrap();
+        let down = Arc::new(AtomicBool::new(false));
+        let count = Arc::new(AtomicUsize::new(0));
+        let a = SwitchNet {
+            net: local.clone(),
+            addr: addr.clone(),
+            down: down.clone(),
+            count: count.clone(),
+        }
+        .into_dyn();
+        let a = NetRef::new_with_value("a".into(), a);
+        let b = NetRef::new_with_value("b".into(), local);
+
+        let select = SelectNet::new(SelectNetConfig {
+            selected: a.clone(),
+            list: vec![OptionalNetRef::new(a), OptionalNetRef::new(b)],
+            pattern: None,
+            pinned: true,
+            failover: false,
+            max_attempts: default_max_attempts(),
+            health_check: Some(HealthCheckConfig {
+                addr: addr.clone(),
+                path: None,
+                status: default_status(),
+                interval: 1,
+                timeout: 1,
+            }),
+        })
+        .unwrap();
+
+        // the pinned one fails the check, but stays in use
+        down.store(true, Ordering::SeqCst);
+        select.health.as_ref().unwrap().check(&select.members).await;
+        assert!(!select.member_health().unwrap()["a"]);
+        down.store(false, Ordering::SeqCst);
+
+        assert_echo(&select.into_dyn(), "127.0.0.1:26671").await;
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    /// Answers each request to `addr` of `net` with `status`.
+    async fn spawn_http_server(net: &Net, addr: &str, status: u16) {
+        let listener = net
+            .tcp_bind(&mut Context::new(), &addr.into_address().unwrap())
+            .await
+            .unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut tcp, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    // the request fits in one read
+                    let mut buf = [0u8; 1024];
+                    let _ = tcp.read(&mut buf).await?;
+                    let response =
+                        format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\n\r\n", status);
+                    tcp.write_all(response.as_bytes()).await
+                });
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_http_health_check() {
+        // both accept TCP, only `good` answers with 204
+        let good = TestNet::new().into_dyn();
+        let bad = TestNet::new().into_dyn();
+        spawn_http_server(&good, "127.0.0.1:26670", 204).await;
+        spawn_http_server(&bad, "127.0.0.1:26670", 200).await;
+        let good = NetRef::new_with_value("good".into(), good);
+        let bad = NetRef::new_with_value("bad".into(), bad);
+
+        let check = |path: Option<&str>| {
+            let select = SelectNet::new(SelectNetConfig {
+                selected: bad.clone(),
+                list: vec![
+                    OptionalNetRef::new(bad.clone()),
+                    OptionalNetRef::new(good.clone()),
+                ],
+                pattern: None,
+                pinned: false,
+                failover: false,
+                max_attempts: default_max_attempts(),
+                health_check: Some(HealthCheckConfig {
+                    addr: "127.0.0.1:26670".into_address().unwrap(),
+                    path: path.map(String::from),
+                    status: default_status(),
+                    interval: 1,
+                    timeout: 1,
+                }),
+            })
+            .unwrap();
+            async move {
+                select.health.as_ref().unwrap().check(&select.members).await;
+                select.member_health().unwrap()
+            }
+        };
+
+        let health = check(Some("/generate_204")).await;
+        assert!(!health["bad"]);
+        assert!(health["good"]);
+
+        // only the connection is checked without `path`
+        let health = check(None).await;
+        assert!(health["bad"]);
+        assert!(health["good"]);
+    }
+
+    #[tokio::test]
+    async fn test_udp() {
+        let blackhole = NetRef::new_with_value("blackhole".into(), BlackholeNet.into_dyn());
+        let local = TestNet::new().into_dyn();
+        spawn_echo_server_udp(&local, "127.0.0.1:26667").await;
+        let local = NetRef::new_with_value("local".into(), local);
+
+        let select = SelectNet::new(SelectNetConfig {
+            selected: local.clone(),
+            list: vec![
+                OptionalNetRef::new(blackhole.clone()),
+                OptionalNetRef::new(local.clone()),
+            ],
+            pattern: None,
+            pinned: false,
+            failover: true,
+            max_attempts: default_max_attempts(),
+            health_check: None,
+        })
+        .unwrap()
+        .into_dyn();
+
+        assert_echo_udp(&select, "127.0.0.1:26667").await;
+        assert_eq!(
+            select
+                .lookup_host(
+                    &mut Context::new(),
+                    &"example.com:80".into_address().unwrap()
+                )
+                .await
+                .unwrap(),
+            vec!["127.0.0.1:80".parse().unwrap()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_member() {
+        let rd = rabbit_digger::RabbitDigger::new(crate::get_registry().unwrap())
+            .await
+            .unwrap();
+        let config: rabbit_digger::Config = serde_json::from_value(serde_json::json!({
+            "net": {
+                "select": {
+                    "type": "select",
+                    "selected": "local",
+                    "list": ["local", "missing"]
+                }
+            },
+            "server": {
+                "echo": { "type": "echo", "bind": "127.0.0.1:0", "listen": "select" }
+            }
+        }))
+        .unwrap();
+
+        rd.start(config).await.unwrap();
+        assert!(rd.get_net("select").await.unwrap().is_some());
+        assert!(rd.get_net("missing").await.unwrap().is_none());
+        rd.stop().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_net_chain() {
+        let rd = rabbit_digger::RabbitDigger::new(crate::get_registry().unwrap())
+            .await
+            .unwrap();
+        let config: rabbit_digger::Config = serde_json::from_value(serde_json::json!({
+            "net": {
+                "proxy": {
+                    "type": "alias",
+                    "net": "select"
+                },
+                "select": {
+                    "type": "select",
+                    "selected": "local",
+                    "list": ["local"]
+                }
+            },
+            "server": {
+                "echo": { "type": "echo", "bind": "127.0.0.1:0", "listen": "proxy" }
+            }
+        }))
+        .unwrap();
+
+        rd.start(config).await.unwrap();
+        assert_eq!(
+            rd.net_chain("proxy").await.unwrap(),
+            Some(vec![
+                "alias".to_string(),
+                "select".to_string(),
+                "local".to_string()
+            ])
+        );
+        assert_eq!(rd.net_chain("missing").await.unwrap(), None);
+        rd.stop().await.unwrap();
+    }
+
+    #[test]
+    fn test_expand_patterns() {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "net": {
+                "hk.a": { "type": "local" },
+                "hk.b": { "type": "local" },
+                "us.a": { "type": "local" },
+                "hk": {
+                    "type": "select",
+                    "pattern": "hk."
+                },
+                "all_a": {
+                    "type": "select",
+                    "selected": "us.a",
+                    "list": ["us.a"],
+                    "pattern": "*.a"
+                }
+            }
+        }))
+        .unwrap();
+        expand_patterns(&mut config).unwrap();
+
+        let hk = &config.net["hk"].opt;
+        assert_eq!(hk["list"], serde_json::json!(["hk.a", "hk.b"]));
+        assert_eq!(hk["selected"], "hk.a");
+        let all_a = &config.net["all_a"].opt;
+        assert_eq!(all_a["list"], serde_json::json!(["us.a", "hk.a"]));
+        assert_eq!(all_a["selected"], "us.a");
+
+        // expanding again doesn't duplicate the members
+        expand_patterns(&mut config).unwrap();
+        assert_eq!(
+            config.net["hk"].opt["list"],
+            serde_json::json!(["hk.a", "hk.b"])
+        );
+
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "net": {
+                "hk": { "type": "select", "pattern": "jp." }
+            }
+        }))
+        .unwrap();
+        assert!(expand_patterns(&mut config).is_err());
+    }
 }
@@ -1,5 +1,7 @@
 pub use debounce_stream::{DebounceStream, DebounceStreamExt};
 pub use exit_stream::exit_stream;
+pub use pattern::match_pattern;
 
 mod debounce_stream;
 mod exit_stream;
+mod pattern;
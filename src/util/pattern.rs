@@ -0,0 +1,52 @@
+/// Match `name` by a glob pattern, or by prefix if there is no wildcard in `pattern`.
+pub fn match_pattern(pattern: &str, name: &str) -> bool {
+    if !pattern.contains(['*', '?']) {
+        return name.starts_with(pattern);
+    }
+
+    let (pattern, name): (Vec<char>, Vec<char>) =
+        (pattern.chars().collect(), name.chars().collect());
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and the name position it matched to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((sp, sn)) => {
+                    star = Some((sp, sn + 1));
+                    p = sp + 1;
+                    n = sn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_pattern() {
+        assert!(match_pattern("hk.", "hk.a"));
+        assert!(!match_pattern("hk.", "us.hk.a"));
+        assert!(match_pattern("*.a", "hk.a"));
+        assert!(match_pattern("h?.*", "hk.a"));
+        assert!(match_pattern("*hk*", "us.hk.a"));
+        assert!(!match_pattern("*.b", "hk.a"));
+        assert!(!match_pattern("h?", "hk.a"));
+        assert!(match_pattern("*K*0*", "HK 01"));
+        assert!(match_pattern("HK 01", "HK 01"));
+        assert!(!match_pattern("*02", "HK 01"));
+    }
+}